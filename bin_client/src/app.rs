@@ -2,52 +2,247 @@
 // Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
 // Please do not distribute.
 
-/// This file implements the Terminal User Interface for the client
-/// It displays the status of the blockchain, the network, the transaction pool, and the miner.
-/// It also allows the user to create and publish transactions.
-/// The user can use the arrow keys to navigate between the text areas and press enter to publish.
-/// It also displays the logs and notifications from the client.
-/// You don't have to modify this file. But you are free to change it if you like.
+//! This file implements the Terminal User Interface for the client
+//! It displays the status of the blockchain, the network, the transaction pool, and the miner.
+//! It also allows the user to create and publish transactions.
+//! The user can use the arrow keys to navigate between the text areas and press enter to publish.
+//! It also displays the logs and notifications from the client.
+//! You don't have to modify this file. But you are free to change it if you like.
 
 use std::collections::BTreeMap;
 
+use lib_chain::block::{ChainStatus, Signature, TxOutput};
+use lib_chain::clock::clock;
+use serde::Serialize;
+use serde_json::Value;
 use tui::{
     backend::{Backend},
-    widgets::{Widget, Block, Borders, Paragraph, Wrap},
-    layout::{Layout, Constraint, Direction, Alignment},
+    widgets::{Block, Borders, Clear, Paragraph, Wrap},
+    layout::{Layout, Constraint, Direction, Alignment, Rect},
     Frame,
-    style::{Style, Color},
+    style::{Style, Color, Modifier},
 };
 use tui_textarea::{TextArea, Input};
 
+use crate::batch;
+use crate::deeplink::SendLink;
+use crate::history::TxHistory;
+use crate::latency::TxLatencyTracker;
+use crate::logfile::RotatingLog;
+use crate::scrollback::{export_logs, LogRing, DEFAULT_LOG_CAPACITY};
+use crate::statusbar::StatusBar;
+use crate::txtrace::TxTraceLog;
+
+/// The number of lines shown by a log panel, which is also how far PageUp/PageDown scroll.
+pub const LOG_PANEL_LINES: usize = 20;
+
+/// A status of bin_nakamoto as its json fields, for the components whose status type the client does not share
+/// (the network, the miner and the transaction pool).
+pub type StatusFields = BTreeMap<String, Value>;
+
+/// The text of a status value: a string as it is, numbers and booleans as json, no value as "none", and the entries of
+/// a list or a map separated by ", ".
+pub fn status_value_text(value: &Value) -> String {
+    match value {
+        Value::Null => "none".to_string(),
+        Value::String(text) => text.clone(),
+        Value::Array(items) => items.iter().map(status_value_text).collect::<Vec<_>>().join(", "),
+        Value::Object(fields) => fields.iter()
+            .map(|(key, value)| format!("{}: {}", key, status_value_text(value)))
+            .collect::<Vec<_>>()
+            .join(", "),
+        value => value.to_string(),
+    }
+}
+
+/// The text of a status panel: one "key: value" line per field of the status.
+pub fn status_text(status: &impl Serialize) -> String {
+    match serde_json::to_value(status) {
+        Ok(Value::Object(fields)) => fields.iter()
+            .map(|(key, value)| format!("{}: {}", key, status_value_text(value)))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+/// What the user asked for in the `Settings` panel, to be done by the caller of `App::on_settings_enter`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SettingsAction {
+    /// Change a config parameter of bin_nakamoto (key, value)
+    UpdateConfig(String, String),
+    /// Write the transaction of the form to a file, unsigned, to be signed offline (path)
+    ExportTx(String),
+    /// Publish a transaction signed offline, read from a file (path)
+    ImportTx(String),
+}
+
+/// The color themes of the UI. All the styles of the UI come from the theme in use.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Theme {
+    /// Light colors on the default (dark) background of the terminal
+    Dark,
+    /// Dark colors on a white background, e.g. for projectors
+    Light,
+    /// Bright bold colors on a black background
+    HighContrast,
+    /// No colors at all. Invalid inputs are underlined and the focus is bold, so nothing relies on colors.
+    Monochrome,
+}
+
+impl Theme {
+    /// All the themes, in the order they are cycled through.
+    pub const ALL: [Theme; 4] = [Theme::Dark, Theme::Light, Theme::HighContrast, Theme::Monochrome];
+
+    /// The name of the theme, as accepted by `from_name`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Theme::Dark => "dark",
+            Theme::Light => "light",
+            Theme::HighContrast => "high-contrast",
+            Theme::Monochrome => "monochrome",
+        }
+    }
+
+    /// Get the theme of the given name (e.g. from the `BIN_CLIENT_THEME` environment variable).
+    pub fn from_name(name: &str) -> Option<Theme> {
+        Theme::ALL.into_iter().find(|theme| theme.name() == name)
+    }
+
+    /// The theme after this one.
+    pub fn next(&self) -> Theme {
+        let idx = Theme::ALL.iter().position(|theme| theme == self).unwrap();
+        Theme::ALL[(idx + 1) % Theme::ALL.len()]
+    }
+
+    /// The style of the whole screen.
+    pub fn base(&self) -> Style {
+        match self {
+            Theme::Dark | Theme::Monochrome => Style::default(),
+            Theme::Light => Style::default().fg(Color::Black).bg(Color::White),
+            Theme::HighContrast => Style::default().fg(Color::White).bg(Color::Black),
+        }
+    }
+
+    /// The style of valid inputs and healthy states.
+    pub fn good(&self, is_focus: bool) -> Style {
+        let style = match (self, is_focus) {
+            (Theme::Dark, true) => Style::default().fg(Color::LightGreen),
+            (Theme::Dark, false) => Style::default().fg(Color::Green),
+            (Theme::Light, _) => Style::default().fg(Color::Green),
+            (Theme::HighContrast, _) => Style::default().fg(Color::LightGreen),
+            (Theme::Monochrome, _) => Style::default(),
+        };
+        if is_focus && *self != Theme::Dark { style.add_modifier(Modifier::BOLD) } else { style }
+    }
+
+    /// The style of invalid inputs and failed states.
+    pub fn bad(&self, is_focus: bool) -> Style {
+        let style = match (self, is_focus) {
+            (Theme::Dark, true) => Style::default().fg(Color::LightRed),
+            (Theme::Dark, false) => Style::default().fg(Color::Red),
+            (Theme::Light, _) => Style::default().fg(Color::Red),
+            (Theme::HighContrast, _) => Style::default().fg(Color::LightRed).add_modifier(Modifier::UNDERLINED),
+            (Theme::Monochrome, _) => Style::default().add_modifier(Modifier::UNDERLINED),
+        };
+        if is_focus && *self != Theme::Dark { style.add_modifier(Modifier::BOLD) } else { style }
+    }
+
+    /// The style of states that need attention but are not failures.
+    pub fn warning(&self) -> Style {
+        match self {
+            Theme::Dark => Style::default().fg(Color::Yellow),
+            Theme::Light => Style::default().fg(Color::Magenta),
+            Theme::HighContrast => Style::default().fg(Color::LightYellow).add_modifier(Modifier::BOLD),
+            Theme::Monochrome => Style::default().add_modifier(Modifier::REVERSED),
+        }
+    }
+
+    /// The style of important values, e.g. the balance.
+    pub fn highlight(&self) -> Style {
+        match self {
+            Theme::Dark => Style::default().fg(Color::LightYellow),
+            Theme::Light => Style::default().fg(Color::Magenta),
+            Theme::HighContrast => Style::default().fg(Color::LightYellow).add_modifier(Modifier::BOLD),
+            Theme::Monochrome => Style::default().add_modifier(Modifier::BOLD),
+        }
+    }
+
+    /// The style of the key binding hints.
+    pub fn hint(&self) -> Style {
+        match self {
+            Theme::Dark => Style::default().fg(Color::LightBlue),
+            Theme::Light => Style::default().fg(Color::Blue),
+            Theme::HighContrast => Style::default().fg(Color::LightCyan),
+            Theme::Monochrome => Style::default().add_modifier(Modifier::ITALIC),
+        }
+    }
+}
+
 /// The struct to represent the terminal user interface for the client.
 pub struct App<'a> {
     /// the friendly name of the user. Will be used in the `Create Transaction` panel of the UI.
     pub friendly_user_name: String,
     /// whether the user inputs in the `Create Transaction` panel of the UI are valid or not.
     pub are_inputs_valid: bool,
-    /// the user ID of the sender. Will be used as the default sender ID in the `Create Transaction` panel of the UI.
+    /// the user ID of the sender, reported in the state dumped by the `DumpState` bot command.
     pub user_id: String,
     /// the balance of the user. Will be displayed in the `Create Transaction` panel of the UI.
     pub user_balance: i64,
+    /// the balance of the user at the end of the longest chain (including blocks not finalized yet).
+    pub user_balance_working: i64,
+    /// the transactions published by the user that are not in a block yet, as (data_string, amount).
+    pub pending_txs: Vec<(String, i64)>,
+    /// the transactions accepted by the wallet for signing but not signed yet, as data_string.
+    pub signing_txs: Vec<String>,
+    /// whether bin_nakamoto pushes its events (finalized blocks, confirmed txs, connected neighbors), so that the status is polled less often.
+    pub events_subscribed: bool,
+    /// the state shown in the status bar at the top of the UI (wallet, node, sync, miner).
+    pub status_bar: StatusBar,
     /// whether the user wants to quit the program or not.
     pub should_quit: bool,
-    /// the status of the blocktree as a dictionary of key-value pairs (for debugging purpose)
-    pub blocktree_status: BTreeMap<String, String>,
-    /// the status of the network as a dictionary of key-value pairs (for debugging purpose)
-    pub network_status: BTreeMap<String, String>,
-    /// the status of the transaction pool as a dictionary of key-value pairs (for debugging purpose)
-    pub txpool_status: BTreeMap<String, String>,
-    /// the status of the miner as a dictionary of key-value pairs (for debugging purpose)
-    pub miner_status: BTreeMap<String, String>,
-    /// the notification logs from the client for debugging purpose.
-    pub notify_log: Vec<String>,
-    /// the stderr logs from the client for debugging purpose.
-    pub stderr_log: Vec<String>,
+    /// the status of the blocktree, once received (for debugging purpose)
+    pub blocktree_status: Option<ChainStatus>,
+    /// the status of the network as its json fields (for debugging purpose)
+    pub network_status: StatusFields,
+    /// the status of the transaction pool as its json fields (for debugging purpose)
+    pub txpool_status: StatusFields,
+    /// the status of the miner as its json fields (for debugging purpose)
+    pub miner_status: StatusFields,
+    /// the statistics of the chain over its last blocks (block interval, txs per block, supply, fork rate) as a dictionary of key-value pairs
+    pub chain_stats: BTreeMap<String, String>,
+    /// the latencies of the transactions created by the user to be included in a block and finalized (shown with the chain statistics)
+    pub tx_latency: TxLatencyTracker,
+    /// the trace events of the lifecycle of the recent transactions, of all the processes (see the `:trace` command)
+    pub tx_traces: TxTraceLog,
+    /// the transactions sent or received by the user. Will be displayed in the `Transaction History` panel of the UI.
+    pub tx_history: TxHistory,
+    /// whether the `Transaction History` panel is shown (on top of the other panels) or not.
+    pub show_history: bool,
+    /// the notification logs from the client for debugging purpose (the last lines, see `LogRing`).
+    pub notify_log: LogRing,
+    /// the stderr logs from the client for debugging purpose (the last lines, see `LogRing`).
+    pub stderr_log: LogRing,
     /// the text areas in the UI for inputting sender Id, receiver ID and message to create a transaction.
     pub textareas: Vec<TextArea<'a>>,
     /// the index of the text area that is currently in focus.
-    pub textarea_choosing_idx: usize
+    pub textarea_choosing_idx: usize,
+    /// the message the `Message` text area is reset to (see `reset_inputs`), from the transaction template of the user.
+    default_message: String,
+    /// the effective config of bin_nakamoto as a dictionary of key-value pairs. Will be displayed in the `Settings` panel of the UI.
+    pub config_status: BTreeMap<String, String>,
+    /// whether the `Settings` panel is shown (on top of the other panels) or not.
+    pub show_settings: bool,
+    /// the text area in the `Settings` panel for inputting a config change as `key=value`.
+    pub settings_textarea: TextArea<'a>,
+    /// whether the `Batch Payment` panel is shown (on top of the other panels) or not.
+    pub show_batch: bool,
+    /// the text area in the `Batch Payment` panel for inputting the payments of a batch, one `receiver amount` per line.
+    pub batch_textarea: TextArea<'a>,
+    /// the color theme of the UI.
+    pub theme: Theme,
+    /// the file where the client's own logs are also written, for debugging after the UI is gone.
+    pub log_file: Option<RotatingLog>
 }
 
 impl<'a> App<'a> {
@@ -64,31 +259,66 @@ impl<'a> App<'a> {
             are_inputs_valid: false,
             user_id: default_sender.clone(),
             user_balance: -1,
+            user_balance_working: -1,
+            pending_txs: vec![],
+            signing_txs: vec![],
+            events_subscribed: false,
+            status_bar: StatusBar::default(),
             should_quit: false,
-            blocktree_status: BTreeMap::new(),
+            blocktree_status: None,
             network_status: BTreeMap::new(),
             txpool_status: BTreeMap::new(),
             miner_status: BTreeMap::new(),
-            notify_log: vec![],
-            stderr_log: vec![],
+            chain_stats: BTreeMap::new(),
+            tx_latency: TxLatencyTracker::default(),
+            tx_traces: TxTraceLog::default(),
+            tx_history: TxHistory::default(),
+            show_history: false,
+            notify_log: LogRing::new(DEFAULT_LOG_CAPACITY),
+            stderr_log: LogRing::new(DEFAULT_LOG_CAPACITY),
             textareas: vec![
                 App::textarea_with_title("Sender ID".to_string(), default_sender),
                 App::textarea_with_title("Receiver ID".to_string(), default_receiver),
-                App::textarea_with_title("Message".to_string(), default_message)
+                App::textarea_with_title("Message".to_string(), default_message.clone())
             ],
-            textarea_choosing_idx: 1
+            textarea_choosing_idx: 1,
+            default_message,
+            config_status: BTreeMap::new(),
+            show_settings: false,
+            settings_textarea: App::textarea_with_title("key=value".to_string(), "".to_string()),
+            show_batch: false,
+            batch_textarea: App::textarea_with_title("receiver amount (one per line)".to_string(), "".to_string()),
+            theme: Theme::Dark,
+            log_file: None
         }
     }
 
     /// Log to the stderr log.
     pub fn client_log(&mut self, log: String) {
-        self.stderr_log.push(format!("[Client({})] {}", self.friendly_user_name, log));
+        self.push_stderr_log(format!("[Client({})] {}", self.friendly_user_name, log));
+    }
+
+    /// Add a line to the stderr log, and to the log file if there is one.
+    fn push_stderr_log(&mut self, line: String) {
+        if let Some(log_file) = self.log_file.as_mut() {
+            let _ = log_file.write_line(&line);
+        }
+        self.stderr_log.push(line);
+    }
+
+    /// Record that a transaction submitted by this client was signed, and write its Submitted span to the log file.
+    /// The trace events are not shown in the log panels; see the `:trace` command.
+    pub fn trace_signed(&mut self, data_string: &str, signature: &Signature) {
+        if let Some(event) = self.tx_traces.record_signed(data_string, signature) {
+            if let Some(log_file) = self.log_file.as_mut() {
+                let _ = log_file.write_line(&event.to_log_line());
+            }
+        }
     }
 
     /// Private function to create a text area with a title.
     fn textarea_with_title(title: String, default_val: String) -> TextArea<'a> {
         let mut textarea = TextArea::new(vec![default_val]);
-        textarea.set_style(Style::default().fg(Color::LightGreen));
         textarea.set_block(
             Block::default()
                 .borders(Borders::ALL)
@@ -100,13 +330,16 @@ impl<'a> App<'a> {
     
     /// Return the values in the text areas on enter.
     pub fn on_enter(&mut self) -> (String, String, String) {
-        let sender = self.textareas[0].lines()[0].clone();
-        let receiver = self.textareas[1].lines()[0].clone();
-        let message = self.textareas[2].lines()[0].clone();
-        self.stderr_log.push(format!("[Client] ENTER pressed.  Sender: {}  Receiver: {}  Message: {}", &sender, &receiver, &message));
+        let (sender, receiver, message) = self.form_inputs();
+        self.push_stderr_log(format!("[Client] ENTER pressed.  Sender: {}  Receiver: {}  Message: {}", &sender, &receiver, &message));
         (sender, receiver, message)
     }
 
+    /// Return the values in the text areas (sender, receiver, message).
+    pub fn form_inputs(&self) -> (String, String, String) {
+        (self.textareas[0].lines()[0].clone(), self.textareas[1].lines()[0].clone(), self.textareas[2].lines()[0].clone())
+    }
+
     /// Change the focus to the text area above.
     pub fn on_up(&mut self) {
         self.textarea_choosing_idx = (self.textarea_choosing_idx + 3 - 1) % 3;
@@ -117,32 +350,180 @@ impl<'a> App<'a> {
         self.textarea_choosing_idx = (self.textarea_choosing_idx + 3 + 1) % 3;
     }
 
+    /// Return the name and the value of the text area in focus, e.g. to copy an address to the clipboard.
+    pub fn focused_input(&self) -> (&'static str, String) {
+        let names = ["Sender ID", "Receiver ID", "Message"];
+        (names[self.textarea_choosing_idx], self.textareas[self.textarea_choosing_idx].lines()[0].clone())
+    }
+
+    /// The balance line of the `Create Transaction` panel, with the outgoing amount still pending inclusion in a block.
+    fn balance_text(&self) -> String {
+        let pending: i64 = self.pending_txs.iter().map(|(_, amount)| amount).sum();
+        let mut balance = format!("Balance: ${} (available soon: ${})", self.user_balance, self.user_balance_working);
+        if !self.pending_txs.is_empty() {
+            balance = format!("{} [pending: -${} in {} tx]", balance, pending, self.pending_txs.len());
+        }
+        if !self.signing_txs.is_empty() {
+            balance = format!("{} [signing {} tx...]", balance, self.signing_txs.len());
+        }
+        balance
+    }
+
     /// Set the values in the text areas.
     pub fn set_inputs(&mut self, receiver: Option<String>, message: Option<String>) {
-        match receiver {
-            Some(receiver) => {
-                self.textareas[1].move_cursor(tui_textarea::CursorMove::Head);
-                self.textareas[1].delete_line_by_end();
-                self.textareas[1].insert_str(&receiver);
+        if let Some(receiver) = receiver {
+            self.textareas[1].move_cursor(tui_textarea::CursorMove::Head);
+            self.textareas[1].delete_line_by_end();
+            self.textareas[1].insert_str(&receiver);
+        }
+        if let Some(message) = message {
+            self.textareas[2].move_cursor(tui_textarea::CursorMove::Head);
+            self.textareas[2].delete_line_by_end();
+            self.textareas[2].insert_str(&message);
+        }
+    }
+
+
+    /// Pre-fill the transaction form from a deep link (see `SendLink`), e.g. `nakamoto:send?to=<addr>&amount=10`.
+    /// The transaction is only created once the user presses ENTER. An invalid link is logged and leaves the form as is.
+    pub fn open_link(&mut self, link: &str) {
+        match SendLink::parse(link) {
+            Ok(send_link) => {
+                self.set_inputs(Some(send_link.to.clone()), Some(send_link.message()));
+                self.textarea_choosing_idx = 2;
+                self.client_log(format!("Opened link: send ${} to {}", send_link.amount, send_link.to));
+            }
+            Err(e) => self.client_log(format!("Invalid link {:?}: {}", link, e)),
+        }
+    }
+
+    /// Reset the transaction form: no receiver, and the default message.
+    pub fn reset_inputs(&mut self) {
+        self.set_inputs(Some(String::new()), Some(self.default_message.clone()));
+    }
+
+    /// Show or hide the `Settings` panel.
+    pub fn toggle_settings(&mut self) {
+        self.show_settings = !self.show_settings;
+    }
+
+    /// Show or hide the `Batch Payment` panel.
+    pub fn toggle_batch(&mut self) {
+        self.show_batch = !self.show_batch;
+    }
+
+    /// Handle the input event for the text area of the `Batch Payment` panel.
+    pub fn on_batch_input(&mut self, input: Input) {
+        self.batch_textarea.input(input);
+    }
+
+    /// Return the sender and the payments typed in the `Batch Payment` panel, then clear and close it.
+    /// Invalid payments are logged and leave the panel as is.
+    pub fn on_batch_enter(&mut self) -> Option<(String, Vec<TxOutput>)> {
+        match batch::parse_payments(self.batch_textarea.lines()) {
+            Ok(payments) => {
+                let sender = self.form_inputs().0;
+                self.push_stderr_log(format!("[Client] Batch created.  Sender: {}  Payments: {}", &sender, batch::summary(&payments)));
+                self.batch_textarea = App::textarea_with_title("receiver amount (one per line)".to_string(), "".to_string());
+                self.show_batch = false;
+                Some((sender, payments))
+            }
+            Err(e) => {
+                self.client_log(format!("Invalid batch: {}", e));
+                None
+            }
+        }
+    }
+
+    /// Show or hide the `Transaction History` panel.
+    pub fn toggle_history(&mut self) {
+        self.show_history = !self.show_history;
+    }
+
+    /// Handle the input event for the text area of the `Settings` panel.
+    pub fn on_settings_input(&mut self, input: Input) {
+        self.settings_textarea.input(input);
+    }
+
+    /// Scroll the STDERR log (or the Notify log) back by one page.
+    pub fn on_page_up(&mut self, notify: bool) {
+        if notify { self.notify_log.scroll_up(LOG_PANEL_LINES) } else { self.stderr_log.scroll_up(LOG_PANEL_LINES) }
+    }
+
+    /// Scroll the STDERR log (or the Notify log) forward by one page.
+    pub fn on_page_down(&mut self, notify: bool) {
+        if notify { self.notify_log.scroll_down(LOG_PANEL_LINES) } else { self.stderr_log.scroll_down(LOG_PANEL_LINES) }
+    }
+
+    /// Run a client command typed in the `Settings` panel (starting with `:`), e.g. `:export-logs <path>` or `:open <link>`.
+    /// The commands that need the other processes are returned to the caller instead.
+    fn run_command(&mut self, command: &str) -> Option<SettingsAction> {
+        match command.split_once(' ').map_or((command.trim(), ""), |(name, arg)| (name, arg.trim())) {
+            (":export-logs", path) if !path.is_empty() => {
+                match export_logs(path, &[("Notify Log", &self.notify_log), ("STDERR Log", &self.stderr_log)]) {
+                    Ok(line_count) => self.client_log(format!("Exported {} log lines to {}", line_count, path)),
+                    Err(e) => self.client_log(format!("Cannot export the logs to {}: {}", path, e)),
+                }
+            }
+            (":open", link) if !link.is_empty() => self.open_link(link),
+            (":export-tx", path) if !path.is_empty() => return Some(SettingsAction::ExportTx(path.to_string())),
+            (":import-tx", path) if !path.is_empty() => return Some(SettingsAction::ImportTx(path.to_string())),
+            (":trace", prefix) => {
+                match self.tx_traces.timeline(Some(prefix).filter(|prefix| !prefix.is_empty())) {
+                    Ok(lines) => lines.into_iter().for_each(|line| self.client_log(line)),
+                    Err(e) => self.client_log(e),
+                }
             }
-            None => {}
+            _ => self.client_log(format!("Unknown command {:?}, expected :export-logs <path>, :open <link>, :export-tx <path>, :import-tx <path> or :trace [tx id prefix]", command)),
+        }
+        None
+    }
+
+    /// Return the action typed in the `Settings` panel on enter (a config change `key=value` or a client command), and clear the text area.
+    /// A line starting with `:` is a client command (see `run_command`).
+    pub fn on_settings_enter(&mut self) -> Option<SettingsAction> {
+        let line = self.settings_textarea.lines()[0].clone();
+        self.settings_textarea.move_cursor(tui_textarea::CursorMove::Head);
+        self.settings_textarea.delete_line_by_end();
+        if line.trim_start().starts_with(':') {
+            return self.run_command(line.trim());
         }
-        match message {
-            Some(message) => {
-                self.textareas[2].move_cursor(tui_textarea::CursorMove::Head);
-                self.textareas[2].delete_line_by_end();
-                self.textareas[2].insert_str(&message);
+        match line.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => Some(SettingsAction::UpdateConfig(key.trim().to_string(), value.trim().to_string())),
+            _ => {
+                self.client_log(format!("Invalid config change {:?}, expected key=value", line));
+                None
             }
-            None => {}
         }
     }
 
+    /// Switch to the next color theme.
+    pub fn cycle_theme(&mut self) {
+        self.theme = self.theme.next();
+        self.client_log(format!("Theme: {}", self.theme.name()));
+    }
 
     /// Set the `should_quit` flag to true.
     pub fn on_quit(&mut self) {
         self.should_quit = true;
     }
 
+    /// The state seen by the client (user, balances, pending txs and the last status of bin_nakamoto) as a json object,
+    /// written by the `DumpState` bot command so that scripted scenarios can check it.
+    pub fn state_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "user_id": self.user_id,
+            "user_balance": self.user_balance,
+            "user_balance_working": self.user_balance_working,
+            "pending_txs": self.pending_txs,
+            "blocktree": self.blocktree_status,
+            "network": self.network_status,
+            "txpool": self.txpool_status,
+            "miner": self.miner_status,
+            "chain_stats": self.chain_stats,
+        })
+    }
+
 
     /// Handle the input event for the text areas.
     pub fn on_textarea_input(&mut self, input: Input) {
@@ -156,26 +537,18 @@ impl<'a> App<'a> {
     }
 
     /// Validating the textarea for user ID.
-    fn validate_id_textarea(textarea: &mut TextArea, is_focus: bool) -> bool {
+    fn validate_id_textarea(textarea: &mut TextArea, is_focus: bool, theme: Theme) -> bool {
         if textarea.lines()[0].len() != 80 {
-            if is_focus {
-                textarea.set_style(Style::default().fg(Color::LightRed));
-            } else {
-                textarea.set_style(Style::default().fg(Color::Red));
-            }
+            textarea.set_style(theme.bad(is_focus));
             false
         } else {
-            if is_focus {
-                textarea.set_style(Style::default().fg(Color::LightGreen));
-            } else {
-                textarea.set_style(Style::default().fg(Color::Green));
-            }
+            textarea.set_style(theme.good(is_focus));
             true
         }
     }
 
     /// Validating the textarea for message.
-    fn validate_message_textarea(textarea: &mut TextArea, is_focus: bool, user_balance: i64) -> bool {
+    fn validate_message_textarea(textarea: &mut TextArea, is_focus: bool, user_balance: i64, theme: Theme) -> bool {
         let mut is_valid = true;
         
         if !textarea.lines()[0].starts_with("SEND $") {
@@ -199,29 +572,23 @@ impl<'a> App<'a> {
         } 
         
         if !is_valid {
-            if is_focus {
-                textarea.set_style(Style::default().fg(Color::LightRed));
-            } else {
-                textarea.set_style(Style::default().fg(Color::Red));
-            }
+            textarea.set_style(theme.bad(is_focus));
             false
         } else {
-            if is_focus {
-                textarea.set_style(Style::default().fg(Color::LightGreen));
-            } else {
-                textarea.set_style(Style::default().fg(Color::Green));
-            }
+            textarea.set_style(theme.good(is_focus));
             true
         }
     }
 
     /// Draw the UI.
     pub fn draw<B: Backend>(&mut self, f: &mut Frame<B>) {
+        f.render_widget(Block::default().style(self.theme.base()), f.size());
         let root_chunks = Layout::default()
             .direction(Direction::Vertical)
             .margin(1)
             .constraints(
                 [
+                    Constraint::Length(1),
                     Constraint::Percentage(60),
                     Constraint::Percentage(40),
                 ].as_ref()
@@ -238,7 +605,7 @@ impl<'a> App<'a> {
                     Constraint::Percentage(34),
                 ].as_ref()
             )
-            .split(root_chunks[0]);
+            .split(root_chunks[1]);
         
         let top_left_chunks = Layout::default()
             .direction(Direction::Vertical)
@@ -264,7 +631,7 @@ impl<'a> App<'a> {
                     Constraint::Length(3),
                     Constraint::Length(3),
                     Constraint::Length(3),
-                    Constraint::Length(4),
+                    Constraint::Length(7),
                 ].as_ref()
             )
             .split(inner_rect);
@@ -285,22 +652,15 @@ impl<'a> App<'a> {
             .margin(0)
             .constraints(
                 [
-                    Constraint::Percentage(50),
-                    Constraint::Percentage(50),
+                    Constraint::Percentage(40),
+                    Constraint::Percentage(20),
+                    Constraint::Percentage(40),
                 ].as_ref()
             )
-            .split(root_chunks[1]);
+            .split(root_chunks[2]);
 
         
 
-        let status_text_transform = | status: &BTreeMap<String, String>| {
-            let mut status_vec: Vec<String> = vec![];
-            for status_item in status {
-                status_vec.push(format!("{:?}", status_item));
-            }
-            status_vec.join("\n")
-        };
-
         let bordered_block_gen = |title| {
             Block::default()
                 .title(title)
@@ -317,35 +677,168 @@ impl<'a> App<'a> {
             else {para}
         };
 
-        let logger_gen = |textvec: &Vec<String>, count: usize, title, is_wrap| {
-            let mut head_reversed = textvec[textvec.len() - std::cmp::min(count, textvec.len())..textvec.len()].to_vec();
-            head_reversed.reverse();
-            paragraph_gen(head_reversed.join("\n"), title, is_wrap)
+        let logger_gen = |log: &LogRing, count: usize, title, is_wrap| {
+            paragraph_gen(log.visible(count).join("\n"), title, is_wrap)
+        };
+        // The title of a scrolled log tells how far back it is
+        let log_title = |log: &LogRing, title: &str| {
+            if log.offset() > 0 { format!("{} (-{}/{})", title, log.offset(), log.len()) } else { title.to_string() }
         };
+        let (notify_title, stderr_title) = (log_title(&self.notify_log, "Notify Log"), log_title(&self.stderr_log, "STDERR Log"));
 
-        f.render_widget(paragraph_gen(status_text_transform(&self.blocktree_status), "BlockTree Status", false), top_left_chunks[0]);
-        f.render_widget(paragraph_gen(status_text_transform(&self.network_status), "Network Status", false), top_left_chunks[1]);
-        f.render_widget(paragraph_gen(status_text_transform(&self.txpool_status), "TxPool Status", false), top_right_chunks[0]);
-        f.render_widget(paragraph_gen(status_text_transform(&self.miner_status), "Miner Status", false), top_right_chunks[1]);
-        f.render_widget(logger_gen(&self.notify_log, 20, "Notify Log", true), bottom_chunks[0]);
-        f.render_widget(logger_gen(&self.stderr_log, 20, "STDERR Log", true), bottom_chunks[1]);
+        // The status bar turns into a warning when the wallet or the node needs attention
+        let now_ms = clock().now_ms();
+        let status_bar_style = if self.status_bar.is_degraded(now_ms) { self.theme.warning() } else { self.theme.highlight() };
+        let pending_tx_count = self.pending_txs.len() + self.signing_txs.len();
+        f.render_widget(Paragraph::new(self.status_bar.line(pending_tx_count, now_ms)).style(status_bar_style), root_chunks[0]);
+        f.render_widget(paragraph_gen(status_text(&self.blocktree_status), "BlockTree Status", false), top_left_chunks[0]);
+        f.render_widget(paragraph_gen(status_text(&self.network_status), "Network Status", false), top_left_chunks[1]);
+        f.render_widget(paragraph_gen(status_text(&self.txpool_status), "TxPool Status", false), top_right_chunks[0]);
+        f.render_widget(paragraph_gen(status_text(&self.miner_status), "Miner Status", false), top_right_chunks[1]);
+        f.render_widget(logger_gen(&self.notify_log, LOG_PANEL_LINES, &notify_title, true), bottom_chunks[0]);
+        let mut statistics = self.chain_stats.clone();
+        statistics.extend(self.tx_latency.get_stats());
+        f.render_widget(paragraph_gen(status_text(&statistics), "Chain Statistics", false), bottom_chunks[1]);
+        f.render_widget(logger_gen(&self.stderr_log, LOG_PANEL_LINES, &stderr_title, true), bottom_chunks[2]);
 
         f.render_widget(middle_block, top_chunks[1]);
-        let is_sender_valid = App::validate_id_textarea(&mut self.textareas[0], self.textarea_choosing_idx == 0);
-        let is_receiver_valid = App::validate_id_textarea(&mut self.textareas[1], self.textarea_choosing_idx == 1);
-        let is_message_valid = App::validate_message_textarea(&mut self.textareas[2], self.textarea_choosing_idx == 2, self.user_balance);
+        let is_sender_valid = App::validate_id_textarea(&mut self.textareas[0], self.textarea_choosing_idx == 0, self.theme);
+        let is_receiver_valid = App::validate_id_textarea(&mut self.textareas[1], self.textarea_choosing_idx == 1, self.theme);
+        let is_message_valid = App::validate_message_textarea(&mut self.textareas[2], self.textarea_choosing_idx == 2, self.user_balance, self.theme);
         self.are_inputs_valid = is_sender_valid && is_receiver_valid && is_message_valid;
         f.render_widget(
-            Paragraph::new(format!("Balance: ${}", self.user_balance))
-                .alignment(Alignment::Left).style(Style::default().fg(Color::LightYellow)),
+            Paragraph::new(self.balance_text())
+                .alignment(Alignment::Left).style(self.theme.highlight()),
             top_middle_chunks[0]);
         f.render_widget(self.textareas[0].widget(), top_middle_chunks[1]);
         f.render_widget(self.textareas[1].widget(), top_middle_chunks[2]);
         f.render_widget(self.textareas[2].widget(), top_middle_chunks[3]);
         f.render_widget(
-            Paragraph::new("Press Up/Down to change input box\nPress ENTER to create transaction".to_string())
-                .alignment(Alignment::Left).style(Style::default().fg(Color::LightBlue)), 
+            Paragraph::new("Press Up/Down to change input box\nPress ENTER to create transaction (Ctrl+N for a batch)\nPress Ctrl+O for settings\nPress Ctrl+Y/Ctrl+B to copy input/tip id\nPress Ctrl+T to change theme\nPress Ctrl+R to reset the form\nPress Ctrl+L for the tx history".to_string())
+                .alignment(Alignment::Left).style(self.theme.hint()), 
             top_middle_chunks[4]);
+
+        if self.show_history {
+            self.draw_history(f);
+        }
+        if self.show_batch {
+            self.draw_batch(f);
+        }
+        if self.show_settings {
+            self.draw_settings(f);
+        }
      }
+
+    /// Draw the `Transaction History` panel in the middle of the screen, on top of the other panels.
+    fn draw_history<B: Backend>(&mut self, f: &mut Frame<B>) {
+        let size = f.size();
+        let area = Rect::new(size.width / 6, size.height / 6, size.width * 2 / 3, size.height * 2 / 3);
+        // The title of a scrolled history tells how far down it is
+        let title = if self.tx_history.offset() > 0 {
+            format!("Transaction History (+{}/{})", self.tx_history.offset(), self.tx_history.len())
+        } else {
+            format!("Transaction History ({})", self.tx_history.len())
+        };
+        let history_block = Block::default()
+            .title(title)
+            .borders(Borders::ALL);
+        let inner_rect = history_block.inner(area);
+        let history_chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .margin(0)
+            .constraints(
+                [
+                    Constraint::Min(0),
+                    Constraint::Length(1),
+                ].as_ref()
+            )
+            .split(inner_rect);
+
+        let lines = self.tx_history.visible(history_chunks[0].height as usize);
+        f.render_widget(Clear, area);
+        f.render_widget(history_block.style(self.theme.base()), area);
+        f.render_widget(Paragraph::new(lines.join("\n")).alignment(Alignment::Left), history_chunks[0]);
+        f.render_widget(
+            Paragraph::new("Press Up/Down or PageUp/PageDown to scroll, Esc to close".to_string())
+                .alignment(Alignment::Left).style(self.theme.hint()),
+            history_chunks[1]);
+    }
+
+    /// Draw the `Batch Payment` panel in the middle of the screen, on top of the other panels.
+    fn draw_batch<B: Backend>(&mut self, f: &mut Frame<B>) {
+        let size = f.size();
+        let area = Rect::new(size.width / 4, size.height / 4, size.width / 2, size.height / 2);
+        let batch_block = Block::default()
+            .title("Batch Payment")
+            .borders(Borders::ALL);
+        let inner_rect = batch_block.inner(area);
+        let batch_chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .margin(0)
+            .constraints(
+                [
+                    Constraint::Min(0),
+                    Constraint::Length(1),
+                    Constraint::Length(2),
+                ].as_ref()
+            )
+            .split(inner_rect);
+
+        // The payments are checked as they are typed, like the inputs of the transaction form
+        let (summary, style) = match batch::parse_payments(self.batch_textarea.lines()) {
+            Ok(payments) => (batch::summary(&payments), self.theme.good(true)),
+            Err(e) => (e, self.theme.bad(true)),
+        };
+        f.render_widget(Clear, area);
+        f.render_widget(batch_block.style(self.theme.base()), area);
+        f.render_widget(self.batch_textarea.widget(), batch_chunks[0]);
+        f.render_widget(Paragraph::new(summary).alignment(Alignment::Left).style(style), batch_chunks[1]);
+        f.render_widget(
+            Paragraph::new("Type one `receiver amount` per line, paid by the sender of the form\nPress Ctrl+S to create the batch, Esc to close".to_string())
+                .alignment(Alignment::Left).style(self.theme.hint()),
+            batch_chunks[2]);
+    }
+
+    /// Draw the `Settings` panel in the middle of the screen, on top of the other panels.
+    fn draw_settings<B: Backend>(&mut self, f: &mut Frame<B>) {
+        let size = f.size();
+        let area = Rect::new(size.width / 4, size.height / 4, size.width / 2, size.height / 2);
+        let settings_block = Block::default()
+            .title("Settings")
+            .borders(Borders::ALL);
+        let inner_rect = settings_block.inner(area);
+        let settings_chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .margin(0)
+            .constraints(
+                [
+                    Constraint::Min(0),
+                    Constraint::Length(3),
+                    Constraint::Length(2),
+                ].as_ref()
+            )
+            .split(inner_rect);
+
+        // Runtime-changeable parameters are marked with a `*`
+        let changeable = self.config_status.get("#runtime_changeable").cloned().unwrap_or_default();
+        let config_text = self.config_status.iter()
+            .filter(|(key, _)| !key.starts_with('#'))
+            .map(|(key, value)| {
+                let mark = if changeable.split(',').any(|k| k == key) { "*" } else { " " };
+                format!("{} {} = {}", mark, key, value)
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        self.settings_textarea.set_style(self.theme.good(true));
+        f.render_widget(Clear, area);
+        f.render_widget(settings_block.style(self.theme.base()), area);
+        f.render_widget(Paragraph::new(config_text).alignment(Alignment::Left), settings_chunks[0]);
+        f.render_widget(self.settings_textarea.widget(), settings_chunks[1]);
+        f.render_widget(
+            Paragraph::new("Type key=value of a * parameter (or :export-logs, :open, :export-tx, :import-tx, :trace) and press ENTER to apply\nPress Esc to close".to_string())
+                .alignment(Alignment::Left).style(self.theme.hint()),
+            settings_chunks[2]);
+    }
 }
 
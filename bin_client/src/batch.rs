@@ -0,0 +1,75 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+//! This file implements the parsing of the `Batch Payment` panel, where the user enters the payments of a batch
+//! transaction (one sender paying several receivers with one signature, see `Transaction::outputs` in lib_chain).
+
+use lib_chain::block::TxOutput;
+
+/// Parse the lines of the `Batch Payment` panel into the payments of a batch: one `<receiver> <amount>` per line, where
+/// the amount may start with `$`. Blank lines are skipped. A batch pays at least 2 receivers, each a positive amount.
+pub fn parse_payments(lines: &[String]) -> Result<Vec<TxOutput>, String> {
+    let mut payments = vec![];
+    for (index, line) in lines.iter().enumerate() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (receiver, amount) = match words.as_slice() {
+            [] => continue,
+            [receiver, amount] => (receiver, amount),
+            _ => return Err(format!("line {}: expected <receiver> <amount>", index + 1)),
+        };
+        let amount = amount
+            .trim_start_matches('$')
+            .parse::<i64>()
+            .ok()
+            .filter(|amount| *amount > 0)
+            .ok_or_else(|| format!("line {}: invalid amount {:?}", index + 1, amount))?;
+        payments.push(TxOutput {
+            receiver: receiver.to_string(),
+            amount,
+        });
+    }
+    if payments.len() < 2 {
+        return Err("a batch pays at least 2 receivers".to_string());
+    }
+    Ok(payments)
+}
+
+/// The summary of the payments of a batch shown under the `Batch Payment` panel.
+pub fn summary(payments: &[TxOutput]) -> String {
+    let total: i64 = payments.iter().map(|payment| payment.amount).sum();
+    format!("{} payments, total ${}", payments.len(), total)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_payments, summary};
+
+    /// Each line is a receiver and an amount; the batch needs 2 valid payments.
+    #[test]
+    fn test_parse_payments() {
+        let lines = |text: &str| {
+            text.lines()
+                .map(|line| line.to_string())
+                .collect::<Vec<String>>()
+        };
+        let payments = parse_payments(&lines("BOB 10\n\n  CAROL $20  \n")).unwrap();
+        assert_eq!(
+            payments
+                .iter()
+                .map(|payment| (payment.receiver.as_str(), payment.amount))
+                .collect::<Vec<_>>(),
+            vec![("BOB", 10), ("CAROL", 20)]
+        );
+        assert_eq!(summary(&payments), "2 payments, total $30");
+
+        assert!(parse_payments(&lines("BOB 10")).is_err());
+        assert_eq!(
+            parse_payments(&lines("BOB 10\nCAROL 0")).unwrap_err(),
+            "line 2: invalid amount \"0\""
+        );
+        assert!(parse_payments(&lines("BOB 10\nCAROL abc")).is_err());
+        assert!(parse_payments(&lines("BOB 10\nCAROL")).is_err());
+        assert!(parse_payments(&lines("BOB 10\nCAROL 5 more")).is_err());
+    }
+}
@@ -0,0 +1,314 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+//! This file implements the multi-node dashboard mode of the client.
+//! In this mode, the client does not start bin_nakamoto or bin_wallet. Instead, it connects to the IPC sockets
+//! of several running bin_nakamoto processes (see `ipc_listen_addr` in their config) and shows their chains side by side.
+//! A node whose working block differs from the one shared by most nodes is highlighted, so that a node falling out
+//! of consensus is obvious during a demo.
+
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crossterm::{
+    event::{self, Event, KeyCode, KeyModifiers},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use tui::{
+    backend::{Backend, CrosstermBackend},
+    layout::{Alignment, Constraint, Direction, Layout},
+    widgets::{Block, Borders, Paragraph},
+    Frame, Terminal,
+};
+
+use lib_chain::block::ChainStatus;
+
+use crate::app::{status_value_text, StatusFields, Theme};
+use crate::{IPCMessageReqNakamoto, IPCMessageRespNakamoto};
+
+/// How often the nodes are polled for their status.
+const POLL_INTERVAL_MS: u64 = 500;
+
+/// The number of characters of block ids shown in the dashboard.
+const SHORT_BLOCK_ID_LEN: usize = 16;
+
+/// The state of one node as last reported through its IPC socket.
+pub struct NodeView {
+    /// the address of the IPC socket of the node ("ip:port")
+    pub addr: String,
+    /// whether the IPC socket is currently connected
+    pub connected: bool,
+    /// the status of the blocktree of the node, once received
+    pub chain_status: Option<ChainStatus>,
+    /// the status of the transaction pool of the node
+    pub txpool_status: StatusFields,
+}
+
+impl NodeView {
+    /// The working block id of the node, if known.
+    fn working_id(&self) -> Option<&String> {
+        self.chain_status
+            .as_ref()
+            .map(|status| &status.working_block_id)
+    }
+}
+
+/// The struct to represent the dashboard of several nodes.
+pub struct Dashboard {
+    /// the nodes, in the order they are displayed
+    pub nodes: Vec<NodeView>,
+    /// whether the user wants to quit the dashboard or not.
+    pub should_quit: bool,
+    /// the color theme of the dashboard.
+    pub theme: Theme,
+}
+
+impl Dashboard {
+    /// Create a dashboard for the IPC sockets at the given addresses ("ip:port").
+    pub fn new(addrs: Vec<String>) -> Dashboard {
+        Dashboard {
+            nodes: addrs
+                .into_iter()
+                .map(|addr| NodeView {
+                    addr,
+                    connected: false,
+                    chain_status: None,
+                    txpool_status: BTreeMap::new(),
+                })
+                .collect(),
+            should_quit: false,
+            theme: Theme::Dark,
+        }
+    }
+
+    /// The working block id shared by most connected nodes, if any node reported one.
+    pub fn consensus_tip(&self) -> Option<String> {
+        let mut counts: BTreeMap<&String, usize> = BTreeMap::new();
+        for node in self.nodes.iter().filter(|node| node.connected) {
+            if let Some(working_id) = node.working_id() {
+                *counts.entry(working_id).or_insert(0) += 1;
+            }
+        }
+        counts
+            .into_iter()
+            .max_by_key(|(_, count)| *count)
+            .map(|(working_id, _)| working_id.clone())
+    }
+
+    /// Draw the nodes side by side.
+    pub fn draw<B: Backend>(&self, f: &mut Frame<B>) {
+        f.render_widget(Block::default().style(self.theme.base()), f.size());
+        let root_chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .margin(1)
+            .constraints([Constraint::Length(1), Constraint::Min(0)].as_ref())
+            .split(f.size());
+        let node_count = self.nodes.len().max(1) as u32;
+        let node_chunks = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints(
+                self.nodes
+                    .iter()
+                    .map(|_| Constraint::Ratio(1, node_count))
+                    .collect::<Vec<_>>(),
+            )
+            .split(root_chunks[1]);
+
+        f.render_widget(
+            Paragraph::new("Multi-node dashboard (press Esc to quit, Ctrl+T to change theme)"),
+            root_chunks[0],
+        );
+
+        let consensus_tip = self.consensus_tip();
+        let short = |id: Option<&String>| -> String {
+            id.map(|id| id.chars().take(SHORT_BLOCK_ID_LEN).collect())
+                .unwrap_or_else(|| "?".to_string())
+        };
+        for (node, chunk) in self.nodes.iter().zip(node_chunks.iter()) {
+            let (state, style) = if !node.connected {
+                ("DISCONNECTED", self.theme.bad(true))
+            } else if node.working_id() != consensus_tip.as_ref() {
+                ("OUT OF CONSENSUS", self.theme.warning())
+            } else {
+                ("in consensus", self.theme.good(false))
+            };
+            let chain_field = |field: fn(&ChainStatus) -> String| {
+                node.chain_status
+                    .as_ref()
+                    .map_or_else(|| "?".to_string(), field)
+            };
+            let text = [
+                format!("state: {}", state),
+                format!(
+                    "height: {}",
+                    chain_field(|status| status.height.to_string())
+                ),
+                format!("tip: {}", short(node.working_id())),
+                format!(
+                    "finalized: {}",
+                    short(
+                        node.chain_status
+                            .as_ref()
+                            .map(|status| &status.finalized_block_id)
+                    )
+                ),
+                format!(
+                    "#blocks: {}",
+                    chain_field(|status| status.block_count.to_string())
+                ),
+                format!(
+                    "#orphans: {}",
+                    chain_field(|status| status.orphan_count.to_string())
+                ),
+                format!(
+                    "txpool: {}",
+                    node.txpool_status
+                        .get("tx_count")
+                        .map_or_else(|| "?".to_string(), status_value_text)
+                ),
+            ]
+            .join("\n");
+            let paragraph = Paragraph::new(text)
+                .style(style)
+                .block(
+                    Block::default()
+                        .title(node.addr.clone())
+                        .borders(Borders::ALL),
+                )
+                .alignment(Alignment::Left);
+            f.render_widget(paragraph, *chunk);
+        }
+    }
+}
+
+/// Poll one node for its status until the dashboard quits, reconnecting whenever the connection is lost.
+fn poll_node(dashboard_p: Arc<Mutex<Dashboard>>, index: usize) {
+    let addr = dashboard_p.lock().unwrap().nodes[index].addr.clone();
+    while !dashboard_p.lock().unwrap().should_quit {
+        if let Ok(stream) = TcpStream::connect(&addr) {
+            dashboard_p.lock().unwrap().nodes[index].connected = true;
+            let _ = poll_connection(&dashboard_p, index, stream);
+            dashboard_p.lock().unwrap().nodes[index].connected = false;
+        }
+        thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+    }
+}
+
+/// Request the status of a node over one connection and record the responses, until the connection fails.
+fn poll_connection(
+    dashboard_p: &Arc<Mutex<Dashboard>>,
+    index: usize,
+    mut stream: TcpStream,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let requests = [
+        IPCMessageReqNakamoto::RequestChainStatus,
+        IPCMessageReqNakamoto::RequestTxPoolStatus,
+    ];
+    loop {
+        for request in requests.iter() {
+            let mut to_send = serde_json::to_string(request).unwrap();
+            to_send.push('\n');
+            stream.write_all(to_send.as_bytes())?;
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let mut dashboard = dashboard_p.lock().unwrap();
+            if dashboard.should_quit {
+                return Ok(());
+            }
+            match serde_json::from_str(&line) {
+                Ok(IPCMessageRespNakamoto::ChainStatus(status)) => {
+                    dashboard.nodes[index].chain_status = Some(status);
+                }
+                Ok(IPCMessageRespNakamoto::TxPoolStatus(status)) => {
+                    dashboard.nodes[index].txpool_status = status;
+                }
+                _ => {}
+            }
+        }
+        thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+    }
+}
+
+/// Run the dashboard for the IPC sockets at the given addresses ("ip:port") until the user presses Esc.
+pub fn run(addrs: Vec<String>, theme: Theme) -> Result<(), io::Error> {
+    let mut dashboard = Dashboard::new(addrs);
+    dashboard.theme = theme;
+    let dashboard_p = Arc::new(Mutex::new(dashboard));
+    let node_count = dashboard_p.lock().unwrap().nodes.len();
+    for index in 0..node_count {
+        let dashboard_p = dashboard_p.clone();
+        thread::spawn(move || poll_node(dashboard_p, index));
+    }
+
+    enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    execute!(stdout, EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+    loop {
+        terminal.draw(|f| dashboard_p.lock().unwrap().draw(f))?;
+        if event::poll(Duration::from_millis(POLL_INTERVAL_MS))? {
+            if let Event::Key(key) = event::read()? {
+                let mut dashboard = dashboard_p.lock().unwrap();
+                match key.code {
+                    KeyCode::Esc => {
+                        dashboard.should_quit = true;
+                        break;
+                    }
+                    KeyCode::Char('t') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                        dashboard.theme = dashboard.theme.next();
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::Dashboard;
+    use lib_chain::block::ChainStatus;
+
+    /// The consensus tip is the working block shared by most connected nodes.
+    #[test]
+    fn test_consensus_tip() {
+        let mut dashboard = Dashboard::new(vec![
+            "127.0.0.1:9101".to_string(),
+            "127.0.0.1:9102".to_string(),
+            "127.0.0.1:9103".to_string(),
+        ]);
+        assert_eq!(dashboard.consensus_tip(), None);
+        for (node, tip) in dashboard.nodes.iter_mut().zip(["A", "B", "B"]) {
+            node.connected = true;
+            node.chain_status = Some(ChainStatus {
+                block_count: 2,
+                orphan_count: 0,
+                root_id: "0".to_string(),
+                working_block_id: tip.to_string(),
+                height: 1,
+                finalized_block_id: "0".to_string(),
+                finalized_height: 0,
+                pruned_blocks: 0,
+                pruned_bytes: 0,
+            });
+        }
+        assert_eq!(dashboard.consensus_tip(), Some("B".to_string()));
+        // disconnected nodes do not count
+        dashboard.nodes[1].connected = false;
+        dashboard.nodes[2].connected = false;
+        assert_eq!(dashboard.consensus_tip(), Some("A".to_string()));
+    }
+}
@@ -0,0 +1,142 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+//! This file implements the deep links of the client: a transaction request written as a URI-like string,
+//! `nakamoto:send?to=<addr>&amount=<n>&memo=<m>`, that pre-fills the transaction form. A link can be given as the
+//! optional argument of bin_client, with the `Open` bot command, or with `:open <link>` in the settings panel, so that
+//! scripted demos and external tools can prepare a payment for the user to confirm.
+//! The values are percent-encoded (`+` is a space), `memo` is optional and the other parameters are required.
+
+/// The scheme of the links, telling them apart from the other arguments of bin_client.
+pub const LINK_SCHEME: &str = "nakamoto:";
+
+/// The scheme and path of a transaction request link.
+pub const SEND_LINK_PREFIX: &str = "nakamoto:send?";
+
+/// A transaction request parsed from a link.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SendLink {
+    /// the user id of the receiver
+    pub to: String,
+    /// the amount to send, always positive
+    pub amount: i64,
+    /// the comment of the message, if any
+    pub memo: Option<String>,
+}
+
+impl SendLink {
+    /// Parse a link, e.g. `nakamoto:send?to=MDgCMQ...&amount=10&memo=Lunch`.
+    /// Unknown or repeated parameters are errors, so that a mistyped link is not half applied.
+    pub fn parse(link: &str) -> Result<SendLink, String> {
+        let query = link
+            .trim()
+            .strip_prefix(SEND_LINK_PREFIX)
+            .ok_or_else(|| format!("A link must start with {:?}", SEND_LINK_PREFIX))?;
+        let (mut to, mut amount, mut memo) = (None, None, None);
+        for param in query.split('&').filter(|param| !param.is_empty()) {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            let slot = match key {
+                "to" => &mut to,
+                "amount" => &mut amount,
+                "memo" => &mut memo,
+                _ => return Err(format!("Unknown parameter {:?}", key)),
+            };
+            if slot.is_some() {
+                return Err(format!("Repeated parameter {:?}", key));
+            }
+            *slot = Some(percent_decode(value)?);
+        }
+        let to = to
+            .filter(|to| !to.is_empty())
+            .ok_or_else(|| "Missing parameter \"to\"".to_string())?;
+        let amount = amount.ok_or_else(|| "Missing parameter \"amount\"".to_string())?;
+        let amount = match amount.parse::<i64>() {
+            Ok(amount) if amount > 0 => amount,
+            _ => return Err(format!("Invalid amount {:?}", amount)),
+        };
+        Ok(SendLink {
+            to,
+            amount,
+            memo: memo.filter(|memo| !memo.is_empty()),
+        })
+    }
+
+    /// The message of the transaction form, e.g. `SEND $10   // Lunch`.
+    pub fn message(&self) -> String {
+        match &self.memo {
+            Some(memo) => format!("SEND ${}   // {}", self.amount, memo),
+            None => format!("SEND ${}", self.amount),
+        }
+    }
+}
+
+/// Decode a percent-encoded value, where `+` is a space.
+fn percent_decode(value: &str) -> Result<String, String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut chars = value.bytes();
+    while let Some(b) = chars.next() {
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = [chars.next(), chars.next()];
+                let byte = match hex {
+                    [Some(hi), Some(lo)] => std::str::from_utf8(&[hi, lo])
+                        .ok()
+                        .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+                    _ => None,
+                };
+                bytes.push(byte.ok_or_else(|| format!("Invalid percent-encoding in {:?}", value))?);
+            }
+            b => bytes.push(b),
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| format!("Invalid UTF-8 in {:?}", value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::SendLink;
+
+    /// A link gives the receiver and the message of the form, and a malformed link is rejected as a whole.
+    #[test]
+    fn test_parse_send_link() {
+        let link =
+            SendLink::parse("nakamoto:send?to=MDgCMQ%2BAb%3D%3D&amount=10&memo=Lunch+at+caf%C3%A9")
+                .unwrap();
+        assert_eq!(link.to, "MDgCMQ+Ab==");
+        assert_eq!(link.amount, 10);
+        assert_eq!(link.message(), "SEND $10   // Lunch at café");
+        let link = SendLink::parse(" nakamoto:send?amount=3&to=B ").unwrap();
+        assert_eq!(
+            link,
+            SendLink {
+                to: "B".to_string(),
+                amount: 3,
+                memo: None
+            }
+        );
+        assert_eq!(link.message(), "SEND $3");
+
+        for invalid in [
+            "send?to=B&amount=3",
+            "nakamoto:send?to=B",
+            "nakamoto:send?amount=3",
+            "nakamoto:send?to=&amount=3",
+            "nakamoto:send?to=B&amount=0",
+            "nakamoto:send?to=B&amount=-3",
+            "nakamoto:send?to=B&amount=3x",
+            "nakamoto:send?to=B&amount=3&amount=4",
+            "nakamoto:send?to=B&amount=3&fee=1",
+            "nakamoto:send?to=B&amount=3&memo=%G0",
+            "nakamoto:send?to=B&amount=3&memo=%4",
+            "nakamoto:send?to=B&amount=3&memo=%FF",
+        ] {
+            assert!(
+                SendLink::parse(invalid).is_err(),
+                "{} should be rejected",
+                invalid
+            );
+        }
+    }
+}
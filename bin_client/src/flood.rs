@@ -0,0 +1,232 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+//! This file implements the FloodRun struct, which records what happens to the transactions generated by the bot
+//! Flood command (see `BotCommand::Flood`): how many were published, accepted by the pool, and how long they took
+//! to be included in a block. Its report is written to the client log, for the performance section of the report.
+
+use crate::latency;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// The amounts of the flood transactions, used in turn. They are small so that a long flood does not run out of money.
+pub const FLOOD_AMOUNTS: [i64; 5] = [1, 2, 3, 4, 5];
+
+/// How long the Flood command waits for the outstanding transactions to be included in a block after the last one is generated.
+pub const FLOOD_DRAIN_MS: u64 = 30000;
+
+/// The upper bounds (inclusive, in ms) of the buckets of the confirmation latency histogram. The last bucket holds the rest.
+pub const FLOOD_LATENCY_BUCKETS_MS: [u64; 7] = [500, 1000, 2000, 5000, 10000, 30000, 60000];
+
+/// The statistics of one Flood command.
+pub struct FloodRun {
+    /// the target rate (transactions per second)
+    pub tx_per_second: u64,
+    /// the target duration of the generation (ms)
+    pub duration_ms: u64,
+    /// when the first transaction was generated
+    started: Instant,
+    /// how long the generation actually took (None while generating)
+    generation_time: Option<Duration>,
+    /// the number of generated transactions
+    pub created: u64,
+    /// the number of transactions signed and published to the pool
+    pub published: u64,
+    /// the number of transactions not signed, because the simulation or the wallet rejected them
+    pub not_signed: u64,
+    /// the number of published transactions rejected by the pool
+    pub pool_rejected: u64,
+    /// the creation time of the transactions not included in a block nor rejected yet, by data_string
+    outstanding: HashMap<String, Instant>,
+    /// the confirmation latencies (from creation to inclusion in a block) of the included transactions (ms)
+    pub latencies_ms: Vec<u64>,
+}
+
+impl FloodRun {
+    /// Start recording a flood at the given rate and duration.
+    pub fn new(tx_per_second: u64, duration_ms: u64, now: Instant) -> FloodRun {
+        FloodRun {
+            tx_per_second,
+            duration_ms,
+            started: now,
+            generation_time: None,
+            created: 0,
+            published: 0,
+            not_signed: 0,
+            pool_rejected: 0,
+            outstanding: HashMap::new(),
+            latencies_ms: vec![],
+        }
+    }
+
+    /// Record a generated transaction.
+    pub fn record_created(&mut self, data_string: String, now: Instant) {
+        self.created += 1;
+        self.outstanding.insert(data_string, now);
+    }
+
+    /// Record the end of the generation.
+    pub fn record_generation_end(&mut self, now: Instant) {
+        self.generation_time = Some(now.duration_since(self.started));
+    }
+
+    /// Record that the transaction was signed and published. Transactions of other commands are ignored.
+    pub fn record_published(&mut self, data_string: &str) {
+        if self.outstanding.contains_key(data_string) {
+            self.published += 1;
+        }
+    }
+
+    /// Record that the transaction was not signed.
+    pub fn record_not_signed(&mut self, data_string: &str) {
+        if self.outstanding.remove(data_string).is_some() {
+            self.not_signed += 1;
+        }
+    }
+
+    /// Record that the pool rejected the published transaction.
+    pub fn record_pool_rejected(&mut self, data_string: &str) {
+        if self.outstanding.remove(data_string).is_some() {
+            self.pool_rejected += 1;
+        }
+    }
+
+    /// Record that the transaction is included in a block of the working chain.
+    pub fn record_included(&mut self, data_string: &str, now: Instant) {
+        if let Some(created) = self.outstanding.remove(data_string) {
+            self.latencies_ms
+                .push(now.duration_since(created).as_millis() as u64);
+        }
+    }
+
+    /// The number of transactions neither included in a block nor rejected yet.
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.len()
+    }
+
+    /// The given percentile (0 to 100) of the confirmation latencies (ms), if any transaction was included.
+    pub fn latency_percentile(&self, percentile: u64) -> Option<u64> {
+        latency::percentile(&self.latencies_ms, percentile)
+    }
+
+    /// The number of confirmation latencies in each bucket of `FLOOD_LATENCY_BUCKETS_MS` (and one more bucket for the rest).
+    pub fn latency_histogram(&self) -> Vec<u64> {
+        let mut histogram = vec![0; FLOOD_LATENCY_BUCKETS_MS.len() + 1];
+        for latency in self.latencies_ms.iter() {
+            let bucket = FLOOD_LATENCY_BUCKETS_MS
+                .iter()
+                .position(|bound| latency <= bound)
+                .unwrap_or(FLOOD_LATENCY_BUCKETS_MS.len());
+            histogram[bucket] += 1;
+        }
+        histogram
+    }
+
+    /// The report of the flood, one line per aspect.
+    pub fn report(&self) -> Vec<String> {
+        let generation_secs = self
+            .generation_time
+            .unwrap_or_default()
+            .as_secs_f64()
+            .max(0.001);
+        let percent = |part: u64, whole: u64| 100.0 * part as f64 / whole.max(1) as f64;
+        let accepted = self.published - self.pool_rejected.min(self.published);
+        let ms = |latency: Option<u64>| match latency {
+            Some(latency) => format!("{} ms", latency),
+            None => "-".to_string(),
+        };
+        let mut histogram = vec![];
+        let mut lower = 0;
+        for (bound, count) in FLOOD_LATENCY_BUCKETS_MS
+            .iter()
+            .zip(self.latency_histogram())
+        {
+            histogram.push(format!("{}-{} ms: {}", lower, bound, count));
+            lower = bound + 1;
+        }
+        histogram.push(format!(
+            ">{} ms: {}",
+            FLOOD_LATENCY_BUCKETS_MS.last().unwrap(),
+            self.latency_histogram().last().unwrap()
+        ));
+        vec![
+            format!(
+                "target {} tx/s for {} ms: {} txs generated in {:.1} s",
+                self.tx_per_second, self.duration_ms, self.created, generation_secs
+            ),
+            format!(
+                "published {} ({:.1} tx/s), {} not signed",
+                self.published,
+                self.published as f64 / generation_secs,
+                self.not_signed
+            ),
+            format!(
+                "pool accepted {}/{} ({:.1}%), rejected {}",
+                accepted,
+                self.published,
+                percent(accepted, self.published),
+                self.pool_rejected
+            ),
+            format!(
+                "confirmed {}, still pending {}, latency p50 {}, p95 {}, max {}",
+                self.latencies_ms.len(),
+                self.outstanding(),
+                ms(self.latency_percentile(50)),
+                ms(self.latency_percentile(95)),
+                ms(self.latencies_ms.iter().max().copied())
+            ),
+            format!("latency histogram: {}", histogram.join(", ")),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::FloodRun;
+    use std::time::{Duration, Instant};
+
+    /// Every transaction ends up in one of the counters, and only the included ones have a latency.
+    #[test]
+    fn test_flood_run() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut run = FloodRun::new(10, 1000, start);
+        for i in 0..10 {
+            run.record_created(format!("tx{}", i), at(i * 100));
+        }
+        run.record_generation_end(at(1000));
+        run.record_not_signed("tx0");
+        for i in 1..10 {
+            run.record_published(&format!("tx{}", i));
+        }
+        run.record_published("not a flood tx");
+        run.record_pool_rejected("tx1");
+        for i in 2..9 {
+            run.record_included(&format!("tx{}", i), at(i * 100 + i * 1000));
+        }
+        run.record_included("tx2", at(99999));
+
+        assert_eq!((run.created, run.published), (10, 9));
+        assert_eq!((run.not_signed, run.pool_rejected), (1, 1));
+        assert_eq!(run.outstanding(), 1);
+        assert_eq!(
+            run.latencies_ms,
+            vec![2000, 3000, 4000, 5000, 6000, 7000, 8000]
+        );
+        assert_eq!(run.latency_percentile(50), Some(5000));
+        assert_eq!(run.latency_percentile(95), Some(8000));
+        assert_eq!(run.latency_histogram(), vec![0, 0, 1, 3, 3, 0, 0, 0]);
+        let report = run.report();
+        assert!(report[1].starts_with("published 9 (9.0 tx/s)"));
+        assert!(report[2].starts_with("pool accepted 8/9"));
+    }
+
+    /// Without any included transaction there is no latency to report.
+    #[test]
+    fn test_flood_run_empty() {
+        let run = FloodRun::new(1, 0, Instant::now());
+        assert_eq!(run.latency_percentile(50), None);
+        assert!(run.report()[3].contains("p50 -"));
+    }
+}
@@ -0,0 +1,189 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+//! This file implements the transaction history of the user, shown in the `Transaction History` panel (Ctrl+L): the
+//! transactions sent or received by the user, as listed by bin_nakamoto for the `RequestAddressHistory` IPC request
+//! (the ones in the tx pool, then the ones of the longest chain, the most recent first). The panel is refreshed with the
+//! status while it is shown, and scrolled with Up/Down and PageUp/PageDown.
+
+use serde::{Deserialize, Serialize};
+
+/// The direction of a transaction relative to the user.
+/// It is the same as the `TxDirection` enum type in the bin_nakamoto process.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum TxDirection {
+    Sent,
+    Received,
+}
+
+/// A transaction sent or received by the user.
+/// It is the same as the `AddressHistoryEntry` struct type in the bin_nakamoto process.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AddressHistoryEntry {
+    pub tx_id: String,
+    pub direction: TxDirection,
+    pub counterparty: String,
+    pub amount: i64,
+    pub fee: i64,
+    pub confirmations: u64,
+    pub finalized: bool,
+}
+
+/// The number of characters of the counterparty address shown in a line of the history.
+const COUNTERPARTY_PREVIEW_LEN: usize = 16;
+
+/// The transactions of the user, the most recent first, and how far the panel is scrolled.
+#[derive(Debug, Default)]
+pub struct TxHistory {
+    /// the transactions, the most recent first
+    entries: Vec<AddressHistoryEntry>,
+    /// the number of most recent transactions hidden by scrolling down (0 shows the most recent ones)
+    offset: usize,
+    /// whether the history has been received from bin_nakamoto at least once
+    loaded: bool,
+}
+
+impl TxHistory {
+    /// Replace the transactions with the ones just received, keeping the scroll position within them.
+    pub fn set_entries(&mut self, entries: Vec<AddressHistoryEntry>) {
+        self.entries = entries;
+        self.offset = self.offset.min(self.entries.len().saturating_sub(1));
+        self.loaded = true;
+    }
+
+    /// The number of transactions.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The number of most recent transactions hidden by scrolling down.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Scroll towards the older transactions by `count` lines, keeping at least one transaction shown.
+    pub fn scroll_down(&mut self, count: usize) {
+        self.offset = (self.offset + count).min(self.entries.len().saturating_sub(1));
+    }
+
+    /// Scroll towards the most recent transactions by `count` lines.
+    pub fn scroll_up(&mut self, count: usize) {
+        self.offset = self.offset.saturating_sub(count);
+    }
+
+    /// The `count` lines shown at the current scroll position, the most recent transaction first.
+    pub fn visible(&self, count: usize) -> Vec<String> {
+        if !self.loaded {
+            return vec!["Loading...".to_string()];
+        }
+        if self.entries.is_empty() {
+            return vec!["No transactions yet".to_string()];
+        }
+        self.entries
+            .iter()
+            .skip(self.offset)
+            .take(count)
+            .map(format_entry)
+            .collect()
+    }
+}
+
+/// Format a transaction as one line, e.g. `-$300 to MDgCMQDZDExOs97s.. (fee $1)  3 conf, finalized`.
+pub fn format_entry(entry: &AddressHistoryEntry) -> String {
+    let (sign, preposition) = match entry.direction {
+        TxDirection::Sent => ("-", "to"),
+        TxDirection::Received => ("+", "from"),
+    };
+    let counterparty = match entry
+        .counterparty
+        .char_indices()
+        .nth(COUNTERPARTY_PREVIEW_LEN)
+    {
+        Some((end, _)) => format!("{}..", &entry.counterparty[..end]),
+        None => entry.counterparty.clone(),
+    };
+    let fee = match entry.fee {
+        0 => String::new(),
+        fee => format!(" (fee ${})", fee),
+    };
+    let status = match (entry.confirmations, entry.finalized) {
+        (0, _) => "pending in tx pool".to_string(),
+        (confirmations, true) => format!("{} conf, finalized", confirmations),
+        (confirmations, false) => format!("{} conf", confirmations),
+    };
+    format!(
+        "{}${} {} {}{}  {}",
+        sign, entry.amount, preposition, counterparty, fee, status
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{format_entry, AddressHistoryEntry, TxDirection, TxHistory};
+
+    fn entry(direction: TxDirection, confirmations: u64, finalized: bool) -> AddressHistoryEntry {
+        AddressHistoryEntry {
+            tx_id: "tx".to_string(),
+            direction,
+            counterparty:
+                "MDgCMQDZDExOs97sRTnQLYtgFjDKpDzmO7Uo5HPP62u6MDimXBpZtGxtwa8dhJe5NBIsJjUCAwEAAQ=="
+                    .to_string(),
+            amount: 300,
+            fee: 0,
+            confirmations,
+            finalized,
+        }
+    }
+
+    /// The lines tell the direction, the counterparty, the amount and how confirmed each transaction is.
+    #[test]
+    fn test_format_entry() {
+        assert_eq!(
+            format_entry(&entry(TxDirection::Sent, 0, false)),
+            "-$300 to MDgCMQDZDExOs97s..  pending in tx pool"
+        );
+        assert_eq!(
+            format_entry(&entry(TxDirection::Received, 2, false)),
+            "+$300 from MDgCMQDZDExOs97s..  2 conf"
+        );
+        let mut finalized = entry(TxDirection::Sent, 7, true);
+        finalized.fee = 1;
+        finalized.counterparty = "GENESIS".to_string();
+        assert_eq!(
+            format_entry(&finalized),
+            "-$300 to GENESIS (fee $1)  7 conf, finalized"
+        );
+    }
+
+    /// The history scrolls within its transactions, and stays within them when they are refreshed.
+    #[test]
+    fn test_tx_history_scroll() {
+        let mut history = TxHistory::default();
+        assert_eq!(history.visible(10), vec!["Loading..."]);
+        history.set_entries(vec![]);
+        assert_eq!(history.visible(10), vec!["No transactions yet"]);
+
+        history.set_entries(
+            (0..5)
+                .map(|i| entry(TxDirection::Received, i, false))
+                .collect(),
+        );
+        assert_eq!(history.visible(2).len(), 2);
+        history.scroll_down(10);
+        assert_eq!(history.offset(), 4);
+        assert_eq!(
+            history.visible(2),
+            vec![format_entry(&entry(TxDirection::Received, 4, false))]
+        );
+        history.set_entries(
+            (0..3)
+                .map(|i| entry(TxDirection::Received, i, false))
+                .collect(),
+        );
+        assert_eq!(history.offset(), 2);
+        history.scroll_up(1);
+        assert_eq!(history.offset(), 1);
+        assert_eq!(history.len(), 3);
+    }
+}
@@ -0,0 +1,156 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+//! This file implements the TxLatencyTracker struct, which measures how long the transactions created by this client
+//! take to be included in a block and to be finalized. The creation times are only kept in the client:
+//! nothing is added to the transactions themselves, so the consensus data is unchanged.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::Instant;
+
+/// The number of most recent latencies the percentiles are computed over.
+pub const LATENCY_WINDOW: usize = 1000;
+
+/// The given percentile (0 to 100) of the values (nearest-rank), or None if there is no value.
+pub fn percentile(values: &[u64], percentile: u64) -> Option<u64> {
+    let mut values = values.to_vec();
+    values.sort_unstable();
+    let rank = (values.len() as u64 * percentile.min(100)).div_ceil(100) as usize;
+    values.get(rank.saturating_sub(1)).copied()
+}
+
+/// The times of one transaction created by this client.
+struct TxTimes {
+    /// when the transaction was created
+    created: Instant,
+    /// when the transaction was first seen in a block of the working chain
+    included: Option<Instant>,
+}
+
+/// The confirmation latencies of the transactions created by this client.
+#[derive(Default)]
+pub struct TxLatencyTracker {
+    /// the transactions not finalized yet, by data_string
+    watched: HashMap<String, TxTimes>,
+    /// the latencies (ms) from creation to the first inclusion in a block, most recent last
+    inclusion_ms: VecDeque<u64>,
+    /// the latencies (ms) from creation to finalization, most recent last
+    finality_ms: VecDeque<u64>,
+}
+
+impl TxLatencyTracker {
+    /// Start watching a transaction created now.
+    pub fn record_created(&mut self, data_string: String, now: Instant) {
+        self.watched.insert(
+            data_string,
+            TxTimes {
+                created: now,
+                included: None,
+            },
+        );
+    }
+
+    /// Record that the transaction is in a block of the working chain. Only the first inclusion counts.
+    pub fn record_included(&mut self, data_string: &str, now: Instant) {
+        if let Some(times) = self.watched.get_mut(data_string) {
+            if times.included.is_none() {
+                times.included = Some(now);
+                push_latency(
+                    &mut self.inclusion_ms,
+                    now.duration_since(times.created).as_millis() as u64,
+                );
+            }
+        }
+    }
+
+    /// Record that the transaction is in a finalized block, and stop watching it.
+    pub fn record_finalized(&mut self, data_string: &str, now: Instant) {
+        if let Some(times) = self.watched.remove(data_string) {
+            push_latency(
+                &mut self.finality_ms,
+                now.duration_since(times.created).as_millis() as u64,
+            );
+        }
+    }
+
+    /// Stop watching a transaction that was rejected before reaching the pool or by the pool.
+    pub fn forget(&mut self, data_string: &str) {
+        self.watched.remove(data_string);
+    }
+
+    /// The transactions that are in a block but not finalized yet, to be checked for finality.
+    pub fn awaiting_finality(&self) -> Vec<String> {
+        self.watched
+            .iter()
+            .filter(|(_, times)| times.included.is_some())
+            .map(|(data_string, _)| data_string.clone())
+            .collect()
+    }
+
+    /// The p50/p95 latencies (ms) to inclusion and to finality, for the statistics panel.
+    pub fn get_stats(&self) -> BTreeMap<String, String> {
+        let mut stats = BTreeMap::new();
+        let (inclusion_ms, finality_ms): (Vec<u64>, Vec<u64>) = (
+            self.inclusion_ms.iter().copied().collect(),
+            self.finality_ms.iter().copied().collect(),
+        );
+        for (name, latencies) in [("inclusion", &inclusion_ms), ("finality", &finality_ms)] {
+            for p in [50, 95] {
+                let value = percentile(latencies, p).map_or("-".to_string(), |ms| ms.to_string());
+                stats.insert(format!("tx_{}_p{}_ms", name, p), value);
+            }
+        }
+        stats.insert("#tx_watched".to_string(), self.watched.len().to_string());
+        stats
+    }
+}
+
+/// Add a latency to a window of the last `LATENCY_WINDOW` latencies.
+fn push_latency(window: &mut VecDeque<u64>, latency_ms: u64) {
+    if window.len() == LATENCY_WINDOW {
+        window.pop_front();
+    }
+    window.push_back(latency_ms);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{percentile, TxLatencyTracker};
+    use std::time::{Duration, Instant};
+
+    /// The percentiles use the nearest rank.
+    #[test]
+    fn test_percentile() {
+        assert_eq!(percentile(&[], 50), None);
+        assert_eq!(percentile(&[7], 95), Some(7));
+        let values: Vec<u64> = (1..=20).rev().collect();
+        assert_eq!(percentile(&values, 50), Some(10));
+        assert_eq!(percentile(&values, 95), Some(19));
+        assert_eq!(percentile(&values, 100), Some(20));
+    }
+
+    /// A transaction is measured from creation to its first inclusion and to its finalization, then no longer watched.
+    #[test]
+    fn test_tx_latency_tracker() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut tracker = TxLatencyTracker::default();
+        tracker.record_created("a".to_string(), at(0));
+        tracker.record_created("b".to_string(), at(100));
+        tracker.record_created("rejected".to_string(), at(100));
+        tracker.forget("rejected");
+        tracker.record_included("a", at(1000));
+        tracker.record_included("a", at(1500));
+        tracker.record_included("unknown", at(1500));
+        assert_eq!(tracker.awaiting_finality(), vec!["a".to_string()]);
+        tracker.record_finalized("a", at(6000));
+        tracker.record_finalized("a", at(7000));
+
+        let stats = tracker.get_stats();
+        assert_eq!(stats["tx_inclusion_p50_ms"], "1000");
+        assert_eq!(stats["tx_finality_p95_ms"], "6000");
+        assert_eq!(stats["#tx_watched"], "1");
+        assert!(tracker.awaiting_finality().is_empty());
+    }
+}
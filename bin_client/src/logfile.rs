@@ -0,0 +1,100 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+//! This file implements the RotatingLog struct, which keeps the stderr of the child processes and the events of the client
+//! in files, so that they can still be read after the UI (and its alternate screen) is gone.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// The size (in bytes) above which a log file is rotated.
+pub const LOG_ROTATE_BYTES: u64 = 1024 * 1024;
+
+/// The number of rotated files kept for each log (`<name>.log.1` is the most recent one).
+pub const LOG_ROTATE_KEEP: usize = 3;
+
+/// A log file that is rotated when it grows above `LOG_ROTATE_BYTES`. Every line is prefixed with a timestamp.
+pub struct RotatingLog {
+    /// the path of the current log file
+    path: PathBuf,
+    /// the current log file
+    file: File,
+    /// the size of the current log file
+    size: u64,
+}
+
+impl RotatingLog {
+    /// Open (or create) the log `<dir>/<name>.log` for appending. The directory is created if necessary.
+    pub fn open(dir: &Path, name: &str) -> io::Result<RotatingLog> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{}.log", name));
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingLog { path, file, size })
+    }
+
+    /// Write one line to the log, prefixed with the current time (seconds since the UNIX epoch, with milliseconds).
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.size >= LOG_ROTATE_BYTES {
+            self.rotate()?;
+        }
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        let entry = format!(
+            "[{}.{:03}] {}\n",
+            now.as_secs(),
+            now.subsec_millis(),
+            line.trim_end()
+        );
+        self.file.write_all(entry.as_bytes())?;
+        self.size += entry.len() as u64;
+        Ok(())
+    }
+
+    /// Shift `<name>.log.i` to `<name>.log.(i+1)`, dropping the oldest one, and start a new `<name>.log`.
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotated = |i: usize| PathBuf::from(format!("{}.{}", self.path.display(), i));
+        for i in (1..LOG_ROTATE_KEEP).rev() {
+            if rotated(i).exists() {
+                fs::rename(rotated(i), rotated(i + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated(1))?;
+        self.file = File::create(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RotatingLog, LOG_ROTATE_BYTES, LOG_ROTATE_KEEP};
+    use std::fs;
+
+    /// A log above the size limit is moved to `.log.1`, and only `LOG_ROTATE_KEEP` rotated files are kept.
+    #[test]
+    fn test_rotating_log() {
+        let dir = std::env::temp_dir().join(format!("bin_client_logs_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let line = "x".repeat(LOG_ROTATE_BYTES as usize);
+        let mut log = RotatingLog::open(&dir, "node").unwrap();
+        for _ in 0..LOG_ROTATE_KEEP + 2 {
+            log.write_line(&line).unwrap();
+        }
+        log.write_line("last").unwrap();
+
+        let current = fs::read_to_string(dir.join("node.log")).unwrap();
+        assert!(current.starts_with('[') && current.ends_with("] last\n"));
+        for i in 1..=LOG_ROTATE_KEEP {
+            assert!(dir.join(format!("node.log.{}", i)).exists());
+        }
+        assert!(!dir
+            .join(format!("node.log.{}", LOG_ROTATE_KEEP + 1))
+            .exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -7,9 +7,7 @@
 /// 2. Read user input (using terminal UI) about transaction creation or quitting.
 /// 3. Display the status and logs to the user (using terminal UI).
 /// 4. IPC communication with the bin_nakamoto and the bin_wallet processes.
-use seccompiler;
-use seccompiler::{BpfMap, BpfProgram};
-
+use base64ct::{Base64, Encoding};
 use tui::{backend::CrosstermBackend, Terminal};
 use tui_textarea::{Input, Key};
 
@@ -19,14 +17,21 @@ use crossterm::{
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
-use std::io::{self, BufRead, BufReader, Read, Write};
+use std::io::{self, BufRead, Read, Write};
+use std::path::Path;
 use std::process::{Command, Stdio};
-use std::time::SystemTime;
 
+use lib_chain::block::{BlockNode, ChainStatus, Transaction, TxOutput};
+use lib_chain::clock::clock;
+use lib_chain::payload::SignedPayload;
+use lib_chain::schema;
+use lib_chain::txtrace::TxTraceEvent;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use serde_json;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::channel;
 use std::sync::{Arc, Mutex};
 use std::{
     thread,
@@ -36,20 +41,67 @@ use std::{
 use std::fs;
 
 mod app;
+mod batch;
+mod dashboard;
+mod deeplink;
+mod flood;
+mod history;
+mod latency;
+mod logfile;
+mod offline;
+mod payment_hook;
+mod scrollback;
+mod statusbar;
+mod stderr_forward;
+mod template;
+mod txtrace;
+
+use app::{SettingsAction, StatusFields};
+use flood::{FloodRun, FLOOD_AMOUNTS, FLOOD_DRAIN_MS};
+use logfile::RotatingLog;
+use scrollback::LogRing;
+use statusbar::WalletState;
+use stderr_forward::StderrForwarder;
+use template::ClientConfig;
 
 /// The enum type for the IPC messages (requests) from this client to the bin_nakamoto process.
 /// It is the same as the `IPCMessageRequest` enum type in the bin_nakamoto process.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 enum IPCMessageReqNakamoto {
     Initialize(String, String, String),
-    GetAddressBalance(String),
+    GetAddressBalance(String, BalanceBranch),
     PublishTx(String, String),
     RequestBlock(String),
+    RequestBlockAtHeight(u64),
+    RequestBlockMetadata(String),
+    RequestBlockWork(String),
+    RequestSimulateTx(String),
+    RequestTxInclusion(String),
+    RequestTxFinality(String),
+    RequestAddressHistory(String),
     RequestNetStatus,
+    RequestGossipTrace,
+    PausePeer(String),
+    ResumePeer(String),
+    BlockPeer(String),
+    UnblockPeer(String),
+    AllowPeer(String),
+    DisallowPeer(String),
     RequestChainStatus,
     RequestMinerStatus,
+    StartMiner,
+    StopMiner,
+    SetMinerConfig(u16, Option<u64>),
+    RequestChainStats(u64),
+    AuditChain,
+    CheckChainInvariants,
     RequestTxPoolStatus,
+    RequestConfig,
+    UpdateConfig(String, String),
+    ImportChain(String),
     RequestStateSerialization,
+    AdvanceClock(u64),
+    SubscribeEvents,
     Quit,
 }
 
@@ -59,26 +111,93 @@ enum IPCMessageReqNakamoto {
 enum IPCMessageRespNakamoto {
     Initialized,
     PublishTxDone,
-    AddressBalance(String, i64),
+    PublishTxRejected(String, TxRejectReason),
+    AddressBalance(String, BalanceBranch, i64),
     BlockData(String),
-    NetStatus(BTreeMap<String, String>),
-    ChainStatus(BTreeMap<String, String>),
-    MinerStatus(BTreeMap<String, String>),
-    TxPoolStatus(BTreeMap<String, String>),
+    BlockAtHeight(u64, String),
+    BlockMetadata(String, String),
+    BlockWork(String, BTreeMap<String, String>),
+    SimulateTxResult(String, bool, String),
+    TxInclusion(String, bool),
+    TxFinality(String, bool),
+    AddressHistory(String, Vec<history::AddressHistoryEntry>),
+    NetStatus(StatusFields),
+    GossipTrace(String),
+    PeerPaused(String),
+    PeerResumed(String),
+    PeerBlocked(String),
+    PeerUnblocked(String),
+    PeerAllowed(String),
+    PeerDisallowed(String),
+    ChainStatus(ChainStatus),
+    MinerStatus(StatusFields),
+    MinerStarted,
+    MinerStopped,
+    MinerConfigSet(u16, Option<u64>),
+    ChainStats(BTreeMap<String, String>),
+    ChainAudit(bool, String),
+    ChainInvariants(Vec<String>),
+    TxPoolStatus(StatusFields),
+    ConfigStatus(BTreeMap<String, String>),
+    ConfigUpdated(String, String),
+    ChainImported(u64, u64, u64),
     StateSerialization(String, String),
+    ClockAdvanced(u64),
+    EventsSubscribed,
+    Event(ChainEvent),
     Quitting,
     Notify(String),
 }
 
+/// An event pushed by bin_nakamoto after SubscribeEvents.
+/// It is the same as the `ChainEvent` enum type in the bin_nakamoto process.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+enum ChainEvent {
+    NewBlockFinalized(String, u64),
+    TxConfirmed(String, String),
+    PeerConnected(String),
+    NewBlock(String, u64),
+    NewTx(String),
+    Reorg(String, String, u64),
+}
+
+/// The branch of the chain to read a balance from.
+/// It is the same as the `BalanceBranch` enum type in the bin_nakamoto process.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+enum BalanceBranch {
+    Finalized,
+    WorkingTip,
+}
+
+/// The reason why bin_nakamoto rejected a published transaction.
+/// It is the same as the `TxRejectReason` enum type in lib_tx_pool.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+enum TxRejectReason {
+    InvalidAmount,
+    InsufficientBalance(i64, i64),
+    Duplicate,
+    PoolFull,
+    InvalidSignature,
+    MessageTooLong(usize, usize),
+    FeeTooLow(i64, i64),
+    TooManyOutputs(usize, usize),
+}
+
 /// The enum type for the IPC messages (requests) from this client to the bin_wallet process.
 /// It is the same as the enum type in the bin_wallet process.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 enum IPCMessageReqWallet {
-    Initialize(String),
+    Initialize(String, Option<String>),
+    Unlock(String),
     Quit,
-    SignRequest(String),
+    SignRequest(String, Option<String>),
     VerifyRequest(String, String),
+    VerifyBatch(Vec<(String, String, String)>),
     GetUserInfo,
+    GetSenderKey,
+    GetSignatureAlgorithm,
+    DeriveAddress(u32),
+    ListAddresses,
 }
 
 /// The enum type for the IPC messages (responses) from the bin_wallet process to this client.
@@ -86,35 +205,350 @@ enum IPCMessageReqWallet {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 enum IPCMessageRespWallet {
     Initialized,
+    Locked,
+    Unlocked,
+    UnlockRejected(String),
     Quitting,
+    SignPending(String),
     SignResponse(String, String),
+    SignRejected(String, String),
     VerifyResponse(bool, String),
+    VerifyBatchResponse(Vec<bool>, u64),
     UserInfo(String, String),
+    SenderKey(Option<String>),
+    SignatureAlgorithm(SignatureAlgorithm),
+    DerivedAddress(u32, String),
+    DeriveRejected(u32, String),
+    AddressList(Vec<(u32, String)>),
+}
+
+/// The signature scheme of the wallet, which the data_string of its transactions must name.
+/// It is the same as the `SignatureAlgorithm` enum type in lib_chain.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+enum SignatureAlgorithm {
+    Rsa,
+    Ed25519,
 }
 
 /// The enum type representing bot commands for controlling the client automatically.
 /// The commands are read from a file or a named pipe and then executed by the client.
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 enum BotCommand {
     /// Send a transaction message from the default user_id of the client to the given receiver_user_id, e.g, Send(`receiver_user_id`, `transaction_message`)
     Send(String, String),
     /// Wait for the given number of milliseconds, e.g., SleepMs(`milliseconds`)
     SleepMs(u64),
+    /// Run the miner until the working chain is the given number of blocks longer, then stop it, e.g., MineBlocks(`block_count`)
+    MineBlocks(u64),
+    /// Define the variable `$name` for the next commands, e.g., Let(`name`, `value`)
+    Let(String, String),
+    /// Set how many times the next Send commands are retried when their transaction is rejected, e.g., SendRetries(`max_retries`)
+    SendRetries(u32),
+    /// List the user ids of all the demo clients, e.g., Peers([`user_id`, ...]).
+    /// The ones other than this client's become `$PEER1`, `$PEER2`, ... in order, so that every client can run the same bot file.
+    Peers(Vec<String>),
+    /// Send small transactions to the peers (or to this client if there is no peer) at the given rate for the given time,
+    /// then log the achieved publish rate, pool acceptance rate and confirmation latencies, e.g., Flood(`tx_per_second`, `duration_ms`)
+    Flood(u64, u64),
+    /// Pre-fill the transaction form from a deep link without sending it, e.g., Open(`nakamoto:send?to=<addr>&amount=10&memo=Lunch`)
+    Open(String),
+    /// Send a transaction message to the given receiver_user_id now and then every given number of milliseconds, alongside
+    /// the next commands, until the client quits, e.g., SendEvery(`interval_ms`, `receiver_user_id`, `transaction_message`).
+    /// An interval of 0 stops the repeated sends started before.
+    SendEvery(u64, String, String),
+    /// Wait until the (finalized) balance of the user is at least the given amount, e.g., WaitForBalance(`threshold`)
+    WaitForBalance(i64),
+    /// Write the state seen by the client (balances, pending txs, status of bin_nakamoto) as json to the given file, e.g., DumpState(`path`)
+    DumpState(String),
+    /// Quit the client (and its bin_nakamoto and bin_wallet), as if the user pressed ESC. The next commands are not run.
+    Quit,
+}
+
+impl BotCommand {
+    /// Replace the variables in the string arguments of the command, see `substitute_bot_vars`.
+    fn substitute(self, vars: &BTreeMap<String, String>) -> Result<BotCommand, String> {
+        Ok(match self {
+            BotCommand::Send(receiver, message) => BotCommand::Send(
+                substitute_bot_vars(&receiver, vars)?,
+                substitute_bot_vars(&message, vars)?,
+            ),
+            BotCommand::Let(name, value) => {
+                BotCommand::Let(name, substitute_bot_vars(&value, vars)?)
+            }
+            BotCommand::Open(link) => BotCommand::Open(substitute_bot_vars(&link, vars)?),
+            BotCommand::SendEvery(interval_ms, receiver, message) => BotCommand::SendEvery(
+                interval_ms,
+                substitute_bot_vars(&receiver, vars)?,
+                substitute_bot_vars(&message, vars)?,
+            ),
+            BotCommand::DumpState(path) => BotCommand::DumpState(substitute_bot_vars(&path, vars)?),
+            bot_command => bot_command,
+        })
+    }
+}
+
+/// Replace the variables of a bot command argument by their values.
+/// A variable is a `$` followed by an upper case name and an optional argument in parentheses, e.g. `$SELF`, `$PEER1` or `$BALANCE(self)`.
+/// A `$` followed by anything else is kept as it is, so that `SEND $100` and `SEND $$BALANCE(self)` work. Unknown variables are errors.
+fn substitute_bot_vars(text: &str, vars: &BTreeMap<String, String>) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(idx) = rest.find('$') {
+        result.push_str(&rest[..idx]);
+        rest = &rest[idx + 1..];
+        if !rest.starts_with(|c: char| c.is_ascii_uppercase()) {
+            result.push('$');
+            continue;
+        }
+        let mut name_len = rest
+            .find(|c: char| !(c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_'))
+            .unwrap_or(rest.len());
+        if rest[name_len..].starts_with('(') {
+            match rest[name_len..].find(')') {
+                Some(close) => name_len += close + 1,
+                None => return Err(format!("Unclosed variable ${}", rest)),
+            }
+        }
+        let name = &rest[..name_len];
+        match vars.get(name) {
+            Some(value) => result.push_str(value),
+            None => return Err(format!("Unknown variable ${}", name)),
+        }
+        rest = &rest[name_len..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// How often a bot command waiting for the chain (e.g. MineBlocks) checks the chain status.
+const BOT_POLL_MS: u64 = 100;
+
+/// How long (in real time) a bot command gives the other threads to run after advancing a virtual clock.
+const BOT_VIRTUAL_YIELD_MS: u64 = 20;
+
+/// The number of times a bot Send is retried when its transaction is rejected, unless changed by SendRetries.
+const BOT_SEND_DEFAULT_RETRIES: u32 = 5;
+
+/// The delay before the first retry of a rejected bot Send. It doubles after every retry.
+const BOT_SEND_RETRY_BASE_MS: u64 = 500;
+
+/// A transaction sent by a bot Send command that is not in a block yet, kept to retry it if it is rejected.
+#[derive(Debug, Clone)]
+struct BotSend {
+    /// the receiver user_id
+    receiver: String,
+    /// the message, without the timestamp added to every attempt
+    message: String,
+    /// the number of retries so far
+    retries: u32,
+    /// the max number of retries
+    max_retries: u32,
+}
+
+impl BotSend {
+    /// The delay before the next retry (exponential backoff).
+    fn backoff(&self) -> Duration {
+        Duration::from_millis(BOT_SEND_RETRY_BASE_MS << self.retries.min(16))
+    }
 }
 
 /// Read a file and return the content as a string.
 fn read_string_from_file(filepath: &str) -> String {
-    let contents = fs::read_to_string(filepath).expect(&("Cannot read ".to_owned() + filepath));
-    contents
+    fs::read_to_string(filepath).unwrap_or_else(|_| panic!("Cannot read {}", filepath))
+}
+
+/// Generate a random token (hex string) shared with bin_wallet at initialization.
+/// Every SignRequest carries this token so that other processes writing to the wallet's stdin cannot get signatures.
+fn gen_wallet_auth_token() -> String {
+    let mut buf = [0u8; 16];
+    File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut buf))
+        .expect("Failed to read /dev/urandom");
+    buf.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The environment variable giving the passphrase of an encrypted wallet, instead of asking it on the terminal.
+const WALLET_PASSPHRASE_ENV: &str = "BIN_CLIENT_WALLET_PASSPHRASE";
+
+/// The number of times the passphrase of an encrypted wallet is asked before the client gives up.
+const MAX_UNLOCK_ATTEMPTS: usize = 3;
+
+/// Ask for a passphrase on the terminal without echoing it. Esc and Ctrl+C cancel the prompt.
+fn prompt_passphrase(prompt: &str) -> io::Result<String> {
+    use crossterm::event::{Event, KeyCode, KeyModifiers};
+    print!("{}", prompt);
+    io::stdout().flush()?;
+    enable_raw_mode()?;
+    let mut passphrase = String::new();
+    let result = loop {
+        match event::read() {
+            Ok(Event::Key(key)) => match key.code {
+                KeyCode::Enter => break Ok(()),
+                KeyCode::Esc => break Err(io::ErrorKind::Interrupted.into()),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    break Err(io::ErrorKind::Interrupted.into())
+                }
+                KeyCode::Char(c) => passphrase.push(c),
+                KeyCode::Backspace => {
+                    passphrase.pop();
+                }
+                _ => {}
+            },
+            Ok(_) => {}
+            Err(e) => break Err(e),
+        }
+    };
+    disable_raw_mode()?;
+    println!();
+    result.map(|_| passphrase)
+}
+
+/// Build the OSC 52 escape sequence that asks the terminal to put the text in the system clipboard.
+/// Unlike a clipboard library, it also works when the client runs over SSH, as the local terminal handles it.
+fn osc52_sequence(text: &str) -> String {
+    format!("\x1b]52;c;{}\x07", Base64::encode_string(text.as_bytes()))
 }
 
+/// Copy the text to the clipboard of the terminal running the client.
+fn copy_to_clipboard(text: &str) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    stdout.write_all(osc52_sequence(text).as_bytes())?;
+    stdout.flush()
+}
+
+/// Get the color theme named by the `BIN_CLIENT_THEME` environment variable, or the dark theme by default.
+fn theme_from_env() -> app::Theme {
+    std::env::var("BIN_CLIENT_THEME")
+        .ok()
+        .and_then(|name| app::Theme::from_name(&name))
+        .unwrap_or(app::Theme::Dark)
+}
+
+/// Get the amount of a transaction from its data_string (the json serialization of (sender, receiver, message),
+/// followed by the sender public key for a hashed sender address).
+/// The message starts with `SEND $<amount>`, as checked by the `Create Transaction` panel. The outputs of a batch are
+/// added to it.
+fn tx_amount(data_string: &str) -> Option<i64> {
+    let tx = Transaction::from_data_string(data_string, String::new()).ok()?;
+    let amount: i64 = tx
+        .message
+        .strip_prefix("SEND $")?
+        .split(' ')
+        .next()?
+        .parse()
+        .ok()?;
+    Some(amount + tx.outputs.iter().map(|output| output.amount).sum::<i64>())
+}
+
+/// The requests that refresh the balances and the tx pool status shown to the user.
+fn balance_refresh_requests(user_id: &str) -> [IPCMessageReqNakamoto; 3] {
+    [
+        IPCMessageReqNakamoto::GetAddressBalance(user_id.to_string(), BalanceBranch::Finalized),
+        IPCMessageReqNakamoto::GetAddressBalance(user_id.to_string(), BalanceBranch::WorkingTip),
+        IPCMessageReqNakamoto::RequestTxPoolStatus,
+    ]
+}
+
+/// The maximum size (in bytes) of one IPC frame read from bin_nakamoto or bin_wallet. It is the same as in bin_nakamoto.
+const MAX_IPC_FRAME_BYTES: usize = 64 * 1024 * 1024;
+
+/// The number of characters of a malformed IPC frame shown in its diagnostic.
+const IPC_FRAME_PREVIEW_LEN: usize = 200;
+
+/// Read the next IPC frame (one json message per line), skipping blank lines.
+/// It returns None at the end of the stream, and an error for a frame that is larger than `max_len` (which is skipped) or not UTF-8.
+fn read_ipc_frame(reader: &mut impl BufRead, max_len: usize) -> Option<Result<String, String>> {
+    loop {
+        let mut frame = Vec::new();
+        let read = Read::take(&mut *reader, max_len as u64 + 1)
+            .read_until(b'\n', &mut frame)
+            .ok()?;
+        if read == 0 {
+            return None;
+        }
+        if frame.len() > max_len && frame.last() != Some(&b'\n') {
+            // Skip the rest of the oversized frame, so that the next frame is read from its start
+            let mut frame_len = frame.len();
+            loop {
+                let mut rest = Vec::new();
+                let read = Read::take(&mut *reader, max_len as u64)
+                    .read_until(b'\n', &mut rest)
+                    .unwrap_or(0);
+                frame_len += read;
+                if read == 0 || rest.last() == Some(&b'\n') {
+                    break;
+                }
+            }
+            return Some(Err(format!(
+                "Frame of {} bytes exceeds the max frame size ({} bytes)",
+                frame_len, max_len
+            )));
+        }
+        match String::from_utf8(frame) {
+            Ok(frame) if frame.trim().is_empty() => continue,
+            Ok(frame) => return Some(Ok(frame.trim().to_string())),
+            Err(_) => return Some(Err("Frame is not valid UTF-8".to_string())),
+        }
+    }
+}
+
+/// Read the next IPC response, passing a diagnostic of every frame that is not a valid response to `diagnose` and skipping it.
+/// It returns None at the end of the stream (i.e. the child process exited).
+fn read_ipc_response<T: serde::de::DeserializeOwned>(
+    reader: &mut impl BufRead,
+    mut diagnose: impl FnMut(String),
+) -> Option<T> {
+    loop {
+        match read_ipc_frame(reader, MAX_IPC_FRAME_BYTES)? {
+            Ok(frame) => match serde_json::from_str(&frame) {
+                Ok(response) => return Some(response),
+                Err(e) => diagnose(format!(
+                    "Malformed IPC frame ({}): {}",
+                    e,
+                    frame
+                        .chars()
+                        .take(IPC_FRAME_PREVIEW_LEN)
+                        .collect::<String>()
+                )),
+            },
+            Err(e) => diagnose(e),
+        }
+    }
+}
+
+/// Split the optional arguments of bin_client (after the five required ones) into the bot commands path and the deep link,
+/// which is the one starting with `nakamoto:`. Any of them can be left out.
+fn split_optional_args(args: impl Iterator<Item = String>) -> (Option<String>, Option<String>) {
+    let (mut bot_command_path, mut deep_link) = (None, None);
+    for arg in args {
+        if arg.starts_with(deeplink::LINK_SCHEME) {
+            deep_link = Some(arg);
+        } else {
+            bot_command_path = Some(arg);
+        }
+    }
+    (bot_command_path, deep_link)
+}
+
+/// The directory where the logs of the client and of its child processes are written.
+const LOG_DIR: &str = "./logs";
+
+/// The number of last blocks of the longest chain that the chain statistics are computed over.
+const CHAIN_STATS_WINDOW: u64 = 100;
+
+/// How often the status is requested from bin_nakamoto.
+const STATUS_POLL_MS: u64 = 500;
+
+/// How often the status is requested from bin_nakamoto once it pushes its events, which trigger the refreshes in between.
+const STATUS_POLL_SUBSCRIBED_MS: u64 = 2000;
+
 /// A flag indicating whether to disable the UI thread if you need to check some debugging outputs that is covered by the UI.
 /// Eventually this should be set to false and you shouldn't output debugging information directly to stdout or stderr.
 const NO_UI_DEBUG_NODE: bool = false;
 
 fn main() {
     // The usage of bin_client is as follows:
-    // bin_client <client_seccomp_path> <nakamoto_config_path> <nakamoto_seccomp_path> <wallet_config_path> <wallet_seccomp_path> [<bot_command_path>]
+    // bin_client <client_seccomp_path> <nakamoto_config_path> <nakamoto_seccomp_path> <wallet_config_path> <wallet_seccomp_path> [<bot_command_path>] [<deep_link>]
     // - `client_seccomp_path`: The path to the seccomp file for this client process for Part B. (You can set this argument to any value during Part A.)
     // - `nakamoto_config_path`: The path to the config folder for the bin_nakamoto process. For example, `./tests/nakamoto_config1`. Your program should read the 3 files in the config folder (`BlockTree.json`, `Config.json`, `TxPool.json`) for initializing bin_nakamoto.
     // - `nakamoto_seccomp_path`: The path to the seccomp file for the bin_nakamoto process for Part B. (You can set this argument to any value during Part A.)
@@ -124,6 +558,25 @@ fn main() {
     //                         an example file of the bot commands can be found at `./tests/_bots/botA-0.jsonl`. You can also look at `run_four.sh` for an example of using the named pipe version of this argument.
     //                         The bot commands are executed by the client in the order they are read from the file or the named pipe.
     //                         The bot commands should be executed in a separate thread so that the UI thread can still be responsive.
+    // - [`deep_link`]: *Optional* argument, before or after `bot_command_path`. A transaction request link, e.g. `nakamoto:send?to=<addr>&amount=10&memo=Lunch`,
+    //                  that pre-fills the transaction form (see `deeplink.rs`). The transaction is created once the user presses ENTER.
+    //
+    // Alternatively, bin_client can be started in the multi-node dashboard mode:
+    // bin_client --dashboard <ipc_addr_1> <ipc_addr_2> ...
+    // - `ipc_addr_i`: The address ("ip:port") of the IPC socket of a running bin_nakamoto process (`ipc_listen_addr` in its config).
+    //                 The client does not start any process in this mode. It shows the heights, tips, and txpool sizes of the nodes side by side.
+    //
+    // The client reads its own optional settings from `Client.json` in the nakamoto config folder, e.g. the template of the transactions:
+    // {"tx_template": {"default_amount": 100, "comment_format": "By {name}", "embed_timestamp": true}, "log_capacity": 10000, "stderr_lines_per_sec": 50} (the defaults).
+    // With {"payment_hook": {"command": "<shell command>", "watched_addresses": [...]}}, the command is run for every payment to the
+    // watched addresses (the user's own by default) in a finalized block, with the payment in the NAKAMOTO_PAYMENT_* environment variables.
+    // The log panels can be scrolled back with PageUp/PageDown (Ctrl for the Notify log) and exported with `:export-logs <path>` in the settings panel.
+    // For cold storage, `:export-tx <path>` in the settings panel writes the transaction of the form unsigned, to be signed offline with
+    // `bin_wallet sign-file <in.json> <out.json> <wallet.json>`, and `:import-tx <path>` publishes the signed file (see `offline.rs`).
+    // If the private key of the wallet is encrypted (`bin_wallet encrypt <in.json> <out.json>`), its passphrase is asked at startup,
+    // or taken from the `BIN_CLIENT_WALLET_PASSPHRASE` environment variable.
+    // In both modes, the color theme can be chosen with the `BIN_CLIENT_THEME` environment variable (`dark`, `light`, `high-contrast` or `monochrome`)
+    // and changed at runtime with Ctrl+T.
     // Please fill in the blank
     // - Create bin_nakamoto process:  Command::new("./target/debug/bin_nakamoto")...
     // - Create bin_wallet process:  Command::new("./target/debug/bin_wallet")...
@@ -131,10 +584,16 @@ fn main() {
     // - Create buffer readers if necessary
     // - Send initialization requests to bin_nakamoto and bin_wallet
 
+    if std::env::args().nth(1).as_deref() == Some("--dashboard") {
+        dashboard::run(std::env::args().skip(2).collect(), theme_from_env()).unwrap();
+        return;
+    }
+
     // Create bin_nakamoto process
     let mut bin_nakamoto = Command::new("./target/debug/bin_nakamoto")
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
         .spawn()
         .expect("Failed to spawn bin_nakamoto process");
 
@@ -142,6 +601,7 @@ fn main() {
     let mut bin_wallet = Command::new("./target/debug/bin_wallet")
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
         .spawn()
         .expect("Failed to spawn bin_wallet process");
 
@@ -157,10 +617,10 @@ fn main() {
         .take()
         .expect("Failed to get stdout of bin_nakamoto");
 
-    // let nakamoto_stderr = bin_nakamoto
-    //     .stderr
-    //     .take()
-    //     .expect("Failed to get stderr of bin_nakamoto");
+    let nakamoto_stderr = bin_nakamoto
+        .stderr
+        .take()
+        .expect("Failed to get stderr of bin_nakamoto");
 
     // Get stdin and stdout of bin_wallet process
     let bin_wallet_stdin_p = Arc::new(Mutex::new(
@@ -174,16 +634,16 @@ fn main() {
         .take()
         .expect("Failed to get stdout of bin_wallet");
 
-    // let bin_wallet_stderr = bin_wallet
-    //     .stderr
-    //     .take()
-    //     .expect("Failed to get stderr of bin_wallet");
+    let bin_wallet_stderr = bin_wallet
+        .stderr
+        .take()
+        .expect("Failed to get stderr of bin_wallet");
 
     // Create buffer readers if necessary
     let bin_nakamoto_reader = Arc::new(Mutex::new(std::io::BufReader::new(nakamoto_stdout)));
     let bin_wallet_reader = Arc::new(Mutex::new(std::io::BufReader::new(bin_wallet_stdout)));
-    // let mut nakamoto_stderr_reader = std::io::BufReader::new(nakamoto_stderr);
-    // let mut wallet_stderr_reader = std::io::BufReader::new(bin_wallet_stderr);
+    let nakamoto_stderr_reader = std::io::BufReader::new(nakamoto_stderr);
+    let wallet_stderr_reader = std::io::BufReader::new(bin_wallet_stderr);
 
     // Read folder path and get the files from the folder
     let folder_path = std::env::args().nth(2).unwrap();
@@ -215,9 +675,11 @@ fn main() {
         .to_string();
 
     // Send initialization requests to bin_wallet
-    let wallet_init_request = IPCMessageReqWallet::Initialize(read_string_from_file(
-        std::env::args().nth(4).unwrap().as_str(),
-    ));
+    let wallet_auth_token = gen_wallet_auth_token();
+    let wallet_init_request = IPCMessageReqWallet::Initialize(
+        read_string_from_file(std::env::args().nth(4).unwrap().as_str()),
+        Some(wallet_auth_token.clone()),
+    );
     let wallet_init_request_str = serde_json::to_string(&wallet_init_request).unwrap();
     writeln!(
         bin_wallet_stdin_p.lock().unwrap(),
@@ -226,18 +688,57 @@ fn main() {
     )
     .expect("Failed to write to bin_wallet stdin");
 
-    let mut wallet_response = String::new();
-    bin_wallet_reader
-        .lock()
-        .unwrap()
-        .read_line(&mut wallet_response)
-        .unwrap();
-    let wallet_response: IPCMessageRespWallet = serde_json::from_str(&wallet_response).unwrap();
+    let wallet_response: IPCMessageRespWallet =
+        read_ipc_response(&mut *bin_wallet_reader.lock().unwrap(), |e| {
+            eprintln!("bin_wallet: {}", e)
+        })
+        .expect("bin_wallet exited during initialization");
 
     match wallet_response {
         IPCMessageRespWallet::Initialized => {
             println!("Wallet initialized");
         }
+        IPCMessageRespWallet::Locked => {
+            // The passphrase is taken from the environment (e.g. in scripted runs) or asked on the terminal
+            let env_passphrase = std::env::var(WALLET_PASSPHRASE_ENV).ok();
+            let attempts = if env_passphrase.is_some() {
+                1
+            } else {
+                MAX_UNLOCK_ATTEMPTS
+            };
+            let mut unlocked = false;
+            for _ in 0..attempts {
+                let passphrase = match &env_passphrase {
+                    Some(passphrase) => passphrase.clone(),
+                    None => prompt_passphrase("Wallet passphrase: ")
+                        .expect("Failed to read the wallet passphrase"),
+                };
+                writeln!(
+                    bin_wallet_stdin_p.lock().unwrap(),
+                    "{}",
+                    serde_json::to_string(&IPCMessageReqWallet::Unlock(passphrase)).unwrap()
+                )
+                .expect("Failed to write to bin_wallet stdin");
+                match read_ipc_response(&mut *bin_wallet_reader.lock().unwrap(), |e| {
+                    eprintln!("bin_wallet: {}", e)
+                })
+                .expect("bin_wallet exited during initialization")
+                {
+                    IPCMessageRespWallet::Unlocked => {
+                        unlocked = true;
+                        break;
+                    }
+                    IPCMessageRespWallet::UnlockRejected(reason) => {
+                        eprintln!("Wallet not unlocked: {}", reason)
+                    }
+                    _ => panic!("Unexpected response from wallet"),
+                }
+            }
+            if !unlocked {
+                panic!("Wallet initialization failed: the wallet is locked");
+            }
+            println!("Wallet unlocked");
+        }
         _ => panic!("Wallet initialization failed"),
     }
 
@@ -262,24 +763,26 @@ fn main() {
     )
     .expect("Failed to write to bin_nakamoto stdin");
 
-    let mut nakamoto_response = String::new();
-
-    bin_nakamoto_reader
-        .lock()
-        .unwrap()
-        .read_line(&mut nakamoto_response)
-        .unwrap();
-
     let nakamoto_response: IPCMessageRespNakamoto =
-        serde_json::from_str(&nakamoto_response).unwrap();
+        read_ipc_response(&mut *bin_nakamoto_reader.lock().unwrap(), |e| {
+            eprintln!("bin_nakamoto: {}", e)
+        })
+        .expect("bin_nakamoto exited during initialization");
     match nakamoto_response {
         IPCMessageRespNakamoto::Initialized => {
             println!("Nakamoto initialized");
         }
         _ => panic!("Nakamoto initialization failed"),
     }
+    // Let bin_nakamoto push its events, answered by EventsSubscribed (read with the other responses below)
+    writeln!(
+        nakamoto_stdin_p.lock().unwrap(),
+        "{}",
+        serde_json::to_string(&IPCMessageReqNakamoto::SubscribeEvents).unwrap()
+    )
+    .expect("Failed to write to bin_nakamoto stdin");
 
-    let client_seccomp_path = std::env::args()
+    let _client_seccomp_path = std::env::args()
         .nth(1)
         .expect("Please specify client seccomp path");
 
@@ -300,13 +803,11 @@ fn main() {
     )
     .expect("Failed to write to bin_wallet stdin");
 
-    let mut wallet_response = String::new();
-    bin_wallet_reader
-        .lock()
-        .unwrap()
-        .read_line(&mut wallet_response)
-        .unwrap();
-    let wallet_response: IPCMessageRespWallet = serde_json::from_str(&wallet_response).unwrap();
+    let wallet_response: IPCMessageRespWallet =
+        read_ipc_response(&mut *bin_wallet_reader.lock().unwrap(), |e| {
+            eprintln!("bin_wallet: {}", e)
+        })
+        .expect("bin_wallet exited during initialization");
 
     match wallet_response {
         IPCMessageRespWallet::UserInfo(name, id) => {
@@ -316,82 +817,416 @@ fn main() {
         _ => panic!("Unexpected response from wallet"),
     }
 
+    // Read the public key to embed in the transactions, if the user id is a hashed address
+    writeln!(
+        bin_wallet_stdin_p.lock().unwrap(),
+        "{}",
+        serde_json::to_string(&IPCMessageReqWallet::GetSenderKey).unwrap()
+    )
+    .expect("Failed to write to bin_wallet stdin");
+    let sender_pub_key = match read_ipc_response(&mut *bin_wallet_reader.lock().unwrap(), |e| {
+        eprintln!("bin_wallet: {}", e)
+    })
+    .expect("bin_wallet exited during initialization")
+    {
+        IPCMessageRespWallet::SenderKey(sender_pub_key) => sender_pub_key,
+        _ => panic!("Unexpected response from wallet"),
+    };
+
+    // Read the signature scheme of the wallet, named by the data_string of an Ed25519 transaction
+    writeln!(
+        bin_wallet_stdin_p.lock().unwrap(),
+        "{}",
+        serde_json::to_string(&IPCMessageReqWallet::GetSignatureAlgorithm).unwrap()
+    )
+    .expect("Failed to write to bin_wallet stdin");
+    let signature_algorithm =
+        match read_ipc_response(&mut *bin_wallet_reader.lock().unwrap(), |e| {
+            eprintln!("bin_wallet: {}", e)
+        })
+        .expect("bin_wallet exited during initialization")
+        {
+            IPCMessageRespWallet::SignatureAlgorithm(algorithm) => algorithm,
+            _ => panic!("Unexpected response from wallet"),
+        };
+
+    // Read the transaction template of this user and the size of the logs from the client config in the nakamoto config folder
+    let client_config = ClientConfig::load(Path::new(&std::env::args().nth(2).unwrap()))
+        .unwrap_or_else(|e| {
+            eprintln!("{}, using the default client config", e);
+            ClientConfig::default()
+        });
+    let tx_template = client_config.tx_template;
+    let payment_hook = client_config.payment_hook;
+
     // Create the Terminal UI app
     let app_arc = Arc::new(Mutex::new(app::App::new(
         user_name.clone(),
         user_id.clone(),
         "".to_string(),
-        format!("SEND $100   // By {}", user_name),
+        tx_template.default_message(&user_name),
     )));
+    {
+        let mut app = app_arc.lock().unwrap();
+        app.theme = theme_from_env();
+        app.notify_log = LogRing::new(client_config.log_capacity);
+        app.stderr_log = LogRing::new(client_config.log_capacity);
+        app.status_bar.wallet = WalletState::Unlocked;
+    }
 
-    // An enclosure func to generate signing requests when creating new transactions.
-    let create_sign_req = |sender: String, receiver: String, message: String| {
-        let timestamped_message = format!(
-            "{}   // {}",
-            message,
-            SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap()
-                .as_millis()
-        );
-        let sign_req = IPCMessageReqWallet::SignRequest(
-            serde_json::to_string(&(sender, receiver, timestamped_message)).unwrap(),
-        );
+    // The optional arguments: the bot commands path and a deep link pre-filling the transaction form
+    let (bot_command_path, deep_link) = split_optional_args(std::env::args().skip(6));
+    if let Some(deep_link) = deep_link {
+        app_arc.lock().unwrap().open_link(&deep_link);
+    }
+
+    // Keep the logs in files named after the nakamoto config folder, so that clients started from the same directory do not mix them
+    let log_dir = Path::new(LOG_DIR);
+    let log_name = Path::new(&std::env::args().nth(2).unwrap())
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "client".to_string());
+    let open_log = |stream: &str| {
+        RotatingLog::open(log_dir, &format!("{}.{}", log_name, stream))
+            .map_err(|e| eprintln!("Cannot open the {} log in {}: {}", stream, LOG_DIR, e))
+            .ok()
+    };
+    app_arc.lock().unwrap().log_file = open_log("client");
+
+    // An enclosure func to generate the data_string (sender, receiver, timestamped message) when creating new transactions.
+    // The timestamp is left out if the transaction template says so.
+    // For a hashed sender address, the sender public key follows, so that the chain can verify the signature.
+    // serde_json writes the canonical encoding of the payload (`SignedPayload` in lib_chain), which the wallet and the chain require.
+    let create_tx_data_string = move |sender: String, receiver: String, message: String| {
+        let timestamped_message = tx_template.finish_message(message, clock().now_ms());
+        match (&sender_pub_key, signature_algorithm) {
+            // An Ed25519 data_string has all the optional fields, then its algorithm (no fee is set by the client)
+            (sender_pub_key, SignatureAlgorithm::Ed25519) => serde_json::to_string(&(
+                sender,
+                receiver,
+                timestamped_message,
+                sender_pub_key.as_deref().unwrap_or_default(),
+                "",
+                "Ed25519",
+            ))
+            .unwrap(),
+            (Some(sender_pub_key), SignatureAlgorithm::Rsa) => {
+                serde_json::to_string(&(sender, receiver, timestamped_message, sender_pub_key))
+                    .unwrap()
+            }
+            (None, SignatureAlgorithm::Rsa) => {
+                serde_json::to_string(&(sender, receiver, timestamped_message)).unwrap()
+            }
+        }
+    };
+
+    // An enclosure func to generate the data_string of a batch: the data_string paying the first payment, with the other
+    // payments as its outputs (see `Transaction::outputs` in lib_chain).
+    let create_batch_data_string = {
+        let create_tx_data_string = create_tx_data_string.clone();
+        move |sender: String, mut payments: Vec<TxOutput>| {
+            let first = payments.remove(0);
+            let message = format!(
+                "SEND ${}   // Batch of {} payments",
+                first.amount,
+                payments.len() + 1
+            );
+            let data_string = create_tx_data_string(sender, first.receiver, message);
+            let mut payload = SignedPayload::decode(&data_string)
+                .expect("the client creates canonical data_strings");
+            payload.outputs = payments;
+            payload.encode()
+        }
+    };
+
+    // An enclosure func to generate signing requests for a data_string that passed the simulation.
+    let create_sign_req = move |data_string: String| {
+        let sign_req =
+            IPCMessageReqWallet::SignRequest(data_string, Some(wallet_auth_token.clone()));
         let mut sign_req_str = serde_json::to_string(&sign_req).unwrap();
         sign_req_str.push('\n');
-        return sign_req_str;
+        sign_req_str
     };
 
+    // The transactions of the bot Send commands, by data_string. They are retried by the thread reading the responses from bin_nakamoto.
+    let bot_sends_p: Arc<Mutex<HashMap<String, BotSend>>> = Arc::new(Mutex::new(HashMap::new()));
+    // The statistics of the running bot Flood command, updated by the threads reading the responses.
+    let flood_p: Arc<Mutex<Option<FloodRun>>> = Arc::new(Mutex::new(None));
+
     // This is optional so .... nvm ....
-    if std::env::args().len() != 6 {
-        // Then there must be 7 arguments provided. The last argument is the bot commands path
+    if let Some(bot_command_path) = bot_command_path {
+        // The bot commands path is the optional argument that is not a deep link
         // Please fill in the blank
         // Create a thread to read the bot commands from `bot_command_path`, execute those commands and update the UI
         // Notice that the `SleepMs(1000)` doesn't mean that the all threads in the whole process should sleep for 1000ms. It means that
         // The next bot command that fakes the user interaction should be processed 1000ms later.
         // It should not block the execution of any other threads or the main thread.
-        let bot_command_path = std::env::args().nth(6).unwrap();
-
-        // Spawn a separate thread to read and execute bot commands
-        // thread::spawn(move || {
-        // Open the bot command file
-        // let file = File::open(bot_command_path).expect("Failed to open bot command file");
-        // let reader = BufReader::new(file);
-
-        // // Read bot commands line by line
-        // for line in reader.lines() {
-        //     if let Ok(command_str) = line {
-        //         // Parse the command string into a BotCommand struct
-        //         let bot_command = match parse_bot_command(&command_str) {
-        //             Some(cmd) => cmd,
-        //             None => {
-        //                 println!("Failed to parse bot command: {}", command_str);
-        //                 continue;
-        //             }
-        //         };
-
-        //         // Execute the bot command and update the app state
-        //         {
-        //             // Lock the app state with the mutex
-        //             let mut app = app_arc.lock().expect("Failed to acquire app mutex");
-
-        //             // Match on the bot command and execute it
-        //             match bot_command {
-        //                 BotCommand::Send(receiver_user_id, transaction_message) => {
-        //                     // Execute the Send command and update the app state
-        //                     /* execute Send command and update app state */
-        //                 }
-        //                 BotCommand::SleepMs(milliseconds) => {
-        //                     // Sleep for the specified number of milliseconds
-        //                     thread::sleep(Duration::from_millis(milliseconds));
-        //                 }
-        //             }
-
-        //             // Release the mutex to allow other threads to acquire it
-        //         }
-        //     }
-        // }
-        // });
+        let app_arc = app_arc.clone();
+        let nakamoto_stdin_p = nakamoto_stdin_p.clone();
+        let user_id = user_id.clone();
+        let bot_sends_p = bot_sends_p.clone();
+        let flood_p = flood_p.clone();
+        let create_tx_data_string = create_tx_data_string.clone();
+        thread::spawn(move || {
+            let mut max_send_retries = BOT_SEND_DEFAULT_RETRIES;
+            let send_request = |request: IPCMessageReqNakamoto| {
+                writeln!(
+                    nakamoto_stdin_p.lock().unwrap(),
+                    "{}",
+                    serde_json::to_string(&request).unwrap()
+                )
+                .expect("Failed to write to bin_nakamoto stdin");
+            };
+            // Wait without blocking the other threads. With a virtual clock, the time of this client and of bin_nakamoto
+            // is advanced instead, then the woken threads (e.g. the status poller) are given a moment to run.
+            let bot_sleep = |milliseconds: u64| {
+                if clock().advance_ms(milliseconds).is_ok() {
+                    send_request(IPCMessageReqNakamoto::AdvanceClock(milliseconds));
+                    thread::sleep(Duration::from_millis(BOT_VIRTUAL_YIELD_MS));
+                } else {
+                    thread::sleep(Duration::from_millis(milliseconds));
+                }
+            };
+            let working_depth = || -> Option<u64> {
+                let app = app_arc.lock().unwrap();
+                app.blocktree_status.as_ref().map(|status| status.height)
+            };
+            // Send a transaction the same way as the user pressing ENTER: the tx is simulated, then signed and published.
+            // It owns what it uses, so that the threads of the SendEvery commands can run it too.
+            let bot_send = {
+                let app_arc = app_arc.clone();
+                let nakamoto_stdin_p = nakamoto_stdin_p.clone();
+                let bot_sends_p = bot_sends_p.clone();
+                let user_id = user_id.clone();
+                let create_tx_data_string = create_tx_data_string.clone();
+                move |receiver_user_id: String, transaction_message: String, max_retries: u32| {
+                    app_arc.lock().unwrap().set_inputs(
+                        Some(receiver_user_id.clone()),
+                        Some(transaction_message.clone()),
+                    );
+                    let data_string = create_tx_data_string(
+                        user_id.clone(),
+                        receiver_user_id.clone(),
+                        transaction_message.clone(),
+                    );
+                    {
+                        let mut app = app_arc.lock().unwrap();
+                        app.tx_latency
+                            .record_created(data_string.clone(), Instant::now());
+                        app.tx_traces
+                            .record_submitted(data_string.clone(), clock().now_ms());
+                    }
+                    bot_sends_p.lock().unwrap().insert(
+                        data_string.clone(),
+                        BotSend {
+                            receiver: receiver_user_id,
+                            message: transaction_message,
+                            retries: 0,
+                            max_retries,
+                        },
+                    );
+                    let request = IPCMessageReqNakamoto::RequestSimulateTx(data_string);
+                    writeln!(
+                        nakamoto_stdin_p.lock().unwrap(),
+                        "{}",
+                        serde_json::to_string(&request).unwrap()
+                    )
+                    .expect("Failed to write to bin_nakamoto stdin");
+                }
+            };
+            // Set to stop the repeated sends of the SendEvery commands, then replaced for the next ones
+            let mut send_every_stop = Arc::new(AtomicBool::new(false));
+
+            // A named pipe blocks on open until the writer opens it, so it is opened in this thread
+            let file = match File::open(&bot_command_path) {
+                Ok(file) => file,
+                Err(e) => {
+                    app_arc.lock().unwrap().client_log(format!(
+                        "Cannot open bot commands {}: {}",
+                        bot_command_path, e
+                    ));
+                    return;
+                }
+            };
+            // The variables defined by Let and Peers. $SELF and $BALANCE(self) are added at execution time.
+            let mut bot_vars: BTreeMap<String, String> = BTreeMap::new();
+            for line in io::BufReader::new(file).lines() {
+                let Ok(line) = line else { break };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let mut vars = bot_vars.clone();
+                vars.insert("SELF".to_string(), user_id.clone());
+                let balance = app_arc.lock().unwrap().user_balance;
+                vars.insert("BALANCE(self)".to_string(), balance.to_string());
+                let bot_command = schema::from_json_str::<BotCommand>("BotCommand", &line)
+                    .and_then(|bot_command| bot_command.substitute(&vars));
+                let bot_command = match bot_command {
+                    Ok(bot_command) => bot_command,
+                    Err(e) => {
+                        app_arc.lock().unwrap().client_log(format!(
+                            "Invalid bot command {}: {}",
+                            line.trim(),
+                            e
+                        ));
+                        continue;
+                    }
+                };
+                app_arc
+                    .lock()
+                    .unwrap()
+                    .client_log(format!("Bot: {:?}", bot_command));
+                match bot_command {
+                    BotCommand::Send(receiver_user_id, transaction_message) => {
+                        bot_send(receiver_user_id, transaction_message, max_send_retries);
+                    }
+                    BotCommand::SendEvery(0, _, _) => {
+                        send_every_stop.store(true, Ordering::Relaxed);
+                        send_every_stop = Arc::new(AtomicBool::new(false));
+                    }
+                    BotCommand::SendEvery(interval_ms, receiver_user_id, transaction_message) => {
+                        // The interval follows the clock of the client, so a virtual clock paces it with the SleepMs commands
+                        let bot_send = bot_send.clone();
+                        let stop = send_every_stop.clone();
+                        let max_retries = max_send_retries;
+                        thread::spawn(move || {
+                            while !stop.load(Ordering::Relaxed) {
+                                bot_send(
+                                    receiver_user_id.clone(),
+                                    transaction_message.clone(),
+                                    max_retries,
+                                );
+                                clock().sleep_ms(interval_ms);
+                            }
+                        });
+                    }
+                    BotCommand::WaitForBalance(threshold) => {
+                        // The balance is updated by the periodic status requests
+                        while app_arc.lock().unwrap().user_balance < threshold {
+                            bot_sleep(BOT_POLL_MS);
+                        }
+                    }
+                    BotCommand::DumpState(path) => {
+                        let state = app_arc.lock().unwrap().state_json();
+                        let result = serde_json::to_string_pretty(&state)
+                            .map_err(|e| e.to_string())
+                            .and_then(|json| fs::write(&path, json).map_err(|e| e.to_string()));
+                        if let Err(e) = result {
+                            app_arc.lock().unwrap().client_log(format!(
+                                "Bot: Cannot dump the state to {}: {}",
+                                path, e
+                            ));
+                        }
+                    }
+                    BotCommand::Quit => {
+                        send_every_stop.store(true, Ordering::Relaxed);
+                        app_arc.lock().unwrap().on_quit();
+                        return;
+                    }
+                    BotCommand::SendRetries(max_retries) => {
+                        max_send_retries = max_retries;
+                    }
+                    BotCommand::Open(link) => {
+                        app_arc.lock().unwrap().open_link(&link);
+                    }
+                    BotCommand::SleepMs(milliseconds) => {
+                        bot_sleep(milliseconds);
+                    }
+                    BotCommand::MineBlocks(block_count) => {
+                        // The depth is updated by the periodic status requests
+                        let start_depth = loop {
+                            if let Some(depth) = working_depth() {
+                                break depth;
+                            }
+                            bot_sleep(BOT_POLL_MS);
+                        };
+                        send_request(IPCMessageReqNakamoto::StartMiner);
+                        while working_depth().unwrap_or(start_depth) < start_depth + block_count {
+                            bot_sleep(BOT_POLL_MS);
+                        }
+                        send_request(IPCMessageReqNakamoto::StopMiner);
+                    }
+                    BotCommand::Let(name, value) => {
+                        bot_vars.insert(name, value);
+                    }
+                    BotCommand::Peers(user_ids) => {
+                        let peers = user_ids.into_iter().filter(|id| *id != user_id);
+                        for (idx, peer) in peers.enumerate() {
+                            bot_vars.insert(format!("PEER{}", idx + 1), peer);
+                        }
+                    }
+                    BotCommand::Flood(0, _) => {
+                        app_arc
+                            .lock()
+                            .unwrap()
+                            .client_log("Bot: Flood needs a positive rate".to_string());
+                    }
+                    BotCommand::Flood(tx_per_second, duration_ms) => {
+                        let mut receivers: Vec<String> = bot_vars
+                            .iter()
+                            .filter(|(name, _)| name.starts_with("PEER"))
+                            .map(|(_, peer)| peer.clone())
+                            .collect();
+                        if receivers.is_empty() {
+                            receivers.push(user_id.clone());
+                        }
+                        let start = Instant::now();
+                        *flood_p.lock().unwrap() =
+                            Some(FloodRun::new(tx_per_second, duration_ms, start));
+                        let interval = Duration::from_secs_f64(1.0 / tx_per_second as f64);
+                        let mut created: u32 = 0;
+                        while start.elapsed() < Duration::from_millis(duration_ms) {
+                            // The counter keeps the data_strings unique when several txs are created in the same ms
+                            let amount = FLOOD_AMOUNTS[created as usize % FLOOD_AMOUNTS.len()];
+                            let data_string = create_tx_data_string(
+                                user_id.clone(),
+                                receivers[created as usize % receivers.len()].clone(),
+                                format!("SEND ${}   // Flood {}", amount, created),
+                            );
+                            if let Some(flood) = flood_p.lock().unwrap().as_mut() {
+                                flood.record_created(data_string.clone(), Instant::now());
+                            }
+                            app_arc
+                                .lock()
+                                .unwrap()
+                                .tx_latency
+                                .record_created(data_string.clone(), Instant::now());
+                            send_request(IPCMessageReqNakamoto::RequestSimulateTx(data_string));
+                            created += 1;
+                            // Pace against the start, so that slow iterations do not lower the rate
+                            if let Some(wait) =
+                                (start + interval * created).checked_duration_since(Instant::now())
+                            {
+                                thread::sleep(wait);
+                            }
+                        }
+                        let drain_start = Instant::now();
+                        if let Some(flood) = flood_p.lock().unwrap().as_mut() {
+                            flood.record_generation_end(drain_start);
+                        }
+                        // Give the outstanding txs some time to be included in a block before reporting
+                        while flood_p
+                            .lock()
+                            .unwrap()
+                            .as_ref()
+                            .map_or(0, FloodRun::outstanding)
+                            > 0
+                            && drain_start.elapsed() < Duration::from_millis(FLOOD_DRAIN_MS)
+                        {
+                            bot_sleep(BOT_POLL_MS);
+                        }
+                        // Take the run out first: the response reader locks the app before the run
+                        let flood = flood_p.lock().unwrap().take();
+                        if let Some(flood) = flood {
+                            let mut app = app_arc.lock().unwrap();
+                            for line in flood.report() {
+                                app.client_log(format!("Bot: Flood {}", line));
+                            }
+                        }
+                    }
+                }
+            }
+        });
     }
 
     // Please fill in the blank
@@ -402,19 +1237,40 @@ fn main() {
     // Spawn a thread to read SignResponse from bin_wallet and send it to bin_nakamoto
     {
         let nakamoto_stdin_p = nakamoto_stdin_p.clone();
+        let app_arc = app_arc.clone();
+        let user_id = user_id.clone();
+        let flood_p = flood_p.clone();
         thread::spawn(move || {
             loop {
-                let mut wallet_response = String::new();
-                bin_wallet_reader
-                    .lock()
-                    .unwrap()
-                    .read_line(&mut wallet_response)
-                    .unwrap();
-                let wallet_response: IPCMessageRespWallet =
-                    serde_json::from_str(&wallet_response).unwrap();
+                let Some(wallet_response) =
+                    read_ipc_response(&mut *bin_wallet_reader.lock().unwrap(), |e| {
+                        app_arc
+                            .lock()
+                            .unwrap()
+                            .client_log(format!("Unexpected output from wallet: {}", e))
+                    })
+                else {
+                    app_arc.lock().unwrap().status_bar.wallet = WalletState::Closed;
+                    break;
+                };
                 match wallet_response {
+                    IPCMessageRespWallet::SignPending(data_string) => {
+                        // The wallet signs in the background, so the UI shows the tx as being signed meanwhile
+                        app_arc.lock().unwrap().signing_txs.push(data_string);
+                    }
                     IPCMessageRespWallet::SignResponse(data_string, signature) => {
-                        // send to bin_nakamoto
+                        if let Some(flood) = flood_p.lock().unwrap().as_mut() {
+                            flood.record_published(&data_string);
+                        }
+                        // Show the amount as pending until the tx is in a block
+                        let amount = tx_amount(&data_string).unwrap_or(0);
+                        {
+                            let mut app = app_arc.lock().unwrap();
+                            app.signing_txs.retain(|signing| *signing != data_string);
+                            app.pending_txs.push((data_string.clone(), amount));
+                            app.trace_signed(&data_string, &signature);
+                        }
+                        // send to bin_nakamoto, then refresh the balances right away instead of at the next periodic update
                         let mut nakamoto_stdin = nakamoto_stdin_p.lock().unwrap();
                         nakamoto_stdin
                             .write_all(
@@ -429,6 +1285,26 @@ fn main() {
                                 .as_bytes(),
                             )
                             .expect("Failed to write to bin_nakamoto stdin");
+                        for request in balance_refresh_requests(&user_id) {
+                            writeln!(
+                                nakamoto_stdin,
+                                "{}",
+                                serde_json::to_string(&request).unwrap()
+                            )
+                            .expect("Failed to write to bin_nakamoto stdin");
+                        }
+                    }
+                    IPCMessageRespWallet::SignRejected(data_string, reason) => {
+                        if let Some(flood) = flood_p.lock().unwrap().as_mut() {
+                            flood.record_not_signed(&data_string);
+                        }
+                        let mut app = app_arc.lock().unwrap();
+                        app.tx_latency.forget(&data_string);
+                        app.signing_txs.retain(|signing| *signing != data_string);
+                        app.client_log(format!(
+                            "Wallet refused to sign {}: {}",
+                            data_string, reason
+                        ));
                     }
                     _ => panic!("Fail at SignResponse"),
                 }
@@ -436,131 +1312,423 @@ fn main() {
         });
     }
 
-    // Spawn a thread to read from stderr of bin_nakamoto and bin_wallet and add those lines to the UI (app.stderr_log) for easier debugging.
-    // {
-    //     let app_arc = app_arc.clone();
-    //     thread::spawn(move || loop {
-    //         let mut nakamoto_stderr = String::new();
-    //         nakamoto_stderr_reader
-    //             .read_line(&mut nakamoto_stderr)
-    //             .expect("Failed to read from bin_nakamoto stderr");
-    //         let mut app = app_arc.lock().expect("Failed to acquire app mutex");
-    //         app.stderr_log.push(nakamoto_stderr);
-
-    //         let mut wallet_stderr = String::new();
-    //         wallet_stderr_reader
-    //             .read_line(&mut wallet_stderr)
-    //             .expect("Failed to read from bin_wallet stderr");
-    //         app.stderr_log.push(wallet_stderr);
-    //     });
-    // }
-
-    // Spawn a thread to periodically request for status update from bin_nakamoto
-    {
-        let nakamoto_stdin_p = nakamoto_stdin_p.clone();
+    // Spawn one thread per child to read from its stderr, add those lines to the UI (app.stderr_log) and to its log file for easier debugging.
+    // The lines shown in the UI are rate-limited and cut (see `StderrForwarder`), so a chatty child does not hold the app lock.
+    // The trace events of the transactions (see `lib_chain::txtrace`) are kept for the `:trace` command instead of being shown.
+    let stderr_lines_per_sec = client_config.stderr_lines_per_sec;
+    for (name, stderr_reader, mut log_file) in [
+        (
+            "Nakamoto",
+            Box::new(nakamoto_stderr_reader) as Box<dyn BufRead + Send>,
+            open_log("nakamoto"),
+        ),
+        ("Wallet", Box::new(wallet_stderr_reader), open_log("wallet")),
+    ] {
         let app_arc = app_arc.clone();
         thread::spawn(move || {
-            loop {
-                // Get AddressBalance from bin_nakamoto
-                let address_balance_request =
-                    IPCMessageReqNakamoto::GetAddressBalance(user_id.clone());
-                let address_balance_request_str =
-                    serde_json::to_string(&address_balance_request).unwrap();
-                let mut nakamoto_stdin = nakamoto_stdin_p.lock().unwrap();
-                nakamoto_stdin
-                    .write_all(format!("{}\n", address_balance_request_str).as_bytes())
-                    .expect("Failed to write to bin_nakamoto stdin");
-
-                // Update UI from response
-                let mut app = app_arc.lock().expect("Failed to acquire app mutex");
-                let mut nakamoto_stdout = String::new();
-                bin_nakamoto_reader
-                    .lock()
-                    .unwrap()
-                    .read_line(&mut nakamoto_stdout)
-                    .unwrap();
-                let nakamoto_stdout: IPCMessageRespNakamoto =
-                    serde_json::from_str(&nakamoto_stdout).unwrap();
-                match nakamoto_stdout {
-                    IPCMessageRespNakamoto::AddressBalance(_user_id, address_balance) => {
-                        app.user_balance = address_balance;
+            let mut forwarder = StderrForwarder::new(name, stderr_lines_per_sec);
+            let start = Instant::now();
+            let push_lines = |lines: Vec<String>| {
+                if !lines.is_empty() {
+                    let mut app = app_arc.lock().unwrap();
+                    for line in lines {
+                        app.stderr_log.push(line);
                     }
-                    _ => panic!("Unexpected response from nakamoto"),
                 }
+            };
+            for line in stderr_reader.lines() {
+                let Ok(line) = line else { break };
+                if let Some(log_file) = log_file.as_mut() {
+                    let _ = log_file.write_line(&line);
+                }
+                if let Some(event) = TxTraceEvent::from_log_line(&line) {
+                    app_arc.lock().unwrap().tx_traces.record(event);
+                    continue;
+                }
+                push_lines(forwarder.forward(&line, start.elapsed().as_millis() as u64));
+            }
+            push_lines(forwarder.flush());
+        });
+    }
 
-                // Get status from bin_nakamoto
-                let chain_status_request = IPCMessageReqNakamoto::RequestChainStatus;
-                let chain_status_request_str =
-                    serde_json::to_string(&chain_status_request).unwrap();
-                writeln!(
-                    nakamoto_stdin_p.lock().unwrap(),
-                    "{}",
-                    chain_status_request_str
-                )
-                .expect("Failed to write to bin_nakamoto stdin");
-
-                let net_status_request = IPCMessageReqNakamoto::RequestNetStatus;
-                let net_status_request_str = serde_json::to_string(&net_status_request).unwrap();
-                writeln!(
-                    nakamoto_stdin_p.lock().unwrap(),
-                    "{}",
-                    net_status_request_str
-                )
-                .expect("Failed to write to bin_nakamoto stdin");
-
-                let miner_status_request = IPCMessageReqNakamoto::RequestMinerStatus;
-                let miner_status_request_str =
-                    serde_json::to_string(&miner_status_request).unwrap();
-                writeln!(
-                    nakamoto_stdin_p.lock().unwrap(),
-                    "{}",
-                    miner_status_request_str
-                )
-                .expect("Failed to write to bin_nakamoto stdin");
-
-                let pool_status_request = IPCMessageReqNakamoto::RequestTxPoolStatus;
-                let pool_status_request_str = serde_json::to_string(&pool_status_request).unwrap();
+    // Spawn a thread to send the requests triggered by the events of bin_nakamoto (e.g. a balance refresh after a block is finalized),
+    // so that the thread reading the responses never blocks on writing to bin_nakamoto.
+    let (event_requests_tx, event_requests_rx) = channel::<IPCMessageReqNakamoto>();
+    {
+        let nakamoto_stdin_p = nakamoto_stdin_p.clone();
+        thread::spawn(move || {
+            for request in event_requests_rx {
                 writeln!(
                     nakamoto_stdin_p.lock().unwrap(),
                     "{}",
-                    pool_status_request_str
+                    serde_json::to_string(&request).unwrap()
                 )
                 .expect("Failed to write to bin_nakamoto stdin");
+            }
+        });
+    }
 
-                let mut nakamoto_response = String::new();
-                bin_nakamoto_reader
-                    .lock()
-                    .unwrap()
-                    .read_line(&mut nakamoto_response)
-                    .unwrap();
-                let nakamoto_response: IPCMessageRespNakamoto =
-                    serde_json::from_str(&nakamoto_response).unwrap();
-
-                match nakamoto_response {
-                    IPCMessageRespNakamoto::ChainStatus(status) => {
-                        app_arc.lock().unwrap().blocktree_status = status;
+    // Spawn a thread to read the responses from bin_nakamoto and update the UI accordingly.
+    // All responses go through this thread, so that requests from different threads do not steal each other's responses.
+    {
+        let bin_wallet_stdin_p = bin_wallet_stdin_p.clone();
+        let nakamoto_stdin_p = nakamoto_stdin_p.clone();
+        let app_arc = app_arc.clone();
+        let user_id = user_id.clone();
+        let refresh_user_id = user_id.clone();
+        // The heights of the finalized blocks requested to look for payments to run the payment hook on, by block_id
+        let mut hook_block_heights: HashMap<String, u64> = HashMap::new();
+        let bot_sends_retry_p = bot_sends_p.clone();
+        let app_retry_p = app_arc.clone();
+        let flood_p = flood_p.clone();
+        let create_tx_data_string = create_tx_data_string.clone();
+        // Retry a rejected bot Send after its backoff, as a new transaction (with a new timestamp), if it has retries left
+        let retry_bot_send =
+            move |app: &mut app::App, data_string: &str, reason: String, retryable: bool| {
+                let Some(bot_send) = bot_sends_retry_p.lock().unwrap().remove(data_string) else {
+                    return;
+                };
+                if !retryable || bot_send.retries >= bot_send.max_retries {
+                    app.client_log(format!(
+                        "Bot: Send {:?} failed after {} retries: {}",
+                        bot_send.message, bot_send.retries, reason
+                    ));
+                    return;
+                }
+                let delay = bot_send.backoff();
+                app.client_log(format!(
+                    "Bot: Send {:?} rejected ({}), retry {}/{} in {} ms",
+                    bot_send.message,
+                    reason,
+                    bot_send.retries + 1,
+                    bot_send.max_retries,
+                    delay.as_millis()
+                ));
+                let bot_sends_p = bot_sends_retry_p.clone();
+                let app_arc = app_retry_p.clone();
+                let nakamoto_stdin_p = nakamoto_stdin_p.clone();
+                let user_id = user_id.clone();
+                let create_tx_data_string = create_tx_data_string.clone();
+                thread::spawn(move || {
+                    clock().sleep_ms(delay.as_millis() as u64);
+                    let data_string = create_tx_data_string(
+                        user_id,
+                        bot_send.receiver.clone(),
+                        bot_send.message.clone(),
+                    );
+                    {
+                        let mut app = app_arc.lock().unwrap();
+                        app.tx_latency
+                            .record_created(data_string.clone(), Instant::now());
+                        app.tx_traces
+                            .record_submitted(data_string.clone(), clock().now_ms());
                     }
-                    IPCMessageRespNakamoto::NetStatus(status) => {
-                        app_arc.lock().unwrap().network_status = status;
+                    bot_sends_p.lock().unwrap().insert(
+                        data_string.clone(),
+                        BotSend {
+                            retries: bot_send.retries + 1,
+                            ..bot_send
+                        },
+                    );
+                    let request = IPCMessageReqNakamoto::RequestSimulateTx(data_string);
+                    writeln!(
+                        nakamoto_stdin_p.lock().unwrap(),
+                        "{}",
+                        serde_json::to_string(&request).unwrap()
+                    )
+                    .expect("Failed to write to bin_nakamoto stdin");
+                });
+            };
+        thread::spawn(move || loop {
+            let Some(nakamoto_response) =
+                read_ipc_response(&mut *bin_nakamoto_reader.lock().unwrap(), |e| {
+                    app_arc
+                        .lock()
+                        .unwrap()
+                        .client_log(format!("Unexpected output from nakamoto: {}", e))
+                })
+            else {
+                app_arc.lock().unwrap().status_bar.node_closed = true;
+                break;
+            };
+
+            let mut app = app_arc.lock().unwrap();
+            app.status_bar.on_node_response(clock().now_ms());
+            match nakamoto_response {
+                IPCMessageRespNakamoto::AddressBalance(_user_id, branch, address_balance) => {
+                    match branch {
+                        BalanceBranch::Finalized => app.user_balance = address_balance,
+                        BalanceBranch::WorkingTip => app.user_balance_working = address_balance,
                     }
-                    IPCMessageRespNakamoto::MinerStatus(status) => {
-                        app_arc.lock().unwrap().miner_status = status;
+                }
+                IPCMessageRespNakamoto::ChainStatus(status) => {
+                    app.status_bar.working_height = Some(status.height);
+                    app.status_bar.orphan_count = status.orphan_count as u64;
+                    app.status_bar.finalized_height = Some(status.finalized_height);
+                    app.blocktree_status = Some(status);
+                }
+                IPCMessageRespNakamoto::NetStatus(status) => {
+                    if let Some(peers) = status.get("peers") {
+                        app.status_bar.set_peers_from_status(peers);
                     }
-                    IPCMessageRespNakamoto::TxPoolStatus(status) => {
-                        app_arc.lock().unwrap().txpool_status = status;
+                    app.network_status = status;
+                }
+                IPCMessageRespNakamoto::MinerStatus(status) => {
+                    app.status_bar.mining = status
+                        .get("is_enabled")
+                        .and_then(|enabled| enabled.as_bool());
+                    app.miner_status = status;
+                }
+                IPCMessageRespNakamoto::TxPoolStatus(status) => {
+                    app.txpool_status = status;
+                }
+                IPCMessageRespNakamoto::ChainStats(stats) => {
+                    app.chain_stats = stats;
+                }
+                IPCMessageRespNakamoto::AddressHistory(_user_id, entries) => {
+                    app.tx_history.set_entries(entries);
+                }
+                IPCMessageRespNakamoto::ConfigStatus(status) => {
+                    app.config_status = status;
+                }
+                IPCMessageRespNakamoto::ConfigUpdated(key, applied) => {
+                    app.client_log(format!("Config applied: {} = {}", key, applied));
+                    app.config_status.insert(key, applied);
+                }
+                IPCMessageRespNakamoto::ChainImported(added, known, rejected) => {
+                    app.client_log(format!(
+                        "Chain imported: {} blocks added, {} already known, {} rejected",
+                        added, known, rejected
+                    ));
+                }
+                IPCMessageRespNakamoto::BlockWork(block_id, work) => {
+                    let figures: Vec<String> = work
+                        .iter()
+                        .map(|(key, value)| format!("{}={}", key, value))
+                        .collect();
+                    app.client_log(format!("Block {} work: {}", block_id, figures.join(", ")));
+                }
+                IPCMessageRespNakamoto::ChainAudit(true, _) => {
+                    app.client_log("Chain audit passed".to_string());
+                }
+                IPCMessageRespNakamoto::ChainAudit(false, violation) => {
+                    app.client_log(format!("Chain audit failed: {}", violation));
+                }
+                IPCMessageRespNakamoto::ChainInvariants(violations) => {
+                    if violations.is_empty() {
+                        app.client_log("Chain invariants hold".to_string());
                     }
-                    _ => panic!("Unexpected response from nakamoto"),
-                };
+                    for violation in violations {
+                        app.client_log(format!("Chain invariant violated: {}", violation));
+                    }
+                }
+                IPCMessageRespNakamoto::SimulateTxResult(data_string, true, _) => {
+                    // The tx would be accepted, so ask the wallet to sign it
+                    bin_wallet_stdin_p
+                        .lock()
+                        .unwrap()
+                        .write_all(create_sign_req(data_string).as_bytes())
+                        .expect("Failed to write to bin_wallet stdin");
+                }
+                IPCMessageRespNakamoto::SimulateTxResult(data_string, false, reason) => {
+                    if let Some(flood) = flood_p.lock().unwrap().as_mut() {
+                        flood.record_not_signed(&data_string);
+                    }
+                    app.tx_latency.forget(&data_string);
+                    app.client_log(format!("Tx not signed, it would be rejected: {}", reason));
+                    // The balance may suffice once mining rewards or incoming payments arrive
+                    let retryable = reason.starts_with("insufficient balance");
+                    retry_bot_send(&mut app, &data_string, reason, retryable);
+                }
+                IPCMessageRespNakamoto::PublishTxDone => {
+                    app.client_log("Tx queued in the tx pool".to_string());
+                }
+                IPCMessageRespNakamoto::PublishTxRejected(data_string, reason) => {
+                    app.pending_txs
+                        .retain(|(pending, _)| *pending != data_string);
+                    app.client_log(format!("Tx rejected by nakamoto: {:?}", reason));
+                    if let Some(flood) = flood_p.lock().unwrap().as_mut() {
+                        flood.record_pool_rejected(&data_string);
+                    }
+                    app.tx_latency.forget(&data_string);
+                    let retryable = matches!(
+                        reason,
+                        TxRejectReason::InsufficientBalance(..) | TxRejectReason::PoolFull
+                    );
+                    retry_bot_send(&mut app, &data_string, format!("{:?}", reason), retryable);
+                }
+                IPCMessageRespNakamoto::TxInclusion(data_string, true)
+                | IPCMessageRespNakamoto::Event(ChainEvent::TxConfirmed(data_string, _)) => {
+                    if let Some(flood) = flood_p.lock().unwrap().as_mut() {
+                        flood.record_included(&data_string, Instant::now());
+                    }
+                    app.tx_latency.record_included(&data_string, Instant::now());
+                    let pending_count = app.pending_txs.len();
+                    app.pending_txs
+                        .retain(|(pending, _)| *pending != data_string);
+                    if app.pending_txs.len() < pending_count {
+                        app.client_log("Tx included in a block".to_string());
+                    }
+                    if let Some(bot_send) = bot_sends_p.lock().unwrap().remove(&data_string) {
+                        app.client_log(format!(
+                            "Bot: Send {:?} included in a block after {} retries",
+                            bot_send.message, bot_send.retries
+                        ));
+                    }
+                }
+                IPCMessageRespNakamoto::TxFinality(data_string, true) => {
+                    app.tx_latency
+                        .record_finalized(&data_string, Instant::now());
+                }
+                IPCMessageRespNakamoto::MinerStarted => {
+                    app.status_bar.mining = Some(true);
+                    app.client_log("Miner started".to_string());
+                }
+                IPCMessageRespNakamoto::MinerStopped => {
+                    app.status_bar.mining = Some(false);
+                    app.client_log("Miner stopped".to_string());
+                }
+                IPCMessageRespNakamoto::MinerConfigSet(thread_count, target_hashrate) => {
+                    let target_hashrate = target_hashrate
+                        .map_or("no limit".to_string(), |rate| format!("{} H/s", rate));
+                    app.client_log(format!(
+                        "Miner set to {} threads ({})",
+                        thread_count, target_hashrate
+                    ));
+                }
+                IPCMessageRespNakamoto::PeerPaused(addr) => {
+                    app.client_log(format!("Traffic with {} paused", addr));
+                }
+                IPCMessageRespNakamoto::PeerResumed(addr) => {
+                    app.client_log(format!("Traffic with {} resumed", addr));
+                }
+                IPCMessageRespNakamoto::PeerBlocked(entry) => {
+                    app.client_log(format!("Peer {} blocked", entry));
+                }
+                IPCMessageRespNakamoto::PeerUnblocked(entry) => {
+                    app.client_log(format!("Peer {} unblocked", entry));
+                }
+                IPCMessageRespNakamoto::PeerAllowed(entry) => {
+                    app.client_log(format!("Peer {} allowed", entry));
+                }
+                IPCMessageRespNakamoto::PeerDisallowed(entry) => {
+                    app.client_log(format!("Peer {} disallowed", entry));
+                }
+                IPCMessageRespNakamoto::EventsSubscribed => {
+                    app.events_subscribed = true;
+                }
+                IPCMessageRespNakamoto::Event(ChainEvent::NewBlockFinalized(block_id, height)) => {
+                    app.status_bar.finalized_height = Some(height);
+                    app.client_log(format!("Block {} finalized at height {}", block_id, height));
+                    if payment_hook.is_enabled() {
+                        hook_block_heights.insert(block_id.clone(), height);
+                        let _ =
+                            event_requests_tx.send(IPCMessageReqNakamoto::RequestBlock(block_id));
+                    }
+                    for request in balance_refresh_requests(&refresh_user_id) {
+                        let _ = event_requests_tx.send(request);
+                    }
+                    let _ = event_requests_tx.send(IPCMessageReqNakamoto::RequestChainStatus);
+                }
+                IPCMessageRespNakamoto::BlockData(block_json) => {
+                    let Ok(block) = serde_json::from_str::<BlockNode>(&block_json) else {
+                        continue;
+                    };
+                    let Some(height) = hook_block_heights.remove(&block.header.block_id) else {
+                        continue;
+                    };
+                    for payment in payment_hook.payments_in_block(&block, &refresh_user_id, height)
+                    {
+                        app.client_log(format!(
+                            "Payment of ${} from {} to {} finalized",
+                            payment.amount, payment.sender, payment.receiver
+                        ));
+                        // Reap the hook in the background, so that the UI never waits for it
+                        match payment_hook.spawn(&payment) {
+                            Ok(mut child) => {
+                                thread::spawn(move || child.wait());
+                            }
+                            Err(e) => app.client_log(format!("Payment hook failed: {}", e)),
+                        }
+                    }
+                }
+                IPCMessageRespNakamoto::Event(ChainEvent::PeerConnected(addr)) => {
+                    app.client_log(format!("Neighbor {} connected", addr));
+                    app.status_bar.peers.insert(addr);
+                    let _ = event_requests_tx.send(IPCMessageReqNakamoto::RequestNetStatus);
+                }
+                IPCMessageRespNakamoto::Event(ChainEvent::NewBlock(_, height)) => {
+                    app.status_bar.working_height = Some(height);
+                }
+                IPCMessageRespNakamoto::Event(ChainEvent::Reorg(old_id, new_id, dropped)) => {
+                    app.client_log(format!(
+                        "Working chain switched from {} to {}, dropping {} blocks",
+                        old_id, new_id, dropped
+                    ));
+                }
+                IPCMessageRespNakamoto::Notify(msg) => {
+                    app.client_log(msg);
+                }
+                _ => {}
+            }
+        });
+    }
 
-                // Sleep for 500ms
-                thread::sleep(Duration::from_millis(500));
+    // Spawn a thread to periodically request for status update from bin_nakamoto
+    {
+        let nakamoto_stdin_p = nakamoto_stdin_p.clone();
+        let app_arc = app_arc.clone();
+        thread::spawn(move || loop {
+            let mut status_requests = balance_refresh_requests(&user_id).to_vec();
+            status_requests.extend([
+                IPCMessageReqNakamoto::RequestChainStatus,
+                IPCMessageReqNakamoto::RequestNetStatus,
+                IPCMessageReqNakamoto::RequestMinerStatus,
+                IPCMessageReqNakamoto::RequestChainStats(CHAIN_STATS_WINDOW),
+                IPCMessageReqNakamoto::RequestConfig,
+            ]);
+            // Check whether the pending txs made it into a block, and whether the included ones are finalized
+            {
+                let app = app_arc.lock().unwrap();
+                if app.show_history {
+                    status_requests.push(IPCMessageReqNakamoto::RequestAddressHistory(
+                        user_id.clone(),
+                    ));
+                }
+                status_requests.extend(app.pending_txs.iter().map(|(data_string, _)| {
+                    IPCMessageReqNakamoto::RequestTxInclusion(data_string.clone())
+                }));
+                status_requests.extend(
+                    app.tx_latency
+                        .awaiting_finality()
+                        .into_iter()
+                        .map(IPCMessageReqNakamoto::RequestTxFinality),
+                );
+            }
+            {
+                let mut nakamoto_stdin = nakamoto_stdin_p.lock().unwrap();
+                for request in status_requests {
+                    writeln!(
+                        nakamoto_stdin,
+                        "{}",
+                        serde_json::to_string(&request).unwrap()
+                    )
+                    .expect("Failed to write to bin_nakamoto stdin");
+                }
             }
+
+            // Sleep until the next poll, less often once the events are pushed
+            let poll_ms = if app_arc.lock().unwrap().events_subscribed {
+                STATUS_POLL_SUBSCRIBED_MS
+            } else {
+                STATUS_POLL_MS
+            };
+            clock().sleep_ms(poll_ms);
         });
     }
 
     // UI thread. Modify it to suit your needs.
     let app_ui_ref = app_arc.clone();
-    let bin_wallet_stdin_p_cloned = bin_wallet_stdin_p.clone();
     let nakamoto_stdin_p_cloned = nakamoto_stdin_p.clone();
     let handle_ui = thread::spawn(move || {
         let tick_rate = Duration::from_millis(200);
@@ -575,6 +1743,21 @@ fn main() {
             }
             return;
         }
+        // Simulate a new tx of the user first; it is signed once bin_nakamoto accepts it
+        let submit_tx = |app: &mut app::App, data_string: String| {
+            app.tx_latency
+                .record_created(data_string.clone(), Instant::now());
+            app.tx_traces
+                .record_submitted(data_string.clone(), clock().now_ms());
+            let simulate_req = IPCMessageReqNakamoto::RequestSimulateTx(data_string);
+            let mut to_send = serde_json::to_string(&simulate_req).unwrap();
+            to_send.push('\n');
+            nakamoto_stdin_p_cloned
+                .lock()
+                .unwrap()
+                .write_all(to_send.as_bytes())
+                .unwrap();
+        };
         let ui_loop = || -> Result<(), io::Error> {
             // setup terminal
             enable_raw_mode()?;
@@ -594,11 +1777,142 @@ fn main() {
                 if crossterm::event::poll(timeout)? {
                     let input = event::read()?.into();
                     let mut app = app_ui_ref.lock().unwrap();
+                    // While the history panel is shown (and the settings panel is not), it takes all the inputs
+                    if app.show_history && !app.show_settings {
+                        match input {
+                            Input { key: Key::Esc, .. }
+                            | Input {
+                                key: Key::Char('l'),
+                                ctrl: true,
+                                ..
+                            } => app.toggle_history(),
+                            Input { key: Key::Up, .. } => app.tx_history.scroll_up(1),
+                            Input { key: Key::Down, .. } => app.tx_history.scroll_down(1),
+                            Input {
+                                key: Key::PageUp, ..
+                            } => app.tx_history.scroll_up(app::LOG_PANEL_LINES),
+                            Input {
+                                key: Key::PageDown, ..
+                            } => app.tx_history.scroll_down(app::LOG_PANEL_LINES),
+                            _ => {}
+                        }
+                        continue;
+                    }
+                    // While the settings panel is shown, it takes all the inputs
+                    if app.show_settings {
+                        match input {
+                            Input { key: Key::Esc, .. }
+                            | Input {
+                                key: Key::Char('o'),
+                                ctrl: true,
+                                ..
+                            } => app.toggle_settings(),
+                            Input {
+                                key: Key::Enter, ..
+                            } => {
+                                let requests = match app.on_settings_enter() {
+                                    Some(SettingsAction::UpdateConfig(key, value)) => {
+                                        vec![IPCMessageReqNakamoto::UpdateConfig(key, value)]
+                                    }
+                                    Some(SettingsAction::ExportTx(path)) => {
+                                        // The same tx as on ENTER, left for an offline wallet to sign
+                                        if !app.are_inputs_valid {
+                                            app.client_log(
+                                                "Invalid inputs! Cannot export Tx.".to_string(),
+                                            );
+                                        } else {
+                                            let (sender, receiver, message) = app.form_inputs();
+                                            let data_string =
+                                                create_tx_data_string(sender, receiver, message);
+                                            match offline::export_unsigned(&path, &data_string) {
+                                                Ok(()) => app.client_log(format!(
+                                                    "Exported unsigned Tx to {}",
+                                                    path
+                                                )),
+                                                Err(e) => app.client_log(e),
+                                            }
+                                        }
+                                        vec![]
+                                    }
+                                    Some(SettingsAction::ImportTx(path)) => {
+                                        match offline::import_signed(&path) {
+                                            Ok((data_string, signature)) => {
+                                                app.client_log(format!(
+                                                    "Publishing Tx signed offline from {}",
+                                                    path
+                                                ));
+                                                let amount = tx_amount(&data_string).unwrap_or(0);
+                                                app.pending_txs.push((data_string.clone(), amount));
+                                                let mut requests =
+                                                    vec![IPCMessageReqNakamoto::PublishTx(
+                                                        data_string,
+                                                        signature,
+                                                    )];
+                                                requests
+                                                    .extend(balance_refresh_requests(&app.user_id));
+                                                requests
+                                            }
+                                            Err(e) => {
+                                                app.client_log(e);
+                                                vec![]
+                                            }
+                                        }
+                                    }
+                                    None => vec![],
+                                };
+                                let mut nakamoto_stdin = nakamoto_stdin_p_cloned.lock().unwrap();
+                                for request in requests {
+                                    writeln!(
+                                        nakamoto_stdin,
+                                        "{}",
+                                        serde_json::to_string(&request).unwrap()
+                                    )
+                                    .unwrap();
+                                }
+                            }
+                            input => app.on_settings_input(input),
+                        }
+                        continue;
+                    }
+                    // While the batch panel is shown (and the settings panel is not), it takes all the inputs
+                    if app.show_batch {
+                        match input {
+                            Input { key: Key::Esc, .. }
+                            | Input {
+                                key: Key::Char('n'),
+                                ctrl: true,
+                                ..
+                            } => app.toggle_batch(),
+                            Input {
+                                key: Key::Char('s'),
+                                ctrl: true,
+                                ..
+                            } => {
+                                if let Some((sender, payments)) = app.on_batch_enter() {
+                                    let data_string = create_batch_data_string(sender, payments);
+                                    submit_tx(&mut app, data_string);
+                                }
+                            }
+                            input => app.on_batch_input(input),
+                        }
+                        continue;
+                    }
                     match input {
                         Input { key: Key::Esc, .. } => {
                             app.on_quit();
                         }
                         Input { key: Key::Down, .. } => app.on_down(),
+                        // PageUp/PageDown scroll the STDERR log, and the Notify log with Ctrl
+                        Input {
+                            key: Key::PageUp,
+                            ctrl,
+                            ..
+                        } => app.on_page_up(ctrl),
+                        Input {
+                            key: Key::PageDown,
+                            ctrl,
+                            ..
+                        } => app.on_page_down(ctrl),
                         Input { key: Key::Up, .. } => app.on_up(),
                         Input {
                             key: Key::Enter, ..
@@ -607,12 +1921,8 @@ fn main() {
                                 app.client_log("Invalid inputs! Cannot create Tx.".to_string());
                             } else {
                                 let (sender, receiver, message) = app.on_enter();
-                                let sign_req_str = create_sign_req(sender, receiver, message);
-                                bin_wallet_stdin_p_cloned
-                                    .lock()
-                                    .unwrap()
-                                    .write_all(sign_req_str.as_bytes())
-                                    .unwrap();
+                                let data_string = create_tx_data_string(sender, receiver, message);
+                                submit_tx(&mut app, data_string);
                             }
                         }
                         // on control + s, request Nakamoto to serialize its state
@@ -624,13 +1934,101 @@ fn main() {
                             let serialize_req = IPCMessageReqNakamoto::RequestStateSerialization;
                             let nakamoto_stdin = nakamoto_stdin_p_cloned.clone();
                             let mut to_send = serde_json::to_string(&serialize_req).unwrap();
-                            to_send.push_str("\n");
+                            to_send.push('\n');
                             nakamoto_stdin
                                 .lock()
                                 .unwrap()
                                 .write_all(to_send.as_bytes())
                                 .unwrap();
                         }
+                        // on control + o, show the settings panel
+                        Input {
+                            key: Key::Char('o'),
+                            ctrl: true,
+                            ..
+                        } => app.toggle_settings(),
+                        // on control + n, show the panel paying several receivers with one transaction
+                        Input {
+                            key: Key::Char('n'),
+                            ctrl: true,
+                            ..
+                        } => app.toggle_batch(),
+                        // on control + l, show the transaction history of the user, refreshed with the status
+                        Input {
+                            key: Key::Char('l'),
+                            ctrl: true,
+                            ..
+                        } => {
+                            app.toggle_history();
+                            let history_req =
+                                IPCMessageReqNakamoto::RequestAddressHistory(app.user_id.clone());
+                            let mut to_send = serde_json::to_string(&history_req).unwrap();
+                            to_send.push('\n');
+                            nakamoto_stdin_p_cloned
+                                .lock()
+                                .unwrap()
+                                .write_all(to_send.as_bytes())
+                                .unwrap();
+                        }
+                        // on control + y, copy the input in focus (e.g. an address) to the clipboard
+                        Input {
+                            key: Key::Char('y'),
+                            ctrl: true,
+                            ..
+                        } => {
+                            let (name, value) = app.focused_input();
+                            match copy_to_clipboard(&value) {
+                                Ok(()) => app.client_log(format!("Copied {} to clipboard", name)),
+                                Err(e) => app.client_log(format!("Cannot copy {}: {}", name, e)),
+                            }
+                        }
+                        // on control + b, copy the working block id to the clipboard
+                        Input {
+                            key: Key::Char('b'),
+                            ctrl: true,
+                            ..
+                        } => match app
+                            .blocktree_status
+                            .as_ref()
+                            .map(|status| status.working_block_id.clone())
+                        {
+                            Some(block_id) => match copy_to_clipboard(&block_id) {
+                                Ok(()) => app.client_log(format!("Copied block id {}", block_id)),
+                                Err(e) => app.client_log(format!("Cannot copy block id: {}", e)),
+                            },
+                            None => app.client_log("No working block id yet".to_string()),
+                        },
+                        // on control + r, reset the transaction form to the template of the user
+                        Input {
+                            key: Key::Char('r'),
+                            ctrl: true,
+                            ..
+                        } => app.reset_inputs(),
+                        // on control + t, switch to the next color theme
+                        Input {
+                            key: Key::Char('t'),
+                            ctrl: true,
+                            ..
+                        } => app.cycle_theme(),
+                        // on control + a, request Nakamoto to audit the supply and the invariants of the chain
+                        Input {
+                            key: Key::Char('a'),
+                            ctrl: true,
+                            ..
+                        } => {
+                            for audit_req in [
+                                IPCMessageReqNakamoto::AuditChain,
+                                IPCMessageReqNakamoto::CheckChainInvariants,
+                            ] {
+                                let mut to_send = serde_json::to_string(&audit_req).unwrap();
+                                to_send.push('\n');
+                                nakamoto_stdin_p_cloned
+                                    .lock()
+                                    .unwrap()
+                                    .write_all(to_send.as_bytes())
+                                    .unwrap();
+                            }
+                        }
                         input => {
                             app.on_textarea_input(input);
                         }
@@ -693,3 +2091,202 @@ fn main() {
 // ./bin_client/policies/seccomp_wallet.json
 // Bob’s user id:
 // MDgCMQDZDExOs97sRTnQLYtgFjDKpDzmO7Uo5HPP62u6MDimXBpZtGxtwa8dhJe5NBIsJjUCAwEAAQ==
+
+#[cfg(test)]
+mod test {
+    use crate::app::{status_text, status_value_text, App, Theme};
+    use crate::{
+        osc52_sequence, read_ipc_frame, read_ipc_response, split_optional_args,
+        substitute_bot_vars, tx_amount, BotCommand, BotSend, IPCMessageRespNakamoto,
+        BOT_SEND_DEFAULT_RETRIES, BOT_SEND_RETRY_BASE_MS,
+    };
+    use lib_chain::block::{ChainStatus, TxOutput};
+    use lib_chain::payload::SignedPayload;
+    use lib_chain::schema::{self, check_published_schema, schema_json};
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+
+    /// The clipboard text is sent base64 encoded inside the OSC 52 escape sequence.
+    #[test]
+    fn test_osc52_sequence() {
+        assert_eq!(osc52_sequence("abc"), "\x1b]52;c;YWJj\x07");
+    }
+
+    /// Malformed frames are reported and skipped instead of stopping the reader.
+    #[test]
+    fn test_read_ipc_response() {
+        let input = "\n[Miner] stray line\n\"MinerStarted\"\n\n{\"Notify\":\"ok\"}\n";
+        let mut reader = input.as_bytes();
+        let mut diagnostics = vec![];
+        let mut next =
+            || read_ipc_response::<IPCMessageRespNakamoto>(&mut reader, |e| diagnostics.push(e));
+        assert!(matches!(next(), Some(IPCMessageRespNakamoto::MinerStarted)));
+        assert!(matches!(next(), Some(IPCMessageRespNakamoto::Notify(msg)) if msg == "ok"));
+        assert!(next().is_none());
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].ends_with("[Miner] stray line"));
+
+        let oversized = format!("{}\n\"MinerStopped\"\n", "x".repeat(30));
+        let mut reader = oversized.as_bytes();
+        assert!(read_ipc_frame(&mut reader, 10).unwrap().is_err());
+        assert_eq!(
+            read_ipc_frame(&mut reader, 20).unwrap().unwrap(),
+            "\"MinerStopped\""
+        );
+    }
+
+    /// The amount of a tx is read from the message of its data_string.
+    #[test]
+    fn test_tx_amount() {
+        let data_string = |message: &str| serde_json::to_string(&("A", "B", message)).unwrap();
+        assert_eq!(
+            tx_amount(&data_string("SEND $300   // By Alice   // 1678250102871")),
+            Some(300)
+        );
+        assert_eq!(tx_amount(&data_string("SEND $42")), Some(42));
+        assert_eq!(tx_amount(&data_string("hello")), None);
+        assert_eq!(tx_amount("not json"), None);
+
+        let mut batch = SignedPayload::decode(&data_string("SEND $5")).unwrap();
+        batch.outputs = vec![TxOutput {
+            receiver: "C".to_string(),
+            amount: 7,
+        }];
+        assert_eq!(tx_amount(&batch.encode()), Some(12));
+    }
+
+    /// Bot variables are replaced by their values, while a `$` before an amount is kept.
+    #[test]
+    fn test_substitute_bot_vars() {
+        let vars = BTreeMap::from([
+            ("SELF".to_string(), "alice".to_string()),
+            ("PEER1".to_string(), "bob".to_string()),
+            ("BALANCE(self)".to_string(), "300".to_string()),
+        ]);
+        let substitute = |text: &str| substitute_bot_vars(text, &vars);
+        assert_eq!(substitute("$PEER1").unwrap(), "bob");
+        assert_eq!(
+            substitute("SEND $100   // By $SELF").unwrap(),
+            "SEND $100   // By alice"
+        );
+        assert_eq!(substitute("SEND $$BALANCE(self)").unwrap(), "SEND $300");
+        assert_eq!(substitute("costs $ 5$").unwrap(), "costs $ 5$");
+        assert!(substitute("$PEER2").is_err());
+        assert!(substitute("$BALANCE(self").is_err());
+    }
+
+    /// The new bot commands are read from their json lines, and their string arguments take the bot variables.
+    /// An invalid command is reported with the argument at fault, and the published schema of the commands is up to date.
+    #[test]
+    fn test_bot_command_parsing() {
+        let vars = BTreeMap::from([
+            ("PEER1".to_string(), "bob".to_string()),
+            ("SELF".to_string(), "alice".to_string()),
+        ]);
+        let parse = |line: &str| {
+            serde_json::from_str::<BotCommand>(line)
+                .unwrap()
+                .substitute(&vars)
+                .unwrap()
+        };
+        assert!(matches!(
+            parse(r#"{"SendEvery": [500, "$PEER1", "SEND $1   // By $SELF"]}"#),
+            BotCommand::SendEvery(500, receiver, message) if receiver == "bob" && message == "SEND $1   // By alice"
+        ));
+        assert!(matches!(
+            parse(r#"{"WaitForBalance": 300}"#),
+            BotCommand::WaitForBalance(300)
+        ));
+        assert!(matches!(
+            parse(r#"{"DumpState": "/tmp/$SELF.json"}"#),
+            BotCommand::DumpState(path) if path == "/tmp/alice.json"
+        ));
+        assert!(matches!(parse(r#""Quit""#), BotCommand::Quit));
+        assert_eq!(
+            schema::from_json_str::<BotCommand>("BotCommand", r#"{"SendEvery": [500, "bob", 1]}"#)
+                .err()
+                .unwrap(),
+            "Invalid BotCommand at `SendEvery[2]`: invalid type: integer `1`, expected a string"
+        );
+        check_published_schema("BotCommand", &schema_json::<BotCommand>()).unwrap();
+
+        let mut app = App::new(
+            "alice".to_string(),
+            "alice".to_string(),
+            "bob".to_string(),
+            String::new(),
+        );
+        app.user_balance = 300;
+        app.blocktree_status = Some(ChainStatus {
+            block_count: 3,
+            orphan_count: 0,
+            root_id: "0".to_string(),
+            working_block_id: "2".to_string(),
+            height: 2,
+            finalized_block_id: "0".to_string(),
+            finalized_height: 0,
+            pruned_blocks: 0,
+            pruned_bytes: 0,
+        });
+        app.miner_status =
+            serde_json::from_str(r#"{"is_running": true, "target_hashrate": null}"#).unwrap();
+        let state = app.state_json();
+        assert_eq!(state["user_balance"], 300);
+        assert_eq!(state["blocktree"]["height"], 2);
+        assert_eq!(state["miner"]["is_running"], true);
+        assert_eq!(
+            status_text(&app.miner_status),
+            "is_running: true\ntarget_hashrate: none"
+        );
+        assert_eq!(
+            status_value_text(&serde_json::json!([{"address": "127.0.0.1:9011", "score": 3}])),
+            "address: 127.0.0.1:9011, score: 3"
+        );
+    }
+
+    /// The deep link can be given before or after the bot commands path, and both are optional.
+    #[test]
+    fn test_split_optional_args() {
+        let split = |args: &[&str]| split_optional_args(args.iter().map(|arg| arg.to_string()));
+        let link = "nakamoto:send?to=B&amount=3";
+        assert_eq!(split(&[]), (None, None));
+        assert_eq!(split(&["bot.jsonl"]), (Some("bot.jsonl".to_string()), None));
+        assert_eq!(split(&[link]), (None, Some(link.to_string())));
+        assert_eq!(
+            split(&[link, "bot.jsonl"]),
+            (Some("bot.jsonl".to_string()), Some(link.to_string()))
+        );
+    }
+
+    /// The delay before a bot Send retry doubles after every retry.
+    #[test]
+    fn test_bot_send_backoff() {
+        let mut bot_send = BotSend {
+            receiver: "bob".to_string(),
+            message: "SEND $1".to_string(),
+            retries: 0,
+            max_retries: BOT_SEND_DEFAULT_RETRIES,
+        };
+        assert_eq!(
+            bot_send.backoff(),
+            Duration::from_millis(BOT_SEND_RETRY_BASE_MS)
+        );
+        bot_send.retries = 3;
+        assert_eq!(
+            bot_send.backoff(),
+            Duration::from_millis(BOT_SEND_RETRY_BASE_MS * 8)
+        );
+    }
+
+    /// Every theme can be selected by its name, and cycling goes through all of them.
+    #[test]
+    fn test_theme_names() {
+        let mut theme = Theme::Dark;
+        for _ in 0..Theme::ALL.len() {
+            assert_eq!(Theme::from_name(theme.name()), Some(theme));
+            theme = theme.next();
+        }
+        assert_eq!(theme, Theme::Dark);
+        assert_eq!(Theme::from_name("neon"), None);
+    }
+}
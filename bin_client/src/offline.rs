@@ -0,0 +1,70 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+//! This file implements the transaction files of the offline (cold-storage) signing flow. The client exports the
+//! transaction of its form unsigned (`:export-tx <path>` in the settings panel), an offline bin_wallet signs it
+//! (`bin_wallet sign-file <in.json> <out.json> <wallet.json>`), and the client imports the signed file and publishes it
+//! (`:import-tx <path>`), so the private key never has to be on the machine running the node.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+/// A transaction file exchanged with bin_wallet sign-file.
+/// It is the same as the `OfflineTx` struct type in the bin_wallet process.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OfflineTx {
+    pub data_string: String,
+    pub signature: Option<String>,
+}
+
+/// Write the data_string of a transaction to be signed offline to the given path.
+pub fn export_unsigned(path: &str, data_string: &str) -> Result<(), String> {
+    let offline_tx = OfflineTx {
+        data_string: data_string.to_string(),
+        signature: None,
+    };
+    fs::write(path, serde_json::to_string_pretty(&offline_tx).unwrap())
+        .map_err(|e| format!("Cannot write {}: {}", path, e))
+}
+
+/// Read a transaction signed offline from the given path, as its (data_string, signature).
+/// The signature is checked by bin_nakamoto when the transaction is published.
+pub fn import_signed(path: &str) -> Result<(String, String), String> {
+    let json = fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+    let offline_tx: OfflineTx =
+        serde_json::from_str(&json).map_err(|e| format!("Invalid {}: {}", path, e))?;
+    match offline_tx.signature {
+        Some(signature) => Ok((offline_tx.data_string, signature)),
+        None => Err(format!("{} is not signed yet", path)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{export_unsigned, import_signed, OfflineTx};
+
+    /// An exported file is not importable until it is signed.
+    #[test]
+    fn test_offline_tx_files() {
+        let path = std::env::temp_dir()
+            .join(format!("bin_client_offline_{}.json", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let data_string = r#"["A","B","SEND $1"]"#;
+        export_unsigned(&path, data_string).unwrap();
+        assert!(import_signed(&path).is_err());
+
+        let mut offline_tx: OfflineTx =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(offline_tx.data_string, data_string);
+        offline_tx.signature = Some("c2lnbmF0dXJl".to_string());
+        std::fs::write(&path, serde_json::to_string(&offline_tx).unwrap()).unwrap();
+        assert_eq!(
+            import_signed(&path).unwrap(),
+            (data_string.to_string(), "c2lnbmF0dXJl".to_string())
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert!(import_signed(&path).is_err());
+    }
+}
@@ -0,0 +1,156 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+//! This file implements the PaymentHook struct, an optional shell command (`payment_hook` in `Client.json`) run whenever
+//! a watched address receives a payment in a finalized block, so that a long-running node can alert the user outside the
+//! terminal, e.g. `{"payment_hook": {"command": "notify-send Nakamoto \"Received $NAKAMOTO_PAYMENT_AMOUNT\""}}`.
+//! The details of the payment are passed in environment variables, never in the command itself, because they come from
+//! the network: a message could otherwise inject shell commands.
+
+use lib_chain::block::BlockNode;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::process::{Child, Command, Stdio};
+
+/// A payment received by a watched address, as found in a finalized block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Payment {
+    pub sender: String,
+    pub receiver: String,
+    pub amount: i64,
+    pub message: String,
+    pub block_id: String,
+    pub height: u64,
+}
+
+/// The command run for every payment received by a watched address. Missing fields take their default value.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct PaymentHook {
+    /// the shell command (run with `sh -c`) for every payment, with the payment in the `NAKAMOTO_PAYMENT_*` environment
+    /// variables (SENDER, RECEIVER, AMOUNT, MESSAGE, BLOCK_ID, HEIGHT); no hook if empty
+    pub command: String,
+    /// the addresses whose incoming payments run the command; the address of the user if empty
+    pub watched_addresses: Vec<String>,
+}
+
+impl PaymentHook {
+    /// Whether there is a command to run.
+    pub fn is_enabled(&self) -> bool {
+        !self.command.trim().is_empty()
+    }
+
+    /// Find the payments received by the watched addresses (or by `user_id` if none are watched) in the given block,
+    /// finalized at the given height: the payments of its transactions as the chain counts them (see
+    /// `Transaction::payments`), the outputs of a batch transaction included. The transfers of an address to itself, and the payments of no money, are not payments.
+    pub fn payments_in_block(&self, block: &BlockNode, user_id: &str, height: u64) -> Vec<Payment> {
+        let is_watched = |address: &str| {
+            if self.watched_addresses.is_empty() {
+                address == user_id
+            } else {
+                self.watched_addresses
+                    .iter()
+                    .any(|watched| watched == address)
+            }
+        };
+        let mut payments = vec![];
+        for tx in block.transactions_block.transactions.iter() {
+            for (receiver, amount) in tx.payments() {
+                if amount <= 0 || *receiver == tx.sender || !is_watched(receiver) {
+                    continue;
+                }
+                payments.push(Payment {
+                    sender: tx.sender.clone(),
+                    receiver: receiver.clone(),
+                    amount,
+                    message: tx.message.clone(),
+                    block_id: block.header.block_id.clone(),
+                    height,
+                });
+            }
+        }
+        payments
+    }
+
+    /// Start the command for the given payment, without waiting for it. Its output is discarded, so that it does not
+    /// garble the UI.
+    pub fn spawn(&self, payment: &Payment) -> io::Result<Child> {
+        Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .env("NAKAMOTO_PAYMENT_SENDER", &payment.sender)
+            .env("NAKAMOTO_PAYMENT_RECEIVER", &payment.receiver)
+            .env("NAKAMOTO_PAYMENT_AMOUNT", payment.amount.to_string())
+            .env("NAKAMOTO_PAYMENT_MESSAGE", &payment.message)
+            .env("NAKAMOTO_PAYMENT_BLOCK_ID", &payment.block_id)
+            .env("NAKAMOTO_PAYMENT_HEIGHT", payment.height.to_string())
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Payment, PaymentHook};
+    use lib_chain::block::BlockNode;
+    use std::fs;
+
+    /// Only the payments to the watched addresses are found, and the command gets them in its environment.
+    #[test]
+    fn test_payment_hook() {
+        let mut block = BlockNode::genesis_block();
+        block.header.block_id = "00abc".to_string();
+        block.transactions_block.transactions = serde_json::from_value(serde_json::json!([
+            {"sender": "alice", "receiver": "bob", "message": "SEND $30   // By Alice   // 1", "sig": ""},
+            {"sender": "bob", "receiver": "bob", "message": "SEND $5   // By Bob   // 2", "sig": ""},
+            {"sender": "bob", "receiver": "carol", "message": "SEND $7   // $(reboot)", "sig": ""},
+            {"sender": "alice", "receiver": "bob", "message": "hello", "sig": ""},
+            {"sender": "dave", "receiver": "carol", "message": "SEND $1   // Batch", "sig": "",
+             "outputs": [{"receiver": "bob", "amount": 9}, {"receiver": "dave", "amount": 2}]}
+        ]))
+        .unwrap();
+        let mut hook = PaymentHook::default();
+        assert!(!hook.is_enabled());
+        let bob_payment = Payment {
+            sender: "alice".to_string(),
+            receiver: "bob".to_string(),
+            amount: 30,
+            message: "SEND $30   // By Alice   // 1".to_string(),
+            block_id: "00abc".to_string(),
+            height: 4,
+        };
+        // the output of a batch is a payment too
+        let batch_payment = Payment {
+            sender: "dave".to_string(),
+            amount: 9,
+            message: "SEND $1   // Batch".to_string(),
+            ..bob_payment.clone()
+        };
+        assert_eq!(
+            hook.payments_in_block(&block, "bob", 4),
+            vec![bob_payment, batch_payment]
+        );
+        hook.watched_addresses = vec!["carol".to_string()];
+        let payments = hook.payments_in_block(&block, "bob", 4);
+        assert_eq!(payments.len(), 2);
+        assert_eq!(payments[0].amount, 7);
+        assert_eq!(payments[1].amount, 1);
+
+        // The message is passed as is, not run
+        let path = std::env::temp_dir().join(format!("payment_hook_{}.txt", std::process::id()));
+        hook.command = format!(
+            "printf '%s|%s' \"$NAKAMOTO_PAYMENT_AMOUNT\" \"$NAKAMOTO_PAYMENT_MESSAGE\" > '{}'",
+            path.display()
+        );
+        assert!(hook.is_enabled());
+        assert!(hook.spawn(&payments[0]).unwrap().wait().unwrap().success());
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "7|SEND $7   // $(reboot)"
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
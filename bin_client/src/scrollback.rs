@@ -0,0 +1,153 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+//! This file implements the LogRing struct, the lines of a log panel of the UI. It keeps the last lines up to a
+//! capacity (`log_capacity` in `Client.json`), so a long-running client does not grow without bound, and it can be
+//! scrolled back page by page (PageUp/PageDown) and exported to a file (`:export-logs <path>` in the settings panel).
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Write};
+
+/// The number of lines kept by a log panel unless `log_capacity` is set in `Client.json`.
+pub const DEFAULT_LOG_CAPACITY: usize = 10000;
+
+/// The last lines of a log, oldest first, and how far back the panel is scrolled.
+pub struct LogRing {
+    /// the lines, oldest first
+    lines: VecDeque<String>,
+    /// the max number of lines kept; the oldest lines are dropped first
+    capacity: usize,
+    /// the number of newest lines hidden by scrolling back (0 shows the newest lines)
+    offset: usize,
+}
+
+impl LogRing {
+    /// An empty log keeping at most `capacity` lines (at least one).
+    pub fn new(capacity: usize) -> LogRing {
+        LogRing {
+            lines: VecDeque::new(),
+            capacity: capacity.max(1),
+            offset: 0,
+        }
+    }
+
+    /// Add a line. While scrolled back, the lines shown stay the same.
+    pub fn push(&mut self, line: String) {
+        if self.lines.len() == self.capacity {
+            self.lines.pop_front();
+        } else if self.offset > 0 {
+            self.offset += 1;
+        }
+        self.lines.push_back(line);
+    }
+
+    /// The number of lines kept.
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// The number of newest lines hidden by scrolling back.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Scroll back by `count` lines, keeping at least one line shown.
+    pub fn scroll_up(&mut self, count: usize) {
+        self.offset = (self.offset + count).min(self.lines.len().saturating_sub(1));
+    }
+
+    /// Scroll forward by `count` lines, back to the newest lines at most.
+    pub fn scroll_down(&mut self, count: usize) {
+        self.offset = self.offset.saturating_sub(count);
+    }
+
+    /// The `count` lines shown at the current scroll position, newest first (as the panels show them).
+    pub fn visible(&self, count: usize) -> Vec<String> {
+        self.lines
+            .iter()
+            .rev()
+            .skip(self.offset)
+            .take(count)
+            .cloned()
+            .collect()
+    }
+
+    /// Write all the lines kept, oldest first, one per line.
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        for line in self.lines.iter() {
+            writeln!(writer, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write the given logs to a file, each after a `== <title> ==` header. Return the number of lines written.
+pub fn export_logs(path: &str, logs: &[(&str, &LogRing)]) -> io::Result<usize> {
+    let mut file = File::create(path)?;
+    let mut line_count = 0;
+    for (title, log) in logs {
+        writeln!(file, "== {} ==", title)?;
+        log.write_to(&mut file)?;
+        line_count += log.len();
+    }
+    file.flush()?;
+    Ok(line_count)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{export_logs, LogRing};
+    use std::fs;
+
+    /// The oldest lines are dropped at capacity, and scrolling back keeps the same lines shown as new ones arrive.
+    #[test]
+    fn test_log_ring() {
+        let mut log = LogRing::new(5);
+        for i in 0..7 {
+            log.push(format!("line {}", i));
+        }
+        assert_eq!(log.len(), 5);
+        assert_eq!(log.visible(2), vec!["line 6", "line 5"]);
+
+        log.scroll_up(2);
+        assert_eq!(log.visible(2), vec!["line 4", "line 3"]);
+        log.scroll_up(100);
+        assert_eq!(log.offset(), 4);
+        assert_eq!(log.visible(2), vec!["line 2"]);
+        log.scroll_down(3);
+        assert_eq!(log.visible(1), vec!["line 5"]);
+
+        // Below capacity, a new line does not move the lines shown
+        let mut log = LogRing::new(10);
+        for i in 0..4 {
+            log.push(format!("line {}", i));
+        }
+        log.scroll_up(1);
+        log.push("line 4".to_string());
+        assert_eq!(log.visible(1), vec!["line 2"]);
+        log.scroll_down(100);
+        assert_eq!(log.visible(1), vec!["line 4"]);
+    }
+
+    /// The exported file holds every log after its header, oldest line first.
+    #[test]
+    fn test_export_logs() {
+        let mut notify = LogRing::new(10);
+        notify.push("a".to_string());
+        let mut stderr = LogRing::new(10);
+        stderr.push("b".to_string());
+        stderr.push("c".to_string());
+        let path = std::env::temp_dir().join(format!("export_logs_{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        let line_count =
+            export_logs(path, &[("Notify Log", &notify), ("STDERR Log", &stderr)]).unwrap();
+        assert_eq!(line_count, 3);
+        assert_eq!(
+            fs::read_to_string(path).unwrap(),
+            "== Notify Log ==\na\n== STDERR Log ==\nb\nc\n"
+        );
+        fs::remove_file(path).unwrap();
+    }
+}
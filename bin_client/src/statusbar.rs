@@ -0,0 +1,176 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+//! This file implements the StatusBar struct, the one line at the top of the UI showing the critical state at a glance:
+//! the wallet, the health of the connection to bin_nakamoto and its neighbors, the sync progress, the miner and the
+//! pending transactions. It is updated by the events pushed by bin_nakamoto (see `SubscribeEvents`) and by the status
+//! responses, and drawn whatever panel is shown.
+
+use serde_json::Value;
+use std::collections::BTreeSet;
+
+/// The time without any response from bin_nakamoto after which the node is shown as unresponsive.
+/// The status is polled at least every 2s, so a healthy node always answers well within it.
+pub const NODE_STALE_MS: u64 = 5000;
+
+/// The state of bin_wallet. The wallet has no lock of its own: it holds the key once initialized (with the auth token
+/// of this client) and signs until it exits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalletState {
+    /// The wallet is not initialized yet
+    Locked,
+    /// The wallet is initialized and signs the requests of this client
+    Unlocked,
+    /// The wallet process has exited
+    Closed,
+}
+
+/// The state shown in the status bar.
+pub struct StatusBar {
+    /// the state of bin_wallet
+    pub wallet: WalletState,
+    /// whether bin_nakamoto has exited (its stdout is closed)
+    pub node_closed: bool,
+    /// when the last response or event was received from bin_nakamoto (ms since the UNIX epoch)
+    pub last_node_response_ms: Option<u64>,
+    /// the neighbors of the node (as "ip:port") that completed the handshake
+    pub peers: BTreeSet<String>,
+    /// the height of the working block (the end of the longest chain), once known
+    pub working_height: Option<u64>,
+    /// the height of the last block finalized, once known
+    pub finalized_height: Option<u64>,
+    /// the number of blocks received whose parent is missing, i.e. still to be synced
+    pub orphan_count: u64,
+    /// whether the miner mines new blocks, once known
+    pub mining: Option<bool>,
+}
+
+impl Default for StatusBar {
+    fn default() -> Self {
+        StatusBar {
+            wallet: WalletState::Locked,
+            node_closed: false,
+            last_node_response_ms: None,
+            peers: BTreeSet::new(),
+            working_height: None,
+            finalized_height: None,
+            orphan_count: 0,
+            mining: None,
+        }
+    }
+}
+
+impl StatusBar {
+    /// Record that bin_nakamoto answered (or pushed an event) at `now_ms`.
+    pub fn on_node_response(&mut self, now_ms: u64) {
+        self.last_node_response_ms = Some(now_ms);
+    }
+
+    /// Replace the known neighbors with the ones of a network status (`peers` as a list of `{"address": "ip:port", ...}`).
+    pub fn set_peers_from_status(&mut self, peers_status: &Value) {
+        self.peers = peers_status
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|peer| peer["address"].as_str())
+            .map(|address| address.to_string())
+            .collect();
+    }
+
+    /// Whether the node is unreachable, unresponsive or alone, so that the bar is drawn as a warning.
+    pub fn is_degraded(&self, now_ms: u64) -> bool {
+        self.node_health(now_ms) != "ok" || self.wallet != WalletState::Unlocked
+    }
+
+    /// The health of the connection to bin_nakamoto.
+    fn node_health(&self, now_ms: u64) -> &'static str {
+        match self.last_node_response_ms {
+            _ if self.node_closed => "closed",
+            None => "connecting",
+            Some(last_ms) if now_ms.saturating_sub(last_ms) > NODE_STALE_MS => "unresponsive",
+            Some(_) if self.peers.is_empty() => "no peers",
+            Some(_) => "ok",
+        }
+    }
+
+    /// The line of the status bar, with the number of transactions of the user not in a block yet.
+    pub fn line(&self, pending_tx_count: usize, now_ms: u64) -> String {
+        let wallet = match self.wallet {
+            WalletState::Locked => "locked",
+            WalletState::Unlocked => "unlocked",
+            WalletState::Closed => "closed",
+        };
+        let sync = match (self.working_height, self.finalized_height) {
+            (None, _) => "unknown".to_string(),
+            (Some(working), finalized) => {
+                let mut sync = format!("height {}", working);
+                if let Some(finalized) = finalized {
+                    sync = format!("{} (finalized {})", sync, finalized);
+                }
+                if self.orphan_count > 0 {
+                    sync = format!("{}, fetching {} orphans", sync, self.orphan_count);
+                }
+                sync
+            }
+        };
+        let mining = match self.mining {
+            Some(true) => "on",
+            Some(false) => "off",
+            None => "unknown",
+        };
+        format!(
+            "Wallet: {} | Node: {} ({} peers) | Sync: {} | Miner: {} | Pending tx: {}",
+            wallet,
+            self.node_health(now_ms),
+            self.peers.len(),
+            sync,
+            mining,
+            pending_tx_count
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{StatusBar, WalletState, NODE_STALE_MS};
+    use serde_json::json;
+
+    /// The line follows the events and status responses, and the node turns unresponsive without responses.
+    #[test]
+    fn test_status_bar_line() {
+        let mut status_bar = StatusBar::default();
+        assert_eq!(
+            status_bar.line(0, 0),
+            "Wallet: locked | Node: connecting (0 peers) | Sync: unknown | Miner: unknown | Pending tx: 0"
+        );
+        assert!(status_bar.is_degraded(0));
+
+        status_bar.wallet = WalletState::Unlocked;
+        status_bar.on_node_response(1000);
+        status_bar.set_peers_from_status(&json!([
+            {"address": "127.0.0.1:9011", "node_id": "1a2b", "score": 3},
+            {"address": "127.0.0.1:9012", "node_id": "3c4d", "score": 0},
+        ]));
+        status_bar.working_height = Some(12);
+        status_bar.finalized_height = Some(6);
+        status_bar.orphan_count = 2;
+        status_bar.mining = Some(true);
+        assert_eq!(
+            status_bar.line(3, 1500),
+            "Wallet: unlocked | Node: ok (2 peers) | Sync: height 12 (finalized 6), fetching 2 orphans | Miner: on | Pending tx: 3"
+        );
+        assert!(!status_bar.is_degraded(1500));
+        assert!(status_bar
+            .line(3, 1000 + NODE_STALE_MS + 1)
+            .contains("Node: unresponsive"));
+        assert!(status_bar.is_degraded(1000 + NODE_STALE_MS + 1));
+
+        status_bar.set_peers_from_status(&json!([]));
+        assert!(status_bar
+            .line(0, 1500)
+            .contains("Node: no peers (0 peers)"));
+        status_bar.node_closed = true;
+        assert!(status_bar.line(0, 1500).contains("Node: closed"));
+    }
+}
@@ -0,0 +1,156 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+//! This file implements the StderrForwarder struct, which turns the stderr lines of a child process (bin_nakamoto or
+//! bin_wallet) into lines of the STDERR log panel, tagged with the name of the child. Each child has its own reader
+//! thread, and a chatty child must neither flood the panel nor keep the UI waiting on the app lock, so:
+//! - a line longer than `MAX_STDERR_LINE_LEN` is cut,
+//! - a line repeated right after itself is counted instead of shown again,
+//! - at most `stderr_lines_per_sec` lines (in `Client.json`) of a child are shown per second; the others are counted and
+//!   reported in one line.
+//!
+//! The log file of the child still gets every line in full.
+
+/// The max length (in chars) of a line shown in the STDERR log panel.
+pub const MAX_STDERR_LINE_LEN: usize = 512;
+
+/// The number of lines of a child shown per second unless `stderr_lines_per_sec` is set in `Client.json`.
+pub const DEFAULT_STDERR_LINES_PER_SEC: usize = 50;
+
+/// The state of the forwarding of the stderr lines of one child process.
+pub struct StderrForwarder {
+    /// the name of the child, with which the lines are tagged
+    source: String,
+    /// the max number of lines shown per second
+    max_lines_per_sec: usize,
+    /// the start of the current one-second window (in ms)
+    window_start_ms: u64,
+    /// the number of lines shown in the current window
+    window_lines: usize,
+    /// the number of lines not shown because of the rate limit, not reported yet
+    dropped: usize,
+    /// the last line read, to count its repeats
+    last_line: Option<String>,
+    /// the number of times the last line was repeated, not reported yet
+    repeats: usize,
+}
+
+impl StderrForwarder {
+    /// A forwarder for the child of the given name, showing at most `max_lines_per_sec` lines per second (at least one).
+    pub fn new(source: &str, max_lines_per_sec: usize) -> StderrForwarder {
+        StderrForwarder {
+            source: source.to_string(),
+            max_lines_per_sec: max_lines_per_sec.max(1),
+            window_start_ms: 0,
+            window_lines: 0,
+            dropped: 0,
+            last_line: None,
+            repeats: 0,
+        }
+    }
+
+    /// Take a line read from the child at the given time (in ms, from any start), and return the lines to show for it:
+    /// none if it repeats the previous line or is over the rate limit, otherwise the line with the reports pending before it.
+    pub fn forward(&mut self, line: &str, now_ms: u64) -> Vec<String> {
+        if self.last_line.as_deref() == Some(line) {
+            self.repeats += 1;
+            return vec![];
+        }
+        self.last_line = Some(line.to_string());
+        let mut lines: Vec<String> = self.report_repeats().into_iter().collect();
+        if now_ms.saturating_sub(self.window_start_ms) >= 1000 {
+            self.window_start_ms = now_ms;
+            self.window_lines = 0;
+            lines.extend(self.report_dropped());
+        }
+        if self.window_lines >= self.max_lines_per_sec {
+            self.dropped += 1;
+            return lines;
+        }
+        self.window_lines += 1;
+        lines.push(self.tagged(&truncate_line(line)));
+        lines
+    }
+
+    /// Return the reports of the repeats and dropped lines not reported yet, e.g. when the child closes its stderr.
+    pub fn flush(&mut self) -> Vec<String> {
+        self.report_repeats()
+            .into_iter()
+            .chain(self.report_dropped())
+            .collect()
+    }
+
+    /// The report of the repeats of the last line, if any.
+    fn report_repeats(&mut self) -> Option<String> {
+        let repeats = std::mem::take(&mut self.repeats);
+        (repeats > 0).then(|| self.tagged(&format!("(last line repeated {} times)", repeats)))
+    }
+
+    /// The report of the lines dropped by the rate limit, if any.
+    fn report_dropped(&mut self) -> Option<String> {
+        let dropped = std::mem::take(&mut self.dropped);
+        (dropped > 0).then(|| {
+            self.tagged(&format!(
+                "({} lines dropped, over {} lines/s)",
+                dropped, self.max_lines_per_sec
+            ))
+        })
+    }
+
+    /// The line tagged with the name of the child.
+    fn tagged(&self, line: &str) -> String {
+        format!("[{}] {}", self.source, line)
+    }
+}
+
+/// Cut the line to `MAX_STDERR_LINE_LEN` chars, marking the cut.
+fn truncate_line(line: &str) -> String {
+    match line.char_indices().nth(MAX_STDERR_LINE_LEN) {
+        Some((end, _)) => format!("{}... ({} bytes)", &line[..end], line.len()),
+        None => line.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{StderrForwarder, MAX_STDERR_LINE_LEN};
+
+    /// Repeated lines are counted, lines over the rate limit are dropped and reported, and long lines are cut.
+    #[test]
+    fn test_stderr_forwarder() {
+        let mut forwarder = StderrForwarder::new("Nakamoto", 2);
+        assert_eq!(forwarder.forward("a", 0), vec!["[Nakamoto] a"]);
+        assert!(forwarder.forward("a", 10).is_empty());
+        assert!(forwarder.forward("a", 20).is_empty());
+        assert_eq!(
+            forwarder.forward("b", 30),
+            vec!["[Nakamoto] (last line repeated 2 times)", "[Nakamoto] b"]
+        );
+        // over 2 lines in the same second
+        assert!(forwarder.forward("c", 40).is_empty());
+        assert!(forwarder.forward("d", 50).is_empty());
+        assert_eq!(
+            forwarder.forward("e", 1000),
+            vec![
+                "[Nakamoto] (2 lines dropped, over 2 lines/s)",
+                "[Nakamoto] e"
+            ]
+        );
+        assert!(forwarder.forward("e", 1010).is_empty());
+        assert_eq!(
+            forwarder.flush(),
+            vec!["[Nakamoto] (last line repeated 1 times)"]
+        );
+        assert!(forwarder.flush().is_empty());
+
+        let long_line = "é".repeat(MAX_STDERR_LINE_LEN + 1);
+        let shown = forwarder.forward(&long_line, 1020);
+        assert_eq!(shown.len(), 1);
+        assert!(shown[0].ends_with(&format!("... ({} bytes)", long_line.len())));
+        assert_eq!(
+            shown[0].chars().filter(|c| *c == 'é').count(),
+            MAX_STDERR_LINE_LEN
+        );
+    }
+}
@@ -0,0 +1,143 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+//! This file implements the ClientConfig struct, the optional settings of the client itself, read from `Client.json`
+//! in the nakamoto config folder (so each user of a multi-node setup has its own): the template of the transactions
+//! (the message the transaction form is reset to, and whether messages get a timestamp), the size of the log panels, the
+//! rate of the stderr lines of the child processes shown in them and the command run on incoming payments.
+
+use crate::payment_hook::PaymentHook;
+use crate::scrollback::DEFAULT_LOG_CAPACITY;
+use crate::stderr_forward::DEFAULT_STDERR_LINES_PER_SEC;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// The name of the client config file in the nakamoto config folder.
+pub const CLIENT_CONFIG_FILE: &str = "Client.json";
+
+/// The settings of the client. Missing fields take their default value, and so does a missing file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ClientConfig {
+    /// the template of the transactions created by the user
+    pub tx_template: TxTemplate,
+    /// the number of lines kept by each log panel for scrolling back; the oldest lines are dropped first
+    pub log_capacity: usize,
+    /// the max number of stderr lines of bin_nakamoto (and of bin_wallet) shown per second in the STDERR log panel
+    /// (see `StderrForwarder`); their log files get every line
+    pub stderr_lines_per_sec: usize,
+    /// the command run when a watched address receives a payment in a finalized block (see `PaymentHook`)
+    pub payment_hook: PaymentHook,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            tx_template: TxTemplate::default(),
+            log_capacity: DEFAULT_LOG_CAPACITY,
+            stderr_lines_per_sec: DEFAULT_STDERR_LINES_PER_SEC,
+            payment_hook: PaymentHook::default(),
+        }
+    }
+}
+
+impl ClientConfig {
+    /// Read the client config from the given nakamoto config folder.
+    /// Return the default config if there is no config file, and an error if the file is not valid.
+    pub fn load(config_dir: &Path) -> Result<ClientConfig, String> {
+        let path = config_dir.join(CLIENT_CONFIG_FILE);
+        if !path.exists() {
+            return Ok(ClientConfig::default());
+        }
+        let json = fs::read_to_string(&path)
+            .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        serde_json::from_str(&json).map_err(|e| format!("Invalid {}: {}", path.display(), e))
+    }
+}
+
+/// The template of the transactions created by the user, e.g. `SEND $100   // By Alice`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct TxTemplate {
+    /// the amount of the default message
+    pub default_amount: i64,
+    /// the comment of the default message, where `{name}` is replaced by the user name; no comment if empty
+    pub comment_format: String,
+    /// whether the creation time (ms) is appended to the messages. Without it, sending the same message twice
+    /// gives the same transaction, which is rejected as a duplicate.
+    pub embed_timestamp: bool,
+}
+
+impl Default for TxTemplate {
+    fn default() -> Self {
+        TxTemplate {
+            default_amount: 100,
+            comment_format: "By {name}".to_string(),
+            embed_timestamp: true,
+        }
+    }
+}
+
+impl TxTemplate {
+    /// The message the transaction form is reset to.
+    pub fn default_message(&self, user_name: &str) -> String {
+        let send = format!("SEND ${}", self.default_amount);
+        if self.comment_format.is_empty() {
+            send
+        } else {
+            format!(
+                "{}   // {}",
+                send,
+                self.comment_format.replace("{name}", user_name)
+            )
+        }
+    }
+
+    /// The message as it is put in a transaction created at `now_ms`.
+    pub fn finish_message(&self, message: String, now_ms: u64) -> String {
+        if self.embed_timestamp {
+            format!("{}   // {}", message, now_ms)
+        } else {
+            message
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ClientConfig, TxTemplate};
+
+    /// The default template gives the original message, and a partial config keeps the other defaults.
+    #[test]
+    fn test_tx_template() {
+        let template = TxTemplate::default();
+        assert_eq!(template.default_message("Alice"), "SEND $100   // By Alice");
+        assert_eq!(
+            template.finish_message("SEND $1".to_string(), 42),
+            "SEND $1   // 42"
+        );
+
+        let config: ClientConfig = serde_json::from_str(
+            r#"{"tx_template": {"default_amount": 5, "comment_format": "", "embed_timestamp": false}}"#,
+        )
+        .unwrap();
+        assert_eq!(config.tx_template.default_message("Alice"), "SEND $5");
+        assert_eq!(
+            config.tx_template.finish_message("SEND $5".to_string(), 42),
+            "SEND $5"
+        );
+        let config: ClientConfig =
+            serde_json::from_str(r#"{"tx_template": {"comment_format": "from {name} with love"}}"#)
+                .unwrap();
+        assert_eq!(
+            config.tx_template.default_message("Bob"),
+            "SEND $100   // from Bob with love"
+        );
+        assert_eq!(
+            serde_json::from_str::<ClientConfig>("{}").unwrap(),
+            ClientConfig::default()
+        );
+    }
+}
@@ -0,0 +1,169 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+//! This file implements the TxTraceLog struct, which collects the trace events of the lifecycle of the transactions
+//! (see `lib_chain::txtrace`): the Submitted span of this client, and the spans the wallet and bin_nakamoto write on their
+//! stderr. The `:trace` command of the `Settings` panel shows the timeline of a transaction from them.
+
+use std::collections::VecDeque;
+
+use lib_chain::block::{Signature, TxId};
+use lib_chain::txtrace::{format_timeline, tx_id_of, TxSpan, TxTraceEvent};
+
+/// The number of most recent trace events kept.
+pub const MAX_TRACE_EVENTS: usize = 10_000;
+
+/// The number of transactions submitted but not signed yet that are kept (the others were refused or lost).
+const MAX_SUBMITTED: usize = 1000;
+
+/// The trace events of the recent transactions, of all the processes.
+#[derive(Default)]
+pub struct TxTraceLog {
+    /// the transactions submitted by this client and not signed yet, with their submit time (ms), oldest first
+    submitted: VecDeque<(String, u64)>,
+    /// the trace events, most recent last
+    events: VecDeque<TxTraceEvent>,
+    /// the last transaction signed, the default of `timeline`
+    last_tx_id: Option<TxId>,
+}
+
+impl TxTraceLog {
+    /// Record that a transaction was submitted by the form or a bot command at the given time. Its id is only known once
+    /// it is signed (see `record_signed`).
+    pub fn record_submitted(&mut self, data_string: String, now_ms: u64) {
+        if self.submitted.len() == MAX_SUBMITTED {
+            self.submitted.pop_front();
+        }
+        self.submitted.push_back((data_string, now_ms));
+    }
+
+    /// Record that a submitted transaction was signed, and return its Submitted span, if it was submitted by this client.
+    pub fn record_signed(
+        &mut self,
+        data_string: &str,
+        signature: &Signature,
+    ) -> Option<TxTraceEvent> {
+        let index = self
+            .submitted
+            .iter()
+            .position(|(submitted, _)| submitted == data_string)?;
+        let (_, submitted_ms) = self.submitted.remove(index)?;
+        let tx_id = tx_id_of(data_string, signature)?;
+        let event = TxTraceEvent::new(
+            tx_id,
+            TxSpan::Submitted,
+            "client",
+            submitted_ms,
+            String::new(),
+        );
+        self.record(event.clone());
+        Some(event)
+    }
+
+    /// Record a trace event, e.g. from the stderr of a child.
+    pub fn record(&mut self, event: TxTraceEvent) {
+        if event.span <= TxSpan::Signed {
+            self.last_tx_id = Some(event.tx_id.clone());
+        }
+        if self.events.len() == MAX_TRACE_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    /// The timeline of the transaction whose id starts with the given prefix, or of the last transaction signed if there
+    /// is no prefix. An error if no transaction, or more than one, matches.
+    pub fn timeline(&self, prefix: Option<&str>) -> Result<Vec<String>, String> {
+        let tx_id = match prefix {
+            None => self
+                .last_tx_id
+                .clone()
+                .ok_or_else(|| "No transaction traced yet".to_string())?,
+            Some(prefix) => {
+                let mut tx_ids: Vec<&TxId> = self
+                    .events
+                    .iter()
+                    .map(|event| &event.tx_id)
+                    .filter(|tx_id| tx_id.starts_with(prefix))
+                    .collect();
+                tx_ids.sort();
+                tx_ids.dedup();
+                match tx_ids.as_slice() {
+                    [tx_id] => tx_id.to_string(),
+                    [] => {
+                        return Err(format!(
+                            "No transaction traced with an id starting with {}",
+                            prefix
+                        ))
+                    }
+                    _ => {
+                        return Err(format!(
+                            "{} transactions traced match {}, give a longer prefix",
+                            tx_ids.len(),
+                            prefix
+                        ))
+                    }
+                }
+            }
+        };
+        let events: Vec<TxTraceEvent> = self.events.iter().cloned().collect();
+        Ok(format_timeline(&tx_id, &events))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TxTraceLog;
+    use lib_chain::txtrace::{TxSpan, TxTraceEvent};
+
+    const DATA_STRING: &str = "[\"alice\",\"bob\",\"SEND $10   // hi\"]";
+
+    /// The Submitted span is only known for the transactions submitted by this client, once they are signed; the timeline
+    /// is found by a prefix of the tx id, the last transaction by default.
+    #[test]
+    fn test_tx_trace_log() {
+        let mut log = TxTraceLog::default();
+        assert!(log.timeline(None).is_err());
+        assert!(log.record_signed(DATA_STRING, &"sig".to_string()).is_none());
+
+        log.record_submitted(DATA_STRING.to_string(), 1000);
+        let submitted = log.record_signed(DATA_STRING, &"sig".to_string()).unwrap();
+        assert_eq!((submitted.span, submitted.at_ms), (TxSpan::Submitted, 1000));
+        let tx_id = submitted.tx_id.clone();
+        log.record(TxTraceEvent::new(
+            tx_id.clone(),
+            TxSpan::Signed,
+            "wallet",
+            1040,
+            String::new(),
+        ));
+        log.record(TxTraceEvent::new(
+            tx_id.clone(),
+            TxSpan::PoolAdmitted,
+            "nakamoto",
+            1100,
+            String::new(),
+        ));
+
+        let timeline = log.timeline(None).unwrap();
+        assert_eq!(timeline[0], format!("Timeline of tx {}:", tx_id));
+        assert_eq!(timeline[1], "  Submitted by client at 1000 (start)");
+        assert_eq!(timeline[2], "  Signed by wallet at 1040 (+40 ms)");
+        assert_eq!(timeline[4], "  not seen yet: Included, Finalized");
+        assert_eq!(log.timeline(Some(&tx_id[..6])).unwrap(), timeline);
+
+        log.record(TxTraceEvent::new(
+            format!("{}x", &tx_id[..6]),
+            TxSpan::Signed,
+            "wallet",
+            2000,
+            String::new(),
+        ));
+        assert!(log
+            .timeline(Some(&tx_id[..6]))
+            .unwrap_err()
+            .contains("2 transactions"));
+        assert!(log.timeline(Some("unknown")).is_err());
+    }
+}
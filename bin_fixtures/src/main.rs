@@ -0,0 +1,453 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// This file implements bin_fixtures, a dev tool that generates reproducible test fixtures:
+// wallets, a chain of mined blocks with signed transactions between those wallets, a tx pool holding a few more
+// pending transactions, and a node config. The same parameters always produce the same files, byte for byte,
+// so a new feature can get its own fixture by re-running the tool instead of hand-editing json files.
+//
+// Usage: bin_fixtures <out_dir> [--blocks N] [--wallets K] [--seed S] [--difficulty D]
+// It writes:
+// - `<out_dir>/_secrets/Wallet.fixture.<Name>.json` for each wallet (usable as the wallet_config_path of bin_client)
+// - `<out_dir>/nakamoto_config_fixture/{BlockTree,TxPool,Config}.json` (usable as the nakamoto_config_path of bin_client)
+
+use base64ct::{Base64, Encoding, LineEnding};
+use lib_chain::block::{
+    BlockNode, BlockNodeHeader, BlockTree, MerkleTree, Puzzle, Transaction, Transactions,
+};
+use lib_miner::miner::Miner;
+use lib_tx_pool::pool::TxPool;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rsa::pkcs1::{DecodeRsaPrivateKey, EncodeRsaPrivateKey, EncodeRsaPublicKey};
+use rsa::pkcs1v15::SigningKey;
+use rsa::signature::Signer;
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use sha2::Sha256;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+
+/// The names given to the generated wallets, in order. Wallets beyond this list are named `User<i>`.
+const WALLET_NAMES: [&str; 8] = [
+    "Carol", "Dave", "Erin", "Frank", "Grace", "Heidi", "Ivan", "Judy",
+];
+
+/// The key size of the generated wallets. Addresses derived from 384-bit keys are what lib_chain expects.
+const WALLET_KEY_BITS: usize = 384;
+
+/// The timestamp of the first generated block (in ms). Block `i` is mined one second after block `i - 1`.
+const FIXTURE_EPOCH_MS: u64 = 1678250000000;
+
+/// The length of the nonces of the generated blocks.
+const NONCE_LEN: u16 = 16;
+
+/// The max number of transactions in one generated block.
+const MAX_TX_PER_BLOCK: usize = 3;
+
+/// The number of pending transactions put in the generated tx pool.
+const POOL_TX_COUNT: usize = 4;
+
+/// The parameters of the generated fixtures.
+#[derive(Debug, Clone, PartialEq)]
+struct FixtureParams {
+    /// the number of blocks mined on top of the genesis block
+    block_count: u64,
+    /// the number of wallets
+    wallet_count: usize,
+    /// the seed of every random choice (keys, transactions, nonces)
+    seed: u64,
+    /// the difficulty the blocks are mined at, also used as both difficulties of the generated config
+    leading_zero_len: u16,
+}
+
+impl Default for FixtureParams {
+    fn default() -> Self {
+        FixtureParams {
+            block_count: 10,
+            wallet_count: 4,
+            seed: 3235,
+            leading_zero_len: 3,
+        }
+    }
+}
+
+/// A generated wallet, serialized in the format read by bin_wallet.
+struct FixtureWallet {
+    user_name: String,
+    priv_key_pem: String,
+    pub_key_pem: String,
+}
+
+impl FixtureWallet {
+    /// Generate a wallet whose keys are drawn from the given rng.
+    fn generate(user_name: String, rng: &mut StdRng) -> FixtureWallet {
+        let private_key = RsaPrivateKey::new(rng, WALLET_KEY_BITS).unwrap();
+        let public_key = RsaPublicKey::from(&private_key);
+        FixtureWallet {
+            user_name,
+            priv_key_pem: private_key
+                .to_pkcs1_pem(LineEnding::LF)
+                .unwrap()
+                .to_string(),
+            pub_key_pem: public_key.to_pkcs1_pem(LineEnding::LF).unwrap(),
+        }
+    }
+
+    /// The user id of the wallet: its public key without the PEM header and footer, as bin_wallet computes it.
+    fn user_id(&self) -> String {
+        self.pub_key_pem
+            .lines()
+            .filter(|line| !line.starts_with("-----"))
+            .map(|line| line.trim())
+            .collect()
+    }
+
+    /// Create a transaction from this wallet, signed the way bin_wallet signs the data_string of bin_client.
+    fn signed_tx(&self, receiver: String, message: String) -> Transaction {
+        let data_string = serde_json::to_string(&(self.user_id(), &receiver, &message)).unwrap();
+        let private_key = RsaPrivateKey::from_pkcs1_pem(&self.priv_key_pem).unwrap();
+        let signature = SigningKey::<Sha256>::new(private_key).sign(data_string.as_bytes());
+        Transaction::new(
+            self.user_id(),
+            receiver,
+            message,
+            Base64::encode_string(signature.as_ref()),
+        )
+    }
+
+    /// The wallet json file, as read by bin_wallet (the spending policy is left to its default).
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "user_name": self.user_name,
+            "priv_key_pem": self.priv_key_pem,
+            "pub_key_pem": self.pub_key_pem,
+        })
+    }
+}
+
+/// The generated fixtures.
+struct Fixtures {
+    wallets: Vec<FixtureWallet>,
+    chain: BlockTree,
+    tx_pool: TxPool,
+    config: serde_json::Value,
+}
+
+/// Draw up to `max_count` transactions between the wallets, each spending at most what its sender has left
+/// in `balances` (which is updated), with a message in the format of bin_client.
+fn random_txs(
+    wallets: &[FixtureWallet],
+    balances: &mut [i64],
+    max_count: usize,
+    timestamp: u64,
+    rng: &mut StdRng,
+) -> Vec<Transaction> {
+    let mut txs = vec![];
+    for _ in 0..rng.gen_range(0..=max_count) {
+        let senders: Vec<usize> = (0..wallets.len()).filter(|i| balances[*i] > 0).collect();
+        if senders.is_empty() || wallets.len() < 2 {
+            break;
+        }
+        let sender = senders[rng.gen_range(0..senders.len())];
+        let receiver = (sender + rng.gen_range(1..wallets.len())) % wallets.len();
+        let amount = rng.gen_range(1..=balances[sender].min(10));
+        balances[sender] -= amount;
+        balances[receiver] += amount;
+        let message = format!(
+            "SEND ${}   // By {}   // {}",
+            amount, wallets[sender].user_name, timestamp
+        );
+        txs.push(wallets[sender].signed_tx(wallets[receiver].user_id(), message));
+    }
+    txs
+}
+
+/// Mine a block with the given transactions on top of the working block of the chain and add it to the chain.
+fn mine_block(
+    chain: &mut BlockTree,
+    txs: Vec<Transaction>,
+    reward_receiver: String,
+    timestamp: u64,
+    params: &FixtureParams,
+) {
+    let parent = chain.working_block_id.clone();
+    let (merkle_root, merkle_tree) = MerkleTree::create_merkle_tree(txs.clone());
+    let puzzle = serde_json::to_string(&Puzzle {
+        parent: parent.clone(),
+        merkle_root: merkle_root.clone(),
+        reward_receiver: reward_receiver.clone(),
+        miner_pub_key: None,
+        timestamp: None,
+    })
+    .unwrap();
+    // A single miner thread seeded by the height keeps the nonces reproducible
+    let solution = Miner::solve_puzzle(
+        Arc::new(Mutex::new(Miner::new())),
+        puzzle,
+        NONCE_LEN,
+        params.leading_zero_len,
+        1,
+        params.seed + chain.block_depth[&parent] + 1,
+        Arc::new(RwLock::new(false)),
+    )
+    .unwrap();
+    let block = BlockNode {
+        header: BlockNodeHeader {
+            parent,
+            merkle_root,
+            timestamp,
+            block_id: solution.hash,
+            nonce: solution.nonce,
+            reward_receiver,
+            miner_signature: None,
+        },
+        transactions_block: Transactions {
+            transactions: txs,
+            merkle_tree,
+        },
+    };
+    chain
+        .add_block(block, params.leading_zero_len)
+        .expect("A generated block is rejected");
+}
+
+/// Generate the fixtures for the given parameters.
+fn generate(params: &FixtureParams) -> Fixtures {
+    let mut rng = StdRng::seed_from_u64(params.seed);
+    let wallets: Vec<FixtureWallet> = (0..params.wallet_count)
+        .map(|i| {
+            let user_name = WALLET_NAMES
+                .get(i)
+                .map(|name| name.to_string())
+                .unwrap_or_else(|| format!("User{}", i));
+            FixtureWallet::generate(user_name, &mut rng)
+        })
+        .collect();
+
+    // The wallets take turns receiving the mining rewards, and spend them in the following blocks
+    let mut chain = BlockTree::new();
+    for height in 1..=params.block_count {
+        let timestamp = FIXTURE_EPOCH_MS + height * 1000;
+        let working_balances = chain.get_working_balance_map();
+        let mut balances: Vec<i64> = wallets
+            .iter()
+            .map(|wallet| {
+                working_balances
+                    .get(&wallet.user_id())
+                    .copied()
+                    .unwrap_or(0)
+            })
+            .collect();
+        let txs = random_txs(
+            &wallets,
+            &mut balances,
+            MAX_TX_PER_BLOCK,
+            timestamp,
+            &mut rng,
+        );
+        let reward_receiver = wallets[(height as usize - 1) % wallets.len()].user_id();
+        mine_block(&mut chain, txs, reward_receiver, timestamp, params);
+    }
+
+    // The pool holds transactions sent after the last block, not included in any block yet
+    let mut tx_pool = TxPool::new();
+    let working_balances = chain.get_working_balance_map();
+    let mut balances: Vec<i64> = wallets
+        .iter()
+        .map(|wallet| {
+            working_balances
+                .get(&wallet.user_id())
+                .copied()
+                .unwrap_or(0)
+        })
+        .collect();
+    let timestamp = FIXTURE_EPOCH_MS + (params.block_count + 1) * 1000;
+    for tx in random_txs(&wallets, &mut balances, POOL_TX_COUNT, timestamp, &mut rng) {
+        tx_pool.add_tx(tx);
+    }
+    tx_pool.last_finalized_block_id = chain.finalized_block_id.clone();
+
+    let config = serde_json::json!({
+        "neighbors": [],
+        "addr": {"ip": "127.0.0.1", "port": 6000},
+        "miner_thread_count": 2,
+        "nonce_len": NONCE_LEN,
+        "difficulty_leading_zero_len": params.leading_zero_len,
+        "difficulty_leading_zero_len_acc": params.leading_zero_len,
+        "miner_thread_0_seed": params.seed,
+        "mining_reward_receiver": wallets[0].user_id(),
+        "max_tx_in_one_block": 16,
+    });
+    Fixtures {
+        wallets,
+        chain,
+        tx_pool,
+        config,
+    }
+}
+
+/// Serialize a state with sorted object keys and sorted sets, so that the same state always gives the same bytes.
+/// `set_keys` are the fields holding a HashSet, whose serialization order is otherwise random.
+fn to_canonical_json<T: serde::Serialize>(state: &T, set_keys: &[&str]) -> String {
+    let mut value = serde_json::to_value(state).unwrap();
+    if let Some(object) = value.as_object_mut() {
+        for key in set_keys {
+            if let Some(serde_json::Value::Array(set)) = object.get_mut(*key) {
+                set.sort_by_key(|item| item.to_string());
+            }
+        }
+    }
+    serde_json::to_string_pretty(&value).unwrap()
+}
+
+/// Write the fixtures under the given directory (see the usage at the top of this file).
+fn write_fixtures(fixtures: &Fixtures, out_dir: &Path) -> std::io::Result<()> {
+    let secrets_dir = out_dir.join("_secrets");
+    let config_dir = out_dir.join("nakamoto_config_fixture");
+    fs::create_dir_all(&secrets_dir)?;
+    fs::create_dir_all(&config_dir)?;
+    for wallet in fixtures.wallets.iter() {
+        fs::write(
+            secrets_dir.join(format!("Wallet.fixture.{}.json", wallet.user_name)),
+            serde_json::to_string_pretty(&wallet.to_json()).unwrap(),
+        )?;
+    }
+    fs::write(
+        config_dir.join("BlockTree.json"),
+        to_canonical_json(&fixtures.chain, &["finalized_tx_ids"]),
+    )?;
+    fs::write(
+        config_dir.join("TxPool.json"),
+        to_canonical_json(&fixtures.tx_pool, &["removed_tx_ids"]),
+    )?;
+    fs::write(
+        config_dir.join("Config.json"),
+        serde_json::to_string_pretty(&fixtures.config).unwrap(),
+    )?;
+    Ok(())
+}
+
+/// Parse the command line arguments (without the program name) into the output directory and the parameters.
+fn parse_args(args: &[String]) -> Result<(String, FixtureParams), String> {
+    let mut params = FixtureParams::default();
+    let mut out_dir = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| -> Result<u64, String> {
+            args.next()
+                .and_then(|value| value.parse::<u64>().ok())
+                .ok_or_else(|| format!("{} expects a number", name))
+        };
+        match arg.as_str() {
+            "--blocks" => params.block_count = value(arg)?,
+            "--wallets" => params.wallet_count = value(arg)? as usize,
+            "--seed" => params.seed = value(arg)?,
+            "--difficulty" => params.leading_zero_len = value(arg)? as u16,
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ if out_dir.is_none() => out_dir = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument {}", arg)),
+        }
+    }
+    if params.wallet_count == 0 {
+        return Err("--wallets must be at least 1".to_string());
+    }
+    let out_dir = out_dir.ok_or_else(|| "Missing the output directory".to_string())?;
+    Ok((out_dir, params))
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (out_dir, params) = match parse_args(&args) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!(
+                "Usage: bin_fixtures <out_dir> [--blocks N] [--wallets K] [--seed S] [--difficulty D]"
+            );
+            std::process::exit(2);
+        }
+    };
+    let fixtures = generate(&params);
+    if let Err(e) = write_fixtures(&fixtures, Path::new(&out_dir)) {
+        eprintln!("Cannot write the fixtures to {}: {}", out_dir, e);
+        std::process::exit(1);
+    }
+    eprintln!(
+        "Generated {} wallets, {} blocks and {} pending transactions in {} ({:?})",
+        fixtures.wallets.len(),
+        params.block_count,
+        fixtures.tx_pool.pool_tx_ids.len(),
+        out_dir,
+        params
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{generate, parse_args, to_canonical_json, FixtureParams};
+    use lib_chain::block::BlockTree;
+    use lib_tx_pool::pool::TxPool;
+
+    fn small_params(seed: u64) -> FixtureParams {
+        FixtureParams {
+            block_count: 8,
+            wallet_count: 3,
+            seed,
+            leading_zero_len: 1,
+        }
+    }
+
+    /// The same parameters give the same files, and the generated chain is valid and carries transactions.
+    #[test]
+    fn test_generate_deterministic() {
+        let fixtures = generate(&small_params(7));
+        let again = generate(&small_params(7));
+        let chain_json = to_canonical_json(&fixtures.chain, &["finalized_tx_ids"]);
+        assert_eq!(
+            chain_json,
+            to_canonical_json(&again.chain, &["finalized_tx_ids"])
+        );
+        assert_eq!(
+            to_canonical_json(&fixtures.tx_pool, &["removed_tx_ids"]),
+            to_canonical_json(&again.tx_pool, &["removed_tx_ids"])
+        );
+        assert_ne!(
+            chain_json,
+            to_canonical_json(&generate(&small_params(8)).chain, &["finalized_tx_ids"])
+        );
+
+        let chain = BlockTree::from_json(&chain_json).unwrap();
+        assert_eq!(chain.height_of(&chain.working_block_id), Some(8));
+        assert!(chain.check_invariants().is_ok());
+        assert!(chain.audit_supply().is_ok());
+        let txs: Vec<_> = chain
+            .iter_main_chain()
+            .skip(1)
+            .flat_map(|block| block.transactions_block.transactions.iter())
+            .collect();
+        assert!(!txs.is_empty());
+        assert!(txs.iter().all(|tx| tx.verify_sig().is_ok()));
+        let tx_pool =
+            TxPool::from_json(&to_canonical_json(&fixtures.tx_pool, &["removed_tx_ids"])).unwrap();
+        assert!(tx_pool
+            .pool_tx_map
+            .values()
+            .all(|tx| tx.verify_sig().is_ok()));
+    }
+
+    /// The options override the defaults, and the output directory is required.
+    #[test]
+    fn test_parse_args() {
+        let args = |args: &[&str]| -> Vec<String> { args.iter().map(|a| a.to_string()).collect() };
+        let (out_dir, params) =
+            parse_args(&args(&["tests", "--blocks", "3", "--seed", "1"])).unwrap();
+        assert_eq!(out_dir, "tests");
+        assert_eq!((params.block_count, params.seed), (3, 1));
+        assert_eq!(params.wallet_count, FixtureParams::default().wallet_count);
+        assert!(parse_args(&args(&["--blocks", "3"])).is_err());
+        assert!(parse_args(&args(&["tests", "--blocks", "x"])).is_err());
+        assert!(parse_args(&args(&["tests", "--wallets", "0"])).is_err());
+    }
+}
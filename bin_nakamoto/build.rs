@@ -0,0 +1,16 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// Generate the gRPC server and client code from proto/nakamoto.proto (only with the `grpc` feature).
+// protoc comes from protoc-bin-vendored, so that building does not require it to be installed.
+fn main() {
+    #[cfg(feature = "grpc")]
+    {
+        std::env::set_var(
+            "PROTOC",
+            protoc_bin_vendored::protoc_bin_path().expect("No vendored protoc for this platform"),
+        );
+        tonic_build::compile_protos("proto/nakamoto.proto").expect("Cannot compile the protos");
+    }
+}
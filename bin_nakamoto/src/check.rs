@@ -0,0 +1,308 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// This file implements the `--check` mode of bin_nakamoto.
+// It checks a config dir (BlockTree.json, TxPool.json and Config.json, as passed to Initialize by bin_client) and
+// optionally a seccomp policy before a node is started with them, so that a broken file is reported with what to fix
+// instead of a panic deep in `Nakamoto::create_nakamoto`.
+
+use crate::nakamoto::Config;
+use lib_chain::block::BlockTree;
+use lib_chain::schema;
+use lib_network::netchannel::NetAddress;
+use lib_tx_pool::pool::TxPool;
+use seccompiler::BpfMap;
+use std::fmt;
+use std::fs;
+use std::net::ToSocketAddrs;
+use std::path::Path;
+
+/// The outcome of one check.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CheckOutcome {
+    /// The check passed, with what was checked
+    Ok(String),
+    /// The check failed, with the problem and how to fix it
+    Failed(String),
+    /// The check was not run, because a file it needs is broken
+    Skipped(String),
+}
+
+/// One check of a config dir, e.g. the parsing of Config.json.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckResult {
+    /// what is checked
+    pub name: String,
+    /// the outcome of the check
+    pub outcome: CheckOutcome,
+}
+
+impl CheckResult {
+    fn new(name: &str, outcome: Result<String, String>) -> CheckResult {
+        CheckResult {
+            name: name.to_string(),
+            outcome: outcome.map_or_else(CheckOutcome::Failed, CheckOutcome::Ok),
+        }
+    }
+
+    fn skipped(name: &str, reason: &str) -> CheckResult {
+        CheckResult {
+            name: name.to_string(),
+            outcome: CheckOutcome::Skipped(reason.to_string()),
+        }
+    }
+
+    /// Whether the check failed.
+    pub fn failed(&self) -> bool {
+        matches!(self.outcome, CheckOutcome::Failed(_))
+    }
+}
+
+impl fmt::Display for CheckResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.outcome {
+            CheckOutcome::Ok(detail) => write!(f, "[ok] {}: {}", self.name, detail),
+            CheckOutcome::Failed(problem) => write!(f, "[FAILED] {}: {}", self.name, problem),
+            CheckOutcome::Skipped(reason) => write!(f, "[skipped] {}: {}", self.name, reason),
+        }
+    }
+}
+
+/// Check the three files of the given config dir, and the seccomp policy if given. Every check is reported, in order;
+/// the checks that need a file that failed to parse are skipped.
+pub fn check_config_dir(config_dir: &Path, policy_path: Option<&Path>) -> Vec<CheckResult> {
+    let read = |file_name: &str| {
+        let path = config_dir.join(file_name);
+        fs::read_to_string(&path).map_err(|e| {
+            format!(
+                "Cannot read {}: {} (the config dir must hold BlockTree.json, TxPool.json and Config.json)",
+                path.display(),
+                e
+            )
+        })
+    };
+    let config = read("Config.json").and_then(|json| parse_config(&json));
+    let chain = read("BlockTree.json").and_then(|json| parse_chain(&json));
+    let tx_pool = read("TxPool.json").and_then(|json| {
+        TxPool::from_json(&json).map_err(|e| format!("TxPool.json is not a valid tx pool: {}", e))
+    });
+
+    let mut results = vec![
+        CheckResult::new(
+            "Config.json",
+            config
+                .as_ref()
+                .map(|_| "valid config".to_string())
+                .map_err(String::clone),
+        ),
+        CheckResult::new(
+            "BlockTree.json",
+            chain
+                .as_ref()
+                .map(|chain| {
+                    format!(
+                        "{} blocks, working block {}",
+                        chain.all_blocks.len(),
+                        chain.working_block_id
+                    )
+                })
+                .map_err(String::clone),
+        ),
+        CheckResult::new(
+            "TxPool.json",
+            tx_pool
+                .as_ref()
+                .map(|tx_pool| format!("{} pending transactions", tx_pool.pool_tx_map.len()))
+                .map_err(String::clone),
+        ),
+    ];
+    match (config, chain) {
+        (Ok(config), Ok(chain)) => {
+            results.push(CheckResult::new("genesis", check_genesis(&config, chain)));
+            results.push(CheckResult::new("addresses", check_addresses(&config)));
+            results.push(CheckResult::new("paths", check_paths(&config)));
+        }
+        (Ok(config), Err(_)) => {
+            results.push(CheckResult::skipped("genesis", "BlockTree.json is broken"));
+            results.push(CheckResult::new("addresses", check_addresses(&config)));
+            results.push(CheckResult::new("paths", check_paths(&config)));
+        }
+        (Err(_), _) => {
+            for name in ["genesis", "addresses", "paths"] {
+                results.push(CheckResult::skipped(name, "Config.json is broken"));
+            }
+        }
+    }
+    match policy_path {
+        Some(policy_path) => results.push(CheckResult::new(
+            "seccomp policy",
+            check_policy(policy_path),
+        )),
+        None => results.push(CheckResult::skipped(
+            "seccomp policy",
+            "no policy path given",
+        )),
+    }
+    results
+}
+
+/// Parse Config.json, rejecting the fields the node does not know (they would be ignored silently, e.g. a misspelled
+/// field), and the difficulties under which the node would reject its own blocks.
+fn parse_config(json: &str) -> Result<Config, String> {
+    let config: Config = schema::from_json_str("Config.json", json)?;
+    let known = serde_json::to_value(&config).unwrap();
+    let value: serde_json::Value = serde_json::from_str(json).unwrap();
+    let unknown: Vec<&String> = value
+        .as_object()
+        .map(|fields| {
+            fields
+                .keys()
+                .filter(|key| known.get(key.as_str()).is_none())
+                .collect()
+        })
+        .unwrap_or_default();
+    if !unknown.is_empty() {
+        return Err(format!(
+            "Config.json has unknown fields {:?}, which the node ignores: fix their names or remove them",
+            unknown
+        ));
+    }
+    if config.difficulty_leading_zero_len < config.difficulty_leading_zero_len_acc {
+        return Err(format!(
+            "difficulty_leading_zero_len ({}) is lower than difficulty_leading_zero_len_acc ({}), so the node rejects \
+             the blocks it mines: raise difficulty_leading_zero_len",
+            config.difficulty_leading_zero_len, config.difficulty_leading_zero_len_acc
+        ));
+    }
+    Ok(config)
+}
+
+/// Parse BlockTree.json and check the invariants of the chain.
+fn parse_chain(json: &str) -> Result<BlockTree, String> {
+    let chain = BlockTree::from_json(json)
+        .map_err(|e| format!("BlockTree.json is not a valid block tree: {}", e))?;
+    chain.check_invariants().map_err(|violations| {
+        format!("BlockTree.json is inconsistent: {}", violations.join("; "))
+    })?;
+    Ok(chain)
+}
+
+/// Check that the chain was built for the consensus params and the checkpoints of the config, as the node does when
+/// it starts.
+fn check_genesis(config: &Config, mut chain: BlockTree) -> Result<String, String> {
+    chain
+        .set_checkpoints(config.checkpoints.iter().cloned().collect())
+        .map_err(|e| {
+            format!(
+                "The chain contradicts the checkpoints of Config.json: {}; fix `checkpoints` or the chain",
+                e
+            )
+        })?;
+    chain.set_consensus_params(config.consensus.clone()).map_err(|e| {
+        format!(
+            "The chain contradicts the consensus params of Config.json: {}; fix `consensus` or regenerate the chain \
+             (e.g. with bin_fixtures)",
+            e
+        )
+    })?;
+    Ok(format!(
+        "the chain matches the consensus params ({} hash, genesis allocation {})",
+        config.consensus.hash_function, config.consensus.genesis_allocation
+    ))
+}
+
+/// Check that the address of the node, its neighbors and its listeners resolve, and that no neighbor is the node itself.
+fn check_addresses(config: &Config) -> Result<String, String> {
+    let mut addresses: Vec<(&str, &NetAddress)> = vec![("addr", &config.addr)];
+    addresses.extend(
+        config
+            .neighbors
+            .iter()
+            .map(|neighbor| ("neighbors", neighbor)),
+    );
+    for (name, address) in [
+        ("ipc_listen_addr", &config.ipc_listen_addr),
+        ("ws_listen_addr", &config.ws_listen_addr),
+        ("grpc_listen_addr", &config.grpc_listen_addr),
+    ] {
+        addresses.extend(address.iter().map(|address| (name, address)));
+    }
+    for (name, address) in addresses.iter() {
+        let resolves = u16::try_from(address.port)
+            .ok()
+            .filter(|port| *port != 0)
+            .and_then(|port| (address.ip.as_str(), port).to_socket_addrs().ok())
+            .is_some_and(|mut addrs| addrs.next().is_some());
+        if !resolves {
+            return Err(format!(
+                "{} {}:{} is not a valid address: use an ip (or host name) and a port between 1 and 65535",
+                name, address.ip, address.port
+            ));
+        }
+    }
+    if let Some(address) = config
+        .ipc_listen_addr
+        .as_ref()
+        .filter(|address| !config.ipc_listen_public && !crate::is_loopback(address))
+    {
+        return Err(format!(
+            "ipc_listen_addr {}:{} is not a loopback address: the IPC socket is not authenticated, set \
+             `ipc_listen_public` to serve it anyway",
+            address.ip, address.port
+        ));
+    }
+    if config.neighbors.contains(&config.addr) {
+        return Err(format!(
+            "the node is its own neighbor ({}:{}): remove it from `neighbors`",
+            config.addr.ip, config.addr.port
+        ));
+    }
+    Ok(format!("{} addresses", addresses.len()))
+}
+
+/// Check that the files the node opens or creates at startup are in existing directories.
+fn check_paths(config: &Config) -> Result<String, String> {
+    let paths = [
+        ("block_store_path", &config.block_store_path),
+        ("tx_pool_log_path", &config.tx_pool_log_path),
+        ("node_key_path", &config.node_key_path),
+        ("peers_path", &config.peers_path),
+    ];
+    let mut checked = 0;
+    for (name, path) in paths.iter() {
+        let Some(path) = path else {
+            continue;
+        };
+        let dir = Path::new(path)
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        if !dir.is_dir() {
+            return Err(format!(
+                "the directory of {} ({}) does not exist: create it or fix the path",
+                name, path
+            ));
+        }
+        checked += 1;
+    }
+    Ok(format!("{} paths", checked))
+}
+
+/// Check that the seccomp policy compiles for this architecture and has the filter applied by the node.
+fn check_policy(policy_path: &Path) -> Result<String, String> {
+    let json = fs::read_to_string(policy_path)
+        .map_err(|e| format!("Cannot read {}: {}", policy_path.display(), e))?;
+    let arch = std::env::consts::ARCH
+        .try_into()
+        .map_err(|e| format!("seccomp is not supported on this architecture: {:?}", e))?;
+    let filter_map: BpfMap = seccompiler::compile_from_json(json.as_bytes(), arch)
+        .map_err(|e| format!("{} does not compile: {}", policy_path.display(), e))?;
+    if !filter_map.contains_key("main_thread") {
+        return Err(format!(
+            "{} has no `main_thread` filter, which is the one the node applies",
+            policy_path.display()
+        ));
+    }
+    Ok(format!("{} compiles", policy_path.display()))
+}
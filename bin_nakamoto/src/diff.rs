@@ -0,0 +1,165 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// This file implements the `--diff` mode of bin_nakamoto.
+// It compares two serialized states (two BlockTree.json or two TxPool.json files, e.g. dumped by two nodes at the end of a run)
+// and reports where they diverge, to help debugging why two nodes ended up with different chains.
+
+use lib_chain::block::{BlockNode, BlockTree};
+use lib_tx_pool::pool::TxPool;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+
+/// Compare the two serialized states in the given files and return the report, one line per difference.
+/// Both files must hold the same kind of state (BlockTree or TxPool).
+pub fn diff_state_files(path_a: &str, path_b: &str) -> Result<Vec<String>, String> {
+    let read =
+        |path: &str| fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path, e));
+    let (json_a, json_b) = (read(path_a)?, read(path_b)?);
+    if let (Ok(chain_a), Ok(chain_b)) =
+        (BlockTree::from_json(&json_a), BlockTree::from_json(&json_b))
+    {
+        return Ok(diff_chains(&chain_a, &chain_b));
+    }
+    if let (Ok(pool_a), Ok(pool_b)) = (TxPool::from_json(&json_a), TxPool::from_json(&json_b)) {
+        return Ok(diff_tx_pools(&pool_a, &pool_b));
+    }
+    Err(format!(
+        "{} and {} are not two BlockTree states or two TxPool states",
+        path_a, path_b
+    ))
+}
+
+/// Compare two block trees: their longest chains (up to the first differing block), finalized blocks and balances.
+pub fn diff_chains(chain_a: &BlockTree, chain_b: &BlockTree) -> Vec<String> {
+    let mut report = vec![];
+    let main_a: Vec<&BlockNode> = chain_a.iter_main_chain().collect();
+    let main_b: Vec<&BlockNode> = chain_b.iter_main_chain().collect();
+    let common_len = main_a
+        .iter()
+        .zip(main_b.iter())
+        .take_while(|(a, b)| a.header.block_id == b.header.block_id)
+        .count();
+
+    if common_len == main_a.len() && common_len == main_b.len() {
+        report.push(format!(
+            "Same longest chain: {} blocks ending at {}",
+            common_len, chain_a.working_block_id
+        ));
+    } else {
+        match common_len {
+            0 => report.push("The longest chains have no block in common".to_string()),
+            _ => report.push(format!(
+                "The longest chains share {} blocks, the last common block is {}",
+                common_len,
+                main_a[common_len - 1].header.block_id
+            )),
+        }
+        for (name, main_chain) in [("A", &main_a), ("B", &main_b)] {
+            match main_chain.get(common_len) {
+                Some(block) => report.push(format!(
+                    "{} continues with {} blocks from {} to {}",
+                    name,
+                    main_chain.len() - common_len,
+                    block.header.block_id,
+                    main_chain.last().unwrap().header.block_id
+                )),
+                None => report.push(format!("{} has no block after it", name)),
+            }
+        }
+    }
+
+    if chain_a.finalized_block_id != chain_b.finalized_block_id {
+        report.push(format!(
+            "Finalized blocks differ: A {} (depth {:?}), B {} (depth {:?})",
+            chain_a.finalized_block_id,
+            chain_a.height_of(&chain_a.finalized_block_id),
+            chain_b.finalized_block_id,
+            chain_b.height_of(&chain_b.finalized_block_id)
+        ));
+    }
+    let only_a = chain_a
+        .all_blocks
+        .keys()
+        .filter(|id| !chain_b.all_blocks.contains_key(*id))
+        .count();
+    let only_b = chain_b
+        .all_blocks
+        .keys()
+        .filter(|id| !chain_a.all_blocks.contains_key(*id))
+        .count();
+    if only_a > 0 || only_b > 0 {
+        report.push(format!(
+            "Blocks known only to A: {}, only to B: {}",
+            only_a, only_b
+        ));
+    }
+
+    report.extend(diff_balances(
+        "Finalized",
+        &chain_a.finalized_balance_map,
+        &chain_b.finalized_balance_map,
+    ));
+    report.extend(diff_balances(
+        "Working",
+        &chain_a.get_working_balance_map(),
+        &chain_b.get_working_balance_map(),
+    ));
+    report
+}
+
+/// Report the addresses whose balances differ between the two maps. A missing address has a balance of 0.
+fn diff_balances(
+    branch: &str,
+    balances_a: &HashMap<String, i64>,
+    balances_b: &HashMap<String, i64>,
+) -> Vec<String> {
+    let user_ids: BTreeSet<&String> = balances_a.keys().chain(balances_b.keys()).collect();
+    user_ids
+        .into_iter()
+        .filter_map(|user_id| {
+            let balance_a = balances_a.get(user_id).copied().unwrap_or(0);
+            let balance_b = balances_b.get(user_id).copied().unwrap_or(0);
+            (balance_a != balance_b).then(|| {
+                format!(
+                    "{} balance of {} differs: A {}, B {}",
+                    branch, user_id, balance_a, balance_b
+                )
+            })
+        })
+        .collect()
+}
+
+/// Compare two tx pools: the transactions only one of them holds.
+pub fn diff_tx_pools(pool_a: &TxPool, pool_b: &TxPool) -> Vec<String> {
+    let mut report = vec![];
+    let only_in = |pool: &TxPool, other: &TxPool| -> BTreeMap<String, String> {
+        pool.pool_tx_map
+            .iter()
+            .filter(|(tx_id, _)| !other.pool_tx_map.contains_key(*tx_id))
+            .map(|(tx_id, tx)| (tx_id.clone(), tx.message.clone()))
+            .collect()
+    };
+    for (name, only) in [
+        ("A", only_in(pool_a, pool_b)),
+        ("B", only_in(pool_b, pool_a)),
+    ] {
+        for (tx_id, message) in only {
+            report.push(format!("Tx only in {}: {} ({})", name, tx_id, message));
+        }
+    }
+    if pool_a.last_finalized_block_id != pool_b.last_finalized_block_id {
+        report.push(format!(
+            "Last finalized blocks differ: A {}, B {}",
+            pool_a.last_finalized_block_id, pool_b.last_finalized_block_id
+        ));
+    }
+    if report.is_empty() {
+        report.push(format!(
+            "Same tx pool: {} transactions",
+            pool_a.pool_tx_map.len()
+        ));
+    }
+    report
+}
@@ -0,0 +1,348 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// This file implements the gRPC server of bin_nakamoto (see `proto/nakamoto.proto`), built with the `grpc` feature.
+// Every call goes through `handle_remote_request`, like the json IPC socket, so both interfaces answer the same way.
+// The calls that control the node (StartMiner, StopMiner, Call and the config status) go through `handle_admin_request`
+// instead, and only for a client sending the `grpc_admin_token` of the config; they are refused if there is none.
+// The server runs on its own tokio runtime; the node itself stays thread-based, so the calls are answered on blocking
+// threads and the events are bridged from the subscription channels of the node.
+
+use crate::nakamoto::{BalanceBranch, ChainEvent, Nakamoto};
+use crate::{handle_remote_request, handle_request, IPCMessageReq, IPCMessageResp};
+use lib_network::netchannel::NetAddress;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::thread;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::Stream;
+use tonic::{Request, Response, Status};
+
+pub mod proto {
+    tonic::include_proto!("nakamoto");
+}
+
+use proto::node_server::{Node, NodeServer};
+use proto::{
+    block_request, BalanceReply, BalanceRequest, BlockReply, BlockRequest, CallReply, CallRequest,
+    ConfirmedTx, Empty, FinalizedBlock, PublishTxReply, PublishTxRequest, StatusKind, StatusReply,
+    StatusRequest,
+};
+
+/// The number of events buffered for a subscriber that reads them slower than they happen.
+const SUBSCRIPTION_BUFFER: usize = 64;
+
+/// The gRPC service, answering the calls with the given node.
+pub struct NodeService {
+    nakamoto: Nakamoto,
+    /// the token of the admin calls (see `grpc_admin_token` in the config), which are refused if there is none
+    admin_token: Option<String>,
+}
+
+impl NodeService {
+    /// Handle one IPC request on a blocking thread, so that waiting for the locks of the node does not stall the runtime.
+    async fn handle(&self, req: IPCMessageReq) -> Result<IPCMessageResp, Status> {
+        let nakamoto = self.nakamoto.clone();
+        tokio::task::spawn_blocking(move || handle_remote_request(&nakamoto, Ok(req)))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))
+    }
+
+    /// Handle one IPC request of an admin call on a blocking thread, if the call carries the admin token.
+    async fn handle_admin<T>(
+        &self,
+        request: &Request<T>,
+        req: Result<IPCMessageReq, String>,
+    ) -> Result<IPCMessageResp, Status> {
+        match &self.admin_token {
+            None => {
+                return Err(Status::permission_denied(
+                    "The node has no grpc_admin_token: admin calls are only accepted on stdin",
+                ))
+            }
+            Some(admin_token) if !carries_token(request, admin_token) => {
+                return Err(Status::unauthenticated("Missing or wrong admin token"))
+            }
+            Some(_) => {}
+        }
+        let nakamoto = self.nakamoto.clone();
+        tokio::task::spawn_blocking(move || handle_admin_request(&nakamoto, req))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))
+    }
+
+    /// Stream the events of the node picked by `pick`, until the client goes away.
+    fn subscribe<T: Send + 'static>(
+        &self,
+        pick: fn(ChainEvent) -> Option<T>,
+    ) -> ReceiverStream<Result<T, Status>> {
+        let events = self.nakamoto.subscribe_events();
+        let (sender, receiver) = mpsc::channel(SUBSCRIPTION_BUFFER);
+        // The subscription of the node is dropped with this thread, once the client is gone
+        thread::spawn(move || {
+            for item in events.into_iter().filter_map(pick) {
+                if sender.blocking_send(Ok(item)).is_err() {
+                    break;
+                }
+            }
+        });
+        ReceiverStream::new(receiver)
+    }
+}
+
+/// Whether the call carries the given token as `authorization: Bearer <token>`.
+/// The comparison runs in constant time, so that the token cannot be guessed byte by byte from the latency.
+fn carries_token<T>(request: &Request<T>, token: &str) -> bool {
+    let expected = format!("Bearer {}", token);
+    let provided = request
+        .metadata()
+        .get("authorization")
+        .map(|value| value.as_bytes())
+        .unwrap_or_default();
+    expected.len() == provided.len()
+        && expected
+            .as_bytes()
+            .iter()
+            .zip(provided)
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// Handle one request from a remote client authenticated as an admin (see `grpc_admin_token`), which may also control
+/// and reconfigure the node. Initialize and Quit are still only accepted on stdin, so that no remote client can replace
+/// or stop the node, and so is SubscribeEvents, because the events are pushed on stdout.
+fn handle_admin_request(nakamoto: &Nakamoto, req: Result<IPCMessageReq, String>) -> IPCMessageResp {
+    match req {
+        Ok(IPCMessageReq::Initialize(..)) | Ok(IPCMessageReq::Quit) => {
+            IPCMessageResp::Notify("Initialize and Quit are only accepted on stdin".to_string())
+        }
+        Ok(IPCMessageReq::SubscribeEvents) => {
+            IPCMessageResp::Notify("Events are only pushed on stdout".to_string())
+        }
+        Ok(req) => handle_request(&mut Some(nakamoto.clone()), req),
+        Err(e) => IPCMessageResp::Notify(e),
+    }
+}
+
+/// The error for a response that does not answer the request (a Notify explaining why, or another response).
+fn unexpected(resp: IPCMessageResp) -> Status {
+    match resp {
+        IPCMessageResp::Notify(msg) => Status::invalid_argument(msg),
+        resp => Status::internal(format!("Unexpected response {:?}", resp)),
+    }
+}
+
+/// The fields of a status as the strings of the `map<string, string>` of a `StatusReply`: the strings as they are, and
+/// the other values (numbers, booleans, lists, etc.) as json.
+fn string_map(status: &impl Serialize) -> HashMap<String, String> {
+    match serde_json::to_value(status).unwrap() {
+        serde_json::Value::Object(fields) => fields
+            .into_iter()
+            .map(|(key, value)| match value {
+                serde_json::Value::String(value) => (key, value),
+                value => (key, value.to_string()),
+            })
+            .collect(),
+        _ => HashMap::new(),
+    }
+}
+
+type EventStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl Node for NodeService {
+    async fn get_balance(
+        &self,
+        request: Request<BalanceRequest>,
+    ) -> Result<Response<BalanceReply>, Status> {
+        let request = request.into_inner();
+        let branch = match request.working_tip {
+            true => BalanceBranch::WorkingTip,
+            false => BalanceBranch::Finalized,
+        };
+        match self
+            .handle(IPCMessageReq::GetAddressBalance(request.user_id, branch))
+            .await?
+        {
+            IPCMessageResp::AddressBalance(_, _, balance) => {
+                Ok(Response::new(BalanceReply { balance }))
+            }
+            resp => Err(unexpected(resp)),
+        }
+    }
+
+    async fn publish_tx(
+        &self,
+        request: Request<PublishTxRequest>,
+    ) -> Result<Response<PublishTxReply>, Status> {
+        let request = request.into_inner();
+        match self
+            .handle(IPCMessageReq::PublishTx(
+                request.data_string,
+                request.signature,
+            ))
+            .await?
+        {
+            IPCMessageResp::PublishTxDone => Ok(Response::new(PublishTxReply {
+                accepted: true,
+                reason: String::new(),
+            })),
+            IPCMessageResp::PublishTxRejected(_, reason) => Ok(Response::new(PublishTxReply {
+                accepted: false,
+                reason: reason.to_string(),
+            })),
+            resp => Err(unexpected(resp)),
+        }
+    }
+
+    async fn get_block(
+        &self,
+        request: Request<BlockRequest>,
+    ) -> Result<Response<BlockReply>, Status> {
+        let req = match request.into_inner().block {
+            Some(block_request::Block::BlockId(block_id)) => IPCMessageReq::RequestBlock(block_id),
+            Some(block_request::Block::Height(height)) => {
+                IPCMessageReq::RequestBlockAtHeight(height)
+            }
+            None => {
+                return Err(Status::invalid_argument(
+                    "Either block_id or height is required",
+                ))
+            }
+        };
+        match self.handle(req).await? {
+            IPCMessageResp::BlockData(block_json)
+            | IPCMessageResp::BlockAtHeight(_, block_json) => {
+                Ok(Response::new(BlockReply { block_json }))
+            }
+            IPCMessageResp::Notify(msg) => Err(Status::not_found(msg)),
+            resp => Err(unexpected(resp)),
+        }
+    }
+
+    async fn get_status(
+        &self,
+        request: Request<StatusRequest>,
+    ) -> Result<Response<StatusReply>, Status> {
+        let resp = match request.get_ref().kind() {
+            StatusKind::Chain => self.handle(IPCMessageReq::RequestChainStatus).await?,
+            StatusKind::Net => self.handle(IPCMessageReq::RequestNetStatus).await?,
+            StatusKind::Miner => self.handle(IPCMessageReq::RequestMinerStatus).await?,
+            StatusKind::TxPool => self.handle(IPCMessageReq::RequestTxPoolStatus).await?,
+            StatusKind::Config => {
+                self.handle_admin(&request, Ok(IPCMessageReq::RequestConfig))
+                    .await?
+            }
+        };
+        let status = match resp {
+            IPCMessageResp::ChainStatus(status) => string_map(&status),
+            IPCMessageResp::NetStatus(status) => string_map(&status),
+            IPCMessageResp::MinerStatus(status) => string_map(&status),
+            IPCMessageResp::TxPoolStatus(status) => string_map(&status),
+            IPCMessageResp::ConfigStatus(status) => string_map(&status),
+            resp => return Err(unexpected(resp)),
+        };
+        Ok(Response::new(StatusReply { status }))
+    }
+
+    async fn start_miner(&self, request: Request<Empty>) -> Result<Response<Empty>, Status> {
+        match self
+            .handle_admin(&request, Ok(IPCMessageReq::StartMiner))
+            .await?
+        {
+            IPCMessageResp::MinerStarted => Ok(Response::new(Empty {})),
+            resp => Err(unexpected(resp)),
+        }
+    }
+
+    async fn stop_miner(&self, request: Request<Empty>) -> Result<Response<Empty>, Status> {
+        match self
+            .handle_admin(&request, Ok(IPCMessageReq::StopMiner))
+            .await?
+        {
+            IPCMessageResp::MinerStopped => Ok(Response::new(Empty {})),
+            resp => Err(unexpected(resp)),
+        }
+    }
+
+    async fn call(&self, request: Request<CallRequest>) -> Result<Response<CallReply>, Status> {
+        let req = serde_json::from_str::<IPCMessageReq>(&request.get_ref().request_json)
+            .map_err(|e| format!("Malformed request: {}", e));
+        let resp = self.handle_admin(&request, req).await?;
+        Ok(Response::new(CallReply {
+            response_json: serde_json::to_string(&resp).unwrap(),
+        }))
+    }
+
+    type SubscribeBlocksStream = EventStream<FinalizedBlock>;
+
+    async fn subscribe_blocks(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<Self::SubscribeBlocksStream>, Status> {
+        let stream = self.subscribe(|event| match event {
+            ChainEvent::NewBlockFinalized(block_id, height) => {
+                Some(FinalizedBlock { block_id, height })
+            }
+            _ => None,
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    type SubscribeTransactionsStream = EventStream<ConfirmedTx>;
+
+    async fn subscribe_transactions(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<Self::SubscribeTransactionsStream>, Status> {
+        let stream = self.subscribe(|event| match event {
+            ChainEvent::TxConfirmed(data_string, block_id) => Some(ConfirmedTx {
+                data_string,
+                block_id,
+            }),
+            _ => None,
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+/// Serve the node API over gRPC at the given address, on a runtime in a background thread.
+/// A failure to start (e.g. the address is in use) is reported on stderr, like for the IPC socket.
+pub fn serve_grpc(addr: NetAddress, nakamoto: Nakamoto) {
+    let socket_addr = match format!("{}:{}", addr.ip, addr.port).parse() {
+        Ok(socket_addr) => socket_addr,
+        Err(e) => {
+            eprintln!("Cannot serve gRPC on {}:{}: {}", addr.ip, addr.port, e);
+            return;
+        }
+    };
+    thread::spawn(move || {
+        let runtime = match tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_all()
+            .build()
+        {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                eprintln!("Cannot start the gRPC runtime: {}", e);
+                return;
+            }
+        };
+        let admin_token = nakamoto.config_p.lock().unwrap().grpc_admin_token.clone();
+        let service = NodeServer::new(NodeService {
+            nakamoto,
+            admin_token,
+        });
+        let served = runtime.block_on(
+            tonic::transport::Server::builder()
+                .add_service(service)
+                .serve(socket_addr),
+        );
+        if let Err(e) = served {
+            eprintln!("Cannot serve gRPC on {}:{}: {}", addr.ip, addr.port, e);
+        }
+    });
+}
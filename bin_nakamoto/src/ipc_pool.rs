@@ -0,0 +1,100 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// This file implements the ReadPool, which answers the read-only IPC requests of stdin on a few worker threads,
+// so that a slow request (e.g. RequestStateSerialization of a long chain) does not hold up the balance queries behind it.
+// The workers only take read locks on the block tree, so they run in parallel with each other.
+// Mutating requests are still handled one by one on the main thread, after `wait_idle` (see main).
+
+use crate::nakamoto::Nakamoto;
+use crate::{handle_request, IPCMessageReq, IPCMessageResp};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// The number of worker threads answering read-only IPC requests.
+pub(crate) const IPC_READ_WORKERS: usize = 4;
+
+/// A fixed set of worker threads answering read-only IPC requests.
+/// Responses are passed to `respond` as soon as they are ready, so two reads may be answered out of order.
+/// This is fine for the client, which matches each response by its variant and the key it echoes (user_id, block_id, ...),
+/// since the IPC protocol has no request ids.
+pub(crate) struct ReadPool {
+    /// the sender of the requests to the workers (None once the pool is shut down)
+    sender: Option<Sender<IPCMessageReq>>,
+    /// the worker threads
+    workers: Vec<JoinHandle<()>>,
+    /// the number of dispatched requests that are not answered yet
+    pending: Arc<(Mutex<usize>, Condvar)>,
+}
+
+impl ReadPool {
+    /// Start `worker_count` workers answering requests with the given Nakamoto instance.
+    pub(crate) fn new(
+        nakamoto: Nakamoto,
+        worker_count: usize,
+        respond: Arc<dyn Fn(&IPCMessageResp) + Send + Sync>,
+    ) -> ReadPool {
+        let (sender, receiver) = mpsc::channel::<IPCMessageReq>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let pending = Arc::new((Mutex::new(0usize), Condvar::new()));
+        let workers = (0..worker_count.max(1))
+            .map(|_| {
+                let receiver = receiver.clone();
+                let pending = pending.clone();
+                let respond = respond.clone();
+                let mut nakamoto = Some(nakamoto.clone());
+                thread::spawn(move || loop {
+                    // The receiver lock is released before handling the request, so that other workers can take the next one
+                    let req = match receiver.lock().unwrap().recv() {
+                        Ok(req) => req,
+                        Err(_) => break,
+                    };
+                    respond(&handle_request(&mut nakamoto, req));
+                    let (count, idle) = &*pending;
+                    *count.lock().unwrap() -= 1;
+                    idle.notify_all();
+                })
+            })
+            .collect();
+        ReadPool {
+            sender: Some(sender),
+            workers,
+            pending,
+        }
+    }
+
+    /// Queue a read-only request for the workers.
+    pub(crate) fn dispatch(&self, req: IPCMessageReq) {
+        debug_assert!(req.is_read_only(), "Not a read-only request: {:?}", req);
+        *self.pending.0.lock().unwrap() += 1;
+        self.sender
+            .as_ref()
+            .expect("The read pool is shut down")
+            .send(req)
+            .expect("The read workers exited");
+    }
+
+    /// Block until every dispatched request has been answered.
+    pub(crate) fn wait_idle(&self) {
+        let (count, idle) = &*self.pending;
+        let _count = idle
+            .wait_while(count.lock().unwrap(), |count| *count > 0)
+            .unwrap();
+    }
+
+    /// Answer the queued requests, then stop the workers.
+    pub(crate) fn shutdown(&mut self) {
+        self.sender = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Drop for ReadPool {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
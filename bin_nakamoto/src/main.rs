@@ -7,35 +7,34 @@
 /// It reads commands from stdin and writes responses to stdout to facilitate IPC communication with bin_client eventually.
 /// However, you can also run it directly from the command line to test it.
 /// You can see detailed instructions in the comments below.
+mod check;
+mod diff;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod ipc_pool;
 mod nakamoto;
-use lib_chain::block::{BlockTree, Signature, Transaction};
-use nakamoto::Nakamoto;
+mod rpc;
+mod ws;
+use ipc_pool::{ReadPool, IPC_READ_WORKERS};
+use lib_chain::block::{ChainStatus, Signature, Transaction};
+use lib_chain::clock::clock;
+use lib_network::netchannel::NetAddress;
+use lib_network::p2pnetwork::NetworkStatus;
+use lib_tx_pool::pool::{TxPoolStatus, TxRejectReason};
+use nakamoto::{AddressHistoryEntry, BalanceBranch, ChainEvent, Nakamoto, NodeMinerStatus};
 
 use seccompiler::BpfMap;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 use std::fs;
-use std::io::{self, BufRead, Write};
+use std::io::{self, BufRead, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread;
 
 // Read a string from a file (to help you debug)
 fn read_string_from_file(filepath: &str) -> String {
-    let contents = fs::read_to_string(filepath).expect(&("Cannot read ".to_owned() + filepath));
-    contents
-}
-
-// Append a string to a file (to help you debug)
-fn append_string_to_file(filepath: &str, content: String) {
-    // if not exists, create file
-    if !std::path::Path::new(filepath).exists() {
-        fs::File::create(filepath).unwrap();
-    }
-    fs::OpenOptions::new()
-        .write(true)
-        .append(true)
-        .open(filepath)
-        .unwrap()
-        .write_all(content.as_bytes())
-        .unwrap();
+    fs::read_to_string(filepath).unwrap_or_else(|_| panic!("Cannot read {}", filepath))
 }
 
 /// This enum represents IPC messsage requests from the stdin
@@ -43,26 +42,141 @@ fn append_string_to_file(filepath: &str, content: String) {
 enum IPCMessageReq {
     /// Initialize the Nakamoto instance using the given (blocktree_json, tx_pool_json, config_json)
     Initialize(String, String, String),
-    /// Get the balance of the given address (user_id)
-    GetAddressBalance(String),
+    /// Get the balance of the given address on the given branch (user_id, branch)
+    GetAddressBalance(String, BalanceBranch),
     /// Publish a transaction to the network (data_string, signature)
     PublishTx(String, Signature),
     /// Get the block data of the given block_id
     RequestBlock(String),
+    /// Get the block data at the given height of the longest chain (height)
+    RequestBlockAtHeight(u64),
+    /// Get where (which peer) and when the given block_id was first seen
+    RequestBlockMetadata(String),
+    /// Get the difficulty and work figures of a block and its branch (block_id)
+    RequestBlockWork(String),
+    /// Check whether the transaction would be accepted by the working chain, without publishing it (data_string)
+    RequestSimulateTx(String),
+    /// Check whether the published transaction is in a block of the working chain yet (data_string)
+    RequestTxInclusion(String),
+    /// Check whether the published transaction is in a finalized block yet (data_string)
+    RequestTxFinality(String),
+    /// Get the transactions sent or received by the given address on the longest chain and in the tx pool (user_id)
+    RequestAddressHistory(String),
     /// Get the network status (for debugging)
     RequestNetStatus,
+    /// Get the gossip trace events recorded by this node (for reconstructing block and tx propagation)
+    RequestGossipTrace,
+    /// Stop the traffic to and from the given neighbor, to simulate a network partition (neighbor_addr as "ip:port")
+    PausePeer(String),
+    /// Resume the traffic to and from the given neighbor (neighbor_addr as "ip:port")
+    ResumePeer(String),
+    /// Block the peers matching the entry, disconnecting them if connected (entry as "ip:port", ip or node id)
+    BlockPeer(String),
+    /// Remove the entry from the blocklist of peers (entry)
+    UnblockPeer(String),
+    /// Add the entry to the allowlist of peers, turning the allowlist on if it is off (entry as "ip:port", ip or node id)
+    AllowPeer(String),
+    /// Remove the entry from the allowlist of peers (entry)
+    DisallowPeer(String),
     /// Get the chain status (for debugging)
     RequestChainStatus,
     /// Get the miner status (for debugging)
     RequestMinerStatus,
+    /// Let the miner mine new blocks
+    StartMiner,
+    /// Stop the miner, including the block it is mining, until StartMiner
+    StopMiner,
+    /// Change the mining work while the node runs, including on the block being mined (thread_count, target_hashrate)
+    /// The target hash rate (hashes per second of all the threads) is not limited if None
+    SetMinerConfig(u16, Option<u64>),
+    /// Get the chain statistics over the given number of last blocks (block_count)
+    RequestChainStats(u64),
+    /// Check the supply invariant of the chain (balances add up to the genesis supply plus the rewards)
+    AuditChain,
+    /// Check that the bookkeeping maps of the block tree (children, depths, parents) are consistent (for debugging)
+    CheckChainInvariants,
     /// Get the tx pool status (for debugging)
     RequestTxPoolStatus,
+    /// Get the effective config (mining and block creation parameters)
+    RequestConfig,
+    /// Change a runtime-changeable config parameter (key, value), e.g. ("difficulty_leading_zero_len", "5")
+    UpdateConfig(String, String),
+    /// Merge the blocks of another node's serialized state into the local block tree, validating each (blocktree_json)
+    ImportChain(String),
+    /// Get a checkpoint of the finalized state of the chain, signed by this node
+    RequestCheckpoint,
+    /// Replace the chain with one built from a signed checkpoint, keeping the blocks after it (checkpoint_json)
+    LoadCheckpoint(String),
     /// Get the state serialization (including BlockTree and TxPool)
     RequestStateSerialization,
+    /// Move the virtual clock forward by the given time (ms), for reproducible bot scenarios (see `clock::VIRTUAL_CLOCK_ENV`)
+    AdvanceClock(u64),
+    /// Push the events of the node (finalized blocks, confirmed transactions, connected neighbors) to stdout as they happen
+    SubscribeEvents,
     /// Quit the program
     Quit,
 }
 
+impl IPCMessageReq {
+    /// Whether the request only reads the state of the node, so that it can be answered in parallel with other reads.
+    fn is_read_only(&self) -> bool {
+        match self {
+            IPCMessageReq::GetAddressBalance(..)
+            | IPCMessageReq::RequestBlock(_)
+            | IPCMessageReq::RequestBlockAtHeight(_)
+            | IPCMessageReq::RequestBlockMetadata(_)
+            | IPCMessageReq::RequestBlockWork(_)
+            | IPCMessageReq::RequestSimulateTx(_)
+            | IPCMessageReq::RequestTxInclusion(_)
+            | IPCMessageReq::RequestTxFinality(_)
+            | IPCMessageReq::RequestAddressHistory(_)
+            | IPCMessageReq::RequestNetStatus
+            | IPCMessageReq::RequestGossipTrace
+            | IPCMessageReq::RequestChainStatus
+            | IPCMessageReq::RequestMinerStatus
+            | IPCMessageReq::RequestChainStats(_)
+            | IPCMessageReq::AuditChain
+            | IPCMessageReq::CheckChainInvariants
+            | IPCMessageReq::RequestTxPoolStatus
+            | IPCMessageReq::RequestConfig
+            | IPCMessageReq::RequestCheckpoint
+            | IPCMessageReq::RequestStateSerialization => true,
+            IPCMessageReq::Initialize(..)
+            | IPCMessageReq::PublishTx(..)
+            | IPCMessageReq::PausePeer(_)
+            | IPCMessageReq::ResumePeer(_)
+            | IPCMessageReq::BlockPeer(_)
+            | IPCMessageReq::UnblockPeer(_)
+            | IPCMessageReq::AllowPeer(_)
+            | IPCMessageReq::DisallowPeer(_)
+            | IPCMessageReq::StartMiner
+            | IPCMessageReq::StopMiner
+            | IPCMessageReq::SetMinerConfig(..)
+            | IPCMessageReq::UpdateConfig(..)
+            | IPCMessageReq::ImportChain(_)
+            | IPCMessageReq::LoadCheckpoint(_)
+            | IPCMessageReq::AdvanceClock(_)
+            | IPCMessageReq::SubscribeEvents
+            | IPCMessageReq::Quit => false,
+        }
+    }
+
+    /// Whether a remote client (the IPC socket or the gRPC server) may send the request: the status reads, the tx
+    /// simulation and the tx publication. The requests that change the node or dump its state or config, and the ones
+    /// that walk the whole chain under its lock (so that a client could stall the node), are only accepted on stdin.
+    fn is_remote_allowed(&self) -> bool {
+        match self {
+            IPCMessageReq::PublishTx(..) => true,
+            IPCMessageReq::RequestConfig
+            | IPCMessageReq::RequestStateSerialization
+            | IPCMessageReq::AuditChain
+            | IPCMessageReq::CheckChainInvariants
+            | IPCMessageReq::RequestCheckpoint => false,
+            req => req.is_read_only(),
+        }
+    }
+}
+
 /// This enum represents IPC messsage responses to the stdout
 #[derive(Serialize, Deserialize, Debug, Clone)]
 enum IPCMessageResp {
@@ -70,31 +184,628 @@ enum IPCMessageResp {
     Initialized,
     /// The transaction has been published (responding to PublishTx)
     PublishTxDone,
-    /// The balance of the given address (user_id, balance)
-    AddressBalance(String, i64),
+    /// The transaction has been rejected and is not queued (responding to PublishTx) (data_string, reason)
+    PublishTxRejected(String, TxRejectReason),
+    /// The balance of the given address on the given branch (user_id, branch, balance)
+    AddressBalance(String, BalanceBranch, i64),
     /// The block data of the given block_id (block_data)
     BlockData(String),
-    /// The network status as a dictionary of strings (for debugging)
-    NetStatus(BTreeMap<String, String>),
-    /// The chain status as a dictionary of strings (for debugging)
-    ChainStatus(BTreeMap<String, String>),
-    /// The miner status as a dictionary of strings (for debugging)
-    MinerStatus(BTreeMap<String, String>),
-    /// The tx pool status as a dictionary of strings (for debugging)
-    TxPoolStatus(BTreeMap<String, String>),
+    /// The block data at the given height of the longest chain (height, block_data)
+    BlockAtHeight(u64, String),
+    /// Where and when the given block was first seen (block_id, block_metadata_json)
+    BlockMetadata(String, String),
+    /// The difficulty and work figures of the given block (block_id, work_map): leading_zero_len, work, branch_work, height
+    BlockWork(String, BTreeMap<String, String>),
+    /// The outcome of a simulated transaction (data_string, accepted, reason_if_rejected)
+    SimulateTxResult(String, bool, String),
+    /// Whether the transaction is in a block of the working chain (data_string, included)
+    TxInclusion(String, bool),
+    /// Whether the transaction is in a finalized block (data_string, finalized)
+    TxFinality(String, bool),
+    /// The transactions of the given address, the most recent first (user_id, entries)
+    AddressHistory(String, Vec<AddressHistoryEntry>),
+    /// The network status (for debugging)
+    NetStatus(Box<NetworkStatus>),
+    /// The gossip trace events recorded by this node (trace_events_json)
+    GossipTrace(String),
+    /// The traffic to and from the neighbor is stopped (responding to PausePeer) (neighbor_addr)
+    PeerPaused(String),
+    /// The traffic to and from the neighbor is resumed (responding to ResumePeer) (neighbor_addr)
+    PeerResumed(String),
+    /// The entry is added to the blocklist of peers (responding to BlockPeer) (entry)
+    PeerBlocked(String),
+    /// The entry is removed from the blocklist of peers (responding to UnblockPeer) (entry)
+    PeerUnblocked(String),
+    /// The entry is added to the allowlist of peers (responding to AllowPeer) (entry)
+    PeerAllowed(String),
+    /// The entry is removed from the allowlist of peers (responding to DisallowPeer) (entry)
+    PeerDisallowed(String),
+    /// The chain status (for debugging)
+    ChainStatus(ChainStatus),
+    /// The miner status (for debugging)
+    MinerStatus(NodeMinerStatus),
+    /// The miner mines new blocks (responding to StartMiner)
+    MinerStarted,
+    /// The miner is stopped (responding to StopMiner)
+    MinerStopped,
+    /// The mining work has been changed (responding to SetMinerConfig) (thread_count, target_hashrate)
+    MinerConfigSet(u16, Option<u64>),
+    /// The chain statistics as a dictionary of strings
+    ChainStats(BTreeMap<String, String>),
+    /// The result of the chain audit (passed, violation_if_failed)
+    ChainAudit(bool, String),
+    /// The violations of the block tree invariants found, none if it is consistent (violations)
+    ChainInvariants(Vec<String>),
+    /// The tx pool status (for debugging)
+    TxPoolStatus(TxPoolStatus),
+    /// The effective config as a dictionary of strings
+    ConfigStatus(BTreeMap<String, String>),
+    /// The config parameter has been changed (responding to UpdateConfig) (key, applied_value)
+    ConfigUpdated(String, String),
+    /// The blocks of the other state have been merged (responding to ImportChain) (added, known, rejected)
+    ChainImported(u64, u64, u64),
+    /// The signed checkpoint of the finalized state of the chain (checkpoint_json)
+    Checkpoint(String),
+    /// The chain has been built from the checkpoint (responding to LoadCheckpoint) (height, finalized_block_id)
+    CheckpointLoaded(u64, String),
     /// The state serialization (blocktree_json_string, tx_pool_json_string)
     StateSerialization(String, String),
+    /// The new time of the virtual clock (now_ms)
+    ClockAdvanced(u64),
+    /// The events of the node are pushed from now on (responding to SubscribeEvents)
+    EventsSubscribed,
+    /// This is not a response, but an event of the node pushed after SubscribeEvents (event)
+    Event(ChainEvent),
     /// The program is quitting (responding to Quit)
     Quitting,
     /// This is not an actual response, but an arbitrary notification message for debugging
     Notify(String),
 }
 
+/// Rebuild a transaction from the `data_string` signed by the wallet and its signature.
+/// The `data_string` is the canonical json encoding of the `SignedPayload` (sender, receiver, message, then the optional
+/// sender public key and fee), so it is decoded as json instead of being sliced by hand (the message may contain quotes, commas, etc).
+fn parse_tx_data_string(data_string: &str, sig: Signature) -> Result<Transaction, String> {
+    Transaction::from_data_string(data_string, sig)
+}
+
+/// Handle one IPC request and return its response.
+/// It is shared by the stdin loop and the connections to the IPC socket.
+fn handle_request(nakamoto: &mut Option<Nakamoto>, req: IPCMessageReq) -> IPCMessageResp {
+    match req {
+        IPCMessageReq::Initialize(blocktree_json, tx_pool_json, config_json) => {
+            // Initialize the Nakamoto instance using the given (blocktree_json, tx_pool_json, config_json)
+            *nakamoto = Some(Nakamoto::create_nakamoto(
+                blocktree_json,
+                tx_pool_json,
+                config_json,
+            ));
+
+            IPCMessageResp::Initialized
+        }
+        IPCMessageReq::GetAddressBalance(user_id, branch) => {
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            let balance = nakamoto.get_balance(&user_id, branch);
+            IPCMessageResp::AddressBalance(user_id, branch, balance)
+        }
+        IPCMessageReq::PublishTx(data_string, signature) => {
+            // Publish a transaction to the network (data_string, signature)
+            match parse_tx_data_string(&data_string, signature) {
+                Ok(tx) => {
+                    let nakamoto = nakamoto.as_mut().unwrap();
+                    match nakamoto.publish_tx(tx) {
+                        Ok(()) => IPCMessageResp::PublishTxDone,
+                        Err(reason) => IPCMessageResp::PublishTxRejected(data_string, reason),
+                    }
+                }
+                Err(e) => IPCMessageResp::Notify(format!("PublishTx rejected: {}", e)),
+            }
+        }
+        IPCMessageReq::RequestBlock(block_id) => {
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            match nakamoto.get_block_json(&block_id) {
+                Some(block_data) => IPCMessageResp::BlockData(block_data),
+                None => IPCMessageResp::Notify(format!("Unknown block {}", block_id)),
+            }
+        }
+        IPCMessageReq::RequestBlockAtHeight(height) => {
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            match nakamoto.get_block_at_height_json(height) {
+                Some(block_data) => IPCMessageResp::BlockAtHeight(height, block_data),
+                None => IPCMessageResp::Notify(format!("No block at height {}", height)),
+            }
+        }
+        IPCMessageReq::RequestBlockMetadata(block_id) => {
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            match nakamoto.get_block_metadata_json(&block_id) {
+                Some(metadata) => IPCMessageResp::BlockMetadata(block_id, metadata),
+                None => IPCMessageResp::Notify(format!("No metadata for block {}", block_id)),
+            }
+        }
+        IPCMessageReq::RequestBlockWork(block_id) => {
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            match nakamoto.get_block_work(&block_id) {
+                Some(work) => IPCMessageResp::BlockWork(block_id, work),
+                None => IPCMessageResp::Notify(format!("No block {} in the block tree", block_id)),
+            }
+        }
+        IPCMessageReq::RequestSimulateTx(data_string) => {
+            // Evaluate the transaction against the working chain without adding it to the tx pool
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            match parse_tx_data_string(&data_string, Signature::new())
+                .and_then(|tx| nakamoto.simulate_tx(&tx).map_err(|e| e.to_string()))
+            {
+                Ok(()) => IPCMessageResp::SimulateTxResult(data_string, true, String::new()),
+                Err(e) => IPCMessageResp::SimulateTxResult(data_string, false, e),
+            }
+        }
+        IPCMessageReq::RequestTxInclusion(data_string) => {
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            match parse_tx_data_string(&data_string, Signature::new()) {
+                Ok(tx) => {
+                    let included = nakamoto.is_tx_on_working_chain(&tx);
+                    IPCMessageResp::TxInclusion(data_string, included)
+                }
+                Err(e) => IPCMessageResp::Notify(format!("RequestTxInclusion rejected: {}", e)),
+            }
+        }
+        IPCMessageReq::RequestTxFinality(data_string) => {
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            match parse_tx_data_string(&data_string, Signature::new()) {
+                Ok(tx) => {
+                    let finalized = nakamoto.is_tx_finalized(&tx);
+                    IPCMessageResp::TxFinality(data_string, finalized)
+                }
+                Err(e) => IPCMessageResp::Notify(format!("RequestTxFinality rejected: {}", e)),
+            }
+        }
+        IPCMessageReq::RequestAddressHistory(user_id) => {
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            let history = nakamoto.get_address_history(&user_id);
+            IPCMessageResp::AddressHistory(user_id, history)
+        }
+        IPCMessageReq::RequestNetStatus => {
+            // Get the network status (for debugging)
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            IPCMessageResp::NetStatus(Box::new(nakamoto.get_network_status()))
+        }
+        IPCMessageReq::RequestGossipTrace => {
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            IPCMessageResp::GossipTrace(nakamoto.get_gossip_trace_json())
+        }
+        IPCMessageReq::PausePeer(addr) => {
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            match nakamoto.pause_peer(&addr) {
+                Ok(()) => IPCMessageResp::PeerPaused(addr),
+                Err(e) => IPCMessageResp::Notify(format!("PausePeer failed: {}", e)),
+            }
+        }
+        IPCMessageReq::ResumePeer(addr) => {
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            match nakamoto.resume_peer(&addr) {
+                Ok(()) => IPCMessageResp::PeerResumed(addr),
+                Err(e) => IPCMessageResp::Notify(format!("ResumePeer failed: {}", e)),
+            }
+        }
+        IPCMessageReq::BlockPeer(entry) => {
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            match nakamoto.block_peer(&entry) {
+                Ok(()) => IPCMessageResp::PeerBlocked(entry),
+                Err(e) => IPCMessageResp::Notify(format!("BlockPeer failed: {}", e)),
+            }
+        }
+        IPCMessageReq::UnblockPeer(entry) => {
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            match nakamoto.unblock_peer(&entry) {
+                Ok(()) => IPCMessageResp::PeerUnblocked(entry),
+                Err(e) => IPCMessageResp::Notify(format!("UnblockPeer failed: {}", e)),
+            }
+        }
+        IPCMessageReq::AllowPeer(entry) => {
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            match nakamoto.allow_peer(&entry) {
+                Ok(()) => IPCMessageResp::PeerAllowed(entry),
+                Err(e) => IPCMessageResp::Notify(format!("AllowPeer failed: {}", e)),
+            }
+        }
+        IPCMessageReq::DisallowPeer(entry) => {
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            match nakamoto.disallow_peer(&entry) {
+                Ok(()) => IPCMessageResp::PeerDisallowed(entry),
+                Err(e) => IPCMessageResp::Notify(format!("DisallowPeer failed: {}", e)),
+            }
+        }
+        IPCMessageReq::RequestChainStatus => {
+            // Get the chain status (for debugging)
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            IPCMessageResp::ChainStatus(nakamoto.get_chain_status())
+        }
+        IPCMessageReq::RequestMinerStatus => {
+            // Get the miner status (for debugging)
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            IPCMessageResp::MinerStatus(nakamoto.get_miner_status())
+        }
+        IPCMessageReq::StartMiner => {
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            nakamoto.start_miner();
+            IPCMessageResp::MinerStarted
+        }
+        IPCMessageReq::StopMiner => {
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            nakamoto.stop_miner();
+            IPCMessageResp::MinerStopped
+        }
+        IPCMessageReq::SetMinerConfig(thread_count, target_hashrate) => {
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            match nakamoto.set_miner_config(thread_count, target_hashrate) {
+                Ok(()) => IPCMessageResp::MinerConfigSet(thread_count, target_hashrate),
+                Err(e) => IPCMessageResp::Notify(format!("SetMinerConfig failed: {}", e)),
+            }
+        }
+        IPCMessageReq::RequestChainStats(last_n) => {
+            // Get the chain statistics over the last `last_n` blocks
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            IPCMessageResp::ChainStats(nakamoto.get_chain_stats(last_n))
+        }
+        IPCMessageReq::AuditChain => {
+            // Check the supply invariant of the chain
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            match nakamoto.audit_chain() {
+                Ok(()) => IPCMessageResp::ChainAudit(true, String::new()),
+                Err(violation) => IPCMessageResp::ChainAudit(false, violation),
+            }
+        }
+        IPCMessageReq::CheckChainInvariants => {
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            IPCMessageResp::ChainInvariants(
+                nakamoto.check_chain_invariants().err().unwrap_or_default(),
+            )
+        }
+        IPCMessageReq::RequestTxPoolStatus => {
+            // Get the tx pool status (for debugging)
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            IPCMessageResp::TxPoolStatus(nakamoto.get_txpool_status())
+        }
+        IPCMessageReq::RequestConfig => {
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            IPCMessageResp::ConfigStatus(nakamoto.get_config_status())
+        }
+        IPCMessageReq::UpdateConfig(key, value) => {
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            match nakamoto.update_config(&key, &value) {
+                Ok(applied) => IPCMessageResp::ConfigUpdated(key, applied),
+                Err(e) => IPCMessageResp::Notify(format!("UpdateConfig failed: {}", e)),
+            }
+        }
+        IPCMessageReq::ImportChain(blocktree_json) => {
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            match nakamoto.import_chain(&blocktree_json) {
+                Ok(summary) => IPCMessageResp::ChainImported(
+                    summary.added.len() as u64,
+                    summary.known as u64,
+                    summary.rejected.len() as u64,
+                ),
+                Err(e) => IPCMessageResp::Notify(format!("ImportChain failed: {}", e)),
+            }
+        }
+        IPCMessageReq::RequestCheckpoint => {
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            IPCMessageResp::Checkpoint(serde_json::to_string(&nakamoto.get_checkpoint()).unwrap())
+        }
+        IPCMessageReq::LoadCheckpoint(checkpoint_json) => {
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            match nakamoto.load_checkpoint(&checkpoint_json) {
+                Ok(checkpoint) => IPCMessageResp::CheckpointLoaded(
+                    checkpoint.height,
+                    checkpoint.finalized_block_id,
+                ),
+                Err(e) => IPCMessageResp::Notify(format!("LoadCheckpoint failed: {}", e)),
+            }
+        }
+        IPCMessageReq::RequestStateSerialization => {
+            // Get the state serialization (including BlockTree and TxPool)
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            IPCMessageResp::StateSerialization(
+                nakamoto.get_serialized_chain(),
+                nakamoto.get_serialized_txpool(),
+            )
+        }
+        IPCMessageReq::AdvanceClock(ms) => match clock().advance_ms(ms) {
+            Ok(now_ms) => IPCMessageResp::ClockAdvanced(now_ms),
+            Err(e) => IPCMessageResp::Notify(format!("AdvanceClock failed: {}", e)),
+        },
+        IPCMessageReq::SubscribeEvents => {
+            // Forward the events of the node to stdout, in between the responses, until the node quits
+            let nakamoto = nakamoto
+                .as_ref()
+                .expect("Nakamoto instance not initialized");
+            let events = nakamoto.subscribe_events();
+            thread::spawn(move || {
+                for event in events {
+                    write_ipc_frame(&encode_ipc_frame(&IPCMessageResp::Event(event)));
+                }
+            });
+            IPCMessageResp::EventsSubscribed
+        }
+        IPCMessageReq::Quit => {
+            // Quit the program
+            IPCMessageResp::Quitting
+        }
+    }
+}
+
+/// The maximum size (in bytes) of one IPC frame, i.e. one json message on one line.
+/// It bounds the memory used by a reader; the serialized state of a long chain is the largest legitimate frame.
+const MAX_IPC_FRAME_BYTES: usize = 64 * 1024 * 1024;
+
+/// Read the next IPC frame (one json message per line), skipping blank lines.
+/// It returns None at the end of the stream, and an error for a frame that is larger than `max_len` (which is skipped) or not UTF-8.
+fn read_ipc_frame(reader: &mut impl BufRead, max_len: usize) -> Option<Result<String, String>> {
+    loop {
+        let mut frame = Vec::new();
+        let read = Read::take(&mut *reader, max_len as u64 + 1)
+            .read_until(b'\n', &mut frame)
+            .ok()?;
+        if read == 0 {
+            return None;
+        }
+        if frame.len() > max_len && frame.last() != Some(&b'\n') {
+            // Skip the rest of the oversized frame, so that the next frame is read from its start
+            let mut frame_len = frame.len();
+            loop {
+                let mut rest = Vec::new();
+                let read = Read::take(&mut *reader, max_len as u64)
+                    .read_until(b'\n', &mut rest)
+                    .unwrap_or(0);
+                frame_len += read;
+                if read == 0 || rest.last() == Some(&b'\n') {
+                    break;
+                }
+            }
+            return Some(Err(format!(
+                "Frame of {} bytes exceeds the max frame size ({} bytes)",
+                frame_len, max_len
+            )));
+        }
+        match String::from_utf8(frame) {
+            Ok(frame) if frame.trim().is_empty() => continue,
+            Ok(frame) => return Some(Ok(frame.trim().to_string())),
+            Err(_) => return Some(Err("Frame is not valid UTF-8".to_string())),
+        }
+    }
+}
+
+/// Parse one IPC frame as a request. Frames that cannot be read or parsed are described in the error.
+fn parse_ipc_request(frame: Result<String, String>) -> Result<IPCMessageReq, String> {
+    serde_json::from_str(&frame?).map_err(|e| format!("Malformed request: {}", e))
+}
+
+/// Serialize an IPC response as one frame (without the newline).
+/// A response that would exceed `MAX_IPC_FRAME_BYTES` is replaced by a Notify, so that readers never receive an oversized frame.
+fn encode_ipc_frame(response: &IPCMessageResp) -> String {
+    let frame = serde_json::to_string(response).unwrap();
+    if frame.len() < MAX_IPC_FRAME_BYTES {
+        return frame;
+    }
+    serde_json::to_string(&IPCMessageResp::Notify(format!(
+        "Response of {} bytes exceeds the max frame size ({} bytes)",
+        frame.len(),
+        MAX_IPC_FRAME_BYTES
+    )))
+    .unwrap()
+}
+
+/// Write one IPC response frame (see `encode_ipc_frame`) to stdout, followed by a newline.
+/// Stdout is reserved for these frames, because the client parses every stdout line as a response;
+/// diagnostics go to stderr instead (the client keeps them in its logs).
+pub(crate) fn write_ipc_frame(frame: &str) {
+    debug_assert!(
+        !frame.contains('\n')
+            && frame.len() < MAX_IPC_FRAME_BYTES
+            && serde_json::from_str::<IPCMessageResp>(frame).is_ok(),
+        "Not an IPC response frame: {}",
+        frame
+    );
+    let mut stdout = io::stdout().lock();
+    writeln!(stdout, "{}", frame).expect("Failed to write to stdout");
+    stdout.flush().expect("Failed to flush stdout");
+}
+
+/// Handle one request (or the reason why it could not be parsed) from a remote client: the IPC socket or the gRPC server.
+/// Only the requests allowed by `IPCMessageReq::is_remote_allowed` are answered, so that remote clients cannot
+/// replace, stop or reconfigure the node; the others are only accepted on stdin.
+fn handle_remote_request(
+    nakamoto: &Nakamoto,
+    req: Result<IPCMessageReq, String>,
+) -> IPCMessageResp {
+    match req {
+        Ok(req) if req.is_remote_allowed() => handle_request(&mut Some(nakamoto.clone()), req),
+        Ok(_) => IPCMessageResp::Notify(
+            "The request is only accepted on stdin: remote clients may only read the status and publish txs"
+                .to_string(),
+        ),
+        Err(e) => IPCMessageResp::Notify(e),
+    }
+}
+
+/// Whether the address only resolves to loopback addresses, i.e. is only reachable from this machine.
+fn is_loopback(addr: &NetAddress) -> bool {
+    u16::try_from(addr.port)
+        .ok()
+        .and_then(|port| (addr.ip.as_str(), port).to_socket_addrs().ok())
+        .map(|addrs| addrs.collect::<Vec<_>>())
+        .is_some_and(|addrs| !addrs.is_empty() && addrs.iter().all(|addr| addr.ip().is_loopback()))
+}
+
+/// Serve the IPC over TCP at the given address in background threads, one json request and response per line
+/// (see `handle_remote_request` for the requests refused). Unless `public` is set, the address must be a loopback
+/// address, since the socket is not authenticated.
+fn serve_ipc_socket(addr: NetAddress, public: bool, nakamoto: Nakamoto) {
+    if !public && !is_loopback(&addr) {
+        eprintln!(
+            "Not serving IPC on {}:{}: not a loopback address (set `ipc_listen_public` to allow it)",
+            addr.ip, addr.port
+        );
+        return;
+    }
+    let listener = match TcpListener::bind(format!("{}:{}", addr.ip, addr.port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Cannot serve IPC on {}:{}: {}", addr.ip, addr.port, e);
+            return;
+        }
+    };
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let nakamoto = nakamoto.clone();
+            thread::spawn(move || serve_ipc_connection(stream, nakamoto));
+        }
+    });
+}
+
+/// Answer the IPC requests read from one connection to the IPC socket until it is closed.
+fn serve_ipc_connection(stream: TcpStream, nakamoto: Nakamoto) {
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return,
+    };
+    let mut reader = io::BufReader::new(stream);
+    while let Some(frame) = read_ipc_frame(&mut reader, MAX_IPC_FRAME_BYTES) {
+        let response = handle_remote_request(&nakamoto, parse_ipc_request(frame));
+        let mut output = encode_ipc_frame(&response);
+        output.push('\n');
+        if writer.write_all(output.as_bytes()).is_err() {
+            break;
+        }
+    }
+}
+
 fn main() {
-    // bin_nakamoto has only one optional argument: the path to the seccomp policy file
+    // `bin_nakamoto --diff <state_a_path> <state_b_path>` compares two serialized states (two BlockTree.json or two TxPool.json files)
+    // and prints where they diverge, instead of running a node.
+    if std::env::args().nth(1).as_deref() == Some("--diff") {
+        let (Some(path_a), Some(path_b)) = (std::env::args().nth(2), std::env::args().nth(3))
+        else {
+            eprintln!("Usage: bin_nakamoto --diff <state_a_path> <state_b_path>");
+            std::process::exit(2);
+        };
+        match diff::diff_state_files(&path_a, &path_b) {
+            Ok(report) => report.iter().for_each(|line| println!("{}", line)),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // `bin_nakamoto --check <config_dir> [<seccomp_policy_path>]` checks the BlockTree.json, TxPool.json and Config.json
+    // of a config dir (and the seccomp policy, if given) and prints what to fix, instead of running a node.
+    if std::env::args().nth(1).as_deref() == Some("--check") {
+        let Some(config_dir) = std::env::args().nth(2) else {
+            eprintln!("Usage: bin_nakamoto --check <config_dir> [<seccomp_policy_path>]");
+            std::process::exit(2);
+        };
+        let policy_path = std::env::args().nth(3);
+        let results = check::check_config_dir(
+            std::path::Path::new(&config_dir),
+            policy_path.as_deref().map(std::path::Path::new),
+        );
+        results.iter().for_each(|result| println!("{}", result));
+        if results.iter().any(|result| result.failed()) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // `bin_nakamoto --rpc-port <port> ...` also serves the node over HTTP JSON-RPC on 127.0.0.1 at that port once it is
+    // initialized (see `rpc.rs`), e.g. for block explorers that do not spawn the node.
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let rpc_port = match args.iter().position(|arg| arg == "--rpc-port") {
+        Some(index) => match args
+            .get(index + 1)
+            .and_then(|port| port.parse::<u16>().ok())
+        {
+            Some(port) => {
+                args.drain(index..index + 2);
+                Some(port)
+            }
+            None => {
+                eprintln!("Usage: bin_nakamoto [--rpc-port <port>] [<seccomp_policy_path>]");
+                std::process::exit(2);
+            }
+        },
+        None => None,
+    };
+
+    // bin_nakamoto has only one other optional argument: the path to the seccomp policy file
     // If the argument is provided, bin_nakamoto will read and apply the seccomp policy at the beginning of the program
     // Otherwise, it will proceed to the normal execution
-    let maybe_policy_path = std::env::args().nth(1);
+    let maybe_policy_path = args.into_iter().next();
     if let Some(policy_path) = maybe_policy_path {
         // Please fill in the blank
         // If the first param is provided, read the seccomp config and apply it
@@ -105,132 +816,2044 @@ fn main() {
         .unwrap();
         let filter = filter_map.get("main_thread").unwrap();
 
-        seccompiler::apply_filter(&filter).unwrap();
+        seccompiler::apply_filter(filter).unwrap();
     }
 
     // The main logic of the bin_nakamoto starts here
     // It reads IPC calls from stdin and write IPC responses to stdout in a loop.
     // The first IPC call should be Initialize, whose parameters are serialized BlockTree, TxPool, and Config.
-    // After that, there can be artitrary number of IPC calls, including GetAddressBalance, PublishTx, RequestBlock, RequestNetStatus, RequestChainStatus, RequestMinerStatus, RequestTxPoolStatus, RequestStateSerialization, etc.
+    // After that, there can be artitrary number of IPC calls, including GetAddressBalance, PublishTx, RequestBlock, RequestNetStatus, RequestChainStatus, RequestMinerStatus, RequestTxPoolStatus, RequestConfig, UpdateConfig, RequestStateSerialization, etc.
     // Eventually, the program will quit when receiving a Quit IPC call.
     // Please fill in the blank
     // Loop over stdin and handle IPC messages
+    // Once initialized, read-only requests are answered by the read pool, while the other requests are handled here in order.
+    // A mutating request waits until the reads received before it are answered, so that they observe the state before it.
     let mut nakamoto: Option<Nakamoto> = None;
-    let stdin = io::stdin();
-    for line in stdin.lock().lines() {
-        let input = line.unwrap();
-        let req: IPCMessageReq =
-            serde_json::from_str(&input).expect("Failed to parse input as IPCMessageReq");
-        let response = match req {
-            IPCMessageReq::Initialize(blocktree_json, tx_pool_json, config_json) => {
-                // Initialize the Nakamoto instance using the given (blocktree_json, tx_pool_json, config_json)
-                nakamoto = Some(Nakamoto::create_nakamoto(
-                    blocktree_json,
-                    tx_pool_json,
-                    config_json,
-                ));
-
-                IPCMessageResp::Initialized
-            }
-            IPCMessageReq::GetAddressBalance(user_id) => {
-                let nakamoto = nakamoto
-                    .as_ref()
-                    .expect("Nakamoto instance not initialized");
-                let chain = nakamoto.get_serialized_chain();
-                // Deserialize the chain
-                let deserialized_chain: BlockTree = serde_json::from_str(&chain).unwrap();
-                // Get the balance of the given address
-                let balance = deserialized_chain
-                    .finalized_balance_map
-                    .get(&user_id)
-                    .unwrap();
-
-                IPCMessageResp::AddressBalance(user_id, *balance)
-            }
-            IPCMessageReq::PublishTx(data_string, signature) => {
-                // Publish a transaction to the network (data_string, signature)
-
-                // Get sender from data_string
-                // Remove the first and last three characters
-                let data_string = data_string[2..data_string.len() - 2].to_string();
-                let split_data_string = data_string.split("\",\"").collect::<Vec<&str>>();
-                // println!("{:?}", split_data_string);
-                let sender_id = split_data_string[0].to_string();
-                let receiver_id = split_data_string[1].to_string();
-                let msg = split_data_string[2].to_string();
-
-                // Create a transaction instance
-                let tx = Transaction {
-                    sender: sender_id,
-                    receiver: receiver_id,
-                    message: msg,
-                    sig: signature,
-                };
-                // Publish to the network
-                let nakamoto = nakamoto.as_mut().unwrap();
-                nakamoto.publish_tx(tx);
-
-                IPCMessageResp::PublishTxDone
-            }
-            IPCMessageReq::RequestBlock(block_id) => {
-                let nakamoto = nakamoto
-                    .as_ref()
-                    .expect("Nakamoto instance not initialized");
-
-                let chain = nakamoto.get_serialized_chain();
-                // Deserialize the chain
-                let deserialized_chain: BlockTree = serde_json::from_str(&chain).unwrap();
-                // Get the block data of the given block_id and serialize it
-                let block_data = deserialized_chain.all_blocks.get(&block_id).unwrap();
-                let serialized_block_data = serde_json::to_string(&block_data).unwrap();
-
-                //create block instance
-                IPCMessageResp::BlockData(serialized_block_data)
-            }
-            IPCMessageReq::RequestNetStatus => {
-                // Get the network status (for debugging)
-                let nakamoto = nakamoto
-                    .as_ref()
-                    .expect("Nakamoto instance not initialized");
-                IPCMessageResp::NetStatus(nakamoto.get_network_status())
-            }
-            IPCMessageReq::RequestChainStatus => {
-                // Get the chain status (for debugging)
-                let nakamoto = nakamoto
-                    .as_ref()
-                    .expect("Nakamoto instance not initialized");
-                IPCMessageResp::ChainStatus(nakamoto.get_chain_status())
-            }
-            IPCMessageReq::RequestMinerStatus => {
-                // Get the miner status (for debugging)
-                let nakamoto = nakamoto
-                    .as_ref()
-                    .expect("Nakamoto instance not initialized");
-                IPCMessageResp::MinerStatus(nakamoto.get_miner_status())
-            }
-            IPCMessageReq::RequestTxPoolStatus => {
-                // Get the tx pool status (for debugging)
-                let nakamoto = nakamoto
-                    .as_ref()
-                    .expect("Nakamoto instance not initialized");
-                IPCMessageResp::TxPoolStatus(nakamoto.get_txpool_status())
-            }
-            IPCMessageReq::RequestStateSerialization => {
-                // Get the state serialization (including BlockTree and TxPool)
-                let nakamoto = nakamoto
-                    .as_ref()
-                    .expect("Nakamoto instance not initialized");
-                IPCMessageResp::StateSerialization(
-                    nakamoto.get_serialized_chain(),
-                    nakamoto.get_serialized_txpool(),
-                )
+    let mut read_pool: Option<ReadPool> = None;
+    let mut stdin = io::stdin().lock();
+    while let Some(frame) = read_ipc_frame(&mut stdin, MAX_IPC_FRAME_BYTES) {
+        let req = match parse_ipc_request(frame) {
+            Ok(req) => req,
+            Err(e) => {
+                write_ipc_frame(&encode_ipc_frame(&IPCMessageResp::Notify(e)));
+                continue;
+            }
+        };
+        if let (true, Some(read_pool)) = (req.is_read_only(), &read_pool) {
+            read_pool.dispatch(req);
+            continue;
+        }
+        if let Some(read_pool) = &read_pool {
+            read_pool.wait_idle();
+        }
+        let is_initialize = matches!(req, IPCMessageReq::Initialize(..));
+        let response = handle_request(&mut nakamoto, req);
+        if let (true, Some(nakamoto)) = (is_initialize, &nakamoto) {
+            let (ipc_listen_addr, ipc_listen_public) = {
+                let config = nakamoto.config_p.lock().unwrap();
+                (config.ipc_listen_addr.clone(), config.ipc_listen_public)
+            };
+            if let Some(addr) = ipc_listen_addr {
+                serve_ipc_socket(addr, ipc_listen_public, nakamoto.clone());
             }
-            IPCMessageReq::Quit => {
-                // Quit the program
-                IPCMessageResp::Quitting
+            let ws_listen_addr = nakamoto.config_p.lock().unwrap().ws_listen_addr.clone();
+            if let Some(addr) = ws_listen_addr {
+                ws::serve_ws(addr, nakamoto.clone());
             }
+            if let Some(port) = rpc_port {
+                rpc::serve_rpc(port, nakamoto.clone());
+            }
+            let grpc_listen_addr = nakamoto.config_p.lock().unwrap().grpc_listen_addr.clone();
+            if let Some(addr) = grpc_listen_addr {
+                #[cfg(feature = "grpc")]
+                grpc::serve_grpc(addr, nakamoto.clone());
+                #[cfg(not(feature = "grpc"))]
+                eprintln!(
+                    "Cannot serve gRPC on {}:{}: bin_nakamoto is built without the grpc feature",
+                    addr.ip, addr.port
+                );
+            }
+            read_pool = Some(ReadPool::new(
+                nakamoto.clone(),
+                IPC_READ_WORKERS,
+                Arc::new(|response| write_ipc_frame(&encode_ipc_frame(response))),
+            ));
+        }
+        write_ipc_frame(&encode_ipc_frame(&response));
+    }
+    // Answer the reads still queued before exiting
+    if let Some(mut read_pool) = read_pool {
+        read_pool.shutdown();
+    }
+    // Sync the last changes of the tx pool log
+    if let Some(nakamoto) = &nakamoto {
+        if let Err(e) = nakamoto.tx_pool_p.lock().unwrap().sync_log() {
+            eprintln!("{}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::check::{check_config_dir, CheckOutcome};
+    use crate::diff::{diff_chains, diff_tx_pools};
+    use crate::ipc_pool::ReadPool;
+    use crate::nakamoto::{
+        address_history, create_puzzle, simulate_tx_against, AutoDifficulty, BalanceBranch,
+        ChainEvent, Config, Nakamoto, TipWatch, TxDirection, TxTracer, AUTO_DIFFICULTY_WINDOW,
+        TIP_WAIT_MS,
+    };
+    use crate::ws::WsMessage;
+    use crate::{
+        handle_request, is_loopback, parse_ipc_request, parse_tx_data_string, read_ipc_frame,
+        serve_ipc_socket, write_ipc_frame, IPCMessageReq, IPCMessageResp,
+    };
+    use lib_chain::block::{
+        BlockNode, BlockTree, MinerSignature, SignatureAlgorithm, Transaction, TxOutput,
+    };
+    use lib_chain::checkpoint::Checkpoint;
+    use lib_chain::clock::clock;
+    use lib_chain::consensus_params::ConsensusParams;
+    use lib_chain::schema::{self, check_published_schema, schema_json};
+    use lib_chain::txtrace::TxSpan;
+    use lib_miner::miner::Miner;
+    use lib_network::identity::{node_id_of, NodeIdentity};
+    use lib_network::netchannel::NetAddress;
+    use lib_network::p2pnetwork::P2PNetwork;
+    use lib_tx_pool::pool::{TxPool, TxRejectReason};
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpStream;
+    use std::sync::{Arc, Mutex, RwLock};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    const ALICE: &str =
+        "MDgCMQCqrJ1yIJ7cDQIdTuS+4CkKn/tQPN7bZFbbGCBhvjQxs71f6Vu+sD9eh8JGpfiZSckCAwEAAQ==";
+    const BOB: &str =
+        "MDgCMQDZDExOs97sRTnQLYtgFjDKpDzmO7Uo5HPP62u6MDimXBpZtGxtwa8dhJe5NBIsJjUCAwEAAQ==";
+
+    /// Build the data_string exactly as bin_client does before sending it to the wallet.
+    fn data_string(sender: &str, receiver: &str, message: &str) -> String {
+        serde_json::to_string(&(sender, receiver, message)).unwrap()
+    }
+
+    fn assert_round_trip(message: &str) {
+        let tx = parse_tx_data_string(&data_string(ALICE, BOB, message), "sig".to_string())
+            .unwrap_or_else(|e| panic!("Failed to parse message {:?}: {}", message, e));
+        assert_eq!(tx.sender, ALICE);
+        assert_eq!(tx.receiver, BOB);
+        assert_eq!(tx.message, message);
+        assert_eq!(tx.sig, "sig");
+        // the signature is checked against the same bytes
+        assert_eq!(tx.signed_data(), data_string(ALICE, BOB, message));
+    }
+
+    /// The data_string produced by the client is parsed back into the original triple.
+    #[test]
+    fn test_parse_tx_data_string_basic() {
+        assert_round_trip("SEND $300   // By Alice   // 1678250102871");
+    }
+
+    /// Messages containing json metacharacters must not break the parsing.
+    #[test]
+    fn test_parse_tx_data_string_adversarial() {
+        let messages = [
+            "",
+            "\"",
+            "SEND $10 // \"quoted\" name",
+            "SEND $10 // \",\"",
+            "SEND $10 // \"]",
+            "[\"a\",\"b\",\"c\"]",
+            "back\\slash\\",
+            "new\nline\r\ttab",
+            "SEND $10 // 你好 🚀",
+            "\u{0}\u{1f}\u{7f}",
+        ];
+        for message in messages {
+            assert_round_trip(message);
+        }
+    }
+
+    /// Fuzz the parser with pseudo-random messages drawn from a hostile alphabet.
+    #[test]
+    fn test_parse_tx_data_string_fuzz() {
+        let alphabet: Vec<char> = "\"\\,[]{}:$/ \n\t\u{0}aZ9é🚀".chars().collect();
+        let mut state: u64 = 3235;
+        for _ in 0..2000 {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            let len = (state >> 58) as usize;
+            let message: String = (0..len)
+                .map(|_| {
+                    state = state
+                        .wrapping_mul(6364136223846793005)
+                        .wrapping_add(1442695040888963407);
+                    alphabet[(state >> 33) as usize % alphabet.len()]
+                })
+                .collect();
+            assert_round_trip(&message);
+        }
+    }
+
+    /// A data_string from a hashed sender address carries the sender public key as a fourth field.
+    #[test]
+    fn test_parse_tx_data_string_sender_key() {
+        let keyed_data_string = serde_json::to_string(&("addr", BOB, "SEND $1", ALICE)).unwrap();
+        let tx = parse_tx_data_string(&keyed_data_string, "sig".to_string()).unwrap();
+        assert_eq!(tx.sender, "addr");
+        assert_eq!(tx.sender_pub_key.as_deref(), Some(ALICE));
+        assert_eq!(tx.signed_data(), keyed_data_string);
+        assert!(
+            parse_tx_data_string(&data_string(ALICE, BOB, "SEND $1"), "sig".to_string())
+                .unwrap()
+                .sender_pub_key
+                .is_none()
+        );
+    }
+
+    /// A data_string with a fee carries it as a fifth field, after the (possibly empty) sender public key.
+    #[test]
+    fn test_parse_tx_data_string_fee() {
+        let fee_data_string = serde_json::to_string(&(ALICE, BOB, "SEND $1", "", "3")).unwrap();
+        let tx = parse_tx_data_string(&fee_data_string, "sig".to_string()).unwrap();
+        assert_eq!(tx.fee, Some(3));
+        assert!(tx.sender_pub_key.is_none());
+        assert_eq!(tx.signed_data(), fee_data_string);
+        assert_eq!(
+            parse_tx_data_string(&data_string(ALICE, BOB, "SEND $1"), "sig".to_string())
+                .unwrap()
+                .get_fee(),
+            0
+        );
+    }
+
+    /// Anything that is not a json triple (or quadruple, or quintuple with a fee) of strings is rejected instead of panicking.
+    #[test]
+    fn test_parse_tx_data_string_malformed() {
+        let inputs = [
+            "",
+            "[",
+            "[\"a\",\"b\"]",
+            "[\"a\",\"b\",\"c\",\"d\",\"e\"]",
+            "[\"a\",\"b\",\"c\",\"d\",\"1\",\"f\"]",
+            "[\"a\",\"b\",3]",
+            "{\"sender\":\"a\"}",
+            "\"a\",\"b\",\"c\"",
+        ];
+        for input in inputs {
+            assert!(parse_tx_data_string(input, "sig".to_string()).is_err());
+        }
+    }
+
+    fn unsigned_tx(sender: &str, receiver: &str, message: &str) -> Transaction {
+        Transaction::new(
+            sender.to_string(),
+            receiver.to_string(),
+            message.to_string(),
+            String::new(),
+        )
+    }
+
+    fn put_in_pool(tx_pool: &mut TxPool, tx: Transaction) {
+        tx_pool.pool_tx_ids.push(tx.gen_hash());
+        tx_pool.pool_tx_map.insert(tx.gen_hash(), tx);
+    }
+
+    /// A transaction within the sender's balance is accepted; the simulation leaves the pool untouched.
+    #[test]
+    fn test_simulate_tx_accepted() {
+        let chain = BlockTree::new();
+        let tx_pool = TxPool::new();
+        let tx = unsigned_tx(ALICE, BOB, "SEND $300   // By Alice   // 1678250102871");
+        assert_eq!(simulate_tx_against(&chain, &tx_pool, &tx), Ok(()));
+        assert!(tx_pool.pool_tx_ids.is_empty());
+    }
+
+    /// Missing, zero and negative amounts are rejected.
+    #[test]
+    fn test_simulate_tx_invalid_amount() {
+        let chain = BlockTree::new();
+        let tx_pool = TxPool::new();
+        for message in ["SEND 300", "SEND $0", "SEND $-5", "SEND $abc", ""] {
+            let tx = unsigned_tx(ALICE, BOB, message);
+            assert_eq!(
+                simulate_tx_against(&chain, &tx_pool, &tx),
+                Err(TxRejectReason::InvalidAmount)
+            );
+        }
+    }
+
+    /// A message longer than the consensus params allow is rejected.
+    #[test]
+    fn test_simulate_tx_message_too_long() {
+        let mut chain = BlockTree::new();
+        chain
+            .set_consensus_params(ConsensusParams {
+                max_message_len: 16,
+                ..ConsensusParams::default()
+            })
+            .unwrap();
+        let tx_pool = TxPool::new();
+        let tx = unsigned_tx(ALICE, BOB, "SEND $300   // By Alice");
+        assert_eq!(
+            simulate_tx_against(&chain, &tx_pool, &tx),
+            Err(TxRejectReason::MessageTooLong(23, 16))
+        );
+    }
+
+    /// The sender must afford the amount, including what it already committed in the tx pool.
+    #[test]
+    fn test_simulate_tx_insufficient_balance() {
+        let chain = BlockTree::new();
+        let mut tx_pool = TxPool::new();
+        let tx = unsigned_tx(BOB, ALICE, "SEND $1   // By Bob   // 1");
+        assert_eq!(
+            simulate_tx_against(&chain, &tx_pool, &tx),
+            Err(TxRejectReason::InsufficientBalance(0, 1))
+        );
+
+        put_in_pool(
+            &mut tx_pool,
+            unsigned_tx(ALICE, BOB, "SEND $299792000   // By Alice   // 1"),
+        );
+        let tx = unsigned_tx(ALICE, BOB, "SEND $458   // By Alice   // 2");
+        assert_eq!(simulate_tx_against(&chain, &tx_pool, &tx), Ok(()));
+        let tx = unsigned_tx(ALICE, BOB, "SEND $459   // By Alice   // 2");
+        assert_eq!(
+            simulate_tx_against(&chain, &tx_pool, &tx),
+            Err(TxRejectReason::InsufficientBalance(458, 459))
+        );
+    }
+
+    /// The sender must afford the fees as well, and the fee must reach the minimum relay fee.
+    #[test]
+    fn test_simulate_tx_fee() {
+        let chain = BlockTree::new();
+        let mut tx_pool = TxPool::new();
+        let with_fee = |message: &str, fee: i64| Transaction {
+            fee: Some(fee),
+            ..unsigned_tx(ALICE, BOB, message)
+        };
+        assert_eq!(
+            simulate_tx_against(&chain, &tx_pool, &with_fee("SEND $1   // 1", -1)),
+            Err(TxRejectReason::FeeTooLow(-1, 0))
+        );
+        tx_pool.min_relay_fee = 2;
+        assert_eq!(
+            simulate_tx_against(&chain, &tx_pool, &unsigned_tx(ALICE, BOB, "SEND $1   // 1")),
+            Err(TxRejectReason::FeeTooLow(0, 2))
+        );
+
+        put_in_pool(&mut tx_pool, with_fee("SEND $299792000   // 1", 8));
+        assert_eq!(
+            simulate_tx_against(&chain, &tx_pool, &with_fee("SEND $448   // 2", 2)),
+            Ok(())
+        );
+        assert_eq!(
+            simulate_tx_against(&chain, &tx_pool, &with_fee("SEND $448   // 2", 3)),
+            Err(TxRejectReason::InsufficientBalance(450, 451))
+        );
+    }
+
+    /// A batch must afford all its payments, its outputs must be positive and within the consensus params, and it is only
+    /// a duplicate of a batch with the same outputs.
+    #[test]
+    fn test_simulate_tx_batch() {
+        let chain = BlockTree::new();
+        let mut tx_pool = TxPool::new();
+        let batch = |amounts: &[i64]| Transaction {
+            outputs: amounts
+                .iter()
+                .map(|amount| TxOutput {
+                    receiver: format!("CAROL{}", amount),
+                    amount: *amount,
+                })
+                .collect(),
+            ..unsigned_tx(ALICE, BOB, "SEND $299792000   // By Alice   // 1")
+        };
+        assert_eq!(
+            simulate_tx_against(&chain, &tx_pool, &batch(&[458])),
+            Ok(())
+        );
+        assert_eq!(
+            simulate_tx_against(&chain, &tx_pool, &batch(&[400, 59])),
+            Err(TxRejectReason::InsufficientBalance(299792458, 299792459))
+        );
+        assert_eq!(
+            simulate_tx_against(&chain, &tx_pool, &batch(&[1, 0])),
+            Err(TxRejectReason::InvalidAmount)
+        );
+        // a total that overflows is not an amount the sender could afford
+        assert_eq!(
+            simulate_tx_against(&chain, &tx_pool, &batch(&[i64::MAX])),
+            Err(TxRejectReason::InvalidAmount)
+        );
+        assert_eq!(
+            simulate_tx_against(&chain, &tx_pool, &batch(&[1; 17])),
+            Err(TxRejectReason::TooManyOutputs(17, 16))
+        );
+
+        put_in_pool(&mut tx_pool, batch(&[200]));
+        assert_eq!(
+            simulate_tx_against(&chain, &tx_pool, &batch(&[200])),
+            Err(TxRejectReason::Duplicate)
+        );
+        // the pool already commits 299792200 of the 299792458
+        assert_eq!(
+            simulate_tx_against(
+                &chain,
+                &tx_pool,
+                &unsigned_tx(ALICE, BOB, "SEND $259   // 2")
+            ),
+            Err(TxRejectReason::InsufficientBalance(258, 259))
+        );
+    }
+
+    /// A transaction identical to one already in the tx pool is a duplicate.
+    #[test]
+    fn test_simulate_tx_duplicate() {
+        let chain = BlockTree::new();
+        let mut tx_pool = TxPool::new();
+        let tx = unsigned_tx(ALICE, BOB, "SEND $300   // By Alice   // 1678250102871");
+        put_in_pool(&mut tx_pool, tx.clone());
+        assert_eq!(
+            simulate_tx_against(&chain, &tx_pool, &tx),
+            Err(TxRejectReason::Duplicate)
+        );
+    }
+
+    /// The history of an address lists its txs in the tx pool, then the ones of the longest chain with their
+    /// confirmations, the most recent first.
+    #[test]
+    fn test_address_history() {
+        let mut chain = BlockTree::new();
+        mine_block(&mut chain, BOB, 0);
+        mine_block(&mut chain, BOB, 1);
+        let mut tx_pool = TxPool::new();
+        put_in_pool(
+            &mut tx_pool,
+            unsigned_tx(ALICE, BOB, "SEND $300   // By Alice   // 1678250102871"),
+        );
+
+        let history = address_history(&chain, &tx_pool, ALICE);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].direction, TxDirection::Sent);
+        assert_eq!(history[0].counterparty, BOB);
+        assert_eq!(history[0].amount, 300);
+        assert_eq!((history[0].confirmations, history[0].finalized), (0, false));
+        // the genesis allocation, 2 blocks below the working block
+        assert_eq!(history[1].direction, TxDirection::Received);
+        assert_eq!(history[1].counterparty, "GENESIS");
+        assert_eq!(history[1].amount, 299792458);
+        assert_eq!((history[1].confirmations, history[1].finalized), (3, true));
+
+        let history = address_history(&chain, &tx_pool, BOB);
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].direction, TxDirection::Received);
+        assert_eq!(history[0].counterparty, ALICE);
+        assert!(address_history(&chain, &TxPool::new(), BOB).is_empty());
+
+        // a batch is listed with all its payments for its sender, and with the ones to them for its other receivers
+        let batch = Transaction {
+            outputs: vec![TxOutput {
+                receiver: "CAROL".to_string(),
+                amount: 20,
+            }],
+            ..unsigned_tx(ALICE, BOB, "SEND $10   // By Alice   // 2")
         };
-        let output = serde_json::to_string(&response).unwrap();
-        println!("{}", output);
+        put_in_pool(&mut tx_pool, batch);
+        assert_eq!(address_history(&chain, &tx_pool, ALICE)[0].amount, 30);
+        let history = address_history(&chain, &tx_pool, "CAROL");
+        assert_eq!(history.len(), 1);
+        assert_eq!(
+            (history[0].direction, history[0].amount),
+            (TxDirection::Received, 20)
+        );
+    }
+
+    fn config_json(extra_fields: &str) -> String {
+        format!(
+            r#"{{"neighbors": [], "addr": {{"ip": "127.0.0.1", "port": 9000}},
+                "miner_thread_count": 2, "nonce_len": 16, "difficulty_leading_zero_len": 3,
+                "difficulty_leading_zero_len_acc": 3, "miner_thread_0_seed": 0,
+                "mining_reward_receiver": "{}", "max_tx_in_one_block": 16{}}}"#,
+            BOB, extra_fields
+        )
+    }
+
+    /// The published schema of Config is up to date, and an invalid config is reported with the path of the field at fault.
+    #[test]
+    fn test_config_schema() {
+        check_published_schema("Config", &schema_json::<Config>()).unwrap();
+        let e = schema::from_json_str::<Config>(
+            "Config",
+            &config_json(r#", "consensus": {"mining_reward": "10"}"#),
+        )
+        .unwrap_err();
+        assert_eq!(
+            e,
+            "Invalid Config at `consensus.mining_reward`: invalid type: string \"10\", expected i64"
+        );
+        let e = schema::from_json_str::<Config>(
+            "Config",
+            &config_json("").replace(r#""nonce_len": 16,"#, ""),
+        )
+        .unwrap_err();
+        assert_eq!(e, "Invalid Config: missing field `nonce_len`");
+    }
+
+    /// The transactions never expire and the oldest one is evicted by default (configs without the fields).
+    #[test]
+    fn test_config_tx_expiry() {
+        let status = serde_json::from_str::<Config>(&config_json(""))
+            .unwrap()
+            .get_status();
+        assert_eq!(status["tx_ttl_ms"], "none");
+        assert_eq!(status["tx_eviction_policy"], "OldestFirst");
+        let status = serde_json::from_str::<Config>(&config_json(
+            r#", "tx_ttl_ms": 60000, "tx_eviction_policy": "LowestFeeFirst""#,
+        ))
+        .unwrap()
+        .get_status();
+        assert_eq!(status["tx_ttl_ms"], "60000");
+        assert_eq!(status["tx_eviction_policy"], "LowestFeeFirst");
+    }
+
+    /// Empty blocks are mined by default (configs without the flag), otherwise only after the max wait.
+    #[test]
+    fn test_config_should_mine_empty_block() {
+        let config: Config = serde_json::from_str(&config_json("")).unwrap();
+        assert!(config.should_mine_empty_block(0));
+
+        let config: Config =
+            serde_json::from_str(&config_json(r#", "mine_empty_blocks": false"#)).unwrap();
+        assert!(!config.should_mine_empty_block(0));
+        assert!(!config.should_mine_empty_block(u64::MAX));
+
+        let config: Config = serde_json::from_str(&config_json(
+            r#", "mine_empty_blocks": false, "empty_block_max_wait_ms": 5000"#,
+        ))
+        .unwrap();
+        assert!(!config.should_mine_empty_block(4999));
+        assert!(config.should_mine_empty_block(5000));
+    }
+
+    /// A puzzle on an empty tx pool builds an empty block on top of the working block instead of panicking.
+    #[test]
+    fn test_create_puzzle_empty_pool() {
+        let chain_p = Arc::new(RwLock::new(BlockTree::new()));
+        let tx_pool_p = Arc::new(Mutex::new(TxPool::new()));
+        let (puzzle_str, block) = create_puzzle(
+            chain_p,
+            tx_pool_p,
+            16,
+            BOB.to_string(),
+            clock().now_ms(),
+            None,
+        );
+
+        assert!(block.transactions_block.transactions.is_empty());
+        assert_eq!(block.header.parent, "0");
+        assert_eq!(block.header.reward_receiver, BOB);
+        assert!(block.header.timestamp >= 1);
+        assert_eq!(
+            block.transactions_block.merkle_tree.hashes.last().unwrap()[0],
+            block.header.merkle_root
+        );
+        assert!(puzzle_str.contains(&block.header.merkle_root));
+    }
+
+    /// A block mined at difficulty 2 is accepted by a node that mines at a higher difficulty
+    /// but accepts difficulty 2, and only by nodes whose acceptance difficulty it meets.
+    #[test]
+    fn test_acceptance_difficulty_split() {
+        let chain_p = Arc::new(RwLock::new(BlockTree::new()));
+        let tx_pool_p = Arc::new(Mutex::new(TxPool::new()));
+        let (puzzle, mut block) = create_puzzle(
+            chain_p,
+            tx_pool_p,
+            16,
+            BOB.to_string(),
+            clock().now_ms(),
+            None,
+        );
+        let solution = Miner::solve_puzzle(
+            Arc::new(Mutex::new(Miner::new())),
+            puzzle,
+            16,
+            2,
+            1,
+            0,
+            Arc::new(RwLock::new(false)),
+        )
+        .unwrap();
+        block.header.nonce = solution.nonce;
+        block.header.block_id = solution.hash;
+
+        let mut lenient_node = BlockTree::new();
+        assert!(lenient_node.add_block(block.clone(), 2).is_ok());
+        assert_eq!(lenient_node.working_block_id, block.header.block_id);
+
+        let mut strict_node = BlockTree::new();
+        assert_eq!(
+            strict_node.add_block(block.clone(), 4).is_ok(),
+            block.header.block_id.starts_with("0000")
+        );
+    }
+
+    /// Only the runtime-changeable parameters can be updated, and the mining difficulty stays at or above the acceptance difficulty.
+    #[test]
+    fn test_config_set_runtime_param() {
+        let mut config: Config = serde_json::from_str(&config_json("")).unwrap();
+        assert!(config
+            .set_runtime_param("difficulty_leading_zero_len", "5")
+            .is_ok());
+        assert_eq!(config.difficulty_leading_zero_len, 5);
+        assert!(config
+            .set_runtime_param("difficulty_leading_zero_len", "2")
+            .is_err());
+        assert!(config.set_runtime_param("miner_thread_count", "0").is_err());
+        assert!(config
+            .set_runtime_param("miner_target_hashrate", "0")
+            .is_err());
+        assert!(config
+            .set_runtime_param("miner_target_hashrate", "1000")
+            .is_ok());
+        assert_eq!(config.miner_target_hashrate, Some(1000));
+        assert!(config
+            .set_runtime_param("max_tx_in_one_block", "many")
+            .is_err());
+        assert!(config
+            .set_runtime_param("mine_empty_blocks", "false")
+            .is_ok());
+        assert!(config
+            .set_runtime_param("empty_block_max_wait_ms", "3000")
+            .is_ok());
+        assert!(!config.should_mine_empty_block(2999));
+        assert!(config.should_mine_empty_block(3000));
+        assert!(config
+            .set_runtime_param("empty_block_max_wait_ms", "none")
+            .is_ok());
+        assert_eq!(config.get_status()["empty_block_max_wait_ms"], "none");
+        assert!(config.set_runtime_param("announce_tips", "yes").is_err());
+        assert!(config.set_runtime_param("announce_tips", "true").is_ok());
+        assert_eq!(config.get_status()["announce_tips"], "true");
+        assert!(config.set_runtime_param("nonce_len", "8").is_err());
+        assert_eq!(config.nonce_len, 16);
+    }
+
+    /// The automatic difficulty follows the average of the last solve times, one leading zero at a time,
+    /// and never goes below the acceptance difficulty.
+    #[test]
+    fn test_auto_difficulty() {
+        let mut config: Config = serde_json::from_str(&config_json("")).unwrap();
+        let mut auto_difficulty = AutoDifficulty::default();
+        assert!(auto_difficulty.record_solve(1, &config).is_none());
+
+        config
+            .set_runtime_param("target_block_interval_ms", "10000")
+            .unwrap();
+        assert!(config
+            .set_runtime_param("target_block_interval_ms", "0")
+            .is_err());
+        let acc = config.difficulty_leading_zero_len_acc;
+        config.difficulty_leading_zero_len = acc + 1;
+        // Solving 5 times too fast raises the difficulty once the window is full
+        for _ in 1..AUTO_DIFFICULTY_WINDOW {
+            assert!(auto_difficulty.record_solve(2000, &config).is_none());
+        }
+        assert_eq!(auto_difficulty.record_solve(2000, &config), Some(acc + 2));
+        // Solve times within 4 times the target keep the difficulty
+        config.difficulty_leading_zero_len = acc + 2;
+        for _ in 0..AUTO_DIFFICULTY_WINDOW * 2 {
+            assert!(auto_difficulty.record_solve(30000, &config).is_none());
+        }
+        // Solving too slowly lowers it, down to the acceptance difficulty only
+        let mut lowered = None;
+        for _ in 0..AUTO_DIFFICULTY_WINDOW {
+            lowered = auto_difficulty.record_solve(90000, &config);
+        }
+        assert_eq!(lowered, Some(acc + 1));
+        config.difficulty_leading_zero_len = acc;
+        for _ in 0..AUTO_DIFFICULTY_WINDOW * 2 {
+            assert!(auto_difficulty.record_solve(90000, &config).is_none());
+        }
+        assert_eq!(config.get_status()["target_block_interval_ms"], "10000");
+    }
+
+    /// The traced transactions get their Included span from the TxConfirmed events, and their Finalized span (once) when
+    /// their block is finalized; the other transactions are not traced.
+    #[test]
+    fn test_tx_tracer() {
+        let mut tracer = TxTracer::default();
+        let admitted = tracer.on_admitted("tx_a".to_string(), "id_a".to_string(), 1000);
+        assert_eq!(
+            (admitted.tx_id.as_str(), admitted.span, admitted.at_ms),
+            ("id_a", TxSpan::PoolAdmitted, 1000)
+        );
+        let confirmed = |data_string: &str| {
+            ChainEvent::TxConfirmed(data_string.to_string(), "block_1".to_string())
+        };
+        assert!(tracer.on_event(&confirmed("tx_b"), 2000).is_empty());
+        let included = tracer.on_event(&confirmed("tx_a"), 2000);
+        assert_eq!(included.len(), 1);
+        assert_eq!(
+            (included[0].span, included[0].detail.as_str()),
+            (TxSpan::Included, "block block_1")
+        );
+        let finalize = ChainEvent::NewBlockFinalized("block_1".to_string(), 1);
+        let finalized = tracer.on_event(&finalize, 3000);
+        assert_eq!(finalized.len(), 1);
+        assert_eq!(
+            (finalized[0].tx_id.as_str(), finalized[0].span),
+            ("id_a", TxSpan::Finalized)
+        );
+        assert!(tracer.on_event(&finalize, 3000).is_empty());
+        assert!(tracer.on_event(&confirmed("tx_a"), 4000).is_empty());
+    }
+
+    /// A tip with more work than the working block makes the miner wait for its body, until the body arrives or for
+    /// `TIP_WAIT_MS` at most; the tips with no more work and the blocks already in the chain are ignored.
+    #[test]
+    fn test_tip_watch() {
+        let mut chain = BlockTree::new();
+        let mut tip_watch = TipWatch::default();
+        let block = mine_block(&mut chain.clone(), ALICE, 1);
+        let work = block.work();
+        assert!(!tip_watch.should_wait(&chain, 0));
+        assert!(tip_watch.on_tip(&chain, &block.header.block_id, work, 1000));
+        assert!(tip_watch.should_wait(&chain, 1000 + TIP_WAIT_MS - 1));
+        assert!(!tip_watch.should_wait(&chain, 1000 + TIP_WAIT_MS));
+        assert!(!tip_watch.should_wait(&chain, 1000));
+
+        assert!(tip_watch.on_tip(&chain, &block.header.block_id, work, 1000));
+        chain.add_block(block.clone(), 1).unwrap();
+        assert!(!tip_watch.should_wait(&chain, 1000));
+        assert!(!tip_watch.on_tip(&chain, &block.header.block_id, work, 1000));
+        assert!(!tip_watch.on_tip(&chain, "competing_block", work, 1000));
+        assert!(tip_watch.on_tip(&chain, "longer_branch_tip", work + 1, 1000));
+        tip_watch.on_announced();
+        let status = tip_watch.get_status();
+        assert_eq!(status.tips_announced, 1);
+        assert_eq!(status.tips_received, 5);
+        assert_eq!(status.puzzles_cancelled_by_tips, 3);
+    }
+
+    /// Frames are read one per line: blank lines are skipped, and an oversized frame is reported and skipped whole.
+    #[test]
+    fn test_read_ipc_frame() {
+        let input = format!(
+            "\n  \n{}\n{}\n\"Quit\"\nnot json\n\"RequestConfig\"",
+            r#""RequestChainStatus""#,
+            "x".repeat(50)
+        );
+        let mut reader = BufReader::new(input.as_bytes());
+        let mut next = || read_ipc_frame(&mut reader, 20).map(parse_ipc_request);
+        assert!(matches!(
+            next(),
+            Some(Ok(IPCMessageReq::RequestChainStatus))
+        ));
+        assert_eq!(
+            next().unwrap().unwrap_err(),
+            "Frame of 51 bytes exceeds the max frame size (20 bytes)"
+        );
+        assert!(matches!(next(), Some(Ok(IPCMessageReq::Quit))));
+        assert!(next()
+            .unwrap()
+            .unwrap_err()
+            .starts_with("Malformed request"));
+        assert!(matches!(next(), Some(Ok(IPCMessageReq::RequestConfig))));
+        assert!(next().is_none());
+    }
+
+    /// Anything but a single-line IPC response is refused on stdout (in debug builds).
+    #[test]
+    #[should_panic(expected = "Not an IPC response frame")]
+    fn test_write_ipc_frame_stray_output() {
+        write_ipc_frame(r#"{"Notify":"ok"}"#);
+        write_ipc_frame("Thread 0 cancelled");
+    }
+
+    /// A state whose longest chain disagrees with a checkpoint of the config is refused.
+    #[test]
+    #[should_panic(expected = "contradicts the checkpoints")]
+    fn test_checkpoint_contradicted_by_state() {
+        let config = config_json(r#", "checkpoints": [[0, "not the genesis block"]]"#);
+        Nakamoto::create_nakamoto(
+            serde_json::to_string(&BlockTree::new()).unwrap(),
+            serde_json::to_string(&TxPool::new()).unwrap(),
+            config,
+        );
+    }
+
+    /// A node configured not to mine on start only mines between StartMiner and StopMiner.
+    #[test]
+    fn test_start_stop_miner() {
+        let mut config: serde_json::Value =
+            serde_json::from_str(&config_json(r#", "mine_on_start": false"#)).unwrap();
+        config["addr"]["port"] = 9071.into();
+        let nakamoto = Nakamoto::create_nakamoto(
+            serde_json::to_string(&BlockTree::new()).unwrap(),
+            serde_json::to_string(&TxPool::new()).unwrap(),
+            config.to_string(),
+        );
+        let depth = || nakamoto.get_chain_status().height;
+        thread::sleep(Duration::from_millis(300));
+        assert_eq!(depth(), 0);
+        assert!(!nakamoto.get_miner_status().is_enabled);
+
+        nakamoto.start_miner();
+        let started = Instant::now();
+        while depth() < 2 {
+            assert!(
+                started.elapsed() < Duration::from_secs(20),
+                "no blocks mined"
+            );
+            thread::sleep(Duration::from_millis(20));
+        }
+        nakamoto.stop_miner();
+        thread::sleep(Duration::from_millis(200));
+        let stopped_depth = depth();
+        thread::sleep(Duration::from_millis(500));
+        assert_eq!(depth(), stopped_depth);
+    }
+
+    /// With a retarget interval in the consensus params, the miner mines at the difficulty the chain expects, which rises
+    /// above the configured one when the blocks come faster than the target.
+    #[test]
+    fn test_consensus_retarget_mining() {
+        let mut config: serde_json::Value = serde_json::from_str(&config_json(
+            r#", "consensus": {"retarget_interval": 2, "target_block_time_ms": 1000000}"#,
+        ))
+        .unwrap();
+        config["addr"]["port"] = 9211.into();
+        config["difficulty_leading_zero_len"] = 1.into();
+        config["difficulty_leading_zero_len_acc"] = 1.into();
+        let nakamoto = Nakamoto::create_nakamoto(
+            serde_json::to_string(&BlockTree::new()).unwrap(),
+            serde_json::to_string(&TxPool::new()).unwrap(),
+            config.to_string(),
+        );
+        let started = Instant::now();
+        while nakamoto.get_chain_status().height < 5 {
+            assert!(
+                started.elapsed() < Duration::from_secs(60),
+                "no blocks mined"
+            );
+            thread::sleep(Duration::from_millis(20));
+        }
+        nakamoto.stop_miner();
+
+        let chain = nakamoto.chain_p.read().unwrap();
+        let main_chain: Vec<&BlockNode> = chain.iter_main_chain().skip(1).collect();
+        for block in main_chain.iter() {
+            let expected = chain.expected_difficulty(&block.header.parent, 1).unwrap();
+            assert!(block.leading_zero_len() >= expected as u32);
+        }
+        // Heights 3 and 4 follow the fast first period, heights 5 and 6 the fast second one
+        assert_eq!(
+            chain.expected_difficulty(&main_chain[1].header.block_id, 1),
+            Some(2)
+        );
+        assert_eq!(
+            chain.expected_difficulty(&main_chain[3].header.block_id, 1),
+            Some(3)
+        );
+    }
+
+    /// The whole pipeline at difficulty 1: a signed transaction published to a node goes through the tx pool into a block
+    /// mined on the working chain, which validates, and the balances follow it. The node has no neighbors and only listens
+    /// on 127.0.0.1, so nothing leaves the machine.
+    #[test]
+    fn test_mining_pipeline() {
+        let receiver =
+            "MDgCMQDOpK8YWmcg8ffNF/O7xlBDq/DBdoUnc4yyWrV0y/X3LF+dddjaGksXzGl3tHskpgkCAwEAAQ==";
+        let tx = Transaction {
+            sender: ALICE.to_string(),
+            receiver: receiver.to_string(),
+            message: "SEND $300   // By Alice   // 1678250102871".to_string(),
+            sig: "l8gsKxmAUzhgqbVqGlXaO69+Qhr87QthvZjUbYZXvnb+tanxCi8wm3c5UjHZ+HKm".to_string(),
+            sender_pub_key: None,
+            fee: None,
+            algorithm: SignatureAlgorithm::Rsa,
+            outputs: vec![],
+        };
+        let mut config: serde_json::Value =
+            serde_json::from_str(&config_json(r#", "listen_localhost_only": true"#)).unwrap();
+        config["addr"]["port"] = 9212.into();
+        config["difficulty_leading_zero_len"] = 1.into();
+        config["difficulty_leading_zero_len_acc"] = 1.into();
+        let mut nakamoto = Nakamoto::create_nakamoto(
+            serde_json::to_string(&BlockTree::new()).unwrap(),
+            serde_json::to_string(&TxPool::new()).unwrap(),
+            config.to_string(),
+        );
+        let mut forged_tx = tx.clone();
+        forged_tx.message = "SEND $3000   // By Alice   // 1678250102871".to_string();
+        assert!(nakamoto.publish_tx(forged_tx).is_err());
+        nakamoto.publish_tx(tx.clone()).unwrap();
+
+        let started = Instant::now();
+        while !nakamoto.is_tx_on_working_chain(&tx) {
+            assert!(
+                started.elapsed() < Duration::from_secs(20),
+                "the transaction was not mined"
+            );
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(
+            nakamoto.get_balance(receiver, BalanceBranch::WorkingTip),
+            300
+        );
+        // It leaves the tx pool once its block is followed by enough blocks to be finalized
+        while !nakamoto.is_tx_finalized(&tx) {
+            assert!(
+                started.elapsed() < Duration::from_secs(40),
+                "the transaction was not finalized"
+            );
+            thread::sleep(Duration::from_millis(20));
+        }
+        nakamoto.stop_miner();
+
+        let chain = nakamoto.chain_p.read().unwrap();
+        let block = chain
+            .iter_main_chain()
+            .find(|block| block.transactions_block.transactions.contains(&tx))
+            .unwrap();
+        assert_eq!(
+            block.validate_block(1),
+            (true, block.header.block_id.clone())
+        );
+        assert_eq!(block.header.reward_receiver, BOB);
+        assert!(chain.audit_supply().is_ok());
+        assert!(chain.check_invariants().is_ok());
+        drop(chain);
+        assert_eq!(
+            nakamoto.get_balance(receiver, BalanceBranch::WorkingTip),
+            300
+        );
+        assert_eq!(nakamoto.get_txpool_status().tx_count, 0);
+    }
+
+    /// Mine a block on the working block of the chain at difficulty 1, rewarding the given address.
+    fn mine_block(chain: &mut BlockTree, reward_receiver: &str, seed: u64) -> BlockNode {
+        mine_signed_block(chain, reward_receiver, seed, None)
+    }
+
+    /// Mine a block like `mine_block`, signed by the given node identity if any.
+    fn mine_signed_block(
+        chain: &mut BlockTree,
+        reward_receiver: &str,
+        seed: u64,
+        signer: Option<&NodeIdentity>,
+    ) -> BlockNode {
+        let chain_p = Arc::new(RwLock::new(chain.clone()));
+        let tx_pool_p = Arc::new(Mutex::new(TxPool::new()));
+        let (puzzle, mut block) = create_puzzle(
+            chain_p,
+            tx_pool_p,
+            16,
+            reward_receiver.to_string(),
+            clock().now_ms(),
+            signer.map(|identity| identity.pub_key_pem.clone()),
+        );
+        let solution = Miner::solve_puzzle(
+            Arc::new(Mutex::new(Miner::new())),
+            puzzle,
+            16,
+            1,
+            1,
+            seed,
+            Arc::new(RwLock::new(false)),
+        )
+        .unwrap();
+        block.header.nonce = solution.nonce;
+        block.header.block_id = solution.hash;
+        if let Some(identity) = signer {
+            block.header.miner_signature = Some(MinerSignature {
+                pub_key_pem: identity.pub_key_pem.clone(),
+                sig: identity.sign(&block.header.block_id),
+            });
+        }
+        chain.add_block(block.clone(), 1).unwrap();
+        block
+    }
+
+    /// ImportChain merges the valid blocks of another node's state and rejects the tampered ones.
+    #[test]
+    fn test_import_chain() {
+        let mut config: serde_json::Value =
+            serde_json::from_str(&config_json(r#", "mine_on_start": false"#)).unwrap();
+        config["addr"]["port"] = 9081.into();
+        config["difficulty_leading_zero_len_acc"] = 1.into();
+        let mut nakamoto = Some(Nakamoto::create_nakamoto(
+            serde_json::to_string(&BlockTree::new()).unwrap(),
+            serde_json::to_string(&TxPool::new()).unwrap(),
+            config.to_string(),
+        ));
+        let mut foreign_chain = BlockTree::new();
+        mine_block(&mut foreign_chain, ALICE, 0);
+        let tip = mine_block(&mut foreign_chain, ALICE, 1);
+        let mut import = |chain: &BlockTree| {
+            let req = IPCMessageReq::ImportChain(serde_json::to_string(chain).unwrap());
+            handle_request(&mut nakamoto, req)
+        };
+        assert!(matches!(
+            import(&foreign_chain),
+            IPCMessageResp::ChainImported(2, 0, 0)
+        ));
+
+        let mut tampered = mine_block(&mut foreign_chain, BOB, 2);
+        tampered.header.reward_receiver = ALICE.to_string();
+        foreign_chain.all_blocks.remove(&tampered.header.block_id);
+        foreign_chain
+            .orphans
+            .insert(tampered.header.block_id.clone(), tampered);
+        assert!(matches!(
+            import(&foreign_chain),
+            IPCMessageResp::ChainImported(0, 2, 1)
+        ));
+        let nakamoto = nakamoto.unwrap();
+        assert_eq!(
+            nakamoto.get_chain_status().working_block_id,
+            tip.header.block_id
+        );
+        let metadata: serde_json::Value = serde_json::from_str(
+            &nakamoto
+                .get_block_metadata_json(&tip.header.block_id)
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(metadata["source"], "import");
+    }
+
+    /// A new node loads the signed checkpoint of another node instead of replaying its chain, then catches up on the blocks
+    /// after the checkpoint; unsigned, tampered or untrusted checkpoints, and any checkpoint without trusted signers, are
+    /// refused.
+    #[test]
+    fn test_load_checkpoint() {
+        let start = |port: u16, chain: &BlockTree, checkpoint_signers: &[&str]| {
+            let mut config: serde_json::Value =
+                serde_json::from_str(&config_json(r#", "mine_on_start": false"#)).unwrap();
+            config["addr"]["port"] = port.into();
+            config["difficulty_leading_zero_len_acc"] = 1.into();
+            config["checkpoint_signers"] = checkpoint_signers.into();
+            Some(Nakamoto::create_nakamoto(
+                serde_json::to_string(chain).unwrap(),
+                serde_json::to_string(&TxPool::new()).unwrap(),
+                config.to_string(),
+            ))
+        };
+        let mut chain = BlockTree::new();
+        for seed in 0..8 {
+            mine_block(&mut chain, BOB, seed);
+        }
+        let mut old_node = start(9217, &chain, &[]);
+        let checkpoint_json = match handle_request(&mut old_node, IPCMessageReq::RequestCheckpoint)
+        {
+            IPCMessageResp::Checkpoint(checkpoint_json) => checkpoint_json,
+            resp => panic!("unexpected response {:?}", resp),
+        };
+        let checkpoint: Checkpoint = serde_json::from_str(&checkpoint_json).unwrap();
+        assert!(checkpoint.height == 2 && checkpoint.is_signed());
+        let signer = node_id_of(&checkpoint.signature.as_ref().unwrap().pub_key_pem);
+
+        let mut untrusting_node = start(9218, &BlockTree::new(), &["another node"]);
+        let req = IPCMessageReq::LoadCheckpoint(checkpoint_json.clone());
+        assert!(matches!(
+            handle_request(&mut untrusting_node, req),
+            IPCMessageResp::Notify(e) if e.contains("not a checkpoint signer")
+        ));
+        let mut unconfigured_node = start(9223, &BlockTree::new(), &[]);
+        let req = IPCMessageReq::LoadCheckpoint(checkpoint_json.clone());
+        assert!(matches!(
+            handle_request(&mut unconfigured_node, req),
+            IPCMessageResp::Notify(e) if e.contains("No checkpoint signer")
+        ));
+
+        let mut new_node = start(9219, &BlockTree::new(), &[&signer]);
+        let mut tampered = checkpoint.clone();
+        *tampered.finalized_balance_map.get_mut(BOB).unwrap() -= 10;
+        *tampered.finalized_balance_map.get_mut(ALICE).unwrap() += 10;
+        let req = IPCMessageReq::LoadCheckpoint(serde_json::to_string(&tampered).unwrap());
+        assert!(matches!(
+            handle_request(&mut new_node, req),
+            IPCMessageResp::Notify(e) if e.contains("not signed")
+        ));
+        let req = IPCMessageReq::LoadCheckpoint(checkpoint_json);
+        assert!(matches!(
+            handle_request(&mut new_node, req),
+            IPCMessageResp::CheckpointLoaded(2, block_id) if block_id == checkpoint.finalized_block_id
+        ));
+        let new_node_ref = new_node.as_ref().unwrap();
+        assert_eq!(
+            new_node_ref.get_chain_status().root_id,
+            checkpoint.finalized_block_id
+        );
+        let req = IPCMessageReq::GetAddressBalance(BOB.to_string(), BalanceBranch::Finalized);
+        assert!(matches!(
+            handle_request(&mut new_node, req),
+            IPCMessageResp::AddressBalance(_, _, 20)
+        ));
+
+        // the blocks after the checkpoint are then synced as usual
+        let req = IPCMessageReq::ImportChain(serde_json::to_string(&chain).unwrap());
+        handle_request(&mut new_node, req);
+        let new_node = new_node.unwrap();
+        assert_eq!(
+            new_node.get_chain_status().working_block_id,
+            chain.working_block_id
+        );
+        assert!(new_node.audit_chain().is_ok() && new_node.check_chain_invariants().is_ok());
+        assert_eq!(
+            new_node.get_checkpoint().finalized_block_id,
+            chain.finalized_block_id
+        );
+    }
+
+    /// With a tx pool log, the transactions published to a node survive a restart from an empty serialized tx pool.
+    #[test]
+    fn test_tx_pool_log_restart() {
+        let path =
+            std::env::temp_dir().join(format!("nakamoto_tx_pool_log_{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let start = |port: u16| {
+            let mut config: serde_json::Value =
+                serde_json::from_str(&config_json(r#", "mine_on_start": false"#)).unwrap();
+            config["addr"]["port"] = port.into();
+            config["tx_pool_log_path"] = path.display().to_string().into();
+            Some(Nakamoto::create_nakamoto(
+                serde_json::to_string(&BlockTree::new()).unwrap(),
+                serde_json::to_string(&TxPool::new()).unwrap(),
+                config.to_string(),
+            ))
+        };
+        let data_string = serde_json::to_string(&(
+            ALICE,
+            "MDgCMQDOpK8YWmcg8ffNF/O7xlBDq/DBdoUnc4yyWrV0y/X3LF+dddjaGksXzGl3tHskpgkCAwEAAQ==",
+            "SEND $300   // By Alice   // 1678250102871",
+        ))
+        .unwrap();
+        let mut nakamoto = start(9215);
+        let req = IPCMessageReq::PublishTx(
+            data_string,
+            "l8gsKxmAUzhgqbVqGlXaO69+Qhr87QthvZjUbYZXvnb+tanxCi8wm3c5UjHZ+HKm".to_string(),
+        );
+        assert!(matches!(
+            handle_request(&mut nakamoto, req),
+            IPCMessageResp::PublishTxDone
+        ));
+
+        let restarted = start(9216).unwrap();
+        assert_eq!(restarted.get_txpool_status().tx_count, 1);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    /// With a block store, the blocks added to the chain survive a restart from the genesis-only state.
+    #[test]
+    fn test_block_store_restart() {
+        let path =
+            std::env::temp_dir().join(format!("nakamoto_block_store_{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let start = |port: u16| {
+            let mut config: serde_json::Value =
+                serde_json::from_str(&config_json(r#", "mine_on_start": false"#)).unwrap();
+            config["addr"]["port"] = port.into();
+            config["difficulty_leading_zero_len_acc"] = 1.into();
+            config["block_store_path"] = path.display().to_string().into();
+            Some(Nakamoto::create_nakamoto(
+                serde_json::to_string(&BlockTree::new()).unwrap(),
+                serde_json::to_string(&TxPool::new()).unwrap(),
+                config.to_string(),
+            ))
+        };
+        let mut foreign_chain = BlockTree::new();
+        mine_block(&mut foreign_chain, ALICE, 0);
+        let tip = mine_block(&mut foreign_chain, ALICE, 1);
+        let mut nakamoto = start(9111);
+        let req = IPCMessageReq::ImportChain(serde_json::to_string(&foreign_chain).unwrap());
+        assert!(matches!(
+            handle_request(&mut nakamoto, req),
+            IPCMessageResp::ChainImported(2, 0, 0)
+        ));
+
+        let restarted = start(9112).unwrap();
+        assert_eq!(
+            restarted.get_chain_status().working_block_id,
+            tip.header.block_id
+        );
+        let metadata: serde_json::Value = serde_json::from_str(
+            &restarted
+                .get_block_metadata_json(&tip.header.block_id)
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(metadata["source"], "import");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// A node with `max_future_block_time_ms` drops the blocks from the network timestamped too far ahead of its
+    /// network time, and mines its blocks at the network time.
+    #[test]
+    fn test_future_block_dropped() {
+        let mut config: serde_json::Value = serde_json::from_str(&config_json(
+            r#", "mine_on_start": false, "max_future_block_time_ms": 60000"#,
+        ))
+        .unwrap();
+        config["addr"]["port"] = 9203.into();
+        config["difficulty_leading_zero_len_acc"] = 1.into();
+        let nakamoto = Nakamoto::create_nakamoto(
+            serde_json::to_string(&BlockTree::new()).unwrap(),
+            serde_json::to_string(&TxPool::new()).unwrap(),
+            config.to_string(),
+        );
+        assert_eq!(
+            nakamoto.get_config_status()["max_future_block_time_ms"],
+            "60000"
+        );
+        let (_network, _block_in, _tx_in, block_out, _tx_out, _req) = P2PNetwork::create(
+            NetAddress::new("127.0.0.1".to_string(), 9204),
+            vec![NetAddress::new("127.0.0.1".to_string(), 9203)],
+        );
+        thread::sleep(Duration::from_millis(300));
+        // both peers run on this machine, so the network time is the time of the node
+        assert_eq!(nakamoto.get_network_status().time_offset_ms, 0);
+
+        let mut future_block = mine_block(&mut BlockTree::new(), ALICE, 0);
+        future_block.header.timestamp = clock().now_ms() + 3_600_000;
+        let present_block = mine_block(&mut BlockTree::new(), ALICE, 1);
+        block_out.send(future_block.clone()).unwrap();
+        block_out.send(present_block.clone()).unwrap();
+        let started = Instant::now();
+        while nakamoto.get_chain_status().working_block_id != present_block.header.block_id {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert!(!nakamoto
+            .chain_p
+            .read()
+            .unwrap()
+            .all_blocks
+            .contains_key(&future_block.header.block_id));
+    }
+
+    /// A block from the network that becomes the working block cancels the puzzle being solved on the old one, and the
+    /// miner restarts on a puzzle extending the new block.
+    #[test]
+    fn test_received_block_restarts_miner() {
+        let mut config: serde_json::Value = serde_json::from_str(&config_json("")).unwrap();
+        config["addr"]["port"] = 9213.into();
+        // The puzzles are too hard to be solved during the test
+        config["difficulty_leading_zero_len"] = 8.into();
+        config["difficulty_leading_zero_len_acc"] = 1.into();
+        let nakamoto = Nakamoto::create_nakamoto(
+            serde_json::to_string(&BlockTree::new()).unwrap(),
+            serde_json::to_string(&TxPool::new()).unwrap(),
+            config.to_string(),
+        );
+        let (_network, _block_in, _tx_in, block_out, _tx_out, _req) = P2PNetwork::create(
+            NetAddress::new("127.0.0.1".to_string(), 9214),
+            vec![NetAddress::new("127.0.0.1".to_string(), 9213)],
+        );
+        let miner_status = || nakamoto.get_miner_status().miner;
+        let started = Instant::now();
+        while !miner_status().is_running {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(miner_status().cancelled_count, 0);
+
+        let block = mine_block(&mut BlockTree::new(), ALICE, 0);
+        block_out.send(block.clone()).unwrap();
+        while nakamoto.get_chain_status().working_block_id != block.header.block_id
+            || miner_status().cancelled_count != 1
+            || !miner_status().is_running
+        {
+            assert!(
+                started.elapsed() < Duration::from_secs(10),
+                "the miner did not restart on the new block"
+            );
+            thread::sleep(Duration::from_millis(20));
+        }
+        nakamoto.stop_miner();
+    }
+
+    /// A restarted node whose neighbor is down reconnects to the peers saved in its peers file.
+    #[test]
+    fn test_peers_file_restart() {
+        let path = std::env::temp_dir().join(format!("nakamoto_peers_{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let start = |port: u16, neighbor_port: u16, saves_peers: bool| {
+            let mut config: serde_json::Value =
+                serde_json::from_str(&config_json(r#", "mine_on_start": false"#)).unwrap();
+            config["addr"]["port"] = port.into();
+            config["neighbors"] = serde_json::json!([{"ip": "127.0.0.1", "port": neighbor_port}]);
+            if saves_peers {
+                config["peers_path"] = path.display().to_string().into();
+            }
+            Nakamoto::create_nakamoto(
+                serde_json::to_string(&BlockTree::new()).unwrap(),
+                serde_json::to_string(&TxPool::new()).unwrap(),
+                config.to_string(),
+            )
+        };
+        let wait_until = |what: &str, cond: &dyn Fn() -> bool| {
+            let started = Instant::now();
+            while !cond() {
+                assert!(started.elapsed() < Duration::from_secs(10), "{}", what);
+                thread::sleep(Duration::from_millis(100));
+            }
+        };
+        let peer = start(9191, 9192, false);
+        let _first_run = start(9192, 9191, true);
+        wait_until("the peers file was not saved", &|| {
+            lib_network::addrbook::load_peers(&path.display().to_string())
+                .unwrap()
+                .iter()
+                .any(|record| record.address.port == 9191 && record.node_id.is_some())
+        });
+
+        // the configured neighbor (9193) is down, so only the saved peer can be reached
+        let restarted = start(9194, 9193, true);
+        wait_until("the saved peer was not dialed", &|| {
+            let status = restarted.get_network_status();
+            status.outbound_peers == 1 && status.max_outbound_peers == Some(1)
+        });
+        let status = peer.get_network_status();
+        assert_eq!((status.inbound_peers, status.max_inbound_peers), (2, None));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// The subscribers are told about the blocks finalized and the neighbors connected as they happen.
+    #[test]
+    fn test_subscribe_events() {
+        let mut config: serde_json::Value = serde_json::from_str(&config_json(
+            r#", "mine_on_start": false, "consensus": {"finality_depth": 1}"#,
+        ))
+        .unwrap();
+        config["addr"]["port"] = 9101.into();
+        config["difficulty_leading_zero_len_acc"] = 1.into();
+        let nakamoto = Nakamoto::create_nakamoto(
+            serde_json::to_string(&BlockTree::new()).unwrap(),
+            serde_json::to_string(&TxPool::new()).unwrap(),
+            config.to_string(),
+        );
+        let events = nakamoto.subscribe_events();
+        let mut foreign_chain = BlockTree::new();
+        let first = mine_block(&mut foreign_chain, ALICE, 0);
+        let second = mine_block(&mut foreign_chain, ALICE, 1);
+        nakamoto
+            .import_chain(&serde_json::to_string(&foreign_chain).unwrap())
+            .unwrap();
+        assert_eq!(
+            events.try_iter().collect::<Vec<ChainEvent>>(),
+            vec![
+                ChainEvent::NewBlock(first.header.block_id.clone(), 1),
+                ChainEvent::NewBlock(second.header.block_id.clone(), 2),
+                ChainEvent::NewBlockFinalized(first.header.block_id.clone(), 1),
+            ]
+        );
+
+        // A longer branch from the finalized block replaces the second block
+        let mut other_chain = BlockTree::new();
+        other_chain.add_block(first.clone(), 1).unwrap();
+        let other_blocks: Vec<BlockNode> = (10..12)
+            .map(|seed| mine_block(&mut other_chain, BOB, seed))
+            .collect();
+        nakamoto
+            .import_chain(&serde_json::to_string(&other_chain).unwrap())
+            .unwrap();
+        let reorg_events: Vec<ChainEvent> = events.try_iter().collect();
+        assert_eq!(
+            reorg_events.first(),
+            Some(&ChainEvent::Reorg(
+                second.header.block_id,
+                other_blocks[1].header.block_id.clone(),
+                1
+            ))
+        );
+        assert_eq!(
+            reorg_events
+                .iter()
+                .filter(|event| matches!(event, ChainEvent::NewBlock(..)))
+                .count(),
+            2
+        );
+
+        config["addr"]["port"] = 9102.into();
+        config["neighbors"] = serde_json::json!([{"ip": "127.0.0.1", "port": 9101}]);
+        let _neighbor = Nakamoto::create_nakamoto(
+            serde_json::to_string(&BlockTree::new()).unwrap(),
+            serde_json::to_string(&TxPool::new()).unwrap(),
+            config.to_string(),
+        );
+        // The neighbor dialed this node, so it is named after the socket it connected from
+        let Ok(ChainEvent::PeerConnected(peer)) = events.recv_timeout(Duration::from_secs(5))
+        else {
+            panic!("no peer connected");
+        };
+        let status = nakamoto.get_network_status();
+        assert!(status
+            .peers
+            .iter()
+            .any(|status| status.address == peer && status.listen_address == "127.0.0.1:9102"));
+    }
+
+    /// A WebSocket connection gets a status snapshot first, then the events of the node as json text frames.
+    #[test]
+    fn test_ws_event_stream() {
+        let mut config: serde_json::Value =
+            serde_json::from_str(&config_json(r#", "mine_on_start": false"#)).unwrap();
+        config["addr"]["port"] = 9131.into();
+        config["difficulty_leading_zero_len_acc"] = 1.into();
+        let nakamoto = Nakamoto::create_nakamoto(
+            serde_json::to_string(&BlockTree::new()).unwrap(),
+            serde_json::to_string(&TxPool::new()).unwrap(),
+            config.to_string(),
+        );
+        crate::ws::serve_ws(
+            NetAddress {
+                ip: "127.0.0.1".to_string(),
+                port: 9132,
+            },
+            nakamoto.clone(),
+        );
+        let (mut socket, _) = tungstenite::connect("ws://127.0.0.1:9132").unwrap();
+        let mut read_message = || {
+            let frame = socket.read().unwrap();
+            serde_json::from_str::<WsMessage>(frame.to_text().unwrap()).unwrap()
+        };
+        match read_message() {
+            WsMessage::Status(status) => assert_eq!(
+                status.chain.working_block_id,
+                nakamoto.get_chain_status().working_block_id
+            ),
+            message => panic!("Unexpected message {:?}", message),
+        }
+
+        let mut foreign_chain = BlockTree::new();
+        let block = mine_block(&mut foreign_chain, ALICE, 0);
+        nakamoto
+            .import_chain(&serde_json::to_string(&foreign_chain).unwrap())
+            .unwrap();
+        assert_eq!(
+            read_message(),
+            WsMessage::Event(ChainEvent::NewBlock(block.header.block_id, 1))
+        );
+    }
+
+    /// The node API is served over gRPC with the same answers as the IPC, and the finalized blocks are streamed.
+    #[cfg(feature = "grpc")]
+    #[test]
+    fn test_grpc_server() {
+        use crate::grpc::proto::node_client::NodeClient;
+        use crate::grpc::proto::{BalanceRequest, CallRequest, Empty, StatusKind, StatusRequest};
+
+        let mut config: serde_json::Value = serde_json::from_str(&config_json(
+            r#", "mine_on_start": false, "consensus": {"finality_depth": 1}"#,
+        ))
+        .unwrap();
+        config["addr"]["port"] = 9121.into();
+        config["difficulty_leading_zero_len_acc"] = 1.into();
+        config["grpc_admin_token"] = "s3cr3t".into();
+        let nakamoto = Nakamoto::create_nakamoto(
+            serde_json::to_string(&BlockTree::new()).unwrap(),
+            serde_json::to_string(&TxPool::new()).unwrap(),
+            config.to_string(),
+        );
+        crate::grpc::serve_grpc(
+            NetAddress {
+                ip: "127.0.0.1".to_string(),
+                port: 9122,
+            },
+            nakamoto.clone(),
+        );
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            // The server starts in the background
+            let mut client = None;
+            for _ in 0..100 {
+                match NodeClient::connect("http://127.0.0.1:9122").await {
+                    Ok(connected) => {
+                        client = Some(connected);
+                        break;
+                    }
+                    Err(_) => thread::sleep(Duration::from_millis(50)),
+                }
+            }
+            let mut client = client.expect("The gRPC server did not start");
+
+            let status = client
+                .get_status(StatusRequest {
+                    kind: StatusKind::Chain as i32,
+                })
+                .await
+                .unwrap()
+                .into_inner()
+                .status;
+            assert_eq!(
+                status["working_block_id"],
+                nakamoto.get_chain_status().working_block_id
+            );
+            let balance = client
+                .get_balance(BalanceRequest {
+                    user_id: BOB.to_string(),
+                    working_tip: false,
+                })
+                .await
+                .unwrap()
+                .into_inner()
+                .balance;
+            assert_eq!(balance, nakamoto.get_balance(BOB, BalanceBranch::Finalized));
+            // The admin calls need the admin token
+            let call = |request_json: &str, token: &str| {
+                let mut request = tonic::Request::new(CallRequest {
+                    request_json: request_json.to_string(),
+                });
+                if !token.is_empty() {
+                    request.metadata_mut().insert(
+                        "authorization",
+                        format!("Bearer {}", token).parse().unwrap(),
+                    );
+                }
+                request
+            };
+            let refused = client
+                .call(call(r#"{"SetMinerConfig": [3, null]}"#, ""))
+                .await
+                .unwrap_err();
+            assert_eq!(refused.code(), tonic::Code::Unauthenticated);
+            let refused = client
+                .stop_miner(tonic::Request::new(Empty {}))
+                .await
+                .unwrap_err();
+            assert_eq!(refused.code(), tonic::Code::Unauthenticated);
+            assert!(client
+                .call(call(r#"{"SetMinerConfig": [3, null]}"#, "wrong"))
+                .await
+                .is_err());
+            let reply = client
+                .call(call(r#"{"SetMinerConfig": [3, null]}"#, "s3cr3t"))
+                .await
+                .unwrap()
+                .into_inner();
+            assert!(reply.response_json.contains("MinerConfigSet"));
+            let reply = client
+                .call(call(r#""Quit""#, "s3cr3t"))
+                .await
+                .unwrap()
+                .into_inner();
+            assert!(reply.response_json.contains("only accepted on stdin"));
+
+            let mut blocks = client
+                .subscribe_blocks(Empty {})
+                .await
+                .unwrap()
+                .into_inner();
+            let mut foreign_chain = BlockTree::new();
+            let first = mine_block(&mut foreign_chain, ALICE, 0);
+            mine_block(&mut foreign_chain, ALICE, 1);
+            nakamoto
+                .import_chain(&serde_json::to_string(&foreign_chain).unwrap())
+                .unwrap();
+            let block = blocks.message().await.unwrap().unwrap();
+            assert_eq!(block.block_id, first.header.block_id);
+            assert_eq!(block.height, 1);
+        });
+    }
+
+    /// A block signed by its miner is accepted. A block with a signature over another block is rejected, and so is a
+    /// block whose signature was stripped or replaced by the one of another node, since the key is in the block id.
+    #[test]
+    fn test_signed_blocks() {
+        let identity = NodeIdentity::generate();
+        let unsigned_block = mine_block(&mut BlockTree::new(), ALICE, 0);
+        assert!(!serde_json::to_string(&unsigned_block)
+            .unwrap()
+            .contains("miner_signature"));
+        let block = mine_signed_block(&mut BlockTree::new(), ALICE, 0, Some(&identity));
+        assert!(block.validate_block(1).0);
+
+        let mut forged_block = block.clone();
+        forged_block.header.miner_signature = Some(MinerSignature {
+            pub_key_pem: identity.pub_key_pem.clone(),
+            sig: identity.sign("another block"),
+        });
+        assert!(!forged_block.validate_block(1).0);
+        let mut stripped_block = block.clone();
+        stripped_block.header.miner_signature = None;
+        assert!(!stripped_block.validate_block(1).0);
+        let other_identity = NodeIdentity::generate();
+        let mut resigned_block = block.clone();
+        resigned_block.header.miner_signature = Some(MinerSignature {
+            pub_key_pem: other_identity.pub_key_pem.clone(),
+            sig: other_identity.sign(&block.header.block_id),
+        });
+        assert!(!resigned_block.validate_block(1).0);
+        let mut chain = BlockTree::new();
+        assert!(chain.add_block(forged_block, 1).is_err());
+        assert!(chain.add_block(stripped_block, 1).is_err());
+        assert!(chain.add_block(resigned_block, 1).is_err());
+        chain
+            .add_block(
+                serde_json::from_str(&serde_json::to_string(&block).unwrap()).unwrap(),
+                1,
+            )
+            .unwrap();
+        assert_eq!(
+            chain.all_blocks[&block.header.block_id]
+                .header
+                .miner_signature,
+            block.header.miner_signature
+        );
+    }
+
+    /// Read-only requests are answered by the read pool, each exactly once, and `wait_idle` waits for all of them.
+    #[test]
+    fn test_read_pool() {
+        let mut config: serde_json::Value =
+            serde_json::from_str(&config_json(r#", "mine_on_start": false"#)).unwrap();
+        config["addr"]["port"] = 9091.into();
+        let nakamoto = Nakamoto::create_nakamoto(
+            serde_json::to_string(&BlockTree::new()).unwrap(),
+            serde_json::to_string(&TxPool::new()).unwrap(),
+            config.to_string(),
+        );
+        assert!(IPCMessageReq::RequestStateSerialization.is_read_only());
+        assert!(!IPCMessageReq::PublishTx(String::new(), String::new()).is_read_only());
+        assert!(!IPCMessageReq::UpdateConfig(String::new(), String::new()).is_read_only());
+
+        let responses = Arc::new(Mutex::new(vec![]));
+        let responses_p = responses.clone();
+        let mut read_pool = ReadPool::new(
+            nakamoto,
+            3,
+            Arc::new(move |response: &IPCMessageResp| {
+                responses_p.lock().unwrap().push(response.clone())
+            }),
+        );
+        for i in 0..20 {
+            read_pool.dispatch(IPCMessageReq::GetAddressBalance(
+                format!("user{}", i),
+                BalanceBranch::WorkingTip,
+            ));
+            read_pool.dispatch(IPCMessageReq::RequestStateSerialization);
+        }
+        read_pool.wait_idle();
+        let mut users: Vec<String> = responses
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|response| match response {
+                IPCMessageResp::AddressBalance(user_id, _, 0) => Some(user_id.clone()),
+                _ => None,
+            })
+            .collect();
+        users.sort_by_key(|user_id| user_id[4..].parse::<usize>().unwrap());
+        assert_eq!(
+            users,
+            (0..20).map(|i| format!("user{}", i)).collect::<Vec<_>>()
+        );
+        assert_eq!(responses.lock().unwrap().len(), 40);
+        read_pool.shutdown();
+    }
+
+    /// The tests run without the virtual clock variable, so the node clock is real and cannot be advanced.
+    #[test]
+    fn test_advance_real_clock() {
+        assert!(!clock().is_virtual());
+        assert!(matches!(
+            handle_request(&mut None, IPCMessageReq::AdvanceClock(1)),
+            IPCMessageResp::Notify(_)
+        ));
+    }
+
+    /// Two chains that forked after a common block diverge at that block and differ in the rewards of their own blocks.
+    #[test]
+    fn test_diff_chains() {
+        let mut chain_a = BlockTree::new();
+        let common = mine_block(&mut chain_a, ALICE, 0);
+        let mut chain_b = chain_a.clone();
+        assert_eq!(
+            diff_chains(&chain_a, &chain_b)[0],
+            "Same longest chain: 2 blocks ending at ".to_string() + &common.header.block_id
+        );
+        assert_eq!(diff_chains(&chain_a, &chain_b).len(), 1);
+
+        let fork_a = mine_block(&mut chain_a, ALICE, 1);
+        let fork_b = mine_block(&mut chain_b, BOB, 2);
+        let report = diff_chains(&chain_a, &chain_b);
+        assert_eq!(
+            report[0],
+            format!(
+                "The longest chains share 2 blocks, the last common block is {}",
+                common.header.block_id
+            )
+        );
+        assert!(report[1].contains(&fork_a.header.block_id));
+        assert!(report[2].contains(&fork_b.header.block_id));
+        assert!(report.contains(&"Blocks known only to A: 1, only to B: 1".to_string()));
+        assert!(report
+            .iter()
+            .any(|line| line.starts_with("Working balance of") && line.contains(BOB)));
+    }
+
+    /// Tx pools report the transactions only one of them holds.
+    #[test]
+    fn test_diff_tx_pools() {
+        let mut pool_a = TxPool::new();
+        let pool_b = TxPool::new();
+        assert_eq!(
+            diff_tx_pools(&pool_a, &pool_b),
+            vec!["Same tx pool: 0 transactions"]
+        );
+        let tx = unsigned_tx(ALICE, BOB, "SEND $1");
+        put_in_pool(&mut pool_a, tx.clone());
+        assert_eq!(
+            diff_tx_pools(&pool_a, &pool_b),
+            vec![format!("Tx only in A: {} (SEND $1)", tx.gen_hash())]
+        );
+    }
+
+    /// A valid config dir passes every check; a misspelled field, a chain built for other consensus params, an address
+    /// that does not resolve and a broken policy are each reported with what to fix.
+    #[test]
+    fn test_check_config_dir() {
+        let dir = std::env::temp_dir().join(format!("nakamoto_check_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let write_config_dir = |config: &str| {
+            std::fs::write(
+                dir.join("BlockTree.json"),
+                serde_json::to_string(&BlockTree::new()).unwrap(),
+            )
+            .unwrap();
+            std::fs::write(
+                dir.join("TxPool.json"),
+                serde_json::to_string(&TxPool::new()).unwrap(),
+            )
+            .unwrap();
+            std::fs::write(dir.join("Config.json"), config).unwrap();
+        };
+        let policy = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../bin_client/policies/seccomp_nakamoto.json");
+        let outcome = |results: &[crate::check::CheckResult], name: &str| {
+            results
+                .iter()
+                .find(|result| result.name == name)
+                .unwrap()
+                .outcome
+                .clone()
+        };
+
+        write_config_dir(&config_json(
+            r#", "ipc_listen_addr": {"ip": "127.0.0.1", "port": 9001}"#,
+        ));
+        let results = check_config_dir(&dir, Some(&policy));
+        assert!(
+            results
+                .iter()
+                .all(|result| matches!(result.outcome, CheckOutcome::Ok(_))),
+            "{:?}",
+            results
+        );
+        assert_eq!(
+            outcome(&check_config_dir(&dir, None), "seccomp policy"),
+            CheckOutcome::Skipped("no policy path given".to_string())
+        );
+
+        // the dependent checks are skipped when Config.json is broken
+        write_config_dir(&config_json(r#", "neighbours": []"#));
+        let results = check_config_dir(&dir, None);
+        assert!(
+            matches!(outcome(&results, "Config.json"), CheckOutcome::Failed(e) if e.contains("neighbours"))
+        );
+        assert!(matches!(
+            outcome(&results, "genesis"),
+            CheckOutcome::Skipped(_)
+        ));
+        assert!(matches!(
+            outcome(&results, "BlockTree.json"),
+            CheckOutcome::Ok(_)
+        ));
+
+        write_config_dir(&config_json(r#", "consensus": {"genesis_allocation": 1}"#));
+        assert!(
+            matches!(outcome(&check_config_dir(&dir, None), "genesis"), CheckOutcome::Failed(e) if e.contains("`consensus`"))
+        );
+        write_config_dir(&config_json(
+            r#", "ipc_listen_addr": {"ip": "not an ip", "port": 9001}"#,
+        ));
+        assert!(
+            matches!(outcome(&check_config_dir(&dir, None), "addresses"), CheckOutcome::Failed(e) if e.contains("not an ip"))
+        );
+        write_config_dir(&config_json(
+            r#", "ipc_listen_addr": {"ip": "0.0.0.0", "port": 9001}"#,
+        ));
+        assert!(
+            matches!(outcome(&check_config_dir(&dir, None), "addresses"), CheckOutcome::Failed(e) if e.contains("ipc_listen_public"))
+        );
+        write_config_dir(&config_json(
+            r#", "ipc_listen_addr": {"ip": "0.0.0.0", "port": 9001}, "ipc_listen_public": true"#,
+        ));
+        assert!(matches!(
+            outcome(&check_config_dir(&dir, None), "addresses"),
+            CheckOutcome::Ok(_)
+        ));
+        write_config_dir(&config_json(
+            r#", "block_store_path": "/no/such/dir/blocks.jsonl""#,
+        ));
+        assert!(
+            matches!(outcome(&check_config_dir(&dir, None), "paths"), CheckOutcome::Failed(e) if e.contains("block_store_path"))
+        );
+
+        // a missing file and a policy that does not compile
+        std::fs::remove_file(dir.join("TxPool.json")).unwrap();
+        let broken_policy = dir.join("policy.json");
+        std::fs::write(
+            &broken_policy,
+            r#"{"main_thread": {"default_action": "nope"}}"#,
+        )
+        .unwrap();
+        let results = check_config_dir(&dir, Some(&broken_policy));
+        assert!(
+            matches!(outcome(&results, "TxPool.json"), CheckOutcome::Failed(e) if e.contains("Cannot read"))
+        );
+        assert!(
+            matches!(outcome(&results, "seccomp policy"), CheckOutcome::Failed(e) if e.contains("does not compile"))
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Send one HTTP request to the JSON-RPC endpoint and return the status line and the body of the response.
+    fn http_request(port: u16, method: &str, content_type: &str, body: &str) -> (String, String) {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        write!(
+            stream,
+            "{} / HTTP/1.1\r\nHost: localhost\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            content_type,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        std::io::Read::read_to_string(&mut stream, &mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head.lines().next().unwrap().to_string(), body.to_string())
+    }
+
+    /// The JSON-RPC endpoint answers the named methods, with JSON-RPC errors for the requests it cannot answer, and
+    /// only to json requests.
+    #[test]
+    fn test_rpc_server() {
+        let mut config: serde_json::Value =
+            serde_json::from_str(&config_json(r#", "mine_on_start": false"#)).unwrap();
+        config["addr"]["port"] = 9181.into();
+        let nakamoto = Nakamoto::create_nakamoto(
+            serde_json::to_string(&BlockTree::new()).unwrap(),
+            serde_json::to_string(&TxPool::new()).unwrap(),
+            config.to_string(),
+        );
+        crate::rpc::serve_rpc(9182, nakamoto.clone());
+        let rpc = |method: &str, params: serde_json::Value| -> serde_json::Value {
+            let request =
+                serde_json::json!({"jsonrpc": "2.0", "id": 7, "method": method, "params": params});
+            let (status, body) =
+                http_request(9182, "POST", "application/json", &request.to_string());
+            assert_eq!(status, "HTTP/1.1 200 OK");
+            let response: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(response["id"], 7);
+            response
+        };
+
+        let status = &rpc("getchainstatus", serde_json::json!([]))["result"];
+        assert_eq!(status["height"], 0);
+        let genesis_id = status["working_block_id"].as_str().unwrap().to_string();
+        let balance = rpc("getbalance", serde_json::json!([ALICE]))["result"].clone();
+        assert_eq!(
+            balance,
+            nakamoto.get_balance(ALICE, BalanceBranch::Finalized)
+        );
+        let balance = rpc("getbalance", serde_json::json!([ALICE, "WorkingTip"]))["result"].clone();
+        assert_eq!(
+            balance,
+            nakamoto.get_balance(ALICE, BalanceBranch::WorkingTip)
+        );
+        let block = rpc("getblock", serde_json::json!([0]))["result"].clone();
+        assert_eq!(block["header"]["block_id"], genesis_id.as_str());
+        assert_eq!(
+            rpc("getblock", serde_json::json!([genesis_id]))["result"],
+            block
+        );
+        assert_eq!(
+            rpc("getblock", serde_json::json!(["unknown"]))["error"]["code"],
+            -32000
+        );
+
+        // a transaction that cannot be parsed is rejected with the reason
+        let rejected = rpc("publishtx", serde_json::json!(["not a data_string", "sig"]));
+        assert!(rejected["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("PublishTx rejected"));
+        assert_eq!(
+            rpc("publishtx", serde_json::json!(["only one param"]))["error"]["code"],
+            -32602
+        );
+        assert_eq!(
+            rpc("getbalance", serde_json::json!([ALICE, "Nowhere"]))["error"]["code"],
+            -32602
+        );
+        assert_eq!(
+            rpc("unknownmethod", serde_json::json!([]))["error"]["code"],
+            -32601
+        );
+
+        // the other IPC requests are not served
+        assert_eq!(
+            rpc("call", serde_json::json!(["StopMiner"]))["error"]["code"],
+            -32601
+        );
+
+        let (status, body) = http_request(9182, "POST", "application/json", "not json");
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&body).unwrap()["error"]["code"],
+            -32700
+        );
+        let (status, _) = http_request(9182, "GET", "application/json", "");
+        assert_eq!(status, "HTTP/1.1 405 Method Not Allowed");
+        // a form posted by a web page is refused
+        let request = serde_json::json!({"jsonrpc": "2.0", "id": 7, "method": "getchainstatus"});
+        let (status, _) = http_request(9182, "POST", "text/plain", &request.to_string());
+        assert_eq!(status, "HTTP/1.1 415 Unsupported Media Type");
+        let (status, _) = http_request(
+            9182,
+            "POST",
+            "application/json; charset=utf-8",
+            &request.to_string(),
+        );
+        assert_eq!(status, "HTTP/1.1 200 OK");
+    }
+
+    /// The IPC socket is only served on a non-loopback address when `ipc_listen_public` is set.
+    #[test]
+    fn test_is_loopback() {
+        assert!(is_loopback(&NetAddress::new("127.0.0.1".to_string(), 9001)));
+        assert!(is_loopback(&NetAddress::new("::1".to_string(), 9001)));
+        assert!(!is_loopback(&NetAddress::new("0.0.0.0".to_string(), 9001)));
+        assert!(!is_loopback(&NetAddress::new("10.0.0.1".to_string(), 9001)));
+        assert!(!is_loopback(&NetAddress::new(
+            "not an ip".to_string(),
+            9001
+        )));
+    }
+
+    /// The IPC socket answers the status reads and PublishTx as stdin does, and refuses the other requests.
+    #[test]
+    fn test_ipc_socket() {
+        let mut config: serde_json::Value =
+            serde_json::from_str(&config_json(r#", "mine_empty_blocks": false"#)).unwrap();
+        config["addr"]["port"] = 9061.into();
+        let nakamoto = Nakamoto::create_nakamoto(
+            serde_json::to_string(&BlockTree::new()).unwrap(),
+            serde_json::to_string(&TxPool::new()).unwrap(),
+            config.to_string(),
+        );
+        serve_ipc_socket(
+            NetAddress::new("127.0.0.1".to_string(), 9062),
+            false,
+            nakamoto,
+        );
+
+        let stream = TcpStream::connect("127.0.0.1:9062").unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut request = |req: &str| -> IPCMessageResp {
+            (&stream)
+                .write_all(format!("{}\n", req).as_bytes())
+                .unwrap();
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            serde_json::from_str(&line).unwrap()
+        };
+        match request(r#""RequestChainStatus""#) {
+            IPCMessageResp::ChainStatus(status) => assert_eq!(status.height, 0),
+            other => panic!("Unexpected response {:?}", other),
+        }
+        assert!(matches!(request(r#""Quit""#), IPCMessageResp::Notify(_)));
+        assert!(matches!(request("not json"), IPCMessageResp::Notify(_)));
+        match request(r#"{"GetAddressBalance": ["nobody", "Finalized"]}"#) {
+            IPCMessageResp::AddressBalance(_, _, balance) => assert_eq!(balance, 0),
+            other => panic!("Unexpected response {:?}", other),
+        }
+        // The genesis tx is on every working chain
+        let genesis_tx = serde_json::json!({
+            "RequestTxInclusion": serde_json::json!(["GENESIS", ALICE, "SEND $299792458"]).to_string()
+        });
+        assert!(matches!(
+            request(&genesis_tx.to_string()),
+            IPCMessageResp::TxInclusion(_, true)
+        ));
+        let other_tx = serde_json::json!({
+            "RequestTxInclusion": serde_json::json!([ALICE, BOB, "SEND $1"]).to_string()
+        });
+        assert!(matches!(
+            request(&other_tx.to_string()),
+            IPCMessageResp::TxInclusion(_, false)
+        ));
+        let genesis_finality = serde_json::json!({
+            "RequestTxFinality": serde_json::json!(["GENESIS", ALICE, "SEND $299792458"]).to_string()
+        });
+        assert!(matches!(
+            request(&genesis_finality.to_string()),
+            IPCMessageResp::TxFinality(_, true)
+        ));
+        // The requests that change the node, dump its config or walk the whole chain are only accepted on stdin
+        for req in [
+            r#"{"UpdateConfig": ["max_tx_in_one_block", "4"]}"#,
+            r#""RequestConfig""#,
+            r#"{"SetMinerConfig": [3, 5000]}"#,
+            r#""StopMiner""#,
+            r#"{"AdvanceClock": 1000}"#,
+            r#""RequestStateSerialization""#,
+            r#""AuditChain""#,
+            r#""CheckChainInvariants""#,
+            r#""RequestCheckpoint""#,
+        ] {
+            assert!(matches!(request(req), IPCMessageResp::Notify(_)), "{}", req);
+        }
+        match request(r#""RequestMinerStatus""#) {
+            IPCMessageResp::MinerStatus(status) => {
+                assert_ne!(status.miner.target_hashrate, Some(5000))
+            }
+            other => panic!("Unexpected response {:?}", other),
+        }
+        match request(r#"{"RequestBlockAtHeight": 0}"#) {
+            IPCMessageResp::BlockAtHeight(0, block_data) => {
+                assert!(block_data.contains(r#""block_id":"0""#))
+            }
+            other => panic!("Unexpected response {:?}", other),
+        }
+        assert!(matches!(
+            request(r#"{"RequestBlockAtHeight": 1}"#),
+            IPCMessageResp::Notify(_)
+        ));
+        match request(r#"{"RequestBlockWork": "0"}"#) {
+            IPCMessageResp::BlockWork(block_id, work) => {
+                assert_eq!(
+                    (block_id.as_str(), work["branch_work"].as_str()),
+                    ("0", "0")
+                )
+            }
+            other => panic!("Unexpected response {:?}", other),
+        }
+        assert!(matches!(
+            request(r#"{"RequestBlockWork": "unknown"}"#),
+            IPCMessageResp::Notify(_)
+        ));
     }
 }
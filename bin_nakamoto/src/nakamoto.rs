@@ -7,24 +7,126 @@
 // You can see detailed instructions in the comments below.
 
 use lib_chain::block::{
-    BlockNode, BlockNodeHeader, BlockTree, MerkleTree, Puzzle, Transaction, Transactions,
+    BlockId, BlockNode, BlockNodeHeader, BlockTree, ChainStatus, ImportSummary, MerkleTree,
+    MinerSignature, Puzzle, Transaction, Transactions, TxId,
 };
-use lib_miner::miner::{Miner, PuzzleSolution};
+use lib_chain::checkpoint::Checkpoint;
+use lib_chain::clock::clock;
+use lib_chain::consensus_params::ConsensusParams;
+use lib_chain::schema;
+use lib_chain::store::{BlockStore, FileBlockStore};
+use lib_chain::txtrace::{TxSpan, TxTraceEvent};
+use lib_miner::miner::{Miner, MinerStatus};
+use lib_network::addrbook::{load_peers, save_peers};
+use lib_network::identity::{node_id_of, NodeIdentity};
 use lib_network::netchannel::NetAddress;
-use lib_network::p2pnetwork::P2PNetwork;
-use lib_tx_pool::pool::TxPool;
+use lib_network::p2pnetwork::{InboundLimits, NetworkStatus, P2PNetwork};
+use lib_network::peerfilter::PeerFilter;
+use lib_tx_pool::pool::{EvictionPolicy, TxPool, TxPoolStatus, TxRejectReason};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashMap};
-use std::sync::mpsc::Sender;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex, RwLock};
-use std::{thread, time::Duration};
+use std::thread;
+use std::time::Instant;
 
 type UserId = String;
 
+/// The branch of the chain to read a balance from.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum BalanceBranch {
+    /// The balance after the finalized block
+    Finalized,
+    /// The balance after the working block (the end of the longest chain), including blocks pending finalization
+    WorkingTip,
+}
+
+/// The direction of a transaction relative to an address (see `AddressHistoryEntry`).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum TxDirection {
+    /// The address sent the transaction (including to itself)
+    Sent,
+    /// The address received the transaction
+    Received,
+}
+
+/// A transaction sent or received by an address, as listed by `Nakamoto::get_address_history`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AddressHistoryEntry {
+    /// the id of the transaction
+    pub tx_id: TxId,
+    /// whether the address sent or received the transaction
+    pub direction: TxDirection,
+    /// the other address: the receiver of a sent transaction (the first one of a batch), the sender of a received one
+    pub counterparty: UserId,
+    /// the amount sent, without the fee (0 if the message carries no amount): all the payments of a sent batch, only the
+    /// ones to the address of a received one
+    pub amount: i64,
+    /// the fee paid by the sender
+    pub fee: i64,
+    /// the number of blocks of the longest chain from the block of the transaction to the working block (1 if it is in
+    /// the working block); 0 if it is only in the tx pool
+    pub confirmations: u64,
+    /// whether the block of the transaction is finalized
+    pub finalized: bool,
+}
+
+/// The status of the miner of a node (see `Nakamoto::get_miner_status`): the status of the miner itself, whether
+/// mining is enabled, and the tip counters.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NodeMinerStatus {
+    #[serde(flatten)]
+    pub miner: MinerStatus,
+    /// whether the node mines new blocks (see `Nakamoto::start_miner`)
+    pub is_enabled: bool,
+    #[serde(flatten)]
+    pub tips: TipStatus,
+}
+
+/// The tip counters of a node (see `TipWatch`).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TipStatus {
+    /// the number of tips announced by this node
+    pub tips_announced: u64,
+    /// the number of tips announced by peers
+    pub tips_received: u64,
+    /// the number of puzzles stopped by the tips of peers
+    pub puzzles_cancelled_by_tips: u64,
+}
+
+/// The maximum number of transactions listed by `Nakamoto::get_address_history` (the most recent ones).
+pub const MAX_ADDRESS_HISTORY_LEN: usize = 1000;
+
+/// An event of the node, pushed to the subscribers (see `Nakamoto::subscribe_events`) as it happens,
+/// so that a client does not have to poll for it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ChainEvent {
+    /// A block has been finalized (block_id, height)
+    NewBlockFinalized(BlockId, u64),
+    /// A transaction is now in a block of the working chain (data_string, block_id)
+    TxConfirmed(String, BlockId),
+    /// A neighbor has completed the handshake (the address of its connection as "ip:port")
+    PeerConnected(String),
+    /// A block has joined the working chain (block_id, height)
+    NewBlock(BlockId, u64),
+    /// A transaction has been accepted into the tx pool (data_string)
+    NewTx(String),
+    /// The working chain has switched to another branch, dropping blocks of the previous one
+    /// (old_working_id, new_working_id, dropped_block_count)
+    Reorg(BlockId, BlockId, u64),
+}
+
+/// The channels of the subscribers to the events of the node. The channels of the subscribers gone are dropped.
+type EventSubscribers = Arc<Mutex<Vec<Sender<ChainEvent>>>>;
+
+/// The on-disk store of the blocks of the chain, if enabled (see `Config::block_store_path`).
+type SharedBlockStore = Option<Arc<Mutex<dyn BlockStore>>>;
+
 /// The struct to represent configuration of the Nakamoto instance.
 /// The configuration does not contain any user information. The Nakamoto algorithm is user-independent.
 /// The configuration sets information about neighboring nodes, miner, block creation, etc.
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 pub struct Config {
     /// the list of addresses of neighboring nodes
     pub neighbors: Vec<NetAddress>,
@@ -32,11 +134,16 @@ pub struct Config {
     pub addr: NetAddress,
     /// the number of threads used to mine a new block (for miner)
     pub miner_thread_count: u16,
+    /// the max number of hashes per second of all the mining threads together, e.g. to keep a shared machine responsive;
+    /// no limit if unset (for miner)
+    #[serde(default)]
+    pub miner_target_hashrate: Option<u64>,
     /// the length of the nonce string (for miner)
     pub nonce_len: u16,
     // difficulty to mine a new block (for miner)
     pub difficulty_leading_zero_len: u16,
     // difficulty to accept a new block (for verifying the block)
+    // Blocks from any node (including this one) are accepted at this difficulty, so it should not exceed the mining difficulty.
     pub difficulty_leading_zero_len_acc: u16,
     // the seed for the miner thread 0 (for miner)
     pub miner_thread_0_seed: u64,
@@ -44,43 +151,616 @@ pub struct Config {
     pub mining_reward_receiver: UserId,
     // the max number of transactions in one block (for creating a new block)
     pub max_tx_in_one_block: u16,
+    /// whether blocks are mined when there is no transaction to include (for miner)
+    #[serde(default = "default_mine_empty_blocks")]
+    pub mine_empty_blocks: bool,
+    /// when `mine_empty_blocks` is false, the max time (in ms) the miner idles before mining an empty block anyway (for miner)
+    #[serde(default)]
+    pub empty_block_max_wait_ms: Option<u64>,
+    /// whether the miner starts mining at initialization; otherwise it waits for the StartMiner IPC call (for miner)
+    #[serde(default = "default_mine_on_start")]
+    pub mine_on_start: bool,
+    /// the address on which the IPC is also served over TCP, one json request per line (e.g. for dashboards)
+    #[serde(default)]
+    pub ipc_listen_addr: Option<NetAddress>,
+    /// whether `ipc_listen_addr` may be a non-loopback address, i.e. the IPC socket is reachable from other machines
+    #[serde(default)]
+    pub ipc_listen_public: bool,
+    /// the address on which the events and periodic status snapshots of the node are pushed over WebSocket, e.g. for a
+    /// browser dashboard (see `dashboard.html`)
+    #[serde(default)]
+    pub ws_listen_addr: Option<NetAddress>,
+    /// the address on which the node API is also served over gRPC (see `proto/nakamoto.proto`), e.g. for tools in other languages
+    #[serde(default)]
+    pub grpc_listen_addr: Option<NetAddress>,
+    /// the token a gRPC client sends as `authorization: Bearer <token>` to control the node (StartMiner, StopMiner, Call
+    /// and the config status). Without it, gRPC clients may only read the status and publish txs.
+    #[serde(default)]
+    pub grpc_admin_token: Option<String>,
+    /// the json file holding the node key, generated there on first run; a fresh key is used on every run if unset (for network)
+    #[serde(default)]
+    pub node_key_path: Option<String>,
+    /// whether every message sent to neighbors is signed with the node key (for network)
+    #[serde(default)]
+    pub sign_net_messages: bool,
+    /// if set, only the peers matching one of these entries ("ip:port", ip or node id) are kept after the handshake (for network)
+    #[serde(default)]
+    pub peer_allowlist: Option<Vec<String>>,
+    /// the peers matching one of these entries ("ip:port", ip or node id) are never dialed, accepted or kept (for network)
+    #[serde(default)]
+    pub peer_blocklist: Vec<String>,
+    /// the maximum number of connections accepted at once, the others are refused as busy; None for no limit (for network)
+    #[serde(default)]
+    pub max_inbound_peers: Option<usize>,
+    /// whether the node only accepts connections from this machine, by listening on 127.0.0.1 (for network)
+    #[serde(default)]
+    pub listen_localhost_only: bool,
+    /// the number of peers this node dials itself, including the neighbors; while it dials fewer, it dials the addresses
+    /// learned from its peers. Only the neighbors are dialed if unset (for network)
+    #[serde(default)]
+    pub max_outbound_peers: Option<usize>,
+    /// the json file (e.g. `peers.json`) in which the address book of the node is saved periodically with the scores of its
+    /// peers, and from which it is loaded at startup, so that a restarted node reaches the peers it knew even if its
+    /// neighbors are down. With it, the node dials up to as many peers as it has neighbors if `max_outbound_peers` is
+    /// unset. No file if unset (for network)
+    #[serde(default)]
+    pub peers_path: Option<String>,
+    /// whether the blocks mined by this node are announced to its peers as tips ahead of their bodies, and whether the tips
+    /// announced by peers with more work stop the puzzle being solved; compare the fork rate with and without it (for miner)
+    #[serde(default)]
+    pub announce_tips: bool,
+    /// whether the blocks mined by this node carry a signature with the node key, attributing them to this node (for miner)
+    #[serde(default)]
+    pub sign_blocks: bool,
+    /// the side branches whose tip is more than this many blocks behind the finalized block are pruned periodically;
+    /// nothing is pruned if unset (for blocktree)
+    #[serde(default)]
+    pub prune_keep_depth: Option<u64>,
+    /// known-good (height, block_id) pairs; blocks on branches that disagree with them are rejected,
+    /// and a state whose longest chain disagrees with them is refused at initialization (for blocktree)
+    #[serde(default)]
+    pub checkpoints: Vec<(u64, String)>,
+    /// the node ids of the nodes whose signed checkpoints (see `RequestCheckpoint`) this node loads with `LoadCheckpoint`;
+    /// no checkpoint is loaded if empty (for blocktree)
+    #[serde(default)]
+    pub checkpoint_signers: Vec<String>,
+    /// the blocks received from the network whose timestamp is more than this many ms ahead of the network-adjusted time
+    /// (see `network_now_ms`) are dropped, since their miner's clock is wrong; no limit if unset (for blocktree)
+    #[serde(default)]
+    pub max_future_block_time_ms: Option<u64>,
+    /// when set, the mining difficulty is raised or lowered after the blocks mined by this node, so that the local solve times
+    /// stay around this interval (in ms); it never goes below `difficulty_leading_zero_len_acc` (for miner)
+    #[serde(default)]
+    pub target_block_interval_ms: Option<u64>,
+    /// the mining reward, finality depth, size limits and genesis allocation; all the nodes of a network must agree on them.
+    /// Missing fields take the original values (for blocktree)
+    #[serde(default)]
+    pub consensus: ConsensusParams,
+    /// the file in which every block added to the chain is also written, so that the node restarts from it; the blocks in it
+    /// are added on top of the serialized chain at initialization. No file if unset (for blocktree)
+    #[serde(default)]
+    pub block_store_path: Option<String>,
+    /// the file in which every change of the tx pool is also written, so that the node restarts with its tx pool; when it
+    /// exists at initialization, the tx pool is recovered from it instead of the serialized one. No file if unset (for tx pool)
+    #[serde(default)]
+    pub tx_pool_log_path: Option<String>,
+    /// the minimum fee of the transactions accepted into the tx pool of this node, whether submitted locally or relayed by
+    /// neighbors; blocks including lower fees are still accepted (for tx pool)
+    #[serde(default)]
+    pub min_relay_fee: i64,
+    /// how long (in ms) a transaction stays in the tx pool without being finalized before it is dropped; the transactions
+    /// never expire if unset (for tx pool)
+    #[serde(default)]
+    pub tx_ttl_ms: Option<u64>,
+    /// which transaction is dropped to make room for a new one once the tx pool is full: `OldestFirst` or `LowestFeeFirst`
+    /// (for tx pool)
+    #[serde(default)]
+    pub tx_eviction_policy: EvictionPolicy,
+}
+
+fn default_mine_empty_blocks() -> bool {
+    true
+}
+
+fn default_mine_on_start() -> bool {
+    true
+}
+
+/// The config fields that can be changed while the node runs (through the UpdateConfig IPC call).
+/// The other fields (addresses, acceptance difficulty, keys, etc.) only take effect at initialization.
+pub const RUNTIME_CONFIG_KEYS: [&str; 8] = [
+    "difficulty_leading_zero_len",
+    "miner_thread_count",
+    "miner_target_hashrate",
+    "max_tx_in_one_block",
+    "mine_empty_blocks",
+    "empty_block_max_wait_ms",
+    "target_block_interval_ms",
+    "announce_tips",
+];
+
+impl Config {
+    /// Get the effective mining and block creation parameters as a dictionary of strings.
+    pub fn get_status(&self) -> BTreeMap<String, String> {
+        let mut status = BTreeMap::new();
+        status.insert(
+            "difficulty_leading_zero_len".to_string(),
+            self.difficulty_leading_zero_len.to_string(),
+        );
+        status.insert(
+            "difficulty_leading_zero_len_acc".to_string(),
+            self.difficulty_leading_zero_len_acc.to_string(),
+        );
+        status.insert(
+            "miner_thread_count".to_string(),
+            self.miner_thread_count.to_string(),
+        );
+        status.insert(
+            "miner_target_hashrate".to_string(),
+            self.miner_target_hashrate
+                .map_or("none".to_string(), |rate| rate.to_string()),
+        );
+        status.insert("nonce_len".to_string(), self.nonce_len.to_string());
+        status.insert(
+            "max_tx_in_one_block".to_string(),
+            self.max_tx_in_one_block.to_string(),
+        );
+        status.insert(
+            "mine_empty_blocks".to_string(),
+            self.mine_empty_blocks.to_string(),
+        );
+        status.insert(
+            "empty_block_max_wait_ms".to_string(),
+            self.empty_block_max_wait_ms
+                .map_or("none".to_string(), |ms| ms.to_string()),
+        );
+        status.insert(
+            "prune_keep_depth".to_string(),
+            self.prune_keep_depth
+                .map_or("none".to_string(), |depth| depth.to_string()),
+        );
+        status.insert(
+            "target_block_interval_ms".to_string(),
+            self.target_block_interval_ms
+                .map_or("none".to_string(), |ms| ms.to_string()),
+        );
+        status.insert(
+            "max_future_block_time_ms".to_string(),
+            self.max_future_block_time_ms
+                .map_or("none".to_string(), |ms| ms.to_string()),
+        );
+        status.insert("sign_blocks".to_string(), self.sign_blocks.to_string());
+        status.insert("announce_tips".to_string(), self.announce_tips.to_string());
+        status.insert("min_relay_fee".to_string(), self.min_relay_fee.to_string());
+        status.insert(
+            "tx_ttl_ms".to_string(),
+            self.tx_ttl_ms
+                .map_or("none".to_string(), |ms| ms.to_string()),
+        );
+        status.insert(
+            "tx_eviction_policy".to_string(),
+            format!("{:?}", self.tx_eviction_policy),
+        );
+        status.insert(
+            "mining_reward".to_string(),
+            self.consensus.mining_reward.to_string(),
+        );
+        status.insert(
+            "finality_depth".to_string(),
+            self.consensus.finality_depth.to_string(),
+        );
+        status.insert(
+            "max_block_tx_count".to_string(),
+            self.consensus.max_block_tx_count.to_string(),
+        );
+        status.insert(
+            "max_message_len".to_string(),
+            self.consensus.max_message_len.to_string(),
+        );
+        status.insert(
+            "max_tx_outputs".to_string(),
+            self.consensus.max_tx_outputs.to_string(),
+        );
+        status.insert(
+            "hash_function".to_string(),
+            self.consensus.hash_function.to_string(),
+        );
+        status.insert(
+            "#checkpoints".to_string(),
+            self.checkpoints.len().to_string(),
+        );
+        status.insert(
+            "#runtime_changeable".to_string(),
+            RUNTIME_CONFIG_KEYS.join(","),
+        );
+        status
+    }
+
+    /// Set one of the `RUNTIME_CONFIG_KEYS` from its string value.
+    /// The mining difficulty cannot go below the acceptance difficulty, otherwise this node would reject its own blocks.
+    pub fn set_runtime_param(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let parse_u16 = |value: &str| {
+            value
+                .parse::<u16>()
+                .map_err(|_| format!("{} expects a number, got {:?}", key, value))
+        };
+        match key {
+            "difficulty_leading_zero_len" => {
+                let len = parse_u16(value)?;
+                if len < self.difficulty_leading_zero_len_acc {
+                    return Err(format!(
+                        "{} cannot be below difficulty_leading_zero_len_acc ({})",
+                        key, self.difficulty_leading_zero_len_acc
+                    ));
+                }
+                self.difficulty_leading_zero_len = len;
+            }
+            "miner_thread_count" => match parse_u16(value)? {
+                0 => return Err(format!("{} must be at least 1", key)),
+                count => self.miner_thread_count = count,
+            },
+            "miner_target_hashrate" => {
+                self.miner_target_hashrate = match value {
+                    "none" => None,
+                    _ => match value.parse() {
+                        Ok(0) | Err(_) => {
+                            return Err(format!(
+                                "{} expects a positive number or none, got {:?}",
+                                key, value
+                            ))
+                        }
+                        Ok(rate) => Some(rate),
+                    },
+                };
+            }
+            "max_tx_in_one_block" => match parse_u16(value)? {
+                0 => return Err(format!("{} must be at least 1", key)),
+                count => self.max_tx_in_one_block = count,
+            },
+            "mine_empty_blocks" => {
+                self.mine_empty_blocks = value
+                    .parse()
+                    .map_err(|_| format!("{} expects true or false, got {:?}", key, value))?;
+            }
+            "empty_block_max_wait_ms" => {
+                self.empty_block_max_wait_ms = match value {
+                    "none" => None,
+                    _ => Some(value.parse().map_err(|_| {
+                        format!("{} expects a number or none, got {:?}", key, value)
+                    })?),
+                };
+            }
+            "target_block_interval_ms" => {
+                self.target_block_interval_ms = match value {
+                    "none" => None,
+                    _ => match value.parse() {
+                        Ok(0) | Err(_) => {
+                            return Err(format!(
+                                "{} expects a positive number or none, got {:?}",
+                                key, value
+                            ))
+                        }
+                        Ok(ms) => Some(ms),
+                    },
+                };
+            }
+            "announce_tips" => {
+                self.announce_tips = value
+                    .parse()
+                    .map_err(|_| format!("{} expects true or false, got {:?}", key, value))?;
+            }
+            _ => {
+                return Err(format!(
+                    "{} cannot be changed at runtime (changeable: {})",
+                    key,
+                    RUNTIME_CONFIG_KEYS.join(", ")
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the miner should mine an empty block after idling for `idle_ms` with nothing to include.
+    pub fn should_mine_empty_block(&self, idle_ms: u64) -> bool {
+        self.mine_empty_blocks
+            || self
+                .empty_block_max_wait_ms
+                .is_some_and(|max_wait_ms| idle_ms >= max_wait_ms)
+    }
+}
+
+/// How long the miner controller sleeps before checking the tx pool again when it idles.
+const MINER_IDLE_POLL_MS: u64 = 100;
+
+/// The number of recent local solve times the automatic difficulty is based on (see `Config::target_block_interval_ms`).
+pub(crate) const AUTO_DIFFICULTY_WINDOW: usize = 4;
+
+/// The highest mining difficulty the automatic difficulty goes to: a sha256 hash has 64 hex digits.
+const AUTO_DIFFICULTY_MAX: u16 = 64;
+
+/// The automatic mining difficulty of the miner controller (see `Config::target_block_interval_ms`).
+/// One more leading zero makes a puzzle 16 times harder, so the difficulty only moves when the average solve time
+/// is more than 4 times off the target: a move then brings it closer to the target than it was.
+#[derive(Default)]
+pub(crate) struct AutoDifficulty {
+    /// the solve times (ms) of the last puzzles solved by this node at `difficulty`
+    solve_times_ms: VecDeque<u64>,
+    /// the difficulty the solve times were measured at
+    difficulty: u16,
+}
+
+impl AutoDifficulty {
+    /// Record a puzzle solved by this node and return the difficulty to mine at from now on, if it should change.
+    /// The solve times measured at another difficulty (e.g. changed through UpdateConfig) are discarded.
+    pub(crate) fn record_solve(&mut self, solve_ms: u64, config: &Config) -> Option<u16> {
+        let target_ms = config.target_block_interval_ms?;
+        if self.difficulty != config.difficulty_leading_zero_len {
+            self.solve_times_ms.clear();
+            self.difficulty = config.difficulty_leading_zero_len;
+        }
+        self.solve_times_ms.push_back(solve_ms);
+        if self.solve_times_ms.len() > AUTO_DIFFICULTY_WINDOW {
+            self.solve_times_ms.pop_front();
+        }
+        if self.solve_times_ms.len() < AUTO_DIFFICULTY_WINDOW {
+            return None;
+        }
+        let average_ms = self.solve_times_ms.iter().sum::<u64>() / self.solve_times_ms.len() as u64;
+        let difficulty = if average_ms.saturating_mul(4) < target_ms {
+            (self.difficulty + 1).min(AUTO_DIFFICULTY_MAX)
+        } else if average_ms > target_ms.saturating_mul(4) {
+            self.difficulty
+                .saturating_sub(1)
+                .max(config.difficulty_leading_zero_len_acc)
+        } else {
+            self.difficulty
+        };
+        (difficulty != self.difficulty).then_some(difficulty)
+    }
+}
+
+/// How long (in ms) the miner waits for the body of a tip announced by a peer before mining on its own working block again.
+pub(crate) const TIP_WAIT_MS: u64 = 2000;
+
+/// The tips announced by this node and its peers, and their effect on the miner (see `Config::announce_tips`).
+/// A peer announcing a tip with more work than the working block stops the puzzle being solved, and the miner waits
+/// for the body of the tip instead of mining a block that would only become stale.
+#[derive(Default)]
+pub(crate) struct TipWatch {
+    /// the announced tip the miner waits for (block_id, until when in ms), if any
+    awaited_tip: Option<(BlockId, u64)>,
+    /// the number of tips announced by this node
+    tips_announced: u64,
+    /// the number of tips announced by peers
+    tips_received: u64,
+    /// the number of puzzles stopped by the tips of peers
+    puzzles_cancelled: u64,
+}
+
+impl TipWatch {
+    /// Record a tip announced by a peer and return whether the puzzle being solved should be stopped for it,
+    /// i.e. whether the tip is not in the chain yet and its branch has more work than the working block.
+    pub(crate) fn on_tip(
+        &mut self,
+        chain: &BlockTree,
+        block_id: &str,
+        work: u128,
+        now_ms: u64,
+    ) -> bool {
+        self.tips_received += 1;
+        if chain.all_blocks.contains_key(block_id)
+            || work <= chain.branch_work(&chain.working_block_id).unwrap_or(0)
+        {
+            return false;
+        }
+        self.awaited_tip = Some((block_id.to_string(), now_ms + TIP_WAIT_MS));
+        self.puzzles_cancelled += 1;
+        true
+    }
+
+    /// Whether the miner should wait for the body of an announced tip instead of solving a new puzzle.
+    /// The tip is forgotten once its body is in the chain or after `TIP_WAIT_MS`.
+    pub(crate) fn should_wait(&mut self, chain: &BlockTree, now_ms: u64) -> bool {
+        match &self.awaited_tip {
+            Some((block_id, until_ms))
+                if now_ms < *until_ms && !chain.all_blocks.contains_key(block_id) =>
+            {
+                true
+            }
+            _ => {
+                self.awaited_tip = None;
+                false
+            }
+        }
+    }
+
+    /// Record a tip announced by this node.
+    pub(crate) fn on_announced(&mut self) {
+        self.tips_announced += 1;
+    }
+
+    /// Get the tip counters, for the miner status.
+    pub(crate) fn get_status(&self) -> TipStatus {
+        TipStatus {
+            tips_announced: self.tips_announced,
+            tips_received: self.tips_received,
+            puzzles_cancelled_by_tips: self.puzzles_cancelled,
+        }
+    }
 }
 
-/// Create a puzzle for the miner given a chain and a tx pool (as smart pointers).
+/// The number of transactions traced at a time (see `TxTracer`). The oldest ones are forgotten first.
+const MAX_TRACED_TXS: usize = 10_000;
+
+/// The transactions published through this node (by its client), whose lifecycle is traced on stderr until they are
+/// finalized (see `lib_chain::txtrace`): their admission into the tx pool, then their inclusion in a block of the
+/// working chain and its finalization, followed through the events of the node.
+#[derive(Default)]
+pub(crate) struct TxTracer {
+    /// the tx ids of the traced transactions, by data_string
+    traced: HashMap<String, TxId>,
+    /// the data_strings of the traced transactions, the oldest first
+    order: VecDeque<String>,
+    /// the traced transactions of each block of the working chain not finalized yet (data_string), by block id
+    included: HashMap<BlockId, Vec<String>>,
+}
+
+impl TxTracer {
+    /// Start tracing a transaction admitted into the tx pool at the given time, and return its PoolAdmitted span.
+    pub(crate) fn on_admitted(
+        &mut self,
+        data_string: String,
+        tx_id: TxId,
+        now_ms: u64,
+    ) -> TxTraceEvent {
+        if self.order.len() == MAX_TRACED_TXS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.traced.remove(&oldest);
+                for data_strings in self.included.values_mut() {
+                    data_strings.retain(|data_string| *data_string != oldest);
+                }
+                self.included
+                    .retain(|_, data_strings| !data_strings.is_empty());
+            }
+        }
+        if self
+            .traced
+            .insert(data_string.clone(), tx_id.clone())
+            .is_none()
+        {
+            self.order.push_back(data_string);
+        }
+        TxTraceEvent::new(
+            tx_id,
+            TxSpan::PoolAdmitted,
+            "nakamoto",
+            now_ms,
+            String::new(),
+        )
+    }
+
+    /// Return the spans of the traced transactions marked by an event of the node at the given time: Included for a
+    /// TxConfirmed, Finalized for the transactions of a block finalized, which are no longer traced.
+    pub(crate) fn on_event(&mut self, event: &ChainEvent, now_ms: u64) -> Vec<TxTraceEvent> {
+        match event {
+            ChainEvent::TxConfirmed(data_string, block_id) => match self.traced.get(data_string) {
+                Some(tx_id) => {
+                    self.included
+                        .entry(block_id.clone())
+                        .or_default()
+                        .push(data_string.clone());
+                    vec![TxTraceEvent::new(
+                        tx_id.clone(),
+                        TxSpan::Included,
+                        "nakamoto",
+                        now_ms,
+                        format!("block {}", block_id),
+                    )]
+                }
+                None => vec![],
+            },
+            ChainEvent::NewBlockFinalized(block_id, height) => {
+                let data_strings = self.included.remove(block_id).unwrap_or_default();
+                self.order
+                    .retain(|data_string| !data_strings.contains(data_string));
+                data_strings
+                    .iter()
+                    .filter_map(|data_string| self.traced.remove(data_string))
+                    .map(|tx_id| {
+                        TxTraceEvent::new(
+                            tx_id,
+                            TxSpan::Finalized,
+                            "nakamoto",
+                            now_ms,
+                            format!("block {} at height {}", block_id, height),
+                        )
+                    })
+                    .collect()
+            }
+            _ => vec![],
+        }
+    }
+}
+
+/// How often the losing branches of the block tree are pruned (see `Config::prune_keep_depth`).
+const PRUNE_INTERVAL_MS: u64 = 5000;
+
+/// How often the address book is saved to its file (see `Config::peers_path`).
+const PEERS_SAVE_INTERVAL_MS: u64 = 5000;
+
+/// How often the expired transactions are dropped from the tx pool (if `tx_ttl_ms` is set).
+const TX_EXPIRY_INTERVAL_MS: u64 = 1000;
+
+/// The block metadata source recorded for blocks mined by this node.
+const LOCAL_BLOCK_SOURCE: &str = "local";
+
+/// The current time in milliseconds since the UNIX epoch (virtual for reproducible scenarios, see `clock::Clock`).
+fn now_ms() -> u64 {
+    clock().now_ms()
+}
+
+/// The network-adjusted time in milliseconds since the UNIX epoch: the time of this node moved by the median offset of
+/// the clocks of its peers (see `P2PNetwork::network_time_offset_ms`), so that a node whose clock is skewed still
+/// timestamps its blocks, and checks the timestamps of the blocks it receives, like the rest of the network.
+fn network_now_ms(network_p: &Mutex<P2PNetwork>) -> u64 {
+    let offset_ms = network_p.lock().unwrap().network_time_offset_ms();
+    now_ms().saturating_add_signed(offset_ms)
+}
+
+/// Create a puzzle for the miner given a chain and a tx pool (as smart pointers), for a block with the given timestamp
+/// (the network-adjusted time when mining), to be signed with the given public key if any (see `Puzzle::miner_pub_key`).
 /// It returns the puzzle (serialization of the Puzzle struct) and the corresponding incomplete block (nonce and block_id not filled)
-fn create_puzzle(
-    chain_p: Arc<Mutex<BlockTree>>,
+pub(crate) fn create_puzzle(
+    chain_p: Arc<RwLock<BlockTree>>,
     tx_pool_p: Arc<Mutex<TxPool>>,
     tx_count: u16,
     reward_receiver: UserId,
+    timestamp: u64,
+    miner_pub_key: Option<String>,
 ) -> (String, BlockNode) {
     // Please fill in the blank
     // Filter transactions from tx_pool and get the last node of the longest chain.
     // todo();
 
-    let blocktree = chain_p.lock().unwrap();
+    let blocktree = chain_p.read().unwrap();
     let txpool = tx_pool_p.lock().unwrap();
+    // The block must be accepted by the other nodes
+    let tx_count =
+        tx_count.min(u16::try_from(blocktree.consensus.max_block_tx_count).unwrap_or(u16::MAX));
 
-    let finalized_txs = &blocktree.finalized_tx_ids;
-    let mut excluding_txs = Vec::<Transaction>::new();
-    // excluding txs are txs that are not in finalized_txs
-    for tx in txpool.pool_tx_ids.iter() {
-        if !finalized_txs.contains(tx) {
-            let txs = txpool.pool_tx_map.get(tx).unwrap().clone();
-            excluding_txs.push(txs);
+    // Exclude txs already on the longest chain, whether finalized or not
+    let mut excluding_txs: Vec<Transaction> = blocktree
+        .get_pending_finalization_blocks()
+        .into_iter()
+        .flat_map(|block| block.transactions_block.transactions)
+        .collect();
+    let finalized_txs: &HashSet<TxId> = &blocktree.finalized_tx_ids;
+    for tx_id in txpool.pool_tx_ids.iter() {
+        if finalized_txs.contains(tx_id) {
+            excluding_txs.push(txpool.pool_tx_map[tx_id].clone());
         }
     }
     let filtered_txs = txpool.filter_tx(tx_count, &excluding_txs);
-    let last_block_id = blocktree.working_block_id.clone();
-    let last_block = blocktree.get_block(last_block_id).unwrap().clone();
+    let parent = blocktree.working_block_id.clone();
+    // A clock behind the blocks before it would get the block rejected (see `BlockTree::median_time_past`)
+    let timestamp = match blocktree.consensus.retargets() {
+        true => timestamp.max(blocktree.median_time_past(&parent).unwrap_or(0) + 1),
+        false => timestamp.max(1),
+    };
+    let (merkle_root, merkle_tree) = MerkleTree::create_merkle_tree_with(
+        filtered_txs.clone(),
+        blocktree.consensus.hash_function,
+    );
 
     // // build the puzzle
     let puzzle = Puzzle {
         // Please fill in the blank
         // Create a puzzle with the block_id of the parent node and the merkle root of the transactions.
-        parent: last_block.header.parent.clone(),
-        merkle_root: last_block.header.merkle_root.clone(),
+        parent: parent.clone(),
+        merkle_root: merkle_root.clone(),
         reward_receiver: reward_receiver.clone(),
+        miner_pub_key,
+        timestamp: blocktree.consensus.retargets().then_some(timestamp),
     };
     let puzzle_str = serde_json::to_string(&puzzle).unwrap().to_owned();
 
@@ -92,46 +772,318 @@ fn create_puzzle(
 
     let pre_block = BlockNode {
         header: BlockNodeHeader {
-            parent: last_block.header.parent.clone(),
-            merkle_root: last_block.header.merkle_root.clone(),
-            reward_receiver: reward_receiver.clone(),
+            parent,
+            merkle_root,
+            reward_receiver,
             nonce: "".to_string(),
             block_id: "".to_string(),
-            timestamp: 1,
+            timestamp,
+            miner_signature: None,
         },
 
         transactions_block: Transactions {
-            transactions: filtered_txs.clone(),
-            merkle_tree: MerkleTree::create_merkle_tree(filtered_txs.clone()).1,
+            transactions: filtered_txs,
+            merkle_tree,
         },
     };
 
-    return (puzzle_str, pre_block);
+    (puzzle_str, pre_block)
+}
+
+/// Remove from the tx pool the transactions of the blocks finalized since it was last updated, e.g. after a block was added.
+fn remove_finalized_txs(chain: &BlockTree, tx_pool_p: &Mutex<TxPool>) {
+    let mut tx_pool = tx_pool_p.lock().unwrap();
+    if tx_pool.last_finalized_block_id == chain.finalized_block_id {
+        return;
+    }
+    let finalized_blocks =
+        chain.get_finalized_blocks_since(tx_pool.last_finalized_block_id.clone());
+    if !finalized_blocks.is_empty() {
+        tx_pool.remove_txs_from_finalized_blocks(&finalized_blocks);
+    }
+}
+
+/// Get the events of the blocks added to the chain since its working block was `old_working_id` and its finalized block
+/// was `old_finalized_id`: a Reorg if the old working block is no longer on the working chain, a NewBlock per block that
+/// joined the working chain followed by a TxConfirmed per transaction in it, then a NewBlockFinalized per block finalized
+/// (since the fork point, if the old finalized block was reorganized away), from the oldest to the most recent.
+pub(crate) fn chain_events_since(
+    chain: &BlockTree,
+    old_working_id: &str,
+    old_finalized_id: &str,
+) -> Vec<ChainEvent> {
+    let mut events: Vec<ChainEvent> = Vec::new();
+    if chain.working_block_id != old_working_id {
+        let new_blocks = chain.get_main_chain_blocks_since(old_working_id);
+        if let Some(first_new_block) = new_blocks.first() {
+            let fork_id = &first_new_block.header.parent;
+            if fork_id != old_working_id {
+                let old_height = chain.height_of(old_working_id).unwrap_or(0);
+                let fork_height = chain.height_of(fork_id).unwrap_or(0);
+                events.push(ChainEvent::Reorg(
+                    old_working_id.to_string(),
+                    chain.working_block_id.clone(),
+                    old_height.saturating_sub(fork_height),
+                ));
+            }
+        }
+        for block in new_blocks {
+            let block_id = block.header.block_id.clone();
+            let height = chain.height_of(&block_id).unwrap_or(0);
+            events.push(ChainEvent::NewBlock(block_id, height));
+            events.extend(block.transactions_block.transactions.iter().map(|tx| {
+                ChainEvent::TxConfirmed(tx.signed_data(), block.header.block_id.clone())
+            }));
+        }
+    }
+    // After a reorganization below the old finalized block, the blocks are finalized again from the fork point
+    let since_finalized_id = chain
+        .fork_point(&chain.finalized_block_id, old_finalized_id)
+        .unwrap_or_else(|| old_finalized_id.to_string());
+    let mut finalized_events: Vec<ChainEvent> = chain
+        .iter_ancestors(&chain.finalized_block_id)
+        .take_while(|block| block.header.block_id != since_finalized_id)
+        .map(|block| {
+            let block_id = block.header.block_id.clone();
+            let height = chain.height_of(&block_id).unwrap_or(0);
+            ChainEvent::NewBlockFinalized(block_id, height)
+        })
+        .collect();
+    finalized_events.reverse(); // oldest to newest
+    events.extend(finalized_events);
+    events
+}
+
+/// Send the events to every subscriber, forgetting the subscribers that are gone.
+fn publish_events(subscribers: &EventSubscribers, events: Vec<ChainEvent>) {
+    if events.is_empty() {
+        return;
+    }
+    subscribers.lock().unwrap().retain(|subscriber| {
+        events
+            .iter()
+            .all(|event| subscriber.send(event.clone()).is_ok())
+    });
+}
+
+/// Write the given block of the chain (just added, possibly as an orphan) to the block store, if enabled.
+fn persist_block(block_store: &SharedBlockStore, chain: &BlockTree, block_id: &str) {
+    let Some(block_store) = block_store else {
+        return;
+    };
+    let Some(block) = chain
+        .all_blocks
+        .get(block_id)
+        .or_else(|| chain.orphans.get(block_id))
+    else {
+        return;
+    };
+    let metadata = chain.get_block_metadata(block_id);
+    if let Err(e) = block_store.lock().unwrap().put_block(block, &metadata) {
+        eprintln!("[BlockStore] Block {} not stored: {}", block_id, e);
+    }
+}
+
+/// Check whether a transaction with the same sender, receivers and message is in the given block or one of its ancestors.
+fn is_tx_in_ancestors(chain: &BlockTree, block_id: &str, transaction: &Transaction) -> bool {
+    chain.iter_ancestors(block_id).any(|block| {
+        block
+            .transactions_block
+            .transactions
+            .iter()
+            .any(|tx| is_same_payment(tx, transaction))
+    })
+}
+
+/// Whether two transactions make the same payments with the same message, whatever their signature.
+fn is_same_payment(tx: &Transaction, other: &Transaction) -> bool {
+    tx.sender == other.sender
+        && tx.receiver == other.receiver
+        && tx.message == other.message
+        && tx.outputs == other.outputs
+}
+
+/// Get the transactions sent or received by the given address, the most recent first: the ones only in the tx pool,
+/// then the ones of the longest chain, walking it from the working block back to the genesis block.
+/// At most `MAX_ADDRESS_HISTORY_LEN` transactions are listed.
+pub(crate) fn address_history(
+    chain: &BlockTree,
+    tx_pool: &TxPool,
+    user_id: &str,
+) -> Vec<AddressHistoryEntry> {
+    let entry = |tx: &Transaction, confirmations: u64, finalized: bool| {
+        let (direction, counterparty, amount) = match tx.sender == user_id {
+            true => (
+                TxDirection::Sent,
+                &tx.receiver,
+                tx.total_amount().unwrap_or(0),
+            ),
+            false => {
+                let received = tx
+                    .payments()
+                    .iter()
+                    .filter(|(receiver, _)| *receiver == user_id)
+                    .map(|(_, amount)| amount)
+                    .sum();
+                (TxDirection::Received, &tx.sender, received)
+            }
+        };
+        AddressHistoryEntry {
+            tx_id: tx.gen_hash(),
+            direction,
+            counterparty: counterparty.clone(),
+            amount,
+            fee: tx.get_fee(),
+            confirmations,
+            finalized,
+        }
+    };
+    let involves_user = |tx: &&Transaction| {
+        tx.sender == user_id
+            || tx
+                .payments()
+                .iter()
+                .any(|(receiver, _)| *receiver == user_id)
+    };
+    let working_depth = chain.block_depth[&chain.working_block_id];
+    let finalized_depth = chain.block_depth[&chain.finalized_block_id];
+    let mut chain_entries = Vec::new();
+    for block in chain.iter_ancestors(&chain.working_block_id) {
+        let depth = chain.block_depth[&block.header.block_id];
+        let txs = block.transactions_block.transactions.iter().rev();
+        for tx in txs.filter(involves_user) {
+            chain_entries.push(entry(
+                tx,
+                working_depth - depth + 1,
+                depth <= finalized_depth,
+            ));
+        }
+        if chain_entries.len() >= MAX_ADDRESS_HISTORY_LEN {
+            break;
+        }
+    }
+    // The txs of the pool already in a block of the longest chain are listed with their block
+    let chain_tx_ids: HashSet<&TxId> = chain_entries.iter().map(|entry| &entry.tx_id).collect();
+    let mut history: Vec<AddressHistoryEntry> = tx_pool
+        .pool_tx_ids
+        .iter()
+        .rev()
+        .filter_map(|tx_id| tx_pool.pool_tx_map.get(tx_id))
+        .filter(involves_user)
+        .filter(|tx| {
+            !chain_tx_ids.contains(&tx.gen_hash())
+                && !chain.finalized_tx_ids.contains(&tx.gen_hash())
+        })
+        .map(|tx| entry(tx, 0, false))
+        .collect();
+    history.extend(chain_entries);
+    history.truncate(MAX_ADDRESS_HISTORY_LEN);
+    history
+}
+
+/// Check whether the given transaction would be accepted against the working chain and the tx pool.
+/// The signature is not checked, so the transaction can be simulated before it is signed.
+/// It returns the reason of the rejection as an error.
+pub(crate) fn simulate_tx_against(
+    chain: &BlockTree,
+    tx_pool: &TxPool,
+    tx: &Transaction,
+) -> Result<(), TxRejectReason> {
+    if !matches!(tx.get_amount(), Some(amount) if amount > 0)
+        || tx.outputs.iter().any(|output| output.amount <= 0)
+    {
+        return Err(TxRejectReason::InvalidAmount);
+    }
+    if tx.outputs.len() > chain.consensus.max_tx_outputs {
+        return Err(TxRejectReason::TooManyOutputs(
+            tx.outputs.len(),
+            chain.consensus.max_tx_outputs,
+        ));
+    }
+    let Some(cost) = tx.total_cost() else {
+        return Err(TxRejectReason::InvalidAmount);
+    };
+    if tx.message.len() > chain.consensus.max_message_len {
+        return Err(TxRejectReason::MessageTooLong(
+            tx.message.len(),
+            chain.consensus.max_message_len,
+        ));
+    }
+    // A negative fee is never valid, whatever the minimum relay fee
+    let min_fee = tx_pool.min_relay_fee.max(0);
+    if tx.get_fee() < min_fee {
+        return Err(TxRejectReason::FeeTooLow(tx.get_fee(), min_fee));
+    }
+
+    let is_same_tx = |other: &Transaction| is_same_payment(other, tx);
+    let pending_blocks = chain.get_pending_finalization_blocks();
+    let pending_txs: Vec<&Transaction> = pending_blocks
+        .iter()
+        .flat_map(|block| block.transactions_block.transactions.iter())
+        .collect();
+    if pending_txs.iter().any(|other| is_same_tx(other)) {
+        return Err(TxRejectReason::Duplicate);
+    }
+    if tx_pool.pool_tx_map.values().any(is_same_tx) {
+        return Err(TxRejectReason::Duplicate);
+    }
+
+    // Money (amounts and fees) the sender already committed in the tx pool (and not yet included in the working chain)
+    let pending_tx_ids: Vec<TxId> = pending_txs.iter().map(|other| other.gen_hash()).collect();
+    let committed = tx_pool
+        .pool_tx_map
+        .iter()
+        .filter(|(tx_id, other)| other.sender == tx.sender && !pending_tx_ids.contains(tx_id))
+        .filter(|(tx_id, _)| !chain.finalized_tx_ids.contains(*tx_id))
+        .try_fold(0i64, |committed, (_, other)| {
+            committed.checked_add(other.total_cost()?)
+        });
+    let balance = chain
+        .get_working_balance_map()
+        .get(&tx.sender)
+        .copied()
+        .unwrap_or(0);
+    // Nothing is available if the committed money overflows
+    let available = committed.map_or(0, |committed| balance.saturating_sub(committed));
+    if available < cost {
+        return Err(TxRejectReason::InsufficientBalance(available, cost));
+    }
+    Ok(())
 }
 
 /// The struct to represent the Nakamoto instance.
 /// The Nakamoto instance contains the chain, the miner, the network and the tx pool as smart pointers.
 /// It also contains a FIFO channel for sending transactions to the Blockchain
+/// Clones share the same chain, miner, network and tx pool, e.g. to serve IPC requests from several threads.
+#[derive(Clone)]
 pub struct Nakamoto {
     /// the chain (BlockTree)
-    pub chain_p: Arc<Mutex<BlockTree>>,
+    pub chain_p: Arc<RwLock<BlockTree>>,
     /// the miner
     pub miner_p: Arc<Mutex<Miner>>,
     /// the p2pnetwork
     pub network_p: Arc<Mutex<P2PNetwork>>,
     /// the transaction pool
     pub tx_pool_p: Arc<Mutex<TxPool>>,
-    /// the FIFO channel for sending transactions to the Blockchain
-    trans_tx: Sender<Transaction>,
+    /// the effective config, including the parameters changed at runtime
+    pub config_p: Arc<Mutex<Config>>,
+    /// whether the miner thread creates new puzzles (changed by StartMiner and StopMiner)
+    pub mining_enabled_p: Arc<Mutex<bool>>,
+    /// the cancellation token of the puzzle being solved, set to stop the miner in the middle of a puzzle
+    miner_cancel_p: Arc<RwLock<bool>>,
+    /// the tips announced by this node and its peers
+    tip_watch_p: Arc<Mutex<TipWatch>>,
+    /// the transactions published through this node whose lifecycle is traced
+    tx_tracer_p: Arc<Mutex<TxTracer>>,
+    /// the subscribers to the events of the node (see `subscribe_events`)
+    event_subscribers_p: EventSubscribers,
+    /// the on-disk store of the blocks of the chain, if enabled
+    block_store_p: SharedBlockStore,
+    /// the key of this node, with which it signs its checkpoints
+    identity: NodeIdentity,
 }
 
 impl Nakamoto {
-    /// A function to send notification messages to stdout (For debugging purpose only)
-    pub fn stdout_notify(msg: String) {
-        let msg = HashMap::from([("Notify".to_string(), msg.clone())]);
-        println!("{}", serde_json::to_string(&msg).unwrap());
-    }
-
     /// Create a Nakamoto instance given the serialized chain, tx pool and config as three json strings.
     pub fn create_nakamoto(chain_str: String, tx_pool_str: String, config_str: String) -> Nakamoto {
         // Please fill in the blank
@@ -144,26 +1096,440 @@ impl Nakamoto {
 
         // Deserialize the config from the given json string.
         let config: Config =
-            serde_json::from_str(&config_str).expect("Failed to deserialize config");
-        let chain: Arc<Mutex<BlockTree>> = Arc::new(Mutex::new(
-            serde_json::from_str(&chain_str).expect("Failed to deserialize chain"),
-        ));
-        let tx_pool: Arc<Mutex<TxPool>> = Arc::new(Mutex::new(
-            serde_json::from_str(&tx_pool_str).expect("Failed to deserialize tx pool"),
-        ));
+            schema::from_json_str("Config", &config_str).unwrap_or_else(|e| panic!("{}", e));
+        let mut chain = BlockTree::from_json(&chain_str)
+            .unwrap_or_else(|e| panic!("Failed to deserialize chain: {}", e));
+        chain
+            .set_checkpoints(config.checkpoints.iter().cloned().collect())
+            .unwrap_or_else(|e| {
+                panic!("The chain contradicts the checkpoints in the config: {}", e)
+            });
+        chain
+            .set_consensus_params(config.consensus.clone())
+            .unwrap_or_else(|e| {
+                panic!(
+                    "The chain contradicts the consensus params in the config: {}",
+                    e
+                )
+            });
+        // The stored blocks are added on top of the serialized chain, then the blocks of the serialized chain missing
+        // from the store are written to it, so that the store holds the whole chain from now on
+        let block_store: SharedBlockStore = config.block_store_path.as_ref().map(|path| {
+            let mut store = FileBlockStore::open(path).unwrap_or_else(|e| panic!("{}", e));
+            let summary = chain
+                .load_from_store(&store, config.difficulty_leading_zero_len_acc)
+                .unwrap_or_else(|e| panic!("Failed to load the block store: {}", e));
+            for (block_id, e) in summary.rejected.iter() {
+                eprintln!("[BlockStore] Stored block {} rejected: {}", block_id, e);
+            }
+            chain
+                .save_to_store(&mut store)
+                .unwrap_or_else(|e| panic!("Failed to fill the block store: {}", e));
+            Arc::new(Mutex::new(store)) as Arc<Mutex<dyn BlockStore>>
+        });
+        let chain: Arc<RwLock<BlockTree>> = Arc::new(RwLock::new(chain));
+        // The tx pool log holds every change since the node last started, so it is more recent than the serialized tx pool.
+        // It is then rewritten with a snapshot of the tx pool, and records its changes from now on
+        let tx_pool = match config
+            .tx_pool_log_path
+            .as_ref()
+            .filter(|path| std::path::Path::new(path).exists())
+        {
+            Some(path) => TxPool::recover_from_log(path)
+                .unwrap_or_else(|e| panic!("Failed to recover the tx pool from its log: {}", e)),
+            None => TxPool::from_json(&tx_pool_str)
+                .unwrap_or_else(|e| panic!("Failed to deserialize tx pool: {}", e)),
+        };
+        let mut tx_pool = TxPool {
+            min_relay_fee: config.min_relay_fee,
+            tx_ttl_ms: config.tx_ttl_ms,
+            eviction_policy: config.tx_eviction_policy,
+            ..tx_pool
+        };
+        if let Some(path) = &config.tx_pool_log_path {
+            tx_pool
+                .open_log(path)
+                .unwrap_or_else(|e| panic!("Failed to open the tx pool log: {}", e));
+        }
+        let tx_pool: Arc<Mutex<TxPool>> = Arc::new(Mutex::new(tx_pool));
 
         // Create the miner and the network according to the config.
-        let miner = Miner {
-            thread_count: 0,
-            leading_zero_len: 0,
-            is_running: false,
+        let mut miner = Miner {
+            thread_count: config.miner_thread_count,
+            leading_zero_len: config.difficulty_leading_zero_len,
+            hash_function: config.consensus.hash_function,
+            ..Miner::new()
         };
+        miner.set_target_hashrate(config.miner_target_hashrate);
         let arc_miner = Arc::new(Mutex::new(miner));
-        let network = P2PNetwork::create(config.addr, config.neighbors);
+        let identity = match &config.node_key_path {
+            Some(path) => NodeIdentity::load_or_generate(path).unwrap_or_else(|e| {
+                eprintln!("{}, using a fresh node key", e);
+                NodeIdentity::generate()
+            }),
+            None => NodeIdentity::generate(),
+        };
+        let block_signer = config.sign_blocks.then(|| identity.clone());
+        let checkpoint_signer = identity.clone();
+        let network = P2PNetwork::create_with_identity(
+            config.addr.clone(),
+            config.neighbors.clone(),
+            identity,
+            config.sign_net_messages,
+            PeerFilter::new(config.peer_allowlist.clone(), config.peer_blocklist.clone()),
+            InboundLimits {
+                max_inbound_peers: config.max_inbound_peers,
+                localhost_only: config.listen_localhost_only,
+            },
+        );
+        {
+            let mut p2p = network.0.lock().unwrap();
+            let mut max_outbound_peers = config.max_outbound_peers;
+            if let Some(path) = &config.peers_path {
+                match load_peers(path) {
+                    Ok(records) => {
+                        p2p.restore_address_book(records);
+                    }
+                    Err(e) => eprintln!("{}, starting with the neighbors only", e),
+                }
+                // The stored peers replace the neighbors that are down
+                max_outbound_peers.get_or_insert(config.neighbors.len().max(1));
+            }
+            p2p.set_max_outbound_peers(max_outbound_peers);
+        }
+        let event_subscribers: EventSubscribers = Arc::new(Mutex::new(Vec::new()));
+        {
+            // The neighbors completing the handshake are pushed to the subscribers
+            let event_subscribers_p = event_subscribers.clone();
+            network
+                .0
+                .lock()
+                .unwrap()
+                .set_peer_listener(Arc::new(move |peer| {
+                    publish_events(
+                        &event_subscribers_p,
+                        vec![ChainEvent::PeerConnected(peer.to_string())],
+                    )
+                }));
+        }
+        {
+            // Block requests and GetData from neighbors are answered from the local chain
+            let chain_p = chain.clone();
+            network
+                .0
+                .lock()
+                .unwrap()
+                .set_block_provider(Arc::new(move |block_id| {
+                    chain_p.read().unwrap().get_block(block_id.to_string())
+                }));
+        }
 
+        let miner_cancel_p = Arc::new(RwLock::new(false));
         // Start necessary threads that read from and write to FIFO channels provided by the network.
+        {
+            // Blocks from the network are accepted at the acceptance difficulty, whatever difficulty this node mines at
+            let chain_p = chain.clone();
+            let tx_pool_p = tx_pool.clone();
+            let network_p = network.0.clone();
+            let miner_cancel_p = miner_cancel_p.clone();
+            let block_receiver = network.1;
+            let block_request_sender = network.5.clone();
+            let leading_zero_len_acc = config.difficulty_leading_zero_len_acc;
+            let max_future_block_time_ms = config.max_future_block_time_ms;
+            let event_subscribers_p = event_subscribers.clone();
+            let block_store_p = block_store.clone();
+            thread::spawn(move || {
+                for block in block_receiver {
+                    let block_id = block.header.block_id.clone();
+                    if let Some(max_future_ms) = max_future_block_time_ms {
+                        let network_now = network_now_ms(&network_p);
+                        if block.header.timestamp > network_now.saturating_add(max_future_ms) {
+                            eprintln!(
+                                "[Nakamoto] Dropping block {}: its timestamp is {} ms ahead of the network time",
+                                block_id,
+                                block.header.timestamp - network_now
+                            );
+                            continue;
+                        }
+                    }
+                    let source = network_p
+                        .lock()
+                        .unwrap()
+                        .get_block_source(&block_id)
+                        .unwrap_or_else(|| "unknown".to_string());
+                    let parent_id = block.header.parent.clone();
+                    let mut chain = chain_p.write().unwrap();
+                    let old_working_id = chain.working_block_id.clone();
+                    let old_finalized_id = chain.finalized_block_id.clone();
+                    if chain.add_block(block, leading_zero_len_acc).is_ok() {
+                        chain.record_block_metadata(&block_id, &source, now_ms());
+                        persist_block(&block_store_p, &chain, &block_id);
+                        // An orphan cannot be connected until its parent is fetched from the network
+                        if chain.orphans.contains_key(&block_id) {
+                            let _ = block_request_sender.send(parent_id);
+                        }
+                        remove_finalized_txs(&chain, &tx_pool_p);
+                        // The puzzle being solved extends the old working block, so it is rebuilt on the new one
+                        if chain.working_block_id != old_working_id {
+                            eprintln!(
+                                "[Miner] New working block {}, restarting the puzzle",
+                                chain.working_block_id
+                            );
+                            *miner_cancel_p.write().unwrap() = true;
+                        }
+                        let events = chain_events_since(&chain, &old_working_id, &old_finalized_id);
+                        drop(chain);
+                        publish_events(&event_subscribers_p, events);
+                    }
+                }
+            });
+        }
+        {
+            // The txs gossiped by peers get the same consensus checks as the txs of a block before entering the pool
+            let chain_p = chain.clone();
+            let tx_pool_p = tx_pool.clone();
+            let tx_receiver = network.2;
+            let event_subscribers_p = event_subscribers.clone();
+            thread::spawn(move || {
+                for tx in tx_receiver {
+                    if let Err(e) = chain_p.read().unwrap().consensus.check_tx(&tx) {
+                        eprintln!("[TxPool] Gossiped tx {} rejected: {}", tx.gen_hash(), e);
+                        continue;
+                    }
+                    let data_string = tx.signed_data();
+                    if tx_pool_p
+                        .lock()
+                        .unwrap()
+                        .try_add_tx_at(tx, now_ms())
+                        .is_ok()
+                    {
+                        publish_events(&event_subscribers_p, vec![ChainEvent::NewTx(data_string)]);
+                    }
+                }
+            });
+        }
+
+        let mining_enabled_p = Arc::new(Mutex::new(config.mine_on_start));
+        let config_p = Arc::new(Mutex::new(config));
+        let tip_watch_p = Arc::new(Mutex::new(TipWatch::default()));
+        {
+            // A tip with more work announced by a peer stops the puzzle being solved
+            let chain_p = chain.clone();
+            let config_p = config_p.clone();
+            let miner_cancel_p = miner_cancel_p.clone();
+            let tip_watch_p = tip_watch_p.clone();
+            network.0.lock().unwrap().set_tip_listener(Arc::new(
+                move |peer, height, block_id, work| {
+                    if !config_p.lock().unwrap().announce_tips {
+                        return;
+                    }
+                    let chain = chain_p.read().unwrap();
+                    if tip_watch_p
+                        .lock()
+                        .unwrap()
+                        .on_tip(&chain, block_id, work, now_ms())
+                    {
+                        eprintln!(
+                            "[Miner] Tip {} at height {} announced by {}, stopping the puzzle",
+                            block_id, height, peer
+                        );
+                        *miner_cancel_p.write().unwrap() = true;
+                    }
+                },
+            ));
+        }
+
         // Start necessary thread(s) to control the miner.
-        //todo
+        {
+            let chain_p = chain.clone();
+            let tx_pool_p = tx_pool.clone();
+            let miner_p = arc_miner.clone();
+            let block_broadcast = network.3.clone();
+            let network_p = network.0.clone();
+            let tip_watch_p = tip_watch_p.clone();
+            let config_p = config_p.clone();
+            let mining_enabled_p = mining_enabled_p.clone();
+            let miner_cancel_p = miner_cancel_p.clone();
+            let event_subscribers_p = event_subscribers.clone();
+            let block_store_p = block_store.clone();
+            thread::spawn(move || {
+                // The miner pacing follows the clock, so that a virtual clock decides when empty blocks are mined
+                let mut idle_since_ms = now_ms();
+                let mut auto_difficulty = AutoDifficulty::default();
+                loop {
+                    {
+                        // The token is reset under the same lock that StopMiner takes, so that a stop is never missed
+                        let mining_enabled = mining_enabled_p.lock().unwrap();
+                        if !*mining_enabled {
+                            drop(mining_enabled);
+                            clock().sleep_ms(MINER_IDLE_POLL_MS);
+                            idle_since_ms = now_ms();
+                            continue;
+                        }
+                        *miner_cancel_p.write().unwrap() = false;
+                    }
+                    // A block mined now on the working block would lose against the tip announced by a peer
+                    if tip_watch_p
+                        .lock()
+                        .unwrap()
+                        .should_wait(&chain_p.read().unwrap(), now_ms())
+                    {
+                        clock().sleep_ms(MINER_IDLE_POLL_MS);
+                        continue;
+                    }
+                    // Runtime config changes take effect from the next puzzle
+                    let config = config_p.lock().unwrap().clone();
+                    let (puzzle, mut block) = create_puzzle(
+                        chain_p.clone(),
+                        tx_pool_p.clone(),
+                        config.max_tx_in_one_block,
+                        config.mining_reward_receiver.clone(),
+                        network_now_ms(&network_p),
+                        block_signer
+                            .as_ref()
+                            .map(|identity| identity.pub_key_pem.clone()),
+                    );
+                    // Idle until there are txs to include, unless empty blocks may be mined
+                    if block.transactions_block.transactions.is_empty()
+                        && !config.should_mine_empty_block(now_ms().saturating_sub(idle_since_ms))
+                    {
+                        clock().sleep_ms(MINER_IDLE_POLL_MS);
+                        continue;
+                    }
+
+                    // The chain may require more leading zeros than configured (see `ConsensusParams::retarget_interval`)
+                    let difficulty = chain_p
+                        .read()
+                        .unwrap()
+                        .expected_difficulty(
+                            &block.header.parent,
+                            config.difficulty_leading_zero_len_acc,
+                        )
+                        .map_or(config.difficulty_leading_zero_len, |expected| {
+                            expected.max(config.difficulty_leading_zero_len)
+                        });
+
+                    miner_p.lock().unwrap().is_running = true;
+                    let solve_started = Instant::now();
+                    let solution = Miner::solve_puzzle(
+                        miner_p.clone(),
+                        puzzle,
+                        config.nonce_len,
+                        difficulty,
+                        config.miner_thread_count,
+                        config.miner_thread_0_seed,
+                        miner_cancel_p.clone(),
+                    );
+                    miner_p.lock().unwrap().is_running = false;
+                    idle_since_ms = now_ms();
+
+                    if let Some(solution) = solution {
+                        let solve_ms = solve_started.elapsed().as_millis() as u64;
+                        if let Some(difficulty) = auto_difficulty.record_solve(solve_ms, &config) {
+                            // Unless the difficulty was changed through UpdateConfig in the meantime
+                            let mut current_config = config_p.lock().unwrap();
+                            if current_config.difficulty_leading_zero_len
+                                == config.difficulty_leading_zero_len
+                            {
+                                eprintln!(
+                                    "[Miner] Auto difficulty: {} -> {} leading zeros",
+                                    config.difficulty_leading_zero_len, difficulty
+                                );
+                                current_config.difficulty_leading_zero_len = difficulty;
+                                miner_p.lock().unwrap().leading_zero_len = difficulty;
+                            }
+                        }
+                        block.header.nonce = solution.nonce;
+                        block.header.block_id = solution.hash;
+                        if let Some(identity) = &block_signer {
+                            block.header.miner_signature = Some(MinerSignature {
+                                pub_key_pem: identity.pub_key_pem.clone(),
+                                sig: identity.sign(&block.header.block_id),
+                            });
+                        }
+                        let mut chain = chain_p.write().unwrap();
+                        let old_working_id = chain.working_block_id.clone();
+                        let old_finalized_id = chain.finalized_block_id.clone();
+                        let added =
+                            chain.add_block(block.clone(), config.difficulty_leading_zero_len_acc);
+                        if added.is_ok() {
+                            chain.record_block_metadata(
+                                &block.header.block_id,
+                                LOCAL_BLOCK_SOURCE,
+                                now_ms(),
+                            );
+                            persist_block(&block_store_p, &chain, &block.header.block_id);
+                            remove_finalized_txs(&chain, &tx_pool_p);
+                            let events =
+                                chain_events_since(&chain, &old_working_id, &old_finalized_id);
+                            let block_id = &block.header.block_id;
+                            let tip = (chain.height_of(block_id), chain.branch_work(block_id));
+                            drop(chain);
+                            // The tip is announced ahead of the gossip of the block body
+                            if let (true, (Some(height), Some(work))) = (config.announce_tips, tip)
+                            {
+                                network_p
+                                    .lock()
+                                    .unwrap()
+                                    .announce_tip(height, block_id, work);
+                                tip_watch_p.lock().unwrap().on_announced();
+                            }
+                            publish_events(&event_subscribers_p, events);
+                            let _ = block_broadcast.send(block);
+                        }
+                    }
+                }
+            });
+        }
+
+        // Start a thread that prunes the losing branches of the block tree, if enabled.
+        {
+            let chain_p = chain.clone();
+            let config_p = config_p.clone();
+            thread::spawn(move || loop {
+                clock().sleep_ms(PRUNE_INTERVAL_MS);
+                let prune_keep_depth = config_p.lock().unwrap().prune_keep_depth;
+                if let Some(keep_depth) = prune_keep_depth {
+                    chain_p.write().unwrap().prune_losing_branches(keep_depth);
+                }
+            });
+        }
+
+        // Start a thread that saves the address book to its file, if enabled.
+        if let Some(path) = config_p.lock().unwrap().peers_path.clone() {
+            let network_p = network.0.clone();
+            thread::spawn(move || loop {
+                clock().sleep_ms(PEERS_SAVE_INTERVAL_MS);
+                let records = network_p.lock().unwrap().get_address_book();
+                if let Err(e) = save_peers(&path, &records) {
+                    eprintln!("{}", e);
+                }
+            });
+        }
+
+        // Start a thread that drops the expired transactions of the tx pool, if they expire.
+        if config_p.lock().unwrap().tx_ttl_ms.is_some() {
+            let tx_pool_p = tx_pool.clone();
+            thread::spawn(move || loop {
+                clock().sleep_ms(TX_EXPIRY_INTERVAL_MS);
+                tx_pool_p.lock().unwrap().purge_expired(now_ms());
+            });
+        }
+
+        // Start a thread that traces the inclusion and the finalization of the transactions published through this node.
+        let tx_tracer_p = Arc::new(Mutex::new(TxTracer::default()));
+        {
+            let (sender, receiver) = channel();
+            event_subscribers.lock().unwrap().push(sender);
+            let tx_tracer_p = tx_tracer_p.clone();
+            thread::spawn(move || {
+                for event in receiver {
+                    for trace in tx_tracer_p.lock().unwrap().on_event(&event, now_ms()) {
+                        eprintln!("{}", trace.to_log_line());
+                    }
+                }
+            });
+        }
 
         // Return the Nakamoto instance that holds pointers to the chain, the miner, the network and the tx pool.
         Nakamoto {
@@ -171,42 +1537,395 @@ impl Nakamoto {
             miner_p: arc_miner,
             network_p: network.0,
             tx_pool_p: tx_pool,
-            trans_tx: network.4,
+            config_p,
+            mining_enabled_p,
+            miner_cancel_p,
+            tip_watch_p,
+            tx_tracer_p,
+            event_subscribers_p: event_subscribers,
+            block_store_p: block_store,
+            identity: checkpoint_signer,
         }
     }
 
-    /// Get the status of the network as a dictionary of strings. For debugging purpose.
-    pub fn get_network_status(&self) -> BTreeMap<String, String> {
+    /// Subscribe to the events of the node: the finalized blocks, the confirmed transactions and the connected neighbors
+    /// are sent on the returned channel as they happen, until it is dropped.
+    pub fn subscribe_events(&self) -> Receiver<ChainEvent> {
+        let (sender, receiver) = channel();
+        self.event_subscribers_p.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Let the miner create and solve new puzzles.
+    pub fn start_miner(&self) {
+        *self.mining_enabled_p.lock().unwrap() = true;
+    }
+
+    /// Stop the miner, including the puzzle it is solving. No block is mined until `start_miner` is called.
+    pub fn stop_miner(&self) {
+        let mut mining_enabled = self.mining_enabled_p.lock().unwrap();
+        *mining_enabled = false;
+        *self.miner_cancel_p.write().unwrap() = true;
+    }
+
+    /// Get the effective config as a dictionary of strings.
+    pub fn get_config_status(&self) -> BTreeMap<String, String> {
+        self.config_p.lock().unwrap().get_status()
+    }
+
+    /// Change one of the runtime-changeable config parameters and return its applied value.
+    pub fn update_config(&self, key: &str, value: &str) -> Result<String, String> {
+        let mut config = self.config_p.lock().unwrap();
+        config.set_runtime_param(key, value)?;
+        self.apply_miner_config(&config);
+        Ok(config.get_status()[key].clone())
+    }
+
+    /// Change the number of mining threads and the hash rate limit (None for no limit) while the node runs.
+    /// Both apply to the block being mined, and are kept in the config for the next blocks.
+    pub fn set_miner_config(
+        &self,
+        thread_count: u16,
+        target_hashrate: Option<u64>,
+    ) -> Result<(), String> {
+        let mut config = self.config_p.lock().unwrap();
+        let mut updated = config.clone();
+        updated.set_runtime_param("miner_thread_count", &thread_count.to_string())?;
+        updated.set_runtime_param(
+            "miner_target_hashrate",
+            &target_hashrate.map_or("none".to_string(), |rate| rate.to_string()),
+        )?;
+        *config = updated;
+        self.apply_miner_config(&config);
+        Ok(())
+    }
+
+    /// Apply the mining parameters of the config to the miner, including to the puzzle being solved.
+    /// A puzzle cannot start threads, so it is restarted (like when a new block arrives) if more threads are needed.
+    fn apply_miner_config(&self, config: &Config) {
+        let mut miner = self.miner_p.lock().unwrap();
+        miner.leading_zero_len = config.difficulty_leading_zero_len;
+        miner.set_target_hashrate(config.miner_target_hashrate);
+        if miner.set_thread_count(config.miner_thread_count) {
+            *self.miner_cancel_p.write().unwrap() = true;
+        }
+    }
+
+    /// Get the statistics of the chain over its last `last_n` blocks as a dictionary of strings.
+    pub fn get_chain_stats(&self, last_n: u64) -> BTreeMap<String, String> {
+        self.chain_p.read().unwrap().get_chain_stats(last_n)
+    }
+
+    /// Check the supply invariant of the chain. Return a description of the violation, if any.
+    pub fn audit_chain(&self) -> Result<(), String> {
+        self.chain_p.read().unwrap().audit_supply()
+    }
+
+    /// Check that the bookkeeping maps of the block tree agree with each other. Return the violations, if any.
+    pub fn check_chain_invariants(&self) -> Result<(), Vec<String>> {
+        self.chain_p.read().unwrap().check_invariants()
+    }
+
+    /// Get the status of the network. For debugging purpose.
+    pub fn get_network_status(&self) -> NetworkStatus {
         self.network_p.lock().unwrap().get_status()
     }
 
-    /// Get the status of the chain as a dictionary of strings. For debugging purpose.
-    pub fn get_chain_status(&self) -> BTreeMap<String, String> {
-        self.chain_p.lock().unwrap().get_status()
+    /// Stop the traffic to and from the given neighbor ("ip:port"), e.g. to simulate a network partition.
+    pub fn pause_peer(&self, addr: &str) -> Result<(), String> {
+        self.network_p.lock().unwrap().pause_peer(addr)
+    }
+
+    /// Resume the traffic to and from the given neighbor ("ip:port").
+    pub fn resume_peer(&self, addr: &str) -> Result<(), String> {
+        self.network_p.lock().unwrap().resume_peer(addr)
+    }
+
+    /// Block the peers matching the given entry ("ip:port", ip or node id), disconnecting them if they are connected.
+    pub fn block_peer(&self, entry: &str) -> Result<(), String> {
+        self.network_p
+            .lock()
+            .unwrap()
+            .peer_filter_mut()
+            .block(entry)
+    }
+
+    /// Unblock the peers matching the given entry, so that they can connect again.
+    pub fn unblock_peer(&self, entry: &str) -> Result<(), String> {
+        self.network_p
+            .lock()
+            .unwrap()
+            .peer_filter_mut()
+            .unblock(entry)
+    }
+
+    /// Add the given entry to the allowlist of peers, turning the allowlist on if it is off.
+    pub fn allow_peer(&self, entry: &str) -> Result<(), String> {
+        self.network_p
+            .lock()
+            .unwrap()
+            .peer_filter_mut()
+            .allow(entry)
+    }
+
+    /// Remove the given entry from the allowlist of peers, disconnecting the peers it no longer allows.
+    pub fn disallow_peer(&self, entry: &str) -> Result<(), String> {
+        self.network_p
+            .lock()
+            .unwrap()
+            .peer_filter_mut()
+            .disallow(entry)
+    }
+
+    /// Get the gossip trace events recorded by the network, serialized as a json array.
+    pub fn get_gossip_trace_json(&self) -> String {
+        let trace_log = self.network_p.lock().unwrap().get_trace_log();
+        serde_json::to_string(&trace_log).unwrap()
+    }
+
+    /// Get the status of the chain. For debugging purpose.
+    pub fn get_chain_status(&self) -> ChainStatus {
+        self.chain_p.read().unwrap().get_status()
     }
 
-    /// Get the status of the transaction pool as a dictionary of strings. For debugging purpose.
-    pub fn get_txpool_status(&self) -> BTreeMap<String, String> {
+    /// Get the status of the transaction pool. For debugging purpose.
+    pub fn get_txpool_status(&self) -> TxPoolStatus {
         self.tx_pool_p.lock().unwrap().get_status()
     }
 
-    /// Get the status of the miner as a dictionary of strings. For debugging purpose.
-    pub fn get_miner_status(&self) -> BTreeMap<String, String> {
-        self.miner_p.lock().unwrap().get_status()
+    /// Get the status of the miner. For debugging purpose.
+    pub fn get_miner_status(&self) -> NodeMinerStatus {
+        NodeMinerStatus {
+            miner: self.miner_p.lock().unwrap().get_status(),
+            is_enabled: *self.mining_enabled_p.lock().unwrap(),
+            tips: self.tip_watch_p.lock().unwrap().get_status(),
+        }
+    }
+
+    /// Get the balance of the given address on the given branch. An unknown address has a balance of 0.
+    pub fn get_balance(&self, user_id: &str, branch: BalanceBranch) -> i64 {
+        let chain = self.chain_p.read().unwrap();
+        let balance = match branch {
+            BalanceBranch::Finalized => chain.finalized_balance_map.get(user_id).copied(),
+            BalanceBranch::WorkingTip => chain.get_working_balance_map().get(user_id).copied(),
+        };
+        balance.unwrap_or(0)
+    }
+
+    /// Get the block of the given block_id serialized as a json string, if it exists in the chain.
+    pub fn get_block_json(&self, block_id: &str) -> Option<String> {
+        let chain = self.chain_p.read().unwrap();
+        chain
+            .all_blocks
+            .get(block_id)
+            .map(|block| serde_json::to_string(block).unwrap())
+    }
+
+    /// Get the block at the given height of the longest chain serialized as a json string, if the chain is that long.
+    pub fn get_block_at_height_json(&self, height: u64) -> Option<String> {
+        let chain = self.chain_p.read().unwrap();
+        chain
+            .get_block_at_height(height)
+            .map(|block| serde_json::to_string(&block).unwrap())
     }
 
-    /// Publish a transaction to the Blockchain
-    pub fn publish_tx(&mut self, transaction: Transaction) -> () {
+    /// Get where and when the given block was first seen, serialized as a json string, if it is recorded.
+    pub fn get_block_metadata_json(&self, block_id: &str) -> Option<String> {
+        let chain = self.chain_p.read().unwrap();
+        chain
+            .block_metadata
+            .get(block_id)
+            .map(|metadata| serde_json::to_string(metadata).unwrap())
+    }
+
+    /// Get the difficulty and work figures of the given block, if it is in the block tree.
+    pub fn get_block_work(&self, block_id: &str) -> Option<BTreeMap<String, String>> {
+        self.chain_p.read().unwrap().get_block_work(block_id)
+    }
+
+    /// Publish a transaction to the Blockchain.
+    /// Return the reason why the transaction is rejected (by the working chain or by the tx pool), if any.
+    pub fn publish_tx(&mut self, transaction: Transaction) -> Result<(), TxRejectReason> {
         // Please fill in the blank
         // Add the transaction to the transaction pool and send it to the broadcast channel
 
+        let chain = self.chain_p.read().unwrap();
         let mut tx_pool = self.tx_pool_p.lock().unwrap();
-        tx_pool.add_tx(transaction.clone());
+        simulate_tx_against(&chain, &tx_pool, &transaction)?;
+        let data_string = transaction.signed_data();
+        let tx_id = transaction.gen_hash();
+        tx_pool.try_add_tx_at(transaction, now_ms())?;
+        drop(tx_pool);
+        drop(chain);
+        let trace =
+            self.tx_tracer_p
+                .lock()
+                .unwrap()
+                .on_admitted(data_string.clone(), tx_id, now_ms());
+        eprintln!("{}", trace.to_log_line());
+        publish_events(
+            &self.event_subscribers_p,
+            vec![ChainEvent::NewTx(data_string)],
+        );
+        Ok(())
+    }
+
+    /// Check whether a transaction with the same sender, receiver and message is in a block of the working chain.
+    /// The signature is not compared, so that the client can check the transactions it published by their data_string.
+    pub fn is_tx_on_working_chain(&self, transaction: &Transaction) -> bool {
+        let chain = self.chain_p.read().unwrap();
+        is_tx_in_ancestors(&chain, &chain.working_block_id, transaction)
+    }
+
+    /// Check whether a transaction with the same sender, receiver and message is in a finalized block (see `is_tx_on_working_chain`).
+    pub fn is_tx_finalized(&self, transaction: &Transaction) -> bool {
+        let chain = self.chain_p.read().unwrap();
+        is_tx_in_ancestors(&chain, &chain.finalized_block_id, transaction)
+    }
+
+    /// Merge the blocks of another node's serialized BlockTree into the local block tree.
+    /// Each block is validated at the acceptance difficulty, as if it had been received from the network,
+    /// so that two nodes can be reconciled offline after a network failure or after one of them was down.
+    pub fn import_chain(&self, blocktree_json: &str) -> Result<ImportSummary, String> {
+        let foreign_chain = BlockTree::from_json(blocktree_json)?;
+        let leading_zero_len_acc = self
+            .config_p
+            .lock()
+            .unwrap()
+            .difficulty_leading_zero_len_acc;
+        let mut chain = self.chain_p.write().unwrap();
+        let old_working_id = chain.working_block_id.clone();
+        let old_finalized_id = chain.finalized_block_id.clone();
+        let summary = chain.import_blocks(&foreign_chain, leading_zero_len_acc);
+        for block_id in summary.added.iter() {
+            chain.record_block_metadata(block_id, "import", now_ms());
+            persist_block(&self.block_store_p, &chain, block_id);
+        }
+        remove_finalized_txs(&chain, &self.tx_pool_p);
+        if chain.working_block_id != old_working_id {
+            *self.miner_cancel_p.write().unwrap() = true;
+        }
+        let events = chain_events_since(&chain, &old_working_id, &old_finalized_id);
+        drop(chain);
+        publish_events(&self.event_subscribers_p, events);
+        Ok(summary)
+    }
+
+    /// Take a checkpoint of the finalized state of the chain (see `BlockTree::checkpoint`), signed with the key of this node.
+    pub fn get_checkpoint(&self) -> Checkpoint {
+        let mut checkpoint = self.chain_p.read().unwrap().checkpoint();
+        checkpoint.signature = Some(MinerSignature {
+            pub_key_pem: self.identity.pub_key_pem.clone(),
+            sig: self.identity.sign(&checkpoint.digest()),
+        });
+        checkpoint
+    }
+
+    /// Replace the chain with one built from the given checkpoint (see `BlockTree::from_checkpoint`), so that a new node
+    /// does not replay the chain from the genesis block. The blocks of the chain after the checkpoint block are kept, and
+    /// the transactions finalized by the checkpoint leave the tx pool.
+    /// The checkpoint must be signed by one of the `checkpoint_signers` of the config (none is loaded if there is none), and
+    /// be ahead of the finalized block of the chain. It is refused with a block store, since the stored blocks before the
+    /// checkpoint could not be added to the new chain after a restart.
+    pub fn load_checkpoint(&self, checkpoint_json: &str) -> Result<Checkpoint, String> {
+        let checkpoint: Checkpoint =
+            serde_json::from_str(checkpoint_json).map_err(|e| e.to_string())?;
+        let config = self.config_p.lock().unwrap().clone();
+        if config.block_store_path.is_some() {
+            return Err("Checkpoints cannot be loaded with a block store".to_string());
+        }
+        let signer = match &checkpoint.signature {
+            Some(signature) if checkpoint.is_signed() => node_id_of(&signature.pub_key_pem),
+            _ => return Err("The checkpoint is not signed".to_string()),
+        };
+        if config.checkpoint_signers.is_empty() {
+            return Err(
+                "No checkpoint signer is configured (see `checkpoint_signers` in the config)"
+                    .to_string(),
+            );
+        }
+        if !config.checkpoint_signers.contains(&signer) {
+            return Err(format!(
+                "The checkpoint is signed by {}, which is not a checkpoint signer",
+                signer
+            ));
+        }
+
+        let mut chain = self.chain_p.write().unwrap();
+        let finalized_height = chain.block_depth[&chain.finalized_block_id];
+        if checkpoint.height <= finalized_height {
+            return Err(format!(
+                "The chain is already finalized up to height {}",
+                finalized_height
+            ));
+        }
+        // The descendants of the checkpoint block (each one after its parent), then the orphans
+        let mut blocks = vec![];
+        let mut next_ids = vec![checkpoint.finalized_block_id.clone()];
+        while let Some(block_id) = next_ids.pop() {
+            for child_id in chain.children_map.get(&block_id).into_iter().flatten() {
+                blocks.push(chain.all_blocks[child_id].clone());
+                next_ids.push(child_id.clone());
+            }
+        }
+        blocks.extend(chain.orphans.values().cloned());
+        let mut new_chain = BlockTree::from_checkpoint(
+            &checkpoint,
+            config.consensus.clone(),
+            blocks,
+            config.difficulty_leading_zero_len_acc,
+        )?;
+        new_chain.set_checkpoints(config.checkpoints.iter().cloned().collect())?;
+        new_chain.record_block_metadata(&checkpoint.finalized_block_id, "checkpoint", now_ms());
+        for (block_id, metadata) in chain.block_metadata.iter() {
+            if new_chain.all_blocks.contains_key(block_id)
+                || new_chain.orphans.contains_key(block_id)
+            {
+                new_chain.record_block_metadata(block_id, &metadata.source, metadata.first_seen_ms);
+            }
+        }
+
+        let old_working_id = chain.working_block_id.clone();
+        let old_finalized_id = chain.finalized_block_id.clone();
+        *chain = new_chain;
+        {
+            let mut tx_pool = self.tx_pool_p.lock().unwrap();
+            let finalized_tx_ids: Vec<TxId> = tx_pool
+                .pool_tx_ids
+                .iter()
+                .filter(|tx_id| chain.finalized_tx_ids.contains(*tx_id))
+                .cloned()
+                .collect();
+            for tx_id in finalized_tx_ids {
+                tx_pool.del_tx(tx_id);
+            }
+        }
+        remove_finalized_txs(&chain, &self.tx_pool_p);
+        *self.miner_cancel_p.write().unwrap() = true;
+        let events = chain_events_since(&chain, &old_working_id, &old_finalized_id);
+        drop(chain);
+        publish_events(&self.event_subscribers_p, events);
+        Ok(checkpoint)
+    }
+
+    /// Simulate a transaction against the current working chain without adding it to the tx pool.
+    /// Return the reason why the transaction would be rejected, if any.
+    pub fn simulate_tx(&self, transaction: &Transaction) -> Result<(), TxRejectReason> {
+        let chain = self.chain_p.read().unwrap();
+        let tx_pool = self.tx_pool_p.lock().unwrap();
+        simulate_tx_against(&chain, &tx_pool, transaction)
+    }
+
+    /// Get the transactions sent or received by the given address, the most recent first (see `address_history`).
+    pub fn get_address_history(&self, user_id: &str) -> Vec<AddressHistoryEntry> {
+        let chain = self.chain_p.read().unwrap();
+        let tx_pool = self.tx_pool_p.lock().unwrap();
+        address_history(&chain, &tx_pool, user_id)
     }
 
     /// Get the serialized chain as a json string.
     pub fn get_serialized_chain(&self) -> String {
-        let chain = self.chain_p.lock().unwrap().clone();
+        let chain = self.chain_p.read().unwrap().clone();
         serde_json::to_string_pretty(&chain).unwrap()
     }
 
@@ -0,0 +1,239 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// This file implements the JSON-RPC endpoint of bin_nakamoto over HTTP, served on 127.0.0.1 at the port given by
+// `bin_nakamoto --rpc-port <port>`, so that external tools (e.g. a block explorer) can query the node without spawning it.
+// Every HTTP POST carries one JSON-RPC 2.0 request, e.g. `{"jsonrpc": "2.0", "id": 1, "method": "getbalance", "params": ["<user_id>"]}`:
+// - `getbalance [user_id, branch?]`: the balance on the "Finalized" (default) or "WorkingTip" branch
+// - `publishtx [data_string, signature]`: true once the transaction is queued, or an error with the reason it is rejected
+// - `getblock [block_id or height]`: the block, by id or by its height on the longest chain
+// - `getchainstatus []`: the chain status
+// Every call goes through `handle_remote_request`, like the json IPC socket and the gRPC server, so all the interfaces
+// answer the same way. One request is answered per connection, which is then closed.
+// The requests must have the `Content-Type: application/json` header, which a web page can only send to another origin
+// after a CORS preflight this server never answers, so that a page open in a browser cannot publish txs to the node.
+
+use crate::nakamoto::{BalanceBranch, Nakamoto};
+use crate::{handle_remote_request, IPCMessageReq, IPCMessageResp, MAX_IPC_FRAME_BYTES};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+/// The maximum size of the request line and the headers of an HTTP request.
+const MAX_RPC_HEADER_BYTES: usize = 16 * 1024;
+
+/// How long a connection may take to send its request before it is closed.
+const RPC_READ_TIMEOUT_MS: u64 = 10_000;
+
+/// The JSON-RPC error codes (see the JSON-RPC 2.0 specification).
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// The error code of a request refused by the node (e.g. a rejected transaction or an unknown block).
+const NODE_ERROR: i64 = -32000;
+
+/// A JSON-RPC error (code, message).
+type RpcError = (i64, String);
+
+/// Serve the JSON-RPC endpoint on 127.0.0.1 at the given port in background threads, one per connection.
+/// A failure to start (e.g. the port is in use) is reported on stderr, like for the IPC socket.
+pub fn serve_rpc(port: u16, nakamoto: Nakamoto) {
+    let listener = match TcpListener::bind(("127.0.0.1", port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Cannot serve JSON-RPC on 127.0.0.1:{}: {}", port, e);
+            return;
+        }
+    };
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let nakamoto = nakamoto.clone();
+            thread::spawn(move || serve_rpc_connection(stream, nakamoto));
+        }
+    });
+}
+
+/// Answer the HTTP request of one connection and close it.
+fn serve_rpc_connection(stream: TcpStream, nakamoto: Nakamoto) {
+    let _ = stream.set_read_timeout(Some(Duration::from_millis(RPC_READ_TIMEOUT_MS)));
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return,
+    };
+    let (status, body) = match read_http_request(&mut BufReader::new(stream)) {
+        Ok(body) => ("200 OK", handle_rpc(&nakamoto, &body).to_string()),
+        Err((status, msg)) => (status, msg),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    let _ = writer.write_all(response.as_bytes());
+}
+
+/// Read an HTTP POST request and return its body, or the HTTP status and message to answer instead.
+fn read_http_request(reader: &mut impl BufRead) -> Result<String, (&'static str, String)> {
+    let bad_request = |msg: &str| ("400 Bad Request", msg.to_string());
+    let mut header_bytes = 0;
+    let mut read_line = |reader: &mut dyn BufRead| -> Result<String, (&'static str, String)> {
+        let mut line = String::new();
+        let read = Read::take(&mut *reader, (MAX_RPC_HEADER_BYTES - header_bytes) as u64)
+            .read_line(&mut line)
+            .map_err(|_| bad_request("Unreadable request"))?;
+        header_bytes += read;
+        if !line.ends_with('\n') {
+            return Err(bad_request("Truncated or oversized request header"));
+        }
+        Ok(line.trim_end().to_string())
+    };
+    let request_line = read_line(reader)?;
+    let method = request_line
+        .split(' ')
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    let mut content_len = None;
+    let mut is_json = false;
+    loop {
+        let line = read_line(reader)?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_len = Some(
+                    value
+                        .trim()
+                        .parse::<usize>()
+                        .map_err(|_| bad_request("Invalid Content-Length"))?,
+                );
+            } else if name.trim().eq_ignore_ascii_case("content-type") {
+                is_json = value
+                    .split(';')
+                    .next()
+                    .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"));
+            }
+        }
+    }
+    if method != "POST" {
+        return Err((
+            "405 Method Not Allowed",
+            "JSON-RPC requests are sent with POST".to_string(),
+        ));
+    }
+    if !is_json {
+        return Err((
+            "415 Unsupported Media Type",
+            "JSON-RPC requests are sent with Content-Type: application/json".to_string(),
+        ));
+    }
+    let content_len =
+        content_len.ok_or(("411 Length Required", "Missing Content-Length".to_string()))?;
+    if content_len > MAX_IPC_FRAME_BYTES {
+        return Err((
+            "413 Payload Too Large",
+            "Request body too large".to_string(),
+        ));
+    }
+    let mut body = vec![0; content_len];
+    reader
+        .read_exact(&mut body)
+        .map_err(|_| bad_request("Truncated request body"))?;
+    String::from_utf8(body).map_err(|_| bad_request("Request body is not valid UTF-8"))
+}
+
+/// Answer one JSON-RPC request (the body of an HTTP request) with the JSON-RPC response object.
+pub(crate) fn handle_rpc(nakamoto: &Nakamoto, body: &str) -> Value {
+    let request: Value = match serde_json::from_str(body) {
+        Ok(request) => request,
+        Err(e) => return rpc_response(Value::Null, Err((PARSE_ERROR, e.to_string()))),
+    };
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let result = match request.get("method").and_then(Value::as_str) {
+        Some(method) => {
+            let params = request.get("params").cloned().unwrap_or(json!([]));
+            rpc_ipc_request(method, &params)
+                .and_then(|req| rpc_result(handle_remote_request(nakamoto, Ok(req))))
+        }
+        None => Err((INVALID_REQUEST, "A request needs a method".to_string())),
+    };
+    rpc_response(id, result)
+}
+
+/// Build the JSON-RPC response object with the given id.
+fn rpc_response(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err((code, message)) => {
+            json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+        }
+    }
+}
+
+/// Get the IPC request of a JSON-RPC method and its positional parameters.
+fn rpc_ipc_request(method: &str, params: &Value) -> Result<IPCMessageReq, RpcError> {
+    let params = params
+        .as_array()
+        .ok_or_else(|| (INVALID_PARAMS, "The params are an array".to_string()))?;
+    let string_param = |index: usize, name: &str| -> Result<String, RpcError> {
+        params
+            .get(index)
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .ok_or_else(|| (INVALID_PARAMS, format!("Missing string param {}", name)))
+    };
+    match method {
+        "getbalance" => {
+            let branch = match params.get(1) {
+                None => BalanceBranch::Finalized,
+                Some(branch) => serde_json::from_value(branch.clone()).map_err(|_| {
+                    (
+                        INVALID_PARAMS,
+                        "The branch is \"Finalized\" or \"WorkingTip\"".to_string(),
+                    )
+                })?,
+            };
+            Ok(IPCMessageReq::GetAddressBalance(
+                string_param(0, "user_id")?,
+                branch,
+            ))
+        }
+        "publishtx" => Ok(IPCMessageReq::PublishTx(
+            string_param(0, "data_string")?,
+            string_param(1, "signature")?,
+        )),
+        "getblock" => match params.first() {
+            Some(Value::String(block_id)) => Ok(IPCMessageReq::RequestBlock(block_id.clone())),
+            Some(height) if height.is_u64() => Ok(IPCMessageReq::RequestBlockAtHeight(
+                height.as_u64().unwrap(),
+            )),
+            _ => Err((
+                INVALID_PARAMS,
+                "Missing param block_id (string) or height (number)".to_string(),
+            )),
+        },
+        "getchainstatus" => Ok(IPCMessageReq::RequestChainStatus),
+        _ => Err((METHOD_NOT_FOUND, format!("Unknown method {}", method))),
+    }
+}
+
+/// Get the JSON-RPC result of the response to one of the named methods.
+fn rpc_result(resp: IPCMessageResp) -> Result<Value, RpcError> {
+    match resp {
+        IPCMessageResp::AddressBalance(_, _, balance) => Ok(json!(balance)),
+        IPCMessageResp::PublishTxDone => Ok(json!(true)),
+        IPCMessageResp::PublishTxRejected(_, reason) => Err((NODE_ERROR, reason.to_string())),
+        IPCMessageResp::BlockData(block_json) | IPCMessageResp::BlockAtHeight(_, block_json) => {
+            serde_json::from_str(&block_json).map_err(|e| (NODE_ERROR, e.to_string()))
+        }
+        IPCMessageResp::ChainStatus(status) => Ok(json!(status)),
+        IPCMessageResp::Notify(msg) => Err((NODE_ERROR, msg)),
+        resp => Err((NODE_ERROR, format!("Unexpected response {:?}", resp))),
+    }
+}
@@ -0,0 +1,106 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// This file implements the WebSocket endpoint of bin_nakamoto, served at `ws_listen_addr` (see the node config).
+// Every connection receives the events of the node (new blocks, new transactions, reorgs, etc.) as json text frames as
+// they happen, and a snapshot of the status of the node when it connects and then periodically, so that a browser page
+// (see `dashboard.html`) can show the nodes of a demo live. The endpoint only pushes: the frames sent by the browser are
+// ignored, and the node is never changed through it.
+
+use crate::nakamoto::{ChainEvent, Nakamoto, NodeMinerStatus};
+use lib_chain::block::ChainStatus;
+use lib_network::netchannel::NetAddress;
+use lib_network::p2pnetwork::NetworkStatus;
+use lib_tx_pool::pool::TxPoolStatus;
+use serde::{Deserialize, Serialize};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread;
+use std::time::{Duration, Instant};
+use tungstenite::{Message, WebSocket};
+
+/// The interval between two status snapshots sent to a connection.
+pub const WS_STATUS_INTERVAL_MS: u64 = 2000;
+
+/// A json message pushed to the WebSocket connections.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum WsMessage {
+    /// An event of the node, as pushed on stdout after SubscribeEvents (event)
+    Event(ChainEvent),
+    /// The status of the node, by component (status_snapshot)
+    Status(Box<StatusSnapshot>),
+}
+
+/// The status of every component of the node, as sent in a `WsMessage::Status`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StatusSnapshot {
+    pub chain: ChainStatus,
+    pub net: NetworkStatus,
+    pub miner: NodeMinerStatus,
+    pub tx_pool: TxPoolStatus,
+}
+
+/// Get the status of every component of the node, as sent in a `WsMessage::Status`.
+fn status_snapshot(nakamoto: &Nakamoto) -> StatusSnapshot {
+    StatusSnapshot {
+        chain: nakamoto.get_chain_status(),
+        net: nakamoto.get_network_status(),
+        miner: nakamoto.get_miner_status(),
+        tx_pool: nakamoto.get_txpool_status(),
+    }
+}
+
+/// Serve the WebSocket endpoint at the given address in background threads, one per connection.
+pub fn serve_ws(addr: NetAddress, nakamoto: Nakamoto) {
+    let listener = match TcpListener::bind(format!("{}:{}", addr.ip, addr.port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Cannot serve WebSocket on {}:{}: {}", addr.ip, addr.port, e);
+            return;
+        }
+    };
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let nakamoto = nakamoto.clone();
+            thread::spawn(move || serve_ws_connection(stream, nakamoto));
+        }
+    });
+}
+
+/// Push the events and the status snapshots to one connection until it is closed.
+fn serve_ws_connection(stream: TcpStream, nakamoto: Nakamoto) {
+    let mut socket = match tungstenite::accept(stream) {
+        Ok(socket) => socket,
+        Err(e) => {
+            eprintln!("[WebSocket] Handshake failed: {}", e);
+            return;
+        }
+    };
+    let events = nakamoto.subscribe_events();
+    let interval = Duration::from_millis(WS_STATUS_INTERVAL_MS);
+    let mut next_snapshot = Instant::now();
+    loop {
+        let now = Instant::now();
+        let message = if now >= next_snapshot {
+            next_snapshot = now + interval;
+            WsMessage::Status(Box::new(status_snapshot(&nakamoto)))
+        } else {
+            match events.recv_timeout(next_snapshot - now) {
+                Ok(event) => WsMessage::Event(event),
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        };
+        if send(&mut socket, &message).is_err() {
+            break;
+        }
+    }
+}
+
+/// Send one message as a json text frame. It fails once the connection is closed.
+fn send(socket: &mut WebSocket<TcpStream>, message: &WsMessage) -> Result<(), String> {
+    socket
+        .send(Message::text(serde_json::to_string(message).unwrap()))
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,89 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// This file implements bin_verify, a command line front-end of lib_verify for graders and students:
+// it checks submitted json artifacts without running a node, and prints every problem found.
+//
+// Usage:
+// - `bin_verify block <BlockNode.json> <difficulty>`: check a block on its own
+// - `bin_verify state <BlockTree.json> <TxPool.json>`: check that a saved block tree and tx pool are consistent
+// - `bin_verify tx <Transaction.json>`: check the signature of a transaction
+// It exits with 0 if the artifacts are valid, 1 if they are not, and 2 on a usage error.
+
+use lib_chain::block::{BlockTree, Transaction};
+use lib_tx_pool::pool::TxPool;
+use lib_verify::verify;
+use std::fs;
+
+const USAGE: &str = "Usage: bin_verify block <BlockNode.json> <difficulty>
+       bin_verify state <BlockTree.json> <TxPool.json>
+       bin_verify tx <Transaction.json>";
+
+/// Read a json file into its string.
+fn read_file(path: &str) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path, e))
+}
+
+/// Run the check asked by the arguments. Return the problems found, or a usage error.
+fn run(args: &[String]) -> Result<Result<String, Vec<String>>, String> {
+    let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+    match args[..] {
+        ["block", path, difficulty] => {
+            let difficulty = difficulty
+                .parse::<u16>()
+                .map_err(|_| format!("The difficulty must be a number, got {:?}", difficulty))?;
+            Ok(verify::verify_block_file(path, difficulty)
+                .map(|_| format!("Block {} is valid at difficulty {}", path, difficulty))
+                .map_err(|e| vec![e]))
+        }
+        ["state", blocktree_path, txpool_path] => {
+            let loaded = read_file(blocktree_path)
+                .and_then(|json| BlockTree::from_json(&json))
+                .and_then(|blocktree| {
+                    let txpool = TxPool::from_json(&read_file(txpool_path)?)?;
+                    Ok((blocktree, txpool))
+                });
+            Ok(match loaded {
+                Ok((blocktree, txpool)) => verify::verify_state_consistency(&blocktree, &txpool)
+                    .map(|_| {
+                        format!(
+                            "{} and {} are consistent ({} blocks, {} pending transactions)",
+                            blocktree_path,
+                            txpool_path,
+                            blocktree.all_blocks.len(),
+                            txpool.pool_tx_ids.len()
+                        )
+                    }),
+                Err(e) => Err(vec![e]),
+            })
+        }
+        ["tx", path] => Ok(read_file(path)
+            .and_then(|json| {
+                serde_json::from_str::<Transaction>(&json)
+                    .map_err(|e| format!("{} is not a transaction: {}", path, e))
+            })
+            .and_then(|tx| verify::verify_tx_signature(&tx))
+            .map(|_| format!("Transaction {} is correctly signed", path))
+            .map_err(|e| vec![e])),
+        _ => Err("Unexpected arguments".to_string()),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(Ok(summary)) => println!("OK: {}", summary),
+        Ok(Err(problems)) => {
+            for problem in problems {
+                println!("INVALID: {}", problem);
+            }
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    }
+}
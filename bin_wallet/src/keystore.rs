@@ -0,0 +1,110 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// This file implements the encrypted keystore of bin_wallet: the private key of a wallet json file can be stored encrypted
+// with a passphrase instead of in plaintext (`bin_wallet encrypt <in.json> <out.json>`). The encryption key is derived from
+// the passphrase and a random salt with PBKDF2-HMAC-SHA256, and the PEM is encrypted with AES-256-GCM, so a wrong
+// passphrase or a tampered file is detected when decrypting. Such a wallet is locked until `Wallet::unlock` is called
+// (the `Unlock` IPC request), and the decrypted key is only kept in memory, in a buffer zeroized when dropped (as is the
+// AES key derived from the passphrase, once the cipher is set up).
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64ct::{Base64, Encoding};
+use rand::Rng;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use zeroize::{Zeroize, Zeroizing};
+
+/// The cipher of the encrypted keys.
+pub const KEYSTORE_CIPHER: &str = "aes-256-gcm";
+
+/// The key derivation function of the encrypted keys.
+pub const KEYSTORE_KDF: &str = "pbkdf2-hmac-sha256";
+
+/// The number of PBKDF2 iterations of the keys encrypted by `bin_wallet encrypt`.
+pub const DEFAULT_KDF_ITERATIONS: u32 = 200_000;
+
+/// The length in bytes of the random salt of the key derivation.
+const SALT_LEN: usize = 16;
+
+/// The length in bytes of the AES-GCM nonce.
+const NONCE_LEN: usize = 12;
+
+/// A private key encrypted with a passphrase, as stored in a wallet json file.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct EncryptedKey {
+    /// The cipher, `KEYSTORE_CIPHER`
+    pub cipher: String,
+    /// The key derivation function, `KEYSTORE_KDF`
+    pub kdf: String,
+    /// The number of iterations of the key derivation
+    pub iterations: u32,
+    /// The salt of the key derivation in Base64 format
+    pub salt: String,
+    /// The nonce of the cipher in Base64 format
+    pub nonce: String,
+    /// The encrypted private key (followed by the authentication tag) in Base64 format
+    pub ciphertext: String,
+}
+
+impl EncryptedKey {
+    /// Encrypt a private key with a passphrase, using a fresh salt and nonce.
+    pub fn encrypt(priv_key_pem: &str, passphrase: &str, iterations: u32) -> EncryptedKey {
+        let salt: [u8; SALT_LEN] = rand::thread_rng().gen();
+        let nonce: [u8; NONCE_LEN] = rand::thread_rng().gen();
+        let cipher = new_cipher(passphrase, &salt, iterations);
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), priv_key_pem.as_bytes())
+            .expect("AES-GCM encrypts messages of any reasonable length");
+        EncryptedKey {
+            cipher: KEYSTORE_CIPHER.to_string(),
+            kdf: KEYSTORE_KDF.to_string(),
+            iterations,
+            salt: Base64::encode_string(&salt),
+            nonce: Base64::encode_string(&nonce),
+            ciphertext: Base64::encode_string(&ciphertext),
+        }
+    }
+
+    /// Decrypt the private key with a passphrase.
+    /// A wrong passphrase and a tampered key are the same error, since the authentication tag tells them apart from neither.
+    pub fn decrypt(&self, passphrase: &str) -> Result<Zeroizing<String>, String> {
+        if self.cipher != KEYSTORE_CIPHER || self.kdf != KEYSTORE_KDF {
+            return Err(format!(
+                "Unsupported key encryption {} with {}",
+                self.cipher, self.kdf
+            ));
+        }
+        let decode = |field: &str, value: &str| {
+            Base64::decode_vec(value)
+                .map_err(|e| format!("Malformed {} of the encrypted key: {}", field, e))
+        };
+        let salt = decode("salt", &self.salt)?;
+        let nonce = decode("nonce", &self.nonce)?;
+        let ciphertext = decode("ciphertext", &self.ciphertext)?;
+        if nonce.len() != NONCE_LEN {
+            return Err("Malformed nonce of the encrypted key".to_string());
+        }
+        let cipher = new_cipher(passphrase, &salt, self.iterations);
+        let plaintext = cipher
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+            .map_err(|_| "Wrong passphrase or corrupted key".to_string())?;
+        String::from_utf8(plaintext)
+            .map(Zeroizing::new)
+            .map_err(|e| {
+                e.into_bytes().zeroize();
+                "Wrong passphrase or corrupted key".to_string()
+            })
+    }
+}
+
+/// Set up the cipher with the AES-256 key derived from a passphrase, and zeroize the key.
+fn new_cipher(passphrase: &str, salt: &[u8], iterations: u32) -> Aes256Gcm {
+    let mut key = Key::<Aes256Gcm>::default();
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, iterations, &mut key);
+    let cipher = Aes256Gcm::new(&key);
+    key.as_mut_slice().zeroize();
+    cipher
+}
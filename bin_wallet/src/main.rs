@@ -8,37 +8,32 @@
 // However, you can run it directly from the command line to test it.
 // You can see detailed instructions in the comments below.
 
+mod keystore;
+mod offline;
+mod policy;
 mod wallet;
+use lib_chain::block::SignatureAlgorithm;
+use lib_chain::payload::SignedPayload;
+use lib_chain::txtrace::{tx_id_of, TxSpan, TxTraceEvent};
 use seccompiler::*;
 use serde::{Deserialize, Serialize};
-use serde_json::*;
+use std::collections::BTreeMap;
 use std::fs;
-use std::io::{self, BufRead, Write};
+use std::io::{self, BufRead};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Instant, SystemTime};
+use zeroize::Zeroizing;
 
 /// Read a string from a file (help with debugging)
 fn read_string_from_file(filepath: &str) -> String {
-    let contents = fs::read_to_string(filepath).expect(&("Cannot read ".to_owned() + filepath));
-    contents
+    fs::read_to_string(filepath).unwrap_or_else(|_| panic!("Cannot read {}", filepath))
 }
 
 /// Write a string to a file (to help you debug)
 fn write_string_to_file(filepath: &str, content: String) {
-    fs::write(filepath, content).expect(&("Cannot write ".to_owned() + filepath));
-}
-
-/// Append a string to a file (to help you debug)
-fn append_string_to_file(filepath: &str, content: String) {
-    // if not exists, create file
-    if !std::path::Path::new(filepath).exists() {
-        fs::File::create(filepath).unwrap();
-    }
-    fs::OpenOptions::new()
-        .write(true)
-        .append(true)
-        .open(filepath)
-        .unwrap()
-        .write_all(content.as_bytes())
-        .unwrap();
+    fs::write(filepath, content).unwrap_or_else(|_| panic!("Cannot write {}", filepath));
 }
 
 /// The enum representing IPC message requests from the stdin
@@ -46,14 +41,28 @@ fn append_string_to_file(filepath: &str, content: String) {
 enum IPCMessageReq {
     /// Quit the execution
     Quit,
-    /// Initialize the wallet by deserializing the provided json string
-    Initialize(String),
-    /// Sign the provided data string using the private key
-    SignRequest(String),
+    /// Initialize the wallet by deserializing the provided json string (`wallet_json`, `auth_token`).
+    /// If an `auth_token` is given, every later SignRequest must carry the same token.
+    Initialize(String, Option<String>),
+    /// Decrypt the private key of a locked wallet with the provided passphrase (`passphrase`)
+    Unlock(String),
+    /// Sign the provided data string using the private key (`data_string`, `auth_token`)
+    SignRequest(String, Option<String>),
     /// Verify the provided (`data_string`, `signature_in_base64`) using the public key
     VerifyRequest(String, String),
+    /// Verify each (`data_string`, `signature_in_base64`, `pub_key`) with its own public key, e.g. the transactions of a received block.
+    /// It does not need the wallet to be initialized.
+    VerifyBatch(Vec<(String, String, String)>),
     /// Get the user info
     GetUserInfo,
+    /// Get the public key to embed in the data_string of the transactions, if the user id is a hashed address
+    GetSenderKey,
+    /// Get the signature scheme of the wallet, which the data_string of the transactions must name
+    GetSignatureAlgorithm,
+    /// Derive the address at the given index from the seed of the wallet (`index`). It is listed by ListAddresses afterwards.
+    DeriveAddress(u32),
+    /// List the derived addresses: the first `hd_address_count` of the wallet json file and the ones derived since Initialize
+    ListAddresses,
 }
 
 /// The enum representing IPC message responses to the stdout
@@ -61,17 +70,244 @@ enum IPCMessageReq {
 enum IPCMessageResp {
     /// The wallet has been initialized
     Initialized,
+    /// The wallet has been initialized, but its private key is encrypted: it must be unlocked before signing
+    Locked,
+    /// The private key has been decrypted
+    Unlocked,
+    /// The private key could not be decrypted, e.g. the passphrase is wrong (Reason)
+    UnlockRejected(String),
     /// The wallet is quitting normally
     Quitting,
+    /// The sign request passed the checks and is queued for signing; its SignResponse follows when ready (DataString)
+    SignPending(String),
     /// The response to a sign request (DataString, Signature)
     SignResponse(String, String),
+    /// The sign request has been refused and nothing was signed (DataString, Reason)
+    SignRejected(String, String),
     /// The response to a verify request (isSuccess, DataString)
     VerifyResponse(bool, String),
+    /// The response to a verify batch request: whether each signature is valid, in order, and the time spent verifying (is_valid, elapsed_us)
+    VerifyBatchResponse(Vec<bool>, u64),
     /// The response to the get user info request (username, user_id). User Id is transformed from the public key.
     UserInfo(String, String),
+    /// The response to the get sender key request (sender_pub_key): None if the user id is the public key itself
+    SenderKey(Option<String>),
+    /// The response to the get signature algorithm request (algorithm)
+    SignatureAlgorithm(SignatureAlgorithm),
+    /// The response to a derive address request (index, user_id)
+    DerivedAddress(u32, String),
+    /// The address could not be derived, e.g. the wallet has no seed (index, Reason)
+    DeriveRejected(u32, String),
+    /// The response to the list addresses request, ordered by index ([(index, user_id)])
+    AddressList(Vec<(u32, String)>),
+}
+
+/// Check the token attached to a request against the token given at Initialize.
+/// Requests are always accepted if no token was set. The comparison runs in constant time
+/// so the token cannot be guessed byte by byte from the response latency.
+fn check_auth_token(expected: Option<&str>, provided: Option<&str>) -> bool {
+    let expected = match expected {
+        Some(expected) => expected.as_bytes(),
+        None => return true,
+    };
+    let provided = match provided {
+        Some(provided) => provided.as_bytes(),
+        None => return false,
+    };
+    if expected.len() != provided.len() {
+        return false;
+    }
+    expected
+        .iter()
+        .zip(provided.iter())
+        .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+        == 0
+}
+
+/// The work of the signing thread, done in the order of the requests.
+enum SignJob {
+    /// Sign the data string with the wallet (wallet, data_string)
+    Sign(Arc<wallet::Wallet>, String),
+    /// Answer Quitting, once the signatures requested before it are delivered
+    Quit,
+}
+
+/// Start the thread that signs the data strings sent to `jobs` one after the other, passing each response to `respond`.
+/// Signing with a large key takes a while, so the requests are acknowledged (SignPending) by the main loop first
+/// and the other requests are answered in the meantime. The seccomp policy must allow creating this thread.
+fn spawn_signer(
+    jobs: Receiver<SignJob>,
+    respond: impl Fn(IPCMessageResp) + Send + 'static,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        for job in jobs {
+            match job {
+                SignJob::Sign(wallet, data) => {
+                    let signature = wallet.sign(&data);
+                    trace_signed(&data, &signature);
+                    respond(IPCMessageResp::SignResponse(data, signature));
+                }
+                SignJob::Quit => respond(IPCMessageResp::Quitting),
+            }
+        }
+    })
+}
+
+/// Write the Signed span of the transaction to stderr (see `lib_chain::txtrace`), so that bin_client can assemble its
+/// timeline. Stdout only carries the IPC responses.
+fn trace_signed(data_string: &str, signature: &String) {
+    if let Some(tx_id) = tx_id_of(data_string, signature) {
+        let now_ms = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let event = TxTraceEvent::new(tx_id, TxSpan::Signed, "wallet", now_ms, String::new());
+        eprintln!("{}", event.to_log_line());
+    }
+}
+
+/// Get the user id of the derived address at the given index, deriving it only once per session
+/// since generating a key pair takes a while.
+fn derived_address(
+    wallet: &wallet::Wallet,
+    derived_addresses: &mut BTreeMap<u32, String>,
+    index: u32,
+) -> std::result::Result<String, String> {
+    if let Some(user_id) = derived_addresses.get(&index) {
+        return Ok(user_id.clone());
+    }
+    let user_id = wallet.derive(index)?.get_user_id();
+    derived_addresses.insert(index, user_id.clone());
+    Ok(user_id)
+}
+
+/// Prompt on stderr and read a passphrase from the first line of stdin, for the command-line modes.
+fn read_passphrase(prompt: &str) -> std::result::Result<Zeroizing<String>, String> {
+    eprint!("{}", prompt);
+    let mut passphrase = Zeroizing::new(String::new());
+    io::stdin()
+        .read_line(&mut passphrase)
+        .map_err(|e| format!("Cannot read the passphrase: {}", e))?;
+    Ok(Zeroizing::new(
+        passphrase.trim_end_matches(['\r', '\n']).to_string(),
+    ))
+}
+
+/// Read a wallet json file, which holds the private key, into a buffer zeroized once it is parsed.
+fn read_wallet_file(filepath: &str) -> Zeroizing<String> {
+    Zeroizing::new(read_string_from_file(filepath))
+}
+
+/// Unlock a wallet with a passphrase read from stdin if its private key is encrypted, for the command-line modes.
+fn unlock_with_prompt(mut wallet: wallet::Wallet) -> std::result::Result<wallet::Wallet, String> {
+    if wallet.is_locked() {
+        wallet.unlock(&read_passphrase("Passphrase: ")?)?;
+    }
+    Ok(wallet)
+}
+
+/// The size of the RSA keys of the wallets generated by `bin_wallet generate`, the same as the ones of the provided wallets.
+const RSA_KEY_BITS: usize = 384;
+
+/// Write a response to stdout, one json per line.
+fn write_response(response: IPCMessageResp) {
+    println!("{}", serde_json::to_string(&response).unwrap());
 }
 
 fn main() {
+    // Alternatively, bin_wallet signs a transaction file offline and exits (see offline.rs):
+    // bin_wallet sign-file <in.json> <out.json> <wallet.json>
+    if std::env::args().nth(1).as_deref() == Some("sign-file") {
+        let args: Vec<String> = std::env::args().skip(2).collect();
+        let [in_path, out_path, wallet_path] = args.as_slice() else {
+            eprintln!("Usage: bin_wallet sign-file <in.json> <out.json> <wallet.json>");
+            std::process::exit(2);
+        };
+        let signed = wallet::Wallet::from_json(&read_wallet_file(wallet_path))
+            .and_then(unlock_with_prompt)
+            .and_then(|wallet| offline::sign_file(&wallet, in_path, out_path));
+        match signed {
+            Ok(tx) => println!(
+                "Signed {} -> {}: {:?}{}\nWritten to {}",
+                tx.sender,
+                tx.receiver,
+                tx.message,
+                tx.outputs
+                    .iter()
+                    .map(|output| format!("\n  and ${} to {}", output.amount, output.receiver))
+                    .collect::<String>(),
+                out_path
+            ),
+            Err(e) => {
+                eprintln!("Not signed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // Or it encrypts the private key of a wallet file with a passphrase read from stdin (see keystore.rs):
+    // bin_wallet encrypt <in.json> <out.json>
+    if std::env::args().nth(1).as_deref() == Some("encrypt") {
+        let args: Vec<String> = std::env::args().skip(2).collect();
+        let [in_path, out_path] = args.as_slice() else {
+            eprintln!("Usage: bin_wallet encrypt <in.json> <out.json>");
+            std::process::exit(2);
+        };
+        let encrypted =
+            wallet::Wallet::from_json(&read_wallet_file(in_path)).and_then(|mut wallet| {
+                let passphrase = read_passphrase("New passphrase: ")?;
+                if passphrase.is_empty() {
+                    return Err("The passphrase is empty".to_string());
+                }
+                wallet.encrypt_key(&passphrase, keystore::DEFAULT_KDF_ITERATIONS)?;
+                Ok(wallet)
+            });
+        match encrypted {
+            Ok(wallet) => {
+                write_string_to_file(out_path, serde_json::to_string_pretty(&wallet).unwrap());
+                println!("Written to {}", out_path);
+            }
+            Err(e) => {
+                eprintln!("Not encrypted: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // Or it generates a new wallet file, with an Ed25519 key pair by default:
+    // bin_wallet generate <out.json> <user_name> [ed25519|rsa]
+    if std::env::args().nth(1).as_deref() == Some("generate") {
+        let args: Vec<String> = std::env::args().skip(2).collect();
+        let new_wallet = match args.as_slice() {
+            [out_path, user_name] => {
+                Some((out_path, wallet::Wallet::new_ed25519(user_name.clone())))
+            }
+            [out_path, user_name, algorithm] => match algorithm.as_str() {
+                "ed25519" => Some((out_path, wallet::Wallet::new_ed25519(user_name.clone()))),
+                "rsa" => Some((
+                    out_path,
+                    wallet::Wallet::new(user_name.clone(), RSA_KEY_BITS),
+                )),
+                _ => None,
+            },
+            _ => None,
+        };
+        let Some((out_path, new_wallet)) = new_wallet else {
+            eprintln!("Usage: bin_wallet generate <out.json> <user_name> [ed25519|rsa]");
+            std::process::exit(2);
+        };
+        write_string_to_file(out_path, serde_json::to_string_pretty(&new_wallet).unwrap());
+        println!(
+            "Generated the {:?} wallet of {}\nWritten to {}",
+            new_wallet.algorithm,
+            new_wallet.get_user_id(),
+            out_path
+        );
+        return;
+    }
+
     // bin_wallet has only one optional argument: the path to the seccomp policy file
     // If the argument is provided, bin_wallet will read and apply the seccomp policy at the beginning of the program
     // Otherwise, it will proceed to the normal execution
@@ -85,7 +321,7 @@ fn main() {
         .unwrap();
         let filter = filter_map.get("main_thread").unwrap();
 
-        seccompiler::apply_filter(&filter).unwrap();
+        seccompiler::apply_filter(filter).unwrap();
     }
 
     // The main logic of the bin_wallet starts here
@@ -93,43 +329,140 @@ fn main() {
     // The first IPC call is always the Initialize call with the wallet data provided.
     // After that, there can be arbitrary number of SignRequest, VerifyRequest, and GetUserInfo calls.
     // Eventually, the Quit call will be received and the program will exit.
+    use policy::PolicyEnforcer;
     use wallet::Wallet;
     // Please fill in the blank
     //todo!();
-    let mut wallet: Option<Wallet> = None;
+    let mut wallet: Option<Arc<Wallet>> = None;
+    let mut auth_token: Option<String> = None;
+    let mut policy_enforcer: Option<PolicyEnforcer> = None;
+    let mut derived_addresses: BTreeMap<u32, String> = BTreeMap::new();
+    let (sign_jobs, sign_jobs_rx) = mpsc::channel();
+    let signer = spawn_signer(sign_jobs_rx, write_response);
     let stdin = io::stdin();
     for line in stdin.lock().lines() {
-        let input = line.unwrap();
+        // A request may hold a wallet json or a passphrase, so the line is zeroized once parsed
+        let input = Zeroizing::new(line.unwrap());
         let request: IPCMessageReq =
             serde_json::from_str(&input).expect("Failed to parse input as IPCMessageReq");
         let response = match request {
-            IPCMessageReq::Quit => IPCMessageResp::Quitting,
-            IPCMessageReq::Initialize(wallet_json) => {
-                wallet = Some(
-                    serde_json::from_str(&wallet_json)
-                        .expect("Failed to parse wallet_json as Wallet"),
-                );
-                IPCMessageResp::Initialized
+            IPCMessageReq::Quit => {
+                // Answered by the signing thread, after the pending signatures
+                sign_jobs.send(SignJob::Quit).unwrap();
+                continue;
+            }
+            IPCMessageReq::Initialize(wallet_json, token) => {
+                let wallet_json = Zeroizing::new(wallet_json);
+                let new_wallet =
+                    Wallet::from_json(&wallet_json).expect("Failed to parse wallet_json as Wallet");
+                policy_enforcer = Some(PolicyEnforcer::new(new_wallet.spending_policy.clone()));
+                let is_locked = new_wallet.is_locked();
+                wallet = Some(Arc::new(new_wallet));
+                auth_token = token;
+                derived_addresses.clear();
+                if is_locked {
+                    IPCMessageResp::Locked
+                } else {
+                    IPCMessageResp::Initialized
+                }
             }
-            IPCMessageReq::SignRequest(data) => {
+            IPCMessageReq::Unlock(passphrase) => {
+                let passphrase = Zeroizing::new(passphrase);
+                let current = wallet.as_ref().expect("Wallet not initialized");
+                // The signatures already queued keep the wallet they were requested with
+                let mut unlocked = Wallet::clone(current);
+                match unlocked.unlock(&passphrase) {
+                    Ok(()) => {
+                        wallet = Some(Arc::new(unlocked));
+                        IPCMessageResp::Unlocked
+                    }
+                    Err(e) => IPCMessageResp::UnlockRejected(e),
+                }
+            }
+            IPCMessageReq::SignRequest(data, token) => {
                 let wallet = wallet.as_ref().expect("Wallet not initialized");
-                let signature = wallet.sign(&data);
-                IPCMessageResp::SignResponse(data, signature)
+                let policy_enforcer = policy_enforcer.as_mut().unwrap();
+                let now_ms = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap()
+                    .as_millis() as u64;
+                if !check_auth_token(auth_token.as_deref(), token.as_deref()) {
+                    IPCMessageResp::SignRejected(data, "Invalid auth token".to_string())
+                } else if wallet.is_locked() {
+                    IPCMessageResp::SignRejected(data, "The wallet is locked".to_string())
+                } else if let Err(e) = SignedPayload::decode(&data)
+                    .and_then(|payload| wallet.check_algorithm(&payload))
+                {
+                    // Only transaction payloads are signed, in the encoding the chain verifies
+                    IPCMessageResp::SignRejected(data, e)
+                } else if let Err(e) = policy_enforcer.check_and_record(&data, now_ms) {
+                    IPCMessageResp::SignRejected(data, format!("Policy violation: {}", e))
+                } else {
+                    sign_jobs
+                        .send(SignJob::Sign(wallet.clone(), data.clone()))
+                        .unwrap();
+                    IPCMessageResp::SignPending(data)
+                }
             }
             IPCMessageReq::VerifyRequest(data, signature) => {
                 let wallet = wallet.as_ref().expect("Wallet not initialized");
                 let is_valid = wallet.verify(&data, &signature);
                 IPCMessageResp::VerifyResponse(is_valid, data)
             }
+            IPCMessageReq::VerifyBatch(batch) => {
+                let started = Instant::now();
+                let results = batch
+                    .iter()
+                    .map(|(data, signature, pub_key)| {
+                        // The signature scheme is named by the data_string (RSA for the older encodings)
+                        let algorithm = SignedPayload::decode(data)
+                            .map(|payload| payload.algorithm)
+                            .unwrap_or_default();
+                        Wallet::verify_with_key(algorithm, pub_key, data, signature)
+                    })
+                    .collect();
+                IPCMessageResp::VerifyBatchResponse(results, started.elapsed().as_micros() as u64)
+            }
             IPCMessageReq::GetUserInfo => {
                 let wallet = wallet.as_ref().expect("Wallet not initialized");
                 let user_id = wallet.get_user_id();
                 let username = wallet.get_user_name();
                 IPCMessageResp::UserInfo(username, user_id)
             }
+            IPCMessageReq::GetSenderKey => {
+                let wallet = wallet.as_ref().expect("Wallet not initialized");
+                IPCMessageResp::SenderKey(wallet.get_sender_pub_key())
+            }
+            IPCMessageReq::GetSignatureAlgorithm => {
+                let wallet = wallet.as_ref().expect("Wallet not initialized");
+                IPCMessageResp::SignatureAlgorithm(wallet.algorithm)
+            }
+            IPCMessageReq::DeriveAddress(index) => {
+                let wallet = wallet.as_ref().expect("Wallet not initialized");
+                match derived_address(wallet, &mut derived_addresses, index) {
+                    Ok(user_id) => IPCMessageResp::DerivedAddress(index, user_id),
+                    Err(e) => IPCMessageResp::DeriveRejected(index, e),
+                }
+            }
+            IPCMessageReq::ListAddresses => {
+                let wallet = wallet.as_ref().expect("Wallet not initialized");
+                let mut addresses = vec![];
+                if wallet.get_hd_seed().is_ok() {
+                    for index in 0..wallet.hd_address_count {
+                        let user_id = derived_address(wallet, &mut derived_addresses, index)
+                            .expect("Failed to derive an address");
+                        addresses.push((index, user_id));
+                    }
+                }
+                addresses.extend(
+                    derived_addresses
+                        .range(wallet.hd_address_count..)
+                        .map(|(index, user_id)| (*index, user_id.clone())),
+                );
+                IPCMessageResp::AddressList(addresses)
+            }
         };
-        let output = serde_json::to_string(&response).unwrap();
-        println!("{}", output);
+        write_response(response);
         // if request == IPCMessageReq::Quit {
         //     println!("{}\n", serde_json::to_string(&IPCMessageResp::Quitting).unwrap());
         //     break;
@@ -137,13 +470,29 @@ fn main() {
     }
 
     //println!("{}\n", serde_json::to_string(&IPCMessageResp::Quitting).unwrap());
+
+    // Deliver the signatures still queued before exiting
+    drop(sign_jobs);
+    signer.join().unwrap();
 }
 
 #[cfg(test)]
 mod test {
     use crate::{
-        read_string_from_file, wallet::Wallet, write_string_to_file, IPCMessageReq, IPCMessageResp,
+        check_auth_token, derived_address, keystore,
+        offline::{sign_file, OfflineTx},
+        policy::{PolicyEnforcer, SpendingPolicy},
+        read_string_from_file, spawn_signer,
+        wallet::{Wallet, WALLET_MIGRATIONS},
+        write_string_to_file, IPCMessageResp, SignJob,
     };
+    use lib_chain::address::AddressFormat;
+    use lib_chain::block::{SignatureAlgorithm, Transaction};
+    use lib_chain::payload::SignedPayload;
+    use lib_chain::schema::{check_published_schema, schema_json};
+    use std::collections::BTreeMap;
+    use std::sync::{mpsc, Arc, Mutex};
+    use zeroize::Zeroizing;
 
     /// This test generates a new wallet and writes it to a file.
     #[test]
@@ -159,7 +508,7 @@ mod test {
         let bin_wallet: Wallet =
             serde_json::from_str(&read_string_from_file("../tests/_secrets/Wallet.B.json"))
                 .unwrap();
-        println!("Private key Pem:\n{}\n", bin_wallet.priv_key_pem);
+        println!("Private key Pem:\n{}\n", *bin_wallet.priv_key_pem);
         println!("Public key Pem:\n{}\n", bin_wallet.pub_key_pem);
         let msg = "hello world";
         let sig64 = bin_wallet.sign(msg);
@@ -168,6 +517,80 @@ mod test {
         println!("msg: {}\nsig64: {}\nverify: {}", msg, sig64, verify_result);
     }
 
+    /// The signing thread answers the sign requests in order, and Quitting only after the signatures requested before it.
+    #[test]
+    fn test_signer_queue() {
+        let bin_wallet = Arc::new(Wallet::new("Queue".to_string(), 384));
+        let responses = Arc::new(Mutex::new(vec![]));
+        let (sign_jobs, sign_jobs_rx) = mpsc::channel();
+        let signer = {
+            let responses = responses.clone();
+            spawn_signer(sign_jobs_rx, move |response| {
+                responses.lock().unwrap().push(response)
+            })
+        };
+        for data in ["first", "second"] {
+            sign_jobs
+                .send(SignJob::Sign(bin_wallet.clone(), data.to_string()))
+                .unwrap();
+        }
+        sign_jobs.send(SignJob::Quit).unwrap();
+        drop(sign_jobs);
+        signer.join().unwrap();
+
+        let responses = responses.lock().unwrap();
+        assert_eq!(responses.len(), 3);
+        for (response, expected) in responses.iter().zip(["first", "second"]) {
+            match response {
+                IPCMessageResp::SignResponse(data, signature) => {
+                    assert_eq!(data, expected);
+                    assert!(bin_wallet.verify(data, signature));
+                }
+                other => panic!("Unexpected response {:?}", other),
+            }
+        }
+        assert!(matches!(responses[2], IPCMessageResp::Quitting));
+    }
+
+    /// A signature is verified against the given key only; malformed keys and signatures are invalid, not errors.
+    #[test]
+    fn test_verify_with_key() {
+        let bin_wallet = Wallet::new("Batch".to_string(), 384);
+        let other_wallet = Wallet::new("Other".to_string(), 384);
+        let (pub_key, other_pub_key) = (bin_wallet.get_pub_key_id(), other_wallet.get_pub_key_id());
+        let signature = bin_wallet.sign("hello");
+        assert!(Wallet::verify_with_key(
+            SignatureAlgorithm::Rsa,
+            &pub_key,
+            "hello",
+            &signature
+        ));
+        assert!(!Wallet::verify_with_key(
+            SignatureAlgorithm::Rsa,
+            &pub_key,
+            "hello!",
+            &signature
+        ));
+        assert!(!Wallet::verify_with_key(
+            SignatureAlgorithm::Rsa,
+            &other_pub_key,
+            "hello",
+            &signature
+        ));
+        assert!(!Wallet::verify_with_key(
+            SignatureAlgorithm::Rsa,
+            "not a key",
+            "hello",
+            &signature
+        ));
+        assert!(!Wallet::verify_with_key(
+            SignatureAlgorithm::Rsa,
+            &pub_key,
+            "hello",
+            "not a signature"
+        ));
+    }
+
     /// This test reads a wallet from a file and uses it to verify a message signed by a reference implementation.
     #[test]
     fn test_bin_wallet_verifying_alice() {
@@ -184,4 +607,395 @@ mod test {
         let verify_result = bin_wallet.verify(&msg, &sig2);
         assert!(!verify_result);
     }
+
+    /// A wallet with hashed addresses signs transactions that embed its public key, which the chain verifies.
+    #[test]
+    fn test_hashed_address_signing() {
+        let mut bin_wallet = Wallet::new("Hashed".to_string(), 384);
+        assert_eq!(bin_wallet.get_user_id(), bin_wallet.get_pub_key_id());
+        assert!(bin_wallet.get_sender_pub_key().is_none());
+        bin_wallet.address_format = AddressFormat::Sha256Base58;
+        let user_id = bin_wallet.get_user_id();
+        let sender_pub_key = bin_wallet.get_sender_pub_key().unwrap();
+        assert_eq!(sender_pub_key, bin_wallet.get_pub_key_id());
+        assert_ne!(user_id, sender_pub_key);
+
+        let data_string =
+            serde_json::to_string(&(&user_id, "B", "SEND $10   // By Hashed", &sender_pub_key))
+                .unwrap();
+        let tx =
+            Transaction::from_data_string(&data_string, bin_wallet.sign(&data_string)).unwrap();
+        assert!(tx.verify_sig().is_ok());
+        let mut spoofed_tx = tx.clone();
+        spoofed_tx.sender = "another address".to_string();
+        assert!(spoofed_tx.verify_sig().is_err());
+
+        // the address format is kept in the wallet json file, and older files use the raw public key
+        let wallet_json = serde_json::to_string(&bin_wallet).unwrap();
+        assert_eq!(
+            Wallet::from_json(&wallet_json).unwrap().get_user_id(),
+            user_id
+        );
+    }
+
+    /// A transaction whose message contains json metacharacters is signed over its canonical payload, so the chain verifies it.
+    #[test]
+    fn test_signing_escaped_message() {
+        let bin_wallet = Wallet::new("Escaped".to_string(), 384);
+        let message = "SEND $10   // \"quoted\", [bracketed] \\ back\nslash";
+        let data_string = serde_json::to_string(&(bin_wallet.get_user_id(), "B", message)).unwrap();
+        let tx =
+            Transaction::from_data_string(&data_string, bin_wallet.sign(&data_string)).unwrap();
+        assert_eq!(tx.message, message);
+        assert!(tx.verify_sig().is_ok());
+    }
+
+    /// Sign requests must carry the token set at Initialize (if any).
+    #[test]
+    fn test_check_auth_token() {
+        assert!(check_auth_token(None, None));
+        assert!(check_auth_token(None, Some("anything")));
+        assert!(check_auth_token(Some("s3cr3t"), Some("s3cr3t")));
+        assert!(!check_auth_token(Some("s3cr3t"), None));
+        assert!(!check_auth_token(Some("s3cr3t"), Some("s3cr3T")));
+        assert!(!check_auth_token(Some("s3cr3t"), Some("s3cr3t ")));
+        assert!(!check_auth_token(Some("s3cr3t"), Some("")));
+    }
+
+    fn send_data(amount: i64) -> String {
+        serde_json::to_string(&("A", "B", format!("SEND ${}   // By Alice", amount))).unwrap()
+    }
+
+    /// Transactions above the per-tx maximum are rejected.
+    #[test]
+    fn test_policy_max_amount_per_tx() {
+        let mut enforcer = PolicyEnforcer::new(SpendingPolicy {
+            max_amount_per_tx: Some(100),
+            ..Default::default()
+        });
+        assert!(enforcer.check_and_record(&send_data(100), 0).is_ok());
+        assert!(enforcer.check_and_record(&send_data(101), 1).is_err());
+        assert!(enforcer.check_and_record("not json", 2).is_err());
+    }
+
+    /// The daily limit applies to the total of the last 24 hours.
+    #[test]
+    fn test_policy_daily_limit() {
+        let day_ms = 24 * 60 * 60 * 1000;
+        let mut enforcer = PolicyEnforcer::new(SpendingPolicy {
+            daily_limit: Some(500),
+            ..Default::default()
+        });
+        assert!(enforcer.check_and_record(&send_data(300), 0).is_ok());
+        assert!(enforcer.check_and_record(&send_data(200), 1000).is_ok());
+        assert!(enforcer.check_and_record(&send_data(1), 2000).is_err());
+        // rejected requests are not counted, and old spendings leave the window
+        assert!(enforcer.check_and_record(&send_data(300), day_ms).is_ok());
+        assert!(enforcer
+            .check_and_record(&send_data(1), day_ms + 999)
+            .is_err());
+        assert!(enforcer
+            .check_and_record(&send_data(200), day_ms + 1000)
+            .is_ok());
+    }
+
+    /// The limits apply to the total of the payments of a batch, which must all be positive.
+    #[test]
+    fn test_policy_batch() {
+        let batch_data = |outputs: &str| {
+            serde_json::to_string(&("A", "B", "SEND $60   // By Alice", "", "", "", outputs))
+                .unwrap()
+        };
+        let mut enforcer = PolicyEnforcer::new(SpendingPolicy {
+            max_amount_per_tx: Some(100),
+            ..Default::default()
+        });
+        assert!(enforcer
+            .check_and_record(&batch_data(r#"[["C",40]]"#), 0)
+            .is_ok());
+        assert!(enforcer
+            .check_and_record(&batch_data(r#"[["C",40],["D",1]]"#), 1)
+            .is_err());
+        assert!(enforcer
+            .check_and_record(&batch_data(r#"[["C",-40]]"#), 2)
+            .is_err());
+    }
+
+    /// The limits apply to the amount plus the fee, which cannot be negative.
+    #[test]
+    fn test_policy_fee() {
+        let fee_data = |amount: i64, fee: i64| {
+            serde_json::to_string(&(
+                "A",
+                "B",
+                format!("SEND ${}   // By Alice", amount),
+                "",
+                fee.to_string(),
+            ))
+            .unwrap()
+        };
+        let mut enforcer = PolicyEnforcer::new(SpendingPolicy {
+            max_amount_per_tx: Some(100),
+            daily_limit: Some(150),
+            ..Default::default()
+        });
+        assert!(enforcer.check_and_record(&fee_data(90, 10), 0).is_ok());
+        assert!(enforcer.check_and_record(&fee_data(95, 10), 1).is_err());
+        assert!(enforcer.check_and_record(&fee_data(10, -5), 2).is_err());
+        // 100 of the 150 are spent, fee included
+        assert!(enforcer.check_and_record(&fee_data(45, 6), 3).is_err());
+        assert!(enforcer.check_and_record(&fee_data(45, 5), 4).is_ok());
+    }
+
+    /// Signatures within the cooldown are rejected.
+    #[test]
+    fn test_policy_cooldown() {
+        let mut enforcer = PolicyEnforcer::new(SpendingPolicy {
+            cooldown_ms: Some(1000),
+            ..Default::default()
+        });
+        assert!(enforcer.check_and_record(&send_data(10), 5000).is_ok());
+        assert!(enforcer.check_and_record(&send_data(10), 5999).is_err());
+        assert!(enforcer.check_and_record(&send_data(10), 6000).is_ok());
+    }
+
+    /// A wallet json file without a policy (written before policies and versions) gets an unrestricted policy.
+    #[test]
+    fn test_policy_default_in_wallet_json() {
+        let wallet_json = r#"{"user_name":"A","priv_key_pem":"","pub_key_pem":""}"#;
+        let wallet = Wallet::from_json(wallet_json).unwrap();
+        assert_eq!(wallet.spending_policy, SpendingPolicy::default());
+        assert_eq!(wallet.version, WALLET_MIGRATIONS.current_version());
+        let newer_json = format!(
+            r#"{{"user_name":"A","priv_key_pem":"","pub_key_pem":"","version":{}}}"#,
+            WALLET_MIGRATIONS.current_version() + 1
+        );
+        assert!(Wallet::from_json(&newer_json).is_err());
+        let mut enforcer = PolicyEnforcer::new(wallet.spending_policy);
+        assert!(enforcer.check_and_record(&send_data(299792458), 0).is_ok());
+    }
+
+    /// The published schema of Wallet is up to date, and an invalid wallet is reported with the path of the field at fault.
+    #[test]
+    fn test_wallet_schema() {
+        check_published_schema("Wallet", &schema_json::<Wallet>()).unwrap();
+        let wallet_json = r#"{"user_name":"A","priv_key_pem":"","pub_key_pem":"","spending_policy":{"daily_limit":"100"}}"#;
+        let e = Wallet::from_json(wallet_json).err().unwrap();
+        assert_eq!(
+            e,
+            "Invalid Wallet at `spending_policy.daily_limit`: invalid type: string \"100\", expected i64"
+        );
+    }
+
+    /// The addresses derived from a seed only depend on the seed and the index, and can sign on their own.
+    #[test]
+    fn test_derive_address() {
+        let mut bin_wallet = Wallet::new("HD".to_string(), 384);
+        bin_wallet.address_format = AddressFormat::Sha256Base58;
+        let derived = bin_wallet.derive(1).unwrap();
+        assert_eq!(derived.address_format, AddressFormat::Sha256Base58);
+        assert_ne!(derived.get_user_id(), bin_wallet.get_user_id());
+        assert_ne!(
+            derived.get_user_id(),
+            bin_wallet.derive(2).unwrap().get_user_id()
+        );
+        let signature = derived.sign("hello");
+        assert!(Wallet::verify_with_key(
+            SignatureAlgorithm::Rsa,
+            &derived.get_pub_key_id(),
+            "hello",
+            &signature
+        ));
+
+        // the seed is kept in the wallet json file, and gives the same addresses after reloading
+        let reloaded = Wallet::from_json(&serde_json::to_string(&bin_wallet).unwrap()).unwrap();
+        assert_eq!(
+            reloaded.derive(1).unwrap().get_user_id(),
+            derived.get_user_id()
+        );
+        let mut derived_addresses = BTreeMap::new();
+        assert_eq!(
+            derived_address(&reloaded, &mut derived_addresses, 1).unwrap(),
+            derived.get_user_id()
+        );
+        assert_eq!(derived_addresses.len(), 1);
+
+        // older wallet files have no seed
+        let wallet_json = r#"{"user_name":"A","priv_key_pem":"","pub_key_pem":""}"#;
+        let old_wallet = Wallet::from_json(wallet_json).unwrap();
+        assert!(old_wallet.hd_seed.is_none());
+        assert!(old_wallet.derive(0).is_err());
+    }
+
+    /// An Ed25519 wallet signs transactions that verify on chain, derives Ed25519 addresses, and only signs the
+    /// data strings of its own signature scheme.
+    #[test]
+    fn test_ed25519_wallet() {
+        let mut bin_wallet = Wallet::new_ed25519("Ed".to_string());
+        assert!(bin_wallet
+            .pub_key_pem
+            .starts_with("-----BEGIN PUBLIC KEY-----\n"));
+        assert_eq!(bin_wallet.get_user_id().len(), 60);
+        let payload = SignedPayload {
+            sender: bin_wallet.get_user_id(),
+            receiver: "BOB".to_string(),
+            message: "SEND $3   // By Ed   // 1".to_string(),
+            sender_pub_key: None,
+            fee: None,
+            algorithm: SignatureAlgorithm::Ed25519,
+            outputs: vec![],
+        };
+        assert!(bin_wallet.check_algorithm(&payload).is_ok());
+        let data_string = payload.encode();
+        let signature = bin_wallet.sign(&data_string);
+        assert!(bin_wallet.verify(&data_string, &signature));
+        assert!(Wallet::verify_with_key(
+            SignatureAlgorithm::Ed25519,
+            &bin_wallet.get_pub_key_id(),
+            &data_string,
+            &signature
+        ));
+        assert!(!Wallet::verify_with_key(
+            SignatureAlgorithm::Rsa,
+            &bin_wallet.get_pub_key_id(),
+            &data_string,
+            &signature
+        ));
+        let tx = Transaction::from_data_string(&data_string, signature).unwrap();
+        assert!(tx.verify_sig().is_ok());
+        // an RSA data string would not verify with this key
+        let rsa_payload = SignedPayload {
+            algorithm: SignatureAlgorithm::Rsa,
+            ..payload
+        };
+        assert!(bin_wallet.check_algorithm(&rsa_payload).is_err());
+
+        // the derived addresses are Ed25519 ones too, and the key survives an encryption round trip
+        let derived = bin_wallet.derive(1).unwrap();
+        assert_eq!(derived.algorithm, SignatureAlgorithm::Ed25519);
+        assert_eq!(
+            derived.get_user_id(),
+            bin_wallet.derive(1).unwrap().get_user_id()
+        );
+        assert!(derived.verify("hello", &derived.sign("hello")));
+        bin_wallet.encrypt_key("passphrase", 1).unwrap();
+        let mut reloaded = Wallet::from_json(&serde_json::to_string(&bin_wallet).unwrap()).unwrap();
+        assert_eq!(reloaded.algorithm, SignatureAlgorithm::Ed25519);
+        assert!(reloaded.unlock("wrong").is_err());
+        reloaded.unlock("passphrase").unwrap();
+        assert!(bin_wallet.verify("hello", &reloaded.sign("hello")));
+    }
+
+    /// The key material is held in zeroizing buffers without changing the json file, and the PEM of an Ed25519 key is
+    /// written without reallocating (which would leave a copy of the key in the freed buffer).
+    #[test]
+    fn test_wallet_key_buffers() {
+        let bin_wallet = Wallet::new_ed25519("Ed".to_string());
+        assert_eq!(
+            bin_wallet.priv_key_pem.len(),
+            bin_wallet.priv_key_pem.capacity()
+        );
+        let json = serde_json::to_value(&bin_wallet).unwrap();
+        assert_eq!(
+            json["priv_key_pem"].as_str(),
+            Some(bin_wallet.priv_key_pem.as_str())
+        );
+        assert!(json["hd_seed"].is_string());
+        let reloaded = Wallet::from_json(&json.to_string()).unwrap();
+        assert_eq!(reloaded.priv_key_pem, bin_wallet.priv_key_pem);
+        assert_eq!(reloaded.hd_seed, bin_wallet.hd_seed);
+
+        let encrypted = keystore::EncryptedKey::encrypt(&bin_wallet.priv_key_pem, "passphrase", 1);
+        let decrypted: Zeroizing<String> = encrypted.decrypt("passphrase").unwrap();
+        assert_eq!(decrypted, bin_wallet.priv_key_pem);
+    }
+
+    /// An encrypted private key and seed are not in the json file, and the wallet signs and derives addresses only once
+    /// unlocked with the passphrase.
+    #[test]
+    fn test_encrypted_keystore() {
+        let mut bin_wallet = Wallet::new("Locked".to_string(), 384);
+        let priv_key_pem = bin_wallet.priv_key_pem.clone();
+        let hd_seed = bin_wallet.hd_seed.clone().unwrap();
+        let user_id = bin_wallet.get_user_id();
+        let derived_id = bin_wallet.derive(0).unwrap().get_user_id();
+        bin_wallet.encrypt_key("correct horse", 1000).unwrap();
+        assert!(!bin_wallet.is_locked());
+        assert_eq!(bin_wallet.derive(0).unwrap().get_user_id(), derived_id);
+        let wallet_json = serde_json::to_string(&bin_wallet).unwrap();
+        assert!(!wallet_json.contains(priv_key_pem.lines().nth(1).unwrap()));
+        assert!(!wallet_json.contains(hd_seed.as_str()));
+
+        let mut reloaded = Wallet::from_json(&wallet_json).unwrap();
+        assert!(reloaded.is_locked());
+        assert_eq!(reloaded.get_user_id(), user_id);
+        assert!(reloaded.derive(0).is_err());
+        assert!(reloaded.unlock("wrong horse").is_err());
+        assert!(reloaded.is_locked());
+        reloaded.unlock("correct horse").unwrap();
+        assert!(!reloaded.is_locked());
+        assert!(bin_wallet.verify("hello", &reloaded.sign("hello")));
+        assert_eq!(reloaded.derive(0).unwrap().get_user_id(), derived_id);
+        // the decrypted key is never written back
+        assert_eq!(serde_json::to_string(&reloaded).unwrap(), wallet_json);
+
+        // a tampered key or a key of another wallet is rejected
+        let mut tampered = Wallet::from_json(&wallet_json).unwrap();
+        let encrypted = tampered.encrypted_priv_key.as_mut().unwrap();
+        encrypted.ciphertext = encrypted.ciphertext.replacen(|c| c != 'A', "A", 1);
+        assert!(tampered.unlock("correct horse").is_err());
+        let mut other = Wallet::new("Other".to_string(), 384);
+        other.encrypt_key("correct horse", 1000).unwrap();
+        let mut mismatched = Wallet::from_json(&wallet_json).unwrap();
+        mismatched.encrypted_priv_key = other.encrypted_priv_key.clone();
+        assert!(mismatched.unlock("correct horse").is_err());
+        // a key encrypted before the seed was is a bare PEM, and its plaintext seed is only used once unlocked
+        let mut old = Wallet::from_json(&wallet_json).unwrap();
+        old.encrypted_priv_key = Some(keystore::EncryptedKey::encrypt(
+            &priv_key_pem,
+            "correct horse",
+            1000,
+        ));
+        old.hd_seed = Some(hd_seed);
+        assert!(old.derive(0).is_err());
+        old.unlock("correct horse").unwrap();
+        assert_eq!(old.derive(0).unwrap().get_user_id(), derived_id);
+        // a plaintext wallet is never locked
+        assert!(Wallet::new("Plain".to_string(), 384)
+            .unlock("correct horse")
+            .is_err());
+    }
+
+    /// An unsigned transaction file is signed offline if it is sent from the wallet and allowed by its policy.
+    #[test]
+    fn test_sign_file() {
+        let mut bin_wallet = Wallet::new("Cold".to_string(), 384);
+        bin_wallet.spending_policy.max_amount_per_tx = Some(100);
+        let dir = std::env::temp_dir().join(format!("bin_wallet_sign_file_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().to_string();
+        let write_unsigned = |name: &str, sender: &str, message: &str| {
+            let offline_tx = OfflineTx {
+                data_string: serde_json::to_string(&(sender, "B", message)).unwrap(),
+                signature: None,
+            };
+            write_string_to_file(&path(name), serde_json::to_string(&offline_tx).unwrap());
+        };
+
+        write_unsigned("in.json", &bin_wallet.get_user_id(), "SEND $100   // Cold");
+        let tx = sign_file(&bin_wallet, &path("in.json"), &path("out.json")).unwrap();
+        assert!(tx.verify_sig().is_ok());
+        let signed: OfflineTx =
+            serde_json::from_str(&read_string_from_file(&path("out.json"))).unwrap();
+        assert!(bin_wallet.verify(&signed.data_string, signed.signature.as_ref().unwrap()));
+        // a signed file is not signed again
+        assert!(sign_file(&bin_wallet, &path("out.json"), &path("again.json")).is_err());
+
+        write_unsigned("other.json", "another address", "SEND $1");
+        assert!(sign_file(&bin_wallet, &path("other.json"), &path("out2.json")).is_err());
+        write_unsigned("too_much.json", &bin_wallet.get_user_id(), "SEND $101");
+        assert!(sign_file(&bin_wallet, &path("too_much.json"), &path("out3.json")).is_err());
+        assert!(!dir.join("out2.json").exists() && !dir.join("out3.json").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,64 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// This file implements the offline (cold-storage) signing of bin_wallet: `bin_wallet sign-file <in.json> <out.json> <wallet.json>`.
+// bin_client exports an unsigned transaction file, which is carried to a machine holding the wallet file and no network,
+// signed there by this command, and carried back to be imported and published by bin_client. The private key never
+// leaves the offline machine, and the signing process only reads the two input files and writes the output file.
+use crate::policy::PolicyEnforcer;
+use crate::wallet::Wallet;
+use lib_chain::block::Transaction;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::time::SystemTime;
+
+/// A transaction file exchanged with bin_client: unsigned when exported, signed after `sign-file`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OfflineTx {
+    /// The data_string of the transaction (sender, receiver, message and the optional fields)
+    pub data_string: String,
+    /// The signature of the data_string in Base64 format, None until the file is signed
+    pub signature: Option<String>,
+}
+
+/// Sign the unsigned transaction file at `in_path` with the wallet and write the signed file to `out_path`.
+/// The transaction must be sent from the wallet's own address and allowed by its spending policy (checked as the only
+/// signature of the day, since an offline wallet keeps no history). It returns the signed transaction for the operator to review.
+pub fn sign_file(wallet: &Wallet, in_path: &str, out_path: &str) -> Result<Transaction, String> {
+    let json =
+        fs::read_to_string(in_path).map_err(|e| format!("Cannot read {}: {}", in_path, e))?;
+    let offline_tx: OfflineTx =
+        serde_json::from_str(&json).map_err(|e| format!("Invalid {}: {}", in_path, e))?;
+    if offline_tx.signature.is_some() {
+        return Err(format!("{} is already signed", in_path));
+    }
+    let tx = Transaction::from_data_string(&offline_tx.data_string, String::new())?;
+    if tx.sender != wallet.get_user_id() {
+        return Err(format!(
+            "The transaction is sent from {}, not from this wallet ({})",
+            tx.sender,
+            wallet.get_user_id()
+        ));
+    }
+    wallet.check_algorithm(&tx.payload())?;
+    let now_ms = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    PolicyEnforcer::new(wallet.spending_policy.clone())
+        .check_and_record(&offline_tx.data_string, now_ms)
+        .map_err(|e| format!("Policy violation: {}", e))?;
+
+    let signature = wallet.sign(&offline_tx.data_string);
+    let signed_tx = OfflineTx {
+        data_string: offline_tx.data_string,
+        signature: Some(signature.clone()),
+    };
+    fs::write(out_path, serde_json::to_string_pretty(&signed_tx).unwrap())
+        .map_err(|e| format!("Cannot write {}: {}", out_path, e))?;
+    Ok(Transaction {
+        sig: signature,
+        ..tx
+    })
+}
@@ -0,0 +1,118 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// This file implements the spending policy enforced by the wallet before signing.
+// The policy is stored in the wallet json file (all limits are optional) and checked for every SignRequest,
+// so that even a buggy bot script cannot drain the account.
+use lib_chain::block::Transaction;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// The length of the rolling window used by the daily limit (24 hours in milliseconds)
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+/// The spending limits of a wallet. A limit set to `None` is not enforced.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default, PartialEq)]
+pub struct SpendingPolicy {
+    /// The maximum amount that can be sent in one transaction, fee included
+    pub max_amount_per_tx: Option<i64>,
+    /// The maximum total amount that can be sent within any 24 hours, fees included
+    pub daily_limit: Option<i64>,
+    /// The minimum time (in milliseconds) between two signatures
+    pub cooldown_ms: Option<u64>,
+}
+
+/// Keeps track of the signatures issued so far and checks new requests against the `SpendingPolicy`.
+pub struct PolicyEnforcer {
+    /// The policy to enforce
+    policy: SpendingPolicy,
+    /// The (timestamp_ms, amount) of the signatures issued within the last 24 hours
+    recent_spendings: Vec<(u64, i64)>,
+    /// The timestamp (in milliseconds) of the last signature
+    last_sign_ms: Option<u64>,
+}
+
+impl PolicyEnforcer {
+    /// Create an enforcer with no signature issued yet.
+    pub fn new(policy: SpendingPolicy) -> PolicyEnforcer {
+        PolicyEnforcer {
+            policy,
+            recent_spendings: vec![],
+            last_sign_ms: None,
+        }
+    }
+
+    /// Check whether the `data_string` of a SignRequest can be signed at `now_ms`.
+    /// The `data_string` is the json serialization of (sender, receiver, message) and the optional fields (see
+    /// `SignedPayload`); the amount counted is the total of the payments (those of a batch) plus the fee.
+    /// If it is allowed, the spending is recorded and Ok is returned. Otherwise, the reason is returned.
+    pub fn check_and_record(&mut self, data_string: &str, now_ms: u64) -> Result<(), String> {
+        let amount = Self::parse_amount(data_string)?;
+
+        if let (Some(cooldown_ms), Some(last_sign_ms)) =
+            (self.policy.cooldown_ms, self.last_sign_ms)
+        {
+            let elapsed = now_ms.saturating_sub(last_sign_ms);
+            if elapsed < cooldown_ms {
+                return Err(format!(
+                    "cooldown not elapsed ({}ms left)",
+                    cooldown_ms - elapsed
+                ));
+            }
+        }
+
+        if let Some(max_amount) = self.policy.max_amount_per_tx {
+            if amount > max_amount {
+                return Err(format!(
+                    "amount ${} exceeds the limit of ${} per transaction",
+                    amount, max_amount
+                ));
+            }
+        }
+
+        self.recent_spendings
+            .retain(|(timestamp, _)| now_ms.saturating_sub(*timestamp) < DAY_MS);
+        if let Some(daily_limit) = self.policy.daily_limit {
+            let spent: i64 = self.recent_spendings.iter().map(|(_, amount)| amount).sum();
+            if spent + amount > daily_limit {
+                return Err(format!(
+                    "amount ${} exceeds the daily limit (${} of ${} already spent)",
+                    amount, spent, daily_limit
+                ));
+            }
+        }
+
+        self.recent_spendings.push((now_ms, amount));
+        self.last_sign_ms = Some(now_ms);
+        Ok(())
+    }
+
+    /// Extract the amount from the message in the `data_string`, plus the outputs of a batch and the fee.
+    /// The message format is `SEND $300   // ...`.
+    fn parse_amount(data_string: &str) -> Result<i64, String> {
+        let tx = Transaction::from_data_string(data_string, String::new())?;
+        let amount = tx
+            .message
+            .strip_prefix("SEND $")
+            .and_then(|rest| rest.split(' ').next())
+            .and_then(|amount_str| amount_str.parse::<i64>().ok())
+            .ok_or_else(|| format!("cannot read the amount from message: {}", tx.message))?;
+        let amounts = std::iter::once(amount).chain(tx.outputs.iter().map(|output| output.amount));
+        let mut total: i64 = 0;
+        for amount in amounts {
+            if amount <= 0 {
+                return Err(format!("invalid amount ${}", amount));
+            }
+            total = total
+                .checked_add(amount)
+                .ok_or_else(|| "the total amount overflows".to_string())?;
+        }
+        if tx.get_fee() < 0 {
+            return Err(format!("invalid fee ${}", tx.get_fee()));
+        }
+        total
+            .checked_add(tx.get_fee())
+            .ok_or_else(|| "the total amount overflows".to_string())
+    }
+}
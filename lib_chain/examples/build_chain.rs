@@ -0,0 +1,103 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// This example builds a BlockTree by hand: a branch of empty blocks on top of the genesis block, then a longer fork
+// that takes over as the longest path, finalizing its own blocks instead. The blocks are mined at difficulty 0 (any
+// nonce is a solution), so only the rules of the block tree are at play. The tree is then saved as json and loaded back.
+// Run it with `cargo run -p lib_chain --example build_chain`.
+
+use lib_chain::block::{BlockNode, BlockNodeHeader, BlockTree, MerkleTree, Puzzle, Transactions};
+use sha2::{Digest, Sha256};
+
+/// A block without transactions on top of the given parent, rewarding the given address, valid at difficulty 0.
+fn empty_block(parent: &str, reward_receiver: &str, timestamp: u64) -> BlockNode {
+    let (merkle_root, merkle_tree) = MerkleTree::create_merkle_tree(vec![]);
+    let puzzle = Puzzle {
+        parent: parent.to_string(),
+        merkle_root: merkle_root.clone(),
+        reward_receiver: reward_receiver.to_string(),
+        miner_pub_key: None,
+        timestamp: None,
+    };
+    let nonce = "0".to_string();
+    let block_id = hex::encode(Sha256::digest(
+        (nonce.clone() + &serde_json::to_string(&puzzle).unwrap()).as_bytes(),
+    ));
+    BlockNode {
+        header: BlockNodeHeader {
+            parent: parent.to_string(),
+            merkle_root,
+            timestamp,
+            block_id,
+            nonce,
+            reward_receiver: reward_receiver.to_string(),
+            miner_signature: None,
+        },
+        transactions_block: Transactions {
+            merkle_tree,
+            transactions: vec![],
+        },
+    }
+}
+
+/// Add a branch of `len` empty blocks on top of the given parent and return the id of its last block.
+fn add_branch(chain: &mut BlockTree, parent: &str, len: u64, reward_receiver: &str) -> String {
+    let mut tip = parent.to_string();
+    for i in 0..len {
+        let block = empty_block(&tip, reward_receiver, 1 + i);
+        tip = block.header.block_id.clone();
+        chain.add_block(block, 0).expect("The block is valid");
+    }
+    tip
+}
+
+/// Print where the longest path and the finalized block of the chain are, and the finalized balance of an address.
+fn print_chain(chain: &BlockTree, address: &str) {
+    println!(
+        "  working block at height {}, finalized block at height {}, {} blocks in the tree",
+        chain.height_of(&chain.working_block_id).unwrap(),
+        chain.height_of(&chain.finalized_block_id).unwrap(),
+        chain.all_blocks.len()
+    );
+    println!(
+        "  finalized balance of {}: ${}",
+        address,
+        chain
+            .finalized_balance_map
+            .get(address)
+            .copied()
+            .unwrap_or(0)
+    );
+}
+
+fn main() {
+    let mut chain = BlockTree::new();
+    println!(
+        "Genesis block {} (finality depth {})",
+        chain.root_id, chain.consensus.finality_depth
+    );
+
+    println!("After 8 blocks rewarding ALICE:");
+    let alice_tip = add_branch(&mut chain, "0", 8, "ALICE");
+    print_chain(&chain, "ALICE");
+
+    println!("After a fork of 10 blocks rewarding BOB from the genesis block:");
+    let bob_tip = add_branch(&mut chain, "0", 10, "BOB");
+    print_chain(&chain, "BOB");
+    println!(
+        "  the branches of ALICE and BOB fork at block {}",
+        chain.fork_point(&alice_tip, &bob_tip).unwrap()
+    );
+    chain.check_invariants().expect("The invariants hold");
+    chain.audit_supply().expect("The supply adds up");
+
+    let json = serde_json::to_string(&chain).unwrap();
+    let loaded = BlockTree::from_json(&json).expect("The saved tree loads");
+    println!(
+        "Saved as {} bytes of json and loaded back: working block {}",
+        json.len(),
+        loaded.working_block_id
+    );
+    assert_eq!(loaded.working_block_id, chain.working_block_id);
+}
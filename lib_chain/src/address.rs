@@ -0,0 +1,40 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// This file implements the derivation of addresses (user ids) from public keys.
+// Originally the address of a user is its public key itself (the PEM body joined into one line), which is long and
+// lets anyone verify a signature from the address alone. The hashed format instead uses the base58 encoding of the
+// sha256 hash of the public key, like real cryptocurrencies: the address is short, and a transaction from such an
+// address embeds the full public key (see `Transaction::sender_pub_key`), which must hash to the sender address.
+// Both formats are accepted by the chain, so the wallets and the chains created before the hashed format keep working.
+
+use base64ct::{Base64, Encoding};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::block::UserId;
+
+/// How a wallet derives its address from its public key.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AddressFormat {
+    /// The address is the public key itself (the compatibility mode, and the only format before addresses were hashed)
+    #[default]
+    RawPublicKey,
+    /// The address is the base58 encoding of the sha256 hash of the public key (DER bytes)
+    Sha256Base58,
+}
+
+/// Get the address of a public key given as the Base64 PEM body joined into one line (i.e. a raw public key address).
+/// Return an error if a hashed address is asked for a key that is not valid Base64.
+pub fn address_from_pub_key(pub_key: &str, format: AddressFormat) -> Result<UserId, String> {
+    match format {
+        AddressFormat::RawPublicKey => Ok(pub_key.to_string()),
+        AddressFormat::Sha256Base58 => {
+            let der = Base64::decode_vec(pub_key)
+                .map_err(|e| format!("public key is not Base64 encoded ({})", e))?;
+            Ok(bs58::encode(Sha256::digest(der)).into_string())
+        }
+    }
+}
@@ -2,28 +2,32 @@
 // Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
 // Please do not distribute.
 
+#[cfg(feature = "crypto")]
 use base64ct::{Base64, Encoding};
 /// This file contains the definition of the BlockTree
 /// The BlockTree is a data structure that stores all the blocks that have been mined by this node or received from other nodes.
 /// The longest path in the BlockTree is the main chain. It is the chain from the root to the working_block_id.
-use core::panic;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use sha2::{digest::block_buffer::Block, Digest, Sha256};
-use std::{
-    collections::{BTreeMap, HashMap, HashSet},
-    convert,
-    error::Error,
-    hash,
-    sync::Arc,
-};
-
-use pem::parse;
-use rsa::pkcs1::{
-    DecodeRsaPrivateKey, DecodeRsaPublicKey, EncodeRsaPrivateKey, EncodeRsaPublicKey,
-};
-use rsa::pkcs1v15::{SigningKey, VerifyingKey};
-use rsa::signature::{RandomizedSigner, Signature as RSASig, Signer, Verifier};
-use rsa::{RsaPrivateKey, RsaPublicKey};
+#[cfg(feature = "crypto")]
+use sha2::Sha256;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+use crate::address::{address_from_pub_key, AddressFormat};
+use crate::consensus_params::ConsensusParams;
+#[cfg(feature = "crypto")]
+use crate::ed25519;
+use crate::hash::{HashAlgo, HashFunction};
+use crate::migration::{self, MigrationRegistry};
+use crate::payload::SignedPayload;
+
+#[cfg(feature = "crypto")]
+use rsa::pkcs1::DecodeRsaPublicKey;
+#[cfg(feature = "crypto")]
+use rsa::pkcs1v15::VerifyingKey;
+#[cfg(feature = "crypto")]
+use rsa::signature::{Signature as RSASig, Verifier};
 pub type UserId = String;
 pub type BlockId = String;
 pub type Signature = String;
@@ -31,7 +35,7 @@ pub type TxId = String;
 
 /// Merkle tree is used to verify the integrity of transactions in a block.
 /// It is generated from a list of transactions. It will be stored inside `Transactions` struct.
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
 pub struct MerkleTree {
     /// A list of lists of hashes, where the first list is the list of hashes of the transactions,
     /// the second list is the list of hashes of the first list, and so on.
@@ -45,46 +49,47 @@ impl MerkleTree {
     /// where the first list is the list of hashes of the transactions.
     /// The last list is the list with only one hash, called the Merkle root.
     /// - `txs`: a list of transactions
-    /// - The return value is the root hash of the merkle tree
+    /// - The return value is the root hash of the merkle tree. An empty list gives the hash of the empty string as the root.
     pub fn create_merkle_tree(txs: Vec<Transaction>) -> (String, MerkleTree) {
+        MerkleTree::create_merkle_tree_with(txs, HashFunction::Sha256)
+    }
+
+    /// Create the merkle tree of a chain built with the given hash function (see `create_merkle_tree`), whose leaves are
+    /// the transaction ids under that hash function (see `Transaction::gen_hash_with`).
+    pub fn create_merkle_tree_with(
+        txs: Vec<Transaction>,
+        hash_function: HashFunction,
+    ) -> (String, MerkleTree) {
         if txs.is_empty() {
-            panic!("create_merkle_tree received empty transaction vector.");
+            // An empty block uses the hash of the empty string as its merkle root
+            let root = hash_function.digest_hex(b"");
+            let tree = MerkleTree {
+                hashes: vec![vec![root.clone()]],
+            };
+            return (root, tree);
         }
-        // todo!()
 
-        // To create a Merkle tree from a list of transactions, you can follow these steps:
-        // Create a list of hashes of all transactions.
-        // If the number of hashes is odd, duplicate the last hash to make it even.
-        // Group hashes into pairs and hash each pair to get a new list of hashes.
-        // If the number of hashes is still not one, repeat steps 2 and 3 until you get a single hash, which is the Merkle root.
-
-        let mut hashes: Vec<Vec<String>> = vec![txs.iter().map(|tx| tx.gen_hash()).collect()];
-
-        while hashes.last().unwrap().len() > 1 {
-            let mut level: Vec<String> = Vec::new();
-            let last_level = hashes.last().unwrap();
-
-            if last_level.len() % 2 != 0 {
-                let last_hash = last_level.last().unwrap().to_string();
-                level.push(last_hash.clone());
+        // Every level with an odd number of hashes is padded by duplicating its last hash,
+        // and the padding is kept in the stored level. The leaf level is always padded,
+        // so a single tx gives [[h, h], [sha256(h || h)]].
+        let mut hashes: Vec<Vec<String>> = vec![txs
+            .iter()
+            .map(|tx| tx.gen_hash_with(hash_function))
+            .collect()];
+        loop {
+            let level_count = hashes.len();
+            let last_level = hashes.last_mut().unwrap();
+            if level_count > 1 && last_level.len() == 1 {
+                break;
             }
-
-            for i in (0..last_level.len() - 1).step_by(2) {
-                let mut hasher = Sha256::new();
-
-                let h1 = &last_level[i];
-                let h2 = &last_level[i + 1];
-
-                let mut owned_string: String = h1.to_owned();
-                owned_string.push_str(&h2);
-                let input = owned_string.as_bytes();
-
-                hasher.update(input);
-                let result = hasher.finalize();
-
-                level.push(hex::encode(result));
+            if last_level.len() % 2 == 1 {
+                let last_hash = last_level.last().unwrap().clone();
+                last_level.push(last_hash);
             }
-
+            let level: Vec<String> = last_level
+                .chunks(2)
+                .map(|pair| hash_function.digest_hex(format!("{}{}", pair[0], pair[1]).as_bytes()))
+                .collect();
             hashes.push(level);
         }
 
@@ -97,7 +102,7 @@ impl MerkleTree {
 
 /// The struct containing a list of transactions and the merkle tree of the transactions.
 /// Each block will contain one `Transactions` struct.
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
 pub struct Transactions {
     /// The merkle tree of the transactions
     pub merkle_tree: MerkleTree,
@@ -105,9 +110,36 @@ pub struct Transactions {
     pub transactions: Vec<Transaction>,
 }
 
+/// The signature scheme of a transaction, i.e. of the key pair of its sender.
+/// RSA (PKCS#1 v1.5 with sha256) is the original one; Ed25519 keys and signatures are much smaller (see ed25519.rs).
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SignatureAlgorithm {
+    /// The sender key is a Base64 encoded PKCS#1 DER RSA public key
+    #[default]
+    Rsa,
+    /// The sender key is a Base64 encoded SubjectPublicKeyInfo DER Ed25519 public key
+    Ed25519,
+}
+
+impl SignatureAlgorithm {
+    /// Whether this is the original RSA scheme, whose transactions do not serialize their algorithm.
+    pub fn is_rsa(&self) -> bool {
+        *self == SignatureAlgorithm::Rsa
+    }
+}
+
+/// One payment of a batch transaction besides the one to its receiver (see `Transaction::outputs`).
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+pub struct TxOutput {
+    /// The user_id of the receiver of the payment
+    pub receiver: UserId,
+    /// The amount of money paid to the receiver
+    pub amount: i64,
+}
+
 /// The struct is used to store the information of one transaction.
 /// The transaction id is not stored explicitly, but can be generated from the transaction using the `gen_hash` function.
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
 pub struct Transaction {
     /// The user_id of the sender
     pub sender: UserId,
@@ -121,6 +153,61 @@ pub struct Transaction {
     pub message: String,
     /// The signature of the transaction in base64 format
     pub sig: Signature,
+    /// The public key of the sender (the PEM body joined into one line), when the sender address is hashed
+    /// (see `address::AddressFormat::Sha256Base58`); it must hash to the sender address. None when the sender address
+    /// is the public key itself, so such transactions keep their serialization and their id.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sender_pub_key: Option<String>,
+    /// The fee paid by the sender on top of the amount, credited to the reward receiver of the block that includes
+    /// the transaction. None when there is no fee, so such transactions keep their serialization and their id.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fee: Option<i64>,
+    /// The signature scheme of the sender key. RSA when it is absent, so the transactions signed before Ed25519 keys
+    /// existed keep their serialization and their id.
+    #[serde(default, skip_serializing_if = "SignatureAlgorithm::is_rsa")]
+    pub algorithm: SignatureAlgorithm,
+    /// The other payments of a batch transaction, made by the same signature on top of the amount of the message paid to
+    /// the receiver. Empty for a transaction with one receiver, so such transactions keep their serialization and their id.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outputs: Vec<TxOutput>,
+}
+
+/// The reason why the signature of a transaction is not valid (see `Transaction::verify_sig`).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum TxValidationError {
+    /// The sender is not a Base64 encoded public key of the signature scheme of the transaction (details)
+    BadKeyEncoding(String),
+    /// The signature is not Base64 encoded (details)
+    BadBase64(String),
+    /// The signature was not made by the sender over the transaction data
+    SignatureMismatch,
+    /// The public key embedded in the transaction does not hash to the sender address
+    AddressMismatch,
+    /// The signature cannot be checked: the build has no `crypto` feature
+    NoCrypto,
+}
+
+impl fmt::Display for TxValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TxValidationError::BadKeyEncoding(details) => {
+                write!(f, "bad sender key encoding ({})", details)
+            }
+            TxValidationError::BadBase64(details) => {
+                write!(f, "bad signature encoding ({})", details)
+            }
+            TxValidationError::SignatureMismatch => write!(f, "signature mismatch"),
+            TxValidationError::AddressMismatch => {
+                write!(f, "sender public key does not match the sender address")
+            }
+            TxValidationError::NoCrypto => {
+                write!(
+                    f,
+                    "signatures cannot be checked without the `crypto` feature"
+                )
+            }
+        }
+    }
 }
 
 impl Transaction {
@@ -131,32 +218,163 @@ impl Transaction {
             receiver,
             message,
             sig,
+            sender_pub_key: None,
+            fee: None,
+            algorithm: SignatureAlgorithm::Rsa,
+            outputs: vec![],
+        }
+    }
+
+    /// Create a transaction from its data_string, the json array signed by the wallet:
+    /// `[sender, receiver, message]`, or `[sender, receiver, message, sender_pub_key]` for a hashed sender address.
+    /// A transaction with a fee has both: `[sender, receiver, message, sender_pub_key, fee]`, with an empty sender_pub_key
+    /// for a plain sender address. A transaction signed with Ed25519 has all three and its algorithm:
+    /// `[sender, receiver, message, sender_pub_key, fee, "Ed25519"]`. A batch transaction has all of them (an empty
+    /// algorithm for RSA) and its outputs. See `SignedPayload` for the encoding.
+    pub fn from_data_string(data_string: &str, sig: Signature) -> Result<Transaction, String> {
+        let payload = SignedPayload::decode(data_string)?;
+        Ok(Transaction {
+            sender_pub_key: payload.sender_pub_key,
+            fee: payload.fee,
+            algorithm: payload.algorithm,
+            outputs: payload.outputs,
+            ..Transaction::new(payload.sender, payload.receiver, payload.message, sig)
+        })
+    }
+
+    /// The payload signed by the sender.
+    pub fn payload(&self) -> SignedPayload {
+        SignedPayload {
+            sender: self.sender.clone(),
+            receiver: self.receiver.clone(),
+            message: self.message.clone(),
+            sender_pub_key: self.sender_pub_key.clone(),
+            fee: self.fee,
+            algorithm: self.algorithm,
+            outputs: self.outputs.clone(),
         }
     }
 
+    /// The data signed by the sender: the data_string of the transaction (the encoding of its `payload`).
+    pub fn signed_data(&self) -> String {
+        self.payload().encode()
+    }
+
     /// Compute the transaction id from the transaction. The transaction id is the sha256 hash of the serialized transaction struct in hex format.
+    /// It is the id of the transaction in the tx pool and among the finalized transactions, whatever the hash function of the chain.
     pub fn gen_hash(&self) -> TxId {
-        let mut hasher = Sha256::new();
+        self.gen_hash_with(HashFunction::Sha256)
+    }
+
+    /// Compute the hash of the serialized transaction struct with the given hash function, i.e. its leaf in the merkle
+    /// tree of a chain built with that hash function.
+    pub fn gen_hash_with(&self, hash_function: HashFunction) -> TxId {
         let hasher_str = serde_json::to_string(&self).unwrap();
-        hasher.update(hasher_str);
-        let result = hasher.finalize();
-        let tx_hash: TxId = format!("{:x}", result);
-        tx_hash
+        hash_function.digest_hex(hasher_str.as_bytes())
+    }
+
+    /// Parse the amount of money sent by the transaction from its message (e.g. `300` in `SEND $300   // By Alice`).
+    /// Return None if the message does not carry a well-formed amount.
+    pub fn get_amount(&self) -> Option<i64> {
+        self.message
+            .split('$')
+            .nth(1)?
+            .split_whitespace()
+            .next()?
+            .parse::<i64>()
+            .ok()
     }
 
-    /// Verify the signature of the transaction. Return true if the signature is valid, and false otherwise.
-    pub fn verify_sig(&self) -> bool {
+    /// The fee paid by the sender on top of the amount (0 if there is none).
+    pub fn get_fee(&self) -> i64 {
+        self.fee.unwrap_or(0)
+    }
+
+    /// Whether the transaction pays several receivers (see `outputs`).
+    pub fn is_batch(&self) -> bool {
+        !self.outputs.is_empty()
+    }
+
+    /// The payments made by the transaction: the amount of the message to the receiver (0 if it is not well-formed),
+    /// then the outputs of a batch transaction.
+    pub fn payments(&self) -> Vec<(&UserId, i64)> {
+        let mut payments = vec![(&self.receiver, self.get_amount().unwrap_or(0))];
+        payments.extend(
+            self.outputs
+                .iter()
+                .map(|output| (&output.receiver, output.amount)),
+        );
+        payments
+    }
+
+    /// The total amount paid by the transaction to its receivers, without the fee. None if the sum overflows.
+    pub fn total_amount(&self) -> Option<i64> {
+        self.payments()
+            .iter()
+            .try_fold(0i64, |total, (_, amount)| total.checked_add(*amount))
+    }
+
+    /// The total amount taken from the sender: the amounts paid to its receivers and the fee. None if the sum overflows.
+    pub fn total_cost(&self) -> Option<i64> {
+        self.total_amount()?.checked_add(self.get_fee())
+    }
+
+    /// Verify the signature of the transaction. Return the reason why it is not valid, if it is not.
+    pub fn verify_sig(&self) -> Result<(), TxValidationError> {
         // Please fill in the blank
         // verify the signature using the sender_id as the public key (you might need to change the format into PEM)
         // You can look at the `verify` function in `bin_wallet` for reference. They should have the same functionality.
         // todo!();
 
+        // A hashed sender address is checked against the embedded public key, which then verifies the signature
+        let pub_key = match &self.sender_pub_key {
+            Some(sender_pub_key) => {
+                if address_from_pub_key(sender_pub_key, AddressFormat::Sha256Base58).ok()
+                    != Some(self.sender.clone())
+                {
+                    return Err(TxValidationError::AddressMismatch);
+                }
+                sender_pub_key
+            }
+            None => &self.sender,
+        };
+        self.verify_sig_with_key(pub_key)
+    }
+
+    /// Verify the signature of the transaction with the public key of the sender, according to its signature scheme.
+    #[cfg(feature = "crypto")]
+    fn verify_sig_with_key(&self, pub_key: &str) -> Result<(), TxValidationError> {
+        match self.algorithm {
+            SignatureAlgorithm::Rsa => self.verify_rsa_sig(pub_key),
+            SignatureAlgorithm::Ed25519 => self.verify_ed25519_sig(pub_key),
+        }
+    }
+
+    /// Without the `crypto` feature, the signatures are not checked at all. The tests and the fuzzing builds
+    /// (`--cfg fuzzing`) accept any signature, so that they can build valid transactions without keys; any other build
+    /// fails closed and rejects every signature.
+    #[cfg(not(feature = "crypto"))]
+    fn verify_sig_with_key(&self, _pub_key: &str) -> Result<(), TxValidationError> {
+        if cfg!(any(test, fuzzing)) {
+            Ok(())
+        } else {
+            Err(TxValidationError::NoCrypto)
+        }
+    }
+
+    /// Verify the RSA signature of the transaction with the given public key (Base64 encoded PKCS#1 DER).
+    #[cfg(feature = "crypto")]
+    fn verify_rsa_sig(&self, pub_key: &str) -> Result<(), TxValidationError> {
         // All lines except the last line must be 64 characters in length ...haizz
-        let formatted_string = format!(
-            "{}{}",
-            &self.sender[..64],
-            "\n".to_string() + &self.sender[64..]
-        );
+        let (first_line, rest) = match (pub_key.get(..64), pub_key.get(64..)) {
+            (Some(first_line), Some(rest)) => (first_line, rest),
+            _ => {
+                return Err(TxValidationError::BadKeyEncoding(
+                    "sender too short".to_string(),
+                ))
+            }
+        };
+        let formatted_string = format!("{}\n{}", first_line, rest);
 
         // convert the public key into PEM format
         let pem_encoded_key = format!(
@@ -164,42 +382,111 @@ impl Transaction {
             formatted_string
         );
 
-        let public_key = rsa::RsaPublicKey::from_pkcs1_pem(&pem_encoded_key).unwrap();
+        let public_key = rsa::RsaPublicKey::from_pkcs1_pem(&pem_encoded_key)
+            .map_err(|e| TxValidationError::BadKeyEncoding(e.to_string()))?;
         let verifying_key = VerifyingKey::<Sha256>::new(public_key);
-        let signature = Base64::decode_vec(&self.sig).unwrap();
-        let verify_signature = RSASig::from_bytes(&signature).unwrap();
-
-        // message is a tuple (sender, receiver, message) serialized to a string
-        let mut msg: String = "[\"".to_string();
-        msg.push_str(&self.sender);
-        msg.push_str("\",\"");
-        msg.push_str(&self.receiver);
-        msg.push_str("\",\"");
-        msg.push_str(&self.message);
-        msg.push_str("\"]");
-
-        let verify_result = verifying_key.verify(msg.as_bytes(), &verify_signature);
-
-        return match verify_result {
-            Ok(()) => true,
-            Err(e) => {
-                println!("[Signature verification failed]: {}", e);
-                false
-            }
-        };
+        let signature = Base64::decode_vec(&self.sig)
+            .map_err(|e| TxValidationError::BadBase64(e.to_string()))?;
+        let verify_signature = RSASig::from_bytes(&signature)
+            .map_err(|e| TxValidationError::BadBase64(e.to_string()))?;
+
+        // message is the canonical encoding of the payload (sender, receiver, message, etc.), see `SignedPayload`
+        let msg = self.signed_data();
+
+        verifying_key
+            .verify(msg.as_bytes(), &verify_signature)
+            .map_err(|_| TxValidationError::SignatureMismatch)
     }
+
+    /// Verify the Ed25519 signature of the transaction with the given public key (Base64 encoded DER).
+    #[cfg(feature = "crypto")]
+    fn verify_ed25519_sig(&self, pub_key: &str) -> Result<(), TxValidationError> {
+        let public_key = Base64::decode_vec(pub_key)
+            .map_err(|e| TxValidationError::BadKeyEncoding(e.to_string()))?;
+        let public_key = ed25519::public_key_from_der(&public_key).ok_or_else(|| {
+            TxValidationError::BadKeyEncoding("not an Ed25519 public key".to_string())
+        })?;
+        let signature = Base64::decode_vec(&self.sig)
+            .map_err(|e| TxValidationError::BadBase64(e.to_string()))?;
+        if signature.len() != ed25519::SIGNATURE_LEN {
+            return Err(TxValidationError::BadBase64(format!(
+                "an Ed25519 signature is {} bytes, not {}",
+                ed25519::SIGNATURE_LEN,
+                signature.len()
+            )));
+        }
+        if ed25519::verify(&public_key, self.signed_data().as_bytes(), &signature) {
+            Ok(())
+        } else {
+            Err(TxValidationError::SignatureMismatch)
+        }
+    }
+}
+
+/// The struct representing where and when a block was first seen by this node.
+/// It is kept in a side-table of the block tree and is not part of the block hash.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct BlockMetadata {
+    /// The peer that first delivered the block (e.g. "127.0.0.1:52814"), or "local" if it was mined by this node
+    pub source: String,
+    /// The time (in milliseconds since the UNIX epoch) when the block was first seen
+    pub first_seen_ms: u64,
+}
+
+/// The result of importing the blocks of another block tree (see `BlockTree::import_blocks`).
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ImportSummary {
+    /// The ids of the blocks that were added to the block tree (including the ones kept as orphans)
+    pub added: Vec<BlockId>,
+    /// The number of blocks that were already in the block tree or the orphan map
+    pub known: usize,
+    /// The ids of the blocks that failed validation, with the reason
+    pub rejected: Vec<(BlockId, String)>,
+}
+
+/// The space reclaimed by pruning the losing branches of a block tree (see `BlockTree::prune_losing_branches`).
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq)]
+pub struct PruneStats {
+    /// The number of blocks removed
+    pub pruned_blocks: u64,
+    /// The total size (in bytes) of the removed blocks, serialized as json
+    pub reclaimed_bytes: u64,
+}
+
+/// The status of a block tree (see `BlockTree::get_status`).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChainStatus {
+    /// The number of blocks in the tree, including the root
+    pub block_count: usize,
+    /// The number of orphan blocks
+    pub orphan_count: usize,
+    /// The id of the root block
+    pub root_id: BlockId,
+    /// The id of the working block
+    pub working_block_id: BlockId,
+    /// The height of the working block, i.e. the length of the longest chain
+    pub height: u64,
+    /// The id of the latest finalized block
+    pub finalized_block_id: BlockId,
+    /// The height of the latest finalized block
+    pub finalized_height: u64,
+    /// The number of blocks removed by pruning
+    pub pruned_blocks: u64,
+    /// The total size (in bytes) of the blocks removed by pruning
+    pub pruned_bytes: u64,
 }
 
 /// The struct representing a whole block tree.
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 pub struct BlockTree {
     /// A map from block id to the block node
     pub all_blocks: HashMap<BlockId, BlockNode>,
     /// A map from block id to the list of its children (as block ids)
     pub children_map: HashMap<BlockId, Vec<BlockId>>,
     /// A map from block id to the depth of the block. The genesis block has depth 0.
+    /// The root of a tree built from a checkpoint has the height of the checkpoint (see `BlockTree::from_checkpoint`).
     pub block_depth: HashMap<BlockId, u64>,
-    /// The id of the root block (the genesis block)
+    /// The id of the root block (the genesis block, or the block of the checkpoint the tree was built from)
     pub root_id: BlockId,
     /// The id of the working block (the block at the end of the longest chain)
     pub working_block_id: BlockId,
@@ -213,11 +500,53 @@ pub struct BlockTree {
     pub finalized_balance_map: HashMap<UserId, i64>,
     /// A set of transaction ids that have been finalized. It includes all the transaction ids in the finalized blocks.
     pub finalized_tx_ids: HashSet<TxId>,
+    /// A map from block id to where and when the block was first seen. Not part of any block hash.
+    #[serde(default)]
+    pub block_metadata: HashMap<BlockId, BlockMetadata>,
+    /// The space reclaimed so far by pruning the losing branches
+    #[serde(default)]
+    pub prune_stats: PruneStats,
+    /// The ids of the blocks on the longest path, indexed by their height (see `get_block_at_height`)
+    #[serde(default)]
+    pub main_chain_index: Vec<BlockId>,
+    /// The known-good block id at some heights. A block whose branch holds another block at one of these heights is rejected.
+    /// It comes from the node config (see `set_checkpoints`), so it is not part of the serialized state.
+    #[serde(skip)]
+    pub checkpoints: BTreeMap<u64, BlockId>,
+    /// The mining reward, finality depth, size limits and genesis allocation of the chain.
+    /// They come from the node config (see `set_consensus_params`), so they are not part of the serialized state.
+    #[serde(skip)]
+    pub consensus: ConsensusParams,
+    /// The difficulty required after the last block of a retarget period (see `expected_difficulty`), by the id of that
+    /// block, with the difficulty it was computed from. It is rebuilt as blocks are added, so it is not serialized.
+    #[serde(skip)]
+    pub(crate) retarget_cache: HashMap<BlockId, (u16, u16)>,
+    /// The version of the serialized format (see `BLOCK_TREE_MIGRATIONS`)
+    #[serde(default)]
+    pub version: u32,
 }
 
+/// The highest difficulty a block can be required to meet: a hash has 64 hex digits (see `hash::HASH_LEN`).
+pub const MAX_LEADING_ZERO_LEN: u16 = 64;
+
+/// The number of blocks whose median timestamp a block must be after, on a chain that retargets its difficulty
+/// (see `BlockTree::median_time_past`).
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+/// The migrations of the serialized BlockTree from older formats (see `BlockTree::from_json`).
+pub const BLOCK_TREE_MIGRATIONS: MigrationRegistry = MigrationRegistry {
+    kind: "BlockTree",
+    steps: &[migration::add_version_field],
+};
+
 impl BlockTree {
     /// Create a new block tree with the genesis block as the root.
     pub fn new() -> BlockTree {
+        BlockTree::with_consensus_params(ConsensusParams::default())
+    }
+
+    /// Create a new block tree whose genesis block and rules follow the given consensus parameters.
+    pub fn with_consensus_params(consensus: ConsensusParams) -> BlockTree {
         let mut bt = BlockTree {
             all_blocks: HashMap::new(),
             children_map: HashMap::new(),
@@ -228,23 +557,32 @@ impl BlockTree {
             finalized_block_id: String::new(),
             finalized_balance_map: HashMap::new(),
             finalized_tx_ids: HashSet::new(),
+            block_metadata: HashMap::new(),
+            prune_stats: PruneStats::default(),
+            main_chain_index: vec!["0".to_string()],
+            checkpoints: BTreeMap::new(),
+            consensus,
+            retarget_cache: HashMap::new(),
+            version: BLOCK_TREE_MIGRATIONS.current_version(),
         };
-        let genesis_block = BlockNode::genesis_block();
+        let genesis_block = BlockNode::genesis_block_for(&bt.consensus);
         bt.all_blocks.insert("0".to_string(), genesis_block.clone());
         bt.block_depth.insert("0".to_string(), 0);
         bt.root_id = "0".to_string();
         bt.working_block_id = "0".to_string();
         for tx in genesis_block.transactions_block.transactions {
-            let amount = tx.message.split(" ").collect::<Vec<&str>>()[1]
-                .trim_start_matches('$')
-                .parse::<i64>()
-                .unwrap();
+            let amount = tx.get_amount().unwrap();
             bt.finalized_balance_map.insert(tx.receiver, amount);
         }
         bt.finalized_block_id = "0".to_string();
         bt
     }
 
+    /// Parse a serialized block tree, upgrading it first if it was written in an older format (see `BLOCK_TREE_MIGRATIONS`).
+    pub fn from_json(json: &str) -> Result<BlockTree, String> {
+        BLOCK_TREE_MIGRATIONS.load(json)
+    }
+
     /// Add a block to the block tree. If the block is not valid to be added to the tree
     /// (i.e. it does not satsify the conditions below), ignore the block. Otherwise, add the block to the BlockTree.
     ///
@@ -253,17 +591,25 @@ impl BlockTree {
     /// 3. The block does not exist in the block tree or the orphan map. done
     /// 4. The transactions in the block must be valid. See the `verify_sig` function in the `Transaction` struct for details. done
     /// 5. The parent of the block must exist in the block tree.
-    ///     Otherwise, it will be bookkeeped in the orphans map.
-    ///     When the parent block is added to the block tree, the block will be removed from the orphan map and checked against the conditions again. done
+    ///    Otherwise, it will be bookkeeped in the orphans map.
+    ///    When the parent block is added to the block tree, the block will be removed from the orphan map and checked against the conditions again. done
     /// 6. The transactions in the block must not be duplicated with any transactions in its ancestor blocks. done
-    /// 7. Each sender in the txs in the block must have enough balance to pay for the transaction. done
+    /// 7. Each sender in the txs in the block must have enough balance to pay for the transaction and its fee. done
+    ///    A batch transaction must cover all its payments (see `Transaction::payments`), which are credited in its order.
     ///    Conceptually, the balance of one address is the sum of the money sent to the address minus the money sent from the address
     ///    when walking from the genesis block to this block, according to the order of the txs in the blocks. done
-    ///    Mining reward is a constant of $10 (added to the reward_receiver address **AFTER** considering transactions in the block). done
+    ///    Mining reward is `consensus.mining_reward` (added to the reward_receiver address **AFTER** considering transactions in the block). done
+    ///    The fees of the transactions in the block are credited to the reward_receiver address along with the mining reward.
+    /// 8. The block and its transactions must be within the size limits of `consensus` (see `ConsensusParams::check_block`).
+    /// 9. The block must meet the difficulty expected after its parent (see `expected_difficulty`), which is `leading_zero_len`
+    ///    unless the consensus parameters retarget it.
+    /// 10. If they do, the timestamp of the block, which the difficulty depends on, must be after the median time of the
+    ///     blocks before it (see `median_time_past`).
     ///
     /// When a block is successfully added to the block tree, update the related fields in the BlockTree struct
     /// (e.g., working_block_id, finalized_block_id, finalized_balance_map, finalized_tx_ids, block_depth, children_map, all_blocks, etc)
-
+    /// If the new longest path no longer goes through the finalized block (a fork deeper than the finality depth), the finalized
+    /// balances and tx ids are unwound back to the fork point and the new branch is finalized from there.
     pub fn add_block(&mut self, block: BlockNode, leading_zero_len: u16) -> Result<(), String> {
         //     todo!();
 
@@ -275,8 +621,11 @@ impl BlockTree {
             return Err("Block already exists in the block tree or orphan map.".to_string());
         }
 
+        // Ensure that the block is within the size limits
+        self.consensus.check_block(&block)?;
+
         // Ensure that block is valid
-        if (&block).validate_block(leading_zero_len) != (true, block_id.clone()) {
+        if block.validate_block_for(leading_zero_len, &self.consensus) != (true, block_id.clone()) {
             return Err("Block is not valid.".to_string());
         }
 
@@ -289,20 +638,52 @@ impl BlockTree {
             }
         };
 
-        self.all_blocks.insert(block_id.clone(), block.clone());
-        self.block_depth.insert(
-            block_id.clone(),
-            self.block_depth.get(&parent_id).unwrap() + 1,
-        );
+        // Ensure that the block meets the difficulty expected at its height on its branch.
+        let expected_difficulty = self
+            .cache_expected_difficulty(&parent_id, leading_zero_len)
+            .ok_or("The difficulty expected after the parent cannot be computed.")?;
+        if block.leading_zero_len() < expected_difficulty as u32 {
+            return Err(format!(
+                "Block has {} leading zeros, {} expected after its parent.",
+                block.leading_zero_len(),
+                expected_difficulty
+            ));
+        }
+
+        // Ensure that the timestamps of a branch cannot be moved back to lower its difficulty.
+        if self.consensus.retargets() {
+            let median_time = self.median_time_past(&parent_id).unwrap_or(0);
+            if block.header.timestamp <= median_time {
+                return Err(format!(
+                    "Block timestamp {} is not after the median time {} of the blocks before it.",
+                    block.header.timestamp, median_time
+                ));
+            }
+        }
+
+        // Ensure that the branch of the block agrees with the latest checkpoint at or below its depth.
+        let depth = self.block_depth[&parent_id] + 1;
+        if let Some((&height, checkpoint_id)) = self.checkpoints.range(..=depth).next_back() {
+            let branch_id = match depth - height {
+                0 => Some(&block_id),
+                n => self
+                    .iter_ancestors(&parent_id)
+                    .nth((n - 1) as usize)
+                    .map(|block| &block.header.block_id),
+            };
+            if branch_id != Some(checkpoint_id) {
+                return Err(format!(
+                    "Block contradicts the checkpoint {} at height {}.",
+                    checkpoint_id, height
+                ));
+            }
+        }
+
+        self.all_blocks.insert(block_id.clone(), block);
 
         // Add block to parent's children list
-        let children = self
-            .children_map
-            .entry(parent_id.clone())
-            .or_insert_with(Vec::new);
+        let children = self.children_map.entry(parent_id.clone()).or_default();
         children.push(block_id.clone());
-        let block_node = self.all_blocks.get_mut(&block_id).unwrap();
-        block_node.header.parent = parent_id.clone();
 
         // Update block depth
         let parent_depth = self.block_depth.get(&parent_id).unwrap();
@@ -335,86 +716,272 @@ impl BlockTree {
             }
         }
 
-        let txs = self.get_pending_finalization_txs();
-        let txss = self.get_pending_finalization_txs();
+        self.update_main_chain_index();
 
-        // Verify that each sender in the transactions in the block has enough balance to pay for the transaction.
+        // If a longer fork overtook the branch of the finalized block, the finalized state is first unwound back to the
+        // fork point, so that the blocks of the new branch are replayed from there.
         let mut balance_map = self.finalized_balance_map.clone();
+        let mut finalized_tx_ids = self.finalized_tx_ids.clone();
+        let since_block_id = self.unwind_finalized_state(&mut balance_map, &mut finalized_tx_ids);
+
+        // Move the blocks that became final into the finalized state, from the oldest to the newest.
+        // Each block transfers the money of its txs first, then credits its mining reward and its fees.
+        let finalized_blocks = self.get_finalized_blocks_since(since_block_id.clone());
+        for finalized_block in finalized_blocks.iter() {
+            for tx in finalized_block.transactions_block.transactions.iter() {
+                if !finalized_tx_ids.insert(tx.gen_hash()) {
+                    continue;
+                }
+                // Verify that the sender has enough balance to pay for all the payments of the transaction and its fee.
+                let Some(cost) = tx.total_cost() else {
+                    return Err(format!(
+                        "The total amount of transaction {} overflows.",
+                        tx.gen_hash()
+                    ));
+                };
+                if balance_map.get(&tx.sender).copied().unwrap_or(0) < cost {
+                    return Err(format!(
+                        "Sender {} does not have enough balance to pay for transaction.",
+                        tx.sender
+                    ));
+                }
+                *balance_map.entry(tx.sender.clone()).or_insert(0) -= cost;
+                for (receiver, amount) in tx.payments() {
+                    *balance_map.entry(receiver.clone()).or_insert(0) += amount;
+                }
+            }
+            *balance_map
+                .entry(finalized_block.header.reward_receiver.clone())
+                .or_insert(0) += self.consensus.mining_reward + finalized_block.total_fees();
+        }
+        self.finalized_block_id = match finalized_blocks.last() {
+            Some(last_finalized) => last_finalized.header.block_id.clone(),
+            None => since_block_id,
+        };
 
-        // Transfer money from sender to receiver
-        for tx in txs {
-            let sender = &tx.sender;
-            let receiver = &tx.receiver;
-            let message = &tx.message;
-            let amount_str = message
-                .split("$")
-                .nth(1)
-                .unwrap()
-                .split(" ")
-                .next()
-                .unwrap();
-            let amount = amount_str.parse::<i64>().unwrap();
-
-            if !balance_map.contains_key(sender) || balance_map[sender] < amount {
-                return Err(format!(
-                    "Sender {} does not have enough balance to pay for transaction.",
-                    sender
-                ));
+        // Update balance map
+        self.finalized_balance_map = balance_map;
+        self.finalized_tx_ids = finalized_tx_ids;
+        debug_assert!(
+            self.audit_supply().is_ok(),
+            "{}",
+            self.audit_supply().unwrap_err()
+        );
+        debug_assert!(
+            self.check_invariants().is_ok(),
+            "{:?}",
+            self.check_invariants().unwrap_err()
+        );
+
+        Ok(())
+    }
+
+    /// Undo the finalized blocks that are no longer on the longest path in the given finalized state, from the finalized
+    /// block back to the fork point with the longest path: the mining reward and fees of each block are taken back, and
+    /// its txs are reverted from the last one to the first one and no longer counted as finalized.
+    /// Return the fork point, which is the finalized block itself if it is still on the longest path.
+    fn unwind_finalized_state(
+        &self,
+        balance_map: &mut HashMap<UserId, i64>,
+        finalized_tx_ids: &mut HashSet<TxId>,
+    ) -> BlockId {
+        let fork_id = self
+            .fork_point(&self.finalized_block_id, &self.working_block_id)
+            .unwrap_or_else(|| self.root_id.clone());
+        let unwound_blocks = self
+            .iter_ancestors(&self.finalized_block_id)
+            .take_while(|block| block.header.block_id != fork_id);
+        for block in unwound_blocks {
+            *balance_map
+                .entry(block.header.reward_receiver.clone())
+                .or_insert(0) -= self.consensus.mining_reward + block.total_fees();
+            for tx in block.transactions_block.transactions.iter().rev() {
+                finalized_tx_ids.remove(&tx.gen_hash());
+                for (receiver, amount) in tx.payments() {
+                    *balance_map.entry(receiver.clone()).or_insert(0) -= amount;
+                }
+                // the finalized txs were paid for, so their total does not overflow
+                *balance_map.entry(tx.sender.clone()).or_insert(0) += tx.total_cost().unwrap_or(0);
             }
-            balance_map
-                .entry(sender.clone())
-                .and_modify(|e| *e -= amount);
+        }
+        fork_id
+    }
 
-            // Check if receiver exists in balance map, if not, add it
-            if !balance_map.contains_key(receiver) {
-                balance_map.insert(receiver.clone(), amount);
+    /// Get the last common ancestor of two blocks (one of them if it is an ancestor of the other).
+    /// Return None if one of them is not in the block tree.
+    pub fn fork_point(&self, block_id_a: &str, block_id_b: &str) -> Option<BlockId> {
+        let (mut id_a, mut id_b) = (block_id_a, block_id_b);
+        while id_a != id_b {
+            let (depth_a, depth_b) = (self.block_depth.get(id_a)?, self.block_depth.get(id_b)?);
+            if depth_a >= depth_b {
+                id_a = &self.all_blocks.get(id_a)?.header.parent;
             } else {
-                balance_map
-                    .entry(receiver.clone())
-                    .and_modify(|e| *e += amount);
+                id_b = &self.all_blocks.get(id_b)?.header.parent;
             }
         }
+        Some(id_a.to_string())
+    }
 
-        // self.working_block_id = block_id.clone();
-        // self.all_blocks.insert(block_id.clone(), block.clone());
+    /// Get the block node by the block id if exists. Otherwise, return None.
+    pub fn get_block(&self, block_id: BlockId) -> Option<BlockNode> {
+        // Please fill in the blank
+        // todo!();
+        self.all_blocks.get(&block_id).cloned()
+    }
 
-        // Update finalized tx ids
-        let mut temp = self.finalized_tx_ids.clone();
-        for tx in txss {
-            temp.insert(tx.gen_hash());
-        }
-        self.finalized_tx_ids = temp;
+    /// Record that the block was seen from the given source at the given time.
+    /// Only the first sighting of a block is kept; later calls for the same block id are ignored.
+    pub fn record_block_metadata(&mut self, block_id: &str, source: &str, first_seen_ms: u64) {
+        self.block_metadata
+            .entry(block_id.to_string())
+            .or_insert_with(|| BlockMetadata {
+                source: source.to_string(),
+                first_seen_ms,
+            });
+    }
 
-        let finalized_blocks = self.get_finalized_blocks_since(self.finalized_block_id.clone());
-        if !finalized_blocks.is_empty() {
-            self.finalized_block_id = finalized_blocks[0].header.block_id.clone();
-            // Add $10 to reward receiver; if reward receiver does not exist in balance map, add it
-            let block = &finalized_blocks[0];
-            if balance_map.contains_key(&block.header.reward_receiver) {
-                balance_map
-                    .entry(block.header.reward_receiver.clone())
-                    .and_modify(|e| *e += 10);
-            } else {
-                balance_map.insert(block.header.reward_receiver.clone(), 10);
+    /// Merge the blocks of another block tree (e.g. the serialized state of another node) into this one.
+    /// Every block goes through `add_block`, so it is validated exactly as if it had been received from the network.
+    /// Blocks are added parent first (by their depth in the other tree), followed by the orphans of the other tree.
+    /// The root block of the other tree is skipped.
+    pub fn import_blocks(&mut self, other: &BlockTree, leading_zero_len: u16) -> ImportSummary {
+        let mut blocks: Vec<&BlockNode> = other
+            .all_blocks
+            .values()
+            .filter(|block| block.header.block_id != other.root_id)
+            .collect();
+        blocks.sort_by_key(|block| {
+            (
+                other.block_depth.get(&block.header.block_id).copied(),
+                block.header.block_id.clone(),
+            )
+        });
+        let mut orphans: Vec<&BlockNode> = other.orphans.values().collect();
+        orphans.sort_by_key(|block| block.header.block_id.clone());
+
+        let mut summary = ImportSummary::default();
+        for block in blocks.into_iter().chain(orphans) {
+            let block_id = block.header.block_id.clone();
+            if self.all_blocks.contains_key(&block_id) || self.orphans.contains_key(&block_id) {
+                summary.known += 1;
+                continue;
+            }
+            match self.add_block(block.clone(), leading_zero_len) {
+                Ok(()) => summary.added.push(block_id),
+                Err(e) => summary.rejected.push((block_id, e)),
             }
         }
+        summary
+    }
 
-        // Update balance map
-        self.finalized_balance_map = balance_map;
+    /// Pin the given block ids at the given heights. Blocks added later must agree with them (see `add_block`).
+    /// It fails if the longest path already holds another block at one of the heights, e.g. when the state file is stale or forged.
+    pub fn set_checkpoints(&mut self, checkpoints: BTreeMap<u64, BlockId>) -> Result<(), String> {
+        for (height, checkpoint_id) in checkpoints.iter() {
+            if let Some(block) = self.get_block_at_height(*height) {
+                if block.header.block_id != *checkpoint_id {
+                    return Err(format!(
+                        "The longest path holds block {} at height {} instead of the checkpoint {}",
+                        block.header.block_id, height, checkpoint_id
+                    ));
+                }
+            }
+        }
+        self.checkpoints = checkpoints;
+        Ok(())
+    }
 
+    /// Use the given consensus parameters from now on (they come from the node config).
+    /// Return an error if the genesis block of the tree does not give the genesis allocation of the parameters,
+    /// i.e. if the tree was created with other parameters. A tree built from a checkpoint has no genesis block to check,
+    /// but its difficulty cannot be retargeted, since that depends on the blocks before the checkpoint.
+    pub fn set_consensus_params(&mut self, consensus: ConsensusParams) -> Result<(), String> {
+        self.retarget_cache.clear();
+        if !self.is_rooted_at_genesis() {
+            if consensus.retargets() {
+                return Err(
+                    "The difficulty of a tree built from a checkpoint cannot be retargeted."
+                        .to_string(),
+                );
+            }
+            self.consensus = consensus;
+            return Ok(());
+        }
+        let expected = BlockNode::genesis_block_for(&consensus)
+            .transactions_block
+            .transactions;
+        let actual = &self.all_blocks[&self.root_id]
+            .transactions_block
+            .transactions;
+        if *actual != expected {
+            return Err(format!(
+                "The genesis block does not give ${} to {}.",
+                consensus.genesis_allocation, consensus.genesis_receiver
+            ));
+        }
+        self.consensus = consensus;
         Ok(())
     }
 
-    /// Get the block node by the block id if exists. Otherwise, return None.
-    pub fn get_block(&self, block_id: BlockId) -> Option<BlockNode> {
-        // Please fill in the blank
-        // todo!();
-        for (_, block) in self.all_blocks.iter() {
-            if block.header.block_id == block_id {
-                return Some(block.clone());
+    /// Remove the side branches whose tip is more than `keep_depth` blocks behind the finalized block.
+    /// A side branch forks from an ancestor of the finalized block, so such a branch can no longer become the longest path.
+    /// The blocks of a pruned branch are removed from all_blocks, children_map, block_depth and block_metadata.
+    /// Return the space reclaimed by this call; the running total is kept in `prune_stats`.
+    pub fn prune_losing_branches(&mut self, keep_depth: u64) -> PruneStats {
+        let finalized_depth = self.block_depth[&self.finalized_block_id];
+        let finalized_ancestors: HashSet<BlockId> = self
+            .iter_ancestors(&self.finalized_block_id)
+            .map(|block| block.header.block_id.clone())
+            .collect();
+        let branch_roots: Vec<(BlockId, BlockId)> = finalized_ancestors
+            .iter()
+            .filter_map(|parent_id| Some((parent_id, self.children_map.get(parent_id)?)))
+            .flat_map(|(parent_id, children)| {
+                children
+                    .iter()
+                    .filter(|child_id| !finalized_ancestors.contains(*child_id))
+                    .map(move |child_id| (parent_id.clone(), child_id.clone()))
+            })
+            .collect();
+
+        let mut stats = PruneStats::default();
+        for (parent_id, branch_root) in branch_roots {
+            let mut branch = vec![branch_root.clone()];
+            let mut i = 0;
+            while i < branch.len() {
+                if let Some(children) = self.children_map.get(&branch[i]) {
+                    branch.extend(children.iter().cloned());
+                }
+                i += 1;
+            }
+            let tip_depth = branch
+                .iter()
+                .filter_map(|block_id| self.block_depth.get(block_id))
+                .max()
+                .copied()
+                .unwrap_or(0);
+            if tip_depth + keep_depth >= finalized_depth {
+                continue;
+            }
+
+            if let Some(children) = self.children_map.get_mut(&parent_id) {
+                children.retain(|child_id| *child_id != branch_root);
+            }
+            for block_id in branch {
+                if let Some(block) = self.all_blocks.remove(&block_id) {
+                    stats.pruned_blocks += 1;
+                    stats.reclaimed_bytes +=
+                        serde_json::to_vec(&block).map_or(0, |json| json.len() as u64);
+                }
+                self.children_map.remove(&block_id);
+                self.block_depth.remove(&block_id);
+                self.block_metadata.remove(&block_id);
+                self.retarget_cache.remove(&block_id);
             }
         }
-        return None;
+        self.prune_stats.pruned_blocks += stats.pruned_blocks;
+        self.prune_stats.reclaimed_bytes += stats.reclaimed_bytes;
+        stats
     }
 
     /// Get the finalized blocks on the longest path after the given block id, from the oldest to the most recent.
@@ -424,19 +991,20 @@ impl BlockTree {
         // Please fill in the blank
         // todo!();
 
-        let mut finalized_blocks = Vec::new();
-        let mut block_id = self.working_block_id.clone();
-        let depth = self.block_depth[&block_id];
-        while block_id != since_block_id {
-            let id = block_id.clone();
-            let block = self.get_block(block_id).unwrap();
-            if (depth - self.block_depth[&id]) >= 6 {
-                finalized_blocks.push(block.clone());
-            }
-            block_id = block.header.parent;
-        }
+        let working_depth = self.block_depth[&self.working_block_id];
+        let mut finalized_blocks: Vec<BlockNode> = self
+            .iter_ancestors(&self.working_block_id)
+            .take_while(|block| {
+                block.header.block_id != since_block_id && block.header.block_id != self.root_id
+            })
+            .filter(|block| {
+                let depth = self.block_depth[&block.header.block_id];
+                working_depth.saturating_sub(depth) >= self.consensus.finality_depth
+            })
+            .cloned()
+            .collect();
         finalized_blocks.reverse(); // oldest to newest
-        return finalized_blocks;
+        finalized_blocks
     }
 
     /// Get the pending transactions on the longest chain that are confirmed but not finalized.
@@ -452,50 +1020,548 @@ impl BlockTree {
                 }
             }
         }
-        return pending_txs;
+        pending_txs
+    }
+
+    /// Get the blocks on the longest path after the finalized block (confirmed but not finalized yet),
+    /// from the oldest to the most recent.
+    pub fn get_pending_finalization_blocks(&self) -> Vec<BlockNode> {
+        let mut pending_blocks: Vec<BlockNode> = self
+            .iter_ancestors(&self.working_block_id)
+            .take_while(|block| {
+                block.header.block_id != self.finalized_block_id
+                    && block.header.block_id != self.root_id
+            })
+            .cloned()
+            .collect();
+        pending_blocks.reverse(); // oldest to newest
+        pending_blocks
+    }
+
+    /// Get the blocks on the longest path that were not on it when its working block was `old_working_id`
+    /// (the blocks added since, or the winning branch of a reorganization), from the oldest to the most recent.
+    /// Only the blocks from the working block down to the fork point with the old longest path are visited.
+    pub fn get_main_chain_blocks_since(&self, old_working_id: &str) -> Vec<BlockNode> {
+        if !self.all_blocks.contains_key(old_working_id) {
+            return self.iter_main_chain().skip(1).cloned().collect();
+        }
+        let mut old_id = old_working_id.to_string();
+        let mut new_id = self.working_block_id.clone();
+        let mut new_blocks = Vec::new();
+        while old_id != new_id {
+            if self.block_depth[&new_id] >= self.block_depth[&old_id] {
+                let block = &self.all_blocks[&new_id];
+                new_blocks.push(block.clone());
+                new_id = block.header.parent.clone();
+            } else {
+                old_id = self.all_blocks[&old_id].header.parent.clone();
+            }
+        }
+        new_blocks.reverse(); // oldest to newest
+        new_blocks
+    }
+
+    /// Iterate over the given block and its ancestors, from the given block back to the genesis block.
+    /// The iterator is empty if the block is not in the block tree.
+    pub fn iter_ancestors(&self, block_id: &str) -> Ancestors<'_> {
+        Ancestors {
+            tree: self,
+            next: self.all_blocks.get(block_id),
+        }
+    }
+
+    /// Iterate over the main chain (the longest path), from the genesis block to the working block.
+    pub fn iter_main_chain(&self) -> impl DoubleEndedIterator<Item = &BlockNode> {
+        let mut main_chain: Vec<&BlockNode> = self.iter_ancestors(&self.working_block_id).collect();
+        main_chain.reverse();
+        main_chain.into_iter()
+    }
+
+    /// Get the block on the longest path at the given height, if the longest path is that long.
+    pub fn get_block_at_height(&self, height: u64) -> Option<BlockNode> {
+        if self.main_chain_index.last() == Some(&self.working_block_id) {
+            let block_id = self.main_chain_index.get(height as usize)?;
+            return self.all_blocks.get(block_id).cloned();
+        }
+        // States serialized before the index existed are only indexed once a block is added
+        self.iter_main_chain().nth(height as usize).cloned()
+    }
+
+    /// Make the height index follow the longest path after the working block changed.
+    /// Only the heights from the working block down to the fork point with the previous longest path are rewritten.
+    fn update_main_chain_index(&mut self) {
+        let working_height = self.block_depth[&self.working_block_id] as usize;
+        self.main_chain_index
+            .resize(working_height + 1, String::new());
+        let mut block_id = self.working_block_id.clone();
+        loop {
+            let height = self.block_depth[&block_id] as usize;
+            if self.main_chain_index[height] == block_id {
+                break;
+            }
+            self.main_chain_index[height] = block_id.clone();
+            if block_id == self.root_id {
+                break;
+            }
+            block_id = self.all_blocks[&block_id].header.parent.clone();
+        }
+    }
+
+    /// Whether the root of the tree is the genesis block (which is its own parent) rather than the block of a checkpoint.
+    pub fn is_rooted_at_genesis(&self) -> bool {
+        self.all_blocks
+            .get(&self.root_id)
+            .is_some_and(|root| root.header.parent == self.root_id)
+    }
+
+    /// Get the height (depth) of the given block. The genesis block has height 0.
+    /// Return None if the block is not in the block tree (e.g. an orphan).
+    pub fn height_of(&self, block_id: &str) -> Option<u64> {
+        self.block_depth.get(block_id).copied()
+    }
+
+    /// Get the total work of the branch ending at the given block: the sum of the work (see `BlockNode::work`) of the block
+    /// and its ancestors, excluding the genesis block, which is not mined.
+    /// Return None if the block is not in the block tree (e.g. an orphan).
+    pub fn branch_work(&self, block_id: &str) -> Option<u128> {
+        self.all_blocks.get(block_id)?;
+        Some(
+            self.iter_ancestors(block_id)
+                .filter(|block| block.header.block_id != self.root_id)
+                .fold(0u128, |total, block| total.saturating_add(block.work())),
+        )
+    }
+
+    /// Get the difficulty (leading "0"s of the block id) required of a child of the given block, or None if the block is not
+    /// in the block tree. The difficulty is `min_leading_zero_len` unless the consensus parameters set a retarget interval.
+    /// With one, the heights are split into periods of `retarget_interval` blocks (the first one being heights 1 to
+    /// `retarget_interval`), and the difficulty of a period is the one of the previous period, adjusted by the average time
+    /// between the blocks of that period on the branch:
+    /// - one more leading zero if they came more than 4 times faster than `target_block_time_ms`,
+    /// - one less if they came more than 4 times slower, but never below `min_leading_zero_len`.
+    ///
+    /// One more leading zero makes a block 16 times harder to mine, so a smaller gap would overshoot the target.
+    /// The difficulty after each period is cached by `add_block`, so only the periods since the last cached one are walked.
+    pub fn expected_difficulty(&self, parent_id: &str, min_leading_zero_len: u16) -> Option<u16> {
+        let parent_depth = *self.block_depth.get(parent_id)?;
+        let interval = self.consensus.retarget_interval;
+        if !self.consensus.retargets() {
+            return Some(min_leading_zero_len);
+        }
+        // The last block of the last period closed before the child
+        let boundary = self
+            .iter_ancestors(parent_id)
+            .nth((parent_depth % interval) as usize)?;
+        self.period_difficulty(&boundary.header.block_id, min_leading_zero_len)
+    }
+
+    /// Get the difficulty expected of a child of the given block (see `expected_difficulty`), and cache the one of the
+    /// period it belongs to, so that the next blocks of the branch do not walk the periods before it again.
+    fn cache_expected_difficulty(
+        &mut self,
+        parent_id: &str,
+        min_leading_zero_len: u16,
+    ) -> Option<u16> {
+        let difficulty = self.expected_difficulty(parent_id, min_leading_zero_len)?;
+        if self.consensus.retargets() {
+            let parent_depth = self.block_depth[parent_id];
+            let boundary = self
+                .iter_ancestors(parent_id)
+                .nth((parent_depth % self.consensus.retarget_interval) as usize)?;
+            let boundary_id = boundary.header.block_id.clone();
+            self.retarget_cache
+                .insert(boundary_id, (min_leading_zero_len, difficulty));
+        }
+        Some(difficulty)
+    }
+
+    /// Get the difficulty required after the given block, the last block of a retarget period (or the root of the tree),
+    /// by adjusting the difficulty after the previous period with the pace of the blocks of this one. The periods are
+    /// walked back to the root, or to the last one whose difficulty is cached. None if a period is cut short by the root.
+    fn period_difficulty(&self, boundary_id: &str, min_leading_zero_len: u16) -> Option<u16> {
+        let interval = self.consensus.retarget_interval as usize;
+        // The first and last timestamps of the periods not cached, from the most recent one
+        let mut spans = vec![];
+        let mut boundary_id = boundary_id;
+        let mut difficulty = min_leading_zero_len;
+        while boundary_id != self.root_id {
+            if let Some(&(cached_min, cached)) = self.retarget_cache.get(boundary_id) {
+                if cached_min == min_leading_zero_len {
+                    difficulty = cached;
+                    break;
+                }
+            }
+            let period: Vec<&BlockNode> = self
+                .iter_ancestors(boundary_id)
+                .take(interval + 1)
+                .collect();
+            if period.len() <= interval {
+                return None;
+            }
+            spans.push((
+                period[interval - 1].header.timestamp,
+                period[0].header.timestamp,
+            ));
+            boundary_id = &period[interval].header.block_id;
+        }
+        let target_ms = self.consensus.target_block_time_ms;
+        for (first_ms, last_ms) in spans.into_iter().rev() {
+            let average_ms = last_ms.saturating_sub(first_ms) / (interval as u64 - 1);
+            if average_ms.saturating_mul(4) < target_ms {
+                difficulty = (difficulty + 1).min(MAX_LEADING_ZERO_LEN);
+            } else if average_ms > target_ms.saturating_mul(4) {
+                difficulty = difficulty.saturating_sub(1).max(min_leading_zero_len);
+            }
+        }
+        Some(difficulty)
+    }
+
+    /// Get the median timestamp of the given block and the blocks before it, up to `MEDIAN_TIME_SPAN` blocks, or None if
+    /// the block is not in the block tree. Unlike the timestamp of the last block, it cannot be moved back by one miner.
+    pub fn median_time_past(&self, block_id: &str) -> Option<u64> {
+        let mut timestamps: Vec<u64> = self
+            .iter_ancestors(block_id)
+            .take(MEDIAN_TIME_SPAN)
+            .map(|block| block.header.timestamp)
+            .collect();
+        timestamps.sort_unstable();
+        timestamps.get(timestamps.len() / 2).copied()
+    }
+
+    /// Get the difficulty and work figures of the given block for display: its leading zeros, its work and the work of its branch.
+    /// Return None if the block is not in the block tree (e.g. an orphan).
+    pub fn get_block_work(&self, block_id: &str) -> Option<BTreeMap<String, String>> {
+        let block = self.all_blocks.get(block_id)?;
+        let mut work = BTreeMap::new();
+        work.insert(
+            "leading_zero_len".to_string(),
+            block.leading_zero_len().to_string(),
+        );
+        work.insert("work".to_string(), block.work().to_string());
+        work.insert(
+            "branch_work".to_string(),
+            self.branch_work(block_id)?.to_string(),
+        );
+        work.insert("height".to_string(), self.height_of(block_id)?.to_string());
+        Some(work)
+    }
+
+    /// Get the balance of every address at the working block.
+    /// It starts from the finalized balances and applies the txs and the mining rewards (with the fees) of the pending finalization blocks.
+    pub fn get_working_balance_map(&self) -> HashMap<UserId, i64> {
+        let mut balance_map = self.finalized_balance_map.clone();
+        for block in self.get_pending_finalization_blocks() {
+            for tx in block.transactions_block.transactions.iter() {
+                // the txs of the blocks passed `ConsensusParams::check_tx`, so their total does not overflow
+                *balance_map.entry(tx.sender.clone()).or_insert(0) -= tx.total_cost().unwrap_or(0);
+                for (receiver, amount) in tx.payments() {
+                    *balance_map.entry(receiver.clone()).or_insert(0) += amount;
+                }
+            }
+            *balance_map
+                .entry(block.header.reward_receiver.clone())
+                .or_insert(0) += self.consensus.mining_reward + block.total_fees();
+        }
+        balance_map
+    }
+
+    /// Get statistics of the longest chain over its last `last_n` blocks (the genesis block excluded).
+    /// It includes the average block interval, the average number of transactions per block,
+    /// the total supply in circulation and the fork rate (share of blocks at those depths that are not on the longest chain).
+    pub fn get_chain_stats(&self, last_n: u64) -> BTreeMap<String, String> {
+        let mut main_blocks: Vec<&BlockNode> = self
+            .iter_ancestors(&self.working_block_id)
+            .take_while(|block| block.header.block_id != self.root_id)
+            .take(last_n as usize)
+            .collect();
+        main_blocks.reverse(); // oldest to newest
+        let block_count = main_blocks.len() as u64;
+
+        let mut stats = BTreeMap::new();
+        stats.insert("#window_blocks".to_string(), block_count.to_string());
+        stats.insert("#orphans".to_string(), self.orphans.len().to_string());
+
+        let avg_block_interval = match (main_blocks.first(), main_blocks.last()) {
+            (Some(first), Some(last)) if block_count >= 2 => format!(
+                "{:.1}",
+                last.header.timestamp.saturating_sub(first.header.timestamp) as f64
+                    / (block_count - 1) as f64
+            ),
+            _ => "n/a".to_string(),
+        };
+        stats.insert("avg_block_interval".to_string(), avg_block_interval);
+
+        let tx_count: usize = main_blocks
+            .iter()
+            .map(|block| block.transactions_block.transactions.len())
+            .sum();
+        let avg_tx_per_block = if block_count > 0 {
+            format!("{:.2}", tx_count as f64 / block_count as f64)
+        } else {
+            "n/a".to_string()
+        };
+        stats.insert("avg_tx_per_block".to_string(), avg_tx_per_block);
+
+        let total_supply: i64 = self.get_working_balance_map().values().sum();
+        stats.insert("total_supply".to_string(), total_supply.to_string());
+
+        // Blocks at the same depths as the window that did not make it into the longest chain
+        let working_depth = self.block_depth[&self.working_block_id];
+        let min_depth = working_depth + 1 - block_count;
+        let main_ids: HashSet<&BlockId> = main_blocks.iter().map(|b| &b.header.block_id).collect();
+        let stale_count = self
+            .block_depth
+            .iter()
+            .filter(|(id, depth)| **depth >= min_depth && !main_ids.contains(id))
+            .count() as u64;
+        let fork_rate = if block_count > 0 {
+            format!(
+                "{:.3}",
+                stale_count as f64 / (stale_count + block_count) as f64
+            )
+        } else {
+            "n/a".to_string()
+        };
+        stats.insert("#stale_blocks".to_string(), stale_count.to_string());
+        stats.insert("fork_rate".to_string(), fork_rate);
+
+        stats
+    }
+
+    /// Check the supply invariant of the chain: the finalized balances add up to the genesis supply plus the
+    /// rewards of the finalized blocks, and the working balances add up to the genesis supply plus the rewards
+    /// of all the blocks on the longest chain. No finalized balance may be negative.
+    /// The genesis supply of a tree built from a checkpoint is the genesis allocation of its consensus parameters.
+    /// It returns a description of the first violation found.
+    pub fn audit_supply(&self) -> Result<(), String> {
+        let genesis_supply: i64 = self
+            .all_blocks
+            .get(&self.root_id)
+            .filter(|_| self.is_rooted_at_genesis())
+            .map(|genesis| {
+                genesis
+                    .transactions_block
+                    .transactions
+                    .iter()
+                    .map(|tx| tx.get_amount().unwrap_or(0))
+                    .sum()
+            })
+            .unwrap_or(self.consensus.genesis_allocation);
+
+        let finalized_height = self.height_of(&self.finalized_block_id).unwrap_or(0) as i64;
+        let finalized_total: i64 = self.finalized_balance_map.values().sum();
+        let expected_finalized = genesis_supply + self.consensus.mining_reward * finalized_height;
+        if finalized_total != expected_finalized {
+            return Err(format!(
+                "Finalized balances add up to {} but {} was issued ({} genesis + {} finalized blocks)",
+                finalized_total, expected_finalized, genesis_supply, finalized_height
+            ));
+        }
+
+        let working_height = self.height_of(&self.working_block_id).unwrap_or(0) as i64;
+        let working_total: i64 = self.get_working_balance_map().values().sum();
+        let expected_working = genesis_supply + self.consensus.mining_reward * working_height;
+        if working_total != expected_working {
+            return Err(format!(
+                "Working balances add up to {} but {} was issued ({} genesis + {} blocks)",
+                working_total, expected_working, genesis_supply, working_height
+            ));
+        }
+
+        if let Some((user_id, balance)) = self.finalized_balance_map.iter().find(|(_, b)| **b < 0) {
+            return Err(format!(
+                "Finalized balance of {} is negative ({})",
+                user_id, balance
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check that the bookkeeping maps of the tree agree with each other:
+    /// every child in children_map is a known block whose parent pointer is the key it is listed under,
+    /// every block except the root is listed exactly once under its parent and is one block deeper than it,
+    /// block_depth has no entry for unknown blocks, orphans are not in the tree, the working and finalized blocks are known,
+    /// and the height index holds blocks of the right depth (none below the root of a tree built from a checkpoint).
+    /// It returns a description of every violation found.
+    pub fn check_invariants(&self) -> Result<(), Vec<String>> {
+        let mut violations = vec![];
+        for (parent_id, children) in self.children_map.iter() {
+            if !self.all_blocks.contains_key(parent_id) {
+                violations.push(format!(
+                    "children_map has an entry for unknown block {}",
+                    parent_id
+                ));
+            }
+            for child_id in children {
+                match self.all_blocks.get(child_id) {
+                    None => violations.push(format!(
+                        "Dangling child {} of block {}",
+                        child_id, parent_id
+                    )),
+                    Some(child) if child.header.parent != *parent_id => violations.push(format!(
+                        "Block {} is a child of {} in children_map but its parent is {}",
+                        child_id, parent_id, child.header.parent
+                    )),
+                    Some(_) => {}
+                }
+            }
+        }
+
+        for (block_id, block) in self.all_blocks.iter() {
+            let depth = self.block_depth.get(block_id);
+            if *block_id == self.root_id {
+                if depth.is_none() || (self.is_rooted_at_genesis() && depth != Some(&0)) {
+                    violations.push(format!("Root block {} has depth {:?}", block_id, depth));
+                }
+                continue;
+            }
+            let parent_id = &block.header.parent;
+            if !self.all_blocks.contains_key(parent_id) {
+                violations.push(format!(
+                    "Parent {} of block {} is not in the tree",
+                    parent_id, block_id
+                ));
+                continue;
+            }
+            let listed = self.children_map.get(parent_id).map_or(0, |children| {
+                children.iter().filter(|id| *id == block_id).count()
+            });
+            if listed != 1 {
+                violations.push(format!(
+                    "Block {} is listed {} times as a child of its parent {}",
+                    block_id, listed, parent_id
+                ));
+            }
+            let expected_depth = self.block_depth.get(parent_id).map(|depth| depth + 1);
+            if depth.is_none() || depth.copied() != expected_depth {
+                violations.push(format!(
+                    "Block {} has depth {:?} but its parent {} has depth {:?}",
+                    block_id,
+                    depth,
+                    parent_id,
+                    self.block_depth.get(parent_id)
+                ));
+            }
+        }
+
+        for block_id in self.block_depth.keys() {
+            if !self.all_blocks.contains_key(block_id) {
+                violations.push(format!(
+                    "block_depth has an entry for unknown block {}",
+                    block_id
+                ));
+            }
+        }
+        for block_id in self.orphans.keys() {
+            if self.all_blocks.contains_key(block_id) {
+                violations.push(format!("Orphan {} is also in the tree", block_id));
+            }
+        }
+        for (name, block_id) in [
+            ("Working", &self.working_block_id),
+            ("Finalized", &self.finalized_block_id),
+        ] {
+            if !self.all_blocks.contains_key(block_id) {
+                violations.push(format!("{} block {} is not in the tree", name, block_id));
+            }
+        }
+        if self.main_chain_index.last() == Some(&self.working_block_id) {
+            let root_height = self.height_of(&self.root_id).unwrap_or(0) as usize;
+            for (height, block_id) in self.main_chain_index.iter().enumerate() {
+                if height < root_height && block_id.is_empty() {
+                    continue;
+                }
+                if self.block_depth.get(block_id) != Some(&(height as u64)) {
+                    violations.push(format!(
+                        "Block {} is indexed at height {} but has depth {:?}",
+                        block_id,
+                        height,
+                        self.block_depth.get(block_id)
+                    ));
+                }
+            }
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
     }
 
     /// Get status information of the BlockTree for debug printing.
-    pub fn get_status(&self) -> BTreeMap<String, String> {
+    pub fn get_status(&self) -> ChainStatus {
         // Please fill in the blank
         // For debugging purpose, you can return any dictionary of strings as the status of the BlockTree.
         // It should be displayed in the Client UI eventually.
         // todo!();
-        let mut status = BTreeMap::new();
-        status.insert("#blocks".to_string(), self.all_blocks.len().to_string());
-        status.insert("#orphans".to_string(), self.orphans.len().to_string());
-        status.insert(
-            "finalized_id".to_string(),
-            self.finalized_block_id.to_string(),
-        );
-        status.insert("root_id".to_string(), self.root_id.to_string());
-        status.insert(
-            "working_depth".to_string(),
-            self.block_depth[&self.working_block_id].to_string(),
-        );
-        status.insert("working_id".to_string(), self.working_block_id.to_string());
+        ChainStatus {
+            block_count: self.all_blocks.len(),
+            orphan_count: self.orphans.len(),
+            root_id: self.root_id.clone(),
+            working_block_id: self.working_block_id.clone(),
+            height: self.block_depth[&self.working_block_id],
+            finalized_block_id: self.finalized_block_id.clone(),
+            finalized_height: self.block_depth[&self.finalized_block_id],
+            pruned_blocks: self.prune_stats.pruned_blocks,
+            pruned_bytes: self.prune_stats.reclaimed_bytes,
+        }
+    }
+}
+
+impl Default for BlockTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An iterator over a block and its ancestors in a BlockTree, see `BlockTree::iter_ancestors`.
+pub struct Ancestors<'a> {
+    tree: &'a BlockTree,
+    next: Option<&'a BlockNode>,
+}
 
-        status
+impl<'a> Iterator for Ancestors<'a> {
+    type Item = &'a BlockNode;
+
+    fn next(&mut self) -> Option<&'a BlockNode> {
+        let block = self.next.take()?;
+        // The genesis block is its own parent, so the walk stops at the root
+        if block.header.block_id != self.tree.root_id {
+            self.next = self.tree.all_blocks.get(&block.header.parent);
+        }
+        Some(block)
     }
 }
 
 /// The struct representing a puzzle for the miner to solve. The puzzle is to find a nonce such that when concatenated
-/// with the serialized json string of this `Puzzle` struct, the sha256 hash of the result has the required leading zero length.
-#[derive(Serialize)]
+/// with the serialized json string of this `Puzzle` struct, the hash of the result has the required leading zero length
+/// (the sha256 hash, or the one of the hash function of the chain, see `ConsensusParams::hash_function`).
+#[derive(Serialize, Default)]
 pub struct Puzzle {
     pub parent: BlockId,
     pub merkle_root: String,
     pub reward_receiver: UserId,
+    /// The public key of the node that signs the block (see `BlockNodeHeader::miner_signature`), in PEM format. It is
+    /// left out of the json of the unsigned blocks, so their ids are the same as before blocks could be signed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub miner_pub_key: Option<String>,
+    /// The timestamp of the block, on a chain that retargets its difficulty (see `ConsensusParams::retargets`), since
+    /// the difficulty depends on it. It is left out of the json on the other chains, so their block ids do not change.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
 }
 
 /// The struct representing a block header. Each `BlockNode` has one `BlockNodeHeader`.
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
 pub struct BlockNodeHeader {
     /// The block id of the parent block.
     pub parent: BlockId,
     /// The merkle root of the transactions in the block.
     pub merkle_root: String,
     /// The timestamp of the block. For genesis block, it is 0. For other blocks, greater or equal to 1 is considered valid.
+    /// On a chain that retargets its difficulty, it is part of the block id, and must be after the median time of the
+    /// blocks before it (see `BlockTree::median_time_past`).
     pub timestamp: u64,
     /// The block id of the block (the block id is the sha256 hash of the concatination of the nonce and a `Puzzle` derived from the block)
     pub block_id: BlockId,
@@ -503,10 +1569,69 @@ pub struct BlockNodeHeader {
     pub nonce: String,
     /// The reward receiver of the block.
     pub reward_receiver: UserId,
+    /// The signature of the node that mined the block, if that node signs its blocks. It is checked when present.
+    /// Its public key is part of the `Puzzle`, so the signature can be neither stripped nor replaced without changing
+    /// the block id.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub miner_signature: Option<MinerSignature>,
+}
+
+impl BlockNodeHeader {
+    /// The `Puzzle` the nonce of this block solves on a chain with the given consensus parameters.
+    pub fn puzzle(&self, consensus: &ConsensusParams) -> Puzzle {
+        Puzzle {
+            parent: self.parent.clone(),
+            merkle_root: self.merkle_root.clone(),
+            reward_receiver: self.reward_receiver.clone(),
+            miner_pub_key: self
+                .miner_signature
+                .as_ref()
+                .map(|miner_signature| miner_signature.pub_key_pem.clone()),
+            timestamp: consensus.retargets().then_some(self.timestamp),
+        }
+    }
+}
+
+/// The signature of a mining node over a block (see `BlockNodeHeader::miner_signature`).
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct MinerSignature {
+    /// The public key of the node identity that mined the block, in PEM format
+    pub pub_key_pem: String,
+    /// The Base64 encoded signature of the block id with that key
+    pub sig: String,
+}
+
+impl MinerSignature {
+    /// Verify the signature against the given block id. Malformed keys or signatures fail the verification.
+    /// Without the `crypto` feature, every signature passes in the tests and the fuzzing builds, and fails in any other
+    /// (see `Transaction::verify_sig_with_key`).
+    #[cfg(not(feature = "crypto"))]
+    pub fn verify(&self, _block_id: &str) -> bool {
+        cfg!(any(test, fuzzing))
+    }
+
+    /// Verify the signature against the given block id. Malformed keys or signatures fail the verification.
+    #[cfg(feature = "crypto")]
+    pub fn verify(&self, block_id: &str) -> bool {
+        let public_key = match rsa::RsaPublicKey::from_pkcs1_pem(&self.pub_key_pem) {
+            Ok(public_key) => public_key,
+            Err(_) => return false,
+        };
+        let signature = match Base64::decode_vec(&self.sig)
+            .ok()
+            .and_then(|bytes| RSASig::from_bytes(&bytes).ok())
+        {
+            Some(signature) => signature,
+            None => return false,
+        };
+        VerifyingKey::<Sha256>::new(public_key)
+            .verify(block_id.as_bytes(), &signature)
+            .is_ok()
+    }
 }
 
 /// The struct representing a block node.
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
 pub struct BlockNode {
     /// The header of the block.
     pub header: BlockNodeHeader,
@@ -515,9 +1640,39 @@ pub struct BlockNode {
 }
 
 impl BlockNode {
+    /// The number of leading "0"s of the block id (in hex), i.e. the difficulty the block actually meets.
+    pub fn leading_zero_len(&self) -> u32 {
+        self.header
+            .block_id
+            .chars()
+            .take_while(|c| *c == '0')
+            .count() as u32
+    }
+
+    /// The sum of the fees of the transactions in this block, credited to its reward receiver.
+    pub fn total_fees(&self) -> i64 {
+        self.transactions_block
+            .transactions
+            .iter()
+            .map(|tx| tx.get_fee())
+            .sum()
+    }
+
+    /// The expected number of hashes needed to find this block: 16 to the power of its leading zeros.
+    pub fn work(&self) -> u128 {
+        1u128
+            .checked_shl(4 * self.leading_zero_len())
+            .unwrap_or(u128::MAX)
+    }
+
     /// Create the genesis block that contains the initial transactions
     /// (give $299792458 to the address of Alice `MDgCMQCqrJ1yIJ7cDQIdTuS+4CkKn/tQPN7bZFbbGCBhvjQxs71f6Vu+sD9eh8JGpfiZSckCAwEAAQ==`)
     pub fn genesis_block() -> BlockNode {
+        BlockNode::genesis_block_for(&ConsensusParams::default())
+    }
+
+    /// Create the genesis block giving `genesis_allocation` to `genesis_receiver` (see `ConsensusParams`).
+    pub fn genesis_block_for(consensus: &ConsensusParams) -> BlockNode {
         let header = BlockNodeHeader {
             parent: "0".to_string(),
             merkle_root: "0".to_string(),
@@ -525,14 +1680,14 @@ impl BlockNode {
             block_id: "0".to_string(),
             nonce: "0".to_string(),
             reward_receiver: "GENESIS".to_string(),
+            miner_signature: None,
         };
 
         let transactions_block = Transactions {
             transactions: vec![Transaction::new(
                 "GENESIS".to_owned(),
-                "MDgCMQCqrJ1yIJ7cDQIdTuS+4CkKn/tQPN7bZFbbGCBhvjQxs71f6Vu+sD9eh8JGpfiZSckCAwEAAQ=="
-                    .to_string(),
-                "SEND $299792458".to_owned(),
+                consensus.genesis_receiver.clone(),
+                format!("SEND ${}", consensus.genesis_allocation),
                 "GENESIS".to_owned(),
             )],
             merkle_tree: MerkleTree { hashes: vec![] }, // Skip merkle tree generation for genesis block
@@ -552,25 +1707,50 @@ impl BlockNode {
     /// 2. All the transactions in the block are valid.
     /// 3. The merkle root in the block header is indeed the merkle root of the transactions in the block.
     pub fn validate_block(&self, leading_zero_len: u16) -> (bool, BlockId) {
+        self.validate_block_with(leading_zero_len, HashFunction::Sha256)
+    }
+
+    /// Check the validity of a block of a chain built with the given hash function (see `validate_block`): its block id
+    /// and merkle root are computed with that hash function.
+    pub fn validate_block_with(
+        &self,
+        leading_zero_len: u16,
+        hash_function: HashFunction,
+    ) -> (bool, BlockId) {
+        let consensus = ConsensusParams {
+            hash_function,
+            ..ConsensusParams::default()
+        };
+        self.validate_block_for(leading_zero_len, &consensus)
+    }
+
+    /// Check the validity of a block of a chain with the given consensus parameters (see `validate_block`): its block id
+    /// and merkle root are computed with their hash function, and its block id commits its timestamp if they retarget
+    /// the difficulty (see `Puzzle::timestamp`).
+    pub fn validate_block_for(
+        &self,
+        leading_zero_len: u16,
+        consensus: &ConsensusParams,
+    ) -> (bool, BlockId) {
         // Please fill in the blank
         // todo!();
 
-        let mut hasher = Sha256::new();
+        let hash_function = consensus.hash_function;
+        let mut hasher = hash_function.hasher();
         let block_nonce = self.header.nonce.clone();
         let block_id = self.header.block_id.clone();
 
         // Check that the block's hash satisfies the difficulty requirement.
         if !block_id.starts_with(&"0".repeat(leading_zero_len as usize)) {
-            println!("Block does not satisfy difficulty requirement.");
+            eprintln!(
+                "[BlockTree] Block {} does not satisfy difficulty requirement.",
+                block_id
+            );
             return (false, block_id);
         }
 
         // Create a puzzle struct from the block header and serialize it to a json string.
-        let puzzle = Puzzle {
-            parent: self.header.parent.clone(),
-            merkle_root: self.header.merkle_root.clone(),
-            reward_receiver: self.header.reward_receiver.clone(),
-        };
+        let puzzle = self.header.puzzle(consensus);
         let serialized = serde_json::to_string(&puzzle).unwrap().to_owned();
 
         let mut owned_string: String = block_nonce.clone();
@@ -580,31 +1760,47 @@ impl BlockNode {
 
         // Verify that the block_id of the block is equal to the computed hash in the puzzle solution.
         if hex::encode(res) != block_id {
-            println!(
-                "Block ID does not match computed hash in puzzle solution.{} {}",
+            eprintln!(
+                "[BlockTree] Block ID does not match computed hash in puzzle solution. {} {}",
                 block_id,
                 hex::encode(res)
             );
             return (false, hex::encode(res));
         }
 
+        // Verify the signature of the mining node, if the block carries one. Its key is committed in the puzzle above.
+        if let Some(miner_signature) = &self.header.miner_signature {
+            if !miner_signature.verify(&block_id) {
+                return (false, block_id);
+            }
+        }
+
         // Verify that the transactions in the block are valid using the `verify_sig` function in the `Transaction` struct.
-        let verified = self
+        let invalid_tx = self
             .transactions_block
             .transactions
             .iter()
-            .all(|tx| tx.verify_sig());
-        if !verified {
-            println!("Block contains invalid transactions.");
+            .find_map(|tx| tx.verify_sig().err().map(|e| (tx.gen_hash(), e)));
+        if let Some((tx_id, e)) = invalid_tx {
+            eprintln!(
+                "[BlockTree] Block {} contains invalid transaction {}: {}",
+                block_id, tx_id, e
+            );
             return (false, block_id);
         }
 
         // Verify merkle root of the block matches the merkle root of transactions.
-        let root = self.transactions_block.merkle_tree.hashes.last().unwrap()[0].clone();
-        if root != self.header.merkle_root {
-            println!("Block merkle root does not match merkle root of transactions.");
+        let (root, merkle_tree) = MerkleTree::create_merkle_tree_with(
+            self.transactions_block.transactions.clone(),
+            hash_function,
+        );
+        if root != self.header.merkle_root || merkle_tree != self.transactions_block.merkle_tree {
+            eprintln!(
+                "[BlockTree] Block {} merkle root does not match merkle root of transactions.",
+                block_id
+            );
             return (false, block_id);
         }
-        return (true, block_id);
+        (true, block_id)
     }
 }
@@ -0,0 +1,150 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// This file implements the checkpoints of a block tree, from which a new node syncs without replaying the chain from the
+// genesis block. A `Checkpoint` is a snapshot of the finalized state of a chain (the finalized balances and transaction
+// ids) at its finalized block, together with that block, signed by the node that took it.
+// `BlockTree::from_checkpoint` builds a block tree whose root is the block of the checkpoint instead of the genesis block,
+// then adds the blocks that came after it. Nothing below the root is known to such a tree, so the checkpoint is only as
+// good as its signer: the node loading it decides which signers it trusts.
+
+use crate::block::{BlockId, BlockNode, BlockTree, MinerSignature, TxId, UserId};
+use crate::consensus_params::ConsensusParams;
+use crate::hash::HashFunction;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// A snapshot of the finalized state of a chain at its finalized block (see `BlockTree::checkpoint`).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    /// The height of the finalized block
+    pub height: u64,
+    /// The id of the finalized block
+    pub finalized_block_id: BlockId,
+    /// The finalized block itself, which is the root of the block trees built from the checkpoint
+    pub block: BlockNode,
+    /// The finalized balance of every address at the finalized block
+    pub finalized_balance_map: BTreeMap<UserId, i64>,
+    /// The ids of the transactions finalized up to the finalized block
+    pub finalized_tx_ids: BTreeSet<TxId>,
+    /// The signature of the node that took the checkpoint over its digest (see `digest`), if signed
+    #[serde(default)]
+    pub signature: Option<MinerSignature>,
+}
+
+impl Checkpoint {
+    /// The sha256 hash (in hex format) of the serialized checkpoint without its signature. This is what the signer signs.
+    pub fn digest(&self) -> String {
+        let unsigned = Checkpoint {
+            signature: None,
+            ..self.clone()
+        };
+        HashFunction::Sha256.digest_hex(serde_json::to_string(&unsigned).unwrap().as_bytes())
+    }
+
+    /// Whether the checkpoint carries a valid signature over its digest. It does not tell whether the signer is trusted.
+    pub fn is_signed(&self) -> bool {
+        self.signature
+            .as_ref()
+            .is_some_and(|signature| signature.verify(&self.digest()))
+    }
+
+    /// Check that the checkpoint is consistent with the given consensus parameters, as far as it can be checked without the
+    /// blocks before it: its block hashes to the finalized block id (the genesis block of the parameters at height 0),
+    /// no balance is negative, and the balances add up to the genesis supply plus the rewards of `height` blocks.
+    pub fn check(&self, consensus: &ConsensusParams) -> Result<(), String> {
+        let block_id = &self.block.header.block_id;
+        if *block_id != self.finalized_block_id {
+            return Err(format!(
+                "The checkpoint block is {} instead of the finalized block {}",
+                block_id, self.finalized_block_id
+            ));
+        }
+        if self.height == 0 {
+            if self.block != BlockNode::genesis_block_for(consensus) {
+                return Err("The checkpoint at height 0 is not the genesis block".to_string());
+            }
+        } else if self.block.validate_block_for(0, consensus) != (true, block_id.clone())
+        {
+            return Err(format!("The checkpoint block {} is not valid", block_id));
+        }
+        if let Some((user_id, balance)) = self.finalized_balance_map.iter().find(|(_, b)| **b < 0) {
+            return Err(format!(
+                "The checkpoint balance of {} is negative ({})",
+                user_id, balance
+            ));
+        }
+        let total: i64 = self.finalized_balance_map.values().sum();
+        let expected = consensus.genesis_allocation + consensus.mining_reward * self.height as i64;
+        if total != expected {
+            return Err(format!(
+                "The checkpoint balances add up to {} but {} was issued at height {}",
+                total, expected, self.height
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl BlockTree {
+    /// Take an unsigned checkpoint of the finalized state of this block tree, at its finalized block.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            height: self.block_depth[&self.finalized_block_id],
+            finalized_block_id: self.finalized_block_id.clone(),
+            block: self.all_blocks[&self.finalized_block_id].clone(),
+            finalized_balance_map: self
+                .finalized_balance_map
+                .iter()
+                .map(|(user_id, balance)| (user_id.clone(), *balance))
+                .collect(),
+            finalized_tx_ids: self.finalized_tx_ids.iter().cloned().collect(),
+            signature: None,
+        }
+    }
+
+    /// Create a block tree from a checkpoint (see `Checkpoint::check`), then add the given blocks after it, in order.
+    /// Each block goes through `add_block`, so a block whose parent is not in the tree is kept as an orphan.
+    /// The block of the checkpoint is the root of the tree, at the height of the checkpoint, and is its finalized block.
+    /// The signature of the checkpoint is not checked here. Chains whose difficulty is retargeted are not supported, since
+    /// the difficulty depends on the timestamps of the blocks before the checkpoint.
+    pub fn from_checkpoint(
+        checkpoint: &Checkpoint,
+        consensus: ConsensusParams,
+        blocks: Vec<BlockNode>,
+        leading_zero_len: u16,
+    ) -> Result<BlockTree, String> {
+        checkpoint.check(&consensus)?;
+        if consensus.retargets() {
+            return Err(
+                "Checkpoints are not supported on a chain whose difficulty is retargeted"
+                    .to_string(),
+            );
+        }
+        let root_id = checkpoint.finalized_block_id.clone();
+        let mut main_chain_index = vec![String::new(); checkpoint.height as usize];
+        main_chain_index.push(root_id.clone());
+        let mut bt = BlockTree {
+            all_blocks: HashMap::from([(root_id.clone(), checkpoint.block.clone())]),
+            block_depth: HashMap::from([(root_id.clone(), checkpoint.height)]),
+            root_id: root_id.clone(),
+            working_block_id: root_id.clone(),
+            finalized_block_id: root_id,
+            finalized_balance_map: checkpoint
+                .finalized_balance_map
+                .clone()
+                .into_iter()
+                .collect(),
+            finalized_tx_ids: checkpoint.finalized_tx_ids.iter().cloned().collect(),
+            main_chain_index,
+            ..BlockTree::with_consensus_params(consensus)
+        };
+        for block in blocks {
+            let block_id = block.header.block_id.clone();
+            bt.add_block(block, leading_zero_len)
+                .map_err(|e| format!("Block {} after the checkpoint: {}", block_id, e))?;
+        }
+        Ok(bt)
+    }
+}
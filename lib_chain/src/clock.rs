@@ -0,0 +1,118 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// This file implements the clock shared by bin_client and bin_nakamoto: the real time, or a virtual time for reproducible
+// bot scenarios. The virtual time is only built with the `virtual-clock` feature, and is then switched on by the
+// `NAKAMOTO_VIRTUAL_CLOCK_MS` environment variable (the start time, in ms since the UNIX epoch), which bin_nakamoto inherits
+// from bin_client. It only moves when a bot `SleepMs` (or a bot command waiting for the chain) advances the clock of the
+// client, which sends the AdvanceClock IPC request to bin_nakamoto alongside, so the transactions, the block timestamps and
+// the miner pacing do not depend on the machine. The time it takes to solve a puzzle is still real: only scenarios whose
+// outcome does not race the miner are reproducible.
+
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// The environment variable that switches on the virtual time, starting at its value (in ms since the UNIX epoch).
+pub const VIRTUAL_CLOCK_ENV: &str = "NAKAMOTO_VIRTUAL_CLOCK_MS";
+
+/// The source of the current time and of the sleeps of the background threads.
+pub enum Clock {
+    /// The time of the system
+    Real,
+    /// A time that only moves when it is advanced (see `advance_ms`)
+    Virtual {
+        /// the current virtual time (ms since the UNIX epoch)
+        now_ms: Mutex<u64>,
+        /// notified whenever the virtual time moves
+        advanced: Condvar,
+    },
+}
+
+impl Clock {
+    /// A virtual clock starting at the given time.
+    pub fn new_virtual(start_ms: u64) -> Clock {
+        Clock::Virtual {
+            now_ms: Mutex::new(start_ms),
+            advanced: Condvar::new(),
+        }
+    }
+
+    /// The clock selected by `VIRTUAL_CLOCK_ENV`: virtual if it holds a time and the `virtual-clock` feature is on,
+    /// real otherwise.
+    fn from_env() -> Clock {
+        let Ok(value) = std::env::var(VIRTUAL_CLOCK_ENV) else {
+            return Clock::Real;
+        };
+        if !cfg!(feature = "virtual-clock") {
+            eprintln!(
+                "[Clock] Ignoring {}, built without the `virtual-clock` feature",
+                VIRTUAL_CLOCK_ENV
+            );
+            return Clock::Real;
+        }
+        match value.parse() {
+            Ok(start_ms) => Clock::new_virtual(start_ms),
+            Err(_) => {
+                eprintln!(
+                    "[Clock] Ignoring {}={:?}, not a time in ms",
+                    VIRTUAL_CLOCK_ENV, value
+                );
+                Clock::Real
+            }
+        }
+    }
+
+    /// Whether the time only moves when advanced.
+    pub fn is_virtual(&self) -> bool {
+        matches!(self, Clock::Virtual { .. })
+    }
+
+    /// The current time in milliseconds since the UNIX epoch.
+    pub fn now_ms(&self) -> u64 {
+        match self {
+            Clock::Real => SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            Clock::Virtual { now_ms, .. } => *now_ms.lock().unwrap(),
+        }
+    }
+
+    /// Block the calling thread for the given time. With a virtual clock, until the clock has been advanced that much.
+    pub fn sleep_ms(&self, ms: u64) {
+        match self {
+            Clock::Real => thread::sleep(Duration::from_millis(ms)),
+            Clock::Virtual { now_ms, advanced } => {
+                let deadline_ms = now_ms.lock().unwrap().saturating_add(ms);
+                let _now_ms = advanced
+                    .wait_while(now_ms.lock().unwrap(), |now_ms| *now_ms < deadline_ms)
+                    .unwrap();
+            }
+        }
+    }
+
+    /// Move a virtual clock forward, waking the threads whose sleep is over, and return the new time.
+    /// The real time cannot be moved, so it returns an error.
+    pub fn advance_ms(&self, ms: u64) -> Result<u64, String> {
+        match self {
+            Clock::Real => Err(format!(
+                "The clock is real (build with the `virtual-clock` feature and set {} to use a virtual clock)",
+                VIRTUAL_CLOCK_ENV
+            )),
+            Clock::Virtual { now_ms, advanced } => {
+                let mut now_ms = now_ms.lock().unwrap();
+                *now_ms = now_ms.saturating_add(ms);
+                advanced.notify_all();
+                Ok(*now_ms)
+            }
+        }
+    }
+}
+
+/// The clock of this process, selected by `VIRTUAL_CLOCK_ENV` when it is first used.
+pub fn clock() -> &'static Clock {
+    static CLOCK: OnceLock<Clock> = OnceLock::new();
+    CLOCK.get_or_init(Clock::from_env)
+}
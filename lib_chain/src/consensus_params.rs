@@ -0,0 +1,123 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// This file implements the consensus parameters: the rules every node of a network must agree on, or their chains diverge.
+// They used to be magic numbers in the block tree (the $10 mining reward, the finality depth of 6 blocks, the $299792458 of
+// the genesis block). They are now kept in one struct, loaded from the node config (see `BlockTree::set_consensus_params`),
+// and the defaults are the original values. The limits on the size of blocks and messages are generous by default,
+// so that the chains created before they existed stay valid.
+// The difficulty is fixed by default; with `retarget_interval` set, the difficulty required of a block follows the pace of
+// the blocks before it on its branch (see `BlockTree::expected_difficulty`).
+// The block ids and merkle trees are sha256 hashes by default; `hash_function` builds a chain with another hash function
+// (see hash.rs), which every node and miner of the chain must then use.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::block::{BlockNode, Transaction, UserId};
+use crate::hash::HashFunction;
+
+/// The consensus parameters of a chain. Missing fields in a config take their default value.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct ConsensusParams {
+    /// the mining reward credited to the reward receiver of every block (after the transactions of the block)
+    pub mining_reward: i64,
+    /// the number of blocks that must follow a block on the longest chain for it to be finalized
+    pub finality_depth: u64,
+    /// the max number of transactions in one block
+    pub max_block_tx_count: usize,
+    /// the max length (in bytes) of the message of one transaction
+    pub max_message_len: usize,
+    /// the max number of outputs of one batch transaction (besides the payment to its receiver)
+    pub max_tx_outputs: usize,
+    /// the address credited by the genesis block
+    pub genesis_receiver: UserId,
+    /// the amount credited by the genesis block, i.e. the initial supply
+    pub genesis_allocation: i64,
+    /// the number of blocks between two difficulty adjustments; 0 (or 1) keeps the difficulty fixed
+    pub retarget_interval: u64,
+    /// the average time (in ms) between two blocks the difficulty adjustments aim for
+    pub target_block_time_ms: u64,
+    /// the hash function of the block ids (the proof of work) and of the merkle trees
+    pub hash_function: HashFunction,
+}
+
+impl Default for ConsensusParams {
+    fn default() -> Self {
+        ConsensusParams {
+            mining_reward: 10,
+            finality_depth: 6,
+            max_block_tx_count: 256,
+            max_message_len: 1024,
+            max_tx_outputs: 16,
+            genesis_receiver:
+                "MDgCMQCqrJ1yIJ7cDQIdTuS+4CkKn/tQPN7bZFbbGCBhvjQxs71f6Vu+sD9eh8JGpfiZSckCAwEAAQ=="
+                    .to_string(),
+            genesis_allocation: 299792458,
+            retarget_interval: 0,
+            target_block_time_ms: 10_000,
+            hash_function: HashFunction::Sha256,
+        }
+    }
+}
+
+impl ConsensusParams {
+    /// Whether the difficulty of the chain is retargeted (see `retarget_interval`).
+    pub fn retargets(&self) -> bool {
+        self.retarget_interval >= 2
+    }
+
+    /// Check the size limits of a transaction, that its fee is not negative, that its amount and the amounts of its
+    /// outputs are positive (a negative one would credit the sender) and that their total with the fee does not overflow.
+    /// Return the reason why it is not valid, if it is not.
+    pub fn check_tx(&self, tx: &Transaction) -> Result<(), String> {
+        if !matches!(tx.get_amount(), Some(amount) if amount > 0) {
+            return Err(format!(
+                "Transaction amount is not a positive number of dollars ({:?}).",
+                tx.message
+            ));
+        }
+        if tx.get_fee() < 0 {
+            return Err(format!("Transaction fee is negative ({}).", tx.get_fee()));
+        }
+        if tx.message.len() > self.max_message_len {
+            return Err(format!(
+                "Transaction message is {} bytes long, at most {} allowed.",
+                tx.message.len(),
+                self.max_message_len
+            ));
+        }
+        if tx.outputs.len() > self.max_tx_outputs {
+            return Err(format!(
+                "Transaction has {} outputs, at most {} allowed.",
+                tx.outputs.len(),
+                self.max_tx_outputs
+            ));
+        }
+        if let Some(output) = tx.outputs.iter().find(|output| output.amount <= 0) {
+            return Err(format!(
+                "Transaction output to {} is not positive ({}).",
+                output.receiver, output.amount
+            ));
+        }
+        if tx.total_cost().is_none() {
+            return Err("Transaction total amount and fee overflow.".to_string());
+        }
+        Ok(())
+    }
+
+    /// Check the size limits of a block and of its transactions (see `check_tx`). Return the reason why it is not valid, if it is not.
+    pub fn check_block(&self, block: &BlockNode) -> Result<(), String> {
+        let transactions = &block.transactions_block.transactions;
+        if transactions.len() > self.max_block_tx_count {
+            return Err(format!(
+                "Block has {} transactions, at most {} allowed.",
+                transactions.len(),
+                self.max_block_tx_count
+            ));
+        }
+        transactions.iter().try_for_each(|tx| self.check_tx(tx))
+    }
+}
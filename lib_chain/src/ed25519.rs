@@ -0,0 +1,72 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// This file implements the Ed25519 signature scheme (RFC 8032), the alternative to the RSA signatures of transactions
+// (see `SignatureAlgorithm`): its keys are 32 bytes and its signatures 64 bytes, instead of the hundreds of bytes of RSA.
+// The curve arithmetic is the one of the `ed25519-dalek` crate, which is constant time; this file only adapts it to the
+// raw byte arrays the wallets and the chain exchange. Signatures are verified strictly (`verify_strict`), so that the
+// weak public keys and the signatures with a small-order component, which are valid for more than one message, are not.
+// The keys are exchanged in the standard DER encodings of RFC 8410, like `openssl genpkey -algorithm ed25519` writes
+// them: a public key is a SubjectPublicKeyInfo, and a private key a PKCS#8 PrivateKeyInfo holding the 32-byte seed.
+// The signing keys expanded from the seed are zeroized by `ed25519-dalek` when dropped, so they do not linger in the
+// memory of the wallet.
+
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+
+/// The length in bytes of a seed (the private key), of a public key and of a signature.
+pub const SEED_LEN: usize = ed25519_dalek::SECRET_KEY_LENGTH;
+pub const PUBLIC_KEY_LEN: usize = ed25519_dalek::PUBLIC_KEY_LENGTH;
+pub const SIGNATURE_LEN: usize = ed25519_dalek::SIGNATURE_LENGTH;
+
+/// The DER encoding of a public key (SubjectPublicKeyInfo of id-Ed25519) before its 32 bytes.
+const PUBLIC_KEY_DER_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+/// The DER encoding of a private key (PKCS#8 PrivateKeyInfo of id-Ed25519) before its 32-byte seed.
+const PRIVATE_KEY_DER_PREFIX: [u8; 16] = [
+    0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20,
+];
+
+/// Get the public key of a seed.
+pub fn public_key(seed: &[u8; SEED_LEN]) -> [u8; PUBLIC_KEY_LEN] {
+    SigningKey::from_bytes(seed).verifying_key().to_bytes()
+}
+
+/// Sign a message with the key of a seed.
+pub fn sign(seed: &[u8; SEED_LEN], msg: &[u8]) -> [u8; SIGNATURE_LEN] {
+    SigningKey::from_bytes(seed).sign(msg).to_bytes()
+}
+
+/// Whether the signature of the message is valid for the public key. Malformed keys and signatures are not valid.
+pub fn verify(public_key: &[u8], msg: &[u8], signature: &[u8]) -> bool {
+    let (Ok(public_key), Ok(signature)) = (
+        <&[u8; PUBLIC_KEY_LEN]>::try_from(public_key),
+        Signature::from_slice(signature),
+    ) else {
+        return false;
+    };
+    VerifyingKey::from_bytes(public_key)
+        .is_ok_and(|public_key| public_key.verify_strict(msg, &signature).is_ok())
+}
+
+/// Encode a public key in DER (SubjectPublicKeyInfo).
+pub fn public_key_to_der(public_key: &[u8; PUBLIC_KEY_LEN]) -> Vec<u8> {
+    [&PUBLIC_KEY_DER_PREFIX[..], public_key].concat()
+}
+
+/// Decode a public key from DER (SubjectPublicKeyInfo), if it is an Ed25519 public key.
+pub fn public_key_from_der(der: &[u8]) -> Option<[u8; PUBLIC_KEY_LEN]> {
+    der.strip_prefix(&PUBLIC_KEY_DER_PREFIX)?.try_into().ok()
+}
+
+/// Encode the seed of a private key in DER (PKCS#8 PrivateKeyInfo).
+pub fn seed_to_der(seed: &[u8; SEED_LEN]) -> Vec<u8> {
+    [&PRIVATE_KEY_DER_PREFIX[..], seed].concat()
+}
+
+/// Decode the seed of a private key from DER (PKCS#8 PrivateKeyInfo), if it is an Ed25519 private key.
+pub fn seed_from_der(der: &[u8]) -> Option<[u8; SEED_LEN]> {
+    der.strip_prefix(&PRIVATE_KEY_DER_PREFIX)?.try_into().ok()
+}
@@ -0,0 +1,114 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// This file implements the hash functions a chain can be built with. The block ids (the proof of work) and the merkle
+// trees of a chain are computed with its `HashFunction`, chosen in its consensus params (see
+// `ConsensusParams::hash_function`): sha256 by default, as in the original chains, or Blake3, which is several times
+// faster in software and so lets the same difficulty be compared across hash functions.
+// Every hash function implements the `HashAlgo` trait, an incremental hasher with 32 bytes of output, so a block id
+// always has `MAX_LEADING_ZERO_LEN` hex digits. The transaction ids of the tx pool and of the finalized transactions stay
+// sha256 whatever the chain (see `Transaction::gen_hash`).
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+/// The length (in bytes) of the output of every hash function.
+pub const HASH_LEN: usize = 32;
+
+/// An incremental hash function. A hasher can be cloned to hash several inputs sharing a prefix only once.
+pub trait HashAlgo: Clone {
+    /// Feed data to the hasher.
+    fn update(&mut self, data: &[u8]);
+    /// The hash of all the data fed so far.
+    fn finalize(self) -> [u8; HASH_LEN];
+}
+
+/// The sha256 hash function (the default one).
+#[derive(Clone, Default)]
+pub struct Sha256Algo(Sha256);
+
+impl HashAlgo for Sha256Algo {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finalize(self) -> [u8; HASH_LEN] {
+        self.0.finalize().into()
+    }
+}
+
+/// The Blake3 hash function, with its default 32 bytes of output.
+#[derive(Clone, Default)]
+pub struct Blake3Algo(blake3::Hasher);
+
+impl HashAlgo for Blake3Algo {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finalize(self) -> [u8; HASH_LEN] {
+        self.0.finalize().into()
+    }
+}
+
+/// The hash function of a chain, as named in its consensus params.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HashFunction {
+    #[default]
+    Sha256,
+    Blake3,
+}
+
+impl HashFunction {
+    /// A new hasher of this hash function.
+    pub fn hasher(self) -> ChainHasher {
+        match self {
+            HashFunction::Sha256 => ChainHasher::Sha256(Sha256Algo::default()),
+            HashFunction::Blake3 => ChainHasher::Blake3(Blake3Algo::default()),
+        }
+    }
+
+    /// The hash of the given data, in hex format.
+    pub fn digest_hex(self, data: &[u8]) -> String {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+        hex::encode(hasher.finalize())
+    }
+}
+
+impl fmt::Display for HashFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HashFunction::Sha256 => write!(f, "sha256"),
+            HashFunction::Blake3 => write!(f, "blake3"),
+        }
+    }
+}
+
+/// A hasher of the hash function chosen at runtime (see `HashFunction::hasher`). It does not allocate, so the miner can
+/// clone one for every attempt; the Blake3 state is therefore not boxed, although it is much larger than the sha256 one.
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone)]
+pub enum ChainHasher {
+    Sha256(Sha256Algo),
+    Blake3(Blake3Algo),
+}
+
+impl HashAlgo for ChainHasher {
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            ChainHasher::Sha256(hasher) => hasher.update(data),
+            ChainHasher::Blake3(hasher) => hasher.update(data),
+        }
+    }
+
+    fn finalize(self) -> [u8; HASH_LEN] {
+        match self {
+            ChainHasher::Sha256(hasher) => hasher.finalize(),
+            ChainHasher::Blake3(hasher) => hasher.finalize(),
+        }
+    }
+}
@@ -2,13 +2,44 @@
 // Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
 // Please do not distribute.
 
+pub mod address;
 pub mod block;
+pub mod checkpoint;
+pub mod clock;
+pub mod consensus_params;
+#[cfg(feature = "crypto")]
+pub mod ed25519;
+pub mod hash;
+pub mod migration;
+pub mod payload;
+pub mod schema;
+pub mod store;
+pub mod txtrace;
 
 #[cfg(test)]
 mod tests {
-    use crate::block::{BlockNode, BlockTree, MerkleTree, Transaction};
+    use crate::block::{BlockNode, BlockNodeHeader, BlockTree, MerkleTree, PruneStats, Puzzle, SignatureAlgorithm, Transaction, Transactions, TxOutput, TxValidationError, BLOCK_TREE_MIGRATIONS};
+    use crate::address::{address_from_pub_key, AddressFormat};
+    use crate::checkpoint::Checkpoint;
+    use crate::clock::Clock;
+    use crate::consensus_params::ConsensusParams;
+    use crate::hash::HashFunction;
+    #[cfg(feature = "crypto")]
+    use crate::ed25519;
+    use crate::migration::MigrationRegistry;
+    use crate::payload::SignedPayload;
+    use crate::schema::{check_published_schema, schema_json};
+    use crate::store::{BlockStore, FileBlockStore};
+    use crate::txtrace::{format_timeline, tx_id_of, TxSpan, TxTraceEvent, TX_TRACE_PREFIX};
+    #[cfg(feature = "crypto")]
+    use base64ct::{Base64, Encoding};
+    use sha2::{Digest, Sha256};
     use serde::{de::DeserializeOwned, Serialize};
+    use std::collections::BTreeMap;
     use std::fs;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
 
     fn serialize_clone<T: Serialize + DeserializeOwned>(obj: &T) -> T {
         let jsonstr: String = serde_json::to_string(&obj).unwrap();
@@ -16,16 +47,12 @@ mod tests {
     }
 
     fn read_string_from_file(filepath: &str) -> String {
-        let contents = fs::read_to_string(filepath).expect(&("Cannot read ".to_owned() + filepath));
-        contents
-    }
-
-    fn write_string_to_file(filepath: &str, content: String) {
-        fs::write(filepath, content).expect(&("Cannot write ".to_owned() + filepath));
+        fs::read_to_string(filepath).unwrap_or_else(|_| panic!("Cannot read {}", filepath))
     }
 
     /// Test the signature verification on `Transaction`.
     #[test]
+    #[cfg(feature = "crypto")]
     fn test_transaction_signature() {
         let mut tx = Transaction {
             sender:
@@ -36,10 +63,160 @@ mod tests {
                     .to_string(),
             message: "SEND $300   // By Alice   // 1678250102871".to_string(),
             sig: "l8gsKxmAUzhgqbVqGlXaO69+Qhr87QthvZjUbYZXvnb+tanxCi8wm3c5UjHZ+HKm".to_string(),
+            sender_pub_key: None,
+            fee: None,
+            algorithm: SignatureAlgorithm::Rsa,
+            outputs: vec![],
         };
-        assert!(tx.verify_sig() == true);
+        assert!(tx.verify_sig().is_ok());
+        let signed_tx = tx.clone();
         tx.sig = "A8gsKxmAUzhgqbVqGlXaO69+Qhr87QthvZjUbYZXvnb+tanxCi8wm3c5UjHZ+HKm".to_string();
-        assert!(tx.verify_sig() == false);
+        assert!(tx.verify_sig() == Err(TxValidationError::SignatureMismatch));
+        tx.sig = "not base64!".to_string();
+        assert!(matches!(tx.verify_sig(), Err(TxValidationError::BadBase64(_))));
+        let mut tx = signed_tx.clone();
+        tx.sender = "short".to_string();
+        assert!(matches!(tx.verify_sig(), Err(TxValidationError::BadKeyEncoding(_))));
+        let mut tx = signed_tx;
+        tx.sender = tx.sender.replace('M', "?");
+        assert!(matches!(tx.verify_sig(), Err(TxValidationError::BadKeyEncoding(_))));
+    }
+
+    /// Test a transaction from a hashed sender address: the embedded public key must hash to the sender address
+    #[test]
+    #[cfg(feature = "crypto")]
+    fn test_transaction_hashed_sender_address() {
+        let alice = "MDgCMQCqrJ1yIJ7cDQIdTuS+4CkKn/tQPN7bZFbbGCBhvjQxs71f6Vu+sD9eh8JGpfiZSckCAwEAAQ==";
+        let bob = "MDgCMQDOpK8YWmcg8ffNF/O7xlBDq/DBdoUnc4yyWrV0y/X3LF+dddjaGksXzGl3tHskpgkCAwEAAQ==";
+        assert!(address_from_pub_key(alice, AddressFormat::RawPublicKey).unwrap() == alice);
+        let alice_address = address_from_pub_key(alice, AddressFormat::Sha256Base58).unwrap();
+        assert!(alice_address.len() < 50 && !alice_address.contains(['+', '/', '=']));
+        assert!(address_from_pub_key("not base64!", AddressFormat::Sha256Base58).is_err());
+
+        let data_string = serde_json::to_string(&(&alice_address, bob, "SEND $300", alice)).unwrap();
+        let mut tx = Transaction::from_data_string(&data_string, "l8gsKxmAUzhgqbVqGlXaO69+Qhr87QthvZjUbYZXvnb+tanxCi8wm3c5UjHZ+HKm".to_string()).unwrap();
+        assert!(tx.signed_data() == data_string);
+        // the key matches the address, so the signature is checked (against the data including the key)
+        assert!(tx.verify_sig() == Err(TxValidationError::SignatureMismatch));
+        tx.sender_pub_key = Some(bob.to_string());
+        assert!(tx.verify_sig() == Err(TxValidationError::AddressMismatch));
+        // the transactions of raw public key addresses keep their id
+        let legacy_tx = Transaction::new(alice.to_string(), bob.to_string(), "SEND $300".to_string(), "sig".to_string());
+        assert!(!serde_json::to_string(&legacy_tx).unwrap().contains("sender_pub_key"));
+    }
+
+    /// Test the signature check without the `crypto` feature: any signature passes, but a hashed sender address must
+    /// still match its embedded public key
+    #[test]
+    #[cfg(not(feature = "crypto"))]
+    fn test_transaction_signature_without_crypto() {
+        let alice = "MDgCMQCqrJ1yIJ7cDQIdTuS+4CkKn/tQPN7bZFbbGCBhvjQxs71f6Vu+sD9eh8JGpfiZSckCAwEAAQ==";
+        let tx = Transaction::new(alice.to_string(), "BOB".to_string(), "SEND $300".to_string(), "not a signature".to_string());
+        assert!(tx.verify_sig().is_ok());
+        let mut hashed_tx = tx.clone();
+        hashed_tx.sender = address_from_pub_key(alice, AddressFormat::Sha256Base58).unwrap();
+        hashed_tx.sender_pub_key = Some(alice.to_string());
+        assert!(hashed_tx.verify_sig().is_ok());
+        hashed_tx.sender = "BOB".to_string();
+        assert!(hashed_tx.verify_sig() == Err(TxValidationError::AddressMismatch));
+    }
+
+    /// Test the encoding of the signed payload: it is the compact json array written by the client, whatever the message
+    #[test]
+    fn test_signed_payload_encoding() {
+        for message in ["SEND $300", "SEND $1 // \"quoted\", [bracketed]", "back\\slash\nnew line", "\u{0}\u{1f} 你好 🚀"] {
+            let data_string = serde_json::to_string(&("A", "B", message)).unwrap();
+            let payload = SignedPayload::decode(&data_string).unwrap();
+            assert!(payload.message == message && payload.sender_pub_key.is_none() && payload.fee.is_none());
+            assert!(payload.encode() == data_string);
+            assert!(Transaction::from_data_string(&data_string, "sig".to_string()).unwrap().signed_data() == data_string);
+        }
+        let fee_data_string = serde_json::to_string(&("A", "B", "SEND $1", "", "3")).unwrap();
+        assert!(SignedPayload::decode(&fee_data_string).unwrap().encode() == fee_data_string);
+        // the same fields in another form are rejected, since their signature would not match the canonical encoding
+        for non_canonical in ["[\"A\", \"B\", \"SEND $1\"]", " [\"A\",\"B\",\"SEND $1\"]", "[\"A\",\"B\",\"SEND \\u00241\"]", "[\"A\",\"B\",\"SEND $1\",\"\",\"+3\"]", "[\"A\",\"B\",\"SEND $1\",\"\",\"03\"]"] {
+            assert!(SignedPayload::decode(non_canonical).is_err(), "{} should be rejected", non_canonical);
+        }
+        assert!(SignedPayload::decode("[\"A\",\"B\"]").is_err());
+        assert!(SignedPayload::decode("[\"A\",\"B\",\"SEND $1\",\"\",\"x\"]").is_err());
+        // an Ed25519 payload has 6 fields, ending with its algorithm
+        let ed25519_data_string = serde_json::to_string(&("A", "B", "SEND $1", "", "", "Ed25519")).unwrap();
+        assert!(SignedPayload::decode(&ed25519_data_string).unwrap().algorithm == SignatureAlgorithm::Ed25519);
+        assert!(SignedPayload::decode(&ed25519_data_string).unwrap().fee.is_none());
+        assert!(SignedPayload::decode("[\"A\",\"B\",\"SEND $1\",\"\",\"\",\"Rsa\"]").is_err());
+        assert!(SignedPayload::decode("[\"A\",\"B\",\"SEND $1\",\"\",\"\"]").is_err());
+        // a batch payload has 7 fields, ending with its outputs, and an empty algorithm for RSA
+        let batch_data_string = serde_json::to_string(&("A", "B", "SEND $1", "", "", "", "[[\"C\",2],[\"D\",3]]")).unwrap();
+        let batch = SignedPayload::decode(&batch_data_string).unwrap();
+        assert!(batch.algorithm == SignatureAlgorithm::Rsa && batch.outputs == vec![TxOutput { receiver: "C".to_string(), amount: 2 }, TxOutput { receiver: "D".to_string(), amount: 3 }]);
+        assert!(batch.encode() == batch_data_string);
+        let batch_tx = Transaction::from_data_string(&batch_data_string, "sig".to_string()).unwrap();
+        assert!(batch_tx.is_batch() && batch_tx.total_amount() == Some(6) && batch_tx.signed_data() == batch_data_string);
+        let ed25519_batch = serde_json::to_string(&("A", "B", "SEND $1", "", "", "Ed25519", "[[\"C\",2]]")).unwrap();
+        assert!(SignedPayload::decode(&ed25519_batch).unwrap().algorithm == SignatureAlgorithm::Ed25519);
+        for malformed in [("A", "B", "SEND $1", "", "", "", "[]"), ("A", "B", "SEND $1", "", "", "Rsa", "[[\"C\",2]]"), ("A", "B", "SEND $1", "", "", "", "[[\"C\", 2]]"), ("A", "B", "SEND $1", "", "", "", "[[\"C\",\"2\"]]")] {
+            assert!(SignedPayload::decode(&serde_json::to_string(&malformed).unwrap()).is_err(), "{:?} should be rejected", malformed);
+        }
+    }
+
+    /// Test the Ed25519 implementation against the test vectors of RFC 8032 (section 7.1), and its DER key encodings
+    #[test]
+    #[cfg(feature = "crypto")]
+    fn test_ed25519_rfc8032_vectors() {
+        let vectors = [
+            ("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60", "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a", "", "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b"),
+            ("4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb", "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c", "72", "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00"),
+        ];
+        for (seed, public_key, msg, signature) in vectors {
+            let seed: [u8; 32] = hex::decode(seed).unwrap().try_into().unwrap();
+            let msg = hex::decode(msg).unwrap();
+            assert!(hex::encode(ed25519::public_key(&seed)) == public_key);
+            assert!(hex::encode(ed25519::sign(&seed, &msg)) == signature);
+            assert!(ed25519::verify(&hex::decode(public_key).unwrap(), &msg, &hex::decode(signature).unwrap()));
+            assert!(!ed25519::verify(&hex::decode(public_key).unwrap(), b"another message", &hex::decode(signature).unwrap()));
+            assert!(ed25519::seed_from_der(&ed25519::seed_to_der(&seed)) == Some(seed));
+        }
+        let public_key = ed25519::public_key(&[1; 32]);
+        assert!(Base64::encode_string(&ed25519::public_key_to_der(&public_key)).len() == 60);
+        assert!(ed25519::public_key_from_der(&ed25519::public_key_to_der(&public_key)) == Some(public_key));
+        assert!(ed25519::public_key_from_der(&public_key).is_none());
+        assert!(!ed25519::verify(&public_key[..31], b"", &[0; 64]));
+    }
+
+    /// Test an Ed25519 transaction: it is verified with its scheme, and keeps it in its payload and its id
+    #[test]
+    #[cfg(feature = "crypto")]
+    fn test_transaction_ed25519_signature() {
+        let seed = [7; 32];
+        let sender = Base64::encode_string(&ed25519::public_key_to_der(&ed25519::public_key(&seed)));
+        let payload = SignedPayload { sender: sender.clone(), receiver: "BOB".to_string(), message: "SEND $300   // By Alice   // 1".to_string(), sender_pub_key: None, fee: None, algorithm: SignatureAlgorithm::Ed25519, outputs: vec![] };
+        let data_string = payload.encode();
+        assert!(data_string == serde_json::to_string(&(&sender, "BOB", "SEND $300   // By Alice   // 1", "", "", "Ed25519")).unwrap());
+        let tx = Transaction::from_data_string(&data_string, Base64::encode_string(&ed25519::sign(&seed, data_string.as_bytes()))).unwrap();
+        assert!(tx.algorithm == SignatureAlgorithm::Ed25519 && tx.payload() == payload);
+        assert!(tx.verify_sig().is_ok());
+        assert!(serde_json::to_string(&tx).unwrap().contains("\"algorithm\":\"Ed25519\""));
+
+        let mut tampered = tx.clone();
+        tampered.message = "SEND $3000   // By Alice   // 1".to_string();
+        assert!(tampered.verify_sig() == Err(TxValidationError::SignatureMismatch));
+        // the same signature does not pass for the RSA payload of the same fields
+        let mut rsa_tx = tx.clone();
+        rsa_tx.algorithm = SignatureAlgorithm::Rsa;
+        assert!(matches!(rsa_tx.verify_sig(), Err(TxValidationError::BadKeyEncoding(_))));
+        let mut tx_of_rsa_key = tx.clone();
+        tx_of_rsa_key.sender = "MDgCMQCqrJ1yIJ7cDQIdTuS+4CkKn/tQPN7bZFbbGCBhvjQxs71f6Vu+sD9eh8JGpfiZSckCAwEAAQ==".to_string();
+        assert!(matches!(tx_of_rsa_key.verify_sig(), Err(TxValidationError::BadKeyEncoding(_))));
+        let mut short_sig = tx.clone();
+        short_sig.sig = "l8gsKxmAUzhgqbVqGlXaO69+Qhr87QthvZjUbYZXvnb+tanxCi8wm3c5UjHZ+HKm".to_string();
+        assert!(matches!(short_sig.verify_sig(), Err(TxValidationError::BadBase64(_))));
+
+        // a hashed sender address works the same way, with a fee
+        let address = address_from_pub_key(&sender, AddressFormat::Sha256Base58).unwrap();
+        let payload = SignedPayload { sender: address, sender_pub_key: Some(sender), fee: Some(2), ..payload };
+        let data_string = payload.encode();
+        let tx = Transaction::from_data_string(&data_string, Base64::encode_string(&ed25519::sign(&seed, data_string.as_bytes()))).unwrap();
+        assert!(tx.verify_sig().is_ok() && tx.get_fee() == 2);
     }
 
     /// Test the generation of merkle tree.
@@ -54,6 +231,10 @@ mod tests {
                     .to_string(),
             message: "SEND $100   // By Alice   // 1678198053097".to_string(),
             sig: "LJxQJi3pzVlM/7U/y5BV6kbJ9A3kXAyw2yLmBO3tG0gaEenwjRUbU9FGL7folRYA".to_string(),
+            sender_pub_key: None,
+            fee: None,
+            algorithm: SignatureAlgorithm::Rsa,
+            outputs: vec![],
         };
         let tx2 = Transaction {
             sender:
@@ -64,6 +245,10 @@ mod tests {
                     .to_string(),
             message: "SEND $200   // By Alice   // 1678198045087".to_string(),
             sig: "SOuwjm0I1vwt3LE2dVWuaFJAIYrswewl1/B1eiyuvgyFU4pWeqP4pIcuHgC3JAPh".to_string(),
+            sender_pub_key: None,
+            fee: None,
+            algorithm: SignatureAlgorithm::Rsa,
+            outputs: vec![],
         };
         let tx3 = Transaction {
             sender:
@@ -74,6 +259,10 @@ mod tests {
                     .to_string(),
             message: "SEND $200   // By Alice   // 1678198048091".to_string(),
             sig: "Lbmm5uxAvg8HBlC/wAgpna8iNwaHk+Yw74eKR0F7vonOPiS63YUxR/n07SjNpTUH".to_string(),
+            sender_pub_key: None,
+            fee: None,
+            algorithm: SignatureAlgorithm::Rsa,
+            outputs: vec![],
         };
         let tx4 = Transaction {
             sender:
@@ -84,10 +273,14 @@ mod tests {
                     .to_string(),
             message: "SEND $100   // By Alice   // 1678198050093".to_string(),
             sig: "EQWylQX/AIcQDStCGB6ujRmqDalO5z52VguJO9f5S0C1VPhGFh58r8Mi0Wo+ue8a".to_string(),
+            sender_pub_key: None,
+            fee: None,
+            algorithm: SignatureAlgorithm::Rsa,
+            outputs: vec![],
         };
 
         let tx_vec = vec![tx1, tx2, tx3, tx4];
-        let (merkle_root, merkle) = MerkleTree::create_merkle_tree(tx_vec);
+        let (merkle_root, _merkle) = MerkleTree::create_merkle_tree(tx_vec.clone());
 
         assert!(merkle_root == "3c1ad8fb380a0808e5c0c0a864040d74338de2aa3a9f5aa2657371b4f5a68ae6");
         // Expected output:
@@ -96,6 +289,43 @@ mod tests {
         // d9961bbcbbcedfa994a951662da7285ccc82940a6e65028da233e21b1c543a13, aeec55bd3c6bcc5749cd248fd88b65de45fd14b42abdad4b3c874c2e00865a1b
 
         // 3c1ad8fb380a0808e5c0c0a864040d74338de2aa3a9f5aa2657371b4f5a68ae6
+
+        // Golden vectors for odd sizes: the last hash of an odd level is duplicated
+        let (merkle_root, merkle) = MerkleTree::create_merkle_tree(tx_vec[..1].to_vec());
+        assert!(merkle_root == "3863a32707d8da031b4e96ad19b9ddd84356988ce73ea4a57394d583011bc747");
+        assert!(merkle.hashes == vec![
+            vec![
+                "0cb819e19d34ee88b6c20c78cea0aec818f1d6875479c9c9a1505c27495d7a91".to_string(),
+                "0cb819e19d34ee88b6c20c78cea0aec818f1d6875479c9c9a1505c27495d7a91".to_string(),
+            ],
+            vec!["3863a32707d8da031b4e96ad19b9ddd84356988ce73ea4a57394d583011bc747".to_string()],
+        ]);
+        let (merkle_root, merkle) = MerkleTree::create_merkle_tree(tx_vec[..3].to_vec());
+        assert!(merkle_root == "11309462b78567509bcc0a44c9e8b105163b4be13db57b94fcd667f11b1694c1");
+        assert!(merkle.hashes.iter().map(|level| level.len()).collect::<Vec<_>>() == vec![4, 2, 1]);
+        let mut five_txs = tx_vec.clone();
+        five_txs.push(tx_vec[0].clone());
+        let (merkle_root, _merkle) = MerkleTree::create_merkle_tree(five_txs);
+        assert!(merkle_root == "8bdbc151fa78d9e7464d0e63f3013f03737989b680e3570d2c9fa2efc367f091");
+
+        // An empty list gives the hash of the empty string
+        let (merkle_root, merkle) = MerkleTree::create_merkle_tree(vec![]);
+        assert!(merkle_root == "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert!(merkle.hashes == vec![vec![merkle_root]]);
+    }
+
+    /// Test that the merkle trees stored in the reference blocks are rebuilt exactly (including a single-tx block)
+    #[test]
+    fn test_merkle_tree_reference_blocks() {
+        for i in [1, 2, 3, 4, 5, 6, 7, 8] {
+            let block_json =
+                read_string_from_file(&format!("./testdata/add_blocks_basic__{}.json", i));
+            let block_node = serde_json::from_str::<BlockNode>(&block_json).unwrap();
+            let (merkle_root, merkle) =
+                MerkleTree::create_merkle_tree(block_node.transactions_block.transactions.clone());
+            assert!(merkle_root == block_node.header.merkle_root);
+            assert!(merkle == block_node.transactions_block.merkle_tree);
+        }
     }
 
     /// Test basic block tree creation
@@ -115,9 +345,9 @@ mod tests {
 
         // block add orphan test
         let mut default_block_tree = BlockTree::new();
-        default_block_tree.add_block(block_node.clone(), 5);
-        assert!(default_block_tree.working_block_id == "0".to_string());
-        assert!(default_block_tree.root_id == "0".to_string());
+        default_block_tree.add_block(block_node.clone(), 5).unwrap();
+        assert!(default_block_tree.working_block_id == "0");
+        assert!(default_block_tree.root_id == "0");
         assert!(default_block_tree.orphans.len() == 1);
     }
 
@@ -127,13 +357,13 @@ mod tests {
         let mut default_btree = BlockTree::new();
         // print current pwd
         println!("current dir: {:?}", std::env::current_dir());
-        for i in vec![1, 2, 3, 4, 5, 6, 7, 8] {
+        for i in [1, 2, 3, 4, 5, 6, 7, 8] {
             // read block from "./testdata/add_block_basic__{i}.json"
             // println!("read block from ./testdata/add_blocks_basic__{}.json", i);
             let block_json =
                 read_string_from_file(&format!("./testdata/add_blocks_basic__{}.json", i));
             let block_node = serde_json::from_str::<BlockNode>(&block_json).unwrap();
-            default_btree.add_block(block_node, 5);
+            default_btree.add_block(block_node, 5).unwrap();
         }
         // println!("299791558: {}", default_btree.finalized_balance_map[&"MDgCMQCqrJ1yIJ7cDQIdTuS+4CkKn/tQPN7bZFbbGCBhvjQxs71f6Vu+sD9eh8JGpfiZSckCAwEAAQ==".to_owned()]);
         // println!("300: {}", default_btree.finalized_balance_map[&"MDgCMQDZDExOs97sRTnQLYtgFjDKpDzmO7Uo5HPP62u6MDimXBpZtGxtwa8dhJe5NBIsJjUCAwEAAQ==".to_owned()]);
@@ -154,7 +384,7 @@ mod tests {
 
         assert!(
             default_btree.working_block_id
-                == "0000052b06a4d5c725f3713aed93d4b4e1da93a7b4f7cb870ef1f7e6b6b0fcb8".to_string()
+                == "0000052b06a4d5c725f3713aed93d4b4e1da93a7b4f7cb870ef1f7e6b6b0fcb8"
         );
         assert!(default_btree.finalized_balance_map[&"MDgCMQCqrJ1yIJ7cDQIdTuS+4CkKn/tQPN7bZFbbGCBhvjQxs71f6Vu+sD9eh8JGpfiZSckCAwEAAQ==".to_owned()] == 299791558);
         assert!(default_btree.finalized_balance_map[&"MDgCMQDZDExOs97sRTnQLYtgFjDKpDzmO7Uo5HPP62u6MDimXBpZtGxtwa8dhJe5NBIsJjUCAwEAAQ==".to_owned()] == 300);
@@ -170,22 +400,45 @@ mod tests {
         );
     }
 
+    /// Test the blocks that joined the longest path since an older working block
+    #[test]
+    fn blocktree_main_chain_blocks_since() {
+        let blocks: Vec<BlockNode> = (1..=8).map(|i| {
+            let block_json = read_string_from_file(&format!("./testdata/add_blocks_basic__{}.json", i));
+            serde_json::from_str::<BlockNode>(&block_json).unwrap()
+        }).collect();
+        let mut btree = BlockTree::new();
+        for block in blocks[..3].iter() {
+            btree.add_block(block.clone(), 5).unwrap();
+        }
+        let old_working_id = btree.working_block_id.clone();
+        assert!(btree.get_main_chain_blocks_since(&old_working_id).is_empty());
+        for block in blocks[3..].iter() {
+            btree.add_block(block.clone(), 5).unwrap();
+        }
+        let new_ids: Vec<String> = btree.get_main_chain_blocks_since(&old_working_id).into_iter().map(|block| block.header.block_id).collect();
+        let expected_ids: Vec<String> = blocks[3..].iter().map(|block| block.header.block_id.clone()).collect();
+        assert!(new_ids == expected_ids);
+        assert!(btree.get_main_chain_blocks_since("0").len() == 8);
+        assert!(btree.get_main_chain_blocks_since("unknown").len() == 8);
+    }
+
     /// Test adding blocks to the blocktree (orphan considered)
     #[test]
     fn blocktree_add_blocks_orphan() {
         let mut default_btree = BlockTree::new();
         // print current pwd
         // println!("current dir: {:?}", std::env::current_dir());
-        for i in vec![3, 6, 1, 4, 2, 8, 5, 7] {
+        for i in [3, 6, 1, 4, 2, 8, 5, 7] {
             // read block from "./testdata/add_block_basic__{i}.json"
             let block_json =
                 read_string_from_file(&format!("./testdata/add_blocks_basic__{}.json", i));
             let block_node = serde_json::from_str::<BlockNode>(&block_json).unwrap();
-            default_btree.add_block(block_node, 5);
+            default_btree.add_block(block_node, 5).unwrap();
         }
         assert!(
             default_btree.working_block_id
-                == "0000052b06a4d5c725f3713aed93d4b4e1da93a7b4f7cb870ef1f7e6b6b0fcb8".to_string()
+                == "0000052b06a4d5c725f3713aed93d4b4e1da93a7b4f7cb870ef1f7e6b6b0fcb8"
         );
 
         // println!("299791558: {}", default_btree.finalized_balance_map[&"MDgCMQCqrJ1yIJ7cDQIdTuS+4CkKn/tQPN7bZFbbGCBhvjQxs71f6Vu+sD9eh8JGpfiZSckCAwEAAQ==".to_owned()]);
@@ -208,6 +461,866 @@ mod tests {
         //println!("default_btree: {:?}", default_btree);
     }
 
+    /// Test a chain built with Blake3: its block ids and merkle trees are Blake3 hashes, and blocks of a sha256 chain are rejected
+    #[test]
+    fn test_blake3_chain() {
+        assert!(HashFunction::Blake3.digest_hex(b"") == "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262");
+        assert!(HashFunction::Sha256.digest_hex(b"") == format!("{:x}", Sha256::digest(b"")));
+
+        let block_json = read_string_from_file("./testdata/add_blocks_basic__1.json");
+        let sha256_block = serde_json::from_str::<BlockNode>(&block_json).unwrap();
+        let txs = sha256_block.transactions_block.transactions.clone();
+        assert!(txs[0].gen_hash() == txs[0].gen_hash_with(HashFunction::Sha256));
+        assert!(txs[0].gen_hash() != txs[0].gen_hash_with(HashFunction::Blake3));
+
+        let consensus = ConsensusParams { hash_function: HashFunction::Blake3, ..ConsensusParams::default() };
+        let mut blake3_btree = BlockTree::with_consensus_params(consensus);
+        assert!(blake3_btree.add_block(sha256_block.clone(), 0).is_err());
+
+        // The same block, with the merkle tree and block id of a Blake3 chain
+        let mut blake3_block = sha256_block.clone();
+        let (merkle_root, merkle_tree) = MerkleTree::create_merkle_tree_with(txs, HashFunction::Blake3);
+        assert!(merkle_root != sha256_block.header.merkle_root);
+        blake3_block.header.merkle_root = merkle_root;
+        blake3_block.transactions_block.merkle_tree = merkle_tree;
+        let puzzle = blake3_block.header.puzzle(&blake3_btree.consensus);
+        let puzzle_str = blake3_block.header.nonce.clone() + &serde_json::to_string(&puzzle).unwrap();
+        blake3_block.header.block_id = HashFunction::Blake3.digest_hex(puzzle_str.as_bytes());
+        assert!(!blake3_block.validate_block(0).0);
+        assert!(blake3_block.validate_block_with(0, HashFunction::Blake3) == (true, blake3_block.header.block_id.clone()));
+        blake3_btree.add_block(blake3_block.clone(), 0).unwrap();
+        assert!(blake3_btree.working_block_id == blake3_block.header.block_id);
+        assert!(BlockTree::new().add_block(blake3_block, 0).is_err());
+    }
+
+    /// Test parsing the amount out of transaction messages
+    #[test]
+    fn test_transaction_get_amount() {
+        let tx = |message: &str| {
+            Transaction::new("A".to_string(), "B".to_string(), message.to_string(), "".to_string())
+        };
+        assert_eq!(tx("SEND $300   // By Alice   // 1678173972743").get_amount(), Some(300));
+        assert_eq!(tx("SEND $299792458").get_amount(), Some(299792458));
+        assert_eq!(tx("SEND $-5").get_amount(), Some(-5));
+        assert_eq!(tx("SEND 300").get_amount(), None);
+        assert_eq!(tx("SEND $abc").get_amount(), None);
+        assert_eq!(tx("").get_amount(), None);
+    }
+
+    /// Test the balances at the working block (finalized balances plus the pending finalization blocks)
+    #[test]
+    fn blocktree_working_balance() {
+        let mut default_btree = BlockTree::new();
+        let genesis_balance = default_btree.get_working_balance_map();
+        assert!(genesis_balance == default_btree.finalized_balance_map);
+        assert!(default_btree.get_pending_finalization_blocks().is_empty());
+
+        for i in [1, 2, 3, 4, 5, 6, 7, 8] {
+            let block_json =
+                read_string_from_file(&format!("./testdata/add_blocks_basic__{}.json", i));
+            let block_node = serde_json::from_str::<BlockNode>(&block_json).unwrap();
+            default_btree.add_block(block_node, 5).unwrap();
+        }
+
+        let pending_blocks = default_btree.get_pending_finalization_blocks();
+        assert!(!pending_blocks.is_empty());
+        assert!(pending_blocks.last().unwrap().header.block_id == default_btree.working_block_id);
+        assert!(pending_blocks[0].header.parent == default_btree.finalized_block_id);
+
+        // Transfers only move money around, so the total grows by the mining rewards of the pending blocks
+        let finalized_total: i64 = default_btree.finalized_balance_map.values().sum();
+        let working_total: i64 = default_btree.get_working_balance_map().values().sum();
+        assert!(working_total == finalized_total + 10 * pending_blocks.len() as i64);
+    }
+
+    /// Test that the reward, the finality depth, the size limits and the genesis allocation follow the consensus params
+    #[test]
+    fn blocktree_consensus_params() {
+        let consensus = ConsensusParams { mining_reward: 25, finality_depth: 2, ..ConsensusParams::default() };
+        let mut btree = BlockTree::with_consensus_params(consensus.clone());
+        for i in [1, 2, 3, 4, 5, 6, 7, 8] {
+            let block_json = read_string_from_file(&format!("./testdata/add_blocks_basic__{}.json", i));
+            btree.add_block(serde_json::from_str::<BlockNode>(&block_json).unwrap(), 5).unwrap();
+        }
+        let working_height = btree.height_of(&btree.working_block_id).unwrap();
+        assert!(btree.height_of(&btree.finalized_block_id).unwrap() == working_height - 2);
+        let finalized_total: i64 = btree.finalized_balance_map.values().sum();
+        let working_total: i64 = btree.get_working_balance_map().values().sum();
+        assert!(working_total == finalized_total + 25 * 2);
+        assert!(btree.audit_supply().is_ok());
+
+        // A block with more transactions than allowed is rejected, and so is a transaction with a long message
+        let mut small_btree = BlockTree::with_consensus_params(ConsensusParams { max_block_tx_count: 3, ..consensus });
+        let rejected = (1..=8).map(|i| {
+            let block_json = read_string_from_file(&format!("./testdata/add_blocks_basic__{}.json", i));
+            serde_json::from_str::<BlockNode>(&block_json).unwrap()
+        }).map(|block| (block.transactions_block.transactions.len(), small_btree.add_block(block, 5)))
+            .find(|(_, result)| result.is_err());
+        assert!(matches!(rejected, Some((4, Err(_)))));
+        let long_tx = Transaction::new("A".to_string(), "B".to_string(), "x".repeat(1025), String::new());
+        assert!(ConsensusParams::default().check_tx(&long_tx).is_err());
+        // and so is a transaction that does not pay a positive amount
+        for message in ["SEND $10", "SEND $0", "SEND $-10", "no amount"] {
+            let tx = Transaction::new("A".to_string(), "B".to_string(), message.to_string(), String::new());
+            assert!(ConsensusParams::default().check_tx(&tx).is_ok() == (message == "SEND $10"), "{}", message);
+        }
+
+        // The params of a node must match the genesis block of its chain
+        let mut default_btree = BlockTree::new();
+        let other_genesis = ConsensusParams { genesis_allocation: 1, ..ConsensusParams::default() };
+        assert!(default_btree.set_consensus_params(other_genesis.clone()).is_err());
+        assert!(BlockTree::with_consensus_params(other_genesis).finalized_balance_map.values().sum::<i64>() == 1);
+        assert!(default_btree.set_consensus_params(ConsensusParams::default()).is_ok());
+    }
+
+    /// Test the statistics of the longest chain over its last blocks
+    #[test]
+    fn blocktree_chain_stats() {
+        let mut default_btree = BlockTree::new();
+        let stats = default_btree.get_chain_stats(10);
+        assert!(stats["#window_blocks"] == "0");
+        assert!(stats["avg_block_interval"] == "n/a");
+        assert!(stats["fork_rate"] == "n/a");
+        assert!(stats["total_supply"] == "299792458");
+
+        for i in [1, 2, 3, 4, 5, 6, 7, 8] {
+            let block_json =
+                read_string_from_file(&format!("./testdata/add_blocks_basic__{}.json", i));
+            let block_node = serde_json::from_str::<BlockNode>(&block_json).unwrap();
+            default_btree.add_block(block_node, 5).unwrap();
+        }
+        let stats = default_btree.get_chain_stats(100);
+        assert!(stats["#window_blocks"] == "8");
+        assert!(stats["avg_tx_per_block"] == "3.62");
+        assert!(stats["fork_rate"] == "0.000");
+        let working_supply: i64 = default_btree.get_working_balance_map().values().sum();
+        assert!(stats["total_supply"] == working_supply.to_string());
+        assert!(default_btree.get_chain_stats(3)["#window_blocks"] == "3");
+
+        // A sibling of the first block (same txs, other reward receiver) becomes a stale block
+        let block_json = read_string_from_file("./testdata/add_blocks_basic__1.json");
+        let mut fork_block = serde_json::from_str::<BlockNode>(&block_json).unwrap();
+        fork_block.header.reward_receiver = "FORK".to_string();
+        let puzzle = fork_block.header.puzzle(&default_btree.consensus);
+        let puzzle_str = fork_block.header.nonce.clone() + &serde_json::to_string(&puzzle).unwrap();
+        fork_block.header.block_id = format!("{:x}", Sha256::digest(puzzle_str.as_bytes()));
+        default_btree.add_block(fork_block, 0).unwrap();
+
+        let stats = default_btree.get_chain_stats(100);
+        assert!(stats["#stale_blocks"] == "1");
+        assert!(stats["fork_rate"] == "0.111");
+        assert!(default_btree.get_chain_stats(3)["#stale_blocks"] == "0");
+    }
+
+    /// Test pruning a side branch that falls behind the finalized block
+    #[test]
+    fn blocktree_prune_losing_branches() {
+        let mut default_btree = BlockTree::new();
+        for i in [1, 2, 3, 4, 5, 6, 7, 8] {
+            let block_json =
+                read_string_from_file(&format!("./testdata/add_blocks_basic__{}.json", i));
+            let block_node = serde_json::from_str::<BlockNode>(&block_json).unwrap();
+            default_btree.add_block(block_node, 5).unwrap();
+        }
+        // A sibling of the first block (same txs, other reward receiver), 1 block behind the finalized block
+        let block_json = read_string_from_file("./testdata/add_blocks_basic__1.json");
+        let mut fork_block = serde_json::from_str::<BlockNode>(&block_json).unwrap();
+        fork_block.header.reward_receiver = "FORK".to_string();
+        let puzzle = fork_block.header.puzzle(&default_btree.consensus);
+        let puzzle_str = fork_block.header.nonce.clone() + &serde_json::to_string(&puzzle).unwrap();
+        fork_block.header.block_id = format!("{:x}", Sha256::digest(puzzle_str.as_bytes()));
+        let fork_id = fork_block.header.block_id.clone();
+        default_btree.add_block(fork_block, 0).unwrap();
+        default_btree.record_block_metadata(&fork_id, "127.0.0.1:50001", 1000);
+        assert!(default_btree.height_of(&default_btree.finalized_block_id) == Some(2));
+
+        let working_block_id = default_btree.working_block_id.clone();
+        assert!(default_btree.prune_losing_branches(1).pruned_blocks == 0);
+        let stats = default_btree.prune_losing_branches(0);
+        assert!(stats.pruned_blocks == 1);
+        assert!(stats.reclaimed_bytes > 0);
+        assert!(!default_btree.all_blocks.contains_key(&fork_id));
+        assert!(!default_btree.block_depth.contains_key(&fork_id));
+        assert!(!default_btree.block_metadata.contains_key(&fork_id));
+        assert!(default_btree.children_map.values().all(|children| !children.contains(&fork_id)));
+        assert!(default_btree.all_blocks.len() == 9);
+        assert!(default_btree.working_block_id == working_block_id);
+        assert!(default_btree.audit_supply().is_ok());
+        assert!(default_btree.check_invariants().is_ok());
+
+        assert!(default_btree.prune_losing_branches(0) == PruneStats::default());
+        assert!(default_btree.prune_stats == stats);
+        assert!(default_btree.get_status().pruned_blocks == 1);
+    }
+
+    /// Test the traversal helpers over the main chain and the ancestors of a block
+    #[test]
+    fn blocktree_traversal() {
+        let mut default_btree = BlockTree::new();
+        assert!(default_btree.iter_main_chain().count() == 1);
+        assert!(default_btree.iter_ancestors("0").count() == 1);
+        assert!(default_btree.iter_ancestors("unknown").count() == 0);
+        assert!(default_btree.get_finalized_blocks_since("0".to_string()).is_empty());
+        assert!(default_btree.get_finalized_blocks_since("unknown".to_string()).is_empty());
+
+        for i in [1, 2, 3, 4, 5, 6, 7, 8] {
+            let block_json =
+                read_string_from_file(&format!("./testdata/add_blocks_basic__{}.json", i));
+            let block_node = serde_json::from_str::<BlockNode>(&block_json).unwrap();
+            default_btree.add_block(block_node, 5).unwrap();
+        }
+
+        let working_height = default_btree.height_of(&default_btree.working_block_id).unwrap();
+        let main_chain: Vec<&BlockNode> = default_btree.iter_main_chain().collect();
+        assert!(main_chain.len() as u64 == working_height + 1);
+        assert!(main_chain[0].header.block_id == "0");
+        assert!(main_chain.last().unwrap().header.block_id == default_btree.working_block_id);
+        for (height, block) in main_chain.iter().enumerate() {
+            assert!(default_btree.height_of(&block.header.block_id) == Some(height as u64));
+            if height > 0 {
+                assert!(block.header.parent == main_chain[height - 1].header.block_id);
+            }
+        }
+
+        let finalized_ancestors: Vec<&BlockNode> =
+            default_btree.iter_ancestors(&default_btree.finalized_block_id).collect();
+        assert!(finalized_ancestors.len() as u64 == default_btree.height_of(&default_btree.finalized_block_id).unwrap() + 1);
+        assert!(finalized_ancestors.last().unwrap().header.block_id == "0");
+        assert!(default_btree.height_of("unknown").is_none());
+    }
+
+    /// Test looking up the blocks of the longest path by their height
+    #[test]
+    fn blocktree_block_at_height() {
+        let mut default_btree = BlockTree::new();
+        assert!(default_btree.get_block_at_height(0).unwrap().header.block_id == "0");
+        assert!(default_btree.get_block_at_height(1).is_none());
+        for i in [3, 6, 1, 4, 2, 8, 5, 7] {
+            let block_json =
+                read_string_from_file(&format!("./testdata/add_blocks_basic__{}.json", i));
+            let block_node = serde_json::from_str::<BlockNode>(&block_json).unwrap();
+            default_btree.add_block(block_node, 5).unwrap();
+        }
+
+        let main_chain: Vec<BlockNode> = default_btree.iter_main_chain().cloned().collect();
+        assert!(default_btree.main_chain_index.len() == main_chain.len());
+        for (height, block) in main_chain.iter().enumerate() {
+            assert!(default_btree.get_block_at_height(height as u64).unwrap().header.block_id == block.header.block_id);
+        }
+        assert!(default_btree.get_block_at_height(main_chain.len() as u64).is_none());
+
+        // block trees serialized before the index existed are still answered from the main chain
+        let mut json: serde_json::Value = serde_json::to_value(&default_btree).unwrap();
+        json.as_object_mut().unwrap().remove("main_chain_index");
+        let old_btree: BlockTree = serde_json::from_value(json).unwrap();
+        assert!(old_btree.main_chain_index.is_empty());
+        assert!(old_btree.get_block_at_height(3).unwrap().header.block_id == main_chain[3].header.block_id);
+        assert!(old_btree.check_invariants().is_ok());
+    }
+
+    /// Test the work of each block (16 to the power of its leading zeros) and the accumulated work of its branch
+    #[test]
+    fn blocktree_block_work() {
+        let mut default_btree = BlockTree::new();
+        for i in [3, 6, 1, 4, 2, 8, 5, 7] {
+            let block_json =
+                read_string_from_file(&format!("./testdata/add_blocks_basic__{}.json", i));
+            let block_node = serde_json::from_str::<BlockNode>(&block_json).unwrap();
+            default_btree.add_block(block_node, 5).unwrap();
+        }
+
+        assert!(default_btree.branch_work("0") == Some(0));
+        let mut expected_branch_work = 0u128;
+        for block in default_btree.iter_main_chain().skip(1) {
+            let block_id = &block.header.block_id;
+            assert!(block.leading_zero_len() >= 5);
+            assert!(block.work() == 16u128.pow(block.leading_zero_len()));
+            expected_branch_work += block.work();
+            assert!(default_btree.branch_work(block_id) == Some(expected_branch_work));
+            let work = default_btree.get_block_work(block_id).unwrap();
+            assert!(work["leading_zero_len"] == block.leading_zero_len().to_string());
+            assert!(work["branch_work"] == expected_branch_work.to_string());
+            assert!(work["height"] == default_btree.height_of(block_id).unwrap().to_string());
+        }
+        assert!(default_btree.branch_work("unknown").is_none());
+        assert!(default_btree.get_block_work("unknown").is_none());
+    }
+
+    /// Test that branches contradicting a checkpoint are rejected, and that a state contradicting one is refused
+    #[test]
+    fn blocktree_checkpoints() {
+        let read_block = |i: usize| {
+            let block_json = read_string_from_file(&format!("./testdata/add_blocks_basic__{}.json", i));
+            serde_json::from_str::<BlockNode>(&block_json).unwrap()
+        };
+        // A sibling of the given block (same txs, other reward receiver), valid at difficulty 0
+        let fork_of = |mut block: BlockNode| {
+            block.header.reward_receiver = "FORK".to_string();
+            let puzzle = block.header.puzzle(&ConsensusParams::default());
+            let puzzle_str = block.header.nonce.clone() + &serde_json::to_string(&puzzle).unwrap();
+            block.header.block_id = format!("{:x}", Sha256::digest(puzzle_str.as_bytes()));
+            block
+        };
+        let block_1 = read_block(1);
+        let block_3 = read_block(3);
+        let fork_block = fork_of(read_block(1));
+
+        let mut default_btree = BlockTree::new();
+        let checkpoints: BTreeMap<u64, String> = [(1, block_1.header.block_id.clone()), (3, block_3.header.block_id.clone())].into();
+        default_btree.set_checkpoints(checkpoints.clone()).unwrap();
+        assert!(default_btree.add_block(fork_block.clone(), 0).unwrap_err().contains("checkpoint"));
+        for i in 1..=8 {
+            default_btree.add_block(read_block(i), 5).unwrap();
+        }
+        assert!(default_btree.height_of(&block_3.header.block_id) == Some(3));
+
+        // a stale state whose longest path went another way is refused
+        let mut stale_btree = BlockTree::new();
+        stale_btree.add_block(fork_block, 0).unwrap();
+        assert!(stale_btree.set_checkpoints(checkpoints.clone()).is_err());
+        assert!(stale_btree.checkpoints.is_empty());
+        // checkpoints above the longest path only constrain the blocks to come
+        let mut short_btree = BlockTree::new();
+        short_btree.add_block(block_1, 5).unwrap();
+        short_btree.set_checkpoints(checkpoints).unwrap();
+        short_btree.add_block(read_block(2), 5).unwrap();
+        assert!(short_btree.add_block(fork_of(read_block(3)), 0).unwrap_err().contains("checkpoint"));
+        short_btree.add_block(read_block(3), 5).unwrap();
+    }
+
+    /// Test that a block tree built from a checkpoint and the blocks after it reaches the same state as the full chain
+    #[test]
+    fn blocktree_from_checkpoint() {
+        let read_block = |i: usize| {
+            let block_json = read_string_from_file(&format!("./testdata/add_blocks_basic__{}.json", i));
+            serde_json::from_str::<BlockNode>(&block_json).unwrap()
+        };
+        let mut full_btree = BlockTree::new();
+        for i in 1..=8 {
+            full_btree.add_block(read_block(i), 5).unwrap();
+        }
+        let checkpoint = full_btree.checkpoint();
+        assert!(checkpoint.height == 2 && checkpoint.finalized_block_id == read_block(2).header.block_id);
+        assert!(!checkpoint.is_signed());
+        let checkpoint = serialize_clone(&checkpoint);
+        assert!(checkpoint.digest() == full_btree.checkpoint().digest());
+
+        let blocks: Vec<BlockNode> = (3..=8).map(read_block).collect();
+        let btree = BlockTree::from_checkpoint(&checkpoint, ConsensusParams::default(), blocks.clone(), 5).unwrap();
+        assert!(btree.all_blocks.len() == 7 && btree.root_id == checkpoint.finalized_block_id);
+        assert!(btree.working_block_id == full_btree.working_block_id);
+        assert!(btree.finalized_block_id == full_btree.finalized_block_id);
+        assert!(btree.finalized_balance_map == full_btree.finalized_balance_map);
+        assert!(btree.finalized_tx_ids == full_btree.finalized_tx_ids);
+        assert!(btree.get_working_balance_map() == full_btree.get_working_balance_map());
+        assert!(btree.get_block_at_height(5) == full_btree.get_block_at_height(5));
+        assert!(btree.get_block_at_height(1).is_none());
+        assert!(btree.audit_supply().is_ok() && btree.check_invariants().is_ok());
+        // a saved tree built from a checkpoint loads back with its consensus params
+        let mut reloaded = BlockTree::from_json(&serde_json::to_string(&btree).unwrap()).unwrap();
+        reloaded.set_consensus_params(ConsensusParams::default()).unwrap();
+        assert!(reloaded.check_invariants().is_ok() && !reloaded.is_rooted_at_genesis());
+        // its difficulty cannot be retargeted, since that depends on the blocks before the checkpoint
+        let retarget = ConsensusParams { retarget_interval: 2, ..ConsensusParams::default() };
+        assert!(reloaded.set_consensus_params(retarget.clone()).is_err());
+        assert!(reloaded.consensus == ConsensusParams::default());
+        assert!(BlockTree::from_checkpoint(&checkpoint, retarget, vec![], 5).is_err());
+
+        // the blocks after the checkpoint go through add_block: the ones before it are orphans, and invalid ones fail
+        let btree = BlockTree::from_checkpoint(&checkpoint, ConsensusParams::default(), vec![read_block(1)], 5).unwrap();
+        assert!(btree.orphans.len() == 1 && btree.working_block_id == checkpoint.finalized_block_id);
+        assert!(BlockTree::from_checkpoint(&checkpoint, ConsensusParams::default(), vec![read_block(3), read_block(3)], 5).is_err());
+        let mut tampered = checkpoint.clone();
+        *tampered.finalized_balance_map.values_mut().next().unwrap() += 1;
+        assert!(tampered.digest() != checkpoint.digest());
+        assert!(BlockTree::from_checkpoint(&tampered, ConsensusParams::default(), vec![], 5).unwrap_err().contains("add up"));
+        let mut tampered = checkpoint.clone();
+        tampered.block = read_block(3);
+        assert!(BlockTree::from_checkpoint(&tampered, ConsensusParams::default(), vec![], 5).is_err());
+        // at height 0, the checkpoint is the genesis block
+        let genesis_checkpoint: Checkpoint = BlockTree::new().checkpoint();
+        let btree = BlockTree::from_checkpoint(&genesis_checkpoint, ConsensusParams::default(), blocks[..0].to_vec(), 5).unwrap();
+        assert!(btree.is_rooted_at_genesis() && btree.check_invariants().is_ok());
+    }
+
+    /// Test that a batch transaction pays all its receivers when finalized, is undone by a reorg, and that its outputs
+    /// follow the consensus params
+    #[test]
+    #[cfg(feature = "crypto")]
+    fn blocktree_batch_transaction() {
+        let seed = [9; 32];
+        let sender = Base64::encode_string(&ed25519::public_key_to_der(&ed25519::public_key(&seed)));
+        let consensus = ConsensusParams { finality_depth: 1, genesis_receiver: sender.clone(), genesis_allocation: 100, ..ConsensusParams::default() };
+        let outputs = vec![TxOutput { receiver: "C".to_string(), amount: 20 }, TxOutput { receiver: "D".to_string(), amount: 30 }];
+        let payload = SignedPayload { sender: sender.clone(), receiver: "B".to_string(), message: "SEND $10   // Batch".to_string(), sender_pub_key: None, fee: Some(1), algorithm: SignatureAlgorithm::Ed25519, outputs };
+        let data_string = payload.encode();
+        let tx = Transaction::from_data_string(&data_string, Base64::encode_string(&ed25519::sign(&seed, data_string.as_bytes()))).unwrap();
+        assert!(tx.verify_sig().is_ok() && tx.total_amount() == Some(60) && tx.total_cost() == Some(61));
+        // A block of the given txs on top of the given parent, valid at difficulty 0
+        let block = |parent: &str, transactions: Vec<Transaction>| {
+            let (merkle_root, merkle_tree) = MerkleTree::create_merkle_tree(transactions.clone());
+            let puzzle = Puzzle { parent: parent.to_string(), merkle_root: merkle_root.clone(), reward_receiver: "MINER".to_string(), ..Default::default() };
+            let nonce = "0".to_string();
+            let block_id = format!("{:x}", Sha256::digest((nonce.clone() + &serde_json::to_string(&puzzle).unwrap()).as_bytes()));
+            BlockNode {
+                header: BlockNodeHeader { parent: parent.to_string(), merkle_root, timestamp: 0, block_id, nonce, reward_receiver: "MINER".to_string(), miner_signature: None },
+                transactions_block: Transactions { merkle_tree, transactions },
+            }
+        };
+
+        let mut btree = BlockTree::with_consensus_params(consensus.clone());
+        let batch_block = block("0", vec![tx.clone()]);
+        btree.add_block(batch_block.clone(), 0).unwrap();
+        assert!(btree.get_working_balance_map()["C"] == 20);
+        btree.add_block(block(&batch_block.header.block_id, vec![]), 0).unwrap();
+        assert!(btree.finalized_block_id == batch_block.header.block_id);
+        let balance = |btree: &BlockTree, user_id: &str| btree.finalized_balance_map.get(user_id).copied().unwrap_or(0);
+        assert!([balance(&btree, &sender), balance(&btree, "B"), balance(&btree, "C"), balance(&btree, "D"), balance(&btree, "MINER")] == [39, 10, 20, 30, 11]);
+        assert!(btree.audit_supply().is_ok());
+
+        // a longer fork without the batch takes over, and all its payments are undone
+        let fork_1 = block("0", vec![]);
+        let fork_2 = block(&fork_1.header.block_id, vec![]);
+        let fork_3 = block(&fork_2.header.block_id, vec![]);
+        for fork_block in [fork_1, fork_2, fork_3] {
+            btree.add_block(fork_block, 0).unwrap();
+        }
+        assert!([balance(&btree, &sender), balance(&btree, "B"), balance(&btree, "C"), balance(&btree, "D")] == [100, 0, 0, 0]);
+        assert!(btree.audit_supply().is_ok());
+
+        // the outputs are limited in number and must be positive, and their total with the fee must not overflow
+        assert!(consensus.check_tx(&tx).is_ok());
+        assert!(ConsensusParams { max_tx_outputs: 1, ..consensus.clone() }.check_tx(&tx).is_err());
+        let mut negative_output = tx.clone();
+        negative_output.outputs[1].amount = -30;
+        assert!(consensus.check_tx(&negative_output).is_err());
+        let mut overflowing = tx.clone();
+        overflowing.outputs[1].amount = i64::MAX - 30;
+        assert!(overflowing.total_amount() == Some(i64::MAX) && overflowing.total_cost().is_none());
+        assert!(consensus.check_tx(&overflowing).is_err());
+        overflowing.outputs[1].amount = i64::MAX;
+        assert!(overflowing.total_amount().is_none() && consensus.check_tx(&overflowing).is_err());
+    }
+
+    /// Test the supply invariant on a consistent block tree and on tampered balances
+    #[test]
+    fn blocktree_audit_supply() {
+        let mut default_btree = BlockTree::new();
+        assert!(default_btree.audit_supply().is_ok());
+        // orphans included, so that several blocks get finalized by a single add_block
+        for i in [3, 6, 1, 4, 2, 8, 5, 7] {
+            let block_json =
+                read_string_from_file(&format!("./testdata/add_blocks_basic__{}.json", i));
+            let block_node = serde_json::from_str::<BlockNode>(&block_json).unwrap();
+            default_btree.add_block(block_node, 5).unwrap();
+            assert!(default_btree.audit_supply().is_ok());
+        }
+
+        let mut tampered = default_btree.clone();
+        *tampered.finalized_balance_map.values_mut().next().unwrap() += 1;
+        assert!(tampered.audit_supply().is_err());
+
+        let mut tampered = default_btree.clone();
+        tampered.finalized_balance_map.insert("A".to_string(), -5);
+        tampered.finalized_balance_map.insert("B".to_string(), 5);
+        assert!(tampered.audit_supply().is_err());
+    }
+
+    /// Test the consistency checks between all_blocks, children_map and block_depth
+    #[test]
+    fn blocktree_check_invariants() {
+        let mut default_btree = BlockTree::new();
+        assert!(default_btree.check_invariants().is_ok());
+        for i in [3, 6, 1, 4, 2, 8, 5, 7] {
+            let block_json =
+                read_string_from_file(&format!("./testdata/add_blocks_basic__{}.json", i));
+            let block_node = serde_json::from_str::<BlockNode>(&block_json).unwrap();
+            default_btree.add_block(block_node, 5).unwrap();
+            assert!(default_btree.check_invariants().is_ok());
+        }
+        assert!(serialize_clone(&default_btree).check_invariants().is_ok());
+
+        let block_id = default_btree.working_block_id.clone();
+        let parent_id = default_btree.all_blocks[&block_id].header.parent.clone();
+
+        let mut tampered = default_btree.clone();
+        *tampered.block_depth.get_mut(&block_id).unwrap() += 1;
+        // flagged against its parent and in the height index
+        assert!(tampered.check_invariants().unwrap_err().len() == 2);
+
+        let mut tampered = default_btree.clone();
+        tampered.children_map.get_mut(&parent_id).unwrap().push("unknown".to_string());
+        assert!(tampered.check_invariants().unwrap_err()[0].starts_with("Dangling child unknown"));
+
+        let mut tampered = default_btree.clone();
+        tampered.all_blocks.get_mut(&block_id).unwrap().header.parent = "0".to_string();
+        let violations = tampered.check_invariants().unwrap_err();
+        assert!(violations.iter().any(|v| v.contains("in children_map but its parent is 0")));
+        assert!(violations.iter().any(|v| v.contains("listed 0 times")));
+    }
+
+    /// Test that only the first sighting of a block is recorded and that metadata does not affect block hashes
+    #[test]
+    fn blocktree_block_metadata() {
+        let mut default_btree = BlockTree::new();
+        let block_json = read_string_from_file("./testdata/add_blocks_basic__1.json");
+        let block_node = serde_json::from_str::<BlockNode>(&block_json).unwrap();
+        let block_id = block_node.header.block_id.clone();
+        default_btree.add_block(block_node, 5).unwrap();
+
+        default_btree.record_block_metadata(&block_id, "127.0.0.1:50001", 1000);
+        default_btree.record_block_metadata(&block_id, "127.0.0.1:50002", 2000);
+        let metadata = &default_btree.block_metadata[&block_id];
+        assert!(metadata.source == "127.0.0.1:50001");
+        assert!(metadata.first_seen_ms == 1000);
+        assert!(default_btree.all_blocks[&block_id].header.block_id == block_id);
+
+        // block trees serialized before the side-table existed still deserialize
+        let mut json: serde_json::Value = serde_json::to_value(&default_btree).unwrap();
+        json.as_object_mut().unwrap().remove("block_metadata");
+        let old_btree: BlockTree = serde_json::from_value(json).unwrap();
+        assert!(old_btree.block_metadata.is_empty());
+        assert!(serialize_clone(&default_btree).block_metadata == default_btree.block_metadata);
+    }
+
+    /// Test importing the blocks of another block tree (known, added and invalid blocks)
+    #[test]
+    fn blocktree_import_blocks() {
+        let read_block = |i: usize| {
+            let block_json = read_string_from_file(&format!("./testdata/add_blocks_basic__{}.json", i));
+            serde_json::from_str::<BlockNode>(&block_json).unwrap()
+        };
+        let mut foreign_btree = BlockTree::new();
+        for i in 1..=8 {
+            foreign_btree.add_block(read_block(i), 5).unwrap();
+        }
+        let mut tampered_block = read_block(1);
+        tampered_block.header.nonce = "tampered".to_string();
+        tampered_block.header.block_id = "0000000000000000000000000000000000000000000000000000000000000000".to_string();
+        foreign_btree.orphans.insert(tampered_block.header.block_id.clone(), tampered_block);
+
+        let mut local_btree = BlockTree::new();
+        for i in 1..=3 {
+            local_btree.add_block(read_block(i), 5).unwrap();
+        }
+        let summary = serialize_clone(&local_btree).import_blocks(&serialize_clone(&foreign_btree), 5);
+        assert!(summary.known == 3);
+        assert!(summary.added.len() == 5);
+        assert!(summary.rejected.len() == 1);
+
+        local_btree.import_blocks(&foreign_btree, 5);
+        assert!(local_btree.working_block_id == foreign_btree.working_block_id);
+        assert!(local_btree.finalized_block_id == foreign_btree.finalized_block_id);
+        assert!(local_btree.finalized_balance_map == foreign_btree.finalized_balance_map);
+        assert!(local_btree.orphans.is_empty());
+        assert!(local_btree.check_invariants().is_ok());
+    }
+
+    /// Test restarting a block tree from the blocks written to a file store as they were added, including after a torn write
+    #[test]
+    fn blocktree_file_block_store() {
+        let read_block = |i: usize| {
+            let block_json = read_string_from_file(&format!("./testdata/add_blocks_basic__{}.json", i));
+            serde_json::from_str::<BlockNode>(&block_json).unwrap()
+        };
+        let path = std::env::temp_dir().join(format!("blocktree_file_block_store_{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        // Blocks 1 to 3 are in the json state, written before the store was enabled; the others are stored as they come
+        let mut btree = BlockTree::new();
+        for i in 1..=3 {
+            btree.add_block(read_block(i), 5).unwrap();
+        }
+        let mut store = FileBlockStore::open(&path).unwrap();
+        assert!(store.load_blocks().unwrap().is_empty());
+        assert!(btree.save_to_store(&mut store).unwrap() == 3);
+        assert!(btree.save_to_store(&mut store).unwrap() == 0);
+        for i in [5, 4, 6, 7, 8] {
+            let block = read_block(i);
+            let block_id = block.header.block_id.clone();
+            btree.add_block(block, 5).unwrap();
+            btree.record_block_metadata(&block_id, "127.0.0.1:9011", 1000 + i as u64);
+            store.put_block(btree.all_blocks.get(&block_id).or_else(|| btree.orphans.get(&block_id)).unwrap(), &btree.get_block_metadata(&block_id)).unwrap();
+        }
+        drop(store);
+
+        let mut restarted_btree = BlockTree::new();
+        let summary = restarted_btree.load_from_store(&FileBlockStore::open(&path).unwrap(), 5).unwrap();
+        assert!(summary.added.len() == 8 && summary.known == 0 && summary.rejected.is_empty());
+        assert!(restarted_btree.working_block_id == btree.working_block_id);
+        assert!(restarted_btree.finalized_block_id == btree.finalized_block_id);
+        assert!(restarted_btree.finalized_balance_map == btree.finalized_balance_map);
+        assert!(restarted_btree.orphans.is_empty());
+        assert!(restarted_btree.get_block_metadata(&read_block(6).header.block_id).first_seen_ms == 1006);
+        assert!(restarted_btree.check_invariants().is_ok());
+
+        // A line cut short by a crash is dropped, and the next block starts a new line
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        std::io::Write::write_all(&mut file, b"{\"block\": {\"hea").unwrap();
+        drop(file);
+        let store = FileBlockStore::open(&path).unwrap();
+        assert!(store.load_blocks().unwrap().len() == 8);
+        fs::write(&path, "not a block\n").unwrap();
+        assert!(BlockTree::new().load_from_store(&FileBlockStore::open(&path).unwrap(), 5).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    /// Test loading block trees written before the version field, and by a newer build
+    #[test]
+    fn blocktree_migration() {
+        let mut btree = BlockTree::new();
+        btree.add_block(serde_json::from_str(&read_string_from_file("./testdata/add_blocks_basic__1.json")).unwrap(), 5).unwrap();
+        let mut json = serde_json::to_value(&btree).unwrap();
+        assert!(json["version"] == BLOCK_TREE_MIGRATIONS.current_version());
+
+        json.as_object_mut().unwrap().remove("version");
+        let old_btree = BlockTree::from_json(&json.to_string()).unwrap();
+        assert!(old_btree.version == BLOCK_TREE_MIGRATIONS.current_version());
+        assert!(old_btree.working_block_id == btree.working_block_id);
+
+        json["version"] = (BLOCK_TREE_MIGRATIONS.current_version() + 1).into();
+        assert!(BlockTree::from_json(&json.to_string()).is_err());
+        json["version"] = "1".into();
+        assert!(BlockTree::from_json(&json.to_string()).is_err());
+
+        // Each step from the stored version on is applied in order
+        const RENAME_REGISTRY: MigrationRegistry = MigrationRegistry {
+            kind: "Test",
+            steps: &[
+                |json| { json["steps"] = "0".into(); Ok(()) },
+                |json| { let name = json.as_object_mut().unwrap().remove("old_name").ok_or("no old_name")?; json["name"] = name; Ok(()) },
+            ],
+        };
+        let migrated = RENAME_REGISTRY.migrate(serde_json::json!({"old_name": "a"})).unwrap();
+        assert!(migrated == serde_json::json!({"name": "a", "steps": "0", "version": 2}));
+        let migrated = RENAME_REGISTRY.migrate(serde_json::json!({"old_name": "a", "version": 1})).unwrap();
+        assert!(migrated == serde_json::json!({"name": "a", "version": 2}));
+        assert!(RENAME_REGISTRY.migrate(serde_json::json!({"version": 1})).is_err());
+    }
+
+    /// Test that the published schema of BlockTree is up to date, and that an invalid block tree is reported with the path
+    /// of the field at fault
+    #[test]
+    fn blocktree_schema() {
+        check_published_schema("BlockTree", &schema_json::<BlockTree>()).unwrap();
+
+        let btree = BlockTree::new();
+        let mut json = serde_json::to_value(&btree).unwrap();
+        json["all_blocks"]["0"]["header"].as_object_mut().unwrap().remove("nonce");
+        let e = BlockTree::from_json(&json.to_string()).unwrap_err();
+        assert!(e == "Invalid BlockTree at `all_blocks.0.header`: missing field `nonce`", "{}", e);
+        json["all_blocks"]["0"]["header"]["nonce"] = "0".into();
+        json["block_depth"]["0"] = (-1).into();
+        let e = BlockTree::from_json(&json.to_string()).unwrap_err();
+        assert!(e.starts_with("Invalid BlockTree at `block_depth.0`: invalid value: integer `-1`"), "{}", e);
+        assert!(BlockTree::from_json("{").unwrap_err().starts_with("Cannot parse BlockTree: EOF while parsing"));
+    }
+
+    /// Test that a fork overtaking the finalized block unwinds the finalized state to the fork point and replays the new branch
+    #[test]
+    fn blocktree_deep_fork_reorg() {
+        let read_block = |i: usize| {
+            let block_json = read_string_from_file(&format!("./testdata/add_blocks_basic__{}.json", i));
+            serde_json::from_str::<BlockNode>(&block_json).unwrap()
+        };
+        // A block without txs on top of the given parent, valid at difficulty 0
+        let empty_block = |parent: &str, reward_receiver: &str| {
+            let (merkle_root, merkle_tree) = MerkleTree::create_merkle_tree(vec![]);
+            let puzzle = Puzzle { parent: parent.to_string(), merkle_root: merkle_root.clone(), reward_receiver: reward_receiver.to_string(), ..Default::default() };
+            let nonce = "0".to_string();
+            let block_id = format!("{:x}", Sha256::digest((nonce.clone() + &serde_json::to_string(&puzzle).unwrap()).as_bytes()));
+            BlockNode {
+                header: BlockNodeHeader { parent: parent.to_string(), merkle_root, timestamp: 0, block_id, nonce, reward_receiver: reward_receiver.to_string(), miner_signature: None },
+                transactions_block: Transactions { merkle_tree, transactions: vec![] },
+            }
+        };
+        let branch = |parent: &str, len: usize, reward_receiver: &str| {
+            let mut blocks: Vec<BlockNode> = vec![];
+            for _ in 0..len {
+                let parent = blocks.last().map_or(parent.to_string(), |block| block.header.block_id.clone());
+                blocks.push(empty_block(&parent, reward_receiver));
+            }
+            blocks
+        };
+        // the balances of two trees agree, ignoring the addresses left with nothing
+        let same_finalized_state = |a: &BlockTree, b: &BlockTree| {
+            let non_zero = |btree: &BlockTree| btree.finalized_balance_map.iter().filter(|(_, balance)| **balance != 0).map(|(k, v)| (k.clone(), *v)).collect::<BTreeMap<_, _>>();
+            a.finalized_block_id == b.finalized_block_id && a.finalized_tx_ids == b.finalized_tx_ids && non_zero(a) == non_zero(b)
+        };
+
+        let mut btree = BlockTree::new();
+        for i in 1..=8 {
+            btree.add_block(read_block(i), 5).unwrap();
+        }
+        let old_finalized_id = btree.finalized_block_id.clone();
+        assert!(btree.height_of(&old_finalized_id) == Some(2));
+        assert!(!btree.finalized_tx_ids.is_empty());
+
+        // a fork from the genesis block, 2 blocks longer, takes over: its first 4 blocks are final instead of the txs of the old branch
+        let fork_blocks = branch("0", 10, "FORK");
+        for block in fork_blocks.iter() {
+            btree.add_block(block.clone(), 0).unwrap();
+        }
+        assert!(btree.working_block_id == fork_blocks[9].header.block_id);
+        assert!(btree.finalized_block_id == fork_blocks[3].header.block_id);
+        assert!(btree.fork_point(&old_finalized_id, &btree.working_block_id) == Some("0".to_string()));
+        let mut fork_only = BlockTree::new();
+        for block in fork_blocks.iter() {
+            fork_only.add_block(block.clone(), 0).unwrap();
+        }
+        assert!(same_finalized_state(&btree, &fork_only));
+        assert!(btree.finalized_tx_ids.is_empty());
+        assert!(btree.finalized_balance_map["FORK"] == 4 * 10);
+        assert!(btree.audit_supply().is_ok());
+
+        // the old branch takes over again once it is extended, and its txs are final again
+        let extension = branch(&read_block(8).header.block_id, 3, "BACK");
+        for block in extension.iter() {
+            btree.add_block(block.clone(), 0).unwrap();
+        }
+        assert!(btree.working_block_id == extension[2].header.block_id);
+        assert!(btree.height_of(&btree.finalized_block_id) == Some(5));
+        let mut old_branch_only = BlockTree::new();
+        for i in 1..=8 {
+            old_branch_only.add_block(read_block(i), 5).unwrap();
+        }
+        for block in extension.iter() {
+            old_branch_only.add_block(block.clone(), 0).unwrap();
+        }
+        assert!(same_finalized_state(&btree, &old_branch_only));
+        assert!(btree.finalized_balance_map.get("FORK").copied().unwrap_or(0) == 0);
+        assert!(btree.audit_supply().is_ok());
+        assert!(btree.check_invariants().is_ok());
+    }
+
+    /// Test that the difficulty required of a block follows the pace of the previous retarget period on its branch
+    #[test]
+    fn blocktree_difficulty_retarget() {
+        // A block without txs on top of the given parent, with exactly `leading_zero_len` leading zeros
+        let mined_block = |parent: &str, timestamp: u64, leading_zero_len: u32| {
+            let (merkle_root, merkle_tree) = MerkleTree::create_merkle_tree(vec![]);
+            let puzzle = Puzzle { parent: parent.to_string(), merkle_root: merkle_root.clone(), reward_receiver: "MINER".to_string(), timestamp: Some(timestamp), ..Default::default() };
+            let (nonce, block_id) = (0u64..).map(|nonce| (nonce.to_string(), format!("{:x}", Sha256::digest((nonce.to_string() + &serde_json::to_string(&puzzle).unwrap()).as_bytes()))))
+                .find(|(_, block_id)| block_id.len() - block_id.trim_start_matches('0').len() == leading_zero_len as usize).unwrap();
+            BlockNode {
+                header: BlockNodeHeader { parent: parent.to_string(), merkle_root, timestamp, block_id, nonce, reward_receiver: "MINER".to_string(), miner_signature: None },
+                transactions_block: Transactions { merkle_tree, transactions: vec![] },
+            }
+        };
+
+        // Without a retarget interval, the difficulty is the given one
+        let fixed_btree = BlockTree::new();
+        assert!(fixed_btree.expected_difficulty("0", 3) == Some(3));
+        assert!(fixed_btree.expected_difficulty("unknown", 3).is_none());
+
+        let mut btree = BlockTree::with_consensus_params(ConsensusParams { retarget_interval: 2, target_block_time_ms: 1000, ..ConsensusParams::default() });
+        let mut tip = "0".to_string();
+        let mut add = |btree: &mut BlockTree, timestamp: u64, leading_zero_len: u32| {
+            let block = mined_block(&tip, timestamp, leading_zero_len);
+            let result = btree.add_block(block.clone(), 0);
+            if result.is_ok() {
+                tip = block.header.block_id;
+            }
+            result
+        };
+        // The first period is at the given difficulty; its blocks came 100 times too fast, so one more zero is required
+        assert!(add(&mut btree, 1000, 0).is_ok() && add(&mut btree, 1010, 0).is_ok());
+        assert!(btree.expected_difficulty(&btree.working_block_id, 0) == Some(1));
+        assert!(add(&mut btree, 1020, 0).is_err());
+        // A period on target keeps the difficulty
+        assert!(add(&mut btree, 2000, 1).is_ok() && add(&mut btree, 3000, 1).is_ok());
+        assert!(btree.expected_difficulty(&btree.working_block_id, 0) == Some(1));
+        assert!(add(&mut btree, 4000, 1).is_ok() && add(&mut btree, 14000, 1).is_ok());
+        // A period 10 times too slow lowers it again, but never below the given difficulty
+        assert!(btree.expected_difficulty(&btree.working_block_id, 0) == Some(0));
+        assert!(btree.expected_difficulty(&btree.working_block_id, 1) == Some(1));
+        assert!(add(&mut btree, 15000, 0).is_ok());
+        // The children of a block in the middle of a period expect the difficulty of its period
+        let height_3 = btree.get_block_at_height(3).unwrap().header.block_id;
+        assert!(btree.expected_difficulty(&height_3, 0) == Some(1));
+        assert!(btree.check_invariants().is_ok());
+
+        // The difficulty after each closed period is cached, and the cache gives the difficulty computed from the genesis block
+        assert!(btree.retarget_cache.len() == 4);
+        let mut reloaded = BlockTree::from_json(&serde_json::to_string(&btree).unwrap()).unwrap();
+        reloaded.set_consensus_params(btree.consensus.clone()).unwrap();
+        assert!(reloaded.retarget_cache.is_empty());
+        for block in btree.iter_main_chain() {
+            let block_id = &block.header.block_id;
+            assert!(btree.expected_difficulty(block_id, 0) == reloaded.expected_difficulty(block_id, 0));
+        }
+
+        // The timestamp is part of the block id, so it cannot be moved back by a relaying node
+        let mut moved_block = mined_block(&btree.working_block_id, 16000, 0);
+        moved_block.header.timestamp = 1;
+        assert!(btree.add_block(moved_block, 0).is_err());
+        // A block must come after the median time of the blocks before it (3000 here), not only after its parent
+        assert!(btree.median_time_past(&btree.working_block_id) == Some(3000));
+        assert!(add(&mut btree, 3000, 0).unwrap_err().contains("median time"));
+        assert!(add(&mut btree, 3001, 0).is_ok());
+    }
+
+    /// Test the log lines of the trace events and the timeline assembled from the events of several processes.
+    #[test]
+    fn txtrace_timeline() {
+        let data_string = r#"["A","B","SEND $300   // By A"]"#;
+        let tx_id = tx_id_of(data_string, &"sig".to_string()).unwrap();
+        assert!(tx_id == Transaction::new("A".to_string(), "B".to_string(), "SEND $300   // By A".to_string(), "sig".to_string()).gen_hash());
+        assert!(tx_id_of("not a data_string", &"sig".to_string()).is_none());
+
+        let admitted = TxTraceEvent::new(tx_id.clone(), TxSpan::PoolAdmitted, "nakamoto", 1030, String::new());
+        let line = admitted.to_log_line();
+        assert!(line.starts_with(TX_TRACE_PREFIX) && !line.contains("detail"));
+        // the timestamp of a log file before the prefix is ignored
+        assert!(TxTraceEvent::from_log_line(&format!("1678173972.743 {}", line)) == Some(admitted.clone()));
+        assert!(TxTraceEvent::from_log_line("[BlockStore] Block 0 not stored").is_none());
+
+        // the events are ordered by the lifecycle, even when the clock of a process is behind
+        let events = vec![
+            admitted,
+            TxTraceEvent::new(tx_id.clone(), TxSpan::Included, "nakamoto", 2030, "block 1a2b".to_string()),
+            TxTraceEvent::new(tx_id.clone(), TxSpan::Signed, "wallet", 1040, String::new()),
+            TxTraceEvent::new(tx_id.clone(), TxSpan::Submitted, "client", 1000, String::new()),
+            TxTraceEvent::new("other".to_string(), TxSpan::Finalized, "nakamoto", 3000, String::new()),
+        ];
+        let timeline = format_timeline(&tx_id, &events);
+        assert!(timeline == vec![
+            format!("Timeline of tx {}:", tx_id),
+            "  Submitted by client at 1000 (start)".to_string(),
+            "  Signed by wallet at 1040 (+40 ms)".to_string(),
+            "  PoolAdmitted by nakamoto at 1030 (-10 ms)".to_string(),
+            "  Included by nakamoto at 2030 (+1000 ms): block 1a2b".to_string(),
+            "  not seen yet: Finalized".to_string(),
+            "  total: 1030 ms".to_string(),
+        ]);
+    }
+
+    /// A virtual sleep only ends once the clock has been advanced past it, and the real clock cannot be advanced.
+    #[test]
+    fn virtual_clock() {
+        let clock = Arc::new(Clock::new_virtual(1000));
+        assert!(clock.is_virtual() && !Clock::Real.is_virtual());
+        let sleeper = {
+            let clock = clock.clone();
+            thread::spawn(move || {
+                clock.sleep_ms(500);
+                clock.now_ms()
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert!(clock.advance_ms(300) == Ok(1300));
+        thread::sleep(Duration::from_millis(50));
+        assert!(!sleeper.is_finished());
+        assert!(clock.advance_ms(200) == Ok(1500));
+        assert!(sleeper.join().unwrap() == 1500);
+        assert!(clock.now_ms() == 1500);
+
+        let real = Clock::Real;
+        let before = real.now_ms();
+        assert!(real.advance_ms(1_000_000).is_err());
+        assert!(real.now_ms() < before + 1_000_000);
+    }
+
     /// Your own test that tests your blocktree implementation more throughly (e.g., orphan, invalid block, etc.)
     #[test]
     fn blocktree_additional_test() {
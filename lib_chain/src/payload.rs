@@ -0,0 +1,196 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// This file implements the payload signed by the sender of a transaction, i.e. the `data_string` exchanged by
+// bin_client, bin_wallet and bin_nakamoto. Its one encoding is the compact json array of its string fields, as written by
+// `serde_json`, so the message may contain any character (quotes, backslashes, newlines, etc.) and the signature checked
+// by `Transaction::verify_sig` is always computed over the same bytes the wallet signed.
+// A data_string in any other form (extra spaces, other escapes, a fee written "+3", etc.) is rejected when decoded,
+// instead of being accepted and then failing the signature check.
+// The payload of an Ed25519 transaction names its algorithm in a sixth field, so its signature cannot be replayed as the
+// one of an RSA transaction; the payloads of RSA transactions keep the encoding they had before Ed25519 was supported.
+// The payload of a batch transaction has a seventh field with its other payments (the compact json array of the
+// `[receiver, amount]` pairs), so that one signature covers all of them.
+
+use crate::block::{SignatureAlgorithm, TxOutput, UserId};
+
+/// The data signed by the sender of a transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignedPayload {
+    /// The user_id of the sender
+    pub sender: UserId,
+    /// The user_id of the receiver
+    pub receiver: UserId,
+    /// The message, e.g. `SEND $300   // By Alice   // 1678173972743`
+    pub message: String,
+    /// The public key of the sender, for a hashed sender address
+    pub sender_pub_key: Option<String>,
+    /// The fee paid by the sender on top of the amount
+    pub fee: Option<i64>,
+    /// The signature scheme of the sender key
+    pub algorithm: SignatureAlgorithm,
+    /// The other payments of a batch transaction
+    pub outputs: Vec<TxOutput>,
+}
+
+/// The last field of the data_string of an Ed25519 transaction.
+const ED25519_FIELD: &str = "Ed25519";
+
+impl SignedPayload {
+    /// Encode the payload as its data_string: `[sender, receiver, message]`, followed by the sender public key for a
+    /// hashed sender address, and then by the fee (in decimal) if there is one, with an empty public key if there is none.
+    /// An Ed25519 payload always has the 6 fields `[sender, receiver, message, sender_pub_key, fee, "Ed25519"]`, with an
+    /// empty public key and fee when there are none. A batch payload has 7 fields
+    /// `[sender, receiver, message, sender_pub_key, fee, algorithm, outputs]`, with an empty algorithm for RSA.
+    pub fn encode(&self) -> String {
+        let fee = self.fee.map(|fee| fee.to_string());
+        if !self.outputs.is_empty() {
+            let fields = [
+                self.sender.as_str(),
+                self.receiver.as_str(),
+                self.message.as_str(),
+                self.sender_pub_key.as_deref().unwrap_or_default(),
+                fee.as_deref().unwrap_or_default(),
+                match self.algorithm {
+                    SignatureAlgorithm::Rsa => "",
+                    SignatureAlgorithm::Ed25519 => ED25519_FIELD,
+                },
+                &encode_outputs(&self.outputs),
+            ];
+            return serde_json::to_string(&fields).unwrap();
+        }
+        if self.algorithm == SignatureAlgorithm::Ed25519 {
+            let fields = [
+                self.sender.as_str(),
+                self.receiver.as_str(),
+                self.message.as_str(),
+                self.sender_pub_key.as_deref().unwrap_or_default(),
+                fee.as_deref().unwrap_or_default(),
+                ED25519_FIELD,
+            ];
+            return serde_json::to_string(&fields).unwrap();
+        }
+        let mut fields = vec![
+            self.sender.as_str(),
+            self.receiver.as_str(),
+            self.message.as_str(),
+        ];
+        if self.sender_pub_key.is_some() || self.fee.is_some() {
+            fields.push(self.sender_pub_key.as_deref().unwrap_or_default());
+        }
+        if let Some(fee) = &fee {
+            fields.push(fee);
+        }
+        serde_json::to_string(&fields).unwrap()
+    }
+
+    /// Decode a data_string, which must be exactly the encoding of the payload (see `encode`).
+    pub fn decode(data_string: &str) -> Result<SignedPayload, String> {
+        let fields: Vec<String> = serde_json::from_str(data_string)
+            .map_err(|e| format!("Malformed data_string: {}", e))?;
+        let mut fields = fields.into_iter();
+        // The outputs of a batch are in its seventh and last field, after an empty algorithm for RSA
+        let outputs = match fields.len() {
+            7 => parse_outputs(&fields.next_back().unwrap_or_default())?,
+            _ => vec![],
+        };
+        let is_rsa_batch = |algorithm: &str| !outputs.is_empty() && algorithm.is_empty();
+        let mut payload = match (
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+        ) {
+            (Some(sender), Some(receiver), Some(message), sender_pub_key, None, None) => {
+                SignedPayload {
+                    sender,
+                    receiver,
+                    message,
+                    sender_pub_key,
+                    fee: None,
+                    algorithm: SignatureAlgorithm::Rsa,
+                    outputs: vec![],
+                }
+            }
+            (
+                Some(sender),
+                Some(receiver),
+                Some(message),
+                Some(sender_pub_key),
+                Some(fee),
+                None,
+            ) => SignedPayload {
+                sender,
+                receiver,
+                message,
+                sender_pub_key: Some(sender_pub_key).filter(|key| !key.is_empty()),
+                fee: Some(parse_fee(&fee)?),
+                algorithm: SignatureAlgorithm::Rsa,
+                outputs: vec![],
+            },
+            (
+                Some(sender),
+                Some(receiver),
+                Some(message),
+                Some(sender_pub_key),
+                Some(fee),
+                Some(algorithm),
+            ) if algorithm == ED25519_FIELD || is_rsa_batch(&algorithm) => SignedPayload {
+                sender,
+                receiver,
+                message,
+                sender_pub_key: Some(sender_pub_key).filter(|key| !key.is_empty()),
+                fee: match fee.as_str() {
+                    "" => None,
+                    fee => Some(parse_fee(fee)?),
+                },
+                algorithm: match is_rsa_batch(&algorithm) {
+                    true => SignatureAlgorithm::Rsa,
+                    false => SignatureAlgorithm::Ed25519,
+                },
+                outputs: vec![],
+            },
+            _ => {
+                return Err(
+                    "Malformed data_string: expected 3 to 6 fields, or 7 for a batch".to_string(),
+                )
+            }
+        };
+        payload.outputs = outputs;
+        if payload.encode() != data_string {
+            return Err("Malformed data_string: not in the canonical encoding".to_string());
+        }
+        Ok(payload)
+    }
+}
+
+/// Parse the fee of a data_string, which must be in decimal.
+fn parse_fee(fee: &str) -> Result<i64, String> {
+    fee.parse()
+        .map_err(|_| format!("Malformed data_string: invalid fee {:?}", fee))
+}
+
+/// Encode the outputs of a batch as the compact json array of their `[receiver, amount]` pairs.
+fn encode_outputs(outputs: &[TxOutput]) -> String {
+    let pairs: Vec<(&str, i64)> = outputs
+        .iter()
+        .map(|output| (output.receiver.as_str(), output.amount))
+        .collect();
+    serde_json::to_string(&pairs).unwrap()
+}
+
+/// Parse the outputs of a batch data_string, of which there must be at least one.
+fn parse_outputs(outputs: &str) -> Result<Vec<TxOutput>, String> {
+    let pairs: Vec<(UserId, i64)> = serde_json::from_str(outputs)
+        .map_err(|e| format!("Malformed data_string: invalid outputs ({})", e))?;
+    if pairs.is_empty() {
+        return Err("Malformed data_string: a batch needs at least one output".to_string());
+    }
+    Ok(pairs
+        .into_iter()
+        .map(|(receiver, amount)| TxOutput { receiver, amount })
+        .collect())
+}
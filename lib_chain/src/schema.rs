@@ -0,0 +1,70 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// This file implements the JSON Schemas of the json files read by the programs (BlockTree, TxPool, Config, Wallet and
+// bot command files), and the loading of those files with errors that name the field at fault.
+// The schemas are generated from the Rust types with `schemars` and published in the `schemas` directory of the
+// workspace, so that editors can check hand-edited fixtures. Each crate owning one of the types has a test comparing its
+// schema with the published one (see `check_published_schema`); run the tests with `UPDATE_SCHEMAS=1` to rewrite them
+// after changing a type.
+// serde alone reports a broken file as e.g. "missing field `nonce` at line 1 column 5321"; `from_json_value` and
+// `from_json_str` report it as "at `all_blocks.<block_id>.header`: missing field `nonce`" instead.
+
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::fs;
+use std::path::PathBuf;
+
+/// The environment variable that makes `check_published_schema` rewrite the published schemas instead of comparing them.
+pub const UPDATE_SCHEMAS_VAR: &str = "UPDATE_SCHEMAS";
+
+/// The JSON Schema of a type, as pretty-printed json.
+pub fn schema_json<T: JsonSchema>() -> String {
+    serde_json::to_string_pretty(&schemars::schema_for!(T)).unwrap() + "\n"
+}
+
+/// Deserialize a json value, naming the path of the field at fault in the error (e.g. `consensus.mining_reward`).
+pub fn from_json_value<T: DeserializeOwned>(kind: &str, json: Value) -> Result<T, String> {
+    serde_path_to_error::deserialize(json).map_err(|e| {
+        let path = e.path().to_string();
+        match path.as_str() {
+            "." => format!("Invalid {}: {}", kind, e.into_inner()),
+            _ => format!("Invalid {} at `{}`: {}", kind, path, e.into_inner()),
+        }
+    })
+}
+
+/// Parse and deserialize a json string, with the line and column of a syntax error, or the path of the field at fault
+/// (see `from_json_value`).
+pub fn from_json_str<T: DeserializeOwned>(kind: &str, json_str: &str) -> Result<T, String> {
+    let json: Value =
+        serde_json::from_str(json_str).map_err(|e| format!("Cannot parse {}: {}", kind, e))?;
+    from_json_value(kind, json)
+}
+
+/// The path of the published schema of the given name, in the `schemas` directory of the workspace.
+pub fn published_schema_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("../schemas")
+        .join(format!("{}.schema.json", name))
+}
+
+/// Check that the published schema of the given name is the given one, or rewrite it if `UPDATE_SCHEMAS` is set.
+pub fn check_published_schema(name: &str, schema: &str) -> Result<(), String> {
+    let path = published_schema_path(name);
+    if std::env::var_os(UPDATE_SCHEMAS_VAR).is_some() {
+        return fs::write(&path, schema)
+            .map_err(|e| format!("Cannot write {}: {}", path.display(), e));
+    }
+    let published = fs::read_to_string(&path).unwrap_or_default();
+    if published != schema {
+        return Err(format!(
+            "{} is out of date: run the tests with {}=1 to rewrite it",
+            path.display(),
+            UPDATE_SCHEMAS_VAR
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,183 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// This file implements the on-disk storage of the blocks of a block tree.
+// The BlockTree lives in memory; a `BlockStore` keeps a copy of every block accepted by `BlockTree::add_block` (including
+// the orphans), written as soon as it is added, so that a node restarts from its last state without a full json dump.
+// On restart, the stored blocks are replayed through `add_block` (see `BlockTree::load_from_store`), so the derived state
+// (depths, longest path, finalized balances, etc.) is recomputed instead of trusted.
+// The store is append-only: the blocks later pruned from the tree stay in it, and are pruned again after a restart.
+
+use crate::block::{BlockId, BlockMetadata, BlockNode, BlockTree, ImportSummary};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// A storage of the blocks accepted by a block tree.
+pub trait BlockStore: Send {
+    /// Persist a block accepted by `BlockTree::add_block`, with where and when it was first seen.
+    /// It returns once the block is durably stored.
+    fn put_block(&mut self, block: &BlockNode, metadata: &BlockMetadata) -> Result<(), String>;
+
+    /// Get all the stored blocks, in the order they were put.
+    fn load_blocks(&self) -> Result<Vec<(BlockNode, BlockMetadata)>, String>;
+}
+
+/// One line of a `FileBlockStore`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct StoredBlock {
+    block: BlockNode,
+    metadata: BlockMetadata,
+}
+
+/// A block store in a single append-only file, holding one json `StoredBlock` per line.
+pub struct FileBlockStore {
+    /// The path of the file
+    path: PathBuf,
+    /// The file, opened for appending
+    file: File,
+}
+
+impl FileBlockStore {
+    /// Open the store at the given path, creating the file if it does not exist.
+    /// A last line cut short (e.g. by a crash in the middle of a write) is dropped, so that the next block starts a new line.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<FileBlockStore, String> {
+        let path = path.as_ref().to_path_buf();
+        let content = match fs::read(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(format!("Cannot read block store {}: {}", path.display(), e)),
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| format!("Cannot open block store {}: {}", path.display(), e))?;
+        if !content.is_empty() && !content.ends_with(b"\n") {
+            let complete_len = content
+                .iter()
+                .rposition(|&b| b == b'\n')
+                .map_or(0, |i| i + 1);
+            eprintln!(
+                "[BlockStore] Dropping the incomplete last line of {} ({} bytes)",
+                path.display(),
+                content.len() - complete_len
+            );
+            file.set_len(complete_len as u64)
+                .map_err(|e| format!("Cannot repair block store {}: {}", path.display(), e))?;
+        }
+        Ok(FileBlockStore { path, file })
+    }
+}
+
+impl BlockStore for FileBlockStore {
+    fn put_block(&mut self, block: &BlockNode, metadata: &BlockMetadata) -> Result<(), String> {
+        let stored_block = StoredBlock {
+            block: block.clone(),
+            metadata: metadata.clone(),
+        };
+        let mut line = serde_json::to_string(&stored_block).map_err(|e| e.to_string())?;
+        line.push('\n');
+        self.file
+            .write_all(line.as_bytes())
+            .and_then(|_| self.file.sync_data())
+            .map_err(|e| format!("Cannot write to block store {}: {}", self.path.display(), e))
+    }
+
+    fn load_blocks(&self) -> Result<Vec<(BlockNode, BlockMetadata)>, String> {
+        let content = fs::read_to_string(&self.path)
+            .map_err(|e| format!("Cannot read block store {}: {}", self.path.display(), e))?;
+        content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                serde_json::from_str::<StoredBlock>(line)
+                    .map(|stored_block| (stored_block.block, stored_block.metadata))
+                    .map_err(|e| {
+                        format!(
+                            "Malformed block at line {} of {}: {}",
+                            i + 1,
+                            self.path.display(),
+                            e
+                        )
+                    })
+            })
+            .collect()
+    }
+}
+
+impl BlockTree {
+    /// Add the blocks of a store to this block tree, in the order they were stored.
+    /// Every block goes through `add_block`, so it is validated again, and keeps the metadata it was stored with.
+    pub fn load_from_store(
+        &mut self,
+        store: &dyn BlockStore,
+        leading_zero_len: u16,
+    ) -> Result<ImportSummary, String> {
+        let mut summary = ImportSummary::default();
+        for (block, metadata) in store.load_blocks()? {
+            let block_id = block.header.block_id.clone();
+            if self.all_blocks.contains_key(&block_id) || self.orphans.contains_key(&block_id) {
+                summary.known += 1;
+                continue;
+            }
+            match self.add_block(block, leading_zero_len) {
+                Ok(()) => {
+                    self.record_block_metadata(&block_id, &metadata.source, metadata.first_seen_ms);
+                    summary.added.push(block_id);
+                }
+                Err(e) => summary.rejected.push((block_id, e)),
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Put the blocks of this block tree (parent first, then the orphans) that are not in the given store yet,
+    /// e.g. the blocks of a json state loaded before the store was enabled. The root block is skipped.
+    /// It returns the number of blocks put.
+    pub fn save_to_store(&self, store: &mut dyn BlockStore) -> Result<usize, String> {
+        let stored_ids: HashSet<BlockId> = store
+            .load_blocks()?
+            .into_iter()
+            .map(|(block, _)| block.header.block_id)
+            .collect();
+        let mut blocks: Vec<&BlockNode> = self
+            .all_blocks
+            .values()
+            .filter(|block| block.header.block_id != self.root_id)
+            .collect();
+        blocks.sort_by_key(|block| {
+            (
+                self.block_depth.get(&block.header.block_id).copied(),
+                block.header.block_id.clone(),
+            )
+        });
+        let mut orphans: Vec<&BlockNode> = self.orphans.values().collect();
+        orphans.sort_by_key(|block| block.header.block_id.clone());
+
+        let mut put_count = 0;
+        for block in blocks.into_iter().chain(orphans) {
+            if stored_ids.contains(&block.header.block_id) {
+                continue;
+            }
+            store.put_block(block, &self.get_block_metadata(&block.header.block_id))?;
+            put_count += 1;
+        }
+        Ok(put_count)
+    }
+
+    /// Get where and when the block was first seen, or an unknown source if it was not recorded.
+    pub fn get_block_metadata(&self, block_id: &str) -> BlockMetadata {
+        self.block_metadata
+            .get(block_id)
+            .cloned()
+            .unwrap_or_else(|| BlockMetadata {
+                source: "unknown".to_string(),
+                first_seen_ms: 0,
+            })
+    }
+}
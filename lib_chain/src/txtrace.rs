@@ -0,0 +1,142 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// This file implements the trace spans of the lifecycle of a transaction across the processes: submitted by the form (or
+// a bot command) of bin_client, signed by bin_wallet, admitted into the tx pool of bin_nakamoto, included in a block of its
+// working chain, and finalized. Each process writes the spans it sees as one json line on its log (stderr for bin_wallet
+// and bin_nakamoto, the client log for bin_client), stamped with its own clock and correlated by the id of the
+// transaction. bin_client collects the lines of its children and assembles the timeline of a transaction from them (see
+// `format_timeline`).
+// The clocks of the processes are only as close as the clocks of the machines (or a virtual clock, see bin_nakamoto), so
+// the spans are ordered by the lifecycle, not by their time.
+
+use crate::block::{Signature, Transaction, TxId};
+use serde::{Deserialize, Serialize};
+
+/// The prefix of the log lines carrying a trace event, followed by the event as json.
+pub const TX_TRACE_PREFIX: &str = "[tx-trace] ";
+
+/// A step of the lifecycle of a transaction, in the order they happen.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TxSpan {
+    /// The transaction was created by the form or a bot command of bin_client
+    Submitted,
+    /// The transaction was signed by bin_wallet
+    Signed,
+    /// The transaction was admitted into the tx pool of bin_nakamoto
+    PoolAdmitted,
+    /// The transaction is in a block that joined the working chain
+    Included,
+    /// The block including the transaction was finalized
+    Finalized,
+}
+
+impl TxSpan {
+    /// All the spans, in the order of the lifecycle.
+    pub const ALL: [TxSpan; 5] = [
+        TxSpan::Submitted,
+        TxSpan::Signed,
+        TxSpan::PoolAdmitted,
+        TxSpan::Included,
+        TxSpan::Finalized,
+    ];
+}
+
+/// A step of the lifecycle of a transaction, as seen by one process.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TxTraceEvent {
+    /// The id of the transaction (see `Transaction::gen_hash`)
+    pub tx_id: TxId,
+    /// The step of the lifecycle
+    pub span: TxSpan,
+    /// The process that saw the step ("client", "wallet" or "nakamoto")
+    pub process: String,
+    /// When the step happened, by the clock of the process (ms since the UNIX epoch)
+    pub at_ms: u64,
+    /// What else is known about the step, e.g. the block including the transaction
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub detail: String,
+}
+
+impl TxTraceEvent {
+    /// Create a trace event.
+    pub fn new(
+        tx_id: TxId,
+        span: TxSpan,
+        process: &str,
+        at_ms: u64,
+        detail: String,
+    ) -> TxTraceEvent {
+        TxTraceEvent {
+            tx_id,
+            span,
+            process: process.to_string(),
+            at_ms,
+            detail,
+        }
+    }
+
+    /// The log line of the event: `TX_TRACE_PREFIX` followed by the event as json.
+    pub fn to_log_line(&self) -> String {
+        format!(
+            "{}{}",
+            TX_TRACE_PREFIX,
+            serde_json::to_string(self).unwrap()
+        )
+    }
+
+    /// Parse the event of a log line, if it carries one. Whatever precedes the prefix (e.g. the timestamp of a log file)
+    /// is ignored.
+    pub fn from_log_line(line: &str) -> Option<TxTraceEvent> {
+        let (_, json) = line.split_once(TX_TRACE_PREFIX)?;
+        serde_json::from_str(json).ok()
+    }
+}
+
+/// The id the chain gives to the transaction of the given data_string once signed, if the data_string is well-formed.
+pub fn tx_id_of(data_string: &str, signature: &Signature) -> Option<TxId> {
+    Transaction::from_data_string(data_string, signature.clone())
+        .ok()
+        .map(|tx| tx.gen_hash())
+}
+
+/// The timeline of a transaction from its trace events (of any process, in any order): one line per event in the order
+/// of the lifecycle, with the time since the previous step, then the spans not seen yet and the total time.
+pub fn format_timeline(tx_id: &str, events: &[TxTraceEvent]) -> Vec<String> {
+    let mut events: Vec<&TxTraceEvent> =
+        events.iter().filter(|event| event.tx_id == tx_id).collect();
+    events.sort_by_key(|event| (event.span, event.at_ms));
+    let mut lines = vec![format!("Timeline of tx {}:", tx_id)];
+    let mut previous_ms: Option<u64> = None;
+    for event in events.iter() {
+        let delta = match previous_ms {
+            Some(previous_ms) => format!("{:+} ms", event.at_ms as i64 - previous_ms as i64),
+            None => "start".to_string(),
+        };
+        let mut line = format!(
+            "  {:?} by {} at {} ({})",
+            event.span, event.process, event.at_ms, delta
+        );
+        if !event.detail.is_empty() {
+            line.push_str(&format!(": {}", event.detail));
+        }
+        lines.push(line);
+        previous_ms = Some(event.at_ms);
+    }
+    let missing: Vec<String> = TxSpan::ALL
+        .iter()
+        .filter(|span| !events.iter().any(|event| event.span == **span))
+        .map(|span| format!("{:?}", span))
+        .collect();
+    if !missing.is_empty() {
+        lines.push(format!("  not seen yet: {}", missing.join(", ")));
+    }
+    if let (Some(first), Some(last)) = (events.first(), events.last()) {
+        lines.push(format!(
+            "  total: {} ms",
+            last.at_ms as i64 - first.at_ms as i64
+        ));
+    }
+    lines
+}
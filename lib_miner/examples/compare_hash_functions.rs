@@ -0,0 +1,62 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// This example compares the hash functions a chain can be built with (see `ConsensusParams::hash_function`): the Miner
+// solves the same puzzles with each of them, and the hash rate and the time per puzzle are printed side by side.
+// Run it with `cargo run --release -p lib_miner --example compare_hash_functions [difficulty] [puzzles]`
+// (4 leading zeros and 5 puzzles by default); a debug build measures the unoptimized hashers.
+
+use lib_chain::hash::HashFunction;
+use lib_miner::miner::Miner;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+
+/// The number of threads mining each puzzle.
+const THREAD_COUNT: u16 = 4;
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let difficulty: u16 = args.next().map_or(4, |arg| {
+        arg.parse()
+            .expect("The difficulty is a number of leading zeros")
+    });
+    let puzzle_count: u64 = args.next().map_or(5, |arg| {
+        arg.parse().expect("The number of puzzles is a number")
+    });
+
+    for hash_function in [HashFunction::Sha256, HashFunction::Blake3] {
+        let miner_p = Arc::new(Mutex::new(Miner {
+            hash_function,
+            ..Miner::new()
+        }));
+        let started = Instant::now();
+        for i in 0..puzzle_count {
+            let solution = Miner::solve_puzzle(
+                miner_p.clone(),
+                format!("PUZZLE_{}", i),
+                16,
+                difficulty,
+                THREAD_COUNT,
+                i * THREAD_COUNT as u64,
+                Arc::new(RwLock::new(false)),
+            )
+            .expect("The puzzle is not cancelled");
+            assert_eq!(
+                solution.hash,
+                hash_function.digest_hex((solution.nonce + &solution.puzzle).as_bytes())
+            );
+        }
+        let elapsed = started.elapsed();
+        let hash_count = miner_p.lock().unwrap().hash_count;
+        println!(
+            "{}: {} puzzles in {:.2?} ({:.2?} per puzzle), {} hashes, {:.0} H/s",
+            hash_function,
+            puzzle_count,
+            elapsed,
+            elapsed / puzzle_count.max(1) as u32,
+            hash_count,
+            hash_count as f64 / elapsed.as_secs_f64()
+        );
+    }
+}
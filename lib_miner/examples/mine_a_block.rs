@@ -0,0 +1,116 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// This example mines one block the way bin_nakamoto does, without a node: a signed transaction is added to a TxPool,
+// the pool picks the transactions of the block, the Miner solves the puzzle built from them on top of the genesis block,
+// and the BlockTree accepts the block and credits the receiver.
+// Run it with `cargo run -p lib_miner --example mine_a_block [difficulty]` (3 leading zeros by default).
+
+use lib_chain::block::{
+    BlockNode, BlockNodeHeader, BlockTree, MerkleTree, Puzzle, SignatureAlgorithm, Transaction,
+    Transactions,
+};
+use lib_miner::miner::Miner;
+use lib_tx_pool::pool::TxPool;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// The address credited with the mining reward of the block.
+const MINER_ADDRESS: &str =
+    "MDgCMQDZDExOs97sRTnQLYtgFjDKpDzmO7Uo5HPP62u6MDimXBpZtGxtwa8dhJe5NBIsJjUCAwEAAQ==";
+
+fn main() {
+    let difficulty: u16 = match std::env::args().nth(1) {
+        Some(arg) => arg
+            .parse()
+            .expect("The difficulty is a number of leading zeros"),
+        None => 3,
+    };
+
+    // A transaction signed by the wallet of the genesis receiver, which has the whole initial supply
+    let tx = Transaction {
+        sender: "MDgCMQCqrJ1yIJ7cDQIdTuS+4CkKn/tQPN7bZFbbGCBhvjQxs71f6Vu+sD9eh8JGpfiZSckCAwEAAQ=="
+            .to_string(),
+        receiver:
+            "MDgCMQDOpK8YWmcg8ffNF/O7xlBDq/DBdoUnc4yyWrV0y/X3LF+dddjaGksXzGl3tHskpgkCAwEAAQ=="
+                .to_string(),
+        message: "SEND $300   // By Alice   // 1678250102871".to_string(),
+        sig: "l8gsKxmAUzhgqbVqGlXaO69+Qhr87QthvZjUbYZXvnb+tanxCi8wm3c5UjHZ+HKm".to_string(),
+        sender_pub_key: None,
+        fee: None,
+        algorithm: SignatureAlgorithm::Rsa,
+        outputs: vec![],
+    };
+    let mut tx_pool = TxPool::new();
+    tx_pool
+        .try_add_tx(tx.clone())
+        .expect("The transaction is accepted");
+
+    // The block extends the working block with the transactions picked by the pool
+    let mut chain = BlockTree::new();
+    let transactions = tx_pool.filter_tx(16, &[]);
+    let (merkle_root, merkle_tree) = MerkleTree::create_merkle_tree(transactions.clone());
+    let puzzle = Puzzle {
+        parent: chain.working_block_id.clone(),
+        merkle_root: merkle_root.clone(),
+        reward_receiver: MINER_ADDRESS.to_string(),
+        miner_pub_key: None,
+        timestamp: None,
+    };
+
+    println!(
+        "Mining {} transaction(s) at difficulty {}...",
+        transactions.len(),
+        difficulty
+    );
+    let started = Instant::now();
+    let miner_p = Arc::new(Mutex::new(Miner::new()));
+    let solution = Miner::solve_puzzle(
+        miner_p.clone(),
+        serde_json::to_string(&puzzle).unwrap(),
+        16,
+        difficulty,
+        2,
+        0,
+        Arc::new(RwLock::new(false)),
+    )
+    .expect("Nothing cancels the puzzle");
+    println!(
+        "Solved in {:.2}s: nonce {}, block id {}",
+        started.elapsed().as_secs_f64(),
+        solution.nonce,
+        solution.hash
+    );
+    println!("Miner status: {:?}", miner_p.lock().unwrap().get_status());
+
+    let block = BlockNode {
+        header: BlockNodeHeader {
+            parent: puzzle.parent,
+            merkle_root,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64,
+            block_id: solution.hash,
+            nonce: solution.nonce,
+            reward_receiver: puzzle.reward_receiver,
+            miner_signature: None,
+        },
+        transactions_block: Transactions {
+            merkle_tree,
+            transactions,
+        },
+    };
+    chain
+        .add_block(block, difficulty)
+        .expect("The block is valid");
+    let balances = chain.get_working_balance_map();
+    println!(
+        "Working block {} at height {}",
+        chain.working_block_id,
+        chain.height_of(&chain.working_block_id).unwrap()
+    );
+    println!("Balance of the receiver: ${}", balances[&tx.receiver]);
+    println!("Balance of the miner: ${}", balances[MINER_ADDRESS]);
+}
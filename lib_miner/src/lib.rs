@@ -12,6 +12,7 @@ mod tests {
     use std::time::Duration;
     
     use crate::miner::{Miner, PuzzleSolution};
+    use lib_chain::hash::HashFunction;
     use sha2::{Sha256, Digest};
 
     // Testing the correctness. To start a multi-threaded and solve the puzzle.
@@ -78,8 +79,162 @@ mod tests {
     /// Your own additional test that tests your implementation more throughly (e.g. any performance issue in multi-threading)
     #[test]
     fn test_miner_additional() {
-        // Please fill in the blank
-        
+        // Each thread owns a distinct nonce prefix, however many threads there are
+        let prefixes: std::collections::HashSet<String> =
+            (0..100).map(|i| Miner::thread_nonce_prefix(i, 100, 16)).collect();
+        assert!(prefixes.len() == 100 && prefixes.iter().all(|prefix| prefix.len() == 2));
+        assert!(Miner::thread_nonce_prefix(3, 6, 16) == "D");
+        assert!(Miner::thread_nonce_prefix(27, 100, 1) == "B");
+
+        // With 3-letter nonces each thread only has 676 nonces to try, so it repeats itself quickly,
+        // but the threads never try the same nonce as each other
+        let miner_p = Arc::new(Mutex::new(Miner::new()));
+        let cancellation_token = Arc::new(RwLock::new(false));
+        let cancellation_token_cloned = cancellation_token.clone();
+        let _cancel_timer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(500));
+            *cancellation_token_cloned.write().unwrap() = true;
+        });
+        let solution = Miner::solve_puzzle(
+            miner_p.clone(),
+            "RANDOM_STRING_ANYTHING".to_owned(),
+            3, 12, 4, 43,
+            cancellation_token
+        );
+        _cancel_timer.join().unwrap();
+        assert!(solution.is_none());
+        let miner = miner_p.lock().unwrap();
+        assert!(miner.hash_count > 4 * 676);
+        assert!(miner.duplicate_nonce_count > 0);
+        assert!(miner.cross_thread_collision_count == 0);
+        assert!(miner.get_status().cross_thread_collision_count == 0);
+        assert!(miner.cancelled_count == 1 && miner.get_status().cancelled_count == 1);
+    }
+
+    /// Test solving a puzzle with the hash function of a Blake3 chain
+    #[test]
+    fn test_miner_blake3() {
+        let miner_p = Arc::new(Mutex::new(Miner { hash_function: HashFunction::Blake3, ..Miner::new() }));
+        let cancellation_token = Arc::new(RwLock::new(false));
+        let solution = Miner::solve_puzzle(
+            miner_p.clone(),
+            "RANDOM_STRING_ANYTHING".to_owned(),
+            16, 3, 2, 43,
+            cancellation_token
+        ).unwrap();
+
+        let PuzzleSolution { puzzle, nonce, hash } = solution;
+        assert!(hash.starts_with("000") && hash == HashFunction::Blake3.digest_hex((nonce + &puzzle).as_bytes()));
+        assert!(miner_p.lock().unwrap().get_status().hash_function == HashFunction::Blake3);
+    }
+
+    /// Test that checking the difficulty on the raw hash bytes agrees with checking the hex string
+    #[test]
+    fn test_miner_hash_meets_difficulty() {
+        for i in 0..2000u32 {
+            let hash = Sha256::digest(i.to_be_bytes());
+            let hex_hash = hex::encode(hash);
+            for leading_zero_len in 0..=5 {
+                assert!(Miner::hash_meets_difficulty(&hash, leading_zero_len)
+                    == hex_hash.starts_with(&"0".repeat(leading_zero_len)));
+            }
+        }
+        assert!(Miner::hash_meets_difficulty(&[0; 32], 64));
+        assert!(!Miner::hash_meets_difficulty(&[0; 32], 65));
+    }
+
+    /// Test that the hash rate limit holds across the threads, and that the threads paused by a lowered thread count
+    /// stop hashing until the count is raised again, in the middle of a puzzle.
+    #[test]
+    fn test_miner_throttle_and_thread_scaling() {
+        let miner_p = Arc::new(Mutex::new(Miner::new()));
+        miner_p.lock().unwrap().set_target_hashrate(Some(200));
+        assert!(miner_p.lock().unwrap().get_status().target_hashrate == Some(200));
+        let cancellation_token = Arc::new(RwLock::new(false));
+        let cancellation_token_cloned = cancellation_token.clone();
+        let _cancel_timer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(1000));
+            *cancellation_token_cloned.write().unwrap() = true;
+        });
+        let solution = Miner::solve_puzzle(miner_p.clone(), "RANDOM_STRING_ANYTHING".to_owned(), 16, 16, 2, 43, cancellation_token);
+        assert!(solution.is_none());
+        let hash_count = miner_p.lock().unwrap().hash_count;
+        assert!((100..=300).contains(&hash_count), "{} hashes in 1s at 200 H/s", hash_count);
+
+        // At 1 H/s per thread, a difficulty of 3 takes an hour, so the puzzle is not solved before the threads are paused
+        miner_p.lock().unwrap().set_target_hashrate(Some(1));
+        let cancellation_token = Arc::new(RwLock::new(false));
+        let (solution_sender, solution_receiver) = std::sync::mpsc::channel();
+        {
+            let miner_p = miner_p.clone();
+            let cancellation_token = cancellation_token.clone();
+            thread::spawn(move || {
+                let solution = Miner::solve_puzzle(miner_p, "RANDOM_STRING_ANYTHING".to_owned(), 16, 3, 2, 43, cancellation_token);
+                solution_sender.send(solution).unwrap();
+            });
+        }
+        thread::sleep(Duration::from_millis(100));
+        miner_p.lock().unwrap().is_running = true;
+        assert!(!miner_p.lock().unwrap().set_thread_count(0));
+        miner_p.lock().unwrap().set_target_hashrate(None);
+        assert!(solution_receiver.recv_timeout(Duration::from_millis(300)).is_err());
+        // only the first thread resumes, so it finds the solution; a third thread would wait for the next puzzle
+        assert!(!miner_p.lock().unwrap().set_thread_count(1));
+        let solution = solution_receiver.recv_timeout(Duration::from_secs(5)).unwrap().unwrap();
+        assert!(solution.nonce.starts_with(&Miner::thread_nonce_prefix(0, 2, 16)));
+        miner_p.lock().unwrap().is_running = true;
+        assert!(miner_p.lock().unwrap().set_thread_count(3));
+        assert!(miner_p.lock().unwrap().get_status().thread_count == 3);
+        *cancellation_token.write().unwrap() = true;
+    }
+
+    /// Benchmark of one hash attempt: building a fresh nonce String and hex-encoding every hash (as `solve_puzzle` used to)
+    /// against rewriting the nonce in place after a pre-hashed thread prefix and checking the raw bytes.
+    /// Run it with `cargo test --release -p lib_miner -- --ignored --nocapture bench_miner_attempt`.
+    #[test]
+    #[ignore]
+    fn bench_miner_attempt() {
+        use rand::{Rng, SeedableRng};
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+        const ATTEMPTS: u32 = 2_000_000;
+        let puzzle = serde_json::json!({"parent": "0".repeat(64), "merkle_root": "1".repeat(64), "reward_receiver": "A".repeat(88)}).to_string();
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(43);
+        let start = std::time::Instant::now();
+        let mut found = 0;
+        for _ in 0..ATTEMPTS {
+            let nonce: String = (0..16).map(|_| ALPHABET[rng.gen_range(0..ALPHABET.len())] as char).collect();
+            let mut hasher = Sha256::new();
+            hasher.update(nonce.as_bytes());
+            hasher.update(puzzle.as_bytes());
+            if hex::encode(hasher.finalize()).starts_with("0000") {
+                found += 1;
+            }
+        }
+        let before = start.elapsed();
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(43);
+        let start = std::time::Instant::now();
+        let mut found_after = 0;
+        let prefix_hasher = Sha256::new_with_prefix(b"A");
+        let mut nonce = [b'A'; 16];
+        for _ in 0..ATTEMPTS {
+            for byte in nonce[1..].iter_mut() {
+                *byte = ALPHABET[rng.gen_range(0..ALPHABET.len())];
+            }
+            let mut hasher = prefix_hasher.clone();
+            hasher.update(&nonce[1..]);
+            hasher.update(puzzle.as_bytes());
+            if Miner::hash_meets_difficulty(&hasher.finalize(), 4) {
+                found_after += 1;
+            }
+        }
+        let after = start.elapsed();
+
+        let rate = |elapsed: Duration| ATTEMPTS as f64 / elapsed.as_secs_f64();
+        println!("fresh String + hex: {:.0} H/s ({} found)", rate(before), found);
+        println!("in place + raw bytes: {:.0} H/s ({} found)", rate(after), found_after);
+        println!("speedup: {:.2}x", before.as_secs_f64() / after.as_secs_f64());
     }
 
 }
@@ -7,20 +7,64 @@
 // You can see detailed instructions in the comments below.
 // You can also look at the unit tests in ./lib.rs to understand the expected behavior of the miner.
 
-use hex;
-use rand::{
-    distributions::{Alphanumeric, DistString},
-    Rng, SeedableRng,
-};
-use rand_pcg::Pcg32;
-use sha2::{Digest, Sha256};
-use std::collections::BTreeMap;
+use lib_chain::hash::{HashAlgo, HashFunction};
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU16, AtomicU64, Ordering};
 use std::sync::mpsc::TryRecvError;
 use std::sync::{Arc, Mutex, RwLock};
-use std::thread::{JoinHandle, Thread};
-use std::time::Duration;
+use std::thread;
+use std::time::{Duration, Instant};
 use std::vec::Vec;
-use std::{convert, thread};
+
+/// The characters of the generated nonces.
+const NONCE_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+/// The number of attempts between two checks of the cancellation token by a mining thread.
+/// Reading the token on every attempt costs about as much as a hash when many threads share it.
+const CANCELLATION_CHECK_INTERVAL: u64 = 256;
+
+/// The number of nonces each thread remembers per puzzle to detect nonces tried twice.
+/// Only the first nonces are remembered, so that the check does not grow the memory or slow down a long search.
+pub const NONCE_SAMPLE_SIZE: usize = 4096;
+
+/// The number of attempts between two nonces checked for duplicates by a mining thread.
+/// Looking a nonce up in the sample costs about as much as a hash, so only one attempt in this many is checked.
+pub const NONCE_SAMPLE_INTERVAL: u64 = 16;
+
+/// How often (in ms) a mining thread paused by a lowered thread count checks whether it may resume.
+const PAUSED_THREAD_POLL_MS: u64 = 50;
+
+/// The longest sleep (in ms) of a throttled mining thread between two checks of the cancellation token.
+const THROTTLE_MAX_SLEEP_MS: u64 = 50;
+
+/// The mining parameters that can be changed while a puzzle is being solved, shared with the mining threads.
+#[derive(Debug, Default)]
+pub struct MinerControl {
+    /// the number of threads allowed to hash; the threads of the puzzle beyond it are paused
+    thread_count: AtomicU16,
+    /// the number of threads started for the puzzle being solved
+    spawned_thread_count: AtomicU16,
+    /// the max number of hashes per second of all the threads, 0 for no limit
+    target_hashrate: AtomicU64,
+}
+
+impl MinerControl {
+    /// The max number of hashes per second of one of the active threads, 0 for no limit.
+    fn thread_hashrate(&self) -> u64 {
+        let target_hashrate = self.target_hashrate.load(Ordering::Relaxed);
+        let active_thread_count = self
+            .thread_count
+            .load(Ordering::Relaxed)
+            .min(self.spawned_thread_count.load(Ordering::Relaxed))
+            .max(1);
+        match target_hashrate {
+            0 => 0,
+            rate => (rate / active_thread_count as u64).max(1),
+        }
+    }
+}
 
 // A miner that solve puzzles.
 pub struct Miner {
@@ -34,17 +78,66 @@ pub struct Miner {
 
     /// whether the miner is running or not
     pub is_running: bool,
+
+    /// number of hashes computed by all the threads, over all the puzzles
+    pub hash_count: u64,
+
+    /// number of nonces a thread tried more than once (among the checked and remembered ones, see `NONCE_SAMPLE_INTERVAL`
+    /// and `NONCE_SAMPLE_SIZE`)
+    pub duplicate_nonce_count: u64,
+
+    /// number of nonces tried by more than one thread (among the remembered ones).
+    /// It stays 0 unless the nonce is too short to give every thread its own prefix.
+    pub cross_thread_collision_count: u64,
+
+    /// hashes per second of all the threads on the last puzzle
+    pub last_hash_rate: u64,
+
+    /// number of puzzles cancelled before they were solved (e.g. because a block arrived for the same parent)
+    pub cancelled_count: u64,
+
+    /// the hash function of the puzzles, i.e. of the block ids of the chain (see `ConsensusParams::hash_function`)
+    pub hash_function: HashFunction,
+
+    /// the live thread count and hash rate limit, read by the mining threads (see `set_thread_count` and
+    /// `set_target_hashrate`)
+    pub control: Arc<MinerControl>,
 }
 
 type BlockId = String;
 
+/// The status of a miner (see `Miner::get_status`).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MinerStatus {
+    /// number of threads used to solve the puzzle in parallel
+    pub thread_count: u16,
+    /// max number of hashes per second of all the threads, if limited
+    pub target_hashrate: Option<u64>,
+    /// number of leading "0"s expected in the hash of a solution in hex format
+    pub difficulty: u16,
+    /// the hash function of the puzzles
+    pub hash_function: HashFunction,
+    /// whether the miner is running or not
+    pub is_running: bool,
+    /// number of hashes computed by all the threads, over all the puzzles
+    pub hash_count: u64,
+    /// number of nonces a thread tried more than once (among the checked ones)
+    pub duplicate_nonce_count: u64,
+    /// number of nonces tried by more than one thread
+    pub cross_thread_collision_count: u64,
+    /// hashes per second of all the threads on the last puzzle
+    pub hash_rate: u64,
+    /// number of puzzles cancelled before they were solved
+    pub cancelled_count: u64,
+}
+
 /// The struct to represent a puzzle solution returned by the miner.
 pub struct PuzzleSolution {
     /// the puzzle string
     pub puzzle: String,
     /// the nonce string that should be prepended to the puzzle string for computing the hash
     pub nonce: String,
-    /// the sha256 hash of (nonce || puzzle) in hex format
+    /// the hash of (nonce || puzzle) in hex format, with the hash function of the miner
     pub hash: BlockId,
 }
 
@@ -55,9 +148,63 @@ impl Miner {
             thread_count: 0,
             leading_zero_len: 0,
             is_running: false,
+            hash_count: 0,
+            duplicate_nonce_count: 0,
+            cross_thread_collision_count: 0,
+            last_hash_rate: 0,
+            cancelled_count: 0,
+            hash_function: HashFunction::Sha256,
+            control: Arc::new(MinerControl::default()),
         }
     }
 
+    /// Change the number of threads mining, including on the puzzle being solved: the threads beyond the new count pause
+    /// until it is raised again. A puzzle cannot start threads while it is solved, so return whether the new count is above
+    /// the threads started for the puzzle being solved; the extra threads only mine from the next puzzle.
+    pub fn set_thread_count(&mut self, thread_count: u16) -> bool {
+        self.thread_count = thread_count;
+        self.control
+            .thread_count
+            .store(thread_count, Ordering::Relaxed);
+        self.is_running && thread_count > self.control.spawned_thread_count.load(Ordering::Relaxed)
+    }
+
+    /// Limit the number of hashes per second of all the threads together, including on the puzzle being solved.
+    /// The threads sleep between hashes to stay under the limit. No limit if None.
+    pub fn set_target_hashrate(&mut self, target_hashrate: Option<u64>) {
+        self.control
+            .target_hashrate
+            .store(target_hashrate.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    /// Get the limit of hashes per second of all the threads together, if any.
+    pub fn get_target_hashrate(&self) -> Option<u64> {
+        match self.control.target_hashrate.load(Ordering::Relaxed) {
+            0 => None,
+            rate => Some(rate),
+        }
+    }
+
+    /// The nonce prefix owned by the given thread: the thread index written in base 26 with the letters of the nonce alphabet,
+    /// as long as needed to tell `thread_count` threads apart. The threads only try nonces starting with their own prefix,
+    /// so they never hash the same nonce twice between them, whatever their seeds.
+    /// The prefix is cut to `nonce_len`, in which case some threads share a prefix.
+    pub fn thread_nonce_prefix(thread_index: u16, thread_count: u16, nonce_len: usize) -> String {
+        let base = NONCE_ALPHABET.len() as u64;
+        let mut prefix_len = 1;
+        while base.pow(prefix_len as u32) < thread_count as u64 {
+            prefix_len += 1;
+        }
+        let mut index = thread_index as u64;
+        let mut prefix = vec![NONCE_ALPHABET[0]; prefix_len];
+        for digit in prefix.iter_mut().rev() {
+            *digit = NONCE_ALPHABET[(index % base) as usize];
+            index /= base;
+        }
+        prefix.truncate(nonce_len);
+        String::from_utf8(prefix).unwrap()
+    }
+
     /// The method to solve a puzzle with specified number of threads and difficulty levels.
     /// This method is a function on the class (without `self` as the 1st argument). The first parameter is a smart pointer to a miner instance.
     /// - `miner_p`: the smart pointer to the miner instance
@@ -65,7 +212,9 @@ impl Miner {
     /// - `nonce_len`: the length of the nonce string in the solution. The nonce string should be randomly generated from the alphanumeric characters A-Z, a-z and 0-9.
     /// - `leading_zero_len`: the number of leading "0"s expected in the resulting hash string in hex format.
     /// - `thread_count`: the number of threads to be used for solving the puzzle in parallel.
+    ///   It can be lowered (and raised back) while the puzzle is solved with `set_thread_count`.
     /// - `thread_0_seed`: the seed for the random number generator for the first thread. The seed for the second thread should be `thread_0_seed + 1`, and so on.
+    ///   The seeds only pick the nonces within a thread: each thread searches its own part of the nonces (see `thread_nonce_prefix`).
     /// - `cancellation_token`: a smart pointer to a boolean value. If the value is set to true, all threads should stop even if they have not found a solution.
     /// - return: an optional value with the solution if the puzzle is solved, or None if the puzzle is cancelled.
     pub fn solve_puzzle(
@@ -83,67 +232,92 @@ impl Miner {
         // If any of the threads finds a solution, other threads should stop.
         // Additionally, if the cancellation_token is set to true, all threads should stop.
         // The purpose of the cancellation_token is to allow the miner to stop the computation when other nodes have already solved the exact same puzzle.
-        // calculate the target hash string
-        let target = "0".repeat(leading_zero_len as usize);
-
         // create a channel to communicate the solution back to the main thread
         let (sender, receiver) = std::sync::mpsc::channel();
 
-        // spawn multiple threads
+        // spawn multiple threads, each searching the nonces starting with its own prefix
+        let started = Instant::now();
+        let (control, hash_function) = {
+            let miner = miner_p.lock().unwrap();
+            (miner.control.clone(), miner.hash_function)
+        };
+        control.thread_count.store(thread_count, Ordering::Relaxed);
+        control
+            .spawned_thread_count
+            .store(thread_count, Ordering::Relaxed);
         let mut threads = Vec::new();
-        let mut found_solution = false;
         for i in 0..thread_count {
             let thread_seed = thread_0_seed + i as u64;
-            let miner_p = Arc::clone(&miner_p);
             let sender = sender.clone();
             let cancellation_token = Arc::clone(&cancellation_token);
-            let target = target.clone();
+            let control = Arc::clone(&control);
             let puzzle = puzzle.clone();
             let nonce_len = nonce_len as usize;
+            let leading_zero_len = leading_zero_len as usize;
+            let prefix = Miner::thread_nonce_prefix(i, thread_count, nonce_len);
 
             threads.push(thread::spawn(move || {
                 let mut rng = rand::rngs::StdRng::seed_from_u64(thread_seed);
+                let mut stats = NonceStats::default();
+                // The prefix is hashed once, and the nonce is rewritten in place: an attempt allocates nothing
+                let mut prefix_hasher = hash_function.hasher();
+                prefix_hasher.update(prefix.as_bytes());
+                let mut nonce = prefix.into_bytes();
+                let suffix_start = nonce.len();
+                nonce.resize(nonce_len, NONCE_ALPHABET[0]);
+                let puzzle_bytes = puzzle.as_bytes();
+                let mut pacer = Pacer::new();
 
-                loop {
-                    // check if the puzzle is cancelled
-                    if *cancellation_token.read().unwrap() {
-                        println!("Thread {} cancelled", i);
-                        break;
+                for attempt in 0u64.. {
+                    // check if the puzzle is cancelled, the thread paused or the hash rate limited
+                    // (a throttled thread checks on every attempt, so that it never sleeps through a cancellation)
+                    let thread_hashrate = control.thread_hashrate();
+                    if attempt % CANCELLATION_CHECK_INTERVAL == 0 || thread_hashrate > 0 {
+                        let is_cancelled = || *cancellation_token.read().unwrap();
+                        pacer.pace(&control, is_cancelled);
+                        while i >= control.thread_count.load(Ordering::Relaxed) && !is_cancelled() {
+                            thread::sleep(Duration::from_millis(PAUSED_THREAD_POLL_MS));
+                            pacer.restart();
+                        }
+                        if is_cancelled() {
+                            eprintln!("[Miner] Thread {} cancelled", i);
+                            break;
+                        }
                     }
 
-                    // generate a random nonce string
-                    let nonce: String = (0..nonce_len)
-                        .map(|_| rng.gen_range(b'A'..=b'Z') as char)
-                        .collect();
+                    // generate a random nonce after the prefix of the thread
+                    for byte in nonce[suffix_start..].iter_mut() {
+                        *byte = NONCE_ALPHABET[rng.gen_range(0..NONCE_ALPHABET.len())];
+                    }
+                    stats.record(&nonce);
 
                     // compute the hash of (nonce || puzzle)
-                    let mut hasher = Sha256::new();
-                    hasher.update(nonce.as_bytes());
-                    hasher.update(puzzle.as_bytes());
+                    let mut hasher = prefix_hasher.clone();
+                    hasher.update(&nonce[suffix_start..]);
+                    hasher.update(puzzle_bytes);
                     let hash_bytes = hasher.finalize();
-                    let hash = hex::encode(&hash_bytes);
 
-                    // check if the hash meets the target
-                    if hash.starts_with(&target) {
-                        found_solution = true;
+                    // check if the hash meets the target, and only then encode it
+                    if Miner::hash_meets_difficulty(&hash_bytes, leading_zero_len) {
                         // send the solution back to the main thread
                         sender
                             .send(PuzzleSolution {
-                                puzzle: puzzle,
-                                nonce: nonce,
-                                hash: hash,
+                                nonce: String::from_utf8(nonce).unwrap(),
+                                hash: hex::encode(hash_bytes),
+                                puzzle,
                             })
                             .unwrap();
                         break;
                     }
                 }
+                stats
             }));
         }
 
         // wait for a solution or cancellation
         let solution = loop {
             if *cancellation_token.read().unwrap() {
-                println!("Puzzle cancelled, no solution found");
+                eprintln!("[Miner] Puzzle cancelled, no solution found");
                 break None;
             }
 
@@ -156,11 +330,6 @@ impl Miner {
                     // cancel all threads
                     *cancellation_token.write().unwrap() = true;
 
-                    // join all threads
-                    for thread in threads {
-                        thread.join().unwrap();
-                    }
-
                     break Some(solution);
                 }
                 Err(TryRecvError::Empty) => {
@@ -173,19 +342,147 @@ impl Miner {
             }
         };
 
+        // join all threads (they all stop on the cancellation token) and count their work
+        let stats: Vec<NonceStats> = threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect();
+        let mut miner = miner_p.lock().unwrap();
+        miner.record_nonce_stats(&stats, started.elapsed());
+        if solution.is_none() {
+            miner.cancelled_count += 1;
+        }
+        drop(miner);
+
         solution
     }
 
+    /// Add the work of the threads on one puzzle to the counters.
+    fn record_nonce_stats(&mut self, stats: &[NonceStats], elapsed: Duration) {
+        let hashes: u64 = stats.iter().map(|stats| stats.hashes).sum();
+        self.hash_count += hashes;
+        self.duplicate_nonce_count += stats.iter().map(|stats| stats.duplicates).sum::<u64>();
+        let mut seen: HashSet<&[u8]> = HashSet::new();
+        for stats in stats {
+            for nonce in stats.sample.iter() {
+                if !seen.insert(nonce) {
+                    self.cross_thread_collision_count += 1;
+                }
+            }
+        }
+        self.last_hash_rate = (hashes as f64 / elapsed.as_secs_f64().max(0.001)) as u64;
+    }
+
+    /// Whether the hash starts with `leading_zero_len` "0"s in hex format, checked on the raw hash bytes.
+    pub fn hash_meets_difficulty(hash: &[u8], leading_zero_len: usize) -> bool {
+        let (zero_bytes, half_byte) = (leading_zero_len / 2, leading_zero_len % 2 == 1);
+        if hash.len() < zero_bytes + usize::from(half_byte) {
+            return false;
+        }
+        hash[..zero_bytes].iter().all(|byte| *byte == 0)
+            && (!half_byte || hash[zero_bytes] >> 4 == 0)
+    }
+
     /// Get status information of the miner for debug printing.
-    pub fn get_status(&self) -> BTreeMap<String, String> {
+    pub fn get_status(&self) -> MinerStatus {
         // Please fill in the blank
         // For debugging purpose, you can return any dictionary of strings as the status of the miner.
         // It should be displayed in the Client UI eventually.
         //todo!();
-        let mut status = BTreeMap::new();
-        status.insert("#thread".to_string(), self.thread_count.to_string());
-        status.insert("difficulty".to_string(), self.leading_zero_len.to_string());
-        status.insert("is_running".to_string(), self.is_running.to_string());
-        status
+        MinerStatus {
+            thread_count: self.thread_count,
+            target_hashrate: self.get_target_hashrate(),
+            difficulty: self.leading_zero_len,
+            hash_function: self.hash_function,
+            is_running: self.is_running,
+            hash_count: self.hash_count,
+            duplicate_nonce_count: self.duplicate_nonce_count,
+            cross_thread_collision_count: self.cross_thread_collision_count,
+            hash_rate: self.last_hash_rate,
+            cancelled_count: self.cancelled_count,
+        }
+    }
+}
+
+/// The pace of one throttled thread: it sleeps whenever it is ahead of its hash rate since the rate was set.
+struct Pacer {
+    /// when the current rate was set (or the thread resumed)
+    since: Instant,
+    /// the number of hashes computed since then
+    hashes: u64,
+    /// the max number of hashes per second, 0 for no limit
+    hashrate: u64,
+}
+
+impl Pacer {
+    fn new() -> Pacer {
+        Pacer {
+            since: Instant::now(),
+            hashes: 0,
+            hashrate: 0,
+        }
+    }
+
+    /// Start counting again, e.g. after a pause, so that the time paused is not made up for by hashing faster.
+    fn restart(&mut self) {
+        self.since = Instant::now();
+        self.hashes = 0;
+    }
+
+    /// Count the hash about to be computed, sleeping first while it is ahead of the hash rate of the thread.
+    /// The sleep is cut into slices of `THROTTLE_MAX_SLEEP_MS`, and stops if the puzzle is cancelled or the rate changes.
+    fn pace(&mut self, control: &MinerControl, is_cancelled: impl Fn() -> bool) {
+        let hashrate = control.thread_hashrate();
+        if hashrate != self.hashrate {
+            self.hashrate = hashrate;
+            self.restart();
+        }
+        if hashrate == 0 {
+            return;
+        }
+        let due = Duration::from_secs_f64(self.hashes as f64 / hashrate as f64);
+        self.hashes += 1;
+        while let Some(early) = due.checked_sub(self.since.elapsed()) {
+            if early.is_zero() || is_cancelled() || control.thread_hashrate() != hashrate {
+                break;
+            }
+            thread::sleep(early.min(Duration::from_millis(THROTTLE_MAX_SLEEP_MS)));
+        }
+    }
+}
+
+/// The work of one thread on one puzzle.
+#[derive(Default)]
+struct NonceStats {
+    /// the number of hashes computed
+    hashes: u64,
+    /// the number of nonces tried again (among the checked ones)
+    duplicates: u64,
+    /// the first `NONCE_SAMPLE_SIZE` distinct nonces checked
+    sample: HashSet<Vec<u8>>,
+    /// the number of attempts left before the next nonce is checked
+    until_check: u64,
+}
+
+impl NonceStats {
+    /// Count a nonce about to be hashed, and check one in `NONCE_SAMPLE_INTERVAL` against the sample.
+    fn record(&mut self, nonce: &[u8]) {
+        self.hashes += 1;
+        if self.until_check > 0 {
+            self.until_check -= 1;
+            return;
+        }
+        self.until_check = NONCE_SAMPLE_INTERVAL - 1;
+        if self.sample.contains(nonce) {
+            self.duplicates += 1;
+        } else if self.sample.len() < NONCE_SAMPLE_SIZE {
+            self.sample.insert(nonce.to_vec());
+        }
+    }
+}
+
+impl Default for Miner {
+    fn default() -> Self {
+        Self::new()
     }
 }
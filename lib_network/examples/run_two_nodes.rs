@@ -0,0 +1,88 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// This example runs two P2PNetwork nodes on this machine and gossips a block and a transaction between them, the way
+// bin_nakamoto uses the channels returned by `P2PNetwork::create`: what a node sends on its outgoing channels is
+// broadcast to the peers it dialed, and what its peers send arrives on its incoming channels. Node B dials node A, its
+// only neighbor, so both items go from node B to node A.
+// Run it with `cargo run -p lib_network --example run_two_nodes [port_a] [port_b]` (9400 and 9401 by default).
+
+use lib_chain::block::{BlockNode, BlockNodeHeader, MerkleTree, Transaction, Transactions};
+use lib_network::netchannel::NetAddress;
+use lib_network::p2pnetwork::P2PNetwork;
+use std::thread;
+use std::time::Duration;
+
+/// How long a node waits for an item gossiped by the other one.
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn main() {
+    let port = |index: usize, default: i32| -> i32 {
+        std::env::args()
+            .nth(index)
+            .map_or(default, |arg| arg.parse().expect("A port is a number"))
+    };
+    let address_a = NetAddress::new("127.0.0.1".to_string(), port(1, 9400));
+    let address_b = NetAddress::new("127.0.0.1".to_string(), port(2, 9401));
+
+    let (network_a, block_in_a, tx_in_a, _block_out_a, _tx_out_a, _req_a) =
+        P2PNetwork::create(address_a.clone(), vec![]);
+    let (network_b, block_in_b, _tx_in_b, block_out_b, tx_out_b, _req_b) =
+        P2PNetwork::create(address_b, vec![address_a]);
+    // Give node B the time to complete its handshake with node A
+    thread::sleep(Duration::from_millis(300));
+
+    // The network does not validate what it relays: checking blocks and transactions is up to the node
+    let block = BlockNode {
+        header: BlockNodeHeader {
+            parent: "0".to_string(),
+            merkle_root: MerkleTree::create_merkle_tree(vec![]).0,
+            timestamp: 1,
+            block_id: "example_block".to_string(),
+            nonce: "0".to_string(),
+            reward_receiver: "B".to_string(),
+            miner_signature: None,
+        },
+        transactions_block: Transactions {
+            merkle_tree: MerkleTree { hashes: vec![] },
+            transactions: vec![],
+        },
+    };
+    block_out_b.send(block).unwrap();
+    let received = block_in_a
+        .recv_timeout(RECEIVE_TIMEOUT)
+        .expect("Node A receives the block of node B");
+    println!(
+        "Node A received block {} from {}",
+        received.header.block_id,
+        network_a
+            .lock()
+            .unwrap()
+            .get_block_source(&received.header.block_id)
+            .unwrap_or_default()
+    );
+    // A node never gets back what it sent
+    assert!(block_in_b.recv_timeout(Duration::from_millis(200)).is_err());
+
+    let tx = Transaction::new(
+        "A".to_string(),
+        "B".to_string(),
+        "SEND $1   // By A   // 1".to_string(),
+        String::new(),
+    );
+    tx_out_b.send(tx).unwrap();
+    let received = tx_in_a
+        .recv_timeout(RECEIVE_TIMEOUT)
+        .expect("Node A receives the transaction of node B");
+    println!("Node A received transaction {:?}", received.message);
+
+    println!(
+        "Status of node A: {:?}",
+        network_a.lock().unwrap().get_status()
+    );
+    println!(
+        "Status of node B: {:?}",
+        network_b.lock().unwrap().get_status()
+    );
+}
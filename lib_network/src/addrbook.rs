@@ -0,0 +1,231 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// This file implements the address book of a P2PNetwork: the listening addresses of the peers this node has heard of,
+// from its neighbors list, from the handshakes of the peers that connect to it, and from the `Addr` messages of its peers.
+// Each address records when its node was last seen completing a handshake and how many dials to it failed in a row, so
+// that the addresses shared with other peers are the live ones, and a dead address is dialed less and less often until
+// it is forgotten.
+// The records can be saved to a json file (e.g. `peers.json`) and loaded when the node restarts, so that it can reach
+// the peers it knew even if its neighbors are down.
+
+use crate::identity::NodeId;
+use crate::netchannel::NetAddress;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// The maximum number of addresses kept in the address book. The least useful one is forgotten to make room.
+pub const MAX_KNOWN_ADDRESSES: usize = 1000;
+
+/// The maximum number of addresses sent in (or read from) one `Addr` message.
+pub const MAX_ADDR_LEN: usize = 100;
+
+/// The number of failed dials in a row after which an address that never completed a handshake is forgotten.
+const MAX_FAILURES_UNSEEN: u32 = 3;
+
+/// The number of failed dials in a row after which any address is forgotten.
+const MAX_FAILURES: u32 = 10;
+
+/// The delay (in ms) before dialing an address again after its first failure; it doubles after each further failure.
+const RETRY_MIN_DELAY_MS: u64 = 1000;
+
+/// The longest delay (in ms) before dialing a failing address again.
+const RETRY_MAX_DELAY_MS: u64 = 60_000;
+
+/// What this node knows about the listening address of one peer.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PeerRecord {
+    /// The listening address of the peer
+    pub address: NetAddress,
+    /// When the peer last completed a handshake with this node (ms since the UNIX epoch), if ever
+    pub last_seen_ms: Option<u64>,
+    /// When this node last dialed the address (ms since the UNIX epoch), if ever
+    pub last_attempt_ms: Option<u64>,
+    /// The number of failed dials since the peer was last seen
+    pub failures: u32,
+    /// The node id the peer proved in its last handshake, if it was seen
+    #[serde(default)]
+    pub node_id: Option<NodeId>,
+    /// The score of the peer (see `P2PNetwork::peer_scores`) when the records were taken; better peers are dialed first
+    #[serde(default)]
+    pub score: i64,
+}
+
+/// The listening addresses of the known peers, by "ip:port".
+#[derive(Debug, Clone, Default)]
+pub struct AddressBook {
+    records: BTreeMap<String, PeerRecord>,
+}
+
+/// The "ip:port" name of an address.
+fn name_of(address: &NetAddress) -> String {
+    format!("{}:{}", address.ip, address.port)
+}
+
+impl AddressBook {
+    /// Add an address heard of (e.g. from an `Addr` message). Return whether it is new.
+    pub fn add(&mut self, address: &NetAddress) -> bool {
+        let name = name_of(address);
+        if self.records.contains_key(&name) {
+            return false;
+        }
+        if self.records.len() >= MAX_KNOWN_ADDRESSES {
+            self.evict_one();
+        }
+        self.records.insert(
+            name,
+            PeerRecord {
+                address: address.clone(),
+                last_seen_ms: None,
+                last_attempt_ms: None,
+                failures: 0,
+                node_id: None,
+                score: 0,
+            },
+        );
+        true
+    }
+
+    /// Record that the peer listening at the address completed a handshake, proving the given node id.
+    pub fn mark_seen(&mut self, address: &NetAddress, node_id: &str, now_ms: u64) {
+        self.add(address);
+        if let Some(record) = self.records.get_mut(&name_of(address)) {
+            record.last_seen_ms = Some(now_ms);
+            record.failures = 0;
+            record.node_id = Some(node_id.to_string());
+        }
+    }
+
+    /// Add a record loaded from a file (see `load_peers`), unless its address is already known. Return whether it is new.
+    pub fn restore(&mut self, record: PeerRecord) -> bool {
+        if !self.add(&record.address) {
+            return false;
+        }
+        self.records.insert(name_of(&record.address), record);
+        true
+    }
+
+    /// Record that the address is being dialed.
+    pub fn mark_attempt(&mut self, address: &NetAddress, now_ms: u64) {
+        self.add(address);
+        if let Some(record) = self.records.get_mut(&name_of(address)) {
+            record.last_attempt_ms = Some(now_ms);
+        }
+    }
+
+    /// Record that dialing the address failed, forgetting it after too many failures in a row.
+    pub fn mark_failed(&mut self, address: &NetAddress) {
+        let name = name_of(address);
+        let Some(record) = self.records.get_mut(&name) else {
+            return;
+        };
+        record.failures += 1;
+        let max_failures = match record.last_seen_ms {
+            Some(_) => MAX_FAILURES,
+            None => MAX_FAILURES_UNSEEN,
+        };
+        if record.failures >= max_failures {
+            self.records.remove(&name);
+        }
+    }
+
+    /// Whether the peer listening at the address completed a handshake at or after the given time.
+    pub fn was_seen_since(&self, address: &NetAddress, since_ms: u64) -> bool {
+        self.records
+            .get(&name_of(address))
+            .and_then(|record| record.last_seen_ms)
+            .is_some_and(|seen_ms| seen_ms >= since_ms)
+    }
+
+    /// Get the records of the addresses worth dialing now, the most recently seen first: the ones not in `exclude`
+    /// (by "ip:port") whose last failure is older than their retry delay.
+    pub fn dial_candidates(&self, exclude: &dyn Fn(&str) -> bool, now_ms: u64) -> Vec<PeerRecord> {
+        let mut candidates: Vec<&PeerRecord> = self
+            .records
+            .iter()
+            .filter(|(name, record)| {
+                let retry_delay_ms = match record.failures {
+                    0 => 0,
+                    failures => {
+                        (RETRY_MIN_DELAY_MS << (failures - 1).min(16)).min(RETRY_MAX_DELAY_MS)
+                    }
+                };
+                !exclude(name)
+                    && record
+                        .last_attempt_ms
+                        .is_none_or(|attempt_ms| now_ms >= attempt_ms + retry_delay_ms)
+            })
+            .map(|(_, record)| record)
+            .collect();
+        candidates.sort_by_key(|record| std::cmp::Reverse(record.last_seen_ms));
+        candidates.into_iter().cloned().collect()
+    }
+
+    /// Get the addresses to share with a peer in an `Addr` message: the ones seen alive, the most recently seen first.
+    pub fn live_addresses(&self) -> Vec<NetAddress> {
+        let mut live: Vec<&PeerRecord> = self
+            .records
+            .values()
+            .filter(|record| record.last_seen_ms.is_some() && record.failures == 0)
+            .collect();
+        live.sort_by_key(|record| std::cmp::Reverse(record.last_seen_ms));
+        live.into_iter()
+            .take(MAX_ADDR_LEN)
+            .map(|record| record.address.clone())
+            .collect()
+    }
+
+    /// Get the record of every known address, by "ip:port".
+    pub fn records(&self) -> Vec<PeerRecord> {
+        self.records.values().cloned().collect()
+    }
+
+    /// The number of known addresses.
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Whether no address is known.
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Forget the least useful address: the one with the most failures, and among them the one seen the longest ago.
+    fn evict_one(&mut self) {
+        let evicted = self
+            .records
+            .iter()
+            .max_by_key(|(_, record)| {
+                (
+                    record.failures,
+                    std::cmp::Reverse(record.last_seen_ms.unwrap_or(0)),
+                )
+            })
+            .map(|(name, _)| name.clone());
+        if let Some(name) = evicted {
+            self.records.remove(&name);
+        }
+    }
+}
+
+/// Write the records of an address book to the given json file, replacing it.
+/// The file is written next to it first and then renamed, so that a crash never leaves it half written.
+pub fn save_peers(path: &str, records: &[PeerRecord]) -> Result<(), String> {
+    let tmp_path = format!("{}.tmp", path);
+    fs::write(&tmp_path, serde_json::to_string_pretty(records).unwrap())
+        .map_err(|e| format!("Cannot write peers {}: {}", tmp_path, e))?;
+    fs::rename(&tmp_path, path).map_err(|e| format!("Cannot write peers {}: {}", path, e))
+}
+
+/// Read the records written by `save_peers`. A missing file (e.g. on first run) has no records.
+pub fn load_peers(path: &str) -> Result<Vec<PeerRecord>, String> {
+    if !Path::new(path).exists() {
+        return Ok(Vec::new());
+    }
+    let json =
+        fs::read_to_string(path).map_err(|e| format!("Cannot read peers {}: {}", path, e))?;
+    serde_json::from_str(&json).map_err(|e| format!("Cannot parse peers {}: {}", path, e))
+}
@@ -0,0 +1,190 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// This file implements the connection manager of a P2PNetwork: the health of the connection to each peer, whether it is
+// connected, being dialed, or waiting before the next dial, when it was last heard from, and how often its connection was
+// lost. The neighbors are dialed again after a lost connection or a failed dial, with a delay that doubles after each
+// short-lived connection or failed dial (see `ConnectionManager::backoff`). The health of the peers is shown in the
+// status of the network, so that a flapping or unreachable neighbor can be told from a quiet one.
+
+use crate::netchannel::IDLE_TIMEOUT_MS;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// How long (in ms) the node waits before dialing a neighbor again after losing the connection to it.
+pub const RECONNECT_MIN_DELAY_MS: u64 = 500;
+
+/// The longest wait (in ms) between two attempts to dial a neighbor that cannot be connected.
+pub const RECONNECT_MAX_DELAY_MS: u64 = 30_000;
+
+/// The state of the connection to a peer.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerState {
+    /// The peer is being dialed, or dialed and not done with the handshake yet
+    Connecting,
+    /// At least one connection to the peer completed the handshake and is open
+    Connected,
+    /// The peer is dialed again at the given time (ms since the UNIX epoch)
+    Backoff(u64),
+    /// No connection to the peer is open, and it is not dialed again by this node
+    Disconnected,
+}
+
+/// The health of the connection to one peer.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PeerHealth {
+    /// The state of the connection
+    pub state: PeerState,
+    /// The number of open connections to the peer that completed the handshake (it may dial this node while dialed)
+    pub open_connections: u32,
+    /// When the current connection completed its handshake (ms since the UNIX epoch), if connected
+    pub connected_since_ms: Option<u64>,
+    /// When a message of the peer was last received (ms since the UNIX epoch), if ever
+    pub last_seen_ms: Option<u64>,
+    /// The number of handshakes completed after the first one
+    pub reconnects: u64,
+    /// The number of failed dials since the last handshake
+    pub failures: u32,
+    /// The delay (in ms) before the next dial of the peer, if its connection is lost
+    pub next_delay_ms: u64,
+}
+
+impl Default for PeerHealth {
+    fn default() -> Self {
+        PeerHealth {
+            state: PeerState::Disconnected,
+            open_connections: 0,
+            connected_since_ms: None,
+            last_seen_ms: None,
+            reconnects: 0,
+            failures: 0,
+            next_delay_ms: RECONNECT_MIN_DELAY_MS,
+        }
+    }
+}
+
+impl PeerHealth {
+    /// A short description of the health, e.g. "connected 12.0s, seen 0.4s ago, 1 reconnects".
+    pub fn describe(&self, now_ms: u64) -> String {
+        let secs = |ms: u64| format!("{:.1}s", ms as f64 / 1000.0);
+        let mut parts = vec![match self.state {
+            PeerState::Connecting => "connecting".to_string(),
+            PeerState::Connected => format!(
+                "connected {}",
+                secs(now_ms.saturating_sub(self.connected_since_ms.unwrap_or(now_ms)))
+            ),
+            PeerState::Backoff(until_ms) => {
+                format!("retry in {}", secs(until_ms.saturating_sub(now_ms)))
+            }
+            PeerState::Disconnected => "disconnected".to_string(),
+        }];
+        if let Some(last_seen_ms) = self.last_seen_ms {
+            parts.push(format!(
+                "seen {} ago",
+                secs(now_ms.saturating_sub(last_seen_ms))
+            ));
+        }
+        if self.reconnects > 0 {
+            parts.push(format!("{} reconnects", self.reconnects));
+        }
+        if self.failures > 0 {
+            parts.push(format!("{} failed dials", self.failures));
+        }
+        parts.join(", ")
+    }
+}
+
+/// The health of the connections, by peer (as "ip:port" of its connection, see `P2PNetwork::connections`).
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionManager {
+    peers: BTreeMap<String, PeerHealth>,
+}
+
+impl ConnectionManager {
+    /// Record that the peer is being dialed.
+    pub fn dialing(&mut self, peer: &str) {
+        let health = self.peers.entry(peer.to_string()).or_default();
+        if health.open_connections == 0 {
+            health.state = PeerState::Connecting;
+        }
+    }
+
+    /// Record that a dial of the peer failed.
+    pub fn dial_failed(&mut self, peer: &str) {
+        let health = self.peers.entry(peer.to_string()).or_default();
+        health.failures += 1;
+        if health.open_connections == 0 {
+            health.state = PeerState::Disconnected;
+        }
+    }
+
+    /// Record that a connection to the peer completed the handshake at the given time.
+    pub fn connected(&mut self, peer: &str, now_ms: u64) {
+        let health = self.peers.entry(peer.to_string()).or_default();
+        if health.open_connections == 0 {
+            if health.last_seen_ms.is_some() {
+                health.reconnects += 1;
+            }
+            health.connected_since_ms = Some(now_ms);
+        }
+        health.open_connections += 1;
+        health.state = PeerState::Connected;
+        health.last_seen_ms = Some(now_ms);
+        health.failures = 0;
+    }
+
+    /// Record that a message of the peer was received at the given time.
+    pub fn seen(&mut self, peer: &str, now_ms: u64) {
+        if let Some(health) = self.peers.get_mut(peer) {
+            health.last_seen_ms = Some(now_ms);
+        }
+    }
+
+    /// Record that a connection to the peer that completed the handshake was lost at the given time.
+    /// A connection that lasted `IDLE_TIMEOUT_MS` or more resets the delay before the next dial.
+    pub fn disconnected(&mut self, peer: &str, now_ms: u64) {
+        let Some(health) = self.peers.get_mut(peer) else {
+            return;
+        };
+        health.open_connections = health.open_connections.saturating_sub(1);
+        if health.open_connections > 0 {
+            return;
+        }
+        if health
+            .connected_since_ms
+            .is_some_and(|since_ms| now_ms.saturating_sub(since_ms) >= IDLE_TIMEOUT_MS)
+        {
+            health.next_delay_ms = RECONNECT_MIN_DELAY_MS;
+        }
+        health.connected_since_ms = None;
+        health.state = PeerState::Disconnected;
+    }
+
+    /// Schedule the next dial of the peer after the given time, and return the delay (in ms) to wait before it.
+    /// The delay doubles after each call, up to `RECONNECT_MAX_DELAY_MS`, until a long enough connection resets it.
+    pub fn backoff(&mut self, peer: &str, now_ms: u64) -> u64 {
+        let health = self.peers.entry(peer.to_string()).or_default();
+        let delay_ms = health.next_delay_ms;
+        health.next_delay_ms = (delay_ms * 2).min(RECONNECT_MAX_DELAY_MS);
+        if health.open_connections == 0 {
+            health.state = PeerState::Backoff(now_ms + delay_ms);
+        }
+        delay_ms
+    }
+
+    /// Forget the health of the peer, e.g. of a connection from an ephemeral port that was closed.
+    pub fn forget(&mut self, peer: &str) {
+        self.peers.remove(peer);
+    }
+
+    /// The health of the given peer, if it was ever dialed or connected.
+    pub fn get(&self, peer: &str) -> Option<&PeerHealth> {
+        self.peers.get(peer)
+    }
+
+    /// The health of all peers ever dialed or connected, by peer.
+    pub fn peers(&self) -> impl Iterator<Item = (&String, &PeerHealth)> {
+        self.peers.iter()
+    }
+}
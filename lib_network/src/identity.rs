@@ -0,0 +1,105 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// This file implements the NodeIdentity struct, the keypair that identifies a node in the P2P network.
+// It is separate from the wallet: it authenticates the node in handshakes and (optionally) signs its messages,
+// and it never signs transactions.
+
+use base64ct::{Base64, Encoding, LineEnding};
+use rsa::pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey, EncodeRsaPrivateKey, EncodeRsaPublicKey};
+use rsa::pkcs1v15::{SigningKey, VerifyingKey};
+use rsa::signature::{Signature, Signer, Verifier};
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+
+/// The id of a node: the sha256 hash of its public key in hex format.
+pub type NodeId = String;
+
+/// The size of the node keys. Node keys only authenticate peers in the demo network, so they are smaller than wallet keys.
+pub const NODE_KEY_BITS: usize = 1024;
+
+/// The number of hex characters of a node id shown in status displays.
+const SHORT_NODE_ID_LEN: usize = 8;
+
+/// The keypair of a node, serialized as a json file so that the node keeps its id across restarts.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct NodeIdentity {
+    /// The private key in PEM format
+    pub priv_key_pem: String,
+    /// The public key in PEM format
+    pub pub_key_pem: String,
+}
+
+impl NodeIdentity {
+    /// Generate a new node keypair.
+    pub fn generate() -> NodeIdentity {
+        let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), NODE_KEY_BITS).unwrap();
+        let public_key = RsaPublicKey::from(&private_key);
+        NodeIdentity {
+            priv_key_pem: private_key
+                .to_pkcs1_pem(LineEnding::default())
+                .unwrap()
+                .to_string(),
+            pub_key_pem: public_key.to_pkcs1_pem(LineEnding::default()).unwrap(),
+        }
+    }
+
+    /// Load the node keypair from the given json file, or generate it and write it there on first run.
+    pub fn load_or_generate(path: &str) -> Result<NodeIdentity, String> {
+        if Path::new(path).exists() {
+            let json = fs::read_to_string(path)
+                .map_err(|e| format!("Cannot read node key {}: {}", path, e))?;
+            return serde_json::from_str(&json)
+                .map_err(|e| format!("Cannot parse node key {}: {}", path, e));
+        }
+        let identity = NodeIdentity::generate();
+        fs::write(path, serde_json::to_string_pretty(&identity).unwrap())
+            .map_err(|e| format!("Cannot write node key {}: {}", path, e))?;
+        Ok(identity)
+    }
+
+    /// Get the id of this node.
+    pub fn node_id(&self) -> NodeId {
+        node_id_of(&self.pub_key_pem)
+    }
+
+    /// Sign a message using the private key and return the signature as a Base64 encoded string.
+    pub fn sign(&self, message: &str) -> String {
+        let private_key = RsaPrivateKey::from_pkcs1_pem(&self.priv_key_pem).unwrap();
+        let signer = SigningKey::<Sha256>::new(private_key);
+        Base64::encode_string(signer.sign(message.as_bytes()).as_ref())
+    }
+}
+
+/// Derive the node id from a public key in PEM format.
+pub fn node_id_of(pub_key_pem: &str) -> NodeId {
+    hex::encode(Sha256::digest(pub_key_pem.as_bytes()))
+}
+
+/// Get the short form of a node id for display.
+pub fn short_node_id(node_id: &str) -> &str {
+    &node_id[..node_id.len().min(SHORT_NODE_ID_LEN)]
+}
+
+/// Verify a Base64 encoded signature of a message against a public key in PEM format.
+/// Malformed keys or signatures fail the verification.
+pub fn verify_signature(pub_key_pem: &str, message: &str, signature64: &str) -> bool {
+    let public_key = match RsaPublicKey::from_pkcs1_pem(pub_key_pem) {
+        Ok(public_key) => public_key,
+        Err(_) => return false,
+    };
+    let signature = match Base64::decode_vec(signature64)
+        .ok()
+        .and_then(|bytes| Signature::from_bytes(&bytes).ok())
+    {
+        Some(signature) => signature,
+        None => return false,
+    };
+    VerifyingKey::<Sha256>::new(public_key)
+        .verify(message.as_bytes(), &signature)
+        .is_ok()
+}
@@ -4,6 +4,10 @@
 
 pub mod netchannel;
 pub mod p2pnetwork;
+pub mod identity;
+pub mod peerfilter;
+pub mod addrbook;
+pub mod connmgr;
 
 
 
@@ -12,15 +16,21 @@ mod tests {
     use std::io::BufRead;
     use std::thread;
     use std::time::Duration;
-    use lib_chain::block::{BlockNode, BlockNodeHeader, Transaction, Transactions, MerkleTree};
+    use lib_chain::block::{BlockNode, BlockNodeHeader, SignatureAlgorithm, Transaction, Transactions, MerkleTree};
     use std::net::{TcpListener, TcpStream};
-    use std::io::{Read, Write, BufReader};
+    use std::io::{Write, BufReader};
 
-    use crate::netchannel::{NetAddress, NetMessage, NetChannelTCP};
-    use crate::p2pnetwork::{P2PNetwork};
+    use crate::netchannel::{NetAddress, NetMessage, NetChannel, IDLE_TIMEOUT_MS, KEEPALIVE_INTERVAL_MS};
+    use crate::netchannel::{decode_frame, encode_frame, COMPRESSED_FRAME_PREFIX, COMPRESSION_THRESHOLD};
+    use crate::p2pnetwork::{P2PNetwork, BoundedMap, InboundLimits, MAX_TIME_OFFSET_MS};
+    use crate::identity::{NodeIdentity, node_id_of, verify_signature};
+    use crate::peerfilter::PeerFilter;
+    use crate::addrbook::{AddressBook, load_peers, save_peers};
+    use crate::connmgr::{ConnectionManager, PeerState, RECONNECT_MAX_DELAY_MS, RECONNECT_MIN_DELAY_MS};
+    use std::sync::Arc;
 
 
-    /// Test the NetChannelTCP by creating a fake node that echo messages and connecting to it.
+    /// Test the NetChannel by creating a fake node that echo messages and connecting to it.
     #[test]
     fn test_net_channel() {
         let fake_node1 = TcpListener::bind("127.0.0.1:9014").unwrap();
@@ -42,14 +52,44 @@ mod tests {
                 }
             }
         });
-        let mut net_channel = NetChannelTCP::from_addr(&NetAddress { ip: "127.0.0.1".to_owned(), port: 9014 }).unwrap();
-        net_channel.write_msg(NetMessage::Unknown("hello".to_owned()));
-        let msg = net_channel.read_msg().unwrap();
+        let mut net_channel = NetChannel::from_addr(&NetAddress { ip: "127.0.0.1".to_owned(), port: 9014 }).unwrap();
+        net_channel.send_msg(NetMessage::Unknown("hello".to_owned()));
+        let msg = net_channel.recv_msg().unwrap();
         println!("[Main thread] [Received] {:?}", &msg);
         assert!(msg == NetMessage::Unknown("hello".to_owned()));
+
+        // a large message goes through compressed, and the statistics count both directions
+        let large_msg = NetMessage::Unknown("hello ".repeat(1000));
+        assert!(net_channel.send_msg(large_msg.clone()));
+        assert!(net_channel.recv_msg().unwrap() == large_msg);
+        let stats = net_channel.stats();
+        assert!(stats.msgs_sent == 2 && stats.msgs_received == 2 && stats.compressed_msgs_sent == 1);
+        assert!(stats.bytes_sent == stats.bytes_received && stats.bytes_sent < 1000);
+    }
+
+    /// Test the frames of the NetChannel: small messages are sent as json, large ones compressed.
+    #[test]
+    fn test_net_channel_frames() {
+        let small_msg = NetMessage::Unknown("hello".to_owned());
+        let (frame, compressed) = encode_frame(&small_msg);
+        assert!(!compressed && frame == b"{\"Unknown\":\"hello\"}\n");
+        let large_msg = NetMessage::Unknown("x".repeat(COMPRESSION_THRESHOLD));
+        let (frame, compressed) = encode_frame(&large_msg);
+        let frame = String::from_utf8(frame).unwrap();
+        assert!(compressed && frame.starts_with(COMPRESSED_FRAME_PREFIX) && frame.len() < COMPRESSION_THRESHOLD);
+        assert!(decode_frame(frame.trim()) == Some(large_msg));
+        assert!(decode_frame(r#"{"Unknown":"hello"}"#) == Some(small_msg));
+        assert!(decode_frame("z:not base64!").is_none());
+        assert!(decode_frame("z:aGVsbG8=").is_none());
+        assert!(decode_frame("not json").is_none());
     }
 
 
+    /// The address of the connection of the peer with the given node id, if it completed the handshake.
+    fn peer_of(network: &P2PNetwork, node_id: &str) -> Option<String> {
+        network.peer_ids.iter().find(|(_, id)| id.as_str() == node_id).map(|(peer, _)| peer.clone())
+    }
+
     /// A function for creating a simplified fake neighbor node for testing the P2PNetwork.
     fn fake_neighbor(mut stream: TcpStream) {
         println!("[fake_neighbor] [BEGIN]");
@@ -61,7 +101,11 @@ mod tests {
                 sender: "AAA".to_string(),
                 receiver: "DDD".to_string(),
                 message: "good".to_string(),
-                sig: "blabla".to_string()
+                sig: "blabla".to_string(),
+                sender_pub_key: None,
+                fee: None,
+                algorithm: SignatureAlgorithm::Rsa,
+                outputs: vec![]
             };
             let node_header = BlockNodeHeader {
                 parent: "ZZZZ".to_string(),
@@ -70,6 +114,7 @@ mod tests {
                 block_id: String::new(),
                 nonce: "98765".to_string(),
                 reward_receiver: "AAA".to_string(),
+                miner_signature: None,
             };
             let block = BlockNode {
                 header: node_header,
@@ -126,12 +171,12 @@ mod tests {
         });
         
         let (
-            network,
+            _network,
             upd_block_in_rx, 
             upd_trans_in_rx,
             block_out_tx,
             trans_out_tx,
-            req_block_id_out_tx,
+            _req_block_id_out_tx,
         ) = P2PNetwork::create(
             NetAddress { ip: "127.0.0.1".to_owned(), port: 9011 },
             vec![
@@ -159,7 +204,11 @@ mod tests {
             sender: "hello".to_string(),
             receiver: "hi".to_string(),
             message: "msg".to_string(),
-            sig: "sig".to_string()
+            sig: "sig".to_string(),
+            sender_pub_key: None,
+            fee: None,
+            algorithm: SignatureAlgorithm::Rsa,
+            outputs: vec![]
         };
         let node_header = BlockNodeHeader {
             parent: "hahaha".to_string(),
@@ -168,6 +217,7 @@ mod tests {
             block_id: String::new(),
             nonce: "1111".to_string(),
             reward_receiver: "AAA".to_string(),
+            miner_signature: None,
         };
         let node = BlockNode {
             header: node_header,
@@ -205,13 +255,813 @@ mod tests {
     #[test]
     fn test_p2pnetwork_additional() {
         // Please fill in the blank
-        
+        // Two real nodes: node_b has no neighbors, node_a connects to node_b.
+        let (network_b, block_in_b, _tx_in_b, _block_out_b, _tx_out_b, _req_b) = P2PNetwork::create(
+            NetAddress { ip: "127.0.0.1".to_owned(), port: 9022 },
+            vec![]
+        );
+        thread::sleep(Duration::from_millis(100));
+        let (network_a, _block_in_a, _tx_in_a, block_out_a, _tx_out_a, _req_a) = P2PNetwork::create(
+            NetAddress { ip: "127.0.0.1".to_owned(), port: 9021 },
+            vec![NetAddress { ip: "127.0.0.1".to_owned(), port: 9022 }]
+        );
+
+        let node_header = BlockNodeHeader {
+            parent: "0".to_string(),
+            merkle_root: "0987".to_string(),
+            timestamp: 123,
+            block_id: "traced_block".to_string(),
+            nonce: "1111".to_string(),
+            reward_receiver: "AAA".to_string(),
+            miner_signature: None,
+        };
+        let node = BlockNode {
+            header: node_header,
+            transactions_block:  Transactions { merkle_tree: MerkleTree {hashes: vec![]}, transactions: vec![]},
+        };
+        block_out_a.send(node.clone()).unwrap();
+        // broadcasting the same block again must not relay it twice
+        block_out_a.send(node.clone()).unwrap();
+
+        let received = block_in_b.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert!(received == node);
+        assert!(block_in_b.recv_timeout(Duration::from_millis(200)).is_err());
+
+        // both nodes log the block under the trace id assigned by node_a
+        let trace_a = network_a.lock().unwrap().get_trace_log();
+        let trace_b = network_b.lock().unwrap().get_trace_log();
+        assert!(trace_a.len() == 1 && trace_b.len() == 1);
+        assert!(trace_a[0].from == "local");
+        assert!(trace_a[0].item_id == "traced_block");
+        assert!(trace_b[0].trace_id == trace_a[0].trace_id);
+        assert!(trace_b[0].from != "local");
+        assert!(network_b.lock().unwrap().get_block_source("traced_block") == Some(trace_b[0].from.clone()));
     }
-}
 
+    /// Test that a paused neighbor receives nothing until it is resumed.
+    #[test]
+    fn test_p2pnetwork_pause_peer() {
+        let (_network_b, block_in_b, _tx_in_b, _block_out_b, _tx_out_b, _req_b) = P2PNetwork::create(
+            NetAddress { ip: "127.0.0.1".to_owned(), port: 9032 },
+            vec![]
+        );
+        thread::sleep(Duration::from_millis(100));
+        let (network_a, _block_in_a, _tx_in_a, block_out_a, _tx_out_a, _req_a) = P2PNetwork::create(
+            NetAddress { ip: "127.0.0.1".to_owned(), port: 9031 },
+            vec![NetAddress { ip: "127.0.0.1".to_owned(), port: 9032 }]
+        );
+        let new_block = |block_id: &str| BlockNode {
+            header: BlockNodeHeader {
+                parent: "0".to_string(),
+                merkle_root: "0987".to_string(),
+                timestamp: 123,
+                block_id: block_id.to_string(),
+                nonce: "1111".to_string(),
+                reward_receiver: "AAA".to_string(),
+                miner_signature: None,
+            },
+            transactions_block:  Transactions { merkle_tree: MerkleTree {hashes: vec![]}, transactions: vec![]},
+        };
+
+        assert!(network_a.lock().unwrap().pause_peer("127.0.0.1:9999").is_err());
+        assert!(network_a.lock().unwrap().resume_peer("127.0.0.1:9032").is_err());
+
+        network_a.lock().unwrap().pause_peer("127.0.0.1:9032").unwrap();
+        assert!(network_a.lock().unwrap().get_status().paused_peers == vec!["127.0.0.1:9032"]);
+        block_out_a.send(new_block("during_partition")).unwrap();
+        assert!(block_in_b.recv_timeout(Duration::from_millis(300)).is_err());
+
+        network_a.lock().unwrap().resume_peer("127.0.0.1:9032").unwrap();
+        block_out_a.send(new_block("after_partition")).unwrap();
+        let received = block_in_b.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert!(received.header.block_id == "after_partition");
+    }
+
+    /// Test that a block request is forwarded until a node has the block and the response is routed back.
+    #[test]
+    fn test_p2pnetwork_block_request_routing() {
+        // node_a -> node_b -> node_c, only node_c has the block
+        let (network_c, _block_in_c, _tx_in_c, _block_out_c, _tx_out_c, _req_c) = P2PNetwork::create(
+            NetAddress { ip: "127.0.0.1".to_owned(), port: 9043 },
+            vec![]
+        );
+        thread::sleep(Duration::from_millis(100));
+        let (network_b, block_in_b, _tx_in_b, _block_out_b, _tx_out_b, _req_b) = P2PNetwork::create(
+            NetAddress { ip: "127.0.0.1".to_owned(), port: 9042 },
+            vec![NetAddress { ip: "127.0.0.1".to_owned(), port: 9043 }]
+        );
+        thread::sleep(Duration::from_millis(100));
+        let (network_a, block_in_a, _tx_in_a, _block_out_a, _tx_out_a, req_a) = P2PNetwork::create(
+            NetAddress { ip: "127.0.0.1".to_owned(), port: 9041 },
+            vec![NetAddress { ip: "127.0.0.1".to_owned(), port: 9042 }]
+        );
+
+        let block = BlockNode {
+            header: BlockNodeHeader {
+                parent: "0".to_string(),
+                merkle_root: "0987".to_string(),
+                timestamp: 123,
+                block_id: "requested_block".to_string(),
+                nonce: "1111".to_string(),
+                reward_receiver: "AAA".to_string(),
+                miner_signature: None,
+            },
+            transactions_block:  Transactions { merkle_tree: MerkleTree {hashes: vec![]}, transactions: vec![]},
+        };
+        let served_block = block.clone();
+        network_c.lock().unwrap().set_block_provider(Arc::new(move |block_id| {
+            (block_id == "requested_block").then(|| served_block.clone())
+        }));
+
+        req_a.send("requested_block".to_string()).unwrap();
+        let received = block_in_a.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert!(received == block);
+        assert!(network_a.lock().unwrap().get_block_source("requested_block") == Some("127.0.0.1:9042".to_string()));
+        // the forwarding node only routes the response, it does not take the block
+        assert!(block_in_b.recv_timeout(Duration::from_millis(200)).is_err());
+        assert!(network_b.lock().unwrap().get_block_source("requested_block").is_none());
 
+        // a block nobody has is dropped once the hop limit is exhausted
+        req_a.send("missing_block".to_string()).unwrap();
+        assert!(block_in_a.recv_timeout(Duration::from_millis(300)).is_err());
+    }
 
+    /// Test that the node key is persisted on first run and that its signatures verify.
+    #[test]
+    fn test_node_identity() {
+        let path = std::env::temp_dir().join(format!("node_key_test_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+        let identity = NodeIdentity::load_or_generate(path).unwrap();
+        let reloaded = NodeIdentity::load_or_generate(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert!(identity.node_id() == reloaded.node_id());
+        assert!(identity.node_id() == node_id_of(&identity.pub_key_pem));
+        assert!(identity.node_id().len() == 64);
 
+        let signature = identity.sign("hello");
+        assert!(verify_signature(&identity.pub_key_pem, "hello", &signature));
+        assert!(!verify_signature(&identity.pub_key_pem, "hello!", &signature));
+        assert!(!verify_signature(&identity.pub_key_pem, "hello", "not a signature"));
+        assert!(!verify_signature("not a key", "hello", &signature));
+    }
 
+    /// Test that nodes learn each other's node ids in the handshake and score peers delivering new blocks.
+    #[test]
+    fn test_p2pnetwork_handshake_and_signing() {
+        let identity_b = NodeIdentity::generate();
+        let identity_a = NodeIdentity::generate();
+        let (network_b, block_in_b, _tx_in_b, _block_out_b, _tx_out_b, _req_b) = P2PNetwork::create_with_identity(
+            NetAddress { ip: "127.0.0.1".to_owned(), port: 9052 },
+            vec![],
+            identity_b.clone(),
+            true,
+            PeerFilter::default(),
+            InboundLimits::default()
+        );
+        thread::sleep(Duration::from_millis(100));
+        let (network_a, _block_in_a, _tx_in_a, block_out_a, _tx_out_a, _req_a) = P2PNetwork::create_with_identity(
+            NetAddress { ip: "127.0.0.1".to_owned(), port: 9051 },
+            vec![NetAddress { ip: "127.0.0.1".to_owned(), port: 9052 }],
+            identity_a.clone(),
+            true,
+            PeerFilter::default(),
+            InboundLimits::default()
+        );
 
+        let node = BlockNode {
+            header: BlockNodeHeader {
+                parent: "0".to_string(),
+                merkle_root: "0987".to_string(),
+                timestamp: 123,
+                block_id: "signed_block".to_string(),
+                nonce: "1111".to_string(),
+                reward_receiver: "AAA".to_string(),
+                miner_signature: None,
+            },
+            transactions_block:  Transactions { merkle_tree: MerkleTree {hashes: vec![]}, transactions: vec![]},
+        };
+        block_out_a.send(node.clone()).unwrap();
+        assert!(block_in_b.recv_timeout(Duration::from_secs(2)).unwrap() == node);
+        thread::sleep(Duration::from_millis(100));
+
+        // node_b knows node_a under the ephemeral port it connected from, and the listener address it claims apart
+        let network_b = network_b.lock().unwrap();
+        let peer_a = peer_of(&network_b, &identity_a.node_id()).unwrap();
+        assert!(peer_a.starts_with("127.0.0.1:") && peer_a != "127.0.0.1:9051");
+        let status_b = network_b.get_status();
+        assert!(status_b.peers.iter().any(|peer| peer.address == peer_a && peer.listen_address == "127.0.0.1:9051"));
+        assert!(network_b.peer_scores[&identity_a.node_id()] == 1);
+        assert!(network_b.get_block_source("signed_block") == Some(peer_a));
+        assert!(network_b.get_status().node_id == identity_b.node_id());
+        let network_a = network_a.lock().unwrap();
+        assert!(network_a.peer_ids.get("127.0.0.1:9052") == Some(&identity_b.node_id()));
+    }
+
+    /// Test that the peers rejected by the allowlist or the blocklist are not dialed, not kept after the handshake,
+    /// and disconnected when they are blocked at runtime.
+    #[test]
+    fn test_p2pnetwork_peer_filter() {
+        let filter = PeerFilter::new(Some(vec!["127.0.0.1:9001".to_string(), "ID_A".to_string()]), vec!["10.0.0.1".to_string()]);
+        // before the handshake, only the blocklist is checked, and no peer is kept under the allowlist
+        assert!(!filter.blocks("127.0.0.1:9002", None) && !filter.permits("127.0.0.1:9002", None));
+        assert!(!filter.permits("127.0.0.1:9001", None));
+        assert!(filter.permits("127.0.0.1:9001", Some("ID_B")));
+        assert!(!filter.permits("127.0.0.1:9002", Some("ID_B")));
+        assert!(filter.permits("127.0.0.1:9002", Some("ID_A")));
+        assert!(filter.blocks("10.0.0.1:9001", None) && !filter.permits("10.0.0.1:9001", Some("ID_A")));
+        let mut filter = PeerFilter::default();
+        assert!(filter.permits("127.0.0.1:9002", Some("ID_B")));
+        filter.block("ID_B").unwrap();
+        assert!(filter.block("ID_B").is_err());
+        assert!(!filter.permits("127.0.0.1:9002", Some("ID_B")));
+        filter.unblock("ID_B").unwrap();
+        assert!(filter.unblock("ID_B").is_err());
+        assert!(filter.disallow("ID_A").is_err());
+        filter.allow("ID_A").unwrap();
+        assert!(!filter.permits("127.0.0.1:9002", Some("ID_B")));
+        filter.disallow("ID_A").unwrap();
+        assert!(filter.allowlist == Some(Default::default()));
+        assert!(filter.block("").is_err() && filter.allow("").is_err());
 
+        let new_block = |block_id: &str| BlockNode {
+            header: BlockNodeHeader {
+                parent: "0".to_string(),
+                merkle_root: "0987".to_string(),
+                timestamp: 123,
+                block_id: block_id.to_string(),
+                nonce: "1111".to_string(),
+                reward_receiver: "AAA".to_string(),
+                miner_signature: None,
+            },
+            transactions_block:  Transactions { merkle_tree: MerkleTree {hashes: vec![]}, transactions: vec![]},
+        };
+        let identity_a = NodeIdentity::generate();
+        // node_b blocks node_a by its node id, and node_c blocks node_b by its address
+        let (network_b, block_in_b, _tx_in_b, _block_out_b, _tx_out_b, _req_b) = P2PNetwork::create_with_identity(
+            NetAddress { ip: "127.0.0.1".to_owned(), port: 9072 },
+            vec![],
+            NodeIdentity::generate(),
+            false,
+            PeerFilter::new(None, vec![identity_a.node_id()]),
+            InboundLimits::default()
+        );
+        thread::sleep(Duration::from_millis(100));
+        let (_network_a, _block_in_a, _tx_in_a, block_out_a, _tx_out_a, _req_a) = P2PNetwork::create_with_identity(
+            NetAddress { ip: "127.0.0.1".to_owned(), port: 9071 },
+            vec![NetAddress { ip: "127.0.0.1".to_owned(), port: 9072 }],
+            identity_a,
+            false,
+            PeerFilter::default(),
+            InboundLimits::default()
+        );
+        let (network_c, _block_in_c, _tx_in_c, block_out_c, _tx_out_c, _req_c) = P2PNetwork::create_with_identity(
+            NetAddress { ip: "127.0.0.1".to_owned(), port: 9073 },
+            vec![NetAddress { ip: "127.0.0.1".to_owned(), port: 9072 }],
+            NodeIdentity::generate(),
+            false,
+            PeerFilter::new(None, vec!["127.0.0.1:9072".to_string()]),
+            InboundLimits::default()
+        );
+        block_out_a.send(new_block("from_blocked_node")).unwrap();
+        block_out_c.send(new_block("not_dialed")).unwrap();
+        assert!(block_in_b.recv_timeout(Duration::from_millis(300)).is_err());
+        assert!(network_b.lock().unwrap().peer_ids.is_empty());
+        assert!(network_c.lock().unwrap().get_status().blocked_peers == vec!["127.0.0.1:9072"]);
+
+        // node_d is connected to node_b until node_b blocks it at runtime, by the listener address node_d claims
+        let (network_d, _block_in_d, _tx_in_d, block_out_d, _tx_out_d, _req_d) = P2PNetwork::create(
+            NetAddress { ip: "127.0.0.1".to_owned(), port: 9074 },
+            vec![NetAddress { ip: "127.0.0.1".to_owned(), port: 9072 }]
+        );
+        let node_id_d = network_d.lock().unwrap().node_id.clone();
+        block_out_d.send(new_block("before_block")).unwrap();
+        assert!(block_in_b.recv_timeout(Duration::from_secs(2)).unwrap().header.block_id == "before_block");
+        assert!(peer_of(&network_b.lock().unwrap(), &node_id_d).is_some());
+        network_b.lock().unwrap().peer_filter_mut().block("127.0.0.1:9074").unwrap();
+        block_out_d.send(new_block("after_block")).unwrap();
+        assert!(block_in_b.recv_timeout(Duration::from_millis(300)).is_err());
+        assert!(peer_of(&network_b.lock().unwrap(), &node_id_d).is_none());
+    }
+
+    /// Test the inventory protocol with a fake peer: announced blocks are requested once, and requested blocks are
+    /// answered from the gossiped blocks or from the block provider.
+    #[test]
+    fn test_p2pnetwork_inventory() {
+        let new_block = |block_id: &str| BlockNode {
+            header: BlockNodeHeader {
+                parent: "0".to_string(),
+                merkle_root: "0987".to_string(),
+                timestamp: 123,
+                block_id: block_id.to_string(),
+                nonce: "1111".to_string(),
+                reward_receiver: "AAA".to_string(),
+                miner_signature: None,
+            },
+            transactions_block:  Transactions { merkle_tree: MerkleTree {hashes: vec![]}, transactions: vec![]},
+        };
+        let (network, block_in, _tx_in, _block_out, _tx_out, _req) = P2PNetwork::create(
+            NetAddress { ip: "127.0.0.1".to_owned(), port: 9081 },
+            vec![]
+        );
+        let stored_block = new_block("stored_block");
+        network.lock().unwrap().set_block_provider(Arc::new(move |block_id| (block_id == "stored_block").then(|| stored_block.clone())));
+        thread::sleep(Duration::from_millis(100));
+
+        let mut peer = NetChannel::from_stream(TcpStream::connect("127.0.0.1:9081").unwrap());
+        let Some(NetMessage::Challenge(nonce)) = peer.recv_msg() else { panic!("the peer was not challenged") };
+        // the node only answers once it has introduced itself
+        peer.send_msg(NetMessage::Challenge("nonce".to_string()));
+        assert!(matches!(peer.recv_msg(), Some(NetMessage::Hello(..))));
+        // and the peer is ignored until it has introduced itself: the early announcement is not requested
+        peer.send_msg(NetMessage::Inventory(vec!["early_block".to_string()]));
+        let identity = NodeIdentity::generate();
+        let listen_address = NetAddress { ip: "127.0.0.1".to_owned(), port: 9082 };
+        let signature = identity.sign(&NetMessage::hello_signed_payload(&identity.node_id(), &listen_address, &nonce));
+        peer.send_msg(NetMessage::Hello(identity.node_id(), identity.pub_key_pem.clone(), listen_address, signature));
+        assert!(peer.recv_msg() == Some(NetMessage::GetAddr));
+        assert!(matches!(peer.recv_msg(), Some(NetMessage::Ping(_))));
+
+        peer.send_msg(NetMessage::Inventory(vec!["announced_block".to_string()]));
+        assert!(peer.recv_msg() == Some(NetMessage::GetData(vec!["announced_block".to_string()])));
+        // the block is not requested again while the request is in flight
+        peer.send_msg(NetMessage::Inventory(vec!["announced_block".to_string()]));
+        peer.send_msg(NetMessage::BlockData(new_block("announced_block")));
+        assert!(block_in.recv_timeout(Duration::from_secs(2)).unwrap() == new_block("announced_block"));
+        assert!(network.lock().unwrap().get_status().requested_blocks == 0);
+
+        peer.send_msg(NetMessage::GetData(vec!["unknown_block".to_string(), "stored_block".to_string(), "announced_block".to_string()]));
+        assert!(peer.recv_msg() == Some(NetMessage::BlockData(new_block("announced_block"))));
+        assert!(peer.recv_msg() == Some(NetMessage::BlockData(new_block("stored_block"))));
+        // a block already received is not requested again, so the next message answers the next request
+        peer.send_msg(NetMessage::Inventory(vec!["announced_block".to_string()]));
+        peer.send_msg(NetMessage::GetData(vec!["stored_block".to_string()]));
+        let next_msg = std::iter::from_fn(|| peer.recv_msg()).find(|msg| !matches!(msg, NetMessage::Ping(_)));
+        assert!(next_msg == Some(NetMessage::BlockData(new_block("stored_block"))));
+    }
+
+    /// Test that idle connections are kept alive with pings, that a silent connection is closed after the idle timeout,
+    /// and that a lost neighbor is dialed again.
+    #[test]
+    fn test_p2pnetwork_keepalive_and_reconnect() {
+        let fake_node = TcpListener::bind("127.0.0.1:9092").unwrap();
+        let (_network, _block_in, _tx_in, _block_out, _tx_out, _req) = P2PNetwork::create(
+            NetAddress { ip: "127.0.0.1".to_owned(), port: 9091 },
+            vec![NetAddress { ip: "127.0.0.1".to_owned(), port: 9092 }]
+        );
+
+        // the node dials the fake neighbor again after the fake neighbor closes the connection
+        let mut neighbor = NetChannel::from_stream(fake_node.accept().unwrap().0);
+        assert!(matches!(neighbor.recv_msg(), Some(NetMessage::Challenge(_))));
+        drop(neighbor);
+        let mut neighbor = NetChannel::from_stream(fake_node.accept().unwrap().0);
+        assert!(matches!(neighbor.recv_msg(), Some(NetMessage::Challenge(_))));
+        drop(neighbor);
+
+        // a peer that stays silent is pinged, and then disconnected
+        let mut peer = NetChannel::from_stream(TcpStream::connect("127.0.0.1:9091").unwrap());
+        let connected_at = std::time::Instant::now();
+        assert!(matches!(peer.recv_msg(), Some(NetMessage::Challenge(_))));
+        let ping_count = std::iter::from_fn(|| peer.recv_msg()).filter(|msg| matches!(msg, NetMessage::Ping(_))).count();
+        let elapsed_ms = connected_at.elapsed().as_millis() as u64;
+        assert!(ping_count >= 2);
+        assert!((IDLE_TIMEOUT_MS..IDLE_TIMEOUT_MS + KEEPALIVE_INTERVAL_MS).contains(&elapsed_ms));
+    }
+
+    /// Test that every message written or read by the connections is counted, by peer and by message type.
+    #[test]
+    fn test_p2pnetwork_traffic_stats() {
+        let (network_b, block_in_b, _tx_in_b, _block_out_b, _tx_out_b, _req_b) = P2PNetwork::create(
+            NetAddress { ip: "127.0.0.1".to_owned(), port: 9112 },
+            vec![]
+        );
+        thread::sleep(Duration::from_millis(100));
+        let (network_a, _block_in_a, _tx_in_a, block_out_a, _tx_out_a, _req_a) = P2PNetwork::create(
+            NetAddress { ip: "127.0.0.1".to_owned(), port: 9111 },
+            vec![NetAddress { ip: "127.0.0.1".to_owned(), port: 9112 }]
+        );
+        let node = BlockNode {
+            header: BlockNodeHeader {
+                parent: "0".to_string(),
+                merkle_root: "0987".to_string(),
+                timestamp: 123,
+                block_id: "counted_block".to_string(),
+                nonce: "1111".to_string(),
+                reward_receiver: "AAA".to_string(),
+                miner_signature: None,
+            },
+            transactions_block:  Transactions { merkle_tree: MerkleTree {hashes: vec![]}, transactions: vec![]},
+        };
+        block_out_a.send(node.clone()).unwrap();
+        assert!(block_in_b.recv_timeout(Duration::from_secs(2)).unwrap() == node);
+        thread::sleep(Duration::from_millis(100));
+
+        // node_a announced the block, node_b requested it and node_a sent it, after both handshakes
+        let traffic_a = network_a.lock().unwrap().get_traffic();
+        let traffic_b = network_b.lock().unwrap().get_traffic();
+        assert!(traffic_a.by_type["Hello"] == (1, 1) && traffic_b.by_type["Hello"] == (1, 1));
+        assert!(traffic_a.by_type["Inventory"].0 == 1 && traffic_b.by_type["Inventory"].1 == 1);
+        assert!(traffic_a.by_type["GetData"] == (0, 1) && traffic_b.by_type["GetData"] == (1, 0));
+        assert!(traffic_a.by_type["BlockData"].0 == 1 && traffic_b.by_type["BlockData"].1 == 1);
+        // the incoming connection of node_b is counted under the address node_a connected from
+        let node_id_a = network_a.lock().unwrap().node_id.clone();
+        let peer_a = peer_of(&network_b.lock().unwrap(), &node_id_a).unwrap();
+        assert!(traffic_a.by_peer.keys().collect::<Vec<_>>() == vec!["127.0.0.1:9112"]);
+        assert!(traffic_b.by_peer.keys().collect::<Vec<_>>() == vec![&peer_a]);
+        assert!(traffic_a.by_peer["127.0.0.1:9112"].0 >= 3 && traffic_b.by_peer[&peer_a].1 >= 2);
+        assert!(traffic_a.msgs_sent >= 3 && traffic_a.bytes_sent > 0 && traffic_b.bytes_received > 0);
+        let status_a = network_a.lock().unwrap().get_status();
+        assert!(status_a.traffic.by_type["GetData"] == (0, 1));
+        assert!(status_a.traffic.by_peer.keys().collect::<Vec<_>>() == vec!["127.0.0.1:9112"]);
+        assert!(status_a.traffic.msgs_sent >= traffic_a.msgs_sent);
+        // both nodes see the other one connected, under the address of its connection
+        let health_a = network_a.lock().unwrap().get_peer_health();
+        let health_b = network_b.lock().unwrap().get_peer_health();
+        assert!(health_a["127.0.0.1:9112"].state == PeerState::Connected);
+        assert!(health_b[&peer_a].state == PeerState::Connected);
+        assert!(health_a["127.0.0.1:9112"].last_seen_ms.is_some());
+        assert!(status_a.peer_health["127.0.0.1:9112"].state == PeerState::Connected);
+        assert!(status_a.peers.iter().map(|peer| &peer.address).collect::<Vec<_>>() == vec!["127.0.0.1:9112"]);
+    }
+
+    /// Test the states of the connection manager, and the backoff of the dials of a peer.
+    #[test]
+    fn test_connection_manager() {
+        let mut manager = ConnectionManager::default();
+        let peer = "127.0.0.1:9001";
+
+        // a peer that cannot be dialed waits longer and longer, up to the max delay
+        manager.dialing(peer);
+        assert!(manager.get(peer).unwrap().state == PeerState::Connecting);
+        manager.dial_failed(peer);
+        assert!(manager.get(peer).unwrap().state == PeerState::Disconnected);
+        assert!(manager.backoff(peer, 1000) == RECONNECT_MIN_DELAY_MS);
+        assert!(manager.get(peer).unwrap().state == PeerState::Backoff(1000 + RECONNECT_MIN_DELAY_MS));
+        assert!(manager.backoff(peer, 2000) == 2 * RECONNECT_MIN_DELAY_MS);
+        let delays: Vec<u64> = (0..10).map(|_| manager.backoff(peer, 3000)).collect();
+        assert!(*delays.last().unwrap() == RECONNECT_MAX_DELAY_MS);
+        assert!(manager.get(peer).unwrap().failures == 1);
+
+        // a short-lived connection keeps the backoff, a long one resets it
+        manager.connected(peer, 10_000);
+        let health = manager.get(peer).unwrap();
+        assert!(health.state == PeerState::Connected && health.failures == 0 && health.reconnects == 0);
+        manager.seen(peer, 10_500);
+        assert!(manager.get(peer).unwrap().last_seen_ms == Some(10_500));
+        manager.disconnected(peer, 11_000);
+        assert!(manager.get(peer).unwrap().state == PeerState::Disconnected);
+        assert!(manager.backoff(peer, 11_000) == RECONNECT_MAX_DELAY_MS);
+        manager.connected(peer, 20_000);
+        manager.disconnected(peer, 20_000 + IDLE_TIMEOUT_MS);
+        assert!(manager.get(peer).unwrap().reconnects == 1);
+        assert!(manager.backoff(peer, 30_000) == RECONNECT_MIN_DELAY_MS);
+
+        // the peer stays connected while one of its connections is open
+        manager.connected(peer, 40_000);
+        manager.connected(peer, 40_100);
+        manager.disconnected(peer, 40_200);
+        assert!(manager.get(peer).unwrap().state == PeerState::Connected);
+        assert!(manager.get(peer).unwrap().describe(41_200).starts_with("connected 1.2s, seen 1.1s ago, 2 reconnects"));
+        manager.disconnected(peer, 40_300);
+        assert!(manager.get(peer).unwrap().state == PeerState::Disconnected);
+        assert!(manager.get("127.0.0.1:9002").is_none());
+    }
+
+    /// Test broadcasting blocks and transactions with the methods of the network, and sending a message to one peer.
+    #[test]
+    fn test_p2pnetwork_broadcast_and_send_to() {
+        let (network_b, block_in_b, tx_in_b, _block_out_b, _tx_out_b, _req_b) = P2PNetwork::create(
+            NetAddress { ip: "127.0.0.1".to_owned(), port: 9142 },
+            vec![]
+        );
+        thread::sleep(Duration::from_millis(100));
+        let (network_a, block_in_a, _tx_in_a, _block_out_a, _tx_out_a, _req_a) = P2PNetwork::create(
+            NetAddress { ip: "127.0.0.1".to_owned(), port: 9141 },
+            vec![NetAddress { ip: "127.0.0.1".to_owned(), port: 9142 }]
+        );
+        let new_block = |block_id: &str| BlockNode {
+            header: BlockNodeHeader {
+                parent: "0".to_string(),
+                merkle_root: "0987".to_string(),
+                timestamp: 123,
+                block_id: block_id.to_string(),
+                nonce: "1111".to_string(),
+                reward_receiver: "AAA".to_string(),
+                miner_signature: None,
+            },
+            transactions_block:  Transactions { merkle_tree: MerkleTree {hashes: vec![]}, transactions: vec![]},
+        };
+        let transaction = Transaction {
+            sender: "AAA".to_string(),
+            receiver: "BBB".to_string(),
+            message: "SEND $1".to_string(),
+            sig: "sig".to_string(),
+            sender_pub_key: None,
+            fee: None,
+            algorithm: SignatureAlgorithm::Rsa,
+            outputs: vec![]
+        };
+
+        // a broadcast reaches the neighbors once
+        assert!(network_a.lock().unwrap().broadcast_block(&new_block("broadcast_block")));
+        assert!(!network_a.lock().unwrap().broadcast_block(&new_block("broadcast_block")));
+        assert!(block_in_b.recv_timeout(Duration::from_secs(2)).unwrap() == new_block("broadcast_block"));
+        assert!(network_a.lock().unwrap().broadcast_tx(&transaction));
+        assert!(!network_a.lock().unwrap().broadcast_tx(&transaction));
+        assert!(tx_in_b.recv_timeout(Duration::from_secs(2)).unwrap() == transaction);
+        assert!(tx_in_b.recv_timeout(Duration::from_millis(200)).is_err());
+
+        // node_b reaches node_a, which dialed it, by node id and by the address of its connection, but not a peer that
+        // is not connected, nor node_a by the listener address it only claims
+        let node_id_a = network_a.lock().unwrap().node_id.clone();
+        network_b.lock().unwrap().send_to(&node_id_a, NetMessage::BroadcastBlock(new_block("served_block"))).unwrap();
+        assert!(block_in_a.recv_timeout(Duration::from_secs(2)).unwrap() == new_block("served_block"));
+        let peer_a = peer_of(&network_b.lock().unwrap(), &node_id_a).unwrap();
+        network_b.lock().unwrap().send_to(&peer_a, NetMessage::BlockData(new_block("served_block_2"))).unwrap();
+        assert!(block_in_a.recv_timeout(Duration::from_secs(2)).unwrap() == new_block("served_block_2"));
+        assert!(network_b.lock().unwrap().send_to("127.0.0.1:9141", NetMessage::Ping(0)).is_err());
+        assert!(network_b.lock().unwrap().send_to("127.0.0.1:9143", NetMessage::Ping(0)).is_err());
+        assert!(network_b.lock().unwrap().send_to("unknown_node_id", NetMessage::Ping(0)).is_err());
+    }
+
+    /// Test that the connections beyond the inbound limit are refused as busy, and accepted again once a peer leaves.
+    #[test]
+    fn test_p2pnetwork_inbound_limit() {
+        let (network, _block_in, _tx_in, _block_out, _tx_out, _req) = P2PNetwork::create_with_identity(
+            NetAddress { ip: "0.0.0.0".to_owned(), port: 9151 },
+            vec![],
+            NodeIdentity::generate(),
+            false,
+            PeerFilter::default(),
+            InboundLimits { max_inbound_peers: Some(1), localhost_only: true }
+        );
+        thread::sleep(Duration::from_millis(100));
+
+        let mut peer = NetChannel::from_stream(TcpStream::connect("127.0.0.1:9151").unwrap());
+        assert!(matches!(peer.recv_msg(), Some(NetMessage::Challenge(_))));
+        let mut refused_peer = NetChannel::from_stream(TcpStream::connect("127.0.0.1:9151").unwrap());
+        assert!(refused_peer.recv_msg() == Some(NetMessage::Busy));
+        assert!(refused_peer.recv_msg().is_none());
+        assert!(network.lock().unwrap().get_status().inbound_peers == 1);
+
+        drop(peer);
+        thread::sleep(Duration::from_millis(200));
+        assert!(network.lock().unwrap().get_status().inbound_peers == 0);
+        let mut peer = NetChannel::from_stream(TcpStream::connect("127.0.0.1:9151").unwrap());
+        assert!(matches!(peer.recv_msg(), Some(NetMessage::Challenge(_))));
+    }
+
+    /// Test that a node answers the challenge of a peer with a Hello signed over its nonce, and that a Hello signed over
+    /// another nonce (e.g. captured on another connection) fails the handshake.
+    #[test]
+    fn test_p2pnetwork_hello_challenge() {
+        let node_identity = NodeIdentity::generate();
+        let (network, _block_in, _tx_in, _block_out, _tx_out, _req) = P2PNetwork::create_with_identity(
+            NetAddress { ip: "127.0.0.1".to_owned(), port: 9221 },
+            vec![],
+            node_identity.clone(),
+            false,
+            PeerFilter::default(),
+            InboundLimits::default()
+        );
+        thread::sleep(Duration::from_millis(100));
+        let identity = NodeIdentity::generate();
+        let listen_address = NetAddress { ip: "127.0.0.1".to_owned(), port: 9222 };
+        let hello = |nonce: &str| {
+            let signature = identity.sign(&NetMessage::hello_signed_payload(&identity.node_id(), &listen_address, nonce));
+            NetMessage::Hello(identity.node_id(), identity.pub_key_pem.clone(), listen_address.clone(), signature)
+        };
+
+        // the node signs the nonce of the peer, and only the first one
+        let stream = TcpStream::connect("127.0.0.1:9221").unwrap();
+        let socket_address = stream.local_addr().unwrap().to_string();
+        let mut peer = NetChannel::from_stream(stream);
+        let Some(NetMessage::Challenge(nonce)) = peer.recv_msg() else { panic!("the peer was not challenged") };
+        peer.send_msg(NetMessage::Challenge("peer nonce".to_string()));
+        peer.send_msg(NetMessage::Challenge("other nonce".to_string()));
+        let Some(NetMessage::Hello(node_id, pub_key_pem, node_address, signature)) = peer.recv_msg() else { panic!("the challenge was not answered") };
+        assert!(node_id == node_identity.node_id());
+        assert!(verify_signature(&pub_key_pem, &NetMessage::hello_signed_payload(&node_id, &node_address, "peer nonce"), &signature));
+        peer.send_msg(hello(&nonce));
+        thread::sleep(Duration::from_millis(200));
+        // the peer is known by the address of its socket, not by the listener address it claims
+        assert!(network.lock().unwrap().peer_ids.get(&socket_address) == Some(&identity.node_id()));
+        assert!(!network.lock().unwrap().peer_ids.contains_key("127.0.0.1:9222"));
+        let answers = std::iter::from_fn(|| peer.recv_msg()).take(2).collect::<Vec<_>>();
+        assert!(!answers.iter().any(|msg| matches!(msg, NetMessage::Hello(..))));
+        drop(peer);
+        thread::sleep(Duration::from_millis(200));
+
+        // the Hello of the previous connection is replayed on a new one
+        let mut replaying_peer = NetChannel::from_stream(TcpStream::connect("127.0.0.1:9221").unwrap());
+        assert!(matches!(replaying_peer.recv_msg(), Some(NetMessage::Challenge(other_nonce)) if other_nonce != nonce));
+        let replayed_at = std::time::Instant::now();
+        replaying_peer.send_msg(hello(&nonce));
+        // the connection is closed right away, instead of after the idle timeout
+        assert!(std::iter::from_fn(|| replaying_peer.recv_msg()).all(|msg| !matches!(msg, NetMessage::GetAddr)));
+        assert!(replayed_at.elapsed().as_millis() < KEEPALIVE_INTERVAL_MS as u128);
+
+        // claiming the listener address of an allowed peer does not get a peer allowed
+        network.lock().unwrap().peer_filter_mut().allow("127.0.0.1:9222").unwrap();
+        let mut impostor = NetChannel::from_stream(TcpStream::connect("127.0.0.1:9221").unwrap());
+        let Some(NetMessage::Challenge(nonce)) = impostor.recv_msg() else { panic!("the peer was not challenged") };
+        impostor.send_msg(hello(&nonce));
+        assert!(std::iter::from_fn(|| impostor.recv_msg()).all(|msg| !matches!(msg, NetMessage::GetAddr)));
+        assert!(network.lock().unwrap().peer_ids.is_empty());
+    }
+
+    /// Test that a node answers pings with its time, and estimates the clock offset of its peers from their pongs,
+    /// moving its network time by the median offset, unless it is too large.
+    #[test]
+    fn test_p2pnetwork_time_offset() {
+        let (network, _block_in, _tx_in, _block_out, _tx_out, _req) = P2PNetwork::create(
+            NetAddress { ip: "127.0.0.1".to_owned(), port: 9201 },
+            vec![]
+        );
+        thread::sleep(Duration::from_millis(100));
+        let now_ms = || std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis() as u64;
+
+        // a peer whose clock is a minute ahead completes the handshake, and is pinged right away
+        let identity = NodeIdentity::generate();
+        let listen_address = NetAddress { ip: "127.0.0.1".to_owned(), port: 9202 };
+        let mut peer = NetChannel::from_stream(TcpStream::connect("127.0.0.1:9201").unwrap());
+        let Some(NetMessage::Challenge(nonce)) = peer.recv_msg() else { panic!("the peer was not challenged") };
+        peer.send_msg(NetMessage::Challenge("nonce".to_string()));
+        let signature = identity.sign(&NetMessage::hello_signed_payload(&identity.node_id(), &listen_address, &nonce));
+        peer.send_msg(NetMessage::Hello(identity.node_id(), identity.pub_key_pem.clone(), listen_address, signature));
+        let ping = std::iter::from_fn(|| peer.recv_msg()).find(|msg| matches!(msg, NetMessage::Ping(_)));
+        let Some(NetMessage::Ping(sent_ms)) = ping else { panic!("the peer was not pinged") };
+        peer.send_msg(NetMessage::Pong(sent_ms, now_ms() + 60_000));
+        thread::sleep(Duration::from_millis(200));
+        // the median of the peer (+60s) and of the node itself (0)
+        let offset_ms = network.lock().unwrap().network_time_offset_ms();
+        assert!((29_000..=30_100).contains(&offset_ms));
+        assert!(network.lock().unwrap().get_status().time_offset_samples == 1);
+
+        // the node answers a ping with its own time
+        let before_ms = now_ms();
+        peer.send_msg(NetMessage::Ping(123));
+        let pong = std::iter::from_fn(|| peer.recv_msg()).find(|msg| matches!(msg, NetMessage::Pong(..)));
+        let Some(NetMessage::Pong(123, node_ms)) = pong else { panic!("the ping was not answered") };
+        assert!(node_ms >= before_ms && node_ms <= now_ms());
+
+        // a clock too far off is not followed
+        peer.send_msg(NetMessage::Ping(0));
+        let _ = std::iter::from_fn(|| peer.recv_msg()).find(|msg| matches!(msg, NetMessage::Pong(..)));
+        peer.send_msg(NetMessage::Pong(now_ms(), now_ms() + 3 * MAX_TIME_OFFSET_MS as u64));
+        thread::sleep(Duration::from_millis(200));
+        assert!(network.lock().unwrap().network_time_offset_ms() == 0);
+
+        // the offset of a peer is forgotten when it disconnects
+        peer.send_msg(NetMessage::Pong(now_ms(), now_ms() + 60_000));
+        thread::sleep(Duration::from_millis(200));
+        assert!(network.lock().unwrap().network_time_offset_ms() > 0);
+        drop(peer);
+        thread::sleep(Duration::from_millis(200));
+        assert!(network.lock().unwrap().network_time_offset_ms() == 0);
+        let status = network.lock().unwrap().get_status();
+        assert!(status.time_offset_ms == 0 && status.time_offset_samples == 0);
+    }
+
+    /// Test that a tip announced by a node reaches the tip listener of its peer, which requests the block body right away.
+    #[test]
+    fn test_p2pnetwork_announce_tip() {
+        let (network_b, _block_in_b, _tx_in_b, _block_out_b, _tx_out_b, _req_b) = P2PNetwork::create(
+            NetAddress { ip: "127.0.0.1".to_owned(), port: 9162 },
+            vec![]
+        );
+        let (tip_sender, tip_receiver) = std::sync::mpsc::channel();
+        network_b.lock().unwrap().set_tip_listener(Arc::new(move |peer, height, block_id, work| {
+            let _ = tip_sender.send((peer.to_string(), height, block_id.to_string(), work));
+        }));
+        thread::sleep(Duration::from_millis(100));
+        let (network_a, _block_in_a, _tx_in_a, _block_out_a, _tx_out_a, _req_a) = P2PNetwork::create(
+            NetAddress { ip: "127.0.0.1".to_owned(), port: 9161 },
+            vec![NetAddress { ip: "127.0.0.1".to_owned(), port: 9162 }]
+        );
+        thread::sleep(Duration::from_millis(300));
+
+        // node_b announces to node_a, which dialed it, and node_a announces to node_b
+        assert!(network_b.lock().unwrap().announce_tip(1, "tip_block_b", 16) == 1);
+        assert!(network_a.lock().unwrap().announce_tip(3, "tip_block", 48) == 1);
+        let tip = tip_receiver.recv_timeout(Duration::from_secs(2)).unwrap();
+        let node_id_a = network_a.lock().unwrap().node_id.clone();
+        let peer_a = peer_of(&network_b.lock().unwrap(), &node_id_a).unwrap();
+        assert!(tip == (peer_a, 3, "tip_block".to_string(), 48));
+        thread::sleep(Duration::from_millis(100));
+        // each node requested the body of the other's tip
+        assert!(network_a.lock().unwrap().get_traffic().by_type["GetData"] == (1, 1));
+        assert!(network_b.lock().unwrap().get_traffic().by_type["GetData"] == (1, 1));
+
+        // a paused peer is not reached
+        network_a.lock().unwrap().pause_peer("127.0.0.1:9162").unwrap();
+        assert!(network_a.lock().unwrap().announce_tip(4, "tip_block_2", 64) == 0);
+        assert!(tip_receiver.recv_timeout(Duration::from_millis(200)).is_err());
+    }
+
+    /// Test that a node below its outbound limit dials the peers it learns of from the addresses shared by its neighbor.
+    #[test]
+    fn test_p2pnetwork_peer_discovery() {
+        let (_network_b, _block_in_b, _tx_in_b, _block_out_b, _tx_out_b, _req_b) = P2PNetwork::create(
+            NetAddress { ip: "127.0.0.1".to_owned(), port: 9172 },
+            vec![]
+        );
+        thread::sleep(Duration::from_millis(100));
+        let (network_a, _block_in_a, _tx_in_a, _block_out_a, _tx_out_a, _req_a) = P2PNetwork::create(
+            NetAddress { ip: "127.0.0.1".to_owned(), port: 9171 },
+            vec![NetAddress { ip: "127.0.0.1".to_owned(), port: 9172 }]
+        );
+        thread::sleep(Duration::from_millis(300));
+        // node_c only knows node_b, which knows node_a from its handshake
+        let (network_c, _block_in_c, _tx_in_c, _block_out_c, _tx_out_c, _req_c) = P2PNetwork::create(
+            NetAddress { ip: "127.0.0.1".to_owned(), port: 9173 },
+            vec![NetAddress { ip: "127.0.0.1".to_owned(), port: 9172 }]
+        );
+        network_c.lock().unwrap().set_max_outbound_peers(Some(2));
+        let node_id_c = network_c.lock().unwrap().node_id.clone();
+        let started = std::time::Instant::now();
+        while peer_of(&network_a.lock().unwrap(), &node_id_c).is_none() {
+            assert!(started.elapsed() < Duration::from_secs(5), "node_c did not dial node_a");
+            thread::sleep(Duration::from_millis(50));
+        }
+        let status_c = network_c.lock().unwrap().get_status();
+        assert!(status_c.outbound_peers == 2 && status_c.max_outbound_peers == Some(2));
+        assert!(status_c.known_addresses == 2);
+        let address_book = network_c.lock().unwrap().get_address_book();
+        assert!(address_book.iter().all(|record| record.last_seen_ms.is_some() && record.failures == 0));
+        // node_a dials no one but its neighbor without a limit
+        assert!(network_a.lock().unwrap().get_status().max_outbound_peers.is_none());
+    }
+
+    /// Test that the address book shares the live addresses only, and dials a failing address less and less often
+    /// until it is forgotten.
+    #[test]
+    fn test_address_book() {
+        let address = |port| NetAddress { ip: "127.0.0.1".to_owned(), port };
+        let mut address_book = AddressBook::default();
+        assert!(address_book.add(&address(1)));
+        assert!(!address_book.add(&address(1)));
+        address_book.mark_seen(&address(2), "node_2", 100);
+        assert!(address_book.live_addresses() == vec![address(2)]);
+        let candidates = |address_book: &AddressBook, exclude: &dyn Fn(&str) -> bool, now_ms| {
+            address_book.dial_candidates(exclude, now_ms).into_iter().map(|record| record.address).collect::<Vec<_>>()
+        };
+        assert!(candidates(&address_book, &|_| false, 100) == vec![address(2), address(1)]);
+        assert!(candidates(&address_book, &|name| name == "127.0.0.1:2", 100) == vec![address(1)]);
+
+        // an address never seen is forgotten after 3 failures, waiting 1s then 2s before the next dials
+        address_book.mark_attempt(&address(1), 1000);
+        address_book.mark_failed(&address(1));
+        assert!(!candidates(&address_book, &|_| false, 1999).contains(&address(1)));
+        assert!(candidates(&address_book, &|_| false, 2000).contains(&address(1)));
+        address_book.mark_attempt(&address(1), 2000);
+        address_book.mark_failed(&address(1));
+        assert!(!candidates(&address_book, &|_| false, 3999).contains(&address(1)));
+        address_book.mark_failed(&address(1));
+        assert!(address_book.len() == 1);
+
+        // a live address is kept longer, but no longer shared once it fails
+        address_book.mark_attempt(&address(2), 200);
+        address_book.mark_failed(&address(2));
+        assert!(address_book.live_addresses().is_empty());
+        assert!(!address_book.was_seen_since(&address(2), 200));
+        address_book.mark_seen(&address(2), "node_2", 300);
+        assert!(address_book.was_seen_since(&address(2), 200));
+        assert!(address_book.live_addresses() == vec![address(2)]);
+
+        // the records survive a restart through the peers file
+        let path = std::env::temp_dir().join(format!("lib_network_peers_{}.json", std::process::id())).to_string_lossy().to_string();
+        assert!(load_peers(&path).unwrap().is_empty());
+        save_peers(&path, &address_book.records()).unwrap();
+        let mut restored_book = AddressBook::default();
+        for record in load_peers(&path).unwrap() {
+            assert!(restored_book.restore(record));
+        }
+        assert!(restored_book.records() == address_book.records());
+        assert!(restored_book.records()[0].node_id.as_deref() == Some("node_2"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Test that the gossip bookkeeping forgets its oldest entries beyond its capacity, and that the removed keys do not
+    /// count against it
+    #[test]
+    fn test_bounded_map() {
+        let mut map = BoundedMap::new(3);
+        for key in 0..3 {
+            assert!(map.insert(key, key * 10));
+        }
+        assert!(!map.insert(0, 0) && map.get(&0) == Some(&0));
+        assert!(map.insert(3, 30));
+        assert!(!map.contains_key(&0) && map.get(&3) == Some(&30));
+        assert!(map.remove(&1) == Some(10));
+        assert!(map.insert(4, 40));
+        assert!(map.contains_key(&2) && map.contains_key(&3) && map.contains_key(&4));
+        map.retain(|key, _| *key != 2);
+        assert!(!map.contains_key(&2) && map.contains_key(&3));
+        for key in 5..1000 {
+            assert!(map.insert(key, 0));
+            map.remove(&key);
+        }
+        assert!(map.contains_key(&3) && map.contains_key(&4));
+    }
+}
@@ -2,23 +2,51 @@
 // Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
 // Please do not distribute.
 
-// This file mainly implements the NetChannelTCP struct and related methods.
-// The NetChannelTCP struct is used to send and receive messages over the network.
+// This file mainly implements the NetChannel struct and related methods.
+// The NetChannel struct is used to send and receive messages over the network: it frames, serializes, compresses and
+// counts them, so that the P2PNetwork only deals with NetMessage values.
 // The message format is defined in the NetMessage enum.
 // You can see detailed instructions in the comments below.
-// You can also look at the unit tests in ./lib.rs to understand the expected behavior of the NetChannelTCP.
+// You can also look at the unit tests in ./lib.rs to understand the expected behavior of the NetChannel.
 
 
 use std::{io::BufRead};
 use lib_chain::block::{BlockNode, Transaction, BlockId};
+use crate::identity::NodeId;
 use std::{hash::Hash};
+use schemars::JsonSchema;
 use serde::{Serialize, Deserialize};
 use std::net::{TcpStream};
 use std::io::{Read, Write};
 use std::io::BufReader;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use base64ct::{Base64, Encoding};
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use socket2::{SockRef, TcpKeepalive};
+
+/// How long (in ms) a writer may stay idle before it sends a `Ping`, so that a live connection is never idle for long.
+pub const KEEPALIVE_INTERVAL_MS: u64 = 2000;
+
+/// How long (in ms) a connection may stay silent before it is considered dead, e.g. when the peer's laptop is suspended.
+/// It covers a few missed `Ping` messages.
+pub const IDLE_TIMEOUT_MS: u64 = 3 * KEEPALIVE_INTERVAL_MS;
+
+/// How long (in ms) a connection may stay silent before the OS starts sending TCP keepalive probes, and between the probes.
+const TCP_KEEPALIVE_TIME_MS: u64 = 5000;
+const TCP_KEEPALIVE_PROBE_INTERVAL_MS: u64 = 1000;
+
+/// The size (in bytes of json) from which a message is sent compressed, e.g. a block with many transactions.
+pub const COMPRESSION_THRESHOLD: usize = 1024;
+
+/// The start of a compressed frame, which no json message starts with.
+pub const COMPRESSED_FRAME_PREFIX: &str = "z:";
 
 /// The struct to represent a network address.
-#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Serialize, Deserialize, Debug)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Serialize, Deserialize, JsonSchema, Debug)]
 pub struct NetAddress {
     /// the ip address. Example: "127.0.0.1"
     pub ip: String,
@@ -33,92 +61,281 @@ impl NetAddress {
 }
 
 
-/// The enum to represent a network message that is sent or received using `NetChannelTCP`.
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+/// The id that follows a broadcast block or transaction across all the hops of its gossip.
+pub type TraceId = String;
+
+/// The id that matches a block request with its response along the path the request was forwarded on.
+pub type RequestId = String;
+
+/// The enum to represent a network message that is sent or received using `NetChannel`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub enum NetMessage {
     /// The message to broadcast a block to the neighbor.
     BroadcastBlock(BlockNode),
     /// The message to broadcast a transaction to the neighbor.
     BroadcastTx(Transaction),
-    /// The message to request a block (i.e. missing in the local block tree) from neighbor.
-    RequestBlock(BlockId),
+    /// The message to request a block (i.e. missing in the local block tree) from neighbor (request_id, block_id, hops_left).
+    /// A neighbor that does not have the block forwards the request while `hops_left` is positive.
+    RequestBlock(RequestId, BlockId, u8),
+    /// The message to answer a block request. It is routed back along the reverse path of the request (request_id, block).
+    ResponseBlock(RequestId, BlockNode),
+    /// The message to announce the ids of the blocks the sender has, e.g. a block it just mined or received.
+    /// The neighbor requests the bodies it is missing with `GetData`.
+    Inventory(Vec<BlockId>),
+    /// The message to request the bodies of announced blocks from the neighbor that announced them (block_ids).
+    GetData(Vec<BlockId>),
+    /// The message to answer `GetData` with the body of one block; the blocks the sender does not have are left out.
+    BlockData(BlockNode),
+    /// The message to announce a block just mined by the sender ahead of its body (height, block_id, branch work), so that
+    /// the neighbor can stop mining on a branch with less work. The neighbor requests the body with `GetData`.
+    Tip(u64, BlockId, u128),
+    /// The message to ask the neighbor for the listening addresses of the peers it knows, answered with `Addr`.
+    GetAddr,
+    /// The message to share the listening addresses of live peers (addresses), e.g. to answer `GetAddr`.
+    Addr(Vec<NetAddress>),
+    /// The message to relay a broadcast message together with the trace id assigned by the node it originated from.
+    Traced(TraceId, Box<NetMessage>),
+    /// The first message on every connection, carrying a random nonce (challenge) that the other end signs in its `Hello`,
+    /// so that a `Hello` captured on one connection cannot be replayed on another one.
+    Challenge(String),
+    /// The answer to the `Challenge` of the other end, introducing the sender (node_id, pub_key_pem, listen_address, signature).
+    /// The signature covers `NetMessage::hello_signed_payload` with the nonce of the challenge, and proves the sender owns
+    /// the key its node id is derived from.
+    Hello(NodeId, String, NetAddress, String),
+    /// The message signed by the node at the other end of the connection (signature, message).
+    /// The signature covers the json serialization of the message.
+    Signed(String, Box<NetMessage>),
+    /// The message sent on a connection that has been idle for `KEEPALIVE_INTERVAL_MS`, so that the other end can tell a
+    /// quiet connection from a dead one, and right after the handshake. It carries the time of the sender (ms since the
+    /// UNIX epoch), which the other end echoes in a `Pong`.
+    Ping(u64),
+    /// The answer to a `Ping` (time of the ping, time of the sender when answering), from which the node that sent the
+    /// ping estimates how far the clock of the other end is ahead of its own.
+    Pong(u64, u64),
+    /// The message telling a node that dialed this node that its connection is refused because this node already has
+    /// as many inbound peers as it accepts. The connection is closed right after it.
+    Busy,
     /// The message to represent other temporary messages (e.g. for debugging).
     Unknown(String)
 }
 
+impl NetMessage {
+    /// The name of the message type, looking through the `Traced` and `Signed` wrappers, e.g. "BroadcastBlock".
+    pub fn kind(&self) -> &'static str {
+        match self {
+            NetMessage::Traced(_, msg) | NetMessage::Signed(_, msg) => msg.kind(),
+            NetMessage::BroadcastBlock(_) => "BroadcastBlock",
+            NetMessage::BroadcastTx(_) => "BroadcastTx",
+            NetMessage::RequestBlock(..) => "RequestBlock",
+            NetMessage::ResponseBlock(..) => "ResponseBlock",
+            NetMessage::Inventory(_) => "Inventory",
+            NetMessage::GetData(_) => "GetData",
+            NetMessage::BlockData(_) => "BlockData",
+            NetMessage::Tip(..) => "Tip",
+            NetMessage::GetAddr => "GetAddr",
+            NetMessage::Addr(_) => "Addr",
+            NetMessage::Challenge(_) => "Challenge",
+            NetMessage::Hello(..) => "Hello",
+            NetMessage::Ping(_) => "Ping",
+            NetMessage::Pong(..) => "Pong",
+            NetMessage::Busy => "Busy",
+            NetMessage::Unknown(_) => "Unknown",
+        }
+    }
+
+    /// The payload signed in a `Hello` message answering the challenge (nonce) of the other end of the connection.
+    pub fn hello_signed_payload(node_id: &str, listen_address: &NetAddress, nonce: &str) -> String {
+        format!("hello:{}:{}:{}:{}", node_id, listen_address.ip, listen_address.port, nonce)
+    }
+}
+
+/// The statistics of a `NetChannel`, counted on the wire (after compression) for the bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChannelStats {
+    /// the number of messages sent
+    pub msgs_sent: u64,
+    /// the number of messages received (and parsed)
+    pub msgs_received: u64,
+    /// the number of bytes sent, including the line breaks
+    pub bytes_sent: u64,
+    /// the number of bytes received, including the line breaks
+    pub bytes_received: u64,
+    /// the number of messages sent compressed
+    pub compressed_msgs_sent: u64,
+}
+
+/// The direction of a message on a `NetChannel`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// A function told about every message sent or received on a `NetChannel` and its clones
+/// (peer if known, direction, message, bytes on the wire).
+pub type AccountingHook = Arc<dyn Fn(Option<&str>, Direction, &NetMessage, usize) + Send + Sync>;
+
+/// The counters of a `NetChannel`, shared by its clones (e.g. by the reader and the writer thread of a connection).
+#[derive(Default)]
+struct ChannelMetrics {
+    msgs_sent: AtomicU64,
+    msgs_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    compressed_msgs_sent: AtomicU64,
+    /// The name of the peer at the other end, once known
+    peer: Mutex<Option<String>>,
+    /// The function told about every message, if any
+    hook: Mutex<Option<AccountingHook>>,
+}
+
+impl ChannelMetrics {
+    /// Tell the accounting hook (if any) about a message.
+    fn account(&self, direction: Direction, msg: &NetMessage, bytes: usize) {
+        let hook = self.hook.lock().unwrap().clone();
+        if let Some(hook) = hook {
+            let peer = self.peer.lock().unwrap().clone();
+            hook(peer.as_deref(), direction, msg, bytes);
+        }
+    }
+}
+
 /// The struct to represent a network channel that is used to send and receive messages to a neighbor node.
-pub struct NetChannelTCP {
+/// Each message is one frame: a line holding either the json of the message, or, for a message of at least
+/// `COMPRESSION_THRESHOLD` bytes, `COMPRESSED_FRAME_PREFIX` followed by the deflated json in Base64.
+pub struct NetChannel {
     /// The TCP stream
     stream: TcpStream,
     /// The reader to read from the TCP stream
-    reader: BufReader<TcpStream>
+    reader: BufReader<TcpStream>,
+    /// The counters, shared with the clones of the channel
+    metrics: Arc<ChannelMetrics>,
 }
 
-impl NetChannelTCP {
-    /// Create a new NetChannelTCP from a NetAddress and establish the TCP connection.
+impl NetChannel {
+    /// Create a new NetChannel from a NetAddress and establish the TCP connection.
     /// Return an error string if the connection fails.
     pub fn from_addr(addr: &NetAddress) -> Result<Self,String> {
-        // Please fill in the blank
-        //todo!();
         let stream = TcpStream::connect(format!("{}:{}", addr.ip, addr.port))
-            .map_err(|e| format!("Error connecting to address {}:{}", addr.ip, addr.port))?;
-        let reader = BufReader::new(stream.try_clone().unwrap());
-        Ok(Self {
-            stream,
-            reader
-        })
-        
+            .map_err(|_e| format!("Error connecting to address {}:{}", addr.ip, addr.port))?;
+        Ok(Self::from_stream(stream))
     }
 
-    /// Create a new NetChannelTCP from a TcpStream. 
-    /// This is useful for creating a NetChannelTCP instance from the listener side.
+    /// Create a new NetChannel from a TcpStream. 
+    /// This is useful for creating a NetChannel instance from the listener side.
     pub fn from_stream(stream: TcpStream) -> Self {
-        // Please fill in the blank
-        //todo!();
+        set_keepalive(&stream);
         let reader = BufReader::new(stream.try_clone().unwrap());
         Self {
             stream,
-            reader
+            reader,
+            metrics: Arc::new(ChannelMetrics::default()),
         }
-        
     }
 
-    /// Clone the NetChannelTCP instance.
+    /// Clone the NetChannel instance, sharing its counters.
     /// This is useful if you have multiple threads dealing with reading and writing to the TCP channel.
     pub fn clone_channel(&mut self) -> Self {
-        // Please fill in the blank
-        //todo!();
         Self {
             stream: self.stream.try_clone().unwrap(),
             reader: BufReader::new(self.stream.try_clone().unwrap()),
+            metrics: self.metrics.clone(),
         }
-        
     }
 
-    /// Read one line of message from the TCP stream.
-    /// Return None if the stream is closed.
-    /// Otherwise, parse the line as a NetMessage and return it.
-    pub fn read_msg(&mut self) -> Option<NetMessage> {
-        // Please fill in the blank
-        //todo!();
+    /// Receive the next message.
+    /// Return None if the stream is closed, if nothing is received for `IDLE_TIMEOUT_MS`, or if the frame is malformed.
+    pub fn recv_msg(&mut self) -> Option<NetMessage> {
         let mut line = String::new();
-        match self.reader.read_line(&mut line) {
-            Ok(0) => None,
-            Ok(_) => serde_json::from_str(&line.trim()).ok(),
-            Err(_) => None,
+        let len = match self.reader.read_line(&mut line) {
+            Ok(0) | Err(_) => return None,
+            Ok(len) => len,
+        };
+        self.metrics.bytes_received.fetch_add(len as u64, Ordering::Relaxed);
+        let msg = decode_frame(line.trim())?;
+        self.metrics.msgs_received.fetch_add(1, Ordering::Relaxed);
+        self.metrics.account(Direction::Received, &msg, len);
+        Some(msg)
+    }
+
+    /// Send a message.
+    /// Return false if the stream is broken, or if the message cannot be written within `IDLE_TIMEOUT_MS`.
+    pub fn send_msg(&mut self, msg: NetMessage) -> bool {
+        let (frame, compressed) = encode_frame(&msg);
+        if self.stream.write_all(&frame).is_err() {
+            return false;
         }
-        
+        self.metrics.msgs_sent.fetch_add(1, Ordering::Relaxed);
+        self.metrics.bytes_sent.fetch_add(frame.len() as u64, Ordering::Relaxed);
+        if compressed {
+            self.metrics.compressed_msgs_sent.fetch_add(1, Ordering::Relaxed);
+        }
+        self.metrics.account(Direction::Sent, &msg, frame.len());
+        true
+    }
+
+    /// Set the function told about every message sent or received on this channel and its clones.
+    pub fn set_accounting_hook(&self, hook: AccountingHook) {
+        *self.metrics.hook.lock().unwrap() = Some(hook);
+    }
+
+    /// Set the name of the peer at the other end of this channel and its clones, as given to the accounting hook.
+    pub fn set_peer(&self, peer: &str) {
+        *self.metrics.peer.lock().unwrap() = Some(peer.to_string());
     }
 
-    /// Write a NetMessage to the TCP stream.
-    /// The message is serialized to a one-line JSON string and a newline is appended in the end.
-    pub fn write_msg(&mut self, msg: NetMessage) -> () {
-        // Please fill in the blank
-        let serialized_msg = serde_json::to_string(&msg).unwrap();
-        let mut buf = serialized_msg.as_bytes().to_vec();
-        buf.push(b'\n');
-        self.stream.write_all(&buf).unwrap();
+    /// Get the statistics of the channel and its clones.
+    pub fn stats(&self) -> ChannelStats {
+        ChannelStats {
+            msgs_sent: self.metrics.msgs_sent.load(Ordering::Relaxed),
+            msgs_received: self.metrics.msgs_received.load(Ordering::Relaxed),
+            bytes_sent: self.metrics.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.metrics.bytes_received.load(Ordering::Relaxed),
+            compressed_msgs_sent: self.metrics.compressed_msgs_sent.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Encode a message as one frame, terminated by a line break. Return the frame and whether it is compressed.
+pub fn encode_frame(msg: &NetMessage) -> (Vec<u8>, bool) {
+    let json = serde_json::to_string(msg).unwrap();
+    let (mut frame, compressed) = if json.len() >= COMPRESSION_THRESHOLD {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(json.as_bytes()).unwrap();
+        let deflated = encoder.finish().unwrap();
+        (format!("{}{}", COMPRESSED_FRAME_PREFIX, Base64::encode_string(&deflated)).into_bytes(), true)
+    } else {
+        (json.into_bytes(), false)
+    };
+    frame.push(b'\n');
+    (frame, compressed)
+}
+
+/// Decode a frame (without its line break), compressed or not. Return None if it is malformed.
+pub fn decode_frame(frame: &str) -> Option<NetMessage> {
+    match frame.strip_prefix(COMPRESSED_FRAME_PREFIX) {
+        Some(encoded) => {
+            let deflated = Base64::decode_vec(encoded).ok()?;
+            let mut json = String::new();
+            DeflateDecoder::new(deflated.as_slice()).read_to_string(&mut json).ok()?;
+            serde_json::from_str(&json).ok()
+        }
+        None => serde_json::from_str(frame).ok(),
+    }
+}
+
+/// Enable TCP keepalive and the idle timeouts on a stream, so that a half-open connection does not block its reader
+/// or its writer forever.
+fn set_keepalive(stream: &TcpStream) {
+    let keepalive = TcpKeepalive::new()
+        .with_time(Duration::from_millis(TCP_KEEPALIVE_TIME_MS))
+        .with_interval(Duration::from_millis(TCP_KEEPALIVE_PROBE_INTERVAL_MS));
+    if let Err(e) = SockRef::from(stream).set_tcp_keepalive(&keepalive) {
+        eprintln!("[NetChannel] Cannot enable TCP keepalive: {}", e);
     }
+    let idle_timeout = Some(Duration::from_millis(IDLE_TIMEOUT_MS));
+    let _ = stream.set_read_timeout(idle_timeout);
+    let _ = stream.set_write_timeout(idle_timeout);
 }
 
 
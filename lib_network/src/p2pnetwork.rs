@@ -2,8 +2,11 @@
 // Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
 // Please do not distribute.
 
+use crate::addrbook::{AddressBook, PeerRecord, MAX_ADDR_LEN};
+use crate::connmgr::{ConnectionManager, PeerHealth};
+use crate::identity::{node_id_of, short_node_id, verify_signature, NodeId, NodeIdentity};
 use crate::netchannel::*;
-use futures::{select, stream};
+use crate::peerfilter::PeerFilter;
 /// P2PNetwork is a struct that implements a peer-to-peer network.
 /// It is used to send and receive messages to/from neighbors.
 /// It also automatically broadcasts messages.
@@ -12,25 +15,446 @@ use futures::{select, stream};
 use lib_chain::block::{BlockId, BlockNode, Transaction, TxId};
 use rand::thread_rng;
 use rand::Rng;
-use std::collections::{BTreeMap, HashMap, HashSet};
-use std::io::{BufRead, BufReader, BufWriter, Read, Result, Write};
-use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, TcpListener, TcpStream, ToSocketAddrs};
-use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
-use std::sync::{mpsc, Arc, Mutex};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::hash::Hash;
+use std::net::TcpListener;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
-use std::{convert, io};
+use std::time::{Duration, SystemTime};
+
+/// The maximum number of gossip trace events kept by a node. Older events are dropped first.
+const MAX_TRACE_EVENTS: usize = 10_000;
+
+/// The maximum number of messages held for a connection until the `Hello` of this node is written on it.
+const MAX_HELD_MSGS: usize = 1000;
+
+/// The peer name recorded for blocks and transactions that originate from this node.
+const LOCAL_PEER: &str = "local";
+
+/// How many times a block request may be forwarded before it is dropped.
+/// It covers the diameter of the four-node demo network.
+pub const BLOCK_REQUEST_HOP_LIMIT: u8 = 4;
+
+/// The number of recently gossiped blocks kept to answer `GetData`, before looking up the local chain.
+const MAX_CACHED_BLOCKS: usize = 64;
+
+/// The maximum number of block ids handled from one `Inventory` or `GetData` message.
+const MAX_INVENTORY_LEN: usize = 500;
+
+/// How long (in ms) an announced block requested from one neighbor is not requested again from another one.
+const GET_DATA_TIMEOUT_MS: u64 = 2000;
+
+/// The number of block and transaction ids (and block sources) remembered by a node. Older ones are forgotten first.
+const MAX_SEEN_IDS: usize = 10_000;
+
+/// The maximum number of block requests in flight. The oldest one is forgotten first.
+const MAX_PENDING_BLOCK_REQUESTS: usize = 1000;
+
+/// How long (in ms) a block request in flight waits for its response before it is forgotten.
+const BLOCK_REQUEST_TIMEOUT_MS: u64 = 10_000;
+
+/// How often (in ms) the node looks for a known address to dial while it has fewer outbound peers than its limit.
+const DISCOVERY_INTERVAL_MS: u64 = 1000;
+
+/// How often (in ms) the node asks a peer for more addresses while it has fewer outbound peers than its limit.
+const GET_ADDR_INTERVAL_MS: u64 = 5000;
+
+/// The score change of a peer for each new block or transaction it delivers first.
+const PEER_SCORE_NEW_ITEM: i64 = 1;
+
+/// The score change of a peer for each message with an invalid signature.
+const PEER_SCORE_BAD_SIGNATURE: i64 = -10;
+
+/// The longest round trip (in ms) of a ping whose pong is used to estimate the clock offset of a peer, since the estimate
+/// may be off by half of it.
+const MAX_PING_RTT_MS: u64 = 5000;
+
+/// The largest network time offset (in ms) that is applied. A median offset beyond it means that the clock of this node
+/// is badly wrong or that its peers are lying, so the time of this node is left as it is.
+pub const MAX_TIME_OFFSET_MS: i64 = 70 * 60 * 1000;
+
+/// A function looking up a block in the local chain, used to answer block requests from neighbors.
+pub type BlockProvider = Arc<dyn Fn(&str) -> Option<BlockNode> + Send + Sync>;
+
+/// A function called with the name ("ip:port") of every peer that completes the handshake.
+pub type PeerListener = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// A function called with the name ("ip:port") of a peer and the tip it announced (height, block_id, branch work).
+pub type TipListener = Arc<dyn Fn(&str, u64, &str, u128) + Send + Sync>;
+
+/// The struct to represent one hop of a gossiped block or transaction, as seen by this node.
+/// Joining the events of all nodes on `trace_id` gives the propagation tree of a block or transaction.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GossipTraceEvent {
+    /// The trace id assigned by the node the block or transaction originated from
+    pub trace_id: TraceId,
+    /// Either "block" or "tx"
+    pub kind: String,
+    /// The block id or the transaction id
+    pub item_id: String,
+    /// The peer it was first received from, or "local" if it originated from this node
+    pub from: String,
+    /// The time (in milliseconds since the UNIX epoch) when this node first saw it
+    pub at_ms: u64,
+}
+
+/// The struct to represent the messages sent and received by a node, as counted on its connections.
+/// The counts are pairs of (sent, received).
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct TrafficStats {
+    /// The number of messages sent by this node, including handshakes and pings
+    pub msgs_sent: u64,
+    /// The number of messages received by this node, including handshakes and pings
+    pub msgs_received: u64,
+    /// The number of bytes written to the connections
+    pub bytes_sent: u64,
+    /// The number of bytes read from the connections
+    pub bytes_received: u64,
+    /// The message counts of each peer (as "ip:port"), once its listening address is known
+    pub by_peer: BTreeMap<String, (u64, u64)>,
+    /// The message counts of each message type (see `NetMessage::kind`)
+    pub by_type: BTreeMap<String, (u64, u64)>,
+}
+
+impl TrafficStats {
+    /// Count one message sent to or received from a peer.
+    fn record(&mut self, peer: Option<&str>, direction: Direction, msg: &NetMessage, bytes: usize) {
+        let count = |counts: &mut (u64, u64)| match direction {
+            Direction::Sent => counts.0 += 1,
+            Direction::Received => counts.1 += 1,
+        };
+        match direction {
+            Direction::Sent => {
+                self.msgs_sent += 1;
+                self.bytes_sent += bytes as u64;
+            }
+            Direction::Received => {
+                self.msgs_received += 1;
+                self.bytes_received += bytes as u64;
+            }
+        }
+        if let Some(peer) = peer {
+            count(self.by_peer.entry(peer.to_string()).or_default());
+        }
+        count(self.by_type.entry(msg.kind().to_string()).or_default());
+    }
+}
+
+/// A connected peer of a node, as shown in its status.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PeerStatus {
+    /// The address of the connection to the peer (as "ip:port", see `P2PNetwork::connections`)
+    pub address: String,
+    /// The listening address the peer claims in its handshake (as "ip:port"); it is not verified
+    #[serde(default)]
+    pub listen_address: String,
+    /// The node id of the peer
+    pub node_id: NodeId,
+    /// The score of the peer (see `P2PNetwork::peer_scores`)
+    pub score: i64,
+}
+
+/// The status of a P2PNetwork (see `P2PNetwork::get_status`).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NetworkStatus {
+    /// The id of this node
+    pub node_id: NodeId,
+    /// The address of this node
+    pub address: NetAddress,
+    /// The connected peers, sorted by address
+    pub peers: Vec<PeerStatus>,
+    /// The health of the connection to each peer ever dialed or connected (as "ip:port")
+    pub peer_health: BTreeMap<String, PeerHealth>,
+    /// The messages sent and received by this node
+    pub traffic: TrafficStats,
+    /// The number of blocks requested from a peer and not received yet
+    pub requested_blocks: usize,
+    /// The number of inbound connections
+    pub inbound_peers: usize,
+    /// The maximum number of inbound connections, if any
+    pub max_inbound_peers: Option<usize>,
+    /// The number of outbound connections
+    pub outbound_peers: usize,
+    /// The maximum number of outbound connections, if the node dials more than its neighbors
+    pub max_outbound_peers: Option<usize>,
+    /// The number of addresses in the address book
+    pub known_addresses: usize,
+    /// The estimated offset (in ms) of the clock of this node from the clocks of its peers
+    pub time_offset_ms: i64,
+    /// The number of peers whose clock offset is sampled
+    pub time_offset_samples: usize,
+    /// The peers from which no block or transaction is accepted, sorted
+    pub paused_peers: Vec<String>,
+    /// The only peers allowed to connect, if restricted
+    pub allowed_peers: Option<Vec<String>>,
+    /// The peers not allowed to connect
+    pub blocked_peers: Vec<String>,
+}
+
+/// The limits on the connections accepted by the listener of a node, e.g. to protect a demo node on a shared network.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InboundLimits {
+    /// The maximum number of inbound connections at a time, if any. Further connections are told `Busy` and closed.
+    pub max_inbound_peers: Option<usize>,
+    /// Whether the listener binds to the loopback interface only, so that only the nodes on the same machine can connect.
+    pub localhost_only: bool,
+}
 
 /// The struct to represent statistics of a peer-to-peer network.
 pub struct P2PNetwork {
-    /// The number of messages sent by this node.
-    pub send_msg_count: u64,
-    /// The number of messages received by this node.
-    pub recv_msg_count: u64,
+    /// The id of this node, derived from its node key.
+    pub node_id: NodeId,
+    /// The messages sent and received on all connections, updated by the connections themselves
+    /// (outside of the lock of the network, so that the reader and writer threads never wait for it).
+    traffic: Arc<Mutex<TrafficStats>>,
     /// The address of this node.
     pub address: NetAddress,
     /// The addresses of the neighbors.
     pub neighbors: Vec<NetAddress>,
+    /// A map from block id to the peer (as "ip:port") that first delivered the block, for the most recent blocks.
+    block_sources: BoundedMap<BlockId, String>,
+    /// The most recent gossip trace events, from the oldest to the most recent.
+    pub trace_log: VecDeque<GossipTraceEvent>,
+    /// The ids of the most recent blocks delivered or broadcast, so that each block is relayed only once.
+    seen_block_ids: BoundedMap<BlockId, ()>,
+    /// The ids of the most recent transactions delivered or broadcast, so that each transaction is relayed only once.
+    seen_tx_ids: BoundedMap<TxId, ()>,
+    /// The most recently gossiped blocks, from the oldest to the most recent, to answer `GetData` without the chain.
+    block_cache: VecDeque<BlockNode>,
+    /// The announced blocks requested with `GetData`, mapped to the trace id of the announcement and the time of the request.
+    requested_blocks: HashMap<BlockId, (TraceId, u64)>,
+    /// The channels to the writer threads of the connected neighbors.
+    peer_senders: Vec<(NetAddress, Sender<NetMessage>)>,
+    /// A map from peer (as "ip:port" of the socket of its connection: its listener if dialed by this node, an ephemeral
+    /// port if it dialed this node) to its connection, for every peer that completed the handshake.
+    connections: HashMap<String, Connection>,
+    /// The id of the next connection completing the handshake.
+    next_connection_id: u64,
+    /// The neighbors (as "ip:port") whose traffic is currently dropped, e.g. to simulate a network partition.
+    paused_peers: HashSet<String>,
+    /// The block requests in flight, mapped to the connection the response is routed back to (None if requested by this node)
+    /// and the time of the request. They are forgotten after `BLOCK_REQUEST_TIMEOUT_MS`.
+    pending_block_requests: BoundedMap<RequestId, (Option<Sender<NetMessage>>, u64)>,
+    /// The lookup used to answer block requests locally, if set by the owner of the chain.
+    block_provider: Option<BlockProvider>,
+    /// The function told about the peers connecting, if set by the owner of the network.
+    peer_listener: Option<PeerListener>,
+    /// The function called with the tips announced by peers.
+    tip_listener: Option<TipListener>,
+    /// A map from peer (as "ip:port" of its connection, see `connections`) to the node id it proved in the handshake.
+    pub peer_ids: HashMap<String, NodeId>,
+    /// A map from node id to its score: new blocks and txs raise it, invalid signatures lower it.
+    pub peer_scores: HashMap<NodeId, i64>,
+    /// The limits on the inbound connections.
+    inbound_limits: InboundLimits,
+    /// The number of inbound connections currently open.
+    inbound_peer_count: usize,
+    /// The allowlist and blocklist of peers, checked when connecting and on every message.
+    peer_filter: PeerFilter,
+    /// The listening addresses of the peers this node has heard of, with their liveness.
+    address_book: AddressBook,
+    /// The number of peers this node dials itself (including the neighbors) before it stops dialing the addresses
+    /// learned from its peers. None to only dial the neighbors.
+    max_outbound_peers: Option<usize>,
+    /// The addresses (as "ip:port") learned from peers that are being dialed.
+    dialing: HashSet<String>,
+    /// A map from peer (as "ip:port" of its connection, see `connections`) to how far its clock is ahead of the clock of
+    /// this node (in ms), estimated from the last pong on its connection.
+    time_offsets: HashMap<String, i64>,
+    /// The health of the connection to each peer, and the backoff of the dials of the neighbors.
+    peer_health: ConnectionManager,
+}
+
+/// A connection of a peer that completed the handshake (see `P2PNetwork::connections`).
+struct Connection {
+    /// the id of the connection, so that a closed connection does not forget the one that replaced it
+    id: u64,
+    /// the channel writing to the connection
+    sender: Sender<NetMessage>,
+    /// the listening address the peer claims in its Hello: where it can be dialed, but not a proof of who it is, so
+    /// it never names the connection and can only get the peer rejected by the peer filter
+    listen_address: NetAddress,
+}
+
+/// The identity this node proves in the handshake of every connection, by answering the challenge of the other end.
+#[derive(Clone)]
+struct Handshake {
+    /// the key of this node
+    identity: Arc<NodeIdentity>,
+    /// the listening address of this node
+    address: NetAddress,
+}
+
+impl Handshake {
+    /// The `Hello` answering the challenge (nonce) of the other end of a connection.
+    fn hello(&self, nonce: &str) -> NetMessage {
+        let node_id = self.identity.node_id();
+        let signature = self.identity.sign(&NetMessage::hello_signed_payload(
+            &node_id,
+            &self.address,
+            nonce,
+        ));
+        NetMessage::Hello(
+            node_id,
+            self.identity.pub_key_pem.clone(),
+            self.address.clone(),
+            signature,
+        )
+    }
+}
+
+/// The hook counting the messages of a connection in the given traffic.
+fn accounting_hook(traffic: &Arc<Mutex<TrafficStats>>) -> AccountingHook {
+    let traffic = traffic.clone();
+    Arc::new(move |peer, direction, msg, bytes| {
+        traffic.lock().unwrap().record(peer, direction, msg, bytes)
+    })
+}
+
+/// Spawn a thread writing the messages sent on the returned channel to the given TCP channel, and a `Ping` whenever
+/// no message is sent for `KEEPALIVE_INTERVAL_MS`. The thread stops when the channel is dropped or the connection breaks.
+/// Until the `Hello` of this node is written, only the handshake and the pings are: the other messages are held (up to
+/// `MAX_HELD_MSGS`, the next ones are dropped), so that the other end knows this node before it reads them.
+/// If a signer is given, every message but the handshake and the pings is wrapped in a `Signed` message.
+fn spawn_writer(
+    mut net_channel: NetChannel,
+    signer: Option<Arc<NodeIdentity>>,
+) -> Sender<NetMessage> {
+    let (sender, receiver) = channel::<NetMessage>();
+    thread::spawn(move || {
+        let mut held_msgs: Option<Vec<NetMessage>> = Some(Vec::new());
+        loop {
+            let msg = match receiver.recv_timeout(Duration::from_millis(KEEPALIVE_INTERVAL_MS)) {
+                Ok(msg) => msg,
+                Err(RecvTimeoutError::Timeout) => NetMessage::Ping(now_ms()),
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            let is_handshake = matches!(
+                msg,
+                NetMessage::Challenge(_) | NetMessage::Hello(..) | NetMessage::Ping(_)
+            );
+            let msgs = match held_msgs.as_mut() {
+                // Beyond the limit, the messages are dropped
+                Some(held) if !is_handshake => {
+                    if held.len() < MAX_HELD_MSGS {
+                        held.push(msg);
+                    }
+                    continue;
+                }
+                Some(_) if matches!(msg, NetMessage::Hello(..)) => {
+                    let mut msgs = vec![msg];
+                    msgs.append(&mut held_msgs.take().unwrap_or_default());
+                    msgs
+                }
+                _ => vec![msg],
+            };
+            for msg in msgs {
+                let msg = match (&signer, msg) {
+                    (Some(identity), msg)
+                        if !matches!(
+                            msg,
+                            NetMessage::Challenge(_) | NetMessage::Hello(..) | NetMessage::Ping(_)
+                        ) =>
+                    {
+                        let signature = identity.sign(&serde_json::to_string(&msg).unwrap());
+                        NetMessage::Signed(signature, Box::new(msg))
+                    }
+                    (_, msg) => msg,
+                };
+                if !net_channel.send_msg(msg) {
+                    return;
+                }
+            }
+        }
+    });
+    sender
+}
+
+/// A map that forgets its oldest entries beyond a capacity, so that the bookkeeping of the gossip does not grow with
+/// every item a peer sends.
+pub(crate) struct BoundedMap<K, V> {
+    /// The maximum number of entries
+    capacity: usize,
+    map: HashMap<K, V>,
+    /// The keys in the order they were inserted; the keys removed since are skipped when the oldest entry is forgotten
+    order: VecDeque<K>,
+}
+
+impl<K: Eq + Hash + Clone, V> BoundedMap<K, V> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        BoundedMap {
+            capacity,
+            map: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Insert an entry unless the key is already there, forgetting the oldest entries beyond the capacity.
+    /// Return whether the key is new.
+    pub(crate) fn insert(&mut self, key: K, value: V) -> bool {
+        if self.map.contains_key(&key) {
+            return false;
+        }
+        if self.order.len() >= 2 * self.capacity {
+            let map = &self.map;
+            self.order.retain(|key| map.contains_key(key));
+        }
+        while self.map.len() >= self.capacity {
+            match self.order.pop_front() {
+                Some(oldest) => {
+                    self.map.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+        self.order.push_back(key.clone());
+        self.map.insert(key, value);
+        true
+    }
+
+    pub(crate) fn contains_key(&self, key: &K) -> bool {
+        self.map.contains_key(key)
+    }
+
+    pub(crate) fn get(&self, key: &K) -> Option<&V> {
+        self.map.get(key)
+    }
+
+    pub(crate) fn remove(&mut self, key: &K) -> Option<V> {
+        self.map.remove(key)
+    }
+
+    /// Keep only the entries satisfying the predicate.
+    pub(crate) fn retain(&mut self, mut keep: impl FnMut(&K, &V) -> bool) {
+        self.map.retain(|key, value| keep(key, value));
+        let map = &self.map;
+        self.order.retain(|key| map.contains_key(key));
+    }
+}
+
+/// The current time in milliseconds since the UNIX epoch.
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// The "ip:port" name of a neighbor, as used to pause and resume it.
+fn peer_name(addr: &NetAddress) -> String {
+    format!("{}:{}", addr.ip, addr.port)
+}
+
+/// Generate a fresh random id, used for trace ids and request ids.
+fn new_random_id() -> String {
+    format!("{:016x}", thread_rng().gen::<u64>())
+}
+
+/// A new random nonce, challenging the other end of a connection to sign it in its `Hello`.
+fn new_nonce() -> String {
+    format!("{:032x}", thread_rng().gen::<u128>())
 }
 
 impl P2PNetwork {
@@ -46,6 +470,11 @@ impl P2PNetwork {
     /// 3. Sender<BlockNode>: write to this FIFO channel to broadcast a block to the network.
     /// 4. Sender<Transaction>: write to this FIFO channel to broadcast a transaction to the network.
     /// 5. Sender<BlockId>: write to this FIFO channel to request a block from the network.
+    ///
+    /// Channels 3 and 4 are a shorthand for `broadcast_block` and `broadcast_tx`, and `send_to` addresses one peer.
+    ///
+    /// The node gets a fresh node key; use `create_with_identity` to keep the node id across restarts.
+    #[allow(clippy::type_complexity)]
     pub fn create(
         address: NetAddress,
         neighbors: Vec<NetAddress>,
@@ -56,6 +485,36 @@ impl P2PNetwork {
         Sender<BlockNode>,
         Sender<Transaction>,
         Sender<BlockId>,
+    ) {
+        P2PNetwork::create_with_identity(
+            address,
+            neighbors,
+            NodeIdentity::generate(),
+            false,
+            PeerFilter::default(),
+            InboundLimits::default(),
+        )
+    }
+
+    /// Same as `create`, with the given node key and peer filter.
+    /// The node introduces itself to every connection with a signed handshake,
+    /// and if `sign_messages` is set, it also signs every message it sends.
+    /// The neighbors rejected by the peer filter are not dialed, and the accepted connections are limited by `inbound_limits`.
+    #[allow(clippy::type_complexity)]
+    pub fn create_with_identity(
+        address: NetAddress,
+        neighbors: Vec<NetAddress>,
+        identity: NodeIdentity,
+        sign_messages: bool,
+        peer_filter: PeerFilter,
+        inbound_limits: InboundLimits,
+    ) -> (
+        Arc<Mutex<P2PNetwork>>,
+        Receiver<BlockNode>,
+        Receiver<Transaction>,
+        Sender<BlockNode>,
+        Sender<Transaction>,
+        Sender<BlockId>,
     ) {
         // Please fill in the blank
         // You might need to perform the following steps:
@@ -69,179 +528,238 @@ impl P2PNetwork {
         // 8. return the created P2PNetwork instance and the mpsc channels
 
         // 1. create a P2PNetwork instance
-        let p2p_network = P2PNetwork {
-            send_msg_count: 0,
-            recv_msg_count: 0,
+        let identity = Arc::new(identity);
+        let signer = sign_messages.then(|| identity.clone());
+        let node_id = identity.node_id();
+        let handshake = Handshake {
+            identity: identity.clone(),
             address: address.clone(),
-            neighbors: neighbors.clone(),
         };
+        let traffic = Arc::new(Mutex::new(TrafficStats::default()));
+        let mut address_book = AddressBook::default();
+        for neighbor in &neighbors {
+            address_book.add(neighbor);
+        }
+        let p2p_network = Arc::new(Mutex::new(P2PNetwork {
+            node_id,
+            traffic: traffic.clone(),
+            address: address.clone(),
+            neighbors: neighbors.clone(),
+            block_sources: BoundedMap::new(MAX_SEEN_IDS),
+            trace_log: VecDeque::new(),
+            seen_block_ids: BoundedMap::new(MAX_SEEN_IDS),
+            seen_tx_ids: BoundedMap::new(MAX_SEEN_IDS),
+            block_cache: VecDeque::new(),
+            requested_blocks: HashMap::new(),
+            peer_senders: Vec::new(),
+            connections: HashMap::new(),
+            next_connection_id: 0,
+            paused_peers: HashSet::new(),
+            pending_block_requests: BoundedMap::new(MAX_PENDING_BLOCK_REQUESTS),
+            block_provider: None,
+            peer_listener: None,
+            tip_listener: None,
+            peer_ids: HashMap::new(),
+            peer_scores: HashMap::new(),
+            inbound_limits: inbound_limits.clone(),
+            inbound_peer_count: 0,
+            peer_filter,
+            address_book,
+            max_outbound_peers: None,
+            dialing: HashSet::new(),
+            time_offsets: HashMap::new(),
+            peer_health: ConnectionManager::default(),
+        }));
 
         // 2. create mpsc channels for sending and receiving messages
-
-        let (block_sender, block_receiver) = channel();
-        let (tx_sender, tx_receiver) = channel();
-        let (block_id_sender, block_id_receiver) = channel();
+        // Received blocks and txs are delivered on the first pair; the second pair carries local broadcasts.
+        let (block_in_sender, block_in_receiver) = channel();
+        let (tx_in_sender, tx_in_receiver) = channel();
+        let (block_out_sender, block_out_receiver) = channel::<BlockNode>();
+        let (tx_out_sender, tx_out_receiver) = channel::<Transaction>();
+        let (block_id_sender, block_id_receiver) = channel::<BlockId>();
 
         // 3. create a thread for accepting incoming TCP connections from neighbors
-
-        let p2p_network = Arc::new(Mutex::new(p2p_network));
-        let p2p_clone = p2p_network.clone();
-        let block_sender_clone: Sender<BlockNode> = block_sender.clone();
-        let tx_sender_clone: Sender<Transaction> = tx_sender.clone();
-        let block_id_sender_clone: Sender<BlockId> = block_id_sender.clone();
-        thread::spawn(move || {
-            let socket_string = format!("{}:{}", &address.ip, &address.port);
-            let listener = TcpListener::bind(socket_string).expect("failed to bind TCP listener");
-            for stream in listener.incoming() {
-                match stream {
-                    Ok(stream) => {
-                        let p2p = p2p_clone.clone();
-                        let block_sender = block_sender_clone.clone();
-                        let tx_sender = tx_sender_clone.clone();
-                        let block_id_sender = block_id_sender_clone.clone();
-                        thread::spawn(move || {
-                            let mut reader = BufReader::new(&stream);
-                            let mut writer = BufWriter::new(&stream);
-
-                            loop {
-                                let mut msg = String::new();
-                                match reader.read_line(&mut msg) {
-                                    Ok(_) => {
-                                        let parts: Vec<&str> = msg.trim().split(":").collect();
-                                        match parts[0] {
-                                            "block" => {
-                                                let block = serde_json::from_str(parts[1]).unwrap();
-                                                block_sender.send(block).unwrap();
-                                            }
-                                            "tx" => {
-                                                let tx = serde_json::from_str(parts[1]).unwrap();
-                                                tx_sender.send(tx).unwrap();
-                                            }
-                                            "block_id" => {
-                                                let block_id =
-                                                    serde_json::from_str(parts[1]).unwrap();
-                                                block_id_sender.send(block_id).unwrap();
-                                            }
-                                            _ => {}
-                                        }
-                                    }
-                                    Err(_) => {
-                                        break;
-                                    }
+        {
+            let p2p_network = p2p_network.clone();
+            let block_in_sender = block_in_sender.clone();
+            let tx_in_sender = tx_in_sender.clone();
+            let signer = signer.clone();
+            let handshake = handshake.clone();
+            let accounting_hook = accounting_hook(&traffic);
+            thread::spawn(move || {
+                let bind_ip = match inbound_limits.localhost_only {
+                    true => "127.0.0.1",
+                    false => address.ip.as_str(),
+                };
+                let socket_string = format!("{}:{}", bind_ip, &address.port);
+                let listener =
+                    TcpListener::bind(socket_string).expect("failed to bind TCP listener");
+                for stream in listener.incoming() {
+                    match stream {
+                        Ok(stream) => {
+                            let peer = stream
+                                .peer_addr()
+                                .map(|addr| addr.to_string())
+                                .unwrap_or_else(|_| "unknown".to_string());
+                            if p2p_network.lock().unwrap().peer_filter.blocks(&peer, None) {
+                                eprintln!("[P2PNetwork] Connection from {} refused", peer);
+                                continue;
+                            }
+                            let mut net_channel = NetChannel::from_stream(stream);
+                            net_channel.set_accounting_hook(accounting_hook.clone());
+                            net_channel.set_peer(&peer);
+                            {
+                                let mut p2p = p2p_network.lock().unwrap();
+                                let max_inbound_peers = p2p.inbound_limits.max_inbound_peers;
+                                if max_inbound_peers
+                                    .is_some_and(|max| p2p.inbound_peer_count >= max)
+                                {
+                                    drop(p2p);
+                                    eprintln!(
+                                        "[P2PNetwork] Connection from {} refused: busy",
+                                        peer
+                                    );
+                                    // The connection is closed when the channel is dropped
+                                    net_channel.send_msg(NetMessage::Busy);
+                                    continue;
                                 }
+                                p2p.inbound_peer_count += 1;
                             }
-                        });
-                    }
-                    Err(e) => {
-                        println!("Error: {}", e);
+                            // Responses to block requests are written back on the same connection
+                            let reply = spawn_writer(net_channel.clone_channel(), signer.clone());
+                            let handshake = handshake.clone();
+                            let p2p_network = p2p_network.clone();
+                            let block_in_sender = block_in_sender.clone();
+                            let tx_in_sender = tx_in_sender.clone();
+                            thread::spawn(move || {
+                                P2PNetwork::listen(
+                                    net_channel,
+                                    &peer,
+                                    reply,
+                                    &handshake,
+                                    &p2p_network,
+                                    &block_in_sender,
+                                    &tx_in_sender,
+                                );
+                                let mut p2p = p2p_network.lock().unwrap();
+                                p2p.inbound_peer_count -= 1;
+                                // The ephemeral port of the peer is not dialed nor reused, so its health is forgotten
+                                p2p.peer_health.forget(&peer);
+                            });
+                        }
+                        Err(e) => {
+                            eprintln!("[P2PNetwork] Error: {}", e);
+                        }
                     }
                 }
-            }
-        });
+            });
+        }
 
         // 4. create TCP connections to all neighbors
-        let mut senders: Vec<Sender<String>> = Vec::new();
-
+        // 5. create threads for each TCP connection to send messages (see `dial`)
         for neighbor in &neighbors {
-            let socket_string = format!("{}:{}", &neighbor.ip, &neighbor.port);
-            match TcpStream::connect(socket_string) {
-                Ok(stream) => {
-                    let (sender, receiver) = channel();
-                    senders.push(sender);
-                    // Spawn a thread to send messages over the channel
-                    std::thread::spawn(move || {
-                        let mut writer = BufWriter::new(&stream);
-                        loop {
-                            match receiver.recv() {
-                                Ok(msg) => {
-                                    if let Err(e) = writer.write(msg.as_bytes()) {
-                                        println!("Error: {}", e);
-                                        break;
-                                    }
-                                    if let Err(e) = writer.flush() {
-                                        println!("Error: {}", e);
-                                        break;
-                                    }
+            let connection = P2PNetwork::dial(&p2p_network, neighbor, &signer);
+
+            // 6. create threads to listen to messages from neighbors, dialing them again when the connection is lost
+            let p2p_network = p2p_network.clone();
+            let neighbor = neighbor.clone();
+            let handshake = handshake.clone();
+            let signer = signer.clone();
+            let block_in_sender = block_in_sender.clone();
+            let tx_in_sender = tx_in_sender.clone();
+            thread::spawn(move || {
+                P2PNetwork::supervise(
+                    neighbor,
+                    connection,
+                    handshake,
+                    signer,
+                    &p2p_network,
+                    &block_in_sender,
+                    &tx_in_sender,
+                )
+            });
+        }
+
+        // Dial the addresses learned from peers while there are fewer outbound peers than the limit (if any)
+        {
+            let p2p_network = p2p_network.clone();
+            let handshake = handshake.clone();
+            let signer = signer.clone();
+            let block_in_sender = block_in_sender.clone();
+            let tx_in_sender = tx_in_sender.clone();
+            thread::spawn(move || {
+                let mut last_get_addr_ms = 0;
+                loop {
+                    thread::sleep(Duration::from_millis(DISCOVERY_INTERVAL_MS));
+                    let candidate = {
+                        let mut p2p = p2p_network.lock().unwrap();
+                        match p2p.next_discovery_candidate() {
+                            Some(candidate) => {
+                                p2p.dialing.insert(peer_name(&candidate));
+                                candidate
+                            }
+                            None => {
+                                // Ask a peer for more addresses while below the limit
+                                let now = now_ms();
+                                if p2p.below_outbound_limit()
+                                    && now >= last_get_addr_ms + GET_ADDR_INTERVAL_MS
+                                {
+                                    last_get_addr_ms = now;
+                                    p2p.send_to_random_connection(NetMessage::GetAddr);
                                 }
-                                Err(_) => break,
+                                continue;
                             }
                         }
+                    };
+                    let p2p_network = p2p_network.clone();
+                    let handshake = handshake.clone();
+                    let signer = signer.clone();
+                    let block_in_sender = block_in_sender.clone();
+                    let tx_in_sender = tx_in_sender.clone();
+                    thread::spawn(move || {
+                        P2PNetwork::connect_discovered(
+                            candidate,
+                            handshake,
+                            signer,
+                            &p2p_network,
+                            &block_in_sender,
+                            &tx_in_sender,
+                        )
                     });
                 }
-                Err(e) => {
-                    println!("Error: {}", e);
-                }
-            }
+            });
         }
 
-        // step 5 - 7
-        for neighbor in &neighbors {
-            let p2p_clone = p2p_network.clone();
-            let neighbor_clone = neighbor.clone();
-            let sender = senders.pop().unwrap();
+        // 7. create threads to distribute local messages (broadcast to neighbors)
+        {
+            let p2p_network = p2p_network.clone();
             thread::spawn(move || {
-                let socket_string = format!("{}:{}", &neighbor_clone.ip, &neighbor_clone.port);
-                match TcpStream::connect(socket_string) {
-                    Ok(stream) => {
-                        let mut reader = BufReader::new(&stream);
-                        let mut writer = BufWriter::new(&stream);
-
-                        loop {
-                            let mut msg = String::new();
-                            match reader.read_line(&mut msg) {
-                                Ok(_) => {
-                                    let parts: Vec<&str> = msg.trim().split(":").collect();
-                                    match parts[0] {
-                                        "block" => {
-                                            let block: BlockNode =
-                                                serde_json::from_str(parts[1]).unwrap();
-                                            p2p_clone.lock().unwrap().recv_msg_count += 1;
-                                            sender.send(msg).unwrap();
-                                        }
-                                        "tx" => {
-                                            let tx: Transaction =
-                                                serde_json::from_str(parts[1]).unwrap();
-                                            p2p_clone.lock().unwrap().recv_msg_count += 1;
-                                            sender.send(msg).unwrap();
-                                        }
-                                        "block_id" => {
-                                            let block_id: BlockId =
-                                                serde_json::from_str(parts[1]).unwrap();
-                                            let neighbors_len =
-                                                p2p_clone.lock().unwrap().neighbors.len();
-                                            let random_neighbor_index =
-                                                thread_rng().gen_range(0..neighbors_len);
-                                            let random_neighbor =
-                                                &p2p_clone.lock().unwrap().neighbors
-                                                    [random_neighbor_index];
-                                            let msg = format!("block_id:{}", parts[1]);
-                                            let socket_string = format!(
-                                                "{}:{}",
-                                                &random_neighbor.ip, &random_neighbor.port
-                                            );
-                                            match TcpStream::connect(socket_string) {
-                                                Ok(mut stream) => {
-                                                    let mut writer = BufWriter::new(&stream);
-                                                    writer.write(msg.as_bytes()).unwrap();
-                                                    writer.flush().unwrap();
-                                                }
-                                                Err(e) => {
-                                                    println!("Error: {}", e);
-                                                }
-                                            }
-                                        }
-                                        _ => {}
-                                    }
-                                }
-                                Err(_) => {
-                                    break;
-                                }
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        println!("Error: {}", e);
-                    }
+                for block in block_out_receiver {
+                    p2p_network.lock().unwrap().broadcast_block(&block);
+                }
+            });
+        }
+        {
+            let p2p_network = p2p_network.clone();
+            thread::spawn(move || {
+                for tx in tx_out_receiver {
+                    p2p_network.lock().unwrap().broadcast_tx(&tx);
+                }
+            });
+        }
+        {
+            let p2p_network = p2p_network.clone();
+            thread::spawn(move || {
+                for block_id in block_id_receiver {
+                    let mut p2p = p2p_network.lock().unwrap();
+                    let request_id = new_random_id();
+                    p2p.track_block_request(request_id.clone(), None);
+                    p2p.send_to_random_neighbor(NetMessage::RequestBlock(
+                        request_id,
+                        block_id,
+                        BLOCK_REQUEST_HOP_LIMIT,
+                    ));
                 }
             });
         }
@@ -249,30 +767,861 @@ impl P2PNetwork {
         // 8. return the created P2PNetwork instance and the mpsc channels
         (
             p2p_network,
-            block_receiver,
-            tx_receiver,
-            block_sender,
-            tx_sender,
+            block_in_receiver,
+            tx_in_receiver,
+            block_out_sender,
+            tx_out_sender,
             block_id_sender,
         )
     }
 
+    /// Connect to a neighbor, unless the peer filter rejects it, and register the channel writing to it.
+    /// Return the connection and the channel writing to it, or None if the neighbor is not connected.
+    fn dial(
+        p2p_network: &Arc<Mutex<P2PNetwork>>,
+        neighbor: &NetAddress,
+        signer: &Option<Arc<NodeIdentity>>,
+    ) -> Option<(NetChannel, Sender<NetMessage>)> {
+        let peer = peer_name(neighbor);
+        let traffic = {
+            let mut p2p = p2p_network.lock().unwrap();
+            if p2p.peer_filter.blocks(&peer, None) {
+                eprintln!("[P2PNetwork] {} is blocked, not dialed", peer);
+                return None;
+            }
+            p2p.address_book.mark_attempt(neighbor, now_ms());
+            p2p.peer_health.dialing(&peer);
+            p2p.traffic.clone()
+        };
+        let mut net_channel = match NetChannel::from_addr(neighbor) {
+            Ok(net_channel) => net_channel,
+            Err(e) => {
+                eprintln!("[P2PNetwork] Error: {}", e);
+                let mut p2p = p2p_network.lock().unwrap();
+                p2p.address_book.mark_failed(neighbor);
+                p2p.peer_health.dial_failed(&peer);
+                return None;
+            }
+        };
+        net_channel.set_accounting_hook(accounting_hook(&traffic));
+        net_channel.set_peer(&peer);
+        let sender = spawn_writer(net_channel.clone_channel(), signer.clone());
+        p2p_network
+            .lock()
+            .unwrap()
+            .peer_senders
+            .push((neighbor.clone(), sender.clone()));
+        Some((net_channel, sender))
+    }
+
+    /// Listen to a neighbor over the given connection, and dial it again whenever the connection is lost (closed, or
+    /// silent for `IDLE_TIMEOUT_MS`) or could not be made, after the backoff of the neighbor (see
+    /// `ConnectionManager::backoff`).
+    fn supervise(
+        neighbor: NetAddress,
+        mut connection: Option<(NetChannel, Sender<NetMessage>)>,
+        handshake: Handshake,
+        signer: Option<Arc<NodeIdentity>>,
+        p2p_network: &Arc<Mutex<P2PNetwork>>,
+        block_in_sender: &Sender<BlockNode>,
+        tx_in_sender: &Sender<Transaction>,
+    ) {
+        let peer = peer_name(&neighbor);
+        loop {
+            if let Some((net_channel, sender)) = connection.take() {
+                P2PNetwork::listen(
+                    net_channel,
+                    &peer,
+                    sender,
+                    &handshake,
+                    p2p_network,
+                    block_in_sender,
+                    tx_in_sender,
+                );
+                eprintln!("[P2PNetwork] Connection to {} lost", peer);
+                p2p_network.lock().unwrap().disconnect(&peer);
+            }
+            let delay_ms = p2p_network
+                .lock()
+                .unwrap()
+                .peer_health
+                .backoff(&peer, now_ms());
+            thread::sleep(Duration::from_millis(delay_ms));
+            connection = P2PNetwork::dial(p2p_network, &neighbor, &signer);
+        }
+    }
+
+    /// Connect to an address learned from a peer and listen to it until the connection is lost.
+    /// Unlike the neighbors, it is not dialed again right away: the address goes back to the address book, and a dial
+    /// that did not reach a handshake counts as a failure of the address.
+    fn connect_discovered(
+        address: NetAddress,
+        handshake: Handshake,
+        signer: Option<Arc<NodeIdentity>>,
+        p2p_network: &Arc<Mutex<P2PNetwork>>,
+        block_in_sender: &Sender<BlockNode>,
+        tx_in_sender: &Sender<Transaction>,
+    ) {
+        let peer = peer_name(&address);
+        let dialed_at_ms = now_ms();
+        eprintln!("[P2PNetwork] Dialing discovered peer {}", peer);
+        let connection = P2PNetwork::dial(p2p_network, &address, &signer);
+        p2p_network.lock().unwrap().dialing.remove(&peer);
+        let Some((net_channel, sender)) = connection else {
+            return;
+        };
+        P2PNetwork::listen(
+            net_channel,
+            &peer,
+            sender,
+            &handshake,
+            p2p_network,
+            block_in_sender,
+            tx_in_sender,
+        );
+        eprintln!("[P2PNetwork] Connection to discovered peer {} lost", peer);
+        let mut p2p = p2p_network.lock().unwrap();
+        p2p.disconnect(&peer);
+        if !p2p.address_book.was_seen_since(&address, dialed_at_ms) {
+            p2p.address_book.mark_failed(&address);
+        }
+    }
+
+    /// Read messages from a neighbor until the connection is closed, after the handshake: both ends send a `Challenge`,
+    /// and answer the challenge of the other end with a `Hello` signed with the `handshake` identity.
+    /// New blocks and transactions are delivered to the given channels and relayed to all neighbors.
+    /// Block requests and responses are routed using `reply`, the channel writing back to this neighbor.
+    fn listen(
+        mut net_channel: NetChannel,
+        peer: &str,
+        reply: Sender<NetMessage>,
+        handshake: &Handshake,
+        p2p_network: &Arc<Mutex<P2PNetwork>>,
+        block_in_sender: &Sender<BlockNode>,
+        tx_in_sender: &Sender<Transaction>,
+    ) {
+        let peer = peer.to_string();
+        // The listening address claimed by the peer in the handshake (as "ip:port")
+        let mut listen_name = peer.clone();
+        // The node id and public key proved by the peer in the handshake
+        let mut peer_id: Option<NodeId> = None;
+        let mut peer_key: Option<String> = None;
+        // The id of the connection registered after the handshake
+        let mut connection_id: Option<u64> = None;
+        // The nonce the peer must sign in its Hello, and whether the challenge of the peer was answered
+        let nonce = new_nonce();
+        let _ = reply.send(NetMessage::Challenge(nonce.clone()));
+        let mut challenge_answered = false;
+        while let Some(msg) = net_channel.recv_msg() {
+            let msg = match msg {
+                // Only the first challenge is answered, so that the peer cannot get other nonces signed
+                NetMessage::Challenge(peer_nonce) => {
+                    if !challenge_answered {
+                        challenge_answered = true;
+                        let _ = reply.send(handshake.hello(&peer_nonce));
+                    }
+                    continue;
+                }
+                NetMessage::Hello(node_id, pub_key_pem, listen_address, signature) => {
+                    let payload =
+                        NetMessage::hello_signed_payload(&node_id, &listen_address, &nonce);
+                    if node_id_of(&pub_key_pem) != node_id
+                        || !verify_signature(&pub_key_pem, &payload, &signature)
+                    {
+                        eprintln!("[P2PNetwork] Handshake from {} failed", peer);
+                        break;
+                    }
+                    // The listening address is only claimed, so the connection keeps the address of its socket
+                    listen_name = peer_name(&listen_address);
+                    let mut p2p = p2p_network.lock().unwrap();
+                    if !p2p.keeps(&peer, Some(&node_id), &listen_name) {
+                        eprintln!(
+                            "[P2PNetwork] Peer {} ({}) rejected",
+                            peer,
+                            short_node_id(&node_id)
+                        );
+                        p2p.disconnect(&peer);
+                        break;
+                    }
+                    p2p.peer_ids.insert(peer.clone(), node_id.clone());
+                    p2p.address_book
+                        .mark_seen(&listen_address, &node_id, now_ms());
+                    p2p.peer_health.connected(&peer, now_ms());
+                    // The peer is asked for the addresses it knows, to find more peers
+                    p2p.send_on(&reply, NetMessage::GetAddr);
+                    // The peer is pinged, so that the offset of its clock is known right away
+                    p2p.send_on(&reply, NetMessage::Ping(now_ms()));
+                    let id = p2p.next_connection_id;
+                    p2p.next_connection_id += 1;
+                    p2p.connections.insert(
+                        peer.clone(),
+                        Connection {
+                            id,
+                            sender: reply.clone(),
+                            listen_address,
+                        },
+                    );
+                    connection_id = Some(id);
+                    p2p.peer_scores.entry(node_id.clone()).or_insert(0);
+                    if let Some(peer_listener) = &p2p.peer_listener {
+                        peer_listener(&peer);
+                    }
+                    peer_id = Some(node_id);
+                    peer_key = Some(pub_key_pem);
+                    continue;
+                }
+                NetMessage::Signed(signature, msg) => {
+                    let payload = serde_json::to_string(&msg).unwrap();
+                    if !peer_key
+                        .as_ref()
+                        .is_some_and(|key| verify_signature(key, &payload, &signature))
+                    {
+                        eprintln!("[P2PNetwork] Invalid signature from {}", peer);
+                        let mut p2p = p2p_network.lock().unwrap();
+                        p2p.adjust_peer_score(&peer_id, PEER_SCORE_BAD_SIGNATURE);
+                        continue;
+                    }
+                    *msg
+                }
+                // The pings keep the connection alive, and are answered with the time of this node
+                NetMessage::Ping(sent_ms) => {
+                    let mut p2p = p2p_network.lock().unwrap();
+                    p2p.peer_health.seen(&peer, now_ms());
+                    p2p.send_on(&reply, NetMessage::Pong(sent_ms, now_ms()));
+                    continue;
+                }
+                NetMessage::Busy => {
+                    eprintln!("[P2PNetwork] {} is busy", peer);
+                    break;
+                }
+                msg => msg,
+            };
+            // Nothing but the handshake is handled until the Hello of the peer is verified, so that the peer filter
+            // always knows its node id
+            if peer_id.is_none() {
+                continue;
+            }
+            let peer = peer.as_str();
+            let mut p2p = p2p_network.lock().unwrap();
+            p2p.peer_health.seen(peer, now_ms());
+            // A peer blocked (or no longer allowed) at runtime is disconnected on its next message
+            if !p2p.keeps(peer, peer_id.as_deref(), &listen_name) {
+                eprintln!("[P2PNetwork] Peer {} rejected", peer);
+                p2p.disconnect(peer);
+                break;
+            }
+            if p2p.is_paused(peer, &listen_name) {
+                continue;
+            }
+            // Messages from nodes that do not trace start a new trace here
+            let (trace_id, msg) = match msg {
+                NetMessage::Traced(trace_id, msg) => (trace_id, *msg),
+                msg => (new_random_id(), msg),
+            };
+            match msg {
+                NetMessage::BroadcastBlock(block) | NetMessage::BlockData(block) => {
+                    // A requested block keeps the trace id of the announcement it was requested after
+                    let trace_id = p2p
+                        .requested_blocks
+                        .remove(&block.header.block_id)
+                        .map_or(trace_id, |(trace_id, _)| trace_id);
+                    if p2p.gossip_block(trace_id, block.clone(), peer) {
+                        p2p.adjust_peer_score(&peer_id, PEER_SCORE_NEW_ITEM);
+                        let block_id = block.header.block_id.clone();
+                        p2p.block_sources.insert(block_id, peer.to_string());
+                        drop(p2p);
+                        let _ = block_in_sender.send(block);
+                    }
+                }
+                NetMessage::BroadcastTx(tx) => {
+                    if p2p.gossip_tx(trace_id, tx.clone(), peer) {
+                        p2p.adjust_peer_score(&peer_id, PEER_SCORE_NEW_ITEM);
+                        drop(p2p);
+                        let _ = tx_in_sender.send(tx);
+                    }
+                }
+                NetMessage::Inventory(block_ids) => {
+                    let missing_block_ids = p2p.request_missing_blocks(&trace_id, block_ids);
+                    if !missing_block_ids.is_empty() {
+                        p2p.send_on(&reply, NetMessage::GetData(missing_block_ids));
+                    }
+                }
+                NetMessage::Tip(height, block_id, work) => {
+                    // The body is requested right away, before the announcement of its gossip arrives
+                    let missing_block_ids =
+                        p2p.request_missing_blocks(&trace_id, vec![block_id.clone()]);
+                    if !missing_block_ids.is_empty() {
+                        p2p.send_on(&reply, NetMessage::GetData(missing_block_ids));
+                    }
+                    let tip_listener = p2p.tip_listener.clone();
+                    drop(p2p);
+                    if let Some(tip_listener) = tip_listener {
+                        tip_listener(peer, height, &block_id, work);
+                    }
+                }
+                NetMessage::GetAddr => {
+                    let addresses = p2p
+                        .address_book
+                        .live_addresses()
+                        .into_iter()
+                        .filter(|address| peer_name(address) != listen_name)
+                        .collect();
+                    p2p.send_on(&reply, NetMessage::Addr(addresses));
+                }
+                NetMessage::Addr(addresses) => {
+                    let own_name = peer_name(&p2p.address);
+                    for address in addresses.into_iter().take(MAX_ADDR_LEN) {
+                        let name = peer_name(&address);
+                        if name != own_name && !p2p.peer_filter.blocks(&name, None) {
+                            p2p.address_book.add(&address);
+                        }
+                    }
+                }
+                NetMessage::Pong(sent_ms, peer_ms) => {
+                    // The peer answered halfway through the round trip, unless the pong is too slow to tell
+                    let now = now_ms();
+                    if peer_id.is_some() && sent_ms <= now && now - sent_ms <= MAX_PING_RTT_MS {
+                        let offset_ms = peer_ms as i64 - (sent_ms + (now - sent_ms) / 2) as i64;
+                        p2p.time_offsets.insert(peer.to_string(), offset_ms);
+                    }
+                }
+                NetMessage::GetData(block_ids) => {
+                    let mut blocks = Vec::new();
+                    let mut uncached_block_ids = Vec::new();
+                    for block_id in block_ids.into_iter().take(MAX_INVENTORY_LEN) {
+                        match p2p.get_cached_block(&block_id) {
+                            Some(block) => blocks.push(block),
+                            None => uncached_block_ids.push(block_id),
+                        }
+                    }
+                    let block_provider = p2p.block_provider.clone();
+                    drop(p2p);
+                    // The chain is looked up without holding the network lock
+                    if let Some(provider) = block_provider {
+                        blocks.extend(uncached_block_ids.iter().filter_map(|id| provider(id)));
+                    }
+                    let p2p = p2p_network.lock().unwrap();
+                    for block in blocks {
+                        p2p.send_on(&reply, NetMessage::BlockData(block));
+                    }
+                }
+                NetMessage::RequestBlock(request_id, block_id, hops_left) => {
+                    // A request seen before is looping back, so it is dropped
+                    if p2p.pending_block_requests.contains_key(&request_id) {
+                        continue;
+                    }
+                    let block_provider = p2p.block_provider.clone();
+                    drop(p2p);
+                    // The chain is looked up without holding the network lock
+                    let block = block_provider.and_then(|provider| provider(&block_id));
+                    let mut p2p = p2p_network.lock().unwrap();
+                    if let Some(block) = block {
+                        p2p.send_on(&reply, NetMessage::ResponseBlock(request_id, block));
+                    } else if hops_left > 0 {
+                        p2p.track_block_request(request_id.clone(), Some(reply.clone()));
+                        p2p.send_to_random_neighbor(NetMessage::RequestBlock(
+                            request_id,
+                            block_id,
+                            hops_left - 1,
+                        ));
+                    }
+                }
+                NetMessage::ResponseBlock(request_id, block) => {
+                    match p2p.pending_block_requests.remove(&request_id) {
+                        // Route the response back along the path of the request
+                        Some((Some(requester), _)) => {
+                            p2p.send_on(&requester, NetMessage::ResponseBlock(request_id, block));
+                        }
+                        // The block was requested by this node
+                        Some((None, _)) => {
+                            p2p.block_sources
+                                .insert(block.header.block_id.clone(), peer.to_string());
+                            drop(p2p);
+                            let _ = block_in_sender.send(block);
+                        }
+                        None => {}
+                    }
+                }
+                NetMessage::Traced(..)
+                | NetMessage::Ping(_)
+                | NetMessage::Busy
+                | NetMessage::Challenge(_)
+                | NetMessage::Hello(..)
+                | NetMessage::Signed(..)
+                | NetMessage::Unknown(_) => {}
+            }
+        }
+        // The peer may have connected again in the meantime, so only this connection is forgotten
+        let mut p2p = p2p_network.lock().unwrap();
+        if connection_id.is_some() {
+            p2p.peer_health.disconnected(&peer, now_ms());
+        }
+        if connection_id.is_some()
+            && p2p.connections.get(&peer).map(|connection| connection.id) == connection_id
+        {
+            p2p.connections.remove(&peer);
+            p2p.time_offsets.remove(&peer);
+            p2p.peer_ids.remove(&peer);
+        }
+    }
+
+    /// Broadcast a block of this node to all neighbors, under a new trace id.
+    /// Return whether the block is new, i.e. not broadcast or received before.
+    pub fn broadcast_block(&mut self, block: &BlockNode) -> bool {
+        self.gossip_block(new_random_id(), block.clone(), LOCAL_PEER)
+    }
+
+    /// Broadcast a transaction of this node to all neighbors, under a new trace id.
+    /// Return whether the transaction is new, i.e. not broadcast or received before.
+    pub fn broadcast_tx(&mut self, tx: &Transaction) -> bool {
+        self.gossip_tx(new_random_id(), tx.clone(), LOCAL_PEER)
+    }
+
+    /// Send a message to one connected peer, named by its node id or by the "ip:port" of its connection (see
+    /// `connections`), e.g. to serve it a block. Unlike broadcasts, it also reaches the peers that dialed this node.
+    /// The paused peers are not reached.
+    pub fn send_to(&self, peer_id: &str, msg: NetMessage) -> Result<(), String> {
+        let peer = self
+            .peer_ids
+            .iter()
+            .find(|(_, node_id)| node_id.as_str() == peer_id)
+            .map_or(peer_id, |(peer, _)| peer.as_str());
+        let connection = self
+            .connections
+            .get(peer)
+            .ok_or_else(|| format!("{} is not connected", peer_id))?;
+        if self.is_paused(peer, &peer_name(&connection.listen_address)) {
+            return Err(format!("{} is paused", peer));
+        }
+        connection
+            .sender
+            .send(msg)
+            .map_err(|_| format!("The connection to {} is closed", peer))
+    }
+
+    /// Announce a block just mined by this node to all connected peers (including the ones that dialed this node) ahead
+    /// of its gossip, with its height and the work of its branch. The paused peers are not reached.
+    /// Return the number of peers the announcement was sent to.
+    pub fn announce_tip(&self, height: u64, block_id: &str, work: u128) -> usize {
+        let mut announced = 0;
+        for (peer, connection) in self.connections.iter() {
+            let node_id = self.peer_ids.get(peer).map(|node_id| node_id.as_str());
+            let listen_name = peer_name(&connection.listen_address);
+            if self.is_paused(peer, &listen_name) || !self.keeps(peer, node_id, &listen_name) {
+                continue;
+            }
+            if connection
+                .sender
+                .send(NetMessage::Tip(height, block_id.to_string(), work))
+                .is_ok()
+            {
+                announced += 1;
+            }
+        }
+        announced
+    }
+
+    /// Announce a block to all neighbors under the given trace id, unless it has been seen before.
+    /// The neighbors missing the block request its body, which is kept in the block cache to answer them.
+    /// Return whether the block is new to this node.
+    fn gossip_block(&mut self, trace_id: TraceId, block: BlockNode, from: &str) -> bool {
+        let block_id = block.header.block_id.clone();
+        if !self.seen_block_ids.insert(block_id.clone(), ()) {
+            return false;
+        }
+        self.record_trace(&trace_id, "block", &block_id, from);
+        if self.block_cache.len() == MAX_CACHED_BLOCKS {
+            self.block_cache.pop_front();
+        }
+        self.block_cache.push_back(block);
+        self.send_to_all_neighbors(NetMessage::Traced(
+            trace_id,
+            Box::new(NetMessage::Inventory(vec![block_id])),
+        ));
+        true
+    }
+
+    /// Get the announced block ids that are new to this node and not requested recently, marking them as requested
+    /// under the trace id of the announcement.
+    fn request_missing_blocks(&mut self, trace_id: &str, block_ids: Vec<BlockId>) -> Vec<BlockId> {
+        let now = now_ms();
+        // The requests never answered are forgotten, so that the blocks can be requested from other neighbors
+        self.requested_blocks
+            .retain(|_, (_, requested_at)| now < *requested_at + GET_DATA_TIMEOUT_MS);
+        let mut missing_block_ids = Vec::new();
+        for block_id in block_ids.into_iter().take(MAX_INVENTORY_LEN) {
+            if self.seen_block_ids.contains_key(&block_id)
+                || self.requested_blocks.contains_key(&block_id)
+            {
+                continue;
+            }
+            self.requested_blocks
+                .insert(block_id.clone(), (trace_id.to_string(), now));
+            missing_block_ids.push(block_id);
+        }
+        missing_block_ids
+    }
+
+    /// Get a recently gossiped block from the block cache.
+    fn get_cached_block(&self, block_id: &str) -> Option<BlockNode> {
+        self.block_cache
+            .iter()
+            .find(|block| block.header.block_id == block_id)
+            .cloned()
+    }
+
+    /// Relay a transaction to all neighbors under the given trace id, unless it has been seen before.
+    /// Return whether the transaction is new to this node.
+    fn gossip_tx(&mut self, trace_id: TraceId, tx: Transaction, from: &str) -> bool {
+        let tx_id = tx.gen_hash();
+        if !self.seen_tx_ids.insert(tx_id.clone(), ()) {
+            return false;
+        }
+        self.record_trace(&trace_id, "tx", &tx_id, from);
+        self.send_to_all_neighbors(NetMessage::Traced(
+            trace_id,
+            Box::new(NetMessage::BroadcastTx(tx)),
+        ));
+        true
+    }
+
+    /// Change the score of the peer with the given node id, if it is known.
+    fn adjust_peer_score(&mut self, node_id: &Option<NodeId>, delta: i64) {
+        if let Some(node_id) = node_id {
+            *self.peer_scores.entry(node_id.clone()).or_insert(0) += delta;
+        }
+    }
+
+    /// Record a gossip trace event and echo it in the debug log.
+    fn record_trace(&mut self, trace_id: &str, kind: &str, item_id: &str, from: &str) {
+        eprintln!(
+            "[P2PNetwork] [trace {}] {} {} from {}",
+            trace_id, kind, item_id, from
+        );
+        if self.trace_log.len() == MAX_TRACE_EVENTS {
+            self.trace_log.pop_front();
+        }
+        self.trace_log.push_back(GossipTraceEvent {
+            trace_id: trace_id.to_string(),
+            kind: kind.to_string(),
+            item_id: item_id.to_string(),
+            from: from.to_string(),
+            at_ms: now_ms(),
+        });
+    }
+
+    /// Get the channels to the connected neighbors that are not paused or rejected by the peer filter.
+    fn active_peer_senders(&self) -> Vec<&Sender<NetMessage>> {
+        self.peer_senders
+            .iter()
+            .filter(|(neighbor, _)| {
+                let peer = peer_name(neighbor);
+                let node_id = self.peer_ids.get(&peer).map(|node_id| node_id.as_str());
+                !self.paused_peers.contains(&peer) && self.peer_filter.permits(&peer, node_id)
+            })
+            .map(|(_, sender)| sender)
+            .collect()
+    }
+
+    /// Whether the peer of a connection (as "ip:port") may be kept: the peer filter checks the address of the socket and
+    /// the node id proved in the handshake, and the listening address claimed by the peer can only get it rejected.
+    fn keeps(&self, peer: &str, node_id: Option<&str>, listen_name: &str) -> bool {
+        self.peer_filter.permits(peer, node_id) && !self.peer_filter.blocks(listen_name, None)
+    }
+
+    /// Whether the traffic of a connection (as "ip:port") is paused: it is if the peer was dialed as a paused neighbor,
+    /// or claims the listening address of one.
+    fn is_paused(&self, peer: &str, listen_name: &str) -> bool {
+        self.paused_peers.contains(peer) || self.paused_peers.contains(listen_name)
+    }
+
+    /// Forget the connection to the given peer (as "ip:port"), closing it once its listener returns.
+    fn disconnect(&mut self, peer: &str) {
+        self.peer_senders
+            .retain(|(neighbor, _)| peer_name(neighbor) != peer);
+        self.peer_ids.remove(peer);
+        self.connections.remove(peer);
+    }
+
+    /// Send a message on the channel to one connection.
+    /// It is counted in the traffic once the writer of the connection has written it.
+    fn send_on(&self, sender: &Sender<NetMessage>, msg: NetMessage) {
+        let _ = sender.send(msg);
+    }
+
+    /// Send a message to all connected neighbors that are not paused.
+    fn send_to_all_neighbors(&self, msg: NetMessage) {
+        for sender in self.active_peer_senders() {
+            let _ = sender.send(msg.clone());
+        }
+    }
+
+    /// Whether this node dials fewer peers than its outbound limit, counting the dials in progress.
+    /// Always false without a limit, since only the neighbors are dialed then.
+    fn below_outbound_limit(&self) -> bool {
+        self.max_outbound_peers
+            .is_some_and(|max| self.peer_senders.len() + self.dialing.len() < max)
+    }
+
+    /// Get the next address learned from peers to dial, if this node is below its outbound limit: a known address
+    /// that is not this node, not connected (in either direction), not being dialed and not rejected by the peer filter.
+    /// The peer with the best score is dialed first, and among equal scores the one seen most recently.
+    fn next_discovery_candidate(&self) -> Option<NetAddress> {
+        if !self.below_outbound_limit() {
+            return None;
+        }
+        let own_name = peer_name(&self.address);
+        let exclude = |name: &str| {
+            name == own_name
+                || self.connections.contains_key(name)
+                || self
+                    .connections
+                    .values()
+                    .any(|connection| peer_name(&connection.listen_address) == name)
+                || self.dialing.contains(name)
+                || self
+                    .peer_senders
+                    .iter()
+                    .any(|(neighbor, _)| peer_name(neighbor) == name)
+                || self
+                    .neighbors
+                    .iter()
+                    .any(|neighbor| peer_name(neighbor) == name)
+                || self.peer_filter.blocks(name, None)
+        };
+        self.address_book
+            .dial_candidates(&exclude, now_ms())
+            .into_iter()
+            .min_by_key(|record| std::cmp::Reverse(self.score_of(record)))
+            .map(|record| record.address)
+    }
+
+    /// The score of the peer of an address book record: its current score if its node id is known, else the recorded one.
+    fn score_of(&self, record: &PeerRecord) -> i64 {
+        record
+            .node_id
+            .as_ref()
+            .and_then(|node_id| self.peer_scores.get(node_id))
+            .copied()
+            .unwrap_or(record.score)
+    }
+
+    /// Send a message to one peer that completed the handshake (in either direction, and not paused) chosen at random.
+    fn send_to_random_connection(&self, msg: NetMessage) {
+        let senders: Vec<&Sender<NetMessage>> = self
+            .connections
+            .iter()
+            .filter(|(peer, connection)| {
+                !self.is_paused(peer, &peer_name(&connection.listen_address))
+            })
+            .map(|(_, connection)| &connection.sender)
+            .collect();
+        if senders.is_empty() {
+            return;
+        }
+        let index = thread_rng().gen_range(0..senders.len());
+        let _ = senders[index].send(msg);
+    }
+
+    /// Set the number of peers this node dials itself, including the neighbors: while it dials fewer, it dials the
+    /// addresses learned from its peers (see `NetMessage::Addr`). None to only dial the neighbors.
+    pub fn set_max_outbound_peers(&mut self, max_outbound_peers: Option<usize>) {
+        self.max_outbound_peers = max_outbound_peers;
+    }
+
+    /// Get the records of the addresses in the address book, with the current scores of their peers.
+    pub fn get_address_book(&self) -> Vec<PeerRecord> {
+        self.address_book
+            .records()
+            .into_iter()
+            .map(|record| PeerRecord {
+                score: self.score_of(&record),
+                ..record
+            })
+            .collect()
+    }
+
+    /// Get how far the network time is ahead of the clock of this node (in ms): the median of the clock offsets of the
+    /// connected peers and of this node itself (0), so that the peers with a wrong clock are outvoted by the others.
+    /// It is 0 if the median is beyond `MAX_TIME_OFFSET_MS`.
+    pub fn network_time_offset_ms(&self) -> i64 {
+        let mut offsets: Vec<i64> = self.time_offsets.values().copied().collect();
+        offsets.push(0);
+        offsets.sort();
+        let middle = offsets.len() / 2;
+        let median = match offsets.len() % 2 {
+            1 => offsets[middle],
+            _ => (offsets[middle - 1] + offsets[middle]) / 2,
+        };
+        match median.abs() > MAX_TIME_OFFSET_MS {
+            true => 0,
+            false => median,
+        }
+    }
+
+    /// Add the records of an address book saved before (e.g. by a previous run of this node) to the address book,
+    /// together with the scores of their peers. This node and the peers rejected by the peer filter are left out.
+    /// Return the number of addresses added.
+    pub fn restore_address_book(&mut self, records: Vec<PeerRecord>) -> usize {
+        let own_name = peer_name(&self.address);
+        let mut restored = 0;
+        for record in records {
+            let name = peer_name(&record.address);
+            let node_id = record.node_id.as_deref();
+            let rejected = match node_id {
+                Some(_) => !self.peer_filter.permits(&name, node_id),
+                // A peer whose node id is not known yet is only checked against the blocklist until its handshake
+                None => self.peer_filter.blocks(&name, None),
+            };
+            if name == own_name || rejected {
+                continue;
+            }
+            if let Some(node_id) = &record.node_id {
+                self.peer_scores
+                    .entry(node_id.clone())
+                    .or_insert(record.score);
+            }
+            if self.address_book.restore(record) {
+                restored += 1;
+            }
+        }
+        restored
+    }
+
+    /// Send a message to one connected neighbor (that is not paused) chosen at random.
+    fn send_to_random_neighbor(&self, msg: NetMessage) {
+        let senders = self.active_peer_senders();
+        if senders.is_empty() {
+            return;
+        }
+        let index = thread_rng().gen_range(0..senders.len());
+        let _ = senders[index].send(msg);
+    }
+
+    /// Get a copy of the messages sent and received so far.
+    pub fn get_traffic(&self) -> TrafficStats {
+        self.traffic.lock().unwrap().clone()
+    }
+
+    /// Stop all traffic to and from the given neighbor (as "ip:port") until it is resumed.
+    /// Messages are dropped rather than queued, like in a real network partition.
+    pub fn pause_peer(&mut self, addr: &str) -> Result<(), String> {
+        if !self
+            .neighbors
+            .iter()
+            .any(|neighbor| peer_name(neighbor) == addr)
+        {
+            return Err(format!("{} is not a neighbor", addr));
+        }
+        self.paused_peers.insert(addr.to_string());
+        Ok(())
+    }
+
+    /// Resume the traffic to and from the given neighbor (as "ip:port") paused by `pause_peer`.
+    pub fn resume_peer(&mut self, addr: &str) -> Result<(), String> {
+        if !self.paused_peers.remove(addr) {
+            return Err(format!("{} is not paused", addr));
+        }
+        Ok(())
+    }
+
+    /// Get the allowlist and blocklist of peers.
+    pub fn get_peer_filter(&self) -> &PeerFilter {
+        &self.peer_filter
+    }
+
+    /// Get the allowlist and blocklist of peers, to change them while the network runs.
+    /// The connected peers rejected by the changed filter are disconnected; a neighbor accepted again is dialed again.
+    pub fn peer_filter_mut(&mut self) -> &mut PeerFilter {
+        &mut self.peer_filter
+    }
+
+    /// Set the lookup used to answer block requests from neighbors with blocks from the local chain.
+    pub fn set_block_provider(&mut self, block_provider: BlockProvider) {
+        self.block_provider = Some(block_provider);
+    }
+
+    /// Set the function told about the peers that complete the handshake, e.g. to notify a client.
+    pub fn set_peer_listener(&mut self, peer_listener: PeerListener) {
+        self.peer_listener = Some(peer_listener);
+    }
+
+    /// Set the function called with every tip announced by a peer (see `announce_tip`).
+    /// It is called without holding the network lock.
+    pub fn set_tip_listener(&mut self, tip_listener: TipListener) {
+        self.tip_listener = Some(tip_listener);
+    }
+
+    /// Get the peer that first delivered the given block, if the block was received from the network recently.
+    pub fn get_block_source(&self, block_id: &str) -> Option<String> {
+        self.block_sources.get(&block_id.to_string()).cloned()
+    }
+
+    /// Record a block request in flight, with the connection its response is routed back to (None if requested by this
+    /// node). The requests never answered within `BLOCK_REQUEST_TIMEOUT_MS` are forgotten.
+    fn track_block_request(&mut self, request_id: RequestId, reply: Option<Sender<NetMessage>>) {
+        let now = now_ms();
+        self.pending_block_requests
+            .retain(|_, (_, requested_at)| now < *requested_at + BLOCK_REQUEST_TIMEOUT_MS);
+        self.pending_block_requests.insert(request_id, (reply, now));
+    }
+
+    /// Get the recorded gossip trace events, from the oldest to the most recent.
+    pub fn get_trace_log(&self) -> Vec<GossipTraceEvent> {
+        self.trace_log.iter().cloned().collect()
+    }
+
+    /// Get the health of the connection to every peer ever dialed or connected, by peer (as "ip:port" of its connection).
+    pub fn get_peer_health(&self) -> BTreeMap<String, PeerHealth> {
+        self.peer_health
+            .peers()
+            .map(|(peer, health)| (peer.clone(), health.clone()))
+            .collect()
+    }
+
     /// Get status information of the P2PNetwork for debug printing.
-    pub fn get_status(&self) -> BTreeMap<String, String> {
+    pub fn get_status(&self) -> NetworkStatus {
         // Please fill in the blank
         // For debugging purpose, you can return any dictionary of strings as the status of the network.
         // It should be displayed in the Client UI eventually.
         // todo!();
-        let mut status = BTreeMap::new();
-        let mut address = String::new();
-        let mut port = String::new();
-        port.push_str("ip: ");
-        port.push_str(&self.address.ip);
-        port.push_str(" port: ");
-        port.push_str(&self.address.port.to_string());
-        status.insert("#address".to_string(), port);
-        status.insert("#recv_msg".to_string(), self.recv_msg_count.to_string());
-        status.insert("#send_msg".to_string(), self.send_msg_count.to_string());
-        status
+        let mut peers: Vec<PeerStatus> = self
+            .peer_ids
+            .iter()
+            .map(|(peer, node_id)| PeerStatus {
+                address: peer.clone(),
+                listen_address: self.connections.get(peer).map_or_else(
+                    || peer.clone(),
+                    |connection| peer_name(&connection.listen_address),
+                ),
+                node_id: node_id.clone(),
+                score: self.peer_scores.get(node_id).copied().unwrap_or(0),
+            })
+            .collect();
+        peers.sort_by(|a, b| a.address.cmp(&b.address));
+        let mut paused_peers: Vec<String> = self.paused_peers.iter().cloned().collect();
+        paused_peers.sort();
+        NetworkStatus {
+            node_id: self.node_id.clone(),
+            address: self.address.clone(),
+            peers,
+            peer_health: self.get_peer_health(),
+            traffic: self.get_traffic(),
+            requested_blocks: self.requested_blocks.len(),
+            inbound_peers: self.inbound_peer_count,
+            max_inbound_peers: self.inbound_limits.max_inbound_peers,
+            outbound_peers: self.peer_senders.len(),
+            max_outbound_peers: self.max_outbound_peers,
+            known_addresses: self.address_book.len(),
+            time_offset_ms: self.network_time_offset_ms(),
+            time_offset_samples: self.time_offsets.len(),
+            paused_peers,
+            allowed_peers: self
+                .peer_filter
+                .allowlist
+                .as_ref()
+                .map(|allowlist| allowlist.iter().cloned().collect()),
+            blocked_peers: self.peer_filter.blocklist.iter().cloned().collect(),
+        }
     }
 }
@@ -0,0 +1,107 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// This file implements the allowlist and blocklist of peers of a P2PNetwork, e.g. to exclude a misbehaving node from a
+// shared network. An entry names a peer by the "ip:port" of its listener, by its ip alone, or by its node id.
+// A peer is matched by the address of its socket (its listener when dialed, an ephemeral port when it dialed this node):
+// the listener address it claims in its handshake is not verified, so it can get the peer blocked but never allowed.
+// The blocklist is checked when a neighbor is dialed, when a connection is accepted and when a peer completes the handshake.
+// The allowlist names the peers that are not checked before the handshake, so it is only checked once the node id is known:
+// a peer without a verified node id is never allowed, so nothing but its handshake is handled until then.
+
+use std::collections::BTreeSet;
+
+/// The struct to represent which peers a node connects to.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PeerFilter {
+    /// If set, only the peers matching one of these entries are kept after the handshake.
+    pub allowlist: Option<BTreeSet<String>>,
+    /// The peers matching one of these entries are never dialed, accepted or kept.
+    pub blocklist: BTreeSet<String>,
+}
+
+impl PeerFilter {
+    /// Create a filter from the allowlist (if any) and the blocklist of a config.
+    pub fn new(allowlist: Option<Vec<String>>, blocklist: Vec<String>) -> PeerFilter {
+        PeerFilter {
+            allowlist: allowlist.map(|allowlist| allowlist.into_iter().collect()),
+            blocklist: blocklist.into_iter().collect(),
+        }
+    }
+
+    /// Whether the given entry names the peer with the given address ("ip:port") or node id.
+    fn matches(entry: &str, addr: &str, node_id: Option<&str>) -> bool {
+        let ip = addr.rsplit_once(':').map(|(ip, _)| ip);
+        entry == addr || Some(entry) == ip || Some(entry) == node_id
+    }
+
+    /// Whether the peer with the given address ("ip:port") and node id (None before the handshake) is on the blocklist.
+    /// It is the only check before the handshake, e.g. to dial a peer or accept its connection.
+    pub fn blocks(&self, addr: &str, node_id: Option<&str>) -> bool {
+        self.blocklist
+            .iter()
+            .any(|entry| Self::matches(entry, addr, node_id))
+    }
+
+    /// Whether the peer with the given address ("ip:port") and node id (None before the handshake) may be kept.
+    /// Under an allowlist, a peer without a node id is not.
+    pub fn permits(&self, addr: &str, node_id: Option<&str>) -> bool {
+        if self.blocks(addr, node_id) {
+            return false;
+        }
+        match (&self.allowlist, node_id) {
+            (Some(allowlist), Some(_)) => allowlist
+                .iter()
+                .any(|entry| Self::matches(entry, addr, node_id)),
+            (Some(_), None) => false,
+            (None, _) => true,
+        }
+    }
+
+    /// Add an entry to the blocklist.
+    pub fn block(&mut self, entry: &str) -> Result<(), String> {
+        if entry.is_empty() {
+            return Err("An empty entry matches no peer".to_string());
+        }
+        if !self.blocklist.insert(entry.to_string()) {
+            return Err(format!("{} is already blocked", entry));
+        }
+        Ok(())
+    }
+
+    /// Remove an entry from the blocklist.
+    pub fn unblock(&mut self, entry: &str) -> Result<(), String> {
+        if !self.blocklist.remove(entry) {
+            return Err(format!("{} is not blocked", entry));
+        }
+        Ok(())
+    }
+
+    /// Add an entry to the allowlist. The first entry turns the allowlist on, so the other peers are no longer kept.
+    pub fn allow(&mut self, entry: &str) -> Result<(), String> {
+        if entry.is_empty() {
+            return Err("An empty entry matches no peer".to_string());
+        }
+        if !self
+            .allowlist
+            .get_or_insert_with(BTreeSet::new)
+            .insert(entry.to_string())
+        {
+            return Err(format!("{} is already allowed", entry));
+        }
+        Ok(())
+    }
+
+    /// Remove an entry from the allowlist. The allowlist stays on (allowing no peer) when its last entry is removed.
+    pub fn disallow(&mut self, entry: &str) -> Result<(), String> {
+        if !self
+            .allowlist
+            .as_mut()
+            .is_some_and(|allowlist| allowlist.remove(entry))
+        {
+            return Err(format!("{} is not allowed", entry));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,244 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// This file implements the write-ahead log of the transaction pool.
+// A `TxPoolJournal` is an append-only file with one json `JournalEntry` per line: the pool it is attached to (see
+// `TxPool::open_log`) records every transaction added, deleted, dropped (expired or evicted) and the finalized blocks it
+// was updated with, each one written to the file before the call returns. After a crash, `TxPool::recover_from_log`
+// replays the file into the same pool, so the node does not depend on its last serialized state.
+// The writes are synced to disk by a background thread (group commit): the appends made while a sync runs are synced
+// together by the next one, so appending never waits for the disk while the pool is locked. A crash of the node loses
+// nothing, as the written entries are already in the file; a crash of the machine loses at most the last few changes.
+// The log starts with a snapshot of the pool it was opened for, so reopening it at each start keeps it compact.
+
+use lib_chain::block::{BlockId, Transaction, TxId};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// One change of the transaction pool, as recorded in its journal.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum JournalEntry {
+    /// A transaction added to the pool, with the time it was added if known (in milliseconds since the UNIX epoch)
+    Add(Transaction, Option<u64>),
+    /// A transaction deleted from the pool, and added to its removed transactions (see `TxPool::del_tx`)
+    Del(TxId),
+    /// A transaction dropped from the pool because it expired or was evicted, so it can be submitted again
+    Drop(TxId),
+    /// The last finalized block the pool was updated with
+    Finalized(BlockId),
+}
+
+/// The progress of the background sync of a journal, counted in appended entries.
+#[derive(Debug, Default)]
+struct SyncProgress {
+    /// The number of entries written to the file
+    appended: u64,
+    /// The number of entries synced to disk
+    synced: u64,
+    /// The error of the last sync, if it failed, so the entries after `synced` may not be on disk
+    failed: Option<String>,
+    /// Whether the journal is closed, so the sync thread stops once it has caught up
+    closed: bool,
+}
+
+/// The state shared by a journal and its sync thread.
+#[derive(Debug, Default)]
+struct SyncState {
+    progress: Mutex<SyncProgress>,
+    /// notified whenever `appended`, `synced`, `failed` or `closed` changes
+    changed: Condvar,
+}
+
+/// The write-ahead log of a transaction pool, in a single append-only file.
+#[derive(Debug)]
+pub struct TxPoolJournal {
+    /// The path of the file
+    path: PathBuf,
+    /// The file, opened for appending
+    file: File,
+    /// The progress of the sync thread
+    sync_state: Arc<SyncState>,
+    /// The thread syncing the appended entries to disk, joined when the journal is dropped
+    sync_thread: Option<JoinHandle<()>>,
+}
+
+impl TxPoolJournal {
+    /// Create the journal at the given path with the given entries, replacing the file if it exists.
+    /// The entries are written to a temporary file first, so a crash leaves either the old journal or the new one.
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        entries: &[JournalEntry],
+    ) -> Result<TxPoolJournal, String> {
+        let path = path.as_ref().to_path_buf();
+        let tmp_path = path.with_extension("tmp");
+        let mut content = String::new();
+        for entry in entries {
+            content += &serde_json::to_string(entry).map_err(|e| e.to_string())?;
+            content.push('\n');
+        }
+        File::create(&tmp_path)
+            .and_then(|mut file| {
+                file.write_all(content.as_bytes())?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&tmp_path, &path))
+            .map_err(|e| format!("Cannot write tx pool journal {}: {}", path.display(), e))?;
+        let file = OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .map_err(|e| format!("Cannot open tx pool journal {}: {}", path.display(), e))?;
+        let sync_file = file
+            .try_clone()
+            .map_err(|e| format!("Cannot open tx pool journal {}: {}", path.display(), e))?;
+        let sync_state = Arc::new(SyncState::default());
+        let sync_thread = {
+            let sync_state = sync_state.clone();
+            let path = path.clone();
+            thread::spawn(move || sync_loop(&sync_file, &path, &sync_state))
+        };
+        Ok(TxPoolJournal {
+            path,
+            file,
+            sync_state,
+            sync_thread: Some(sync_thread),
+        })
+    }
+
+    /// Append an entry. It is in the file once this returns, and synced to disk shortly after (see `sync`).
+    pub fn append(&mut self, entry: &JournalEntry) -> Result<(), String> {
+        let mut line = serde_json::to_string(entry).map_err(|e| e.to_string())?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes()).map_err(|e| {
+            format!(
+                "Cannot write to tx pool journal {}: {}",
+                self.path.display(),
+                e
+            )
+        })?;
+        self.sync_state.progress.lock().unwrap().appended += 1;
+        self.sync_state.changed.notify_all();
+        Ok(())
+    }
+
+    /// Wait until the entries appended so far are synced to disk.
+    /// Return the error of the last sync if they are not, because it failed.
+    pub fn sync(&self) -> Result<(), String> {
+        let progress = self.sync_state.progress.lock().unwrap();
+        let appended = progress.appended;
+        let progress = self
+            .sync_state
+            .changed
+            .wait_while(progress, |progress| {
+                progress.synced < appended && progress.failed.is_none()
+            })
+            .unwrap();
+        match &progress.failed {
+            Some(e) if progress.synced < appended => Err(e.clone()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Read the entries of the journal at the given path, in the order they were appended (none if there is no file).
+    /// A last line cut short (e.g. by a crash in the middle of a write) is ignored.
+    pub fn load_entries<P: AsRef<Path>>(path: P) -> Result<Vec<JournalEntry>, String> {
+        let path = path.as_ref();
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(format!(
+                    "Cannot read tx pool journal {}: {}",
+                    path.display(),
+                    e
+                ))
+            }
+        };
+        let complete_len = content.rfind('\n').map_or(0, |i| i + 1);
+        if complete_len < content.len() {
+            eprintln!(
+                "[TxPool] Ignoring the incomplete last line of {} ({} bytes)",
+                path.display(),
+                content.len() - complete_len
+            );
+        }
+        content[..complete_len]
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                serde_json::from_str(line).map_err(|e| {
+                    format!(
+                        "Malformed entry at line {} of {}: {}",
+                        i + 1,
+                        path.display(),
+                        e
+                    )
+                })
+            })
+            .collect()
+    }
+}
+
+impl Drop for TxPoolJournal {
+    /// Sync the last entries, and stop the sync thread.
+    fn drop(&mut self) {
+        self.sync_state.progress.lock().unwrap().closed = true;
+        self.sync_state.changed.notify_all();
+        if let Some(sync_thread) = self.sync_thread.take() {
+            let _ = sync_thread.join();
+        }
+    }
+}
+
+/// Sync the entries appended to the file, all the entries appended since the last sync at once, until the journal is
+/// closed and a sync was attempted for every entry. A failed sync leaves the entries it was for unsynced: it is reported
+/// to the callers of `TxPoolJournal::sync`, and attempted again once another entry is appended or the journal is closed.
+fn sync_loop(file: &File, path: &Path, sync_state: &SyncState) {
+    // The number of entries appended when the last sync was attempted
+    let mut attempted = 0;
+    let mut progress = sync_state.progress.lock().unwrap();
+    loop {
+        progress = sync_state
+            .changed
+            .wait_while(progress, |progress| {
+                progress.appended == attempted && !progress.closed
+            })
+            .unwrap();
+        if progress.appended == attempted {
+            return;
+        }
+        let appended = progress.appended;
+        drop(progress);
+        let result = file.sync_data();
+        attempted = appended;
+        progress = sync_state.progress.lock().unwrap();
+        match result {
+            Ok(()) => {
+                progress.synced = appended;
+                progress.failed = None;
+            }
+            Err(e) => {
+                let e = format!("Cannot sync tx pool journal {}: {}", path.display(), e);
+                eprintln!("[TxPool] {}", e);
+                progress.failed = Some(e);
+            }
+        }
+        sync_state.changed.notify_all();
+    }
+}
+
+/// The journal a pool writes to, if any. A clone of the pool (e.g. taken to serialize it) is detached from the journal,
+/// so that only the pool it was opened for writes to it.
+#[derive(Debug, Default)]
+pub struct AttachedJournal(pub(crate) Option<TxPoolJournal>);
+
+impl Clone for AttachedJournal {
+    fn clone(&self) -> Self {
+        AttachedJournal(None)
+    }
+}
@@ -2,6 +2,7 @@
 // Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
 // Please do not distribute.
 
+pub mod journal;
 pub mod pool;
 
 
@@ -9,17 +10,14 @@ pub mod pool;
 #[cfg(test)]
 mod tests {
     use std::fs;
-    use std::fmt::Debug;
-    use std::collections::HashMap;
-    use serde::{Serialize, Deserialize, de::DeserializeOwned};
-    use lib_chain::block::{BlockTree, BlockNode, Transaction, BlockNodeHeader, Transactions, MerkleTree};
+    use lib_chain::block::Transaction;
 
-    use crate::pool::TxPool;
+    use crate::pool::{EvictionPolicy, TxPool, TxRejectReason, MAX_TX_POOL};
+    use lib_chain::schema::{check_published_schema, schema_json};
 
     fn read_string_from_file(filepath: &str) -> String {
-        let contents = fs::read_to_string(filepath)
-            .expect(&("Cannot read ".to_owned() + filepath));
-        contents
+        fs::read_to_string(filepath)
+            .unwrap_or_else(|_| panic!("Cannot read {}", filepath))
     }
     
     /// Test the basic operations of TxPool
@@ -53,6 +51,157 @@ mod tests {
 
     }
 
+    /// Test the reasons reported when a transaction is not added to the pool
+    #[test]
+    fn test_try_add_tx_reject_reasons() {
+        let txs_str = read_string_from_file("./testdata/txs_0.json");
+        let txs = serde_json::from_str::<Vec<Transaction>>(&txs_str).unwrap();
+
+        let mut tx_pool = TxPool::new();
+        assert_eq!(tx_pool.try_add_tx(txs[0].clone()), Ok(()));
+        assert_eq!(tx_pool.try_add_tx(txs[0].clone()), Err(TxRejectReason::Duplicate));
+
+        tx_pool.del_tx(txs[1].gen_hash());
+        assert_eq!(tx_pool.try_add_tx(txs[1].clone()), Err(TxRejectReason::Duplicate));
+
+        let mut tampered = txs[2].clone();
+        tampered.message = "SEND $299792458   // By Alice   // 1678173978750".to_string();
+        assert_eq!(tx_pool.try_add_tx(tampered), Err(TxRejectReason::InvalidSignature));
+        assert!(tx_pool.pool_tx_ids.len() == 1);
+    }
+
+    /// Test that the minimum relay fee is enforced, and that the transactions paying the highest fees are filtered first
+    #[test]
+    fn test_tx_fees() {
+        let txs_str = read_string_from_file("./testdata/txs_0.json");
+        let txs = serde_json::from_str::<Vec<Transaction>>(&txs_str).unwrap();
+
+        let mut tx_pool = TxPool::new();
+        tx_pool.min_relay_fee = 1;
+        assert_eq!(tx_pool.try_add_tx(txs[0].clone()), Err(TxRejectReason::FeeTooLow(0, 1)));
+        assert!(tx_pool.pool_tx_ids.is_empty());
+
+        // The fee is signed, so the pool is filled directly
+        for (tx, fee) in txs.iter().take(4).zip([None, Some(5), Some(1), Some(5)]) {
+            let mut tx = tx.clone();
+            tx.fee = fee;
+            tx_pool.pool_tx_ids.push(tx.gen_hash());
+            tx_pool.pool_tx_map.insert(tx.gen_hash(), tx);
+        }
+        let filtered_tx = tx_pool.filter_tx(3, &[]);
+        let fees: Vec<i64> = filtered_tx.iter().map(|tx| tx.get_fee()).collect();
+        assert_eq!(fees, vec![5, 5, 1]);
+        assert_eq!(filtered_tx[0].message, txs[1].message);
+        assert_eq!(filtered_tx[1].message, txs[3].message);
+    }
+
+    /// Test that the transactions expire after the time to live, and that a full pool evicts one for a new transaction
+    #[test]
+    fn test_tx_expiry_and_eviction() {
+        let txs_str = read_string_from_file("./testdata/txs_0.json");
+        let txs = serde_json::from_str::<Vec<Transaction>>(&txs_str).unwrap();
+
+        let mut tx_pool = TxPool::new();
+        tx_pool.tx_ttl_ms = Some(1000);
+        assert_eq!(tx_pool.try_add_tx_at(txs[0].clone(), 0), Ok(()));
+        assert_eq!(tx_pool.try_add_tx_at(txs[1].clone(), 500), Ok(()));
+        assert_eq!(tx_pool.purge_expired(999), 0);
+        assert_eq!(tx_pool.purge_expired(1000), 1);
+        assert!(tx_pool.pool_tx_ids == vec![txs[1].gen_hash()]);
+        // an expired transaction can be submitted again, and the transactions of an older pool file are timed from loading
+        assert_eq!(tx_pool.try_add_tx_at(txs[0].clone(), 1200), Ok(()));
+        tx_pool.pool_tx_added_ms.clear();
+        assert_eq!(tx_pool.purge_expired(5000), 0);
+        assert_eq!(tx_pool.purge_expired(6000), 2);
+        assert!(tx_pool.pool_tx_ids.is_empty() && tx_pool.expired_count == 3);
+
+        // Fill the pool directly, the oldest transactions first, with a fee of 2 except for one
+        let mut tx_pool = TxPool::new();
+        for i in 0..MAX_TX_POOL {
+            let mut tx = txs[0].clone();
+            tx.message = format!("SEND $1   // {}", i);
+            tx.fee = Some(if i == 5 { 1 } else { 2 });
+            tx_pool.pool_tx_ids.push(tx.gen_hash());
+            tx_pool.pool_tx_map.insert(tx.gen_hash(), tx);
+        }
+        let oldest_id = tx_pool.pool_tx_ids[0].clone();
+        let cheapest_id = tx_pool.pool_tx_ids[5].clone();
+        // the new transaction pays less than all the others
+        tx_pool.eviction_policy = EvictionPolicy::LowestFeeFirst;
+        assert_eq!(tx_pool.try_add_tx_at(txs[1].clone(), 0), Err(TxRejectReason::PoolFull));
+
+        tx_pool.eviction_policy = EvictionPolicy::OldestFirst;
+        assert_eq!(tx_pool.try_add_tx_at(txs[1].clone(), 0), Ok(()));
+        assert!(!tx_pool.pool_tx_map.contains_key(&oldest_id) && !tx_pool.removed_tx_ids.contains(&oldest_id));
+
+        // now the cheapest transaction is the one without a fee, although it is the newest
+        tx_pool.eviction_policy = EvictionPolicy::LowestFeeFirst;
+        assert_eq!(tx_pool.try_add_tx_at(txs[2].clone(), 0), Ok(()));
+        assert!(!tx_pool.pool_tx_map.contains_key(&txs[1].gen_hash()));
+        assert!(tx_pool.pool_tx_map.contains_key(&cheapest_id) && tx_pool.pool_tx_map.contains_key(&txs[2].gen_hash()));
+        assert!(tx_pool.pool_tx_ids.len() == MAX_TX_POOL && tx_pool.evicted_count == 2);
+        assert_eq!(tx_pool.get_status().evicted_count, 2);
+    }
+
+    /// Test that a pool recovered from its write-ahead log has the same transactions as the pool that wrote it
+    #[test]
+    fn test_tx_pool_log_recovery() {
+        let txs_str = read_string_from_file("./testdata/txs_0.json");
+        let txs = serde_json::from_str::<Vec<Transaction>>(&txs_str).unwrap();
+        let path = std::env::temp_dir().join(format!("tx_pool_log_{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        assert!(TxPool::recover_from_log(&path).unwrap().pool_tx_ids.is_empty());
+
+        // the pool starts with a transaction before the log is opened, which is in its snapshot
+        let mut tx_pool = TxPool::new();
+        tx_pool.tx_ttl_ms = Some(1000);
+        assert_eq!(tx_pool.try_add_tx_at(txs[0].clone(), 0), Ok(()));
+        tx_pool.open_log(&path).unwrap();
+        assert_eq!(tx_pool.try_add_tx_at(txs[1].clone(), 500), Ok(()));
+        assert_eq!(tx_pool.try_add_tx_at(txs[2].clone(), 600), Ok(()));
+        tx_pool.del_tx(txs[2].gen_hash());
+        assert_eq!(tx_pool.purge_expired(1000), 1);
+        assert_eq!(tx_pool.try_add_tx_at(txs[3].clone(), 1100), Ok(()));
+        // a clone does not write to the log
+        let mut clone = tx_pool.clone();
+        assert_eq!(clone.try_add_tx_at(txs[4].clone(), 1200), Ok(()));
+        assert_eq!(tx_pool.sync_log(), Ok(()));
+        assert_eq!(clone.sync_log(), Ok(()));
+
+        let recovered = TxPool::recover_from_log(&path).unwrap();
+        assert!(recovered.pool_tx_ids == vec![txs[1].gen_hash(), txs[3].gen_hash()]);
+        assert!(recovered.pool_tx_ids == tx_pool.pool_tx_ids && recovered.removed_tx_ids == tx_pool.removed_tx_ids);
+        assert!(recovered.pool_tx_added_ms == tx_pool.pool_tx_added_ms);
+
+        // a write cut short by a crash is ignored, and reopening the log compacts it into a snapshot
+        let mut content = fs::read_to_string(&path).unwrap();
+        let line_count = content.lines().count();
+        content += "{\"Add\":[";
+        fs::write(&path, content).unwrap();
+        let mut recovered = TxPool::recover_from_log(&path).unwrap();
+        assert!(recovered.pool_tx_ids == tx_pool.pool_tx_ids);
+        recovered.open_log(&path).unwrap();
+        assert!(fs::read_to_string(&path).unwrap().lines().count() < line_count);
+        assert!(TxPool::recover_from_log(&path).unwrap().pool_tx_ids == tx_pool.pool_tx_ids);
+        let _ = fs::remove_file(&path);
+    }
+
+    /// Test that the published schema of TxPool is up to date, and that an invalid tx pool is reported with the path of
+    /// the field at fault
+    #[test]
+    fn test_tx_pool_schema() {
+        check_published_schema("TxPool", &schema_json::<TxPool>()).unwrap();
+
+        let txs = serde_json::from_str::<Vec<Transaction>>(&read_string_from_file("./testdata/txs_0.json")).unwrap();
+        let mut tx_pool = TxPool::new();
+        tx_pool.add_tx(txs[0].clone());
+        let mut json = serde_json::to_value(&tx_pool).unwrap();
+        let tx_id = txs[0].gen_hash();
+        json["pool_tx_map"][&tx_id]["sender"] = 1.into();
+        let e = TxPool::from_json(&json.to_string()).unwrap_err();
+        assert!(e == format!("Invalid TxPool at `pool_tx_map.{}.sender`: invalid type: integer `1`, expected a string", tx_id), "{}", e);
+    }
+
     /// Your own additional test that tests your implementation more throughly 
     /// (e.g. invalid signature, and test methods that are not covered in the tests above)
     #[test]
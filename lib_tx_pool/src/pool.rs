@@ -5,19 +5,103 @@
 // This file contains the definition of the transaction pool.
 // The transaction pool `TxPool` is a data structure that stores all the valid transactions that are not yet finalized.
 // It helps with filtering the transactions that can be included in a new block.
-use lib_chain::block::{BlockId, BlockNode, Signature, Transaction, TxId};
+// Transactions may expire after a time to live, and once the pool is full, room is made for a new transaction by evicting
+// one according to the `EvictionPolicy`. Expired and evicted transactions are not added to `removed_tx_ids`, so they can
+// be submitted again later.
+// Every change of the pool is also recorded in its write-ahead log once one is opened (see journal.rs).
+use crate::journal::{AttachedJournal, JournalEntry, TxPoolJournal};
+use lib_chain::block::{BlockId, BlockNode, Transaction, TxId};
+use lib_chain::migration::{self, MigrationRegistry};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::{
-    collections::{BTreeMap, HashMap, HashSet},
-    convert,
-    hash::Hash,
-};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// The maximum number of transactions that can be stored in the pool. Beyond it, a transaction is evicted (see `EvictionPolicy`).
+pub const MAX_TX_POOL: usize = 10000;
+
+/// How the pool makes room for a new transaction once it holds `MAX_TX_POOL` transactions.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq)]
+pub enum EvictionPolicy {
+    /// Evict the transaction that was added to the pool first
+    #[default]
+    OldestFirst,
+    /// Evict the transaction paying the lowest fee (the oldest of them). A new transaction paying less than all the
+    /// transactions in the pool is rejected instead.
+    LowestFeeFirst,
+}
 
-/// The maximum number of transactions that can be stored in the pool. Extra transactions will be dropped.
-const MAX_TX_POOL: usize = 10000;
+/// The reason why a transaction is not accepted into the pool.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum TxRejectReason {
+    /// The amount in the message (or of an output of a batch) is missing or not positive, or the total with the fee overflows
+    InvalidAmount,
+    /// The sender cannot afford the transaction (available, required)
+    InsufficientBalance(i64, i64),
+    /// The transaction is already in the pool, on the chain, or has been removed from the pool
+    Duplicate,
+    /// The pool already holds the maximum number of transactions, and none of them can be evicted for this one
+    PoolFull,
+    /// The signature does not match the sender and the transaction data
+    InvalidSignature,
+    /// The message is longer than the consensus params allow (length, max_length)
+    MessageTooLong(usize, usize),
+    /// The fee is lower than the minimum relay fee of the node (fee, min_relay_fee)
+    FeeTooLow(i64, i64),
+    /// The batch has more outputs than the consensus params allow (count, max_count)
+    TooManyOutputs(usize, usize),
+}
+
+impl fmt::Display for TxRejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TxRejectReason::InvalidAmount => write!(f, "invalid amount"),
+            TxRejectReason::InsufficientBalance(available, required) => write!(
+                f,
+                "insufficient balance ({} available, {} required)",
+                available, required
+            ),
+            TxRejectReason::Duplicate => write!(f, "duplicate transaction"),
+            TxRejectReason::PoolFull => write!(f, "tx pool is full"),
+            TxRejectReason::InvalidSignature => write!(f, "invalid signature"),
+            TxRejectReason::MessageTooLong(len, max_len) => write!(
+                f,
+                "message too long ({} bytes, at most {} allowed)",
+                len, max_len
+            ),
+            TxRejectReason::FeeTooLow(fee, min_relay_fee) => write!(
+                f,
+                "fee too low ({}, at least {} required)",
+                fee, min_relay_fee
+            ),
+            TxRejectReason::TooManyOutputs(count, max_count) => write!(
+                f,
+                "too many outputs ({}, at most {} allowed)",
+                count, max_count
+            ),
+        }
+    }
+}
+
+/// The status of a transaction pool (see `TxPool::get_status`).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TxPoolStatus {
+    /// The number of transactions in the pool
+    pub tx_count: usize,
+    /// How long (in ms) a transaction stays in the pool before it expires, if it expires
+    pub tx_ttl_ms: Option<u64>,
+    /// How room is made for a new transaction once the pool is full
+    pub eviction_policy: EvictionPolicy,
+    /// The number of transactions expired since the pool was loaded
+    pub expired_count: u64,
+    /// The number of transactions evicted since the pool was loaded
+    pub evicted_count: u64,
+}
 
 /// A transaction pool that stores received transactions that are not yet finalized.
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 pub struct TxPool {
     /// A list of transaction ids in the pool
     pub pool_tx_ids: Vec<TxId>,
@@ -27,8 +111,38 @@ pub struct TxPool {
     pub removed_tx_ids: HashSet<TxId>,
     /// The id of the last finalized block. Transactions that are finalized will be removed from the pool and added to the removed_tx_ids set.
     pub last_finalized_block_id: BlockId,
+    /// The version of the serialized format (see `TX_POOL_MIGRATIONS`)
+    #[serde(default)]
+    pub version: u32,
+    /// The minimum fee of the transactions accepted into the pool, set from the config of the node (0 by default)
+    #[serde(skip)]
+    pub min_relay_fee: i64,
+    /// The time (in milliseconds since the UNIX epoch) when each transaction of the pool was added
+    #[serde(default)]
+    pub pool_tx_added_ms: HashMap<TxId, u64>,
+    /// How long (in ms) a transaction stays in the pool before it expires, set from the config of the node (no expiry by default)
+    #[serde(skip)]
+    pub tx_ttl_ms: Option<u64>,
+    /// How room is made for a new transaction once the pool is full, set from the config of the node
+    #[serde(skip)]
+    pub eviction_policy: EvictionPolicy,
+    /// The number of transactions expired since the pool was loaded
+    #[serde(skip)]
+    pub expired_count: u64,
+    /// The number of transactions evicted since the pool was loaded
+    #[serde(skip)]
+    pub evicted_count: u64,
+    /// The write-ahead log every change of the pool is recorded in, if one is opened (see `open_log`)
+    #[serde(skip)]
+    pub journal: AttachedJournal,
 }
 
+/// The migrations of the serialized TxPool from older formats (see `TxPool::from_json`).
+pub const TX_POOL_MIGRATIONS: MigrationRegistry = MigrationRegistry {
+    kind: "TxPool",
+    steps: &[migration::add_version_field],
+};
+
 impl TxPool {
     /// Create a new transaction pool
     pub fn new() -> TxPool {
@@ -37,51 +151,201 @@ impl TxPool {
             pool_tx_map: HashMap::new(),
             last_finalized_block_id: "0".to_string(),
             removed_tx_ids: HashSet::new(),
+            version: TX_POOL_MIGRATIONS.current_version(),
+            min_relay_fee: 0,
+            pool_tx_added_ms: HashMap::new(),
+            tx_ttl_ms: None,
+            eviction_policy: EvictionPolicy::default(),
+            expired_count: 0,
+            evicted_count: 0,
+            journal: AttachedJournal::default(),
+        }
+    }
+
+    /// Rebuild a transaction pool from its write-ahead log (see `open_log`), e.g. after a crash: the recorded changes are
+    /// replayed in order, without checking the transactions again. The pool is empty if there is no log at that path.
+    /// The returned pool is not attached to the log: call `open_log` to go on recording its changes.
+    pub fn recover_from_log<P: AsRef<Path>>(path: P) -> Result<TxPool, String> {
+        let mut tx_pool = TxPool::new();
+        for entry in TxPoolJournal::load_entries(path)? {
+            match entry {
+                JournalEntry::Add(tx, added_ms) => {
+                    let tx_id = tx.gen_hash();
+                    if tx_pool.pool_tx_map.insert(tx_id.clone(), tx).is_none() {
+                        tx_pool.pool_tx_ids.push(tx_id.clone());
+                    }
+                    if let Some(added_ms) = added_ms {
+                        tx_pool.pool_tx_added_ms.insert(tx_id, added_ms);
+                    }
+                }
+                JournalEntry::Del(tx_id) => tx_pool.del_tx(tx_id),
+                JournalEntry::Drop(tx_id) => tx_pool.drop_tx(&tx_id),
+                JournalEntry::Finalized(block_id) => tx_pool.last_finalized_block_id = block_id,
+            }
+        }
+        Ok(tx_pool)
+    }
+
+    /// Start recording the changes of the pool in a write-ahead log at the given path. The log is rewritten with a
+    /// snapshot of the pool first (its finalized block, removed transactions and transactions), replacing any older log.
+    pub fn open_log<P: AsRef<Path>>(&mut self, path: P) -> Result<(), String> {
+        let mut removed_tx_ids: Vec<&TxId> = self.removed_tx_ids.iter().collect();
+        removed_tx_ids.sort();
+        let snapshot: Vec<JournalEntry> = std::iter::once(JournalEntry::Finalized(
+            self.last_finalized_block_id.clone(),
+        ))
+        .chain(
+            removed_tx_ids
+                .into_iter()
+                .map(|tx_id| JournalEntry::Del(tx_id.clone())),
+        )
+        .chain(self.pool_tx_ids.iter().map(|tx_id| {
+            JournalEntry::Add(
+                self.pool_tx_map[tx_id].clone(),
+                self.pool_tx_added_ms.get(tx_id).copied(),
+            )
+        }))
+        .collect();
+        self.journal = AttachedJournal(Some(TxPoolJournal::create(path, &snapshot)?));
+        Ok(())
+    }
+
+    /// Wait until the changes recorded so far in the write-ahead log, if one is open, are synced to disk.
+    /// Return an error if they could not be synced.
+    pub fn sync_log(&self) -> Result<(), String> {
+        match self.journal.0.as_ref() {
+            Some(journal) => journal.sync(),
+            None => Ok(()),
+        }
+    }
+
+    /// Record a change of the pool in its write-ahead log, if one is open. A failed write is reported but does not
+    /// undo the change.
+    fn record(&mut self, entry: JournalEntry) {
+        if let Some(journal) = self.journal.0.as_mut() {
+            if let Err(e) = journal.append(&entry) {
+                eprintln!("[TxPool] Change not recorded: {}", e);
+            }
         }
     }
 
+    /// Parse a serialized transaction pool, upgrading it first if it was written in an older format (see `TX_POOL_MIGRATIONS`).
+    pub fn from_json(json: &str) -> Result<TxPool, String> {
+        TX_POOL_MIGRATIONS.load(json)
+    }
+
     /// Add a transaction `tx` to the pool if it satisfies the following conditions:
     /// - The transaction is not already in the pool
     /// - The transaction is not already in the removed_tx_ids set
-    /// - The pool size is less than MAX_TX_POOL
+    /// - The transaction pays at least `min_relay_fee`
     /// - The transaction has valid signature
+    /// - The pool size is less than MAX_TX_POOL, or a transaction can be evicted according to the `eviction_policy`
+    ///
     /// It returns true if the transaction satisfies the conditions above and is successfully added to the pool, and false otherwise.
     pub fn add_tx(&mut self, tx: Transaction) -> bool {
-        // Please fill in the blank
-        // todo!();
+        self.try_add_tx(tx).is_ok()
+    }
+
+    /// Same as `add_tx`, but it returns the reason why the transaction is not added to the pool.
+    pub fn try_add_tx(&mut self, tx: Transaction) -> Result<(), TxRejectReason> {
+        let now_ms = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        self.try_add_tx_at(tx, now_ms)
+    }
+
+    /// Same as `try_add_tx`, at the given time (in milliseconds since the UNIX epoch), e.g. from the clock of the node.
+    /// The expired transactions are purged first.
+    pub fn try_add_tx_at(&mut self, tx: Transaction, now_ms: u64) -> Result<(), TxRejectReason> {
+        let tx_id = tx.gen_hash();
 
         // Check if the transaction is already in the pool or removed_tx_ids set
-        if self.pool_tx_map.contains_key(&tx.gen_hash())
-            || self.removed_tx_ids.contains(&tx.gen_hash())
-        {
-            return false;
+        if self.pool_tx_map.contains_key(&tx_id) || self.removed_tx_ids.contains(&tx_id) {
+            return Err(TxRejectReason::Duplicate);
         }
 
-        // Check if the pool size is less than MAX_TX_POOL
-        if self.pool_tx_ids.len() >= MAX_TX_POOL {
-            return false;
+        // Check if the transaction pays the minimum relay fee
+        if tx.get_fee() < self.min_relay_fee {
+            return Err(TxRejectReason::FeeTooLow(tx.get_fee(), self.min_relay_fee));
         }
 
         // Check if the transaction has a valid signature
-        if !tx.verify_sig() {
-            return false;
+        if let Err(e) = tx.verify_sig() {
+            eprintln!("[TxPool] Transaction {} rejected: {}", tx_id, e);
+            return Err(TxRejectReason::InvalidSignature);
+        }
+
+        // Make room for the transaction if the pool is full, once it is known to be valid
+        self.purge_expired(now_ms);
+        if self.pool_tx_ids.len() >= MAX_TX_POOL {
+            let victim_id = self.eviction_victim(&tx).ok_or(TxRejectReason::PoolFull)?;
+            self.drop_tx(&victim_id);
+            self.evicted_count += 1;
         }
 
         // Add the transaction to the pool
-        self.pool_tx_ids.push(tx.gen_hash());
-        self.pool_tx_map.insert(tx.gen_hash(), tx);
+        self.pool_tx_ids.push(tx_id.clone());
+        self.pool_tx_added_ms.insert(tx_id.clone(), now_ms);
+        self.pool_tx_map.insert(tx_id, tx.clone());
+        self.record(JournalEntry::Add(tx, Some(now_ms)));
+
+        Ok(())
+    }
 
-        true
+    /// The transaction to evict from the full pool to make room for `new_tx`, if any (see `EvictionPolicy`).
+    /// The pool keeps the order of arrival, so the first candidate found is the oldest.
+    fn eviction_victim(&self, new_tx: &Transaction) -> Option<TxId> {
+        match self.eviction_policy {
+            EvictionPolicy::OldestFirst => self.pool_tx_ids.first().cloned(),
+            EvictionPolicy::LowestFeeFirst => self
+                .pool_tx_ids
+                .iter()
+                .min_by_key(|tx_id| self.pool_tx_map[*tx_id].get_fee())
+                .filter(|tx_id| self.pool_tx_map[*tx_id].get_fee() <= new_tx.get_fee())
+                .cloned(),
+        }
+    }
+
+    /// Remove a transaction from the pool without adding it to removed_tx_ids, e.g. when it expires or is evicted.
+    fn drop_tx(&mut self, tx_id: &TxId) {
+        self.pool_tx_map.remove(tx_id);
+        self.pool_tx_added_ms.remove(tx_id);
+        self.pool_tx_ids.retain(|id| id != tx_id);
+        self.record(JournalEntry::Drop(tx_id.clone()));
+    }
+
+    /// Remove the transactions that have been in the pool for `tx_ttl_ms` or longer at `now_ms`, and return how many.
+    /// Nothing expires if there is no time to live. The transactions loaded without a time (from an older serialized pool)
+    /// are timed from now on.
+    pub fn purge_expired(&mut self, now_ms: u64) -> usize {
+        let Some(ttl_ms) = self.tx_ttl_ms else {
+            return 0;
+        };
+        let mut expired_ids = vec![];
+        for tx_id in &self.pool_tx_ids {
+            let added_ms = *self.pool_tx_added_ms.entry(tx_id.clone()).or_insert(now_ms);
+            if now_ms.saturating_sub(added_ms) >= ttl_ms {
+                expired_ids.push(tx_id.clone());
+            }
+        }
+        for tx_id in &expired_ids {
+            self.drop_tx(tx_id);
+        }
+        self.expired_count += expired_ids.len() as u64;
+        expired_ids.len()
     }
 
     /// Deleting a tx from the pool. This function is used by remove_txs_from_finalized_blocks and some unit tests.
     /// It should update pool_tx_ids, pool_tx_map, and removed_tx_ids.
     /// If the transaction does not exist in the pool, make sure it is added to removed_tx_ids.
-    pub fn del_tx(&mut self, tx_id: TxId) -> () {
+    pub fn del_tx(&mut self, tx_id: TxId) {
         // Please fill in the blank
         // todo!();
 
         let id = tx_id.clone();
+        self.record(JournalEntry::Del(tx_id.clone()));
+        self.pool_tx_added_ms.remove(&tx_id);
         // Check if the transaction exists in the pool
         if let Some(_transaction) = self.pool_tx_map.remove(&tx_id) {
             // Add the transaction ID to the set of removed transaction IDs
@@ -105,32 +369,33 @@ impl TxPool {
     /// Filter `max_count` number of tx from the pool. It is used for creating puzzle.
     /// - `max_count`: the maximum number of transactions to be returned
     /// - `excluding_txs`: a list of transactions that should not be included in the returned list.
-    ///                    It is used to filter out those transactions on the longest chain but hasn't been finalized yet.
-    pub fn filter_tx(&self, max_count: u16, excluding_txs: &Vec<Transaction>) -> Vec<Transaction> {
+    ///   It is used to filter out those transactions on the longest chain but hasn't been finalized yet.
+    ///
+    /// The transactions paying the highest fees are returned first, so that miners prioritize them. Transactions with the
+    /// same fee keep the order in which they were added to the pool.
+    pub fn filter_tx(&self, max_count: u16, excluding_txs: &[Transaction]) -> Vec<Transaction> {
         // Please fill in the blank
         // todo!();
 
         let mut filtered_txs: Vec<Transaction> = vec![];
-        let mut count = 0;
 
         for tx_id in &self.pool_tx_ids {
             // Check if the transaction is not in the excluding_txs list
             if !excluding_txs.iter().any(|tx| &tx.gen_hash() == tx_id) {
                 if let Some(tx) = self.pool_tx_map.get(tx_id) {
                     filtered_txs.push(tx.clone());
-                    count += 1;
-                    if count == max_count {
-                        break;
-                    }
                 }
             }
         }
 
+        // The sort is stable, so that the order of arrival breaks ties
+        filtered_txs.sort_by_key(|tx| std::cmp::Reverse(tx.get_fee()));
+        filtered_txs.truncate(max_count as usize);
         filtered_txs
     }
 
     /// Remove transactions from the pool given a list of finalized blocks. Update last_finalized_block_id as the last block in the list.
-    pub fn remove_txs_from_finalized_blocks(&mut self, finalized_blocks: &Vec<BlockNode>) {
+    pub fn remove_txs_from_finalized_blocks(&mut self, finalized_blocks: &[BlockNode]) {
         // Please fill in the blank
         // todo!();
         for block in finalized_blocks {
@@ -139,19 +404,29 @@ impl TxPool {
             }
         }
         self.last_finalized_block_id = finalized_blocks.last().unwrap().header.block_id.clone();
+        self.record(JournalEntry::Finalized(
+            self.last_finalized_block_id.clone(),
+        ));
     }
 
     /// Get status information of the tx_pool for debug printing.
-    pub fn get_status(&self) -> BTreeMap<String, String> {
+    pub fn get_status(&self) -> TxPoolStatus {
         // Please fill in the blank
         // For debugging purpose, you can return any dictionary of strings as the status of the tx_pool.
         // It should be displayed in the Client UI eventually.
         // todo!();
-        let mut status = BTreeMap::new();
-        status.insert(
-            "#pool_tx_map".to_string(),
-            self.pool_tx_map.len().to_string(),
-        );
-        status
+        TxPoolStatus {
+            tx_count: self.pool_tx_map.len(),
+            tx_ttl_ms: self.tx_ttl_ms,
+            eviction_policy: self.eviction_policy,
+            expired_count: self.expired_count,
+            evicted_count: self.evicted_count,
+        }
+    }
+}
+
+impl Default for TxPool {
+    fn default() -> Self {
+        Self::new()
     }
 }
@@ -0,0 +1,67 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+pub mod verify;
+
+#[cfg(test)]
+mod tests {
+    use crate::verify::{verify_block, verify_block_file, verify_state_consistency, verify_tx_signature};
+    use lib_chain::block::{BlockNode, BlockTree};
+    use lib_tx_pool::pool::TxPool;
+    use std::fs;
+
+    fn read_block(i: usize) -> BlockNode {
+        let filepath = format!("../lib_chain/testdata/add_blocks_basic__{}.json", i);
+        let block_json = fs::read_to_string(&filepath).unwrap_or_else(|_| panic!("Cannot read {}", filepath));
+        serde_json::from_str(&block_json).unwrap()
+    }
+
+    /// Test that valid blocks pass and that each kind of tampering is reported
+    #[test]
+    fn verify_blocks() {
+        assert!(verify_block_file("../lib_chain/testdata/add_blocks_basic__1.json", 5).is_ok());
+        assert!(verify_block_file("../lib_chain/testdata/add_blocks_basic__1.json", 20).unwrap_err().contains("leading zeros"));
+        assert!(verify_block_file("../lib_chain/testdata/missing.json", 5).unwrap_err().starts_with("Cannot read"));
+
+        let block = read_block(2);
+        let mut bad_nonce = block.clone();
+        bad_nonce.header.nonce.push('A');
+        assert!(verify_block(&bad_nonce, 5).unwrap_err().contains("does not match the hash"));
+
+        let mut bad_tx = block.clone();
+        bad_tx.transactions_block.transactions[0].message.push('0');
+        assert!(verify_block(&bad_tx, 5).unwrap_err().contains("merkle root"));
+
+        let mut bad_sig = block.transactions_block.transactions[1].clone();
+        assert!(verify_tx_signature(&bad_sig).is_ok());
+        bad_sig.sig = block.transactions_block.transactions[2].sig.clone();
+        assert!(verify_tx_signature(&bad_sig).unwrap_err().contains("not correctly signed"));
+    }
+
+    /// Test that a saved block tree and tx pool are consistent, and that a tampered tx pool or block is reported
+    #[test]
+    fn verify_state() {
+        let mut blocktree = BlockTree::new();
+        for i in 1..=8 {
+            blocktree.add_block(read_block(i), 5).unwrap();
+        }
+        let mut txpool = TxPool::new();
+        let pending_tx = read_block(8).transactions_block.transactions[0].clone();
+        assert!(txpool.add_tx(pending_tx.clone()));
+        assert!(verify_state_consistency(&blocktree, &txpool).is_ok());
+
+        let mut bad_txpool = txpool.clone();
+        bad_txpool.pool_tx_ids.push(pending_tx.gen_hash());
+        bad_txpool.last_finalized_block_id = blocktree.working_block_id.clone();
+        let problems = verify_state_consistency(&blocktree, &bad_txpool).unwrap_err();
+        assert!(problems.len() == 2);
+        assert!(problems[0].contains("more than once"));
+        assert!(problems[1].contains("not finalized"));
+
+        let mut bad_blocktree = blocktree.clone();
+        bad_blocktree.all_blocks.get_mut(&blocktree.working_block_id).unwrap().header.nonce.push('A');
+        let problems = verify_state_consistency(&bad_blocktree, &txpool).unwrap_err();
+        assert!(problems.iter().any(|problem| problem.contains("does not match the hash")));
+    }
+}
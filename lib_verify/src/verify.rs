@@ -0,0 +1,187 @@
+// This file is part of the project for the module CS3235 by Prateek
+// Copyright 2023 Ruishi Li, Bo Wang, and Prateek Saxena.
+// Please do not distribute.
+
+// This file implements the checks of the verification helpers: pure functions over blocks, block trees, tx pools and
+// transactions, which tell why an artifact is invalid instead of only rejecting it (as `BlockTree::add_block` does).
+// They do not need a running node, so they can validate the json files submitted by students (see bin_verify).
+
+use lib_chain::block::{BlockNode, BlockTree, MerkleTree, Transaction};
+use lib_chain::consensus_params::ConsensusParams;
+use lib_tx_pool::pool::TxPool;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+
+/// Check a block on its own (not its place in a block tree):
+/// - its block id is the sha256 hash of its nonce and its puzzle, and has `difficulty` leading "0"s
+/// - its merkle root and merkle tree match its transactions
+/// - its transactions and its miner signature (if any) are correctly signed
+///
+/// Return the first problem found.
+pub fn verify_block(block: &BlockNode, difficulty: u16) -> Result<(), String> {
+    verify_block_for(block, difficulty, &ConsensusParams::default())
+}
+
+/// Check a block on its own like `verify_block`, for a chain with the given consensus parameters: its puzzle commits its
+/// timestamp if they retarget the difficulty (see `Puzzle::timestamp`).
+pub fn verify_block_for(
+    block: &BlockNode,
+    difficulty: u16,
+    consensus: &ConsensusParams,
+) -> Result<(), String> {
+    let block_id = &block.header.block_id;
+    if !block_id.starts_with(&"0".repeat(difficulty as usize)) {
+        return Err(format!(
+            "Block {} has {} leading zeros, {} required",
+            block_id,
+            block.leading_zero_len(),
+            difficulty
+        ));
+    }
+
+    let puzzle = block.header.puzzle(consensus);
+    let mut hasher = Sha256::new();
+    hasher.update(block.header.nonce.as_bytes());
+    hasher.update(serde_json::to_string(&puzzle).unwrap().as_bytes());
+    let hash = hex::encode(hasher.finalize());
+    if hash != *block_id {
+        return Err(format!(
+            "Block {} does not match the hash of its nonce and puzzle ({})",
+            block_id, hash
+        ));
+    }
+
+    let (merkle_root, merkle_tree) =
+        MerkleTree::create_merkle_tree(block.transactions_block.transactions.clone());
+    if merkle_root != block.header.merkle_root {
+        return Err(format!(
+            "Block {} has merkle root {}, but its transactions give {}",
+            block_id, block.header.merkle_root, merkle_root
+        ));
+    }
+    if merkle_tree != block.transactions_block.merkle_tree {
+        return Err(format!(
+            "Block {} has a merkle tree that does not match its transactions",
+            block_id
+        ));
+    }
+
+    for tx in block.transactions_block.transactions.iter() {
+        verify_tx_signature(tx).map_err(|e| format!("Block {}: {}", block_id, e))?;
+    }
+    if let Some(miner_signature) = &block.header.miner_signature {
+        if !miner_signature.verify(block_id) {
+            return Err(format!("Block {} has an invalid miner signature", block_id));
+        }
+    }
+    Ok(())
+}
+
+/// Read a block from a json file (as written by `serde_json` for `BlockNode`) and check it (see `verify_block`).
+pub fn verify_block_file(path: &str, difficulty: u16) -> Result<(), String> {
+    let block_json =
+        fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+    let block: BlockNode =
+        serde_json::from_str(&block_json).map_err(|e| format!("{} is not a block: {}", path, e))?;
+    verify_block(&block, difficulty)
+}
+
+/// Check that the signature of a transaction matches its sender and its data.
+pub fn verify_tx_signature(tx: &Transaction) -> Result<(), String> {
+    tx.verify_sig().map_err(|e| {
+        format!(
+            "Transaction {} is not correctly signed: {}",
+            tx.gen_hash(),
+            e
+        )
+    })
+}
+
+/// Check that a block tree and a tx pool (e.g. the BlockTree.json and TxPool.json saved by a node) are consistent:
+/// - the block tree keeps its own invariants and its supply adds up (see `BlockTree::check_invariants` and `audit_supply`)
+/// - every block but the genesis block hashes to its block id and is correctly signed (the difficulty is not checked)
+/// - the tx pool lists each of its transactions once, under its own id, correctly signed and not marked as removed
+/// - the last finalized block seen by the tx pool is the finalized block of the block tree or one of its ancestors
+///
+/// Return every problem found.
+pub fn verify_state_consistency(blocktree: &BlockTree, txpool: &TxPool) -> Result<(), Vec<String>> {
+    let mut problems = vec![];
+    if let Err(violations) = blocktree.check_invariants() {
+        problems.extend(violations);
+    }
+    if let Err(violation) = blocktree.audit_supply() {
+        problems.push(violation);
+    }
+    let mut block_ids: Vec<&String> = blocktree.all_blocks.keys().collect();
+    block_ids.sort();
+    for block_id in block_ids {
+        if *block_id != blocktree.root_id {
+            if let Err(e) =
+                verify_block_for(&blocktree.all_blocks[block_id], 0, &blocktree.consensus)
+            {
+                problems.push(e);
+            }
+        }
+    }
+
+    let mut listed = HashSet::new();
+    for tx_id in txpool.pool_tx_ids.iter() {
+        if !listed.insert(tx_id) {
+            problems.push(format!(
+                "Tx pool lists transaction {} more than once",
+                tx_id
+            ));
+        }
+        if !txpool.pool_tx_map.contains_key(tx_id) {
+            problems.push(format!(
+                "Tx pool lists transaction {} but does not hold it",
+                tx_id
+            ));
+        }
+        if txpool.removed_tx_ids.contains(tx_id) {
+            problems.push(format!(
+                "Tx pool holds transaction {} marked as removed",
+                tx_id
+            ));
+        }
+    }
+    let mut held: Vec<&String> = txpool.pool_tx_map.keys().collect();
+    held.sort();
+    for tx_id in held {
+        let tx = &txpool.pool_tx_map[tx_id];
+        if !listed.contains(tx_id) {
+            problems.push(format!(
+                "Tx pool holds transaction {} but does not list it",
+                tx_id
+            ));
+        }
+        if tx.gen_hash() != *tx_id {
+            problems.push(format!(
+                "Tx pool holds transaction {} under id {}",
+                tx.gen_hash(),
+                tx_id
+            ));
+        }
+        if let Err(e) = verify_tx_signature(tx) {
+            problems.push(format!("Tx pool: {}", e));
+        }
+    }
+
+    let finalized_in_pool = &txpool.last_finalized_block_id;
+    if !blocktree
+        .iter_ancestors(&blocktree.finalized_block_id)
+        .any(|block| block.header.block_id == *finalized_in_pool)
+    {
+        problems.push(format!(
+            "Tx pool was last updated at block {}, which is not finalized in the block tree",
+            finalized_in_pool
+        ));
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems)
+    }
+}